@@ -33,6 +33,9 @@ fn create_configuration<R: Runtime>(app: &tauri::AppHandle<R>) -> Configuration
     let mut bundled = Configuration::read(path).unwrap();
     bundled.qdrant_url = "http://127.0.0.1:6334".into();
     bundled.max_threads = bleep::default_parallelism() / 2;
+
+    // keep the memory footprint of the embedded qdrant low on desktop
+    bundled.vector_quantization = bleep::VectorQuantization::Scalar;
     bundled.model_dir = app
         .path_resolver()
         .resolve_resource("model")
@@ -0,0 +1,105 @@
+// Generates `src/types/api.generated.ts` from the JSON schema of the server API types.
+//
+// The schema is written by the server tests, see `server/bleep/src/webserver/api_schema.rs`.
+// Pass `--check` to fail instead of writing, when the generated types are out of date.
+
+import { readFileSync, writeFileSync } from 'node:fs';
+import { dirname, resolve } from 'node:path';
+import { fileURLToPath } from 'node:url';
+
+const root = dirname(dirname(fileURLToPath(import.meta.url)));
+const schemaPath = resolve(root, '../server/bleep-client/api-schema.json');
+const outputPath = resolve(root, 'src/types/api.generated.ts');
+
+const PRIMITIVES = {
+  string: 'string',
+  integer: 'number',
+  number: 'number',
+  boolean: 'boolean',
+  null: 'null',
+};
+
+const union = (types) => [...new Set(types)].join(' | ');
+
+const parenthesize = (type) => (type.includes(' | ') ? `(${type})` : type);
+
+const comment = (description, indent) => {
+  if (!description) {
+    return '';
+  }
+  const lines = description.split('\n').map((line) => `${indent} *${line && ` ${line}`}`);
+  return `${indent}/**\n${lines.join('\n')}\n${indent} */\n`;
+};
+
+const key = (name) => (/^[A-Za-z_$][\w$]*$/.test(name) ? name : `'${name}'`);
+
+function type(schema, indent) {
+  if (schema.$ref) {
+    return schema.$ref.replace('#/definitions/', '');
+  }
+  if (schema.enum) {
+    return union(schema.enum.map((value) => JSON.stringify(value).replace(/"/g, "'")));
+  }
+  const variants = schema.oneOf || schema.anyOf || schema.allOf;
+  if (variants) {
+    return union(variants.map((variant) => type(variant, indent)));
+  }
+
+  const types = [].concat(schema.type || []);
+  return union(
+    types.map((t) => {
+      if (t === 'array') {
+        return `${parenthesize(type(schema.items, indent))}[]`;
+      }
+      if (t === 'object') {
+        return object(schema, indent);
+      }
+      return PRIMITIVES[t];
+    }),
+  );
+}
+
+function object(schema, indent) {
+  if (!schema.properties) {
+    const values = schema.additionalProperties;
+    return values && values !== true ? `Record<string, ${type(values, indent)}>` : 'object';
+  }
+
+  const required = new Set(schema.required || []);
+  const inner = `${indent}  `;
+  const fields = Object.entries(schema.properties).map(
+    ([name, property]) =>
+      `${comment(property.description, inner)}${inner}${key(name)}${
+        required.has(name) ? '' : '?'
+      }: ${type(property, inner)};\n`,
+  );
+  return `{\n${fields.join('')}${indent}}`;
+}
+
+function generate(schema) {
+  const declarations = Object.entries(schema.definitions).map(([name, definition]) => {
+    const declaration =
+      definition.type === 'object' && definition.properties
+        ? `export interface ${name} ${object(definition, '')}`
+        : `export type ${name} = ${type(definition, '')};`;
+    return `${comment(definition.description, '')}${declaration}\n`;
+  });
+
+  return [
+    '// Generated by `scripts/generate-api-types.mjs` from `server/bleep-client/api-schema.json`.',
+    '// Do not edit by hand, run `npm run generate:api-types` instead.',
+    '',
+    declarations.join('\n'),
+  ].join('\n');
+}
+
+const generated = generate(JSON.parse(readFileSync(schemaPath, 'utf8')));
+
+if (process.argv.includes('--check')) {
+  if (readFileSync(outputPath, 'utf8') !== generated) {
+    console.error(`${outputPath} is out of date, run \`npm run generate:api-types\``);
+    process.exit(1);
+  }
+} else {
+  writeFileSync(outputPath, generated);
+}
@@ -12,6 +12,9 @@ import {
 } from './services/storage';
 import { LocaleType } from './types/general';
 
+// The server points the `<base>` tag of the index at its base path, like `/bloop/`
+const basePath = new URL(document.baseURI).pathname.replace(/\/$/, '');
+
 const CloudApp = () => {
   const [envConfig, setEnvConfig] = useState({});
   const [locale, setLocale] = useState<LocaleType>(
@@ -38,7 +41,7 @@ const CloudApp = () => {
       },
       invokeTauriCommand: () => Promise.resolve(''),
       release: packageJson.version,
-      apiUrl: import.meta.env.API_URL || '/api',
+      apiUrl: import.meta.env.API_URL || `${basePath}/api`,
       isRepoManagementAllowed: true,
       isSelfServe: true,
       forceAnalytics: true,
@@ -65,7 +68,7 @@ const CloudApp = () => {
 
   return (
     <LocaleContext.Provider value={localeContextValue}>
-      <BrowserRouter>
+      <BrowserRouter basename={basePath || undefined}>
         <App deviceContextValue={deviceContextValue} />
       </BrowserRouter>
     </LocaleContext.Provider>
@@ -170,7 +170,7 @@ const ConversationMessage = ({
                   />
                 ) : (
                   <img
-                    src="/bloopHeadMascot.png"
+                    src="bloopHeadMascot.png"
                     alt="mascot"
                     className="w-4.5 h-4.5"
                   />
@@ -29,7 +29,7 @@ const FirstMessage = ({
   return (
     <div className="flex items-start gap-3 px-4 py-3 hover:bg-chat-bg-shade">
       <div className="w-6 h-6 rounded-full bg-chat-bg-border flex-shrink-0 flex items-center justify-center mt-0.5">
-        <img src="/bloopHeadMascot.png" alt="mascot" className="w-4.5 h-4.5" />
+        <img src="bloopHeadMascot.png" alt="mascot" className="w-4.5 h-4.5" />
       </div>
       <div className="flex flex-col gap-2">
         <p className="body-s text-label-title">
@@ -3,7 +3,7 @@ import React, { useEffect } from 'react';
 
 const DownvoteBtn = ({ isDownvote }: { isDownvote: boolean }) => {
   const RiveDownvoteInline = useRive({
-    src: '/like-red.riv',
+    src: 'like-red.riv',
     autoplay: false,
   });
 
@@ -3,7 +3,7 @@ import React, { useEffect } from 'react';
 
 const UpvoteBtn = ({ isUpvote }: { isUpvote: boolean }) => {
   const RiveUpvoteInline = useRive({
-    src: '/like-blue.riv',
+    src: 'like-blue.riv',
     autoplay: false,
   });
 
@@ -34,7 +34,7 @@ const StudioGuidePopup = () => {
       <div className="bg-bg-shade border border-bg-border shadow-float rounded-md select-none relative">
         <div className="w-full h-72 overflow-hidden relative">
           <img
-            src="/light.png"
+            src="light.png"
             alt=""
             className="fixed -top-44 -right-40 pointer-events-none opacity-[0.16] z-50"
           />
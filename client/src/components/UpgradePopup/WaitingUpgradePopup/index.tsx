@@ -68,7 +68,7 @@ const WaitingUpgradePopup = ({}: Props) => {
           <>
             <div className="bg-bg-base w-full h-[11.25rem]">
               <img
-                src="/upgradeIllustration.png"
+                src="upgradeIllustration.png"
                 className="w-full h-full"
                 alt="celebration"
               />
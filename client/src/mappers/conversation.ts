@@ -21,6 +21,13 @@ export const mapLoadingSteps = (
           `${pa?.length > 20 ? '...' : ''}${pa?.slice(-20)}`,
       }));
     }
+    if (s.type === 'scratchpad') {
+      return {
+        ...s,
+        path: '',
+        displayText: t('Writing') + ' ' + s.content.name,
+      };
+    }
     return {
       ...s,
       path: s.content.query,
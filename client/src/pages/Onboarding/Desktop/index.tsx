@@ -46,7 +46,7 @@ const Desktop = ({ activeTab, closeOnboarding }: Props) => {
     <div className="fixed top-0 bottom-0 left-0 right-0 z-100 bg-bg-sub select-none">
       {os.type === 'Darwin' && <NavBar isSkeleton activeTab={activeTab} />}
       <img
-        src="/light.png"
+        src="light.png"
         alt=""
         className="fixed -top-68 lg:-top-80 xl:-top-96 w-[90vw] lg:w-[80vw] xl:w-[69vw] right-0 pointer-events-none opacity-[0.16] z-50"
       />
@@ -0,0 +1,465 @@
+// Generated by `scripts/generate-api-types.mjs` from `server/bleep-client/api-schema.json`.
+// Do not edit by hand, run `npm run generate:api-types` instead.
+
+export type Arm = 'control' | 'variant';
+
+/**
+ * The arm of an experiment that an exchange was answered with.
+ */
+export interface Assignment {
+  arm: Arm;
+  experiment: string;
+  experiment_id: number;
+  /**
+   * The configuration that was applied, for the variant arm.
+   */
+  variant?: Variant | null;
+}
+
+/**
+ * An external document attached to a query as ad-hoc context.
+ *
+ * Only the provenance of an attachment is stored with the conversation. The extracted text is used to answer the query it was attached to, and then discarded.
+ */
+export interface Attachment {
+  /**
+   * Why the document could not be attached, if fetching it failed.
+   */
+  error?: string | null;
+  title?: string | null;
+  /**
+   * Whether the text was cut short because the document was too long.
+   */
+  truncated: boolean;
+  url: string;
+}
+
+export interface CodeChunk {
+  alias: number;
+  end: number;
+  end_byte?: number | null;
+  path: string;
+  snippet: string;
+  start: number;
+  start_byte?: number | null;
+}
+
+/**
+ * The conversations that were asked for with `page` or `per_page`. Without either, the list is a bare array, as it was before pagination.
+ */
+export interface ConversationPage {
+  conversations: ConversationPreview[];
+  page: number;
+  page_count: number;
+  per_page?: number | null;
+  /**
+   * The number of conversations on every page, after filtering by `q`
+   */
+  total_count: number;
+}
+
+export interface ConversationPreview {
+  created_at: number;
+  pinned: boolean;
+  sort_order?: number | null;
+  thread_id: string;
+  title: string;
+  updated_at: number;
+}
+
+export interface DirEntry {
+  entry_data: EntryData;
+  name: string;
+}
+
+export interface DirectoryData {
+  entries: DirEntry[];
+  relative_path: string;
+  repo_name: string;
+  repo_ref: string;
+}
+
+/**
+ * The response upon encountering an error
+ */
+export interface EndpointError {
+  /**
+   * The kind of this error
+   */
+  kind: ErrorKind;
+  /**
+   * A context aware message describing the error
+   */
+  message: string;
+}
+
+export type EntryData = 'Directory' | {
+  File: {
+    indexed: boolean;
+    lang?: string | null;
+  };
+};
+
+/**
+ * The kind of an error
+ */
+export type ErrorKind = 'user' | 'unknown' | 'not_found' | 'configuration' | 'upstream_service' | 'internal' | 'custom';
+
+/**
+ * A continually updated conversation exchange.
+ *
+ * This contains the query from the user, the intermediate steps the model takes, and the final conclusion from the model alongside the answer, if any.
+ */
+export interface Exchange {
+  answer?: string | null;
+  /**
+   * External documents attached to the query by the user.
+   */
+  attachments?: Attachment[];
+  code_chunks: CodeChunk[];
+  conclusion?: string | null;
+  /**
+   * The experiments this exchange took part in, and the arm it was answered with.
+   */
+  experiments?: Assignment[];
+  /**
+   * A specifically chosen "focused" code chunk.
+   *
+   * This is different from the `code_chunks` list, as focused code chunks also contain the full surrounding context from the source file, not just the relevant snippet.
+   *
+   * In the context of the app, this can be used to show code side-by-side with an outcome, such as when displaying an article.
+   */
+  focused_chunk?: FocusedChunk | null;
+  id: string;
+  /**
+   * The state of the index this exchange was answered from, if a fresh index was requested.
+   */
+  index_freshness?: IndexFreshness | null;
+  paths: string[];
+  /**
+   * The plan for this exchange, if it was made in plan mode.
+   */
+  plan?: Plan | null;
+  query: SemanticQuery;
+  query_timestamp?: string | null;
+  response_timestamp?: string | null;
+  /**
+   * The retrieval settings that were in effect when this exchange was answered.
+   */
+  retrieval?: RetrievalSettings | null;
+  search_steps: SearchStep[];
+  /**
+   * The model calls that chose which function to call, in order.
+   */
+  tool_selections?: ToolSelection[];
+}
+
+export interface FileData {
+  contents: string;
+  indexed: boolean;
+  lang?: string | null;
+  loc: number;
+  relative_path: string;
+  repo_name: string;
+  repo_ref: string;
+  siblings: DirEntry[];
+  size: number;
+  sloc: number;
+}
+
+export interface FileResultData {
+  branches: string;
+  indexed: boolean;
+  is_dir: boolean;
+  lang?: string | null;
+  relative_path: HighlightedString;
+  repo_name: string;
+  repo_ref: string;
+}
+
+export interface FocusedChunk {
+  end_line: number;
+  file_path: string;
+  start_line: number;
+}
+
+export interface HighlightedString {
+  /**
+   * Index ranges that are highlighted as matched.
+   */
+  highlights: Range_of_uint[];
+  text: string;
+}
+
+export interface IndexFreshness {
+  /**
+   * The commit the index was built from.
+   */
+  indexed_commit?: string | null;
+  /**
+   * Whether the index could not be brought up to date in time, and may lag behind upstream.
+   */
+  stale: boolean;
+}
+
+export type Literal = {
+  Plain: LiteralInner;
+} | {
+  Regex: LiteralInner;
+};
+
+export interface LiteralInner {
+  content: string;
+  end: number;
+  start: number;
+}
+
+/**
+ * Metadata pertaining to the query response, such as paging info
+ */
+export interface PagingMetadata {
+  /**
+   * Page number passed in the request
+   */
+  page: number;
+  /**
+   * total number of pages, only populated if the client requests it
+   */
+  page_count?: number | null;
+  /**
+   * Number of items per-page
+   */
+  page_size: number;
+  /**
+   * total number of search results across all pages, only populated if the client requests it
+   */
+  total_count?: number | null;
+}
+
+/**
+ * A numbered plan, which has to be approved by the user before the agent executes it.
+ */
+export interface Plan {
+  status: PlanStatus;
+  steps: PlanStep[];
+}
+
+export type PlanStatus = 'awaiting_approval' | 'approved' | 'rejected' | 'executed' | 'drafting';
+
+export interface PlanStep {
+  description: string;
+  status: PlanStepStatus;
+}
+
+export type PlanStepStatus = 'pending' | 'running' | 'done';
+
+/**
+ * A singular position in a text document
+ */
+export interface Point {
+  /**
+   * The byte index
+   */
+  byte: number;
+  /**
+   * Position within the line
+   */
+  column: number;
+  /**
+   * 0-indexed line number
+   */
+  line: number;
+}
+
+export interface QueryResponse {
+  /**
+   * Number of search results in this response
+   */
+  count: number;
+  /**
+   * Search result data
+   */
+  data: QueryResult[];
+  /**
+   * Paging metadata
+   */
+  metadata: PagingMetadata;
+  /**
+   * Stats for nerds
+   */
+  stats: ResultStats;
+}
+
+export type QueryResult = {
+  data: SnippedFile;
+  kind: 'snippets';
+} | {
+  data: RepositoryResultData;
+  kind: 'repository_result';
+} | {
+  data: FileResultData;
+  kind: 'file_result';
+} | {
+  data: FileData;
+  kind: 'file';
+} | {
+  data: DirectoryData;
+  kind: 'dir';
+} | {
+  data: string;
+  kind: 'flag';
+} | {
+  data: string;
+  kind: 'lang';
+};
+
+export interface Range_of_uint {
+  end: number;
+  start: number;
+}
+
+export interface RepositoryResultData {
+  name: HighlightedString;
+  repo_ref: string;
+}
+
+export interface ResultStats {
+  lang: Record<string, number>;
+  repo: Record<string, number>;
+}
+
+/**
+ * Tuning parameters for the retrieval stages of the agent.
+ *
+ * These can be configured per repository, and the effective values are recorded in every exchange so that answers can be evaluated against the settings that produced them.
+ */
+export interface RetrievalSettings {
+  /**
+   * Maximum number of results returned by lexical path search.
+   */
+  lexical_k?: number;
+  /**
+   * Maximum number of chunks to keep from any single file, if set.
+   */
+  max_chunks_per_file?: number | null;
+  /**
+   * Minimum similarity score for semantic code search results.
+   */
+  min_similarity?: number;
+  /**
+   * Maximum number of chunks returned by a semantic code search.
+   */
+  semantic_k?: number;
+}
+
+export type SearchStep = {
+  content: {
+    query: string;
+    response: string;
+  };
+  type: 'path';
+} | {
+  content: {
+    query: string;
+    response: string;
+  };
+  type: 'code';
+} | {
+  content: {
+    paths: string[];
+    query: string;
+    response: string;
+  };
+  type: 'proc';
+} | {
+  content: {
+    query: string;
+    response: string;
+  };
+  type: 'changes';
+} | {
+  content: {
+    query: string;
+    response: string;
+    returns?: string | null;
+  };
+  type: 'api';
+} | {
+  content: {
+    query: string;
+    response: string;
+  };
+  type: 'schema';
+} | {
+  content: {
+    content: string;
+    name: string;
+    response: string;
+  };
+  type: 'scratchpad';
+};
+
+export interface SemanticQuery {
+  branch: Literal[];
+  langs: Literal[];
+  paths: Literal[];
+  raw_query: string;
+  repos: Literal[];
+  target?: Literal | null;
+}
+
+export interface SnippedFile {
+  lang?: string | null;
+  relative_path: string;
+  repo_name: string;
+  repo_ref: string;
+  snippets: Snippet[];
+}
+
+export interface Snippet {
+  data: string;
+  highlights: Range_of_uint[];
+  line_range: Range_of_uint;
+  symbols: Symbol[];
+}
+
+export interface Symbol {
+  kind: string;
+  range: TextRange;
+}
+
+export interface TextRange {
+  end: Point;
+  start: Point;
+}
+
+/**
+ * A model call that chose the next function to call.
+ */
+export interface ToolSelection {
+  /**
+   * Whether the response was served from the response cache
+   */
+  cached: boolean;
+  function?: string | null;
+}
+
+/**
+ * The alternative configuration that an experiment tries out.
+ *
+ * Everything that is left unset behaves exactly like the control group.
+ */
+export interface Variant {
+  /**
+   * Extra instructions appended to the system prompt of the agent, which picks the tools.
+   */
+  agent_instructions?: string | null;
+  /**
+   * Extra instructions appended to the system prompt of the answer.
+   */
+  answer_instructions?: string | null;
+  /**
+   * Retrieval settings to use instead of the repository's own.
+   */
+  retrieval?: RetrievalSettings | null;
+}
@@ -220,14 +220,18 @@ export type RefDefDataItem = {
 export interface TokenInfoResponse {
   data: {
     file: string;
+    repo?: string;
     data: RefDefDataItem[];
   }[];
 }
 
 export type AllConversationsResponse = {
   created_at: number;
+  updated_at: number;
   thread_id: string;
   title: string;
+  pinned: boolean;
+  sort_order: number | null;
 }[];
 
 type ProcStep = {
@@ -245,7 +249,34 @@ type PathStep = {
   content: { query: string };
 };
 
-export type SearchStepType = ProcStep | CodeStep | PathStep;
+type ChangesStep = {
+  type: 'changes';
+  content: { query: string };
+};
+
+type ApiStep = {
+  type: 'api';
+  content: { query: string; returns: string | null };
+};
+
+type SchemaStep = {
+  type: 'schema';
+  content: { query: string };
+};
+
+type ScratchpadStep = {
+  type: 'scratchpad';
+  content: { name: string; content: string };
+};
+
+export type SearchStepType =
+  | ProcStep
+  | CodeStep
+  | PathStep
+  | ChangesStep
+  | ApiStep
+  | SchemaStep
+  | ScratchpadStep;
 
 export type ConversationType = {
   id: string;
@@ -283,6 +314,17 @@ export type ConversationType = {
   paths: string[];
   response_timestamp: string;
   focused_chunk: { file_path: string } | null;
+  plan?: PlanType;
+};
+
+export type PlanType = {
+  steps: { description: string; status: 'pending' | 'running' | 'done' }[];
+  status:
+    | 'drafting'
+    | 'awaiting_approval'
+    | 'approved'
+    | 'rejected'
+    | 'executed';
 };
 
 export type CodeStudioMessageType =
@@ -58,6 +58,8 @@ export enum SyncStatus {
 
 export enum RepoProvider {
   GitHub = 'github',
+  GitLab = 'gitlab',
+  Bitbucket = 'bitbucket',
   Local = 'local',
 }
 
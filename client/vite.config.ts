@@ -5,6 +5,8 @@ import { sentryVitePlugin } from '@sentry/vite-plugin';
 
 // https://vitejs.dev/config/
 export default defineConfig({
+  // Relative asset paths, so that the server can host the client under any base path
+  base: './',
   build: {
     sourcemap: true, // Source map generation must be turned on
   },
@@ -0,0 +1,24 @@
+use std::{env, fs, path::Path};
+
+use typify::{TypeSpace, TypeSpaceSettings};
+
+/// Written by the tests of `bleep`, from the types the server serializes.
+const SCHEMA: &str = "api-schema.json";
+
+fn main() {
+    println!("cargo:rerun-if-changed={SCHEMA}");
+
+    let schema = serde_json::from_str::<schemars::schema::RootSchema>(
+        &fs::read_to_string(SCHEMA).expect("failed to read the API schema"),
+    )
+    .expect("invalid API schema");
+
+    let mut types = TypeSpace::new(TypeSpaceSettings::default().with_struct_builder(false));
+    types
+        .add_root_schema(schema)
+        .expect("failed to generate types from the API schema");
+
+    let file = syn::parse2::<syn::File>(types.to_stream()).expect("generated invalid Rust");
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("types.rs");
+    fs::write(out, prettyplease::unparse(&file)).expect("failed to write generated types");
+}
@@ -0,0 +1,165 @@
+//! A typed client for the bloop server API.
+//!
+//! The response types are generated from the structs that the server serializes, so they can't
+//! drift apart from what the server sends.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), bleep_client::Error> {
+//! let client = bleep_client::Client::new("http://localhost:7878/api/")?;
+//! let results = client.search("lang:rust Indexer").await?;
+//! println!("{} results", results.count);
+//! # Ok(())
+//! # }
+//! ```
+
+use reqwest::StatusCode;
+use serde::{de::DeserializeOwned, Serialize};
+use url::Url;
+
+/// The types of the server API.
+#[allow(clippy::all)]
+pub mod types {
+    include!(concat!(env!("OUT_DIR"), "/types.rs"));
+}
+
+use types::{ConversationPage, EndpointError, Exchange, QueryResponse};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid URL: {0}")]
+    Url(#[from] url::ParseError),
+
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+
+    /// The server rejected the request
+    #[error("{status}: {}", .error.message)]
+    Api {
+        status: StatusCode,
+        error: EndpointError,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// How to list conversations, see [`Client::conversations`].
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct ListConversations {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo_ref: Option<String>,
+    /// One of `created`, `updated` or `title`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order_by: Option<String>,
+    pub page: usize,
+    /// Without this, every conversation is listed on a single page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_page: Option<usize>,
+    /// Only list conversations with every word of this in their title, queries or answers
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub q: String,
+    /// List the deleted conversations that can still be restored, rather than the others
+    pub deleted: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Client {
+    http: reqwest::Client,
+    /// The base of the API, ending with a slash, like `http://localhost:7878/api/`
+    base: Url,
+    token: Option<String>,
+}
+
+impl Client {
+    pub fn new(base: &str) -> Result<Self> {
+        let mut base = Url::parse(base)?;
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+
+        Ok(Self {
+            http: reqwest::Client::new(),
+            base,
+            token: None,
+        })
+    }
+
+    /// Authenticate as a user of a cloud instance.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub async fn conversations(&self, params: &ListConversations) -> Result<ConversationPage> {
+        self.get("answer/conversations", params).await
+    }
+
+    /// The exchanges of a conversation, with the responses of the search steps left out.
+    pub async fn thread(&self, thread_id: uuid::Uuid) -> Result<Vec<Exchange>> {
+        self.get(&format!("answer/conversations/{thread_id}"), &())
+            .await
+    }
+
+    /// Search for a query in the bloop query language.
+    pub async fn search(&self, q: &str) -> Result<QueryResponse> {
+        self.get("q", &[("q", q)]).await
+    }
+
+    /// Search the code of a repository by meaning, rather than by its text.
+    pub async fn semantic_search(&self, q: &str, repo_ref: &str) -> Result<QueryResponse> {
+        self.get("search/code", &[("q", q), ("repo_ref", repo_ref)])
+            .await
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str, query: &impl Serialize) -> Result<T> {
+        let mut request = self.http.get(self.base.join(path)?).query(query);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response.json().await?);
+        }
+
+        match response.json::<EndpointError>().await {
+            Ok(error) => Err(Error::Api { status, error }),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_paths_onto_the_api_base() {
+        let client = Client::new("http://localhost:7878/api").unwrap();
+        assert_eq!(
+            client.base.join("answer/conversations").unwrap().as_str(),
+            "http://localhost:7878/api/answer/conversations"
+        );
+    }
+
+    #[test]
+    fn reads_generated_types() {
+        let page: ConversationPage = serde_json::from_value(serde_json::json!({
+            "conversations": [{
+                "thread_id": "3f8d2b4e-7c1a-4c6e-9b0d-5a2f1e8c7d6b",
+                "created_at": 1697450000,
+                "updated_at": 1697450000,
+                "title": "Where is the indexer?",
+                "pinned": false,
+                "sort_order": null,
+            }],
+            "page": 0,
+            "per_page": null,
+            "page_count": 1,
+            "total_count": 1,
+        }))
+        .unwrap();
+
+        assert_eq!(page.conversations[0].title, "Where is the indexer?");
+    }
+}
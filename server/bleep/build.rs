@@ -30,6 +30,7 @@ fn set_index_version() {
         "src/semantic/schema.rs",
         "src/semantic/chunk.rs",
         "src/indexes/schema.rs",
+        "src/notebook.rs",
         "src/intelligence/scope_resolution.rs",
         "../languages.yml",
     ];
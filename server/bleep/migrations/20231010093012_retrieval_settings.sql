@@ -0,0 +1,9 @@
+-- Per-repository tuning of the agent's retrieval stages. Repositories without a row here use the
+-- defaults defined in `agent::retrieval::RetrievalSettings`.
+CREATE TABLE retrieval_settings (
+    repo_ref TEXT NOT NULL PRIMARY KEY,
+    lexical_k INTEGER NOT NULL,
+    semantic_k INTEGER NOT NULL,
+    min_similarity REAL NOT NULL,
+    max_chunks_per_file INTEGER
+);
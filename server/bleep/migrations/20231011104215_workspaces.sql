@@ -0,0 +1,38 @@
+-- Workspaces group repositories and users. Settings on the workspace are inherited by every
+-- repository in it, with `NULL` falling back to the application defaults.
+CREATE TABLE workspaces (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now')),
+
+    answer_model TEXT,
+    agent_model TEXT,
+    retention_days INTEGER,
+    daily_answer_quota INTEGER
+);
+
+-- `role` is one of `owner` or `member`. Only owners can change the workspace.
+CREATE TABLE workspace_members (
+    workspace_id INTEGER NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    user_id TEXT NOT NULL,
+    role TEXT NOT NULL,
+
+    PRIMARY KEY (workspace_id, user_id)
+);
+
+CREATE TABLE workspace_repos (
+    workspace_id INTEGER NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    repo_ref TEXT NOT NULL,
+
+    PRIMARY KEY (workspace_id, repo_ref)
+);
+
+-- Number of answers per user per day, used to enforce `daily_answer_quota`.
+CREATE TABLE workspace_usage (
+    workspace_id INTEGER NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    user_id TEXT NOT NULL,
+    day TEXT NOT NULL,
+    answers INTEGER NOT NULL DEFAULT 0,
+
+    PRIMARY KEY (workspace_id, user_id, day)
+);
@@ -0,0 +1,10 @@
+-- Parameterized questions, shared by everyone asking about a repository. Parameters are written as
+-- `{name}` in the template.
+CREATE TABLE question_templates (
+    id INTEGER PRIMARY KEY,
+    repo_ref TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    template TEXT NOT NULL,
+    modified_at DATETIME NOT NULL DEFAULT (datetime('now'))
+);
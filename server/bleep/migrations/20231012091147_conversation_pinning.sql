@@ -0,0 +1,8 @@
+-- Pinned conversations are listed first, followed by any manual `sort_order`.
+--
+-- Conversations are re-inserted on every new exchange, so `created_at` has been the time of the
+-- last update so far. We carry it over to `updated_at`, and preserve `created_at` from now on.
+ALTER TABLE conversations ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE conversations ADD COLUMN sort_order INTEGER;
+ALTER TABLE conversations ADD COLUMN updated_at INTEGER NOT NULL DEFAULT 0;
+UPDATE conversations SET updated_at = created_at;
@@ -0,0 +1,12 @@
+-- How many exchanges of a conversation each reader has seen.
+--
+-- Conversations are re-inserted whenever they change, so this is keyed on the conversation's
+-- owner and thread ID rather than its row ID.
+CREATE TABLE conversation_reads (
+    user_id TEXT NOT NULL,
+    thread_id TEXT NOT NULL,
+    reader_id TEXT NOT NULL,
+    exchanges_read INTEGER NOT NULL,
+
+    PRIMARY KEY (user_id, thread_id, reader_id)
+);
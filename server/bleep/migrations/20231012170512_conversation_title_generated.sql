@@ -0,0 +1,3 @@
+-- Titles that were regenerated by the LLM are kept when the conversation is stored again, instead
+-- of being replaced with the first line of the first query.
+ALTER TABLE conversations ADD COLUMN title_generated BOOLEAN NOT NULL DEFAULT FALSE;
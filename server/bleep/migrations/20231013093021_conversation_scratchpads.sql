@@ -0,0 +1,14 @@
+-- Named notes attached to a conversation, which are written by the agent or the user and included
+-- as context in later exchanges.
+--
+-- Like `conversation_reads`, these are keyed on the conversation's owner and thread ID, as the
+-- conversation row itself is replaced whenever it is stored.
+CREATE TABLE conversation_scratchpads (
+    user_id TEXT NOT NULL,
+    thread_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    content TEXT NOT NULL,
+    updated_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+
+    PRIMARY KEY (user_id, thread_id, name)
+);
@@ -0,0 +1,15 @@
+-- Which tools can be used on the repositories of a workspace. One of `read_only`, `read_suggest`
+-- or `read_write`, with `NULL` allowing everything.
+ALTER TABLE workspaces ADD COLUMN tool_policy TEXT;
+
+-- Every tool use that was denied by a workspace's tool policy, for auditing.
+CREATE TABLE workspace_tool_denials (
+    id INTEGER PRIMARY KEY,
+    workspace_id INTEGER NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    user_id TEXT NOT NULL,
+    repo_ref TEXT NOT NULL,
+    tool TEXT NOT NULL,
+    access TEXT NOT NULL,
+    policy TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now'))
+);
@@ -0,0 +1,12 @@
+-- Suggested workspaces for a user, computed periodically from the GitHub activity of the app
+-- installation. `repos` is a JSON array of repo refs.
+CREATE TABLE workspace_suggestions (
+    id INTEGER PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    repos TEXT NOT NULL,
+    activity INTEGER NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+
+CREATE INDEX workspace_suggestions_user_id ON workspace_suggestions (user_id);
@@ -0,0 +1,13 @@
+-- Filters that attach every matching GitHub repository to a workspace. Both `org` and `topic`
+-- have to match when set.
+CREATE TABLE workspace_repo_filters (
+    id INTEGER PRIMARY KEY,
+    workspace_id INTEGER NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    org TEXT,
+    topic TEXT,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now'))
+);
+
+-- The filter a repository was attached by, or `NULL` for repositories attached by hand.
+ALTER TABLE workspace_repos
+    ADD COLUMN filter_id INTEGER REFERENCES workspace_repo_filters(id) ON DELETE CASCADE;
@@ -0,0 +1,9 @@
+-- Responses to deterministic model calls, keyed by a hash of the full request.
+CREATE TABLE llm_response_cache (
+    prompt_hash TEXT PRIMARY KEY NOT NULL,
+    model TEXT,
+    response TEXT NOT NULL,
+    hits INTEGER NOT NULL DEFAULT 0,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    last_used_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
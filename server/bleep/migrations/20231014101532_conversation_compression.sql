@@ -0,0 +1,20 @@
+-- Exchanges are stored as zstd-compressed JSON in `exchanges_zstd`, leaving `exchanges` empty.
+-- Existing rows keep their plain JSON until they are compressed on startup, and are read from
+-- either column.
+--
+-- The statistics that were computed from the JSON are kept in their own columns, so that they can
+-- still be aggregated in SQL.
+ALTER TABLE conversations ADD COLUMN exchanges_zstd BLOB;
+ALTER TABLE conversations ADD COLUMN exchange_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE conversations ADD COLUMN answer_latencies_ms TEXT NOT NULL DEFAULT '[]';
+
+UPDATE conversations SET
+    exchange_count = json_array_length(exchanges),
+    answer_latencies_ms = (
+        SELECT json_group_array(ms) FROM (
+            SELECT (julianday(json_extract(e.value, '$.response_timestamp'))
+                - julianday(json_extract(e.value, '$.query_timestamp'))) * 86400000.0 AS ms
+            FROM json_each(conversations.exchanges) e
+        )
+        WHERE ms IS NOT NULL
+    );
@@ -0,0 +1,6 @@
+-- Every store of a conversation that changes its exchanges bumps its revision. The revision at
+-- which each exchange last changed is kept alongside, so that clients can sync only the changes.
+ALTER TABLE conversations ADD COLUMN revision INTEGER NOT NULL DEFAULT 0;
+
+-- JSON list of `{ id, hash, revision }`, in the order of the exchanges
+ALTER TABLE conversations ADD COLUMN exchange_revisions TEXT NOT NULL DEFAULT '[]';
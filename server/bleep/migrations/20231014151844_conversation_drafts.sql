@@ -0,0 +1,13 @@
+-- The question a user is typing in a conversation, synced between their devices.
+--
+-- Every write bumps the revision, so that a client can tell whether it overwrote a draft written
+-- on another device that it hadn't seen yet.
+CREATE TABLE conversation_drafts (
+    user_id TEXT NOT NULL,
+    thread_id TEXT NOT NULL,
+    content TEXT NOT NULL,
+    revision INTEGER NOT NULL DEFAULT 1,
+    updated_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+
+    PRIMARY KEY (user_id, thread_id)
+);
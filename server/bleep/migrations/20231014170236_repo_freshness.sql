@@ -0,0 +1,23 @@
+-- How far the index of a repository is behind its upstream default branch, as last checked.
+CREATE TABLE repo_freshness (
+    repo_ref TEXT PRIMARY KEY NOT NULL,
+    behind_commits INTEGER NOT NULL,
+    -- Commit time of the oldest upstream commit that isn't indexed, if any
+    oldest_unindexed_at DATETIME,
+    checked_at DATETIME NOT NULL DEFAULT (datetime('now'))
+);
+
+-- Repositories are stale once their oldest unindexed commit is older than this.
+ALTER TABLE workspaces ADD COLUMN stale_after_days INTEGER;
+
+-- Alerts for the members of a workspace. A notification is resolved when its cause goes away, such
+-- as a stale repository being indexed again.
+CREATE TABLE workspace_notifications (
+    id INTEGER PRIMARY KEY,
+    workspace_id INTEGER NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    repo_ref TEXT,
+    message TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    resolved_at DATETIME
+);
@@ -0,0 +1,17 @@
+-- Notifications without a workspace are for every user of this instance, like low disk space
+-- warnings.
+CREATE TABLE notifications (
+    id INTEGER PRIMARY KEY,
+    workspace_id INTEGER REFERENCES workspaces(id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    repo_ref TEXT,
+    message TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    resolved_at DATETIME
+);
+
+INSERT INTO notifications (id, workspace_id, kind, repo_ref, message, created_at, resolved_at)
+SELECT id, workspace_id, kind, repo_ref, message, created_at, resolved_at
+FROM workspace_notifications;
+
+DROP TABLE workspace_notifications;
@@ -0,0 +1,4 @@
+-- Directories of a repository that a workspace is about, as a JSON array, or `NULL` for the whole
+-- repository. Repositories are only indexed sparsely when every workspace they are attached to
+-- has paths.
+ALTER TABLE workspace_repos ADD COLUMN paths TEXT;
@@ -0,0 +1,22 @@
+-- Experiments try alternative prompt and retrieval configurations on a share of conversations.
+CREATE TABLE experiments (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    traffic_percent INTEGER NOT NULL,
+    variant TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    stopped_at DATETIME
+);
+
+-- Every query answered while an experiment ran, with the arm it was answered with.
+CREATE TABLE experiment_assignments (
+    experiment_id INTEGER NOT NULL REFERENCES experiments(id) ON DELETE CASCADE,
+    query_id TEXT NOT NULL,
+    arm TEXT NOT NULL,
+    latency_ms INTEGER,
+    vote TEXT,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    PRIMARY KEY (experiment_id, query_id)
+);
+
+CREATE INDEX experiment_assignments_query_id ON experiment_assignments (query_id);
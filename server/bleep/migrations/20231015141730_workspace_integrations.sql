@@ -0,0 +1,10 @@
+-- Issue trackers that tickets can be filed in from a workspace's conversations. The API token is
+-- encrypted with the instance key, and bound to its workspace and kind.
+CREATE TABLE workspace_integrations (
+    workspace_id INTEGER NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    settings TEXT NOT NULL,
+    sealed_token BLOB NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    PRIMARY KEY (workspace_id, kind)
+);
@@ -0,0 +1,26 @@
+-- Tenants isolate the users and repositories of customers sharing an instance, when
+-- `--tenant-isolation` is on.
+CREATE TABLE tenants (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now'))
+);
+
+-- A user belongs to a single tenant. `role` is one of `admin` or `member`, and only admins can
+-- change the members of the tenant.
+CREATE TABLE tenant_members (
+    user_id TEXT PRIMARY KEY NOT NULL,
+    tenant_id INTEGER NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    role TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now'))
+);
+
+-- A repository belongs to a single tenant, and so do its indexes and embeddings.
+CREATE TABLE tenant_repos (
+    repo_ref TEXT PRIMARY KEY NOT NULL,
+    tenant_id INTEGER NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE INDEX tenant_members_tenant ON tenant_members (tenant_id);
+CREATE INDEX tenant_repos_tenant ON tenant_repos (tenant_id);
@@ -0,0 +1,18 @@
+-- Full-text index over the titles, queries and answers of conversations, keyed by the `id` of
+-- the conversation. Exchanges are stored compressed, so their text is indexed by the server when
+-- a conversation is stored, while triggers keep titles and deletions in sync.
+--
+-- Conversations stored before this index existed are added to it on startup.
+CREATE VIRTUAL TABLE conversations_fts USING fts5(
+    title,
+    content,
+    tokenize = 'porter unicode61'
+);
+
+CREATE TRIGGER conversations_fts_delete AFTER DELETE ON conversations BEGIN
+    DELETE FROM conversations_fts WHERE rowid = old.id;
+END;
+
+CREATE TRIGGER conversations_fts_title AFTER UPDATE OF title ON conversations BEGIN
+    UPDATE conversations_fts SET title = new.title WHERE rowid = new.id;
+END;
@@ -0,0 +1,3 @@
+-- Deleted conversations stay around for a while, so that they can be restored.
+ALTER TABLE conversations ADD COLUMN deleted_at INTEGER;
+CREATE INDEX conversations_deleted_at ON conversations (deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,16 @@
+-- Deleted workspaces stay around for a while, so that an admin can restore them.
+ALTER TABLE workspaces ADD COLUMN deleted_at DATETIME;
+ALTER TABLE workspaces ADD COLUMN deleted_by TEXT;
+CREATE INDEX workspaces_deleted_at ON workspaces (deleted_at) WHERE deleted_at IS NOT NULL;
+
+-- Changes to workspaces that admins may need to account for. Entries outlive their workspace, so
+-- there is no foreign key.
+CREATE TABLE audit_log (
+    id INTEGER PRIMARY KEY,
+    workspace_id INTEGER,
+    user_id TEXT,
+    action TEXT NOT NULL,
+    detail TEXT,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now'))
+);
+CREATE INDEX audit_log_workspace_id ON audit_log (workspace_id);
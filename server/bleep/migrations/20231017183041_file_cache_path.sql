@@ -0,0 +1,3 @@
+-- The path of every cached entry, so that entries of unchanged paths can be kept without walking
+-- them again. Entries cached before this are re-walked once.
+ALTER TABLE file_cache ADD COLUMN path TEXT;
@@ -0,0 +1,11 @@
+-- Scheduled health reports of indexed repositories, kept as a history per repository.
+CREATE TABLE repo_health_reports (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    repo_ref TEXT NOT NULL,
+    indexed_commit TEXT,
+    report TEXT NOT NULL,
+    summary TEXT,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX repo_health_reports_repo_ref ON repo_health_reports (repo_ref, created_at);
@@ -0,0 +1,17 @@
+-- The tokens of every model call made while answering, for attributing spend to conversations
+-- and users. `cost` is NULL for models without a known price.
+CREATE TABLE token_usage (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id TEXT NOT NULL,
+    thread_id TEXT NOT NULL,
+    exchange_id TEXT NOT NULL,
+    model TEXT NOT NULL,
+    step TEXT NOT NULL,
+    prompt_tokens INTEGER NOT NULL,
+    completion_tokens INTEGER NOT NULL,
+    cost REAL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX token_usage_thread ON token_usage (user_id, thread_id);
+CREATE INDEX token_usage_created_at ON token_usage (created_at);
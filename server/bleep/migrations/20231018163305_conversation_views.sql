@@ -0,0 +1,13 @@
+-- Every time a workspace member opened a conversation of another member, for reviewing who saw
+-- what. Views outlive their conversation and workspace, so there are no foreign keys.
+CREATE TABLE conversation_views (
+    id INTEGER PRIMARY KEY,
+    workspace_id INTEGER NOT NULL,
+    user_id TEXT NOT NULL,
+    thread_id TEXT NOT NULL,
+    viewer_id TEXT NOT NULL,
+    exchanges_seen INTEGER NOT NULL,
+    viewed_at DATETIME NOT NULL DEFAULT (datetime('now'))
+);
+CREATE INDEX conversation_views_viewed_at ON conversation_views (viewed_at);
+CREATE INDEX conversation_views_thread ON conversation_views (user_id, thread_id);
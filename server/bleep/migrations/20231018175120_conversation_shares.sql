@@ -0,0 +1,14 @@
+-- Read-only links to conversations, which anyone with the link can open without signing in.
+--
+-- Only a hash of each token is stored, so that the links can't be recovered from the database.
+-- Revoked shares are kept, to tell a revoked link from one that never existed.
+CREATE TABLE conversation_shares (
+    id INTEGER PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    thread_id TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    expires_at INTEGER,
+    revoked_at INTEGER
+);
+CREATE INDEX conversation_shares_thread ON conversation_shares (user_id, thread_id);
@@ -0,0 +1,6 @@
+-- Stop-words and boosts applied to lexical searches of a repository, as JSON. Repositories without
+-- a row here are searched without rules.
+CREATE TABLE lexical_rules (
+    repo_ref TEXT NOT NULL PRIMARY KEY,
+    rules TEXT NOT NULL
+);
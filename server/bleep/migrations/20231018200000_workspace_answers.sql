@@ -0,0 +1,2 @@
+-- Caps the chunks of any single repository when answering across a workspace.
+ALTER TABLE retrieval_settings ADD COLUMN max_chunks_per_repo INTEGER;
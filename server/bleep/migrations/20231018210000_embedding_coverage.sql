@@ -0,0 +1,11 @@
+-- What the last index of each repository embedded, see `semantic::coverage`.
+CREATE TABLE embedding_coverage (
+    repo_ref TEXT NOT NULL PRIMARY KEY,
+    -- JSON-encoded `semantic::coverage::Run`
+    last_run TEXT NOT NULL,
+    finished_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    -- The last index that embedded at least one chunk
+    last_embedded_at INTEGER,
+    -- The last index that failed to embed or store at least one chunk
+    last_failed_at INTEGER
+);
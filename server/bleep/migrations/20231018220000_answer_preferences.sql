@@ -0,0 +1,5 @@
+-- Per-user preferences for how answers are written, see `webserver::answer::preferences`.
+CREATE TABLE answer_preferences (
+    user_id TEXT NOT NULL PRIMARY KEY,
+    comment_language TEXT
+);
@@ -0,0 +1,18 @@
+-- Deliveries of the push webhooks, see `webserver::repos::webhooks`. Only the latest deliveries of
+-- each webhook are kept.
+CREATE TABLE webhook_deliveries (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    -- `github`, `gitlab` or `sync_hook`
+    source TEXT NOT NULL,
+    event TEXT,
+    repo_ref TEXT,
+    branch TEXT,
+    is_head BOOLEAN,
+    status INTEGER NOT NULL,
+    outcome TEXT NOT NULL,
+    latency_ms INTEGER NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    redelivery_of INTEGER
+);
+
+CREATE INDEX webhook_deliveries_source ON webhook_deliveries (source, id);
@@ -0,0 +1,3 @@
+-- Conversations forked from another start with a copy of its first `forked_at` exchanges.
+ALTER TABLE conversations ADD COLUMN forked_from TEXT;
+ALTER TABLE conversations ADD COLUMN forked_at INTEGER;
@@ -0,0 +1,28 @@
+-- Personal access tokens, see `webserver::tokens`.
+--
+-- Only a hash of each token is stored. `scopes` is a space-separated list, like
+-- `read:search write:ask`.
+CREATE TABLE personal_access_tokens (
+    id INTEGER PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    expires_at INTEGER,
+    last_used_at INTEGER,
+    revoked_at INTEGER
+);
+CREATE INDEX personal_access_tokens_user ON personal_access_tokens (user_id);
+
+-- The latest requests made with each token, including refused ones.
+CREATE TABLE personal_access_token_requests (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    token_id INTEGER NOT NULL REFERENCES personal_access_tokens (id) ON DELETE CASCADE,
+    method TEXT NOT NULL,
+    path TEXT NOT NULL,
+    status INTEGER NOT NULL,
+    latency_ms INTEGER NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+CREATE INDEX personal_access_token_requests_token ON personal_access_token_requests (token_id, id);
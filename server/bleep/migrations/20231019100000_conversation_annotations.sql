@@ -0,0 +1,21 @@
+-- Notes by members of a workspace on ranges of characters of the answers of its conversations, see
+-- `webserver::workspace::annotations`. They are kept apart from the exchanges, so that annotating
+-- doesn't rewrite the conversation of another user.
+CREATE TABLE conversation_annotations (
+    id INTEGER PRIMARY KEY,
+    workspace_id INTEGER NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    -- The owner of the conversation
+    user_id TEXT NOT NULL,
+    thread_id TEXT NOT NULL,
+    exchange_id TEXT NOT NULL,
+    start_char INTEGER NOT NULL,
+    end_char INTEGER NOT NULL,
+    quote TEXT NOT NULL,
+    body TEXT NOT NULL,
+    author_id TEXT NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    updated_at INTEGER,
+    resolved_at INTEGER,
+    resolved_by TEXT
+);
+CREATE INDEX conversation_annotations_thread ON conversation_annotations (workspace_id, thread_id);
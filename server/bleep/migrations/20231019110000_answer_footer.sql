@@ -0,0 +1,6 @@
+CREATE TABLE answer_footer (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    template TEXT NOT NULL,
+    updated_by TEXT,
+    updated_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
@@ -0,0 +1,10 @@
+CREATE TABLE repo_channel_messages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    repo_ref TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    question TEXT NOT NULL,
+    answer TEXT NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+
+CREATE INDEX repo_channel_messages_repo ON repo_channel_messages (repo_ref, id);
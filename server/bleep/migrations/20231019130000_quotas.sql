@@ -0,0 +1,7 @@
+CREATE TABLE quotas (
+    user_id TEXT NOT NULL,
+    day TEXT NOT NULL,
+    llm_calls INTEGER NOT NULL DEFAULT 0,
+    indexing_jobs INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (user_id, day)
+);
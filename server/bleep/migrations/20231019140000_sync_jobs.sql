@@ -0,0 +1,14 @@
+CREATE TABLE sync_jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    repo_ref TEXT NOT NULL,
+    state TEXT NOT NULL,
+    shallow BOOLEAN NOT NULL,
+    force BOOLEAN NOT NULL,
+    error TEXT,
+    retry_of INTEGER,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    started_at INTEGER,
+    finished_at INTEGER
+);
+
+CREATE INDEX sync_jobs_state ON sync_jobs (state);
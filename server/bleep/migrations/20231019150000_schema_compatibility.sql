@@ -0,0 +1,7 @@
+-- The oldest migration a version of bloop needs to know of to open this database, recorded by
+-- the newest version that opened it. Older versions check it before starting on a database that
+-- a newer version migrated.
+CREATE TABLE schema_compatibility (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    compatible_since INTEGER NOT NULL
+);
@@ -0,0 +1,16 @@
+CREATE TABLE reindex_campaigns (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    -- JSON of the filter that picked the repositories
+    filter TEXT NOT NULL,
+    parallelism INTEGER NOT NULL,
+    -- running, done, cancelled or interrupted
+    state TEXT NOT NULL,
+    -- The number of repositories picked
+    total INTEGER NOT NULL,
+    created_by TEXT,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    finished_at INTEGER
+);
+
+ALTER TABLE sync_jobs ADD COLUMN campaign_id INTEGER REFERENCES reindex_campaigns (id);
+CREATE INDEX sync_jobs_campaign_id ON sync_jobs (campaign_id);
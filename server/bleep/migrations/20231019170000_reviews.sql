@@ -0,0 +1,22 @@
+-- Exchanges sampled for human review, see `webserver::answer::reviews`. The question and answer are
+-- copied, so that a review outlives edits to the conversation, and its deletion.
+CREATE TABLE reviews (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    -- The owner of the conversation
+    user_id TEXT NOT NULL,
+    thread_id TEXT NOT NULL,
+    exchange_id TEXT NOT NULL UNIQUE,
+    -- Joins experiment_assignments, to compare the grades of experiment arms
+    query_id TEXT NOT NULL,
+    repo_ref TEXT NOT NULL,
+    query TEXT NOT NULL,
+    answer TEXT NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    reviewer_id TEXT,
+    claimed_at INTEGER,
+    grade INTEGER CHECK (grade BETWEEN 1 AND 5),
+    comment TEXT,
+    graded_at INTEGER
+);
+
+CREATE INDEX reviews_graded_at ON reviews (graded_at);
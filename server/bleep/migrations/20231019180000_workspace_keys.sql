@@ -0,0 +1,18 @@
+-- API keys of workspaces, see `webserver::workspace::keys`. Keys act as a principal of their own,
+-- `key:<id>`, rather than as the member who created them.
+--
+-- Only a hash of each key is stored. `scopes` is a space-separated list, like
+-- `search:read repos:sync`.
+CREATE TABLE workspace_keys (
+    id INTEGER PRIMARY KEY,
+    workspace_id INTEGER NOT NULL REFERENCES workspaces (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT NOT NULL,
+    created_by TEXT NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    expires_at INTEGER,
+    last_used_at INTEGER,
+    revoked_at INTEGER
+);
+CREATE INDEX workspace_keys_workspace ON workspace_keys (workspace_id);
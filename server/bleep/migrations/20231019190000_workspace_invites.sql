@@ -0,0 +1,21 @@
+-- Users only become members of a workspace once they accept an invite from one of its owners.
+CREATE TABLE workspace_invites (
+    workspace_id INTEGER NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    user_id TEXT NOT NULL,
+    role TEXT NOT NULL,
+    invited_by TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now')),
+
+    PRIMARY KEY (workspace_id, user_id)
+);
+
+-- Conversations that their author shared with a workspace. Other members can only read these, and
+-- only while the author is still a member.
+CREATE TABLE workspace_conversations (
+    workspace_id INTEGER NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    user_id TEXT NOT NULL,
+    thread_id TEXT NOT NULL,
+    shared_at DATETIME NOT NULL DEFAULT (datetime('now')),
+
+    PRIMARY KEY (workspace_id, user_id, thread_id)
+);
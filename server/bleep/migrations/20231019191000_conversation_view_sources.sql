@@ -0,0 +1,25 @@
+-- Every read of a conversation is logged, not only those by workspace members. `source` is where
+-- it was read: `workspace`, `link` for shared links, `export`, or `api` for the endpoints that
+-- page through exchanges. Links have no viewer, and record the share they were opened with.
+CREATE TABLE conversation_views_new (
+    id INTEGER PRIMARY KEY,
+    workspace_id INTEGER,
+    user_id TEXT NOT NULL,
+    thread_id TEXT NOT NULL,
+    viewer_id TEXT,
+    share_id INTEGER,
+    source TEXT NOT NULL DEFAULT 'workspace',
+    exchanges_seen INTEGER NOT NULL,
+    viewed_at DATETIME NOT NULL DEFAULT (datetime('now'))
+);
+
+INSERT INTO conversation_views_new
+    (id, workspace_id, user_id, thread_id, viewer_id, exchanges_seen, viewed_at)
+SELECT id, workspace_id, user_id, thread_id, viewer_id, exchanges_seen, viewed_at
+FROM conversation_views;
+
+DROP TABLE conversation_views;
+ALTER TABLE conversation_views_new RENAME TO conversation_views;
+
+CREATE INDEX conversation_views_viewed_at ON conversation_views (viewed_at);
+CREATE INDEX conversation_views_thread ON conversation_views (user_id, thread_id);
@@ -8,27 +8,50 @@ use tracing::{debug, error, info, instrument};
 use crate::{
     analytics::{EventData, QueryEvent},
     indexes::reader::{ContentDocument, FileDocument},
+    intelligence::structural,
     llm_gateway::{self, api::FunctionCall},
+    metrics,
     query::{parser, stopwords::remove_stopwords},
-    repo::RepoRef,
+    repo::{history, RepoRef},
     semantic,
     webserver::{
-        answer::conversations::{self, ConversationId},
+        answer::{
+            conversations::{self, ConversationId},
+            experiments, reviews,
+            scratchpads::Scratchpad,
+        },
         middleware::User,
+        workspace,
     },
     Application,
 };
 
-use self::exchange::{Exchange, SearchStep, Update};
+use self::{
+    deadline::Deadline,
+    exchange::{Exchange, SearchStep, ToolSelection, Update},
+    experiment::Variant,
+    retrieval::RetrievalSettings,
+};
 
 /// The maximum number of steps the agent will take before forcing an answer.
-const MAX_STEPS: usize = 10;
+pub(crate) const MAX_STEPS: usize = 10;
+
+/// The longest a single tool call may take, if the request deadline allows it.
+const TOOL_TIMEOUT: Duration = Duration::from_secs(60);
 
+pub mod attachment;
+mod citations;
+pub mod deadline;
 pub mod exchange;
+pub mod experiment;
 pub mod model;
+pub mod policy;
 pub mod prompts;
+pub mod response_cache;
+pub mod retrieval;
 pub mod symbol;
 pub mod transcoder;
+pub mod usage;
 
 /// A collection of modules that each add methods to `Agent`.
 ///
@@ -37,9 +60,16 @@ pub mod transcoder;
 /// tests.
 mod tools {
     pub mod answer;
+    pub mod api;
+    pub mod changes;
     pub mod code;
+    pub mod history;
     pub mod path;
+    pub mod plan;
     pub mod proc;
+    pub mod schema;
+    pub mod scratchpad;
+    pub mod structural;
 }
 
 pub enum Error {
@@ -61,6 +91,27 @@ pub struct Agent {
     pub answer_model: model::LLMModel,
     pub agent_model: model::LLMModel,
 
+    /// Notes attached to this conversation, which are included in the context of every step.
+    pub scratchpads: Vec<Scratchpad>,
+
+    /// The tools that the workspaces of this repository allow, if it belongs to any.
+    pub(crate) tool_rules: Option<workspace::ToolRules>,
+
+    /// The other repositories of the workspace, when answering across the whole workspace.
+    ///
+    /// Paths in these repositories are prefixed with the name of the repository, so that the
+    /// model can tell them apart from paths in `repo_ref`.
+    pub workspace_repos: Vec<RepoRef>,
+
+    /// The exchanges after the one being answered, when an earlier exchange is retried.
+    ///
+    /// These are stored along with `exchanges`, but they aren't part of the conversation that the
+    /// model sees.
+    pub later_exchanges: Vec<Exchange>,
+
+    /// Every tool call and model request has to finish by this deadline.
+    pub deadline: Deadline,
+
     /// Indicate whether the request was answered.
     ///
     /// This is used in the `Drop` handler, in order to track cancelled answer queries.
@@ -105,6 +156,10 @@ impl Drop for Agent {
 
             ExchangeState::Complete => {
                 tokio::spawn(self.store());
+
+                if let Some(candidate) = self.review_candidate() {
+                    tokio::spawn(reviews::sample(self.app.sql.clone(), candidate));
+                }
             }
         }
     }
@@ -153,6 +208,46 @@ impl Agent {
         self.exchanges.last_mut().expect("exchange list was empty")
     }
 
+    fn retrieval(&self) -> RetrievalSettings {
+        self.last_exchange().retrieval.unwrap_or_default()
+    }
+
+    fn variant(&self) -> Option<&Variant> {
+        self.last_exchange().variant()
+    }
+
+    /// Every repository this agent searches, starting with the one the question was asked in.
+    fn repos(&self) -> impl Iterator<Item = &RepoRef> {
+        std::iter::once(&self.repo_ref).chain(&self.workspace_repos)
+    }
+
+    /// The path shown to the model for a file of a repository.
+    fn qualify_path(&self, repo_ref: &str, path: String) -> String {
+        if self.workspace_repos.is_empty() || repo_ref == self.repo_ref.to_string() {
+            return path;
+        }
+
+        let name = repo_ref
+            .parse::<RepoRef>()
+            .map(|r| r.display_name())
+            .unwrap_or_else(|_| repo_ref.to_owned());
+
+        format!("{name}/{path}")
+    }
+
+    /// The repository a path shown to the model belongs to, and the path within it.
+    fn resolve_path<'a>(&self, path: &'a str) -> (&RepoRef, &'a str) {
+        self.workspace_repos
+            .iter()
+            .find_map(|repo_ref| {
+                let path = path
+                    .strip_prefix(&repo_ref.display_name())?
+                    .strip_prefix('/')?;
+                Some((repo_ref, path))
+            })
+            .unwrap_or((&self.repo_ref, path))
+    }
+
     fn paths(&self) -> impl Iterator<Item = &str> {
         self.exchanges
             .iter()
@@ -177,6 +272,14 @@ impl Agent {
     pub async fn step(&mut self, action: Action) -> Result<Option<Action>> {
         info!(?action, %self.thread_id, "executing next action");
 
+        let _timer = metrics::AGENT_STEP_SECONDS
+            .with_label_values(&[action.name()])
+            .start_timer();
+
+        if self.deadline.is_expired() {
+            return Err(anyhow!("request deadline exceeded"));
+        }
+
         match &action {
             Action::Query(s) => {
                 self.track_query(EventData::input_stage("query").with_payload("q", s));
@@ -201,9 +304,20 @@ impl Agent {
                 return Ok(None);
             }
 
-            Action::Path { query } => self.path_search(query).await?,
-            Action::Code { query } => self.code_search(query).await?,
-            Action::Proc { query, paths } => self.process_files(query, paths).await?,
+            Action::Plan(s) => {
+                self.track_query(EventData::input_stage("plan query").with_payload("q", s));
+                self.plan(s).await.context("plan action failed")?;
+                return Ok(None);
+            }
+
+            Action::ExecutePlan => {
+                self.execute_plan().await.context("plan execution failed")?;
+                return Ok(Some(Action::Answer {
+                    paths: self.paths().enumerate().map(|(i, _)| i).collect(),
+                }));
+            }
+
+            tool => self.run_tool(tool).await?,
         };
 
         if self.last_exchange().search_steps.len() >= MAX_STEPS {
@@ -212,21 +326,146 @@ impl Agent {
             }));
         }
 
+        self.next_action(None).await.map(Some)
+    }
+
+    /// Run a function that was called by the model, returning its response.
+    ///
+    /// Functions that are not allowed by the workspace tool policy are not run. The model is told
+    /// about the denial instead, so that it can choose a different function.
+    async fn run_tool(&mut self, action: &Action) -> Result<String> {
+        if let Some(rules) = &self.tool_rules {
+            let user_id = self.user.username().context("didn't have user ID")?;
+            let (tool, access) = (action.name(), action.access());
+
+            if !rules
+                .authorize(&self.app.sql, user_id, &self.repo_ref, tool, access)
+                .await?
+            {
+                self.track_query(
+                    EventData::input_stage("tool denied")
+                        .with_payload("tool", tool)
+                        .with_payload("policy", rules.policy().as_str()),
+                );
+
+                return Ok(format!(
+                    "functions.{tool} is not allowed in this repository. Call a different function"
+                ));
+            }
+        }
+
+        let what = format!("functions.{}", action.name());
+        let deadline = self.deadline;
+        let call = async {
+            match action {
+                Action::Path { query } => self.path_search(query).await,
+                Action::Code { query } => self.code_search(query).await,
+                Action::Proc { query, paths } => self.process_files(query, paths).await,
+                Action::Changes { query } => self.changes_search(query).await,
+                Action::History(query) => self.git_history(query).await,
+                Action::Structural(pattern) => self.structural_search(pattern).await,
+                Action::Api { query, returns } => self.api_search(query, returns).await,
+                Action::Schema { query } => self.schema_search(query).await,
+                Action::Scratchpad { name, content } => self.write_scratchpad(name, content).await,
+                Action::Query(..)
+                | Action::Answer { .. }
+                | Action::Plan(..)
+                | Action::ExecutePlan => Err(anyhow!("action is not a function: {action:?}")),
+            }
+        };
+
+        deadline.run(&what, TOOL_TIMEOUT, call).await?
+    }
+
+    /// Ask the model which function to call next, optionally with an extra instruction.
+    ///
+    /// Deterministic calls are served from the response cache when the same history was seen
+    /// before.
+    async fn next_action(&mut self, instruction: Option<&str>) -> Result<Action> {
         let functions = serde_json::from_value::<Vec<llm_gateway::api::Function>>(
-            prompts::functions(self.paths().next().is_some()), // Only add proc if there are paths in context
+            // Only add proc if there are paths in context, and changes if there is a working tree
+            prompts::functions(self.paths().next().is_some(), self.repo_ref.is_local()),
         )
         .unwrap();
 
-        let mut history = vec![llm_gateway::api::Message::system(&prompts::system(
-            self.paths(),
-        ))];
+        let mut system_prompt = prompts::system(self.paths(), &self.scratchpads);
+        if let Some(instructions) = self.variant().and_then(|v| v.agent_instructions.as_ref()) {
+            system_prompt = format!("{system_prompt}\n\n{instructions}");
+        }
+
+        let mut history = vec![llm_gateway::api::Message::system(&system_prompt)];
         history.extend(self.history()?);
+        history.extend(instruction.map(llm_gateway::api::Message::user));
 
         let trimmed_history = trim_history(history.clone(), self.agent_model)?;
 
-        let raw_response = self
-            .llm_gateway
-            .chat_stream(&trimmed_history, Some(&functions))
+        let cache_key = response_cache::key(&self.llm_gateway, &trimmed_history, Some(&functions));
+        let cached = match &cache_key {
+            Some(key) => response_cache::get(&self.app.sql, key)
+                .await
+                .map_err(|err| error!(?err, "failed to read LLM response cache"))
+                .ok()
+                .flatten()
+                .and_then(|response| serde_json::from_str::<FunctionCall>(&response).ok()),
+            None => None,
+        };
+
+        let is_cached = cached.is_some();
+        let raw_response = match cached {
+            Some(response) => response,
+            None => self.call_function(&trimmed_history, &functions).await?,
+        };
+
+        if !is_cached {
+            self.record_usage(
+                "agent",
+                self.agent_model.model_name,
+                self.agent_model.tokenizer,
+                &trimmed_history,
+                Some(&functions),
+                &serde_json::to_string(&raw_response)?,
+            )
+            .await;
+        }
+
+        self.track_query(
+            EventData::output_stage("llm_reply")
+                .with_payload("full_history", &history)
+                .with_payload("trimmed_history", &trimmed_history)
+                .with_payload("last_message", history.last())
+                .with_payload("functions", &functions)
+                .with_payload("raw_response", &raw_response)
+                .with_payload("model", &self.llm_gateway.model)
+                .with_payload("cached", is_cached),
+        );
+
+        let action =
+            Action::deserialize_gpt(&raw_response).context("failed to deserialize LLM output")?;
+
+        if let (Some(key), false) = (&cache_key, is_cached) {
+            let response = serde_json::to_string(&raw_response)?;
+            let model = self.llm_gateway.model.as_deref();
+            if let Err(err) = response_cache::put(&self.app.sql, key, model, &response).await {
+                error!(?err, "failed to write LLM response cache");
+            }
+        }
+
+        self.update(Update::ToolSelection(ToolSelection {
+            function: raw_response.name.clone(),
+            cached: is_cached,
+        }))
+        .await?;
+
+        Ok(action)
+    }
+
+    async fn call_function(
+        &self,
+        messages: &[llm_gateway::api::Message],
+        functions: &[llm_gateway::api::Function],
+    ) -> Result<FunctionCall> {
+        self.llm_gateway
+            .chat_stream(messages, Some(functions))
             .await?
             .try_fold(
                 llm_gateway::api::FunctionCall::default(),
@@ -246,22 +485,7 @@ impl Agent {
                 },
             )
             .await
-            .context("failed to fold LLM function call output")?;
-
-        self.track_query(
-            EventData::output_stage("llm_reply")
-                .with_payload("full_history", &history)
-                .with_payload("trimmed_history", &trimmed_history)
-                .with_payload("last_message", history.last())
-                .with_payload("functions", &functions)
-                .with_payload("raw_response", &raw_response)
-                .with_payload("model", &self.llm_gateway.model),
-        );
-
-        let action =
-            Action::deserialize_gpt(&raw_response).context("failed to deserialize LLM output")?;
-
-        Ok(Some(action))
+            .context("failed to fold LLM function call output")
     }
 
     /// The full history of messages, including intermediate function calls
@@ -291,6 +515,29 @@ impl Agent {
                             "code".to_owned(),
                             format!("{{\n \"query\": \"{query}\"\n}}"),
                         ),
+                        SearchStep::Changes { query, .. } => (
+                            "changes".to_owned(),
+                            format!("{{\n \"query\": \"{query}\"\n}}"),
+                        ),
+                        SearchStep::History { query, .. } => {
+                            ("history".to_owned(), serde_json::json!(query).to_string())
+                        }
+                        SearchStep::Structural { pattern, .. } => (
+                            "structural".to_owned(),
+                            serde_json::json!(pattern).to_string(),
+                        ),
+                        SearchStep::Schema { query, .. } => (
+                            "schema".to_owned(),
+                            format!("{{\n \"query\": \"{query}\"\n}}"),
+                        ),
+                        SearchStep::Api { query, returns, .. } => (
+                            "api".to_owned(),
+                            serde_json::json!({ "query": query, "returns": returns }).to_string(),
+                        ),
+                        SearchStep::Scratchpad { name, content, .. } => (
+                            "scratchpad".to_owned(),
+                            serde_json::json!({ "name": name, "content": content }).to_string(),
+                        ),
                         SearchStep::Proc { query, paths, .. } => (
                             "proc".to_owned(),
                             format!(
@@ -348,8 +595,8 @@ impl Agent {
         params: semantic::SemanticSearchParams,
     ) -> Result<Vec<semantic::Payload>> {
         let paths_set = paths
-            .into_iter()
-            .map(|p| parser::Literal::Plain(p.into()))
+            .iter()
+            .map(|p| parser::Literal::Plain(self.resolve_path(p).1.to_owned().into()))
             .collect::<Vec<_>>();
 
         let paths = if paths_set.is_empty() {
@@ -384,13 +631,39 @@ impl Agent {
 
         let query = parser::SemanticQuery {
             target: Some(query),
-            repos: [parser::Literal::Plain(self.repo_ref.display_name().into())].into(),
+            repos: self
+                .repos()
+                .map(|r| parser::Literal::Plain(r.display_name().into()))
+                .collect(),
             paths,
             ..self.last_exchange().query.clone()
         };
 
         debug!(?query, %self.thread_id, "executing semantic query");
-        self.app.semantic.search(&query, params).await
+        if self.workspace_repos.is_empty() {
+            return self.app.semantic.search(&query, params).await;
+        }
+
+        // Search deeper, so that enough results are left once every repository is capped
+        let limit = params.limit;
+        let params = semantic::SemanticSearchParams {
+            limit: limit * 2,
+            ..params
+        };
+
+        let results = self.app.semantic.search(&query, params).await?;
+        let mut results = self
+            .retrieval()
+            .limit_per_repo(results, |chunk| chunk.repo_ref.as_str());
+        results.truncate(limit as usize);
+
+        Ok(results
+            .into_iter()
+            .map(|mut chunk| {
+                chunk.relative_path = self.qualify_path(&chunk.repo_ref, chunk.relative_path);
+                chunk
+            })
+            .collect())
     }
 
     #[allow(dead_code)]
@@ -419,29 +692,65 @@ impl Agent {
 
     async fn get_file_content(&self, path: &str) -> Result<Option<ContentDocument>> {
         let branch = self.last_exchange().query.first_branch();
+        let (repo_ref, repo_path) = self.resolve_path(path);
 
-        debug!(%self.repo_ref, path, ?branch, %self.thread_id, "executing file search");
+        debug!(%repo_ref, path, ?branch, %self.thread_id, "executing file search");
         self.app
             .indexes
             .file
-            .by_path(&self.repo_ref, path, branch.as_deref())
+            .by_path(repo_ref, repo_path, branch.as_deref())
             .await
             .with_context(|| format!("failed to read path: {}", path))
     }
 
-    async fn fuzzy_path_search<'a>(
-        &'a self,
-        query: &str,
-    ) -> impl Iterator<Item = FileDocument> + 'a {
+    async fn fuzzy_path_search(&self, query: &str) -> Vec<FileDocument> {
         let branch = self.last_exchange().query.first_branch();
-        let langs = self.last_exchange().query.langs.iter().map(Deref::deref);
+        let mut documents = vec![];
+
+        for repo_ref in self.repos() {
+            let langs = self.last_exchange().query.langs.iter().map(Deref::deref);
+
+            debug!(%repo_ref, query, ?branch, %self.thread_id, "executing fuzzy search");
+            let matches = self
+                .app
+                .indexes
+                .file
+                .fuzzy_path_match(
+                    repo_ref,
+                    query,
+                    branch.as_deref(),
+                    langs,
+                    self.retrieval().lexical_k as usize,
+                )
+                .await;
+
+            let repo_str = repo_ref.to_string();
+            documents.extend(matches.map(|mut doc| {
+                doc.relative_path = self.qualify_path(&repo_str, doc.relative_path);
+                doc
+            }));
+        }
 
-        debug!(%self.repo_ref, query, ?branch, %self.thread_id, "executing fuzzy search");
-        self.app
-            .indexes
-            .file
-            .fuzzy_path_match(&self.repo_ref, query, branch.as_deref(), langs, 50)
-            .await
+        documents
+    }
+
+    /// The last exchange, if it is sampled for human review.
+    fn review_candidate(&self) -> Option<reviews::Candidate> {
+        let percent = self.app.config.review_sample_percent?;
+        if !reviews::sampled(percent, rand::random()) {
+            return None;
+        }
+
+        let exchange = self.last_exchange();
+        Some(reviews::Candidate {
+            user_id: self.user.username()?.to_owned(),
+            thread_id: self.thread_id,
+            exchange_id: exchange.id,
+            query_id: self.query_id,
+            repo_ref: self.repo_ref.to_string(),
+            query: exchange.query()?,
+            answer: exchange.answer()?.to_owned(),
+        })
     }
 
     /// Store the conversation in the DB.
@@ -450,7 +759,19 @@ impl Agent {
     // NB: This isn't an `async fn` so as to not capture a lifetime.
     fn store(&mut self) -> impl Future<Output = ()> {
         let sql = Arc::clone(&self.app.sql);
-        let conversation = (self.repo_ref.clone(), self.exchanges.clone());
+        let conversation = (
+            self.repo_ref.clone(),
+            self.exchanges
+                .iter()
+                .chain(&self.later_exchanges)
+                .cloned()
+                .collect(),
+        );
+        let experiment_latency = match self.last_exchange() {
+            e if e.experiments.is_empty() => None,
+            e => e.answer_latency(),
+        };
+        let query_id = self.query_id;
         let conversation_id = self
             .user
             .username()
@@ -471,6 +792,13 @@ impl Agent {
             if let Err(e) = result {
                 error!("failed to store conversation: {e}");
             }
+
+            if let Some(latency) = experiment_latency {
+                let ms = latency.num_milliseconds();
+                if let Err(e) = experiments::record_latency(&sql, query_id, ms).await {
+                    error!("failed to record experiment latency: {e}");
+                }
+            }
         }
     }
 }
@@ -539,9 +867,75 @@ pub enum Action {
         query: String,
         paths: Vec<usize>,
     },
+    Changes {
+        query: String,
+    },
+    History(history::Query),
+    Structural(structural::Pattern),
+    Api {
+        query: String,
+        #[serde(default)]
+        returns: Option<String>,
+    },
+    Schema {
+        query: String,
+    },
+    Scratchpad {
+        name: String,
+        content: String,
+    },
+
+    /// Draft a plan for a user-provided query, without executing it.
+    #[serde(skip)]
+    Plan(String),
+
+    /// Execute the approved plan of the last exchange.
+    #[serde(skip)]
+    ExecutePlan,
 }
 
 impl Action {
+    /// The name of the function that corresponds to this action.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Query(..) => "query",
+            Action::Path { .. } => "path",
+            Action::Answer { .. } => "none",
+            Action::Code { .. } => "code",
+            Action::Proc { .. } => "proc",
+            Action::Changes { .. } => "changes",
+            Action::History(..) => "history",
+            Action::Structural(..) => "structural",
+            Action::Api { .. } => "api",
+            Action::Schema { .. } => "schema",
+            Action::Scratchpad { .. } => "scratchpad",
+            Action::Plan(..) => "plan",
+            Action::ExecutePlan => "execute_plan",
+        }
+    }
+
+    /// What this action does to the repository.
+    ///
+    /// Scratchpads are stored with the conversation, not in the repository, so they only count as
+    /// reads.
+    pub fn access(&self) -> policy::ToolAccess {
+        match self {
+            Action::Query(..)
+            | Action::Path { .. }
+            | Action::Answer { .. }
+            | Action::Code { .. }
+            | Action::Proc { .. }
+            | Action::Changes { .. }
+            | Action::History(..)
+            | Action::Structural(..)
+            | Action::Api { .. }
+            | Action::Schema { .. }
+            | Action::Scratchpad { .. }
+            | Action::Plan(..)
+            | Action::ExecutePlan => policy::ToolAccess::Read,
+        }
+    }
+
     /// Deserialize this action from the GPT-tagged enum variant format.
     ///
     /// We convert (2 examples):
@@ -0,0 +1,166 @@
+use std::borrow::Cow;
+
+use anyhow::{bail, Result};
+use tracing::warn;
+use url::Url;
+
+use crate::{fetch::Fetcher, scraper::Article};
+
+/// The maximum number of URLs that can be attached to a single query.
+pub const MAX_ATTACHMENTS: usize = 5;
+
+const MAX_TEXT_CHARS: usize = 20_000;
+
+/// An external document attached to a query as ad-hoc context.
+///
+/// Only the provenance of an attachment is stored with the conversation. The extracted text is
+/// used to answer the query it was attached to, and then discarded.
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone, Default)]
+pub struct Attachment {
+    pub url: String,
+    pub title: Option<String>,
+
+    /// Whether the text was cut short because the document was too long.
+    pub truncated: bool,
+
+    /// Why the document could not be attached, if fetching it failed.
+    pub error: Option<String>,
+
+    #[serde(skip)]
+    pub text: Option<String>,
+}
+
+/// Parse a whitespace-separated list of URLs to attach to a query.
+pub fn parse_urls(urls: &str) -> Result<Vec<Url>, String> {
+    let urls = urls
+        .split_whitespace()
+        .map(|url| {
+            let parsed = Url::parse(url).map_err(|e| format!("invalid URL `{url}`: {e}"))?;
+            check_scheme(&parsed).map_err(|e| e.to_string())?;
+            Ok(parsed)
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    if urls.len() > MAX_ATTACHMENTS {
+        return Err(format!("at most {MAX_ATTACHMENTS} URLs can be attached"));
+    }
+
+    Ok(urls)
+}
+
+/// Fetch all attachments concurrently. Failures are recorded on the attachment itself.
+pub async fn fetch_all(fetcher: &Fetcher, urls: &[Url]) -> Vec<Attachment> {
+    futures::future::join_all(urls.iter().cloned().map(|url| fetch(fetcher, url))).await
+}
+
+async fn fetch(fetcher: &Fetcher, url: Url) -> Attachment {
+    match fetch_text(fetcher, url.clone()).await {
+        Ok(Fetched {
+            title,
+            text,
+            truncated,
+        }) => Attachment {
+            url: url.to_string(),
+            title,
+            truncated,
+            error: None,
+            text: Some(text),
+        },
+        Err(err) => {
+            warn!(?err, %url, "failed to fetch attachment");
+            Attachment {
+                url: url.to_string(),
+                error: Some(err.to_string()),
+                ..Default::default()
+            }
+        }
+    }
+}
+
+struct Fetched {
+    title: Option<String>,
+    text: String,
+    truncated: bool,
+}
+
+async fn fetch_text(fetcher: &Fetcher, url: Url) -> Result<Fetched> {
+    let response = fetcher.get(url).await?;
+    if !response.status.is_success() {
+        bail!("request failed with status {}", response.status);
+    }
+
+    extract(
+        response.url,
+        response.content_type.as_deref().unwrap_or("text/plain"),
+        &response.body,
+        response.truncated,
+    )
+}
+
+fn extract(url: Url, content_type: &str, body: &[u8], truncated: bool) -> Result<Fetched> {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    let body = String::from_utf8_lossy(body);
+    let (title, text) = match mime.as_str() {
+        "text/html" | "application/xhtml+xml" => {
+            let content = Article::from_html(url, &body).content;
+            (
+                content.title.map(Cow::into_owned),
+                content.text.map(Cow::into_owned).unwrap_or_default(),
+            )
+        }
+        "application/json" => (None, body.into_owned()),
+        mime if mime.starts_with("text/") => (None, body.into_owned()),
+        mime => bail!("unsupported content type `{mime}`"),
+    };
+
+    let truncated = truncated || text.chars().count() > MAX_TEXT_CHARS;
+    let text = text.chars().take(MAX_TEXT_CHARS).collect();
+
+    Ok(Fetched {
+        title,
+        text,
+        truncated,
+    })
+}
+
+fn check_scheme(url: &Url) -> Result<()> {
+    match url.scheme() {
+        "http" | "https" => Ok(()),
+        scheme => bail!("unsupported URL scheme `{scheme}`"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_url_lists() {
+        let urls = parse_urls(" https://example.com/a\nhttp://example.com/b?c=d ").unwrap();
+        assert_eq!(
+            urls.iter().map(Url::as_str).collect::<Vec<_>>(),
+            ["https://example.com/a", "http://example.com/b?c=d"]
+        );
+
+        assert!(parse_urls("").unwrap().is_empty());
+        assert!(parse_urls("file:///etc/passwd").is_err());
+        assert!(parse_urls("not a url").is_err());
+        assert!(parse_urls(&"https://example.com ".repeat(MAX_ATTACHMENTS + 1)).is_err());
+    }
+
+    #[test]
+    fn extracts_plain_text() {
+        let url = Url::parse("https://example.com/notes.txt").unwrap();
+        let fetched = extract(url.clone(), "text/plain; charset=utf-8", b"hello", false).unwrap();
+        assert_eq!(fetched.text, "hello");
+        assert!(!fetched.truncated);
+
+        assert!(extract(url, "image/png", b"", false).is_err());
+    }
+}
@@ -0,0 +1,285 @@
+//! Checking the code that an answer cites against the index, once the answer is written.
+//!
+//! Answers cite code by quoting it, in code blocks that name the path and lines of the quote, and
+//! by linking to ranges of lines. Models get line numbers wrong, and files change, so every
+//! citation is looked up in the current index. Quotes found at other lines are re-located, and the
+//! answer is changed to cite those lines instead. Citations that can't be checked are flagged, so
+//! that clients can tell them apart.
+
+use std::{collections::HashMap, ops::Range};
+
+use lazy_regex::regex;
+use tracing::warn;
+
+use super::{
+    exchange::{Citation, CitationStatus},
+    Agent,
+};
+
+/// A citation in the text of an answer, after it was decoded.
+#[derive(Debug, PartialEq)]
+struct Cited<'a> {
+    path: &'a str,
+    start_line: usize,
+    end_line: usize,
+    /// Where the numbers of the first and the last line are written in the answer
+    start_span: Range<usize>,
+    end_span: Range<usize>,
+    /// The quoted code, for citations in code blocks
+    quote: Option<&'a str>,
+}
+
+impl Agent {
+    /// Check the citations of an answer, and return the answer with re-located citations fixed.
+    pub async fn verify_citations(&self, article: &str) -> (String, Vec<Citation>) {
+        let cited = find(article);
+        let mut files = HashMap::new();
+        let mut citations = Vec::with_capacity(cited.len());
+
+        for c in &cited {
+            if !files.contains_key(c.path) {
+                let content = match self.get_file_content(c.path).await {
+                    Ok(doc) => doc.map(|doc| doc.content),
+                    Err(err) => {
+                        warn!(?err, path = c.path, "failed to read cited file");
+                        None
+                    }
+                };
+                files.insert(c.path, content);
+            }
+
+            let status = match &files[c.path] {
+                Some(content) => check(content, c),
+                None => CitationStatus::Unverifiable {
+                    reason: "the file isn't indexed".to_owned(),
+                },
+            };
+
+            citations.push(Citation {
+                path: c.path.to_owned(),
+                start_line: c.start_line,
+                end_line: c.end_line,
+                status,
+            });
+        }
+
+        (rewrite(article, &cited, &citations), citations)
+    }
+}
+
+/// The citations of an answer, in the order they are written.
+fn find(article: &str) -> Vec<Cited<'_>> {
+    let mut cited = vec![];
+
+    // Quotes, as decoded by `transcoder::decode`
+    let quotes = regex!(r"(?m)^(`{3,})type:Quoted,lang:[^,\n]*,path:([^,\n]+),lines:(\d+)-(\d+)\n");
+    for caps in quotes.captures_iter(article) {
+        let (Ok(start_line), Ok(end_line)) = (caps[3].parse::<usize>(), caps[4].parse::<usize>())
+        else {
+            continue;
+        };
+
+        let body = caps.get(0).unwrap().end();
+        let fence = format!("\n{}", &caps[1]);
+        let quote = article[body..]
+            .find(&fence)
+            .map(|len| &article[body..body + len]);
+
+        cited.push(Cited {
+            path: caps.get(2).unwrap().as_str(),
+            start_line,
+            end_line,
+            start_span: caps.get(3).unwrap().range(),
+            end_span: caps.get(4).unwrap().range(),
+            quote,
+        });
+    }
+
+    for caps in regex!(r"\]\(([^)\s#]+)#L(\d+)-L(\d+)\)").captures_iter(article) {
+        let path = caps.get(1).unwrap().as_str();
+        if path.contains("://") {
+            continue;
+        }
+
+        let (Ok(start_line), Ok(end_line)) = (caps[2].parse::<usize>(), caps[3].parse::<usize>())
+        else {
+            continue;
+        };
+
+        cited.push(Cited {
+            path,
+            start_line,
+            end_line,
+            start_span: caps.get(2).unwrap().range(),
+            end_span: caps.get(3).unwrap().range(),
+            quote: None,
+        });
+    }
+
+    cited.sort_by_key(|c| c.start_span.start);
+    cited
+}
+
+fn check(content: &str, cited: &Cited<'_>) -> CitationStatus {
+    let lines = content.lines().collect::<Vec<_>>();
+    let in_range = cited.start_line <= cited.end_line && cited.end_line < lines.len();
+
+    let quote = cited
+        .quote
+        .map(|quote| normalize(quote.lines()))
+        .filter(|quote| !quote.is_empty());
+
+    let Some(quote) = quote else {
+        return if in_range {
+            CitationStatus::Verified
+        } else {
+            CitationStatus::Unverifiable {
+                reason: format!("the file has {} lines", lines.len()),
+            }
+        };
+    };
+
+    if in_range && normalize(lines[cited.start_line..=cited.end_line].iter().copied()) == quote {
+        return CitationStatus::Verified;
+    }
+
+    match locate(&lines, &quote, cited.start_line) {
+        Some((start_line, end_line)) => CitationStatus::Relocated {
+            start_line,
+            end_line,
+        },
+        None => CitationStatus::Unverifiable {
+            reason: "the quoted code isn't in the file".to_owned(),
+        },
+    }
+}
+
+/// Lines without their indentation, skipping blank lines, which models often get wrong.
+fn normalize<'a>(lines: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    lines.map(str::trim).filter(|l| !l.is_empty()).collect()
+}
+
+/// The first and last line of the occurrence of a normalized quote closest to `near`.
+fn locate(lines: &[&str], quote: &[&str], near: usize) -> Option<(usize, usize)> {
+    (0..lines.len())
+        .filter(|&start| lines[start].trim() == quote[0])
+        .filter_map(|start| {
+            let mut quoted = quote.iter().skip(1);
+            let mut next = quoted.next();
+
+            if next.is_none() {
+                return Some((start, start));
+            }
+
+            for (i, line) in lines.iter().enumerate().skip(start + 1) {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+
+                match next {
+                    Some(&expected) if expected == line => next = quoted.next(),
+                    _ => return None,
+                }
+
+                if next.is_none() {
+                    return Some((start, i));
+                }
+            }
+
+            None
+        })
+        .min_by_key(|&(start, _)| start.abs_diff(near))
+}
+
+/// Cite the new lines of re-located citations.
+fn rewrite(article: &str, cited: &[Cited<'_>], citations: &[Citation]) -> String {
+    let mut article = article.to_owned();
+
+    for (c, citation) in cited.iter().zip(citations).rev() {
+        if let CitationStatus::Relocated {
+            start_line,
+            end_line,
+        } = citation.status
+        {
+            article.replace_range(c.end_span.clone(), &end_line.to_string());
+            article.replace_range(c.start_span.clone(), &start_line.to_string());
+        }
+    }
+
+    article
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str =
+        "use std::fmt;\n\nfn main() {\n    let x = 1;\n\n    println!(\"{x}\");\n}\n";
+
+    #[test]
+    fn finds_quotes_and_links() {
+        let article = "See [`main`](src/main.rs#L2-L6):\n\n\
+            ```type:Quoted,lang:Rust,path:src/main.rs,lines:3-3\n    let x = 1;\n```\n\n\
+            and [the docs](https://example.com/a#L1-L2).";
+
+        let cited = find(article);
+        assert_eq!(cited.len(), 2);
+        assert_eq!(cited[0].quote, None);
+        assert_eq!((cited[0].start_line, cited[0].end_line), (2, 6));
+        assert_eq!(cited[1].path, "src/main.rs");
+        assert_eq!(cited[1].quote, Some("    let x = 1;"));
+    }
+
+    #[test]
+    fn checks_and_relocates_quotes() {
+        let cited = |start_line, end_line, quote| Cited {
+            path: "src/main.rs",
+            start_line,
+            end_line,
+            start_span: 0..0,
+            end_span: 0..0,
+            quote,
+        };
+
+        assert_eq!(
+            check(FILE, &cited(3, 5, Some("let x = 1;\nprintln!(\"{x}\");"))),
+            CitationStatus::Verified
+        );
+        assert_eq!(
+            check(FILE, &cited(0, 1, Some("let x = 1;\n\nprintln!(\"{x}\");"))),
+            CitationStatus::Relocated {
+                start_line: 3,
+                end_line: 5
+            }
+        );
+        assert!(matches!(
+            check(FILE, &cited(3, 3, Some("let y = 2;"))),
+            CitationStatus::Unverifiable { .. }
+        ));
+        assert!(matches!(
+            check(FILE, &cited(2, 40, None)),
+            CitationStatus::Unverifiable { .. }
+        ));
+    }
+
+    #[test]
+    fn rewrites_relocated_citations() {
+        let article = "```type:Quoted,lang:Rust,path:src/main.rs,lines:0-0\nlet x = 1;\n```";
+        let cited = find(article);
+        let citations = cited
+            .iter()
+            .map(|c| Citation {
+                path: c.path.to_owned(),
+                start_line: c.start_line,
+                end_line: c.end_line,
+                status: check(FILE, c),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            rewrite(article, &cited, &citations),
+            "```type:Quoted,lang:Rust,path:src/main.rs,lines:3-3\nlet x = 1;\n```"
+        );
+    }
+}
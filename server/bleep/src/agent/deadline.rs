@@ -0,0 +1,69 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+
+/// The point in time by which a request has to be answered.
+///
+/// Every downstream call gets a timeout of at most the remaining time, so that work is not done
+/// for a response that can't be delivered anymore.
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    pub fn after(duration: Duration) -> Self {
+        Self(Instant::now() + duration)
+    }
+
+    pub fn instant(&self) -> Instant {
+        self.0
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_zero()
+    }
+
+    /// The timeout for a single call that should take at most `max`.
+    pub fn timeout(&self, max: Duration) -> Duration {
+        self.remaining().min(max)
+    }
+
+    /// Run a call that should take at most `max`, failing if it or the deadline runs out.
+    pub async fn run<T>(&self, what: &str, max: Duration, f: impl Future<Output = T>) -> Result<T> {
+        if self.is_expired() {
+            return Err(anyhow!("request deadline exceeded before {what}"));
+        }
+
+        let timeout = self.timeout(max);
+        tokio::time::timeout(timeout, f)
+            .await
+            .map_err(|_| anyhow!("{what} timed out after {timeout:?}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_timeouts_to_remaining_time() {
+        let deadline = Deadline::after(Duration::from_secs(10));
+
+        assert!(deadline.timeout(Duration::from_secs(60)) <= Duration::from_secs(10));
+        assert_eq!(
+            deadline.timeout(Duration::from_secs(1)),
+            Duration::from_secs(1)
+        );
+        assert!(!deadline.is_expired());
+
+        let expired = Deadline::after(Duration::ZERO);
+        assert!(expired.is_expired());
+        assert_eq!(expired.timeout(Duration::from_secs(1)), Duration::ZERO);
+    }
+}
@@ -1,4 +1,13 @@
-use crate::query::parser::SemanticQuery;
+use crate::{
+    agent::{
+        attachment::Attachment,
+        experiment::{Assignment, Variant},
+        retrieval::RetrievalSettings,
+    },
+    intelligence::structural,
+    query::parser::SemanticQuery,
+    repo::history,
+};
 use std::fmt;
 
 use chrono::prelude::{DateTime, Utc};
@@ -7,7 +16,7 @@ use chrono::prelude::{DateTime, Utc};
 ///
 /// This contains the query from the user, the intermediate steps the model takes, and the final
 /// conclusion from the model alongside the answer, if any.
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone, Default)]
 pub struct Exchange {
     pub id: uuid::Uuid,
     pub query: SemanticQuery<'static>,
@@ -25,6 +34,50 @@ pub struct Exchange {
     /// as when displaying an article.
     pub focused_chunk: Option<FocusedChunk>,
 
+    /// The retrieval settings that were in effect when this exchange was answered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retrieval: Option<RetrievalSettings>,
+
+    /// The language quoted code comments were translated into, if the user asked for it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment_language: Option<String>,
+
+    /// The footer appended to the answer, as configured by the instance admins.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub footer: Option<String>,
+
+    /// The state of the index this exchange was answered from, if a fresh index was requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_freshness: Option<IndexFreshness>,
+
+    /// External documents attached to the query by the user.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+
+    /// The plan for this exchange, if it was made in plan mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plan: Option<Plan>,
+
+    /// The model calls that chose which function to call, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_selections: Vec<ToolSelection>,
+
+    /// The experiments this exchange took part in, and the arm it was answered with.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub experiments: Vec<Assignment>,
+
+    /// The code that the answer cites, as checked against the index once the answer was written.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub citations: Vec<Citation>,
+
+    /// The overrides this exchange was answered with, if it was retried.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<Retry>,
+
+    /// The earlier answers to this exchange, oldest first, if it was retried.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub revisions: Vec<Revision>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     query_timestamp: Option<DateTime<Utc>>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -53,6 +106,16 @@ impl Exchange {
                 (Some(l @ SearchStep::Path { .. }), r @ SearchStep::Path { .. }) => *l = r,
                 (Some(l @ SearchStep::Code { .. }), r @ SearchStep::Code { .. }) => *l = r,
                 (Some(l @ SearchStep::Proc { .. }), r @ SearchStep::Proc { .. }) => *l = r,
+                (Some(l @ SearchStep::Changes { .. }), r @ SearchStep::Changes { .. }) => *l = r,
+                (Some(l @ SearchStep::History { .. }), r @ SearchStep::History { .. }) => *l = r,
+                (Some(l @ SearchStep::Structural { .. }), r @ SearchStep::Structural { .. }) => {
+                    *l = r
+                }
+                (Some(l @ SearchStep::Api { .. }), r @ SearchStep::Api { .. }) => *l = r,
+                (Some(l @ SearchStep::Schema { .. }), r @ SearchStep::Schema { .. }) => *l = r,
+                (Some(l @ SearchStep::Scratchpad { .. }), r @ SearchStep::Scratchpad { .. }) => {
+                    *l = r
+                }
                 _ => panic!("Tried to replace a step that was not found"),
             },
             Update::Article(full_text) => {
@@ -64,9 +127,29 @@ impl Exchange {
             Update::SetTimestamp => {
                 self.response_timestamp = Some(Utc::now());
             }
+            Update::Plan(plan) => {
+                self.plan = Some(plan);
+            }
+            Update::ToolSelection(selection) => self.tool_selections.push(selection),
+            Update::Citations(citations) => self.citations = citations,
+            Update::PlanStep { index, status } => {
+                if let Some(step) = self.plan.as_mut().and_then(|p| p.steps.get_mut(index)) {
+                    step.status = status;
+                }
+            }
         }
     }
 
+    /// The time between the query and its answer, if it was answered.
+    pub fn answer_latency(&self) -> Option<chrono::Duration> {
+        Some(self.response_timestamp? - self.query_timestamp?)
+    }
+
+    /// The experimental configuration this exchange is answered with, if any.
+    pub fn variant(&self) -> Option<&Variant> {
+        self.experiments.iter().find_map(|a| a.variant.as_ref())
+    }
+
     /// Get the query associated with this exchange, if it has been made.
     pub fn query(&self) -> Option<String> {
         self.query.target().map(|q| q.to_string())
@@ -94,9 +177,65 @@ impl Exchange {
 
         self
     }
+
+    /// The revisions of this exchange, followed by this answer as the latest revision.
+    pub fn into_revisions(self) -> Vec<Revision> {
+        let mut revisions = self.revisions;
+        revisions.push(Revision {
+            answer: self.answer,
+            retrieval: self.retrieval,
+            retry: self.retry,
+            branch: self.query.first_branch().map(|branch| branch.into_owned()),
+            answered_at: self.response_timestamp,
+        });
+
+        revisions
+    }
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+/// Changes to how an exchange is answered, when it is retried.
+#[derive(
+    serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone, Default, PartialEq,
+)]
+pub struct Retry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lexical_k: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub semantic_k: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_similarity: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_chunks_per_file: Option<u64>,
+    /// Answer from this branch, instead of the one the query was answered from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+}
+
+impl Retry {
+    /// The retrieval settings with the overrides of this retry.
+    pub fn apply(&self, settings: RetrievalSettings) -> RetrievalSettings {
+        RetrievalSettings {
+            lexical_k: self.lexical_k.unwrap_or(settings.lexical_k),
+            semantic_k: self.semantic_k.unwrap_or(settings.semantic_k),
+            min_similarity: self.min_similarity.unwrap_or(settings.min_similarity),
+            max_chunks_per_file: self.max_chunks_per_file.or(settings.max_chunks_per_file),
+            ..settings
+        }
+    }
+}
+
+/// An earlier answer to an exchange, which was replaced by retrying the exchange.
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone)]
+pub struct Revision {
+    pub answer: Option<String>,
+    pub retrieval: Option<RetrievalSettings>,
+    /// The overrides of the retry this answer came from, unless it was the first answer
+    pub retry: Option<Retry>,
+    pub branch: Option<String>,
+    pub answered_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone)]
 #[serde(rename_all = "lowercase", tag = "type", content = "content")]
 #[non_exhaustive]
 pub enum SearchStep {
@@ -113,6 +252,32 @@ pub enum SearchStep {
         paths: Vec<String>,
         response: String,
     },
+    Changes {
+        query: String,
+        response: String,
+    },
+    History {
+        query: history::Query,
+        response: String,
+    },
+    Structural {
+        pattern: structural::Pattern,
+        response: String,
+    },
+    Api {
+        query: String,
+        returns: Option<String>,
+        response: String,
+    },
+    Schema {
+        query: String,
+        response: String,
+    },
+    Scratchpad {
+        name: String,
+        content: String,
+        response: String,
+    },
 }
 
 impl SearchStep {
@@ -134,6 +299,32 @@ impl SearchStep {
                 paths: paths.clone(),
                 response: "[hidden, compressed]".into(),
             },
+            Self::Changes { query, .. } => Self::Changes {
+                query: query.clone(),
+                response: "[hidden, compressed]".into(),
+            },
+            Self::History { query, .. } => Self::History {
+                query: query.clone(),
+                response: "[hidden, compressed]".into(),
+            },
+            Self::Structural { pattern, .. } => Self::Structural {
+                pattern: pattern.clone(),
+                response: "[hidden, compressed]".into(),
+            },
+            Self::Api { query, returns, .. } => Self::Api {
+                query: query.clone(),
+                returns: returns.clone(),
+                response: "[hidden, compressed]".into(),
+            },
+            Self::Schema { query, .. } => Self::Schema {
+                query: query.clone(),
+                response: "[hidden, compressed]".into(),
+            },
+            Self::Scratchpad { name, .. } => Self::Scratchpad {
+                name: name.clone(),
+                content: "[hidden, compressed]".into(),
+                response: "[hidden, compressed]".into(),
+            },
         }
     }
 
@@ -142,11 +333,17 @@ impl SearchStep {
             Self::Path { response, .. } => response.clone(),
             Self::Code { response, .. } => response.clone(),
             Self::Proc { response, .. } => response.clone(),
+            Self::Changes { response, .. } => response.clone(),
+            Self::History { response, .. } => response.clone(),
+            Self::Structural { response, .. } => response.clone(),
+            Self::Api { response, .. } => response.clone(),
+            Self::Schema { response, .. } => response.clone(),
+            Self::Scratchpad { response, .. } => response.clone(),
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 pub struct CodeChunk {
     pub path: String,
     pub alias: usize,
@@ -172,7 +369,90 @@ impl fmt::Display for CodeChunk {
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone)]
+pub struct IndexFreshness {
+    /// The commit the index was built from.
+    pub indexed_commit: Option<String>,
+
+    /// Whether the index could not be brought up to date in time, and may lag behind upstream.
+    pub stale: bool,
+}
+
+/// A range of lines of a file that an answer cites, either by quoting it or by linking to it.
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone, PartialEq)]
+pub struct Citation {
+    pub path: String,
+    /// The first cited line, 0-based, as cited by the model
+    pub start_line: usize,
+    /// The last cited line, 0-based and inclusive, as cited by the model
+    pub end_line: usize,
+    #[serde(flatten)]
+    pub status: CitationStatus,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "status")]
+pub enum CitationStatus {
+    /// The file has the cited lines, and they match the quoted code, if any.
+    Verified,
+    /// The quoted code was found at other lines, and the answer was changed to cite those.
+    Relocated { start_line: usize, end_line: usize },
+    /// The citation couldn't be checked against the file, or didn't match it.
+    Unverifiable { reason: String },
+}
+
+/// A model call that chose the next function to call.
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone)]
+pub struct ToolSelection {
+    pub function: Option<String>,
+    /// Whether the response was served from the response cache
+    pub cached: bool,
+}
+
+/// A numbered plan, which has to be approved by the user before the agent executes it.
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone)]
+pub struct Plan {
+    pub steps: Vec<PlanStep>,
+    pub status: PlanStatus,
+}
+
+impl Plan {
+    /// The maximum number of steps in a plan, whether drafted or edited by the user.
+    pub const MAX_STEPS: usize = 8;
+}
+
+#[derive(
+    serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone, PartialEq, Eq,
+)]
+pub struct PlanStep {
+    pub description: String,
+    pub status: PlanStepStatus,
+}
+
+#[derive(
+    serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanStatus {
+    /// The plan is still being generated.
+    Drafting,
+    AwaitingApproval,
+    Approved,
+    Rejected,
+    Executed,
+}
+
+#[derive(
+    serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanStepStatus {
+    Pending,
+    Running,
+    Done,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone, Default)]
 pub struct FocusedChunk {
     pub file_path: String,
     pub start_line: usize,
@@ -186,4 +466,11 @@ pub enum Update {
     Article(String),
     Focus(FocusedChunk),
     SetTimestamp,
+    Plan(Plan),
+    PlanStep {
+        index: usize,
+        status: PlanStepStatus,
+    },
+    ToolSelection(ToolSelection),
+    Citations(Vec<Citation>),
 }
@@ -0,0 +1,184 @@
+use super::retrieval::RetrievalSettings;
+
+/// The alternative configuration that an experiment tries out.
+///
+/// Everything that is left unset behaves exactly like the control group.
+#[derive(
+    serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone, Default, PartialEq,
+)]
+#[serde(default)]
+pub struct Variant {
+    /// Extra instructions appended to the system prompt of the agent, which picks the tools.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub agent_instructions: Option<String>,
+
+    /// Extra instructions appended to the system prompt of the answer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer_instructions: Option<String>,
+
+    /// Retrieval settings to use instead of the repository's own.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retrieval: Option<RetrievalSettings>,
+}
+
+impl Variant {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if let Some(retrieval) = &self.retrieval {
+            retrieval.validate()?;
+        }
+
+        if *self == Self::default() {
+            return Err("the variant doesn't change anything");
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(
+    serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Arm {
+    Control,
+    Variant,
+}
+
+impl Arm {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Arm::Control => "control",
+            Arm::Variant => "variant",
+        }
+    }
+}
+
+/// A running experiment, which receives `traffic_percent` of all conversations.
+#[derive(Debug, Clone)]
+pub struct Experiment {
+    pub id: i64,
+    pub name: String,
+    pub traffic_percent: u8,
+    pub variant: Variant,
+}
+
+/// The arm of an experiment that an exchange was answered with.
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub experiment_id: i64,
+    pub experiment: String,
+    pub arm: Arm,
+
+    /// The configuration that was applied, for the variant arm.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<Variant>,
+}
+
+/// Assign a conversation to the experiments that are running.
+///
+/// Every experiment owns a slice of the 100 buckets, in the order it was created, and
+/// conversations are bucketed by their thread ID. A conversation falls into at most one variant,
+/// so that experiments don't interfere. Conversations outside every slice make up the control
+/// group, which is shared by all experiments.
+///
+/// A conversation that was in a variant before keeps it for as long as the experiment runs,
+/// even if the slices have moved since.
+pub fn assign(
+    experiments: &[Experiment],
+    thread_id: uuid::Uuid,
+    previous: &[Assignment],
+) -> Vec<Assignment> {
+    let variant = |experiment: &Experiment| {
+        vec![Assignment {
+            experiment_id: experiment.id,
+            experiment: experiment.name.clone(),
+            arm: Arm::Variant,
+            variant: Some(experiment.variant.clone()),
+        }]
+    };
+
+    let sticky = previous
+        .iter()
+        .filter(|a| a.arm == Arm::Variant)
+        .find_map(|a| experiments.iter().find(|e| e.id == a.experiment_id));
+
+    if let Some(experiment) = sticky {
+        return variant(experiment);
+    }
+
+    let bucket = (thread_id.as_u128() % 100) as u32;
+    let mut start = 0;
+    for experiment in experiments {
+        let end = start + u32::from(experiment.traffic_percent);
+        if (start..end).contains(&bucket) {
+            return variant(experiment);
+        }
+        start = end;
+    }
+
+    experiments
+        .iter()
+        .map(|experiment| Assignment {
+            experiment_id: experiment.id,
+            experiment: experiment.name.clone(),
+            arm: Arm::Control,
+            variant: None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn experiment(id: i64, traffic_percent: u8) -> Experiment {
+        Experiment {
+            id,
+            name: format!("experiment {id}"),
+            traffic_percent,
+            variant: Variant {
+                answer_instructions: Some("Be brief.".into()),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn thread(bucket: u128) -> uuid::Uuid {
+        uuid::Uuid::from_u128(1000 * 100 + bucket)
+    }
+
+    #[test]
+    fn assigns_slices_in_order() {
+        let experiments = [experiment(1, 10), experiment(2, 20)];
+
+        let first = assign(&experiments, thread(5), &[]);
+        assert_eq!(first.len(), 1);
+        assert_eq!((first[0].experiment_id, first[0].arm), (1, Arm::Variant));
+
+        let second = assign(&experiments, thread(10), &[]);
+        assert_eq!((second[0].experiment_id, second[0].arm), (2, Arm::Variant));
+
+        let control = assign(&experiments, thread(30), &[]);
+        assert_eq!(control.len(), 2);
+        assert!(control
+            .iter()
+            .all(|a| a.arm == Arm::Control && a.variant.is_none()));
+
+        assert!(assign(&[], thread(5), &[]).is_empty());
+    }
+
+    #[test]
+    fn keeps_the_variant_of_a_conversation() {
+        let before = [experiment(1, 10), experiment(2, 20)];
+        let previous = assign(&before, thread(15), &[]);
+
+        // the first experiment stopped, so the slice of the second one moved
+        let after = [experiment(2, 20)];
+        let next = assign(&after, thread(15), &previous);
+        assert_eq!((next[0].experiment_id, next[0].arm), (2, Arm::Variant));
+
+        let previous = assign(&before, thread(5), &[]);
+        let next = assign(&after, thread(25), &previous);
+        assert_eq!(next[0].arm, Arm::Control);
+    }
+}
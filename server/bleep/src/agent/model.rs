@@ -20,6 +20,12 @@ pub struct LLMModel {
 
     /// The system prompt to be used
     pub system_prompt: fn(&str) -> String,
+
+    /// What the provider charges for 1,000 prompt tokens, in USD
+    pub prompt_price: f64,
+
+    /// What the provider charges for 1,000 completion tokens, in USD
+    pub completion_price: f64,
 }
 
 pub const GPT_3_5_TURBO_FINETUNED: LLMModel = LLMModel {
@@ -29,6 +35,8 @@ pub const GPT_3_5_TURBO_FINETUNED: LLMModel = LLMModel {
     prompt_headroom: 1600,
     history_headroom: 1024,
     system_prompt: prompts::answer_article_prompt_finetuned,
+    prompt_price: 0.003,
+    completion_price: 0.006,
 };
 
 // GPT-4 turbo has a context window of 128k tokens
@@ -46,6 +54,8 @@ pub const GPT_4_TURBO_24K: LLMModel = LLMModel {
     prompt_headroom: 2500 + HEADROOM_CORRECTION,
     history_headroom: 2048 + HEADROOM_CORRECTION,
     system_prompt: prompts::answer_article_prompt,
+    prompt_price: 0.01,
+    completion_price: 0.03,
 };
 
 pub const GPT_4: LLMModel = LLMModel {
@@ -55,8 +65,63 @@ pub const GPT_4: LLMModel = LLMModel {
     prompt_headroom: 2500,
     history_headroom: 2048,
     system_prompt: prompts::answer_article_prompt,
+    prompt_price: 0.03,
+    completion_price: 0.06,
 };
 
+// GPT-4 has a context window of 8k tokens
+const GPT_4_MAX_TOKENS: usize = 8192;
+
+/// A model behind an OpenAI-compatible API, with a context window of `context_window` tokens.
+///
+/// Tokens are counted like GPT-4's, which is close enough to budget the prompt. The context of
+/// GPT-4, or of GPT-4 turbo for larger windows, is cut down to the window by adding the difference
+/// to the headrooms, like for `GPT_4_TURBO_24K`. Windows smaller than GPT-4's shrink the headrooms
+/// in proportion, so that there is still room for code.
+pub fn custom(context_window: usize) -> LLMModel {
+    let (tokenizer, max_tokens, scale) = if context_window <= GPT_4_MAX_TOKENS {
+        let scale = context_window as f64 / GPT_4_MAX_TOKENS as f64;
+        ("gpt-4-0613", GPT_4_MAX_TOKENS, scale)
+    } else {
+        ("gpt-4-1106-preview", GPT_4_TURBO_MAX_TOKENS, 1.0)
+    };
+
+    let correction = max_tokens.saturating_sub(context_window);
+    let headroom = |tokens: usize| (tokens as f64 * scale) as usize + correction;
+
+    LLMModel {
+        tokenizer,
+        model_name: "custom",
+        answer_headroom: headroom(1024),
+        prompt_headroom: headroom(2500),
+        history_headroom: headroom(2048),
+        system_prompt: prompts::answer_article_prompt,
+        // Self-hosted models aren't charged for by the token
+        prompt_price: 0.0,
+        completion_price: 0.0,
+    }
+}
+
+/// What the gateway charges for 1,000 prompt and completion tokens of a model, in USD.
+///
+/// This covers the models above, and the ones that agent tools call directly.
+pub fn prices(model_name: &str) -> Option<(f64, f64)> {
+    let model = [GPT_3_5_TURBO_FINETUNED, GPT_4_TURBO_24K, GPT_4]
+        .into_iter()
+        .find(|model| model.model_name == model_name);
+
+    if let Some(model) = model {
+        return Some((model.prompt_price, model.completion_price));
+    }
+
+    match model_name {
+        "gpt-3.5-turbo-0613" => Some((0.0015, 0.002)),
+        "gpt-3.5-turbo-16k-0613" => Some((0.003, 0.004)),
+        "custom" => Some((0.0, 0.0)),
+        _ => None,
+    }
+}
+
 impl FromStr for LLMModel {
     type Err = ();
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -79,3 +144,28 @@ impl<'de> serde::Deserialize<'de> for LLMModel {
             .map_err(|_| serde::de::Error::custom("failed to deserialize"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fits_custom_models_in_their_window() {
+        let small = custom(4096);
+        assert_eq!(small.tokenizer, "gpt-4-0613");
+        assert_eq!(small.answer_headroom, 512 + 4096);
+        assert_eq!(small.prompt_headroom, 1250 + 4096);
+
+        let large = custom(32_000);
+        assert_eq!(large.tokenizer, "gpt-4-1106-preview");
+        assert_eq!(large.answer_headroom, 1024 + 96_000);
+    }
+
+    #[test]
+    fn prices_models_by_gateway_name() {
+        assert_eq!(prices("gpt-4-0613"), Some((0.03, 0.06)));
+        assert_eq!(prices("gpt-3.5-turbo-0613"), Some((0.0015, 0.002)));
+        assert_eq!(prices(custom(4096).model_name), Some((0.0, 0.0)));
+        assert_eq!(prices("claude-2"), None);
+    }
+}
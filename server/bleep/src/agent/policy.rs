@@ -0,0 +1,105 @@
+use std::{fmt, str::FromStr};
+
+/// What a tool does to the repository it runs against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ToolAccess {
+    /// Reads the repository or the index.
+    Read,
+    /// Proposes changes to the repository, such as a patch, without applying them.
+    Suggest,
+    /// Changes the repository on disk.
+    Write,
+}
+
+impl ToolAccess {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Suggest => "suggest",
+            Self::Write => "write",
+        }
+    }
+}
+
+/// The tools that can be used on the repositories of a workspace, ordered from the strictest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum ToolPolicy {
+    ReadOnly,
+    ReadSuggest,
+    #[default]
+    ReadWrite,
+}
+
+impl ToolPolicy {
+    pub fn allows(self, access: ToolAccess) -> bool {
+        access <= self.max_access()
+    }
+
+    fn max_access(self) -> ToolAccess {
+        match self {
+            Self::ReadOnly => ToolAccess::Read,
+            Self::ReadSuggest => ToolAccess::Suggest,
+            Self::ReadWrite => ToolAccess::Write,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ReadOnly => "read_only",
+            Self::ReadSuggest => "read_suggest",
+            Self::ReadWrite => "read_write",
+        }
+    }
+}
+
+impl FromStr for ToolPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read_only" => Ok(Self::ReadOnly),
+            "read_suggest" => Ok(Self::ReadSuggest),
+            "read_write" => Ok(Self::ReadWrite),
+            other => Err(format!("unknown tool policy `{other}`")),
+        }
+    }
+}
+
+impl fmt::Display for ToolPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enforces_access_levels() {
+        use ToolAccess::*;
+
+        assert!(ToolPolicy::ReadOnly.allows(Read));
+        assert!(!ToolPolicy::ReadOnly.allows(Suggest));
+        assert!(!ToolPolicy::ReadOnly.allows(Write));
+
+        assert!(ToolPolicy::ReadSuggest.allows(Suggest));
+        assert!(!ToolPolicy::ReadSuggest.allows(Write));
+
+        assert!(ToolPolicy::default().allows(Write));
+        assert!(ToolPolicy::ReadOnly < ToolPolicy::ReadSuggest);
+    }
+
+    #[test]
+    fn parses_policies() {
+        for policy in [
+            ToolPolicy::ReadOnly,
+            ToolPolicy::ReadSuggest,
+            ToolPolicy::ReadWrite,
+        ] {
+            assert_eq!(policy.as_str().parse::<ToolPolicy>().unwrap(), policy);
+        }
+
+        assert!("write_only".parse::<ToolPolicy>().is_err());
+    }
+}
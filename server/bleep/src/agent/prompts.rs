@@ -1,4 +1,6 @@
-pub fn functions(add_proc: bool) -> serde_json::Value {
+use crate::webserver::answer::scratchpads::Scratchpad;
+
+pub fn functions(add_proc: bool, add_changes: bool) -> serde_json::Value {
     let mut funcs = serde_json::json!(
         [
             {
@@ -29,6 +31,112 @@ pub fn functions(add_proc: bool) -> serde_json::Value {
                     "required": ["query"]
                 }
             },
+            {
+                "name": "api",
+                "description": "Search the API surface of a codebase: the endpoints, gRPC services and message schemas declared in its OpenAPI specs and protobuf files. Use for questions about which services expose an endpoint, or what an endpoint accepts and returns.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "query": {
+                            "type": "string",
+                            "description": "Keywords matched against service, endpoint, route and type names, ignoring case. Can be empty. For example: 'invoice', '/users'"
+                        },
+                        "returns": {
+                            "type": "string",
+                            "description": "Only return endpoints whose response is this type, or a message containing it. For example: 'CustomerId'"
+                        }
+                    },
+                    "required": ["query"]
+                }
+            },
+            {
+                "name": "schema",
+                "description": "Search the data model of a codebase: the tables and columns declared by its SQL migrations and schema files, with the migrations that changed them. Use for questions about tables, columns and foreign keys.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "query": {
+                            "type": "string",
+                            "description": "Keywords matched against table names, column names, types and referenced tables, ignoring case. Can be empty to list all tables. For example: 'user_id', 'orders'"
+                        }
+                    },
+                    "required": ["query"]
+                }
+            },
+            {
+                "name": "structural",
+                "description": "Search the syntax trees of the files in a codebase, to find code by its structure rather than its text. `calls` finds the functions and methods that call a function or method. `derives` finds Rust structs and enums that derive a trait. `query` runs a tree-sitter query against the files of one language.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "pattern": {
+                            "type": "string",
+                            "enum": ["calls", "derives", "query"]
+                        },
+                        "name": {
+                            "type": "string",
+                            "description": "The name of the function or method called, for `calls`, or of the trait derived, for `derives`. For example: 'parse', 'Serialize'"
+                        },
+                        "query": {
+                            "type": "string",
+                            "description": "A tree-sitter query, for `query`. Capture the nodes to return as @match. For example: '(function_item name: (identifier) @name) @match'"
+                        },
+                        "lang": {
+                            "type": "string",
+                            "description": "The language the query is written for, for `query`. For example: 'Rust', 'Python'"
+                        }
+                    },
+                    "required": ["pattern"]
+                }
+            },
+            {
+                "name": "history",
+                "description": "Query the git history of a codebase. Use for questions about who changed some code, when, and why. `log` lists the recent commits, optionally only those touching a path. `blame` shows the commit that last changed each line of a file. `show` shows the message and diff of a commit.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "command": {
+                            "type": "string",
+                            "enum": ["log", "blame", "show"]
+                        },
+                        "path": {
+                            "type": "string",
+                            "description": "The path of a file or directory. Required by `blame`, optional for `log` and `show`. For example: 'src/main.rs'"
+                        },
+                        "start_line": {
+                            "type": "integer",
+                            "description": "The first line to blame, starting from 1"
+                        },
+                        "end_line": {
+                            "type": "integer",
+                            "description": "The last line to blame, inclusive"
+                        },
+                        "commit": {
+                            "type": "string",
+                            "description": "The hash of the commit to show, as returned by `log` or `blame`. Required by `show`"
+                        }
+                    },
+                    "required": ["command"]
+                }
+            },
+            {
+                "name": "scratchpad",
+                "description": "Write a named scratchpad attached to this conversation, replacing its previous content. Scratchpads are shown to you in every later step and conversation turn, so use them for notes, running plans and TODO lists that should persist.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "name": {
+                            "type": "string",
+                            "description": "A short name for the scratchpad. For example: 'plan', 'todo', 'notes'"
+                        },
+                        "content": {
+                            "type": "string",
+                            "description": "The full new content of the scratchpad."
+                        }
+                    },
+                    "required": ["name", "content"]
+                }
+            },
             {
                 "name": "none",
                 "description": "Call this to answer the user. Call this only when you have enough information to answer the user's query.",
@@ -76,12 +184,35 @@ pub fn functions(add_proc: bool) -> serde_json::Value {
             )
         );
     }
+
+    if add_changes {
+        funcs.as_array_mut().unwrap().push(
+            serde_json::json!(
+            {
+                "name": "changes",
+                "description": "Search the files that were changed locally since the codebase was indexed, such as uncommitted work. Use when the user asks about code they recently wrote or edited, as other searches may return outdated results for these files.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "query": {
+                            "type": "string",
+                            "description": "Keywords to match exactly, ignoring case. Lines containing any of the keywords are returned. Can be empty to list the changed files."
+                        }
+                    },
+                    "required": ["query"]
+                }
+            }
+            )
+        );
+    }
     funcs
 }
 
-pub fn system<'a>(paths: impl IntoIterator<Item = &'a str>) -> String {
+pub fn system<'a>(paths: impl IntoIterator<Item = &'a str>, scratchpads: &[Scratchpad]) -> String {
     let mut s = "".to_string();
 
+    s.push_str(&scratchpad_context(scratchpads));
+
     let mut paths = paths.into_iter().peekable();
 
     if paths.peek().is_some() {
@@ -109,10 +240,45 @@ pub fn system<'a>(paths: impl IntoIterator<Item = &'a str>) -> String {
 - If the output of a function is empty, try calling the function again with DIFFERENT arguments OR try calling a different function
 - Only call functions.proc with path indices that are under the PATHS heading above
 - Call functions.proc with paths that might contain relevant information. Either because of the path name or to expand on a chunk returned by functions.code. For example, if a chunk contains a reference to a term in the query, you might want to call functions.proc with the path of the chunk
+- Call functions.structural to find callers of a function or types deriving a trait, as it is more precise than functions.code for these
+- Call functions.history to find who changed code and when. Call it with `blame` and the line range of a definition you already found, then with `show` to read the commits it returns
+- Call functions.scratchpad to keep notes or plans that will be useful later in the conversation, not to answer the query
 - ALWAYS call a function. DO NOT answer the question directly"#);
     s
 }
 
+pub fn plan_prompt(scratchpads: &[Scratchpad]) -> String {
+    format!(
+        r#"{}You are an expert programmer called 'bloop', and you are helping a colleague with a codebase. Before doing anything, write a plan for how you will answer their query. The plan will be shown to your colleague, and you will only carry it out once they approve it.
+
+Follow these rules at all times:
+
+- Respond with a numbered list of steps, and nothing else
+- Write at most 8 steps, and fewer if the query is simple
+- Each step should be a single action, like searching for a file, reading some code, or checking where something is used
+- Describe each step in one sentence, without assuming the structure of the codebase
+- The final step should describe what the answer will contain"#,
+        scratchpad_context(scratchpads)
+    )
+}
+
+/// Format the scratchpads of a conversation for a prompt.
+pub fn scratchpad_context(scratchpads: &[Scratchpad]) -> String {
+    let mut s = String::new();
+
+    if !scratchpads.is_empty() {
+        s.push_str("## SCRATCHPADS ##\n");
+        for scratchpad in scratchpads {
+            s.push_str(&format!(
+                "### {} ###\n{}\n\n",
+                scratchpad.name, scratchpad.content
+            ));
+        }
+    }
+
+    s
+}
+
 pub fn answer_article_prompt(context: &str) -> String {
     format!(
         r#"{context}####
@@ -172,6 +338,20 @@ println!("hello world!");
     )
 }
 
+pub fn comment_translation_prompt(language: &str) -> String {
+    format!(
+        r#"Code comments you quote may be written in a language other than {language}. Quote them exactly as they are written, and translate them for the user:
+- Right after a `<QuotedCode>` block containing comments that aren't in {language}, add a line starting with `Translated comments ({language}):`, followed by a list of the translated comments, in the order they appear in the block
+- When quoting a comment in a sentence, follow it with its translation in parentheses, starting with `translated:`
+- Never translate code, only comments, and never edit the comments inside a `<QuotedCode>` block
+- Do not translate comments already written in {language}"#
+    )
+}
+
+pub fn history_citation_prompt() -> &'static str {
+    "When using information from the GIT HISTORY above, cite the commits it comes from by their hash, exactly as shown, in backticks. For example: The retry limit was raised in `1a2b3c4d`. Do not cite commits that are not in the GIT HISTORY"
+}
+
 // Do not change this prompt. A model needs to be retrained before doing it (the non finetune prompt can be modified instead)
 pub fn answer_article_prompt_finetuned(context: &str) -> String {
     format!(
@@ -298,6 +478,72 @@ And here is the serialized conversation:
     )
 }
 
+pub fn conversation_title_prompt(history: &str) -> String {
+    format!(
+        r#"Your job is to generate a title for a conversation about a software codebase, given the questions asked and the answers received.
+
+Follow these rules strictly:
+    - You MUST only return the new title, and NO additional text
+    - Be brief, only return a few words, 3-7 is ideal
+    - Summarise what the conversation is about, not just the first question
+    - Do NOT include quotation marks in your title
+    - Do NOT use gerunds (e.g. "Searching for...")
+
+Here are some example titles demonstrating the correct style:
+    - Indexer Backpressure Handling
+    - OAuth Token Refresh Flow
+    - Webhook Retry Configuration
+
+######
+
+Here is the conversation:
+=====
+{history}
+====="#
+    )
+}
+
+pub fn channel_prompt(repo_name: &str, context: &str) -> String {
+    format!(
+        r#"Your job is to answer a short question about the software repository `{repo_name}`, in a chat channel shared by its developers.
+
+Follow these rules strictly:
+    - Answer in at most a few sentences of markdown, or a short code snippet
+    - Only use the code below, and cite the files you use as `path:start-end`
+    - If the code below doesn't answer the question, say so, and suggest asking it in a conversation
+    - Do NOT ask follow-up questions, there won't be any
+
+##### CODE #####
+
+{context}"#
+    )
+}
+
+pub fn repo_health_summary_prompt(repo_name: &str, report_json: &str) -> String {
+    format!(
+        r#"Your job is to summarise a health report of the software repository `{repo_name}` for its maintainers.
+
+The report measures, across the repository and for each module:
+    - `doc_coverage`: the share of definitions with a doc comment, from 0 to 1
+    - `test_ratio`: lines of tests for every line of code
+    - `todo_density`: TODO and FIXME comments per thousand lines
+    - `hotspots`: the largest files
+
+Follow these rules strictly:
+    - Write at most 4 short sentences of plain text, with no headings or lists
+    - Point out the modules and files that most need attention, and why
+    - Only mention numbers that are in the report
+    - Do NOT give generic advice about documentation or testing
+
+######
+
+Here is the report:
+=====
+{report_json}
+====="#
+    )
+}
+
 pub fn studio_diff_prompt(context_formatted: &str) -> String {
     format!(
         r#"Below are files from a codebase. Your job is to write a Unified Format patch to complete a provided task. To write a unified format patch, surround it in a code block: ```diff
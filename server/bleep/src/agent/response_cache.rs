@@ -0,0 +1,94 @@
+//! A persistent cache for deterministic model calls.
+//!
+//! At low temperatures, the model reliably makes the same function call for the same history.
+//! Repeated queries, like evaluations and question templates, can then skip the gateway entirely.
+
+use anyhow::Result;
+
+use crate::{db::SqlDb, llm_gateway};
+
+/// Calls at or below this temperature are considered deterministic.
+const MAX_TEMPERATURE: f32 = 0.1;
+
+/// Entries that haven't been used in this many days are pruned.
+pub const RETENTION_DAYS: i64 = 30;
+
+/// The cache key of a request, or `None` if the response can't be cached.
+pub fn key(
+    client: &llm_gateway::Client,
+    messages: &[llm_gateway::api::Message],
+    functions: Option<&[llm_gateway::api::Function]>,
+) -> Option<String> {
+    let temperature = client.temperature?;
+    if temperature > MAX_TEMPERATURE {
+        return None;
+    }
+
+    let request = serde_json::json!({
+        "provider": client.provider,
+        "model": client.model,
+        "temperature": temperature,
+        "max_tokens": client.max_tokens,
+        "messages": messages,
+        "functions": functions,
+    });
+
+    Some(blake3::hash(request.to_string().as_bytes()).to_string())
+}
+
+pub async fn get(db: &SqlDb, key: &str) -> Result<Option<String>> {
+    let row = sqlx::query!(
+        "UPDATE llm_response_cache
+        SET hits = hits + 1, last_used_at = strftime('%s', 'now')
+        WHERE prompt_hash = ?
+        RETURNING response AS \"response!\"",
+        key,
+    )
+    .fetch_optional(db.as_ref())
+    .await?;
+
+    Ok(row.map(|row| row.response))
+}
+
+pub async fn put(db: &SqlDb, key: &str, model: Option<&str>, response: &str) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO llm_response_cache (prompt_hash, model, response) VALUES (?, ?, ?)
+        ON CONFLICT (prompt_hash) DO UPDATE SET
+            response = excluded.response,
+            last_used_at = excluded.last_used_at",
+        key,
+        model,
+        response,
+    )
+    .execute(db.as_ref())
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use llm_gateway::api::Message;
+
+    #[test]
+    fn only_caches_deterministic_requests() {
+        let messages = [
+            Message::system("Call a function"),
+            Message::user("where is auth?"),
+        ];
+        let client = llm_gateway::Client::new("http://localhost");
+
+        assert!(key(&client, &messages, None).is_none());
+        assert!(key(&client.clone().temperature(0.7), &messages, None).is_none());
+
+        let client = client.temperature(0.0);
+        let cached = key(&client, &messages, None).unwrap();
+        assert_eq!(key(&client, &messages, None).unwrap(), cached);
+        assert_ne!(key(&client, &messages[..1], None).unwrap(), cached);
+        assert_ne!(
+            key(&client.model("gpt-4"), &messages, None).unwrap(),
+            cached
+        );
+    }
+}
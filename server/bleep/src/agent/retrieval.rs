@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+/// Tuning parameters for the retrieval stages of the agent.
+///
+/// These can be configured per repository, and the effective values are recorded in every
+/// exchange so that answers can be evaluated against the settings that produced them.
+#[derive(
+    serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone, Copy, PartialEq,
+)]
+#[serde(default)]
+pub struct RetrievalSettings {
+    /// Maximum number of results returned by lexical path search.
+    pub lexical_k: u64,
+
+    /// Maximum number of chunks returned by a semantic code search.
+    pub semantic_k: u64,
+
+    /// Minimum similarity score for semantic code search results.
+    pub min_similarity: f32,
+
+    /// Maximum number of chunks to keep from any single file, if set.
+    pub max_chunks_per_file: Option<u64>,
+
+    /// Maximum number of chunks to keep from any single repository, when answering across a
+    /// workspace. Defaults to half of `semantic_k`.
+    pub max_chunks_per_repo: Option<u64>,
+}
+
+impl Default for RetrievalSettings {
+    fn default() -> Self {
+        Self {
+            lexical_k: 50,
+            semantic_k: 10,
+            min_similarity: 0.3,
+            max_chunks_per_file: None,
+            max_chunks_per_repo: None,
+        }
+    }
+}
+
+/// The most results a lexical path search can be configured to return.
+const MAX_LEXICAL_K: u64 = 500;
+
+/// The most chunks a semantic code search can be configured to return, which all end up in the
+/// prompt.
+const MAX_SEMANTIC_K: u64 = 100;
+
+impl RetrievalSettings {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.lexical_k == 0 || self.semantic_k == 0 {
+            return Err("result limits must be greater than zero");
+        }
+
+        if self.lexical_k > MAX_LEXICAL_K {
+            return Err("`lexical_k` must be at most 500");
+        }
+
+        if self.semantic_k > MAX_SEMANTIC_K {
+            return Err("`semantic_k` must be at most 100");
+        }
+
+        if !(0.0..=1.0).contains(&self.min_similarity) {
+            return Err("`min_similarity` must be between 0 and 1");
+        }
+
+        if self.max_chunks_per_file == Some(0) {
+            return Err("`max_chunks_per_file` must be greater than zero");
+        }
+
+        if self.max_chunks_per_file > Some(MAX_SEMANTIC_K) {
+            return Err("`max_chunks_per_file` must be at most 100");
+        }
+
+        if self.max_chunks_per_repo == Some(0) {
+            return Err("`max_chunks_per_repo` must be greater than zero");
+        }
+
+        if self.max_chunks_per_repo > Some(MAX_SEMANTIC_K) {
+            return Err("`max_chunks_per_repo` must be at most 100");
+        }
+
+        Ok(())
+    }
+
+    /// Drop results beyond the per-file limit, keeping the earliest (most relevant) ones.
+    pub fn limit_per_file<T>(&self, results: Vec<T>, path: impl Fn(&T) -> &str) -> Vec<T> {
+        match self.max_chunks_per_file {
+            Some(max) => limit_per_key(results, max, path),
+            None => results,
+        }
+    }
+
+    /// Drop results beyond the per-repository limit, so that no repository of a workspace
+    /// crowds out the others.
+    pub fn limit_per_repo<T>(&self, results: Vec<T>, repo: impl Fn(&T) -> &str) -> Vec<T> {
+        let max = self
+            .max_chunks_per_repo
+            .unwrap_or_else(|| (self.semantic_k / 2).max(1));
+
+        limit_per_key(results, max, repo)
+    }
+}
+
+fn limit_per_key<T>(results: Vec<T>, max: u64, key: impl Fn(&T) -> &str) -> Vec<T> {
+    let mut counts = HashMap::<String, u64>::new();
+    results
+        .into_iter()
+        .filter(|result| {
+            let count = counts.entry(key(result).to_owned()).or_default();
+            *count += 1;
+            *count <= max
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_results_per_file() {
+        let results = vec![
+            ("a.rs", 1),
+            ("b.rs", 1),
+            ("a.rs", 10),
+            ("a.rs", 20),
+            ("b.rs", 10),
+        ];
+
+        let settings = RetrievalSettings {
+            max_chunks_per_file: Some(2),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            settings.limit_per_file(results.clone(), |(path, _)| *path),
+            [("a.rs", 1), ("b.rs", 1), ("a.rs", 10), ("b.rs", 10)]
+        );
+
+        let unlimited = RetrievalSettings::default().limit_per_file(results, |(path, _)| *path);
+        assert_eq!(unlimited.len(), 5);
+    }
+
+    #[test]
+    fn limits_results_per_repo() {
+        let results = vec![("a", 1), ("a", 2), ("a", 3), ("b", 1), ("a", 4), ("b", 2)];
+
+        let settings = RetrievalSettings {
+            semantic_k: 6,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            settings.limit_per_repo(results, |(repo, _)| *repo),
+            [("a", 1), ("a", 2), ("a", 3), ("b", 1), ("b", 2)]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        assert!(RetrievalSettings::default().validate().is_ok());
+
+        let settings = RetrievalSettings {
+            min_similarity: 1.5,
+            ..Default::default()
+        };
+        assert!(settings.validate().is_err());
+
+        let settings = RetrievalSettings {
+            semantic_k: 0,
+            ..Default::default()
+        };
+        assert!(settings.validate().is_err());
+
+        let settings = RetrievalSettings {
+            semantic_k: MAX_SEMANTIC_K + 1,
+            ..Default::default()
+        };
+        assert!(settings.validate().is_err());
+
+        let settings = RetrievalSettings {
+            lexical_k: u64::MAX,
+            max_chunks_per_repo: Some(MAX_SEMANTIC_K),
+            ..Default::default()
+        };
+        assert!(settings.validate().is_err());
+    }
+}
@@ -5,7 +5,7 @@ use crate::webserver::intelligence::{get_token_info, TokenInfoRequest};
 use anyhow::{Context, Result};
 use tracing::log::{debug, info, warn};
 
-use super::prompts::symbol_classification_prompt;
+use super::{model::GPT_4, prompts::symbol_classification_prompt};
 
 pub struct ChunkWithHoverableSymbols {
     pub chunk: CodeChunk,
@@ -94,7 +94,11 @@ impl Agent {
             .related_symbols
             .iter()
             .flat_map(|file_symbols| {
-                let filename = file_symbols.file.clone();
+                // Definitions in other repositories are labelled with their repository
+                let filename = match &file_symbols.repo {
+                    Some(repo) => format!("{repo}/{}", file_symbols.file),
+                    None => file_symbols.file.clone(),
+                };
 
                 file_symbols
                     .data
@@ -176,12 +180,23 @@ impl Agent {
         let response = match self
             .llm_gateway
             .clone()
-            .model("gpt-4-0613")
+            .model(GPT_4.model_name)
             .temperature(0.0)
             .chat(&messages, None)
             .await
         {
-            Ok(response) => response,
+            Ok(response) => {
+                self.record_usage(
+                    "symbol",
+                    GPT_4.model_name,
+                    GPT_4.tokenizer,
+                    &messages,
+                    None,
+                    &response,
+                )
+                .await;
+                response
+            }
             Err(e) => {
                 warn!(
                     "Symbol classifier llm call failed, picking the first symbol: {}",
@@ -233,7 +248,7 @@ impl Agent {
                     get_token_info(
                         symbol_metadata.token_info_request,
                         &self.repo_ref,
-                        self.app.indexes.clone(),
+                        &self.app,
                         &document,
                         &all_docs,
                         Some(0),
@@ -242,7 +257,9 @@ impl Agent {
                     .await
                     .unwrap()
                     .into_iter()
-                    .filter(|file_symbol| file_symbol.file != symbol_metadata.path)
+                    .filter(|file_symbol| {
+                        file_symbol.repo.is_some() || file_symbol.file != symbol_metadata.path
+                    })
                     .collect::<Vec<_>>()
                 },
             }),
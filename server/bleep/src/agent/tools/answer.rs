@@ -6,8 +6,8 @@ use tracing::{debug, info, instrument, trace};
 
 use crate::{
     agent::{
-        exchange::{CodeChunk, FocusedChunk, Update},
-        model, transcoder, Agent,
+        exchange::{CitationStatus, CodeChunk, FocusedChunk, SearchStep, Update},
+        model, prompts, transcoder, Agent,
     },
     analytics::EventData,
     llm_gateway,
@@ -15,11 +15,17 @@ use crate::{
 
 const CHUNK_MERGE_DISTANCE: usize = 20;
 
+const HISTORY_HEADER: &str = "\n##### GIT HISTORY #####\n\n";
+
 impl Agent {
     #[instrument(skip(self))]
     pub async fn answer(&mut self, aliases: &[usize]) -> Result<()> {
         debug!("creating article response");
 
+        // Start an empty article straight away, so that the client can lay out the answer while
+        // we read the context.
+        self.update(Update::Article(String::new())).await?;
+
         if aliases.len() == 1 {
             let path = self
                 .paths()
@@ -40,7 +46,21 @@ impl Agent {
         }
 
         let context = self.answer_context(aliases).await?;
-        let system_prompt = (self.answer_model.system_prompt)(&context);
+        let mut system_prompt = (self.answer_model.system_prompt)(&context);
+        if let Some(instructions) = self.variant().and_then(|v| v.answer_instructions.as_ref()) {
+            system_prompt = format!("{system_prompt}\n\n{instructions}");
+        }
+
+        if let Some(language) = &self.last_exchange().comment_language {
+            let instructions = prompts::comment_translation_prompt(language);
+            system_prompt = format!("{system_prompt}\n\n{instructions}");
+        }
+
+        if context.contains(HISTORY_HEADER) {
+            let instructions = prompts::history_citation_prompt();
+            system_prompt = format!("{system_prompt}\n\n{instructions}");
+        }
+
         let system_message = llm_gateway::api::Message::system(&system_prompt);
         let history = {
             let h = self.utter_history().collect::<Vec<_>>();
@@ -80,6 +100,29 @@ impl Agent {
             self.update(Update::Article(article)).await?;
         }
 
+        // The citations can only be checked once the whole answer is written
+        let (mut article, citations) = self.verify_citations(&transcoder::decode(&response)).await;
+        if let Some(footer) = &self.last_exchange().footer {
+            article = format!("{article}\n\n{footer}");
+        }
+
+        let unverifiable = citations
+            .iter()
+            .filter(|c| matches!(c.status, CitationStatus::Unverifiable { .. }))
+            .count();
+        self.update(Update::Article(article)).await?;
+        self.update(Update::Citations(citations)).await?;
+
+        self.record_usage(
+            "answer",
+            self.answer_model.model_name,
+            self.answer_model.tokenizer,
+            &messages,
+            None,
+            &response,
+        )
+        .await;
+
         if let Some(article) = self.last_exchange().answer() {
             trace!(%article, "generated answer");
         }
@@ -92,7 +135,9 @@ impl Agent {
                 .with_payload("query_history", &history)
                 .with_payload("response", &response)
                 .with_payload("raw_prompt", &system_prompt)
-                .with_payload("model", self.answer_model.model_name),
+                .with_payload("model", self.answer_model.model_name)
+                .with_payload("unverifiable_citations", unverifiable)
+                .with_payload("experiments", &self.last_exchange().experiments),
         );
 
         Ok(())
@@ -115,6 +160,16 @@ impl Agent {
 
         debug!(?paths, ?aliases, "created filtered path alias list");
 
+        s += &prompts::scratchpad_context(&self.scratchpads);
+
+        if let Some(plan) = &self.last_exchange().plan {
+            s += "##### PLAN #####\n";
+            for (i, step) in plan.steps.iter().enumerate() {
+                s += &format!("{}. {}\n", i + 1, step.description);
+            }
+            s += "\n";
+        }
+
         if !aliases.is_empty() {
             s += "##### PATHS #####\n";
 
@@ -133,6 +188,85 @@ impl Agent {
         let mut remaining_prompt_tokens =
             tiktoken_rs::get_completion_max_tokens(self.answer_model.tokenizer, &s)?;
 
+        // Attached documents and unindexed changes may take up at most half of the prompt, leaving
+        // the rest for code.
+        let mut remaining_attachment_tokens =
+            remaining_prompt_tokens.saturating_sub(self.answer_model.prompt_headroom) / 2;
+
+        let mut has_attachments = false;
+        for attachment in &self.last_exchange().attachments {
+            let Some(text) = &attachment.text else {
+                continue;
+            };
+
+            let formatted_attachment = format!("### {} ###\n{text}\n\n", attachment.url);
+            let attachment_tokens = bpe.encode_ordinary(&formatted_attachment).len();
+
+            if attachment_tokens >= remaining_attachment_tokens {
+                info!(
+                    url = %attachment.url,
+                    "skipping attachment that does not fit"
+                );
+                continue;
+            }
+
+            if !has_attachments {
+                s += "\n##### ATTACHED DOCUMENTS #####\n\n";
+                has_attachments = true;
+            }
+
+            s += &formatted_attachment;
+            remaining_attachment_tokens -= attachment_tokens;
+            remaining_prompt_tokens -= attachment_tokens;
+        }
+
+        // Results from the working tree are more recent than the indexed code chunks
+        let mut has_changes = false;
+        for step in &self.last_exchange().search_steps {
+            let SearchStep::Changes { response, .. } = step else {
+                continue;
+            };
+
+            let changes_tokens = bpe.encode_ordinary(response).len();
+            if changes_tokens >= remaining_attachment_tokens {
+                info!("skipping unindexed changes that do not fit");
+                continue;
+            }
+
+            if !has_changes {
+                s += "\n##### UNINDEXED CHANGES #####\n\n";
+                has_changes = true;
+            }
+
+            s += &format!("{response}\n\n");
+            remaining_attachment_tokens -= changes_tokens;
+            remaining_prompt_tokens -= changes_tokens;
+        }
+
+        // Results of git history queries share the budget of attached documents, and are cited by
+        // commit hash
+        let mut has_history = false;
+        for step in &self.last_exchange().search_steps {
+            let SearchStep::History { response, .. } = step else {
+                continue;
+            };
+
+            let history_tokens = bpe.encode_ordinary(response).len();
+            if history_tokens >= remaining_attachment_tokens {
+                info!("skipping git history that does not fit");
+                continue;
+            }
+
+            if !has_history {
+                s += HISTORY_HEADER;
+                has_history = true;
+            }
+
+            s += &format!("{response}\n\n");
+            remaining_attachment_tokens -= history_tokens;
+            remaining_prompt_tokens -= history_tokens;
+        }
+
         // Select as many recent chunks as possible
         let mut recent_chunks = Vec::new();
         for chunk in code_chunks.iter().rev() {
@@ -206,6 +340,12 @@ impl Agent {
                 });
 
                 let conclusion = e.answer().map(|answer| {
+                    // The model shouldn't write the footer itself, as it's added to every answer
+                    let answer = e
+                        .footer
+                        .as_deref()
+                        .and_then(|footer| answer.strip_suffix(footer))
+                        .map_or(answer, str::trim_end);
                     let encoded = transcoder::encode_summarized(answer, "gpt-4-0613").unwrap();
 
                     llm_gateway::api::Message::PlainText {
@@ -0,0 +1,94 @@
+use anyhow::Result;
+use tracing::instrument;
+
+use crate::{
+    agent::{
+        exchange::{SearchStep, Update},
+        Agent,
+    },
+    analytics::EventData,
+    repo::api_surface::{ApiQuery, ApiSurface},
+};
+
+impl Agent {
+    #[instrument(skip(self))]
+    pub async fn api_search(&mut self, query: &String, returns: &Option<String>) -> Result<String> {
+        self.update(Update::StartStep(SearchStep::Api {
+            query: query.clone(),
+            returns: returns.clone(),
+            response: String::new(),
+        }))
+        .await?;
+
+        let commit = self
+            .app
+            .repo_pool
+            .read_async(&self.repo_ref, |_, repo| repo.indexed_commit.clone())
+            .await
+            .flatten();
+
+        let surface =
+            ApiSurface::for_repo(&self.app.indexes.file, &self.repo_ref, commit.as_deref()).await;
+        let found = surface.search(&ApiQuery {
+            terms: query,
+            returns: returns.as_deref(),
+        });
+
+        let mut lines = vec![];
+        for service in &found.services {
+            let alias = self.get_path_alias(&service.path);
+            for e in &service.endpoints {
+                lines.push(format!(
+                    "{alias}: {}:{}: {} {} {} ({}) -> ({})",
+                    service.path,
+                    e.line,
+                    service.name,
+                    e.method,
+                    e.route,
+                    e.request.join(", "),
+                    e.response.join(", "),
+                ));
+            }
+        }
+
+        for m in &found.messages {
+            let alias = self.get_path_alias(&m.path);
+            let fields = m
+                .fields
+                .iter()
+                .map(|f| format!("{}: {}", f.name, f.ty))
+                .collect::<Vec<_>>()
+                .join(", ");
+            lines.push(format!(
+                "{alias}: {}:{}: message {} {{ {fields} }}",
+                m.path, m.line, m.name
+            ));
+        }
+
+        let response = if surface.services.is_empty() && surface.messages.is_empty() {
+            "There are no OpenAPI specs or protobuf files in this repository".to_owned()
+        } else if lines.is_empty() {
+            "No endpoints or messages match the query".to_owned()
+        } else {
+            lines.join("\n")
+        };
+
+        self.update(Update::ReplaceStep(SearchStep::Api {
+            query: query.clone(),
+            returns: returns.clone(),
+            response: response.clone(),
+        }))
+        .await?;
+
+        self.track_query(
+            EventData::input_stage("api surface search")
+                .with_payload("query", query)
+                .with_payload("returns", returns)
+                .with_payload("services", found.services.len())
+                .with_payload("messages", found.messages.len())
+                .with_payload("raw_prompt", &response),
+        );
+
+        Ok(response)
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::{anyhow, Result};
+use tracing::instrument;
+
+use crate::{
+    agent::{
+        exchange::{SearchStep, Update},
+        Agent,
+    },
+    analytics::EventData,
+    repo::changes,
+};
+
+impl Agent {
+    #[instrument(skip(self))]
+    pub async fn changes_search(&mut self, query: &String) -> Result<String> {
+        self.update(Update::StartStep(SearchStep::Changes {
+            query: query.clone(),
+            response: String::new(),
+        }))
+        .await?;
+
+        let (disk_path, indexed_commit, last_index_unix_secs) = self
+            .app
+            .repo_pool
+            .read_async(&self.repo_ref, |_, repo| {
+                (
+                    repo.disk_path.clone(),
+                    repo.indexed_commit.clone(),
+                    repo.last_index_unix_secs,
+                )
+            })
+            .await
+            .ok_or_else(|| anyhow!("repository not found"))?;
+
+        let search_query = query.clone();
+        let changes = tokio::task::spawn_blocking(move || {
+            changes::search(
+                &disk_path,
+                indexed_commit.as_deref(),
+                last_index_unix_secs,
+                &search_query,
+                None,
+            )
+        })
+        .await??;
+
+        let response = if changes.changed_files.is_empty() {
+            "There are no unindexed changes".to_owned()
+        } else if changes.matches.is_empty() {
+            format!(
+                "No matches in the changed files:\n{}",
+                changes.changed_files.join("\n")
+            )
+        } else {
+            changes
+                .matches
+                .iter()
+                .map(|m| {
+                    let alias = self.get_path_alias(&m.path);
+                    format!("{alias}: {}:{}: {}", m.path, m.line_number, m.line)
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+
+        self.update(Update::ReplaceStep(SearchStep::Changes {
+            query: query.clone(),
+            response: response.clone(),
+        }))
+        .await?;
+
+        self.track_query(
+            EventData::input_stage("unindexed changes search")
+                .with_payload("query", query)
+                .with_payload("changed_files", &changes.changed_files)
+                .with_payload("matches", changes.matches.len())
+                .with_payload("truncated", changes.truncated)
+                .with_payload("raw_prompt", &response),
+        );
+
+        Ok(response)
+    }
+}
@@ -14,8 +14,8 @@ use crate::{
 impl Agent {
     #[instrument(skip(self))]
     pub async fn code_search(&mut self, query: &String) -> Result<String> {
-        const CODE_SEARCH_LIMIT: u64 = 10;
-        const MINIMUM_RESULTS: usize = CODE_SEARCH_LIMIT as usize / 2;
+        let retrieval = self.retrieval();
+        let minimum_results = retrieval.semantic_k as usize / 2;
 
         self.update(Update::StartStep(SearchStep::Code {
             query: query.clone(),
@@ -28,9 +28,9 @@ impl Agent {
                 query.into(),
                 vec![],
                 SemanticSearchParams {
-                    limit: CODE_SEARCH_LIMIT,
+                    limit: retrieval.semantic_k,
                     offset: 0,
-                    threshold: 0.3,
+                    threshold: retrieval.min_similarity,
                     exact_match: false,
                 },
             )
@@ -38,7 +38,7 @@ impl Agent {
 
         debug!("returned {} results", results.len());
 
-        let hyde_docs = if results.len() < MINIMUM_RESULTS {
+        let hyde_docs = if results.len() < minimum_results {
             info!("too few results returned, running HyDE");
 
             let hyde_docs = self.hyde(query).await?;
@@ -49,9 +49,9 @@ impl Agent {
                         hyde_doc,
                         vec![],
                         SemanticSearchParams {
-                            limit: CODE_SEARCH_LIMIT,
+                            limit: retrieval.semantic_k,
                             offset: 0,
-                            threshold: 0.3,
+                            threshold: retrieval.min_similarity,
                             exact_match: false,
                         },
                     )
@@ -65,7 +65,8 @@ impl Agent {
             vec![]
         };
 
-        let mut chunks = results
+        let mut chunks = retrieval
+            .limit_per_file(results, |chunk| chunk.relative_path.as_str())
             .into_iter()
             .map(|chunk| {
                 let relative_path = chunk.relative_path;
@@ -115,6 +116,7 @@ impl Agent {
                 .with_payload("query", query)
                 .with_payload("hyde_queries", &hyde_docs)
                 .with_payload("chunks", &chunks)
+                .with_payload("retrieval", &retrieval)
                 .with_payload("raw_prompt", &response),
         );
 
@@ -132,14 +134,19 @@ impl Agent {
 
         trace!(?query, "generating hyde docs");
 
+        const HYDE_MODEL: &str = "gpt-3.5-turbo-0613";
+
         let response = self
             .llm_gateway
             .clone()
-            .model("gpt-3.5-turbo-0613")
+            .model(HYDE_MODEL)
             .temperature(0.0)
             .chat(&prompt, None)
             .await?;
 
+        self.record_usage("hyde", HYDE_MODEL, HYDE_MODEL, &prompt, None, &response)
+            .await;
+
         trace!("parsing hyde response");
 
         let documents = prompts::try_parse_hypothetical_documents(&response);
@@ -0,0 +1,65 @@
+use anyhow::{anyhow, Result};
+use tracing::instrument;
+
+use crate::{
+    agent::{
+        exchange::{SearchStep, Update},
+        Agent,
+    },
+    analytics::EventData,
+    repo::history,
+};
+
+impl Agent {
+    #[instrument(skip(self))]
+    pub async fn git_history(&mut self, query: &history::Query) -> Result<String> {
+        self.update(Update::StartStep(SearchStep::History {
+            query: query.clone(),
+            response: String::new(),
+        }))
+        .await?;
+
+        // In a workspace, the path names the repository to query the history of
+        let (repo_ref, path) = match &query.path {
+            Some(path) => {
+                let (repo_ref, path) = self.resolve_path(path);
+                (repo_ref.clone(), Some(path.to_owned()))
+            }
+            None => (self.repo_ref.clone(), None),
+        };
+
+        let (disk_path, remote) = self
+            .app
+            .repo_pool
+            .read_async(&repo_ref, |_, repo| {
+                (repo.disk_path.clone(), repo.remote.clone())
+            })
+            .await
+            .ok_or_else(|| anyhow!("repository not found"))?;
+
+        let repo_query = history::Query {
+            path,
+            ..query.clone()
+        };
+
+        let response = tokio::task::spawn_blocking(move || repo_query.run(&disk_path, &remote))
+            .await?
+            // The model can pick another commit or path after a failed query
+            .unwrap_or_else(|err| format!("The history query failed: {err:#}"));
+
+        self.update(Update::ReplaceStep(SearchStep::History {
+            query: query.clone(),
+            response: response.clone(),
+        }))
+        .await?;
+
+        self.track_query(
+            EventData::input_stage("git history")
+                .with_payload("query", query)
+                .with_payload("repo_ref", repo_ref.to_string())
+                .with_payload("raw_prompt", &response),
+        );
+
+        Ok(response)
+    }
+}
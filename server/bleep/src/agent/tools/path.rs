@@ -25,6 +25,7 @@ impl Agent {
         let mut paths = self
             .fuzzy_path_search(query)
             .await
+            .into_iter()
             .map(|c| c.relative_path)
             .collect::<HashSet<_>>() // TODO: This shouldn't be necessary. Path search should return unique results.
             .into_iter()
@@ -0,0 +1,202 @@
+use std::pin::pin;
+
+use anyhow::{bail, Context, Result};
+use futures::StreamExt;
+use tracing::{debug, instrument};
+
+use crate::{
+    agent::{
+        exchange::{Plan, PlanStatus, PlanStep, PlanStepStatus, Update},
+        prompts, Action, Agent,
+    },
+    analytics::EventData,
+    llm_gateway,
+};
+
+impl Agent {
+    /// Draft a numbered plan for a query, which the user has to approve before it is executed.
+    #[instrument(skip(self))]
+    pub async fn plan(&mut self, query: &str) -> Result<()> {
+        let system_prompt = prompts::plan_prompt(&self.scratchpads);
+        let messages = vec![
+            llm_gateway::api::Message::system(&system_prompt),
+            llm_gateway::api::Message::user(query),
+        ];
+
+        let mut stream = pin!(self.llm_gateway.chat_stream(&messages, None).await?);
+
+        let mut response = String::new();
+        while let Some(fragment) = stream.next().await {
+            response += &fragment?;
+
+            self.update(Update::Plan(Plan {
+                steps: parse_plan(&response),
+                status: PlanStatus::Drafting,
+            }))
+            .await?;
+        }
+
+        self.record_usage(
+            "plan",
+            self.agent_model.model_name,
+            self.agent_model.tokenizer,
+            &messages,
+            None,
+            &response,
+        )
+        .await;
+
+        let steps = parse_plan(&response);
+        if steps.is_empty() {
+            bail!("model did not return a plan");
+        }
+
+        self.update(Update::Plan(Plan {
+            steps,
+            status: PlanStatus::AwaitingApproval,
+        }))
+        .await?;
+
+        self.update(Update::SetTimestamp).await?;
+
+        self.track_query(
+            EventData::output_stage("plan")
+                .with_payload("query", query)
+                .with_payload("response", &response)
+                .with_payload("raw_prompt", &system_prompt),
+        );
+
+        Ok(())
+    }
+
+    /// Execute the approved plan of the last exchange, one step at a time.
+    ///
+    /// For every step, the model chooses a single function to call. The results are then used to
+    /// answer the original query, as if the model had called them itself.
+    #[instrument(skip(self))]
+    pub async fn execute_plan(&mut self) -> Result<()> {
+        let plan = self
+            .last_exchange()
+            .plan
+            .clone()
+            .context("exchange does not have a plan")?;
+
+        if plan.status != PlanStatus::Approved {
+            bail!("plan has not been approved");
+        }
+
+        for (index, step) in plan.steps.iter().enumerate() {
+            if step.status == PlanStepStatus::Done {
+                continue;
+            }
+
+            self.update(Update::PlanStep {
+                index,
+                status: PlanStepStatus::Running,
+            })
+            .await?;
+
+            let instruction = format!(
+                "Execute step {} of the approved plan: {}",
+                index + 1,
+                step.description
+            );
+
+            match self.next_action(Some(&instruction)).await? {
+                // A step that doesn't need any more information is done as-is.
+                Action::Answer { .. } => debug!(index, "model skipped plan step"),
+                action => {
+                    self.run_tool(&action).await?;
+                }
+            }
+
+            self.update(Update::PlanStep {
+                index,
+                status: PlanStepStatus::Done,
+            })
+            .await?;
+        }
+
+        let mut plan = self.last_exchange().plan.clone().unwrap_or(plan);
+        plan.status = PlanStatus::Executed;
+        self.update(Update::Plan(plan)).await?;
+
+        Ok(())
+    }
+}
+
+/// Parse the numbered steps out of a (possibly partial) plan.
+///
+/// Lines that are not numbered are treated as a continuation of the previous step.
+fn parse_plan(response: &str) -> Vec<PlanStep> {
+    let mut steps = Vec::<String>::new();
+
+    for line in response.lines().map(str::trim) {
+        let numbered = line
+            .split_once(['.', ')'])
+            .filter(|(number, _)| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+            .map(|(_, rest)| rest.trim());
+
+        match (numbered, steps.last_mut()) {
+            (Some(description), _) => steps.push(description.to_owned()),
+            (None, Some(last)) if !line.is_empty() => {
+                last.push(' ');
+                last.push_str(line);
+            }
+            (None, _) => {}
+        }
+    }
+
+    steps
+        .into_iter()
+        .filter(|description| !description.is_empty())
+        .take(Plan::MAX_STEPS)
+        .map(|description| PlanStep {
+            description,
+            status: PlanStepStatus::Pending,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptions(response: &str) -> Vec<String> {
+        parse_plan(response)
+            .into_iter()
+            .map(|step| step.description)
+            .collect()
+    }
+
+    #[test]
+    fn parses_numbered_steps() {
+        let response = "Here is the plan:\n\
+            1. Find the HTTP router\n\
+            2) Read the auth middleware,\n   \
+            and the session store\n\
+            \n\
+            3. Summarize the request flow";
+
+        assert_eq!(
+            descriptions(response),
+            [
+                "Find the HTTP router",
+                "Read the auth middleware, and the session store",
+                "Summarize the request flow",
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_partial_plans() {
+        assert!(descriptions("").is_empty());
+        assert!(descriptions("Sure, let me").is_empty());
+        assert_eq!(descriptions("1. Find the"), ["Find the"]);
+        assert_eq!(descriptions("1. Find it\n2."), ["Find it"]);
+        assert_eq!(
+            descriptions("v1.2 is not a step\n1. Is a step"),
+            ["Is a step"]
+        );
+    }
+}
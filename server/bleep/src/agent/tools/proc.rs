@@ -31,12 +31,13 @@ impl Agent {
         }))
         .await?;
 
+        let retrieval = self.retrieval();
         let results = self
             .semantic_search(
                 query.into(),
                 paths.clone(),
                 SemanticSearchParams {
-                    limit: 10,
+                    limit: retrieval.semantic_k,
                     offset: 0,
                     threshold: 0.0,
                     exact_match: true,
@@ -44,7 +45,8 @@ impl Agent {
             )
             .await?;
 
-        let mut chunks = results
+        let mut chunks = retrieval
+            .limit_per_file(results, |chunk| chunk.relative_path.as_str())
             .into_iter()
             .map(|chunk| {
                 let relative_path = chunk.relative_path;
@@ -0,0 +1,94 @@
+use anyhow::Result;
+use tracing::instrument;
+
+use crate::{
+    agent::{
+        exchange::{SearchStep, Update},
+        Agent,
+    },
+    analytics::EventData,
+    repo::sql_schema::Schema,
+};
+
+/// Tables beyond this are left out of the response, the model can narrow its query instead.
+const MAX_TABLES: usize = 30;
+
+/// Only the most recent statements of a table are listed.
+const MAX_HISTORY: usize = 5;
+
+impl Agent {
+    #[instrument(skip(self))]
+    pub async fn schema_search(&mut self, query: &String) -> Result<String> {
+        self.update(Update::StartStep(SearchStep::Schema {
+            query: query.clone(),
+            response: String::new(),
+        }))
+        .await?;
+
+        let schema = Schema::for_repo(&self.app.indexes.file, &self.repo_ref).await;
+        let found = schema.search(query);
+
+        let mut lines = vec![];
+        for table in found.tables.iter().take(MAX_TABLES) {
+            let alias = self.get_path_alias(&table.path);
+            lines.push(format!(
+                "{alias}: {}:{}: table {}",
+                table.path, table.line, table.name
+            ));
+
+            for column in &table.columns {
+                match &column.references {
+                    Some(target) => {
+                        lines.push(format!("  {} {} -> {target}", column.name, column.ty))
+                    }
+                    None => lines.push(format!("  {} {}", column.name, column.ty)),
+                }
+            }
+
+            let skipped = table.history.len().saturating_sub(MAX_HISTORY);
+            if table.history.len() > 1 {
+                lines.push("  history:".to_owned());
+                if skipped > 0 {
+                    lines.push(format!("    {skipped} earlier statements"));
+                }
+                for change in &table.history[skipped..] {
+                    lines.push(format!(
+                        "    {}:{}: {}",
+                        change.path, change.line, change.statement
+                    ));
+                }
+            }
+        }
+
+        if found.tables.len() > MAX_TABLES {
+            lines.push(format!(
+                "{} more tables match, use a more specific query",
+                found.tables.len() - MAX_TABLES
+            ));
+        }
+
+        let response = if schema.tables.is_empty() {
+            "There are no SQL migrations or schema files in this repository".to_owned()
+        } else if lines.is_empty() {
+            "No tables or columns match the query".to_owned()
+        } else {
+            lines.join("\n")
+        };
+
+        self.update(Update::ReplaceStep(SearchStep::Schema {
+            query: query.clone(),
+            response: response.clone(),
+        }))
+        .await?;
+
+        self.track_query(
+            EventData::input_stage("sql schema search")
+                .with_payload("query", query)
+                .with_payload("tables", schema.tables.len())
+                .with_payload("matches", found.tables.len())
+                .with_payload("raw_prompt", &response),
+        );
+
+        Ok(response)
+    }
+}
@@ -0,0 +1,77 @@
+use anyhow::{Context, Result};
+use tracing::instrument;
+
+use crate::{
+    agent::{
+        exchange::{SearchStep, Update},
+        Agent,
+    },
+    analytics::EventData,
+    webserver::answer::{
+        conversations::ConversationId,
+        scratchpads::{self, MAX_SCRATCHPADS},
+    },
+};
+
+impl Agent {
+    #[instrument(skip(self, content))]
+    pub async fn write_scratchpad(&mut self, name: &String, content: &String) -> Result<String> {
+        self.update(Update::StartStep(SearchStep::Scratchpad {
+            name: name.clone(),
+            content: content.clone(),
+            response: String::new(),
+        }))
+        .await?;
+
+        let conversation_id = ConversationId {
+            thread_id: self.thread_id,
+            user_id: self
+                .user
+                .username()
+                .context("didn't have user ID")?
+                .to_owned(),
+        };
+
+        // Invalid scratchpads are reported back to the model, so that it can try again.
+        let response = if let Err(e) = scratchpads::validate(name, content) {
+            format!("Failed to save scratchpad: {e}")
+        } else {
+            match scratchpads::save(&self.app.sql, &conversation_id, name, content).await? {
+                Some(scratchpad) => {
+                    let response = format!("Saved scratchpad `{}`", scratchpad.name);
+                    match self
+                        .scratchpads
+                        .iter_mut()
+                        .find(|s| s.name == scratchpad.name)
+                    {
+                        Some(existing) => *existing = scratchpad,
+                        None => {
+                            self.scratchpads.push(scratchpad);
+                            self.scratchpads.sort_by(|a, b| a.name.cmp(&b.name));
+                        }
+                    }
+                    response
+                }
+                None => format!(
+                    "There are already {MAX_SCRATCHPADS} scratchpads, overwrite one of them instead"
+                ),
+            }
+        };
+
+        self.update(Update::ReplaceStep(SearchStep::Scratchpad {
+            name: name.clone(),
+            content: content.clone(),
+            response: response.clone(),
+        }))
+        .await?;
+
+        self.track_query(
+            EventData::input_stage("write scratchpad")
+                .with_payload("name", name)
+                .with_payload("content", content)
+                .with_payload("raw_prompt", &response),
+        );
+
+        Ok(response)
+    }
+}
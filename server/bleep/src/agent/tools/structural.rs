@@ -0,0 +1,94 @@
+use anyhow::Result;
+use tracing::instrument;
+
+use crate::{
+    agent::{
+        exchange::{SearchStep, Update},
+        Agent,
+    },
+    analytics::EventData,
+    intelligence::structural,
+};
+
+/// The most matches shown to the model, across repositories.
+const MAX_MATCHES: usize = 30;
+
+impl Agent {
+    #[instrument(skip(self))]
+    pub async fn structural_search(&mut self, pattern: &structural::Pattern) -> Result<String> {
+        self.update(Update::StartStep(SearchStep::Structural {
+            pattern: pattern.clone(),
+            response: String::new(),
+        }))
+        .await?;
+
+        let mut matches = vec![];
+        let mut truncated = false;
+
+        let response = match pattern.clone().compile() {
+            // The model can fix its pattern after reading the error
+            Err(err) => err,
+            Ok(matcher) => {
+                for repo_ref in self.repos().cloned().collect::<Vec<_>>() {
+                    let limit = MAX_MATCHES - matches.len();
+                    if limit == 0 {
+                        truncated = true;
+                        break;
+                    }
+
+                    let results = matcher
+                        .search(&self.app.indexes.file, &repo_ref, None, None, limit)
+                        .await;
+
+                    truncated |= results.truncated;
+                    matches.extend(results.matches.into_iter().map(|m| {
+                        let path =
+                            self.qualify_path(&repo_ref.to_string(), m.relative_path.clone());
+                        (path, m)
+                    }));
+                }
+
+                if matches.is_empty() {
+                    "No matches".to_owned()
+                } else {
+                    let mut lines = matches
+                        .iter()
+                        .map(|(path, m)| {
+                            let alias = self.get_path_alias(path);
+                            format!(
+                                "{alias}: {path}:{}-{}: {} {}: {}",
+                                m.range.start.line + 1,
+                                m.range.end.line + 1,
+                                m.kind,
+                                m.name.as_deref().unwrap_or("-"),
+                                m.snippet,
+                            )
+                        })
+                        .collect::<Vec<_>>();
+
+                    if truncated {
+                        lines.push("(more matches were left out)".to_owned());
+                    }
+
+                    lines.join("\n")
+                }
+            }
+        };
+
+        self.update(Update::ReplaceStep(SearchStep::Structural {
+            pattern: pattern.clone(),
+            response: response.clone(),
+        }))
+        .await?;
+
+        self.track_query(
+            EventData::input_stage("structural search")
+                .with_payload("pattern", pattern)
+                .with_payload("matches", matches.len())
+                .with_payload("truncated", truncated)
+                .with_payload("raw_prompt", &response),
+        );
+
+        Ok(response)
+    }
+}
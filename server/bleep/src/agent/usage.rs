@@ -0,0 +1,81 @@
+//! The tokens used by the model calls of every exchange, and what they cost.
+//!
+//! Usage is recorded as calls finish, so that cancelled answers are still accounted for. Calls
+//! served from the response cache aren't made, and aren't recorded. Tokens are also counted in
+//! the [metrics](crate::metrics) of the instance.
+
+use tracing::error;
+
+use super::{model, Agent};
+use crate::{
+    llm_gateway::{self, Usage},
+    metrics,
+};
+
+impl Agent {
+    /// Record a model call of the last exchange.
+    ///
+    /// `step` names what the call was for, like `agent` or `answer`. Failing to record usage
+    /// doesn't fail the exchange.
+    pub(crate) async fn record_usage(
+        &self,
+        step: &str,
+        model_name: &str,
+        tokenizer: &str,
+        messages: &[llm_gateway::api::Message],
+        functions: Option<&[llm_gateway::api::Function]>,
+        completion: &str,
+    ) {
+        let usage = match Usage::count(tokenizer, messages, functions, completion) {
+            Ok(usage) => usage,
+            Err(err) => {
+                error!(?err, step, "failed to count tokens");
+                return;
+            }
+        };
+
+        // Metrics count the tokens of every user, including those that aren't signed in
+        metrics::LLM_TOKENS
+            .with_label_values(&[model_name, "prompt"])
+            .inc_by(usage.prompt_tokens as u64);
+        metrics::LLM_TOKENS
+            .with_label_values(&[model_name, "completion"])
+            .inc_by(usage.completion_tokens as u64);
+
+        let Some(user_id) = self.user.username() else {
+            return;
+        };
+
+        // Models behind a configured endpoint aren't charged for by the gateway
+        let cost = if self.llm_gateway.is_gateway() {
+            model::prices(model_name).map(|(prompt, completion)| usage.cost(prompt, completion))
+        } else {
+            Some(0.0)
+        };
+
+        let thread_id = self.thread_id.to_string();
+        let exchange_id = self.last_exchange().id.to_string();
+        let prompt_tokens = usage.prompt_tokens as i64;
+        let completion_tokens = usage.completion_tokens as i64;
+
+        let result = sqlx::query!(
+            "INSERT INTO token_usage \
+             (user_id, thread_id, exchange_id, model, step, prompt_tokens, completion_tokens, cost) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            user_id,
+            thread_id,
+            exchange_id,
+            model_name,
+            step,
+            prompt_tokens,
+            completion_tokens,
+            cost,
+        )
+        .execute(&*self.app.sql)
+        .await;
+
+        if let Err(err) = result {
+            error!(?err, step, "failed to record token usage");
+        }
+    }
+}
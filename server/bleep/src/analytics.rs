@@ -103,6 +103,19 @@ impl RepoEvent {
     }
 }
 
+/// A grade given by a reviewer to an exchange sampled for review.
+#[derive(Debug, Clone)]
+pub struct ReviewEvent {
+    pub review_id: i64,
+    pub query_id: String,
+    pub thread_id: String,
+    pub repo_ref: String,
+    /// From 1 to 5
+    pub grade: u8,
+    /// Whether the reviewer left a comment. Comments aren't sent, as they may quote the answer.
+    pub commented: bool,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct EventData {
     kind: EventKind,
@@ -141,6 +154,14 @@ impl EventData {
             .push((name.to_string(), serde_json::to_value(payload).unwrap()));
         self
     }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn payload(&self, name: &str) -> Option<&Value> {
+        self.payload.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -283,6 +304,26 @@ impl RudderHub {
         }
     }
 
+    pub fn track_review(&self, reviewer: &crate::webserver::middleware::User, event: ReviewEvent) {
+        if let Some(options) = &self.options {
+            self.send(Message::Track(Track {
+                user_id: Some(self.tracking_id(reviewer.username())),
+                event: "answer review".to_owned(),
+                properties: Some(json!({
+                    "device_id": self.device_id(),
+                    "review_id": event.review_id,
+                    "query_id": event.query_id,
+                    "thread_id": event.thread_id,
+                    "repo_ref": event.repo_ref,
+                    "grade": event.grade,
+                    "commented": event.commented,
+                    "package_metadata": options.package_metadata,
+                })),
+                ..Default::default()
+            }));
+        }
+    }
+
     pub fn track_synced_repos(&self, count: usize, username: Option<&str>, org_name: Option<&str>) {
         self.send(Message::Track(Track {
             user_id: Some(self.tracking_id(username)),
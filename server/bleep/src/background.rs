@@ -5,11 +5,13 @@ use tokio::sync::Semaphore;
 use tracing::{debug, error, info};
 
 use crate::{
+    config::minimum_parallelism,
+    metrics,
     repo::{BranchFilterConfig, RepoRef, SyncStatus},
     Application, Configuration,
 };
 
-use std::{future::Future, pin::Pin, sync::Arc, thread};
+use std::{future::Future, pin::Pin, sync::Arc, thread, time::Instant};
 
 mod sync;
 pub(crate) use sync::{SyncConfig, SyncHandle};
@@ -17,9 +19,17 @@ pub(crate) use sync::{SyncConfig, SyncHandle};
 mod control;
 pub(crate) use control::SyncPipes;
 
+pub(crate) mod jobs;
+use jobs::JobState;
+
+pub(crate) mod reindex;
+
 mod notifyqueue;
 use notifyqueue::NotifyQueue;
 
+mod priority;
+pub(crate) use priority::{interactive, yield_to_interactive};
+
 type ProgressStream = tokio::sync::broadcast::Sender<Progress>;
 
 static RAYON_POOL: OnceCell<ThreadPool> = OnceCell::new();
@@ -34,11 +44,11 @@ pub fn rayon_pool() -> &'static ThreadPool {
 #[derive(serde::Serialize, Clone)]
 pub struct Progress {
     #[serde(rename = "ref")]
-    reporef: RepoRef,
+    pub(crate) reporef: RepoRef,
     #[serde(rename = "b")]
     branch_filter: Option<BranchFilterConfig>,
     #[serde(rename = "ev")]
-    event: ProgressEvent,
+    pub(crate) event: ProgressEvent,
 }
 
 #[derive(serde::Serialize, Clone)]
@@ -81,6 +91,12 @@ impl BackgroundExecutor {
 
         let tokio_ref = tokio.clone();
 
+        // Leave some cores to interactive requests, so that indexing doesn't slow down answers
+        let index_threads = config
+            .max_threads
+            .saturating_sub(config.interactive_threads)
+            .max(minimum_parallelism());
+
         // test can re-initialize the app, and we shouldn't fail
         let rayon_pool = rayon::ThreadPoolBuilder::new()
             .spawn_handler(move |thread| {
@@ -103,7 +119,7 @@ impl BackgroundExecutor {
                     })
                     .map(|_| ())
             })
-            .num_threads(config.max_threads)
+            .num_threads(index_threads)
             .build()
             .unwrap();
 
@@ -170,10 +186,18 @@ impl SyncQueue {
                         Ok(_) => {
                             tokio::task::spawn(async move {
                                 info!(?next.reporef, "indexing");
+                                jobs::start(&next).await;
 
+                                let started = Instant::now();
                                 let result = next.run(permit).await;
                                 _ = active.remove(&next.reporef);
 
+                                let (state, error) = jobs::outcome(&result);
+                                metrics::INDEX_SECONDS
+                                    .with_label_values(&[state.as_str()])
+                                    .observe(started.elapsed().as_secs_f64());
+                                jobs::finish(&next, state, error).await;
+
                                 if result.is_ok() {
                                     debug!(?result, "sync finished");
                                 } else {
@@ -223,11 +247,25 @@ impl SyncQueue {
 
         output
     }
+
+    /// The index progress of a running job, in percent.
+    pub(crate) async fn job_progress(&self, job_id: i64) -> Option<u8> {
+        let mut progress = None;
+        self.active
+            .scan_async(|_, handle| {
+                if handle.job_id == Some(job_id) {
+                    progress = Some(handle.pipes.percent());
+                }
+            })
+            .await;
+
+        progress
+    }
 }
 
 #[derive(serde::Serialize, Debug)]
 pub(crate) struct QueuedRepoStatus {
-    reporef: RepoRef,
+    pub(crate) reporef: RepoRef,
     branch_filter: Option<BranchFilterConfig>,
     state: QueueState,
 }
@@ -242,12 +280,14 @@ pub(crate) enum QueueState {
 pub struct BoundSyncQueue(pub(crate) Application);
 impl BoundSyncQueue {
     /// Enqueue repo for syncing
-    pub(crate) async fn enqueue(self, config: SyncConfig) {
-        self.0
-            .sync_queue
-            .queue
-            .push(config.into_handle().await)
-            .await;
+    ///
+    /// Returns the ID of the job, if it could be recorded.
+    pub(crate) async fn enqueue(self, config: SyncConfig) -> Option<i64> {
+        let handle = config.into_handle().await;
+        let job_id = handle.job_id;
+
+        self.0.sync_queue.queue.push(handle).await;
+        job_id
     }
 
     /// Enqueue repos for syncing with the current configuration.
@@ -302,7 +342,10 @@ impl BoundSyncQueue {
 
         if active.is_none() {
             // Re-queue to the front, so clean any currently queued refs
-            jobs.queue.remove(reporef.clone()).await;
+            for handle in jobs.queue.remove(reporef.clone()).await {
+                self::jobs::finish(&handle, JobState::Cancelled, None).await;
+            }
+
             app.repo_pool
                 .update_async(&reporef, |_k, v| v.mark_removed())
                 .await?;
@@ -315,6 +358,12 @@ impl BoundSyncQueue {
         Some(())
     }
 
+    /// Whether the repository is queued for syncing, or being synced.
+    pub(crate) async fn is_pending(&self, reporef: &RepoRef) -> bool {
+        let jobs = &self.0.sync_queue;
+        jobs.queue.contains(reporef).await || jobs.active.contains(reporef)
+    }
+
     pub(crate) async fn cancel(&self, reporef: RepoRef) {
         self.0
             .sync_queue
@@ -326,6 +375,32 @@ impl BoundSyncQueue {
             .await;
     }
 
+    /// Cancel a job, whether it is queued or running.
+    ///
+    /// Returns whether the job was found.
+    pub(crate) async fn cancel_job(&self, job_id: i64) -> bool {
+        let queue = &self.0.sync_queue;
+
+        if let Some(handle) = queue.queue.remove_job(job_id).await {
+            jobs::finish(&handle, JobState::Cancelled, None).await;
+            return true;
+        }
+
+        let mut found = false;
+        queue
+            .active
+            .for_each_async(|_, handle| {
+                if handle.job_id == Some(job_id) {
+                    handle.set_status(|_| SyncStatus::Cancelling);
+                    handle.pipes.cancel();
+                    found = true;
+                }
+            })
+            .await;
+
+        found
+    }
+
     pub(crate) async fn startup_scan(self) -> anyhow::Result<()> {
         let Self(Application { ref repo_pool, .. }) = self;
 
@@ -1,6 +1,6 @@
 use std::{
     sync::{
-        atomic::{AtomicBool, AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
         Arc, RwLock,
     },
     time::{Duration, Instant},
@@ -37,6 +37,9 @@ pub struct SyncPipes {
 
     /// Interrupt signal channel for `gix`
     git_interrupt: Arc<AtomicBool>,
+
+    /// The last index progress that was reported, in percent
+    percent: AtomicU8,
 }
 
 impl SyncPipes {
@@ -51,6 +54,7 @@ impl SyncPipes {
             filter_updates,
             git_interrupt: Default::default(),
             event: Default::default(),
+            percent: Default::default(),
         }
     }
 
@@ -75,6 +79,7 @@ impl SyncPipes {
     }
 
     pub(crate) fn index_percent(&self, current: u8) {
+        self.percent.store(current, Ordering::Relaxed);
         _ = self.progress.send(Progress {
             reporef: self.reporef.clone(),
             branch_filter: self.filter_updates.branch_filter.clone(),
@@ -90,6 +95,11 @@ impl SyncPipes {
         });
     }
 
+    /// How much of the repository has been indexed, in percent.
+    pub(crate) fn percent(&self) -> u8 {
+        self.percent.load(Ordering::Relaxed)
+    }
+
     pub(crate) fn is_interrupted(&self) -> Arc<AtomicBool> {
         Arc::clone(&self.git_interrupt)
     }
@@ -0,0 +1,244 @@
+//! Records of sync jobs, kept in the database so that they outlive the server.
+//!
+//! A record is made for every [`SyncHandle`], and follows it from the queue to the end of its
+//! run. Jobs that a restart interrupted are failed on startup, and queued again.
+
+use serde::Serialize;
+use tracing::{error, info};
+
+use super::sync::{SyncError, SyncHandle};
+use crate::{
+    db::SqlDb,
+    repo::{RepoRef, SyncStatus},
+    Application,
+};
+
+/// The most jobs listed at once.
+pub(crate) const MAX_JOBS: i64 = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Running => "running",
+            Self::Done => "done",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Whether a job in `state` has stopped, and can be run again.
+pub(crate) fn is_finished(state: &str) -> bool {
+    [JobState::Done, JobState::Failed, JobState::Cancelled]
+        .iter()
+        .any(|finished| finished.as_str() == state)
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct Job {
+    pub(crate) id: i64,
+    pub(crate) repo_ref: String,
+    pub(crate) state: String,
+    pub(crate) shallow: bool,
+    pub(crate) force: bool,
+    pub(crate) error: Option<String>,
+    /// The job this one runs again
+    pub(crate) retry_of: Option<i64>,
+    /// The reindex campaign that queued the job
+    pub(crate) campaign_id: Option<i64>,
+    pub(crate) created_at: i64,
+    pub(crate) started_at: Option<i64>,
+    pub(crate) finished_at: Option<i64>,
+    /// How much of the repository was indexed, in percent, while the job runs
+    pub(crate) progress: Option<u8>,
+}
+
+/// Record a job for a sync that is about to be queued.
+///
+/// Syncs still run if the record can't be written, they just can't be controlled by ID.
+pub(super) async fn create(
+    db: &SqlDb,
+    reporef: &RepoRef,
+    shallow: bool,
+    force: bool,
+    retry_of: Option<i64>,
+    campaign_id: Option<i64>,
+) -> Option<i64> {
+    let repo_ref = reporef.to_string();
+    let state = JobState::Queued.as_str();
+
+    let created = sqlx::query_scalar!(
+        r#"INSERT INTO sync_jobs (repo_ref, state, shallow, force, retry_of, campaign_id)
+        VALUES (?, ?, ?, ?, ?, ?)
+        RETURNING id AS "id!""#,
+        repo_ref,
+        state,
+        shallow,
+        force,
+        retry_of,
+        campaign_id,
+    )
+    .fetch_one(db.as_ref())
+    .await;
+
+    match created {
+        Ok(id) => Some(id),
+        Err(err) => {
+            error!(?err, %reporef, "failed to record sync job");
+            None
+        }
+    }
+}
+
+pub(super) async fn start(handle: &SyncHandle) {
+    let Some(id) = handle.job_id else {
+        return;
+    };
+
+    let state = JobState::Running.as_str();
+    if let Err(err) = sqlx::query!(
+        "UPDATE sync_jobs SET state = ?, started_at = strftime('%s', 'now') WHERE id = ?",
+        state,
+        id,
+    )
+    .execute(handle.app.sql.as_ref())
+    .await
+    {
+        error!(?err, id, "failed to record start of sync job");
+    }
+}
+
+pub(super) async fn finish(handle: &SyncHandle, job_state: JobState, error: Option<String>) {
+    let Some(id) = handle.job_id else {
+        return;
+    };
+
+    let state = job_state.as_str();
+    if let Err(err) = sqlx::query!(
+        "UPDATE sync_jobs SET state = ?, error = ?, finished_at = strftime('%s', 'now')
+        WHERE id = ?",
+        state,
+        error,
+        id,
+    )
+    .execute(handle.app.sql.as_ref())
+    .await
+    {
+        error!(?err, id, "failed to record end of sync job");
+    }
+}
+
+/// How a run of a sync ended.
+pub(super) fn outcome(result: &Result<SyncStatus, SyncError>) -> (JobState, Option<String>) {
+    match result {
+        Ok(SyncStatus::Error { message }) => (JobState::Failed, Some(message.clone())),
+        Ok(SyncStatus::Cancelled) | Err(SyncError::Cancelled) => (JobState::Cancelled, None),
+        Ok(_) => (JobState::Done, None),
+        Err(err) => (JobState::Failed, Some(err.to_string())),
+    }
+}
+
+/// The latest jobs, newest first.
+pub(crate) async fn list(db: &SqlDb, limit: i64) -> anyhow::Result<Vec<Job>> {
+    Ok(sqlx::query_as!(
+        Job,
+        r#"SELECT id, repo_ref, state, shallow, force, error, retry_of, campaign_id, created_at,
+            started_at, finished_at, NULL AS "progress: u8"
+        FROM sync_jobs
+        ORDER BY id DESC
+        LIMIT ?"#,
+        limit,
+    )
+    .fetch_all(db.as_ref())
+    .await?)
+}
+
+pub(crate) async fn get(db: &SqlDb, id: i64) -> anyhow::Result<Option<Job>> {
+    Ok(sqlx::query_as!(
+        Job,
+        r#"SELECT id, repo_ref, state, shallow, force, error, retry_of, campaign_id, created_at,
+            started_at, finished_at, NULL AS "progress: u8"
+        FROM sync_jobs
+        WHERE id = ?"#,
+        id,
+    )
+    .fetch_optional(db.as_ref())
+    .await?)
+}
+
+/// Fail the jobs that were queued or running when the server stopped, and queue them again.
+pub(crate) async fn recover(app: &Application) -> anyhow::Result<()> {
+    let queued = JobState::Queued.as_str();
+    let running = JobState::Running.as_str();
+    let failed = JobState::Failed.as_str();
+
+    let interrupted = sqlx::query!(
+        r#"UPDATE sync_jobs
+        SET state = ?, error = 'interrupted by a restart', finished_at = strftime('%s', 'now')
+        WHERE state IN (?, ?)
+        RETURNING id AS "id!", repo_ref AS "repo_ref!", shallow AS "shallow!: bool",
+            force AS "force!: bool""#,
+        failed,
+        queued,
+        running,
+    )
+    .fetch_all(app.sql.as_ref())
+    .await?;
+
+    for job in interrupted {
+        let Ok(reporef) = job.repo_ref.parse::<RepoRef>() else {
+            continue;
+        };
+
+        // Removed repositories would be added again by syncing them
+        if !app.repo_pool.contains(&reporef) || app.write_index().is_pending(&reporef).await {
+            continue;
+        }
+
+        info!(%reporef, "queueing interrupted sync again");
+        app.write_index()
+            .enqueue(
+                super::SyncConfig::new(app, reporef)
+                    .shallow(job.shallow)
+                    .force(job.force)
+                    .retry_of(Some(job.id)),
+            )
+            .await;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_sync_results() {
+        assert_eq!(outcome(&Ok(SyncStatus::Done)), (JobState::Done, None));
+        assert_eq!(
+            outcome(&Err(SyncError::Cancelled)),
+            (JobState::Cancelled, None)
+        );
+        assert_eq!(
+            outcome(&Ok(SyncStatus::Error {
+                message: "no space left".into()
+            })),
+            (JobState::Failed, Some("no space left".to_owned()))
+        );
+        assert_eq!(
+            outcome(&Err(SyncError::SyncInProgress)),
+            (JobState::Failed, Some("syncing in progress".to_owned()))
+        );
+    }
+}
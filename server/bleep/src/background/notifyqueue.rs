@@ -66,11 +66,27 @@ impl NotifyQueue {
             .any(|h| &h.reporef == reporef)
     }
 
-    pub(super) async fn remove(&self, reporef: RepoRef) {
+    /// Remove the queued syncs of a repository, and return them.
+    pub(super) async fn remove(&self, reporef: RepoRef) -> Vec<Arc<SyncHandle>> {
+        self.remove_where(|item| item.reporef == reporef).await
+    }
+
+    /// Remove a queued sync by the ID of its job.
+    pub(super) async fn remove_job(&self, job_id: i64) -> Option<Arc<SyncHandle>> {
+        self.remove_where(|item| item.job_id == Some(job_id))
+            .await
+            .pop()
+    }
+
+    async fn remove_where(&self, pred: impl Fn(&SyncHandle) -> bool) -> Vec<Arc<SyncHandle>> {
         let mut q = self.queue.write().await;
-        if let Ok(ticket) = self.available.try_acquire() {
-            ticket.forget();
+        let (removed, kept) = q.drain(..).partition::<Vec<_>, _>(|item| pred(item));
+
+        if let Ok(tickets) = self.available.try_acquire_many(removed.len() as u32) {
+            tickets.forget();
         }
-        q.retain(|item| item.reporef != reporef);
+
+        q.extend(kept);
+        removed
     }
 }
@@ -0,0 +1,179 @@
+use std::{
+    cell::RefCell,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use tracing::trace;
+
+/// How long a background worker may wait for interactive requests in every [`WINDOW`].
+///
+/// A steady stream of requests slows indexing down to half its speed, but never stops it.
+const PAUSE_PER_WINDOW: Duration = Duration::from_millis(500);
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+static LANE: Lane = Lane::new();
+
+thread_local! {
+    static BUDGET: RefCell<Budget> = RefCell::new(Budget::new(Instant::now()));
+}
+
+/// Mark the current request as interactive, until the returned guard is dropped.
+///
+/// Background jobs pause at their next checkpoint while any interactive request is in flight.
+pub(crate) fn interactive() -> Interactive<'static> {
+    LANE.enter()
+}
+
+/// Checkpoint for background jobs, blocking the current thread while interactive requests are
+/// in flight.
+///
+/// This should only be called from the background thread pool.
+pub(crate) fn yield_to_interactive() {
+    BUDGET.with(|budget| {
+        let mut budget = budget.borrow_mut();
+        let remaining = budget.remaining(Instant::now());
+        if remaining.is_zero() {
+            return;
+        }
+
+        let paused = LANE.wait(remaining);
+        budget.spend(paused);
+
+        if !paused.is_zero() {
+            trace!(?paused, "background job yielded to interactive requests");
+        }
+    });
+}
+
+/// The time a worker has paused for in the current window.
+struct Budget {
+    window_start: Instant,
+    paused: Duration,
+}
+
+impl Budget {
+    fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            paused: Duration::ZERO,
+        }
+    }
+
+    /// How much longer the worker can pause in the current window, starting a new one if it has
+    /// ended.
+    fn remaining(&mut self, now: Instant) -> Duration {
+        if now.duration_since(self.window_start) >= WINDOW {
+            *self = Self::new(now);
+        }
+
+        PAUSE_PER_WINDOW.saturating_sub(self.paused)
+    }
+
+    fn spend(&mut self, paused: Duration) {
+        self.paused += paused;
+    }
+}
+
+struct Lane {
+    interactive: AtomicUsize,
+}
+
+impl Lane {
+    const fn new() -> Self {
+        Self {
+            interactive: AtomicUsize::new(0),
+        }
+    }
+
+    fn enter(&self) -> Interactive<'_> {
+        self.interactive.fetch_add(1, Ordering::SeqCst);
+        Interactive { lane: self }
+    }
+
+    /// Wait until there are no interactive requests, or for `max`, returning the time waited.
+    fn wait(&self, max: Duration) -> Duration {
+        let start = Instant::now();
+        while self.interactive.load(Ordering::SeqCst) > 0 && start.elapsed() < max {
+            std::thread::sleep(POLL_INTERVAL.min(max.saturating_sub(start.elapsed())));
+        }
+
+        start.elapsed()
+    }
+}
+
+pub(crate) struct Interactive<'a> {
+    lane: &'a Lane,
+}
+
+impl Drop for Interactive<'_> {
+    fn drop(&mut self) {
+        self.lane.interactive.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn does_not_pause_when_idle() {
+        let lane = Lane::new();
+        drop(lane.enter());
+
+        assert!(lane.wait(PAUSE_PER_WINDOW) < POLL_INTERVAL);
+    }
+
+    #[test]
+    fn pauses_until_interactive_requests_finish() {
+        static LANE: Lane = Lane::new();
+
+        let guard = LANE.enter();
+
+        let request = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            drop(guard);
+        });
+
+        let paused = LANE.wait(PAUSE_PER_WINDOW);
+        request.join().unwrap();
+
+        assert!(paused >= Duration::from_millis(100));
+        assert!(paused < PAUSE_PER_WINDOW);
+    }
+
+    #[test]
+    fn limits_pauses_per_window() {
+        let start = Instant::now();
+        let mut budget = Budget::new(start);
+
+        assert_eq!(budget.remaining(start), PAUSE_PER_WINDOW);
+
+        budget.spend(Duration::from_millis(400));
+        assert_eq!(
+            budget.remaining(start + Duration::from_millis(600)),
+            Duration::from_millis(100)
+        );
+
+        budget.spend(Duration::from_millis(200));
+        assert!(budget
+            .remaining(start + Duration::from_millis(900))
+            .is_zero());
+
+        // Pauses in one window don't count against the next
+        assert_eq!(budget.remaining(start + WINDOW), PAUSE_PER_WINDOW);
+    }
+
+    #[test]
+    fn stops_pausing_after_max_pause() {
+        let lane = Lane::new();
+        let _guard = lane.enter();
+
+        let paused = lane.wait(Duration::from_millis(50));
+        assert!(paused >= Duration::from_millis(50));
+        assert!(paused < Duration::from_secs(1));
+    }
+}
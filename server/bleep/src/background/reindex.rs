@@ -0,0 +1,421 @@
+//! Reindex campaigns, which rebuild the index of many repositories at once, like to re-embed
+//! everything after an upgrade.
+//!
+//! A campaign picks the repositories that match its [`Filter`] when it starts, and keeps at most
+//! `parallelism` of their syncs queued or running at a time, so that syncs of other repositories
+//! aren't stuck behind the whole campaign. Its progress is aggregated from the records of its
+//! [jobs](super::jobs).
+
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use super::{jobs::JobState, SyncConfig};
+use crate::{
+    repo::{RepoRef, Repository, SyncStatus},
+    Application,
+};
+
+/// The most campaigns listed at once.
+pub(crate) const MAX_CAMPAIGNS: i64 = 50;
+
+/// Which repositories a campaign reindexes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "by", rename_all = "snake_case")]
+pub(crate) enum Filter {
+    All,
+    /// Remote repositories of an organization, group or workspace
+    Org {
+        org: String,
+    },
+    /// Repositories whose most common language is `lang`
+    Lang {
+        lang: String,
+    },
+}
+
+impl Filter {
+    fn matches(&self, reporef: &RepoRef, repo: &Repository) -> bool {
+        match self {
+            Self::All => true,
+            Self::Org { org } => org_of(reporef).map_or(false, |o| o.eq_ignore_ascii_case(org)),
+            Self::Lang { lang } => repo
+                .most_common_lang
+                .as_deref()
+                .map_or(false, |l| l.eq_ignore_ascii_case(lang)),
+        }
+    }
+}
+
+/// The organization of a remote repository, which is the first segment of its name.
+fn org_of(reporef: &RepoRef) -> Option<String> {
+    if reporef.is_local() {
+        return None;
+    }
+
+    reporef
+        .display_name()
+        .split_once('/')
+        .map(|(org, _)| org.to_owned())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CampaignState {
+    Running,
+    Done,
+    Cancelled,
+    /// The server stopped before the campaign was done
+    Interrupted,
+}
+
+impl CampaignState {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Running => "running",
+            Self::Done => "done",
+            Self::Cancelled => "cancelled",
+            Self::Interrupted => "interrupted",
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct Progress {
+    pub(crate) id: i64,
+    pub(crate) filter: Filter,
+    pub(crate) state: String,
+    pub(crate) parallelism: i64,
+    /// The number of repositories the campaign reindexes
+    pub(crate) total: i64,
+    pub(crate) created_by: Option<String>,
+    pub(crate) created_at: i64,
+    pub(crate) finished_at: Option<i64>,
+    /// Repositories whose sync hasn't started yet
+    pub(crate) pending: i64,
+    pub(crate) running: i64,
+    pub(crate) done: i64,
+    pub(crate) failed: i64,
+    pub(crate) cancelled: i64,
+    /// How much of the campaign is done, in percent
+    pub(crate) percent: f64,
+    /// The estimated number of seconds left, once a sync has finished to estimate from
+    pub(crate) eta_secs: Option<u64>,
+}
+
+/// Start reindexing the repositories that match `filter`, returning the ID of the campaign.
+pub(crate) async fn start(
+    app: &Application,
+    filter: Filter,
+    parallelism: usize,
+    created_by: Option<&str>,
+) -> anyhow::Result<i64> {
+    let mut repos = vec![];
+    app.repo_pool
+        .scan_async(|reporef, repo| {
+            if repo.sync_status != SyncStatus::Removed && filter.matches(reporef, repo) {
+                repos.push(reporef.clone());
+            }
+        })
+        .await;
+    repos.sort_by_key(RepoRef::to_string);
+
+    let filter_json = serde_json::to_string(&filter)?;
+    let parallelism_count = parallelism as i64;
+    let state = CampaignState::Running.as_str();
+    let total = repos.len() as i64;
+
+    let id = sqlx::query_scalar!(
+        r#"INSERT INTO reindex_campaigns (filter, parallelism, state, total, created_by)
+        VALUES (?, ?, ?, ?, ?)
+        RETURNING id AS "id!""#,
+        filter_json,
+        parallelism_count,
+        state,
+        total,
+        created_by,
+    )
+    .fetch_one(app.sql.as_ref())
+    .await?;
+
+    info!(id, total, parallelism, "starting reindex campaign");
+    tokio::spawn(run(app.clone(), id, repos, parallelism));
+
+    Ok(id)
+}
+
+async fn run(app: Application, id: i64, repos: Vec<RepoRef>, parallelism: usize) {
+    stream::iter(repos)
+        .for_each_concurrent(parallelism, |reporef| {
+            let app = app.clone();
+            async move {
+                // Cancelling stops the syncs that haven't been queued yet
+                if !is_running(&app, id).await {
+                    return;
+                }
+
+                let handle = SyncConfig::new(&app, reporef)
+                    .force(true)
+                    .campaign(Some(id))
+                    .into_handle()
+                    .await;
+                let job_id = handle.job_id;
+                let finished = handle.notify_done();
+
+                app.sync_queue.queue.push(handle).await;
+
+                // The campaign may have been cancelled while the sync was being queued
+                if let (false, Some(job_id)) = (is_running(&app, id).await, job_id) {
+                    app.write_index().cancel_job(job_id).await;
+                }
+
+                _ = finished.recv_async().await;
+            }
+        })
+        .await;
+
+    let running = CampaignState::Running.as_str();
+    let done = CampaignState::Done.as_str();
+
+    let finished = sqlx::query!(
+        "UPDATE reindex_campaigns SET state = ?, finished_at = strftime('%s', 'now')
+        WHERE id = ? AND state = ?",
+        done,
+        id,
+        running,
+    )
+    .execute(app.sql.as_ref())
+    .await;
+
+    match finished {
+        Ok(_) => info!(id, "reindex campaign finished"),
+        Err(err) => error!(?err, id, "failed to record end of reindex campaign"),
+    }
+}
+
+async fn is_running(app: &Application, id: i64) -> bool {
+    let state = sqlx::query_scalar!("SELECT state FROM reindex_campaigns WHERE id = ?", id)
+        .fetch_optional(app.sql.as_ref())
+        .await;
+
+    match state {
+        Ok(state) => state.as_deref() == Some(CampaignState::Running.as_str()),
+        Err(err) => {
+            // Keep going rather than leave the campaign half done
+            error!(?err, id, "failed to check state of reindex campaign");
+            true
+        }
+    }
+}
+
+/// Stop a running campaign, and cancel its syncs that are queued or running.
+///
+/// Returns whether the campaign was running.
+pub(crate) async fn cancel(app: &Application, id: i64) -> anyhow::Result<bool> {
+    let running = CampaignState::Running.as_str();
+    let cancelled = CampaignState::Cancelled.as_str();
+
+    let stopped = sqlx::query!(
+        "UPDATE reindex_campaigns SET state = ?, finished_at = strftime('%s', 'now')
+        WHERE id = ? AND state = ?",
+        cancelled,
+        id,
+        running,
+    )
+    .execute(app.sql.as_ref())
+    .await?
+    .rows_affected()
+        > 0;
+
+    if !stopped {
+        return Ok(false);
+    }
+
+    let queued = JobState::Queued.as_str();
+    let running = JobState::Running.as_str();
+
+    let jobs = sqlx::query_scalar!(
+        r#"SELECT id AS "id!" FROM sync_jobs WHERE campaign_id = ? AND state IN (?, ?)"#,
+        id,
+        queued,
+        running,
+    )
+    .fetch_all(app.sql.as_ref())
+    .await?;
+
+    for job_id in jobs {
+        app.write_index().cancel_job(job_id).await;
+    }
+
+    info!(id, "cancelled reindex campaign");
+    Ok(true)
+}
+
+pub(crate) async fn get(app: &Application, id: i64) -> anyhow::Result<Option<Progress>> {
+    let Some(campaign) = sqlx::query!(
+        "SELECT filter, parallelism, state, total, created_by, created_at, finished_at
+        FROM reindex_campaigns
+        WHERE id = ?",
+        id,
+    )
+    .fetch_optional(app.sql.as_ref())
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let jobs = sqlx::query!(
+        r#"SELECT id AS "id!", state FROM sync_jobs WHERE campaign_id = ?"#,
+        id
+    )
+    .fetch_all(app.sql.as_ref())
+    .await?;
+
+    let (mut running, mut done, mut failed, mut cancelled) = (0, 0, 0, 0);
+    let mut running_progress = 0.0;
+
+    for job in jobs {
+        match job.state.as_str() {
+            s if s == JobState::Running.as_str() => {
+                running += 1;
+                running_progress +=
+                    f64::from(app.sync_queue.job_progress(job.id).await.unwrap_or(0)) / 100.0;
+            }
+            s if s == JobState::Done.as_str() => done += 1,
+            s if s == JobState::Failed.as_str() => failed += 1,
+            s if s == JobState::Cancelled.as_str() => cancelled += 1,
+            _ => {}
+        }
+    }
+
+    let done_state = JobState::Done.as_str();
+    let avg_secs = sqlx::query_scalar!(
+        r#"SELECT AVG(finished_at - started_at) AS "avg: f64"
+        FROM sync_jobs
+        WHERE campaign_id = ? AND state = ? AND started_at IS NOT NULL"#,
+        id,
+        done_state,
+    )
+    .fetch_one(app.sql.as_ref())
+    .await?;
+
+    let finished = done + failed + cancelled;
+    let (percent, eta_secs) = estimate(
+        campaign.total,
+        finished,
+        running_progress,
+        avg_secs,
+        campaign.parallelism,
+    );
+    let is_running = campaign.state == CampaignState::Running.as_str();
+
+    Ok(Some(Progress {
+        id,
+        filter: serde_json::from_str(&campaign.filter)?,
+        state: campaign.state,
+        parallelism: campaign.parallelism,
+        total: campaign.total,
+        created_by: campaign.created_by,
+        created_at: campaign.created_at,
+        finished_at: campaign.finished_at,
+        pending: (campaign.total - finished - running).max(0),
+        running,
+        done,
+        failed,
+        cancelled,
+        percent,
+        eta_secs: eta_secs.filter(|_| is_running),
+    }))
+}
+
+/// The latest campaigns, newest first.
+pub(crate) async fn list(app: &Application) -> anyhow::Result<Vec<Progress>> {
+    let ids = sqlx::query_scalar!(
+        r#"SELECT id AS "id!" FROM reindex_campaigns ORDER BY id DESC LIMIT ?"#,
+        MAX_CAMPAIGNS,
+    )
+    .fetch_all(app.sql.as_ref())
+    .await?;
+
+    let mut campaigns = vec![];
+    for id in ids {
+        campaigns.extend(get(app, id).await?);
+    }
+
+    Ok(campaigns)
+}
+
+/// Mark the campaigns that were running when the server stopped as interrupted.
+///
+/// Their interrupted syncs are queued again by [`super::jobs::recover`], outside of the campaign.
+pub(crate) async fn recover(app: &Application) -> anyhow::Result<()> {
+    let running = CampaignState::Running.as_str();
+    let interrupted = CampaignState::Interrupted.as_str();
+
+    sqlx::query!(
+        "UPDATE reindex_campaigns SET state = ?, finished_at = strftime('%s', 'now')
+        WHERE state = ?",
+        interrupted,
+        running,
+    )
+    .execute(app.sql.as_ref())
+    .await?;
+
+    Ok(())
+}
+
+/// How much of a campaign is done, in percent, and an estimate of the seconds left.
+///
+/// `running` is the sum of the progress of the running syncs, each between 0 and 1. The estimate
+/// assumes the syncs left take as long as those that are done, `avg_secs` on average, and run
+/// `parallelism` at a time.
+fn estimate(
+    total: i64,
+    finished: i64,
+    running: f64,
+    avg_secs: Option<f64>,
+    parallelism: i64,
+) -> (f64, Option<u64>) {
+    if total == 0 {
+        return (100.0, Some(0));
+    }
+
+    let done = finished as f64 + running;
+    let percent = (done / total as f64 * 100.0).min(100.0);
+    let left = (total as f64 - done).max(0.0);
+    let eta_secs = avg_secs.map(|avg| (avg * left / parallelism.max(1) as f64).round() as u64);
+
+    (percent, eta_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::Backend;
+
+    #[test]
+    fn orgs_of_repositories() {
+        let org = |backend, name| org_of(&RepoRef::new(backend, name).unwrap());
+
+        assert_eq!(
+            org(Backend::Github, "bloopai/bloop"),
+            Some("bloopai".into())
+        );
+        assert_eq!(
+            org(Backend::Gitlab, "gitlab.com/group/subgroup/project"),
+            Some("group".into())
+        );
+        assert_eq!(org(Backend::Local, "/home/user/bloop"), None);
+    }
+
+    #[test]
+    fn estimates_progress() {
+        assert_eq!(estimate(0, 0, 0.0, None, 2), (100.0, Some(0)));
+
+        // Nothing finished yet to estimate from
+        assert_eq!(estimate(10, 0, 0.5, None, 2), (5.0, None));
+
+        // 8 syncs of a minute left, 2 at a time
+        assert_eq!(estimate(10, 2, 0.0, Some(60.0), 2), (20.0, Some(240)));
+        assert_eq!(estimate(4, 3, 1.0, Some(60.0), 2), (100.0, Some(0)));
+    }
+}
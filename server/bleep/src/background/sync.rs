@@ -5,10 +5,10 @@ use tracing::{debug, error, info, warn};
 use crate::{
     cache::FileCache,
     indexes,
-    remotes::RemoteError,
+    remotes::{self, RemoteError},
     repo::{
         iterator::FileFilterRule, Backend, FileFilterConfig, FilterUpdate, RepoError, RepoMetadata,
-        RepoRef, Repository, SyncStatus,
+        RepoRef, Repository, SyncStatus, DEFAULT_CLONE_DEPTH,
     },
     Application,
 };
@@ -24,6 +24,10 @@ pub struct SyncHandle {
     pub(crate) file_cache: FileCache,
     pub(crate) app: Application,
     pub(crate) shallow_config: gix::remote::fetch::Shallow,
+    /// Walk the whole repository, rather than what changed since the last index
+    pub(crate) force: bool,
+    /// The record of this sync, see [`super::jobs`]
+    pub(crate) job_id: Option<i64>,
     shallow: bool,
     exited: flume::Sender<SyncStatus>,
     exit_signal: flume::Receiver<SyncStatus>,
@@ -103,6 +107,10 @@ pub struct SyncConfig {
     reporef: RepoRef,
     filter_updates: Option<FilterUpdate>,
     shallow: bool,
+    clone_depth: Option<Option<NonZeroU32>>,
+    force: bool,
+    retry_of: Option<i64>,
+    campaign_id: Option<i64>,
 }
 
 impl SyncConfig {
@@ -112,6 +120,10 @@ impl SyncConfig {
             reporef,
             filter_updates: None,
             shallow: false,
+            clone_depth: None,
+            force: false,
+            retry_of: None,
+            campaign_id: None,
         }
     }
 
@@ -125,6 +137,33 @@ impl SyncConfig {
         self
     }
 
+    /// Change how much history is fetched, keeping the depth of the last sync if `None`.
+    ///
+    /// `Some(None)` fetches the full history again, and on later syncs.
+    pub fn clone_depth(mut self, depth: Option<Option<NonZeroU32>>) -> Self {
+        self.clone_depth = depth;
+        self
+    }
+
+    /// Rebuild the index of the whole repository, instead of only what changed since the last
+    /// index.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Record the sync as a run of an earlier job again.
+    pub fn retry_of(mut self, job_id: Option<i64>) -> Self {
+        self.retry_of = job_id;
+        self
+    }
+
+    /// Record the sync as part of a reindex campaign.
+    pub fn campaign(mut self, campaign_id: Option<i64>) -> Self {
+        self.campaign_id = campaign_id;
+        self
+    }
+
     pub async fn into_handle(self) -> Arc<SyncHandle> {
         SyncHandle::new(self).await
     }
@@ -137,16 +176,13 @@ impl SyncHandle {
             reporef,
             filter_updates,
             shallow,
-            ..
+            clone_depth,
+            force,
+            retry_of,
+            campaign_id,
         } = config;
         let status = app.sync_queue.broadcast();
 
-        let mut shallow_config = if shallow {
-            gix::remote::fetch::Shallow::DepthAtRemote(NonZeroU32::new(1).unwrap())
-        } else {
-            gix::remote::fetch::Shallow::DepthAtRemote(NonZeroU32::new(1000).unwrap())
-        };
-
         // Going through an extra hoop here to ensure the outward
         // facing interface communicates intent.
         //
@@ -165,7 +201,7 @@ impl SyncHandle {
 
         let (exited, exit_signal) = flume::bounded(1);
         let pipes = SyncPipes::new(reporef.clone(), filter_updates.clone(), status);
-        let current = app
+        let mut current = app
             .repo_pool
             .entry_async(reporef.clone())
             .await
@@ -184,10 +220,13 @@ impl SyncHandle {
                         disk_path,
                         remote,
                         shallow,
+                        clone_depth: None,
+                        sparse_paths: vec![],
                         sync_status: SyncStatus::Queued,
                         pub_sync_status: SyncStatus::Queued,
                         last_index_unix_secs: 0,
                         last_commit_unix_secs: 0,
+                        indexed_commit: None,
                         most_common_lang: None,
                         branch_filter: None,
                         file_filter: Default::default(),
@@ -196,17 +235,40 @@ impl SyncHandle {
                 }
             });
 
-        // if we're not upgrading from shallow to full checkout
-        // this seems to be a speed optimization for git operations
-        if !shallow && !current.get().shallow {
-            shallow_config = gix::remote::fetch::Shallow::NoChange;
+        // Clearing the depth of a depth-limited clone fetches the history that was left out.
+        let unshallow = matches!(clone_depth, Some(None)) && current.get().clone_depth.is_some();
+        if let Some(depth) = clone_depth {
+            current.get_mut().clone_depth = depth;
         }
 
+        let shallow_config = if shallow {
+            gix::remote::fetch::Shallow::DepthAtRemote(NonZeroU32::new(1).unwrap())
+        } else if let Some(depth) = current.get().clone_depth {
+            // Fetching at the configured depth on every sync keeps the history from growing
+            // with every pull.
+            gix::remote::fetch::Shallow::DepthAtRemote(depth)
+        } else if unshallow {
+            gix::remote::fetch::Shallow::undo()
+        } else if !current.get().shallow {
+            // if we're not upgrading from shallow to full checkout
+            // this seems to be a speed optimization for git operations
+            gix::remote::fetch::Shallow::NoChange
+        } else {
+            gix::remote::fetch::Shallow::DepthAtRemote(
+                NonZeroU32::new(DEFAULT_CLONE_DEPTH).unwrap(),
+            )
+        };
+
+        let job_id =
+            super::jobs::create(&app.sql, &reporef, shallow, force, retry_of, campaign_id).await;
+
         let sh = Self {
             app: app.clone(),
             reporef: reporef.clone(),
             file_cache: FileCache::new(app.sql.clone(), app.semantic.clone()),
             shallow_config,
+            force,
+            job_id,
             shallow,
             pipes,
             filter_updates,
@@ -324,6 +386,10 @@ impl SyncHandle {
             if let Some(ref ff) = self.filter_updates.file_filter {
                 orig.file_filter = ff.patch_into(&orig.file_filter);
             }
+
+            if let Some(ref paths) = self.filter_updates.sparse_paths {
+                orig.sparse_paths = paths.clone();
+            }
             orig
         };
 
@@ -501,6 +567,10 @@ impl SyncHandle {
             repo.remove_all()
                 .await
                 .map_err(|e| SyncError::RemoveLocal(repo.disk_path.clone(), e))?;
+
+            if let Err(err) = remotes::objects::forget(&self.app.config, &self.reporef).await {
+                warn!(?err, "failed to drop refs from the shared object store");
+            }
         }
 
         for handle in writers {
@@ -15,8 +15,22 @@ async fn main() -> Result<()> {
     _ = color_eyre::install();
 
     Application::install_logging(&config);
+
+    if config.migrations_dry_run {
+        return Application::print_migration_plan(&config).await;
+    }
+
     let app = Application::initialize(Environment::server(), config, None, None).await?;
 
     app.initialize_sentry();
+
+    if app.config.tui {
+        #[cfg(feature = "tui")]
+        return bleep::tui::run(app).await;
+
+        #[cfg(not(feature = "tui"))]
+        anyhow::bail!("`--tui` needs bleep to be built with the `tui` feature");
+    }
+
     app.run().await
 }
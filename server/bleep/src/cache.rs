@@ -1,11 +1,14 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     ops::Deref,
-    sync::{Arc, RwLock},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
     time::Instant,
 };
 
-use qdrant_client::qdrant::{PointId, PointStruct};
+use qdrant_client::qdrant::{point_id::PointIdOptions, PointId, PointStruct};
 use rayon::prelude::ParallelIterator;
 use scc::hash_map::Entry;
 use sqlx::Sqlite;
@@ -15,6 +18,7 @@ use uuid::Uuid;
 use crate::{
     repo::RepoRef,
     semantic::{
+        coverage::{self, repo_of},
         embedder::{EmbedChunk, EmbedQueue},
         Payload, Semantic,
     },
@@ -30,15 +34,6 @@ pub struct FreshValue<T> {
     pub(crate) value: T,
 }
 
-impl<T: Default> FreshValue<T> {
-    fn fresh_default() -> Self {
-        Self {
-            fresh: true,
-            value: Default::default(),
-        }
-    }
-}
-
 impl<T> PartialEq for FreshValue<T>
 where
     T: PartialEq,
@@ -67,7 +62,8 @@ impl<T> From<T> for FreshValue<T> {
 /// Since it's atomically (as in ACID) read from SQLite, this will be
 /// representative at a single point in time
 pub struct FileCacheSnapshot<'a> {
-    snapshot: Arc<scc::HashMap<CacheKeys, FreshValue<()>>>,
+    /// Cached entries, with the path they were cached for, unless they predate storing paths
+    snapshot: Arc<scc::HashMap<CacheKeys, FreshValue<Option<String>>>>,
     parent: &'a FileCache,
     reporef: &'a RepoRef,
 }
@@ -111,26 +107,47 @@ impl<'a> FileCacheSnapshot<'a> {
     }
 
     #[tracing::instrument(skip(self))]
-    pub(crate) fn is_fresh(&self, keys: &CacheKeys) -> bool {
+    pub(crate) fn is_fresh(&self, keys: &CacheKeys, path: &str) -> bool {
         match self.snapshot.entry(keys.clone()) {
             Entry::Occupied(mut val) => {
-                val.get_mut().fresh = true;
+                let val = val.get_mut();
+                val.fresh = true;
+                val.value.get_or_insert_with(|| path.to_owned());
 
                 trace!("cache hit");
                 true
             }
             Entry::Vacant(val) => {
-                _ = val.insert_entry(FreshValue::fresh_default());
+                _ = val.insert_entry(Some(path.to_owned()).into());
 
                 trace!("cache miss");
                 false
             }
         }
     }
+
+    /// Whether every entry knows its path, so that entries can be kept by path.
+    ///
+    /// This is never the case for an empty cache, which has nothing to keep.
+    pub(crate) fn is_addressable(&self) -> bool {
+        let mut addressable = !self.snapshot.is_empty();
+        self.snapshot.scan(|_, v| addressable &= v.value.is_some());
+
+        addressable
+    }
+
+    /// Keep the entries of `paths` without walking them, as their contents are unchanged.
+    pub(crate) fn keep_paths(&self, paths: &HashSet<String>) {
+        self.snapshot.for_each(|_, v| {
+            if v.value.as_ref().map_or(false, |path| paths.contains(path)) {
+                v.fresh = true;
+            }
+        });
+    }
 }
 
 impl<'a> Deref for FileCacheSnapshot<'a> {
-    type Target = scc::HashMap<CacheKeys, FreshValue<()>>;
+    type Target = scc::HashMap<CacheKeys, FreshValue<Option<String>>>;
 
     fn deref(&self) -> &Self::Target {
         &self.snapshot
@@ -148,6 +165,7 @@ pub struct FileCache {
     db: SqlDb,
     semantic: Semantic,
     embed_queue: EmbedQueue,
+    coverage: coverage::Tracker,
 }
 
 #[derive(Default)]
@@ -169,6 +187,7 @@ impl<'a> FileCache {
             db,
             semantic,
             embed_queue: Default::default(),
+            coverage: Default::default(),
         }
     }
 
@@ -191,7 +210,7 @@ impl<'a> FileCache {
     pub(crate) async fn retrieve(&'a self, reporef: &'a RepoRef) -> FileCacheSnapshot<'a> {
         let repo_str = reporef.to_string();
         let rows = sqlx::query! {
-            "SELECT cache_hash FROM file_cache \
+            "SELECT cache_hash, path FROM file_cache \
              WHERE repo_ref = ?",
             repo_str,
         }
@@ -203,7 +222,7 @@ impl<'a> FileCache {
             let (semantic_hash, tantivy_hash) = row.cache_hash.split_at(64);
             _ = output.insert(
                 CacheKeys::new(semantic_hash, tantivy_hash),
-                FreshValue::stale(()),
+                FreshValue::stale(row.path),
             );
         }
 
@@ -267,12 +286,14 @@ impl<'a> FileCache {
             while let Some(entry) = next {
                 let key = entry.key();
                 let hash = format!("{}{}", key.0, key.1);
+                let path = entry.get().value.as_deref();
                 sqlx::query!(
                     "INSERT INTO file_cache \
-                    (repo_ref, cache_hash) \
-                    VALUES (?, ?)",
+                    (repo_ref, cache_hash, path) \
+                    VALUES (?, ?, ?)",
                     repo_str,
                     hash,
+                    path,
                 )
                 .execute(&mut tx)
                 .await?;
@@ -304,11 +325,62 @@ impl<'a> FileCache {
         let mut tx = self.db.begin().await?;
         self.delete_files(reporef, &mut tx).await?;
         self.delete_chunks(reporef, &mut tx).await?;
+
+        let repo_str = reporef.to_string();
+        sqlx::query! {
+            "DELETE FROM embedding_coverage WHERE repo_ref = ?",
+            repo_str
+        }
+        .execute(&mut *tx)
+        .await?;
+
         tx.commit().await?;
 
         Ok(())
     }
 
+    /// Start counting the chunks embedded for the repository in scope.
+    pub(crate) fn start_coverage(&self, reporef: &RepoRef) {
+        self.coverage.start(&reporef.to_string());
+    }
+
+    /// Save the chunks embedded for the repository in scope since `start_coverage`.
+    ///
+    /// Call this once the index has been synchronized, so that every chunk was sent to the
+    /// embedder.
+    pub(crate) async fn save_coverage(&self, reporef: &RepoRef) -> anyhow::Result<()> {
+        let repo_str = reporef.to_string();
+        let Some(run) = self.coverage.finish(&repo_str) else {
+            return Ok(());
+        };
+
+        let embedded = run.embedded as i64;
+        let failed = run.skipped.failed() as i64;
+        let run = serde_json::to_string(&run)?;
+
+        sqlx::query! {
+            "INSERT INTO embedding_coverage (repo_ref, last_run, last_embedded_at, last_failed_at) \
+             VALUES ( \
+                ?, ?, \
+                CASE WHEN ? > 0 THEN strftime('%s', 'now') END, \
+                CASE WHEN ? > 0 THEN strftime('%s', 'now') END \
+             ) \
+             ON CONFLICT (repo_ref) DO UPDATE SET \
+                last_run = excluded.last_run, \
+                finished_at = excluded.finished_at, \
+                last_embedded_at = COALESCE(excluded.last_embedded_at, last_embedded_at), \
+                last_failed_at = COALESCE(excluded.last_failed_at, last_failed_at)",
+            repo_str,
+            run,
+            embedded,
+            failed,
+        }
+        .execute(self.db.as_ref())
+        .await?;
+
+        Ok(())
+    }
+
     /// Process the next chunk from the embedding queue if the batch size is met.
     pub fn process_embedding_queue(&self) -> anyhow::Result<()> {
         tokio::task::block_in_place(|| {
@@ -323,27 +395,57 @@ impl<'a> FileCache {
     /// the embedder, and commit the results, disregarding the internal
     /// batch sizing.
     async fn batched_embed_or_flush_queue(&self, flush: bool) -> anyhow::Result<()> {
-        let new_points = self.embed_queued_points(flush).await?;
+        // Coverage only counts the collection that is searched
+        let active = self.semantic.write_collections().into_iter().next();
+        let embedded = self.embed_queued_points(flush).await?;
+
+        let _writes = self.semantic.lock_writes().await;
+        for (collection, new_points) in embedded {
+            if new_points.is_empty() {
+                continue;
+            }
 
-        if !new_points.is_empty() {
-            if let Err(err) = self
+            self.semantic
+                .touch_points(new_points.iter().filter_map(point_uuid));
+
+            let repos = if active.as_ref() == Some(&collection) {
+                new_points
+                    .iter()
+                    .filter_map(|p| repo_of(&p.payload))
+                    .map(str::to_owned)
+                    .collect()
+            } else {
+                vec![]
+            };
+
+            let result = self
                 .semantic
                 .qdrant_client()
-                .upsert_points(self.semantic.collection_name(), new_points, None)
-                .await
-            {
-                error!(?err, "failed to write new points into qdrant");
+                .upsert_points(&collection, new_points, None)
+                .await;
+
+            if let Err(err) = &result {
+                error!(?err, %collection, "failed to write new points into qdrant");
             }
+
+            self.coverage.upserted(&repos, result.is_ok());
         }
         Ok(())
     }
 
     /// Empty the queue in batches, and generate embeddings using the
     /// configured embedder
-    async fn embed_queued_points(&self, flush: bool) -> Result<Vec<PointStruct>, anyhow::Error> {
+    ///
+    /// While the embedding model is being migrated, every batch is
+    /// embedded once per collection, keyed by the collection name.
+    async fn embed_queued_points(
+        &self,
+        flush: bool,
+    ) -> Result<HashMap<String, Vec<PointStruct>>, anyhow::Error> {
         let batch_size = self.semantic.config.embedding_batch_size.get();
+        let generations = self.semantic.write_generations();
         let log = &self.embed_queue;
-        let mut output = vec![];
+        let mut output: HashMap<String, Vec<PointStruct>> = HashMap::new();
 
         loop {
             // if we're not currently flushing the log, only process full batches
@@ -362,37 +464,53 @@ impl<'a> FileCache {
                 }
             }
 
-            let (elapsed, res) = {
-                let time = Instant::now();
-                let res = self
-                    .semantic
-                    .embedder()
-                    .batch_embed(batch.iter().map(|c| c.data.as_ref()).collect::<Vec<_>>())
-                    .await;
-
-                (time.elapsed(), res)
-            };
-
-            match res {
-                Ok(res) => {
-                    trace!(?elapsed, size = batch.len(), "batch embedding successful");
-                    output.extend(
-                        res.into_iter()
-                            .zip(batch)
-                            .map(|(embedding, src)| PointStruct {
-                                id: Some(PointId::from(src.id)),
-                                vectors: Some(embedding.into()),
-                                payload: src.payload,
-                            }),
-                    )
+            for (i, generation) in generations.iter().enumerate() {
+                let (elapsed, res) = {
+                    let time = Instant::now();
+                    let res = generation
+                        .embedder
+                        .batch_embed(batch.iter().map(|c| c.data.as_ref()).collect::<Vec<_>>())
+                        .await;
+
+                    (time.elapsed(), res)
+                };
+
+                // Only the active generation, which comes first, counts towards coverage
+                if i == 0 {
+                    for chunk in &batch {
+                        let counters = self
+                            .coverage
+                            .counters(repo_of(&chunk.payload).unwrap_or(""));
+                        if res.is_ok() {
+                            counters.chunk(chunk.data.len());
+                        } else {
+                            counters.embed_failed();
+                        }
+                    }
                 }
-                Err(err) => {
-                    error!(
-                        ?err,
-                        ?elapsed,
-                        size = batch.len(),
-                        "remote batch embeddings failed"
-                    )
+
+                match res {
+                    Ok(res) => {
+                        trace!(?elapsed, size = batch.len(), "batch embedding successful");
+                        output
+                            .entry(generation.generation.collection.clone())
+                            .or_default()
+                            .extend(res.into_iter().zip(&batch).map(|(embedding, src)| {
+                                PointStruct {
+                                    id: Some(PointId::from(src.id.clone())),
+                                    vectors: Some(embedding.into()),
+                                    payload: src.payload.clone(),
+                                }
+                            }))
+                    }
+                    Err(err) => {
+                        error!(
+                            ?err,
+                            ?elapsed,
+                            size = batch.len(),
+                            "remote batch embeddings failed"
+                        )
+                    }
                 }
             }
         }
@@ -411,6 +529,7 @@ impl<'a> FileCache {
         branches: &[String],
     ) -> InsertStats {
         let chunk_cache = self.chunks_for_file(repo_ref, cache_keys).await;
+        let chunks = AtomicUsize::new(0);
         self.semantic
             .chunks_for_buffer(
                 cache_keys.semantic().into(),
@@ -422,12 +541,17 @@ impl<'a> FileCache {
                 branches,
             )
             .for_each(|(data, payload)| {
+                chunks.fetch_add(1, Ordering::Relaxed);
                 let cached = chunk_cache.update_or_embed(&data, payload);
                 if let Err(err) = cached {
                     warn!(?err, %repo_name, %relative_path, "embedding failed");
                 }
             });
 
+        if chunks.into_inner() == 0 && !buffer.trim().is_empty() {
+            self.coverage.counters(&repo_ref.to_string()).too_short();
+        }
+
         match chunk_cache.commit().await {
             Ok(stats) => {
                 info!(
@@ -664,18 +788,22 @@ impl<'a> ChunkCache<'a> {
         }
 
         if !to_delete.is_empty() {
+            let _writes = self.semantic.lock_writes().await;
             self.semantic
-                .qdrant_client()
-                .delete_points(
-                    self.semantic.collection_name(),
-                    &to_delete
-                        .into_iter()
-                        .map(PointId::from)
-                        .collect::<Vec<_>>()
-                        .into(),
-                    None,
-                )
-                .await?;
+                .touch_points(to_delete.iter().map(String::as_str));
+
+            let selector = to_delete
+                .into_iter()
+                .map(PointId::from)
+                .collect::<Vec<_>>()
+                .into();
+
+            for collection in self.semantic.write_collections() {
+                self.semantic
+                    .qdrant_client()
+                    .delete_points(&collection, &selector, None)
+                    .await?;
+            }
         }
         Ok(delete_size)
     }
@@ -688,6 +816,7 @@ impl<'a> ChunkCache<'a> {
     ) -> Result<usize, anyhow::Error> {
         let mut update_size = 0;
         let mut qdrant_updates = tokio::task::JoinSet::new();
+        let _writes = self.semantic.lock_writes().await;
 
         let mut next = self.update.first_occupied_entry();
         while let Some(entry) = next {
@@ -706,6 +835,9 @@ impl<'a> ChunkCache<'a> {
                 .await?;
             }
 
+            self.semantic
+                .touch_points(points.iter().map(String::as_str));
+
             let id = points
                 .iter()
                 .cloned()
@@ -717,13 +849,16 @@ impl<'a> ChunkCache<'a> {
                 [("branches".to_string(), branches_list.to_owned().into())].into(),
             );
 
-            let semantic = self.semantic.clone();
-            qdrant_updates.spawn(async move {
-                semantic
-                    .qdrant_client()
-                    .set_payload(semantic.collection_name(), &id, payload, None)
-                    .await
-            });
+            for collection in self.semantic.write_collections() {
+                let semantic = self.semantic.clone();
+                let (id, payload) = (id.clone(), payload.clone());
+                qdrant_updates.spawn(async move {
+                    semantic
+                        .qdrant_client()
+                        .set_payload(&collection, &id, payload, None)
+                        .await
+                });
+            }
             next = entry.next();
         }
 
@@ -750,3 +885,11 @@ impl<'a> ChunkCache<'a> {
         id
     }
 }
+
+/// The ID of a point, which chunks derive from their content.
+fn point_uuid(point: &PointStruct) -> Option<&str> {
+    match point.id.as_ref()?.point_id_options.as_ref()? {
+        PointIdOptions::Uuid(id) => Some(id),
+        PointIdOptions::Num(_) => None,
+    }
+}
@@ -35,6 +35,32 @@ pub struct Configuration {
     /// Quit after indexing the specified repos
     pub index_only: bool,
 
+    #[clap(long, default_value_t = false)]
+    #[serde(skip)]
+    /// Show a dashboard of indexing, asks and errors in the terminal, instead of logging to it.
+    ///
+    /// Needs the `tui` feature.
+    pub tui: bool,
+
+    #[clap(long, default_value_t = false)]
+    #[serde(skip)]
+    /// Print the database migrations that starting would apply, and quit without applying them
+    pub migrations_dry_run: bool,
+
+    #[clap(long, default_value_t = false)]
+    #[serde(default)]
+    /// Refuse to start while the database has pending migrations, unless started with
+    /// `--migrate`.
+    ///
+    /// Migrations can't be undone, so going back to an older version loses the database. The
+    /// database is saved to <index_dir>/bleep.db.<migration>.bk before migrating either way.
+    pub require_migrate: bool,
+
+    #[clap(long, default_value_t = false)]
+    #[serde(skip)]
+    /// Apply pending database migrations, with `--require-migrate`
+    pub migrate: bool,
+
     #[clap(long, default_value_t = false)]
     #[serde(default)]
     /// Disable periodic reindexing, and `git pull` on remote repositories.
@@ -45,6 +71,13 @@ pub struct Configuration {
     /// Disable system-native notification backends to detect new git commits immediately.
     pub disable_fsevents: bool,
 
+    #[clap(long, default_value_t = false)]
+    #[serde(default)]
+    /// Fetch new clones of remote repositories into an object store shared between them, so that
+    /// forks keep their identical git objects once. Reclaiming the objects of removed
+    /// repositories needs `git` on the `PATH`.
+    pub shared_objects: bool,
+
     #[clap(long, default_value_t = false)]
     #[serde(default)]
     /// Avoid writing logs to files.
@@ -67,6 +100,36 @@ pub struct Configuration {
     /// Maximum number of parallel background threads
     pub max_threads: usize,
 
+    #[clap(long, default_value_t = default_interactive_threads())]
+    #[serde(default = "default_interactive_threads")]
+    /// Number of `max_threads` that indexing leaves to interactive requests like answers
+    pub interactive_threads: usize,
+
+    #[clap(long, default_value_t = default_min_free_disk_mb())]
+    #[serde(default = "default_min_free_disk_mb")]
+    /// Warn when less than this many MB of disk space are left for the index directory
+    pub min_free_disk_mb: u64,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// Warn when clones, indexes and databases take up more than this many MB in total
+    pub max_storage_mb: Option<u64>,
+
+    #[clap(long, default_value_t = default_conversation_trash_days())]
+    #[serde(default = "default_conversation_trash_days")]
+    /// Purge deleted conversations after this many days, until when they can be restored
+    pub conversation_trash_days: u64,
+
+    #[clap(long, default_value_t = default_channel_retention_days())]
+    #[serde(default = "default_channel_retention_days")]
+    /// Delete the messages of repository channels after this many days
+    pub channel_retention_days: u64,
+
+    #[clap(long, default_value_t = default_workspace_trash_days())]
+    #[serde(default = "default_workspace_trash_days")]
+    /// Purge deleted workspaces after this many days, until when an admin can restore them
+    pub workspace_trash_days: u64,
+
     #[clap(long, default_value_t = default_host())]
     #[serde(default = "default_host")]
     /// Bind the webserver to `<port>`
@@ -77,6 +140,24 @@ pub struct Configuration {
     /// Bind the webserver to `<host>`
     pub port: u16,
 
+    #[clap(long)]
+    /// Listen on this unix socket instead of `<host>` and `<port>`.
+    ///
+    /// A socket passed by systemd socket activation takes precedence over both.
+    pub unix_socket: Option<PathBuf>,
+
+    #[clap(long)]
+    /// Permissions of `<unix_socket>` in octal, like `660`
+    pub unix_socket_mode: Option<String>,
+
+    #[clap(long)]
+    /// Serve HTTPS with this PEM certificate chain. It is reloaded when the file changes.
+    pub tls_cert: Option<PathBuf>,
+
+    #[clap(long)]
+    /// PEM private key of `<tls_cert>`
+    pub tls_key: Option<PathBuf>,
+
     //
     // External dependencies
     //
@@ -85,6 +166,30 @@ pub struct Configuration {
     /// URL for the answer-api
     pub answer_api_url: String,
 
+    #[clap(long)]
+    /// Base URL of an OpenAI-compatible chat API, like llama.cpp, vLLM or Ollama, to answer with
+    /// instead of the answer-api
+    pub llm_url: Option<reqwest::Url>,
+
+    #[clap(long)]
+    /// Model to ask `llm_url` for. Servers that only run one model may not need it
+    pub llm_model: Option<String>,
+
+    #[clap(long, default_value_t = default_llm_context_window())]
+    #[serde(default = "default_llm_context_window")]
+    /// Context window of the model at `llm_url`, in tokens
+    pub llm_context_window: usize,
+
+    #[clap(long)]
+    #[serde(serialize_with = "serialize_secret_opt_str", default)]
+    /// API key of `llm_url`
+    pub llm_api_key: Option<SecretString>,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// The model at `llm_url` can't call functions, so they are described in the prompt instead
+    pub llm_prompted_tools: bool,
+
     #[clap(long)]
     /// Key for analytics backend
     pub analytics_key: Option<String>,
@@ -109,6 +214,10 @@ pub struct Configuration {
     /// Path to dynamic libraries used in the app.
     pub dylib_dir: Option<PathBuf>,
 
+    #[clap(long)]
+    /// Directory of additional tree-sitter grammars, one subdirectory per language
+    pub grammar_dir: Option<PathBuf>,
+
     //
     // Semantic values
     //
@@ -137,6 +246,112 @@ pub struct Configuration {
     /// Batch size for batched embeddings
     pub embedding_batch_size: NonZeroUsize,
 
+    #[clap(long, value_enum, default_value_t = VectorQuantization::default())]
+    #[serde(default)]
+    /// Quantization profile for the Qdrant collection.
+    ///
+    /// Quantized vectors are kept in memory, while the original vectors stay on disk and are
+    /// used to rescore the results. Changing this will migrate an existing collection on startup.
+    pub vector_quantization: VectorQuantization,
+
+    #[clap(long, value_enum, default_value_t = EmbedderKind::default())]
+    #[serde(default)]
+    /// Where embeddings come from.
+    ///
+    /// Vectors of different models can't be compared, so an index is only ever searched with the
+    /// model that built it. Switching to another one needs a new collection.
+    pub embedder: EmbedderKind,
+
+    #[clap(long)]
+    /// Address of the embedding API, for the `openai` and `ollama` embedders
+    pub embedder_url: Option<reqwest::Url>,
+
+    #[clap(long)]
+    /// Model the embedding API runs, like `text-embedding-ada-002` or `nomic-embed-text`
+    pub embedder_model: Option<String>,
+
+    #[clap(long)]
+    #[serde(serialize_with = "serialize_secret_opt_str", default)]
+    /// API key of an OpenAI-compatible embedding API
+    pub embedder_api_key: Option<SecretString>,
+
+    //
+    // Outbound requests
+    //
+    #[clap(long = "fetch-allow")]
+    #[serde(default)]
+    /// Only fetch user-provided URLs on these hosts, including their subdomains.
+    ///
+    /// Hosts that resolve to private addresses can only be fetched if they are listed here.
+    pub fetch_allowlist: Vec<String>,
+
+    #[clap(long = "fetch-deny")]
+    #[serde(default)]
+    /// Never fetch user-provided URLs on these hosts, including their subdomains.
+    pub fetch_denylist: Vec<String>,
+
+    //
+    // Tenancy
+    //
+    #[clap(long)]
+    #[serde(default)]
+    /// Serve several isolated tenants from this instance.
+    ///
+    /// Every user has to belong to a tenant, and only sees the repositories of their tenant.
+    pub tenant_isolation: bool,
+
+    #[clap(long = "instance-admin")]
+    #[serde(default)]
+    /// Logins of the users who can use the `/admin` endpoints, including managing tenants.
+    ///
+    /// This applies with or without `--tenant-isolation`. Without any, nobody can use them.
+    pub instance_admins: Vec<String>,
+
+    //
+    // Limits
+    //
+    #[clap(long)]
+    #[serde(default)]
+    /// Requests a minute each user, and each IP address, can make before they are answered with
+    /// `429 Too Many Requests`. Short bursts of up to this many requests are allowed.
+    pub rate_limit_per_minute: Option<u32>,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// Rate limit by the last address of `X-Forwarded-For`, which the proxy added, when serving
+    /// behind a proxy
+    pub trust_forwarded_for: bool,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// Requests that call the LLM, like answers, each user can make per day
+    pub daily_llm_calls_per_user: Option<u32>,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// Syncs of single repositories each user can start per day
+    pub daily_indexing_jobs_per_user: Option<u32>,
+
+    #[clap(long)]
+    #[serde(default)]
+    /// Serve operational metrics at `/metrics`, in the Prometheus text format. The endpoint
+    /// isn't authenticated, so only enable it where scrapers are the only ones that can reach it
+    pub metrics: bool,
+
+    //
+    // Quality
+    //
+    #[clap(long)]
+    #[serde(default)]
+    /// Copy this percentage of answered exchanges into a queue for human review
+    pub review_sample_percent: Option<f64>,
+
+    #[clap(long = "reviewer")]
+    #[serde(default)]
+    /// Logins of the users who can use the review queue, and read the sampled exchanges. If none
+    /// are set, anyone can, or only instance admins with `--tenant-isolation`
+    pub reviewers: Vec<String>,
+
     //
     // Cognito setup
     //
@@ -176,6 +391,63 @@ pub struct Configuration {
     /// Bot secret token
     pub bot_secret: Option<SecretString>,
 
+    #[clap(long)]
+    #[serde(serialize_with = "serialize_secret_opt_str", default)]
+    /// Secret of GitHub push webhooks, which resync the repositories that were pushed to
+    pub github_webhook_secret: Option<SecretString>,
+
+    #[clap(long)]
+    #[serde(serialize_with = "serialize_secret_opt_str", default)]
+    /// Bearer token of sync hooks, which CI or other remotes call to resync a repository
+    pub sync_hook_secret: Option<SecretString>,
+
+    //
+    // GitLab, on gitlab.com or self-hosted
+    //
+    /// Base URL of the GitLab instance to index from, `https://gitlab.com` by default
+    #[clap(long)]
+    pub gitlab_url: Option<reqwest::Url>,
+
+    /// Application ID of the GitLab OAuth application, for signing in with GitLab
+    #[clap(long)]
+    pub gitlab_client_id: Option<String>,
+
+    #[clap(long)]
+    #[serde(serialize_with = "serialize_secret_opt_str", default)]
+    /// Secret of the GitLab OAuth application
+    pub gitlab_client_secret: Option<SecretString>,
+
+    #[clap(long)]
+    #[serde(serialize_with = "serialize_secret_opt_str", default)]
+    /// Personal, group or project access token, instead of signing in with GitLab
+    pub gitlab_token: Option<SecretString>,
+
+    #[clap(long)]
+    #[serde(serialize_with = "serialize_secret_opt_str", default)]
+    /// Secret token of GitLab push webhooks, which resync the repositories that were pushed to
+    pub gitlab_webhook_secret: Option<SecretString>,
+
+    //
+    // Bitbucket, on bitbucket.org or Bitbucket Server
+    //
+    /// Base URL of the Bitbucket Server to index from, `https://bitbucket.org` by default
+    #[clap(long)]
+    pub bitbucket_url: Option<reqwest::Url>,
+
+    /// User of the Bitbucket app password
+    #[clap(long)]
+    pub bitbucket_username: Option<String>,
+
+    #[clap(long)]
+    #[serde(serialize_with = "serialize_secret_opt_str", default)]
+    /// Bitbucket app password, or the password of the user on Bitbucket Server
+    pub bitbucket_app_password: Option<SecretString>,
+
+    #[clap(long)]
+    #[serde(serialize_with = "serialize_secret_opt_str", default)]
+    /// HTTP access token of Bitbucket Server, instead of a username and password
+    pub bitbucket_token: Option<SecretString>,
+
     //
     // Cloud deployment values
     //
@@ -187,6 +459,11 @@ pub struct Configuration {
     #[clap(long)]
     pub frontend_dist: Option<PathBuf>,
 
+    /// URL prefix that the API and the front-end are served under, like `/bloop`, for hosting
+    /// behind a reverse proxy. Include it in `instance_domain` too.
+    #[clap(long)]
+    pub base_path: Option<String>,
+
     #[clap(long)]
     /// Address for the embedding server
     pub embedding_server_url: Option<reqwest::Url>,
@@ -259,6 +536,16 @@ impl Configuration {
     /// In case a default value is recognized in *either* sides,
     /// always the non-default value will be used for the resulting
     /// configuration.
+    /// The OpenAI-compatible API to answer with, if there is one instead of the answer API.
+    pub(crate) fn llm_endpoint(&self) -> Option<crate::llm_gateway::Endpoint> {
+        Some(crate::llm_gateway::Endpoint {
+            url: self.llm_url.clone()?,
+            model: self.llm_model.clone(),
+            api_key: self.llm_api_key.clone(),
+            prompted_tools: self.llm_prompted_tools,
+        })
+    }
+
     pub fn merge(a: Self, b: Self) -> Self {
         // the values here are in the order they're listed in the
         // original `Configuration` declaration
@@ -271,10 +558,20 @@ impl Configuration {
 
             index_only: b.index_only | a.index_only,
 
+            tui: b.tui | a.tui,
+
+            migrations_dry_run: b.migrations_dry_run | a.migrations_dry_run,
+
+            require_migrate: b.require_migrate | a.require_migrate,
+
+            migrate: b.migrate | a.migrate,
+
             disable_background: b.disable_background | a.disable_background,
 
             disable_fsevents: b.disable_fsevents | a.disable_fsevents,
 
+            shared_objects: b.shared_objects | a.shared_objects,
+
             disable_log_write: b.disable_log_write | a.disable_log_write,
 
             buffer_size: right_if_default!(b.buffer_size, a.buffer_size, default_buffer_size()),
@@ -287,10 +584,50 @@ impl Configuration {
 
             max_threads: right_if_default!(b.max_threads, a.max_threads, default_parallelism()),
 
+            interactive_threads: right_if_default!(
+                b.interactive_threads,
+                a.interactive_threads,
+                default_interactive_threads()
+            ),
+
+            min_free_disk_mb: right_if_default!(
+                b.min_free_disk_mb,
+                a.min_free_disk_mb,
+                default_min_free_disk_mb()
+            ),
+
+            max_storage_mb: b.max_storage_mb.or(a.max_storage_mb),
+
+            conversation_trash_days: right_if_default!(
+                b.conversation_trash_days,
+                a.conversation_trash_days,
+                default_conversation_trash_days()
+            ),
+
+            channel_retention_days: right_if_default!(
+                b.channel_retention_days,
+                a.channel_retention_days,
+                default_channel_retention_days()
+            ),
+
+            workspace_trash_days: right_if_default!(
+                b.workspace_trash_days,
+                a.workspace_trash_days,
+                default_workspace_trash_days()
+            ),
+
             host: right_if_default!(b.host, a.host, default_host()),
 
             port: right_if_default!(b.port, a.port, default_port()),
 
+            unix_socket: b.unix_socket.or(a.unix_socket),
+
+            unix_socket_mode: b.unix_socket_mode.or(a.unix_socket_mode),
+
+            tls_cert: b.tls_cert.or(a.tls_cert),
+
+            tls_key: b.tls_key.or(a.tls_key),
+
             model_dir: right_if_default!(b.model_dir, a.model_dir, default_model_dir()),
 
             max_chunk_tokens: right_if_default!(
@@ -311,10 +648,50 @@ impl Configuration {
                 interactive_batch_size()
             ),
 
+            vector_quantization: right_if_default!(
+                b.vector_quantization,
+                a.vector_quantization,
+                VectorQuantization::default()
+            ),
+
+            embedder: right_if_default!(b.embedder, a.embedder, EmbedderKind::default()),
+
+            embedder_url: b.embedder_url.or(a.embedder_url),
+
+            embedder_model: b.embedder_model.or(a.embedder_model),
+
+            embedder_api_key: b.embedder_api_key.or(a.embedder_api_key),
+
+            fetch_allowlist: right_if_default!(b.fetch_allowlist, a.fetch_allowlist, vec![]),
+
+            fetch_denylist: right_if_default!(b.fetch_denylist, a.fetch_denylist, vec![]),
+
+            tenant_isolation: b.tenant_isolation | a.tenant_isolation,
+
+            instance_admins: right_if_default!(b.instance_admins, a.instance_admins, vec![]),
+
+            rate_limit_per_minute: b.rate_limit_per_minute.or(a.rate_limit_per_minute),
+
+            trust_forwarded_for: b.trust_forwarded_for | a.trust_forwarded_for,
+
+            daily_llm_calls_per_user: b.daily_llm_calls_per_user.or(a.daily_llm_calls_per_user),
+
+            daily_indexing_jobs_per_user: b
+                .daily_indexing_jobs_per_user
+                .or(a.daily_indexing_jobs_per_user),
+
+            metrics: b.metrics | a.metrics,
+
+            review_sample_percent: b.review_sample_percent.or(a.review_sample_percent),
+
+            reviewers: right_if_default!(b.reviewers, a.reviewers, vec![]),
+
             embedding_server_url: b.embedding_server_url.or(a.embedding_server_url),
 
             frontend_dist: b.frontend_dist.or(a.frontend_dist),
 
+            base_path: b.base_path.or(a.base_path),
+
             qdrant_url: right_if_default!(b.qdrant_url, a.qdrant_url, String::new()),
 
             answer_api_url: right_if_default!(
@@ -323,6 +700,20 @@ impl Configuration {
                 default_answer_api_url()
             ),
 
+            llm_url: b.llm_url.or(a.llm_url),
+
+            llm_model: b.llm_model.or(a.llm_model),
+
+            llm_context_window: right_if_default!(
+                b.llm_context_window,
+                a.llm_context_window,
+                default_llm_context_window()
+            ),
+
+            llm_api_key: b.llm_api_key.or(a.llm_api_key),
+
+            llm_prompted_tools: b.llm_prompted_tools | a.llm_prompted_tools,
+
             cognito_userpool_id: b.cognito_userpool_id.or(a.cognito_userpool_id),
 
             cognito_client_id: b.cognito_client_id.or(a.cognito_client_id),
@@ -341,6 +732,28 @@ impl Configuration {
 
             bot_secret: b.bot_secret.or(a.bot_secret),
 
+            github_webhook_secret: b.github_webhook_secret.or(a.github_webhook_secret),
+
+            sync_hook_secret: b.sync_hook_secret.or(a.sync_hook_secret),
+
+            gitlab_url: b.gitlab_url.or(a.gitlab_url),
+
+            gitlab_client_id: b.gitlab_client_id.or(a.gitlab_client_id),
+
+            gitlab_client_secret: b.gitlab_client_secret.or(a.gitlab_client_secret),
+
+            gitlab_token: b.gitlab_token.or(a.gitlab_token),
+
+            gitlab_webhook_secret: b.gitlab_webhook_secret.or(a.gitlab_webhook_secret),
+
+            bitbucket_url: b.bitbucket_url.or(a.bitbucket_url),
+
+            bitbucket_username: b.bitbucket_username.or(a.bitbucket_username),
+
+            bitbucket_app_password: b.bitbucket_app_password.or(a.bitbucket_app_password),
+
+            bitbucket_token: b.bitbucket_token.or(a.bitbucket_token),
+
             analytics_key: b.analytics_key.or(a.analytics_key),
             analytics_key_fe: b.analytics_key_fe.or(a.analytics_key_fe),
 
@@ -351,6 +764,8 @@ impl Configuration {
             sentry_dsn_fe: b.sentry_dsn_fe.or(a.sentry_dsn_fe),
 
             dylib_dir: b.dylib_dir.or(a.dylib_dir),
+
+            grammar_dir: b.grammar_dir.or(a.grammar_dir),
         }
     }
 
@@ -358,6 +773,46 @@ impl Configuration {
     pub fn log_dir(&self) -> PathBuf {
         self.index_dir.join("logs")
     }
+
+    /// The GitLab instance to index from.
+    pub fn gitlab_url(&self) -> reqwest::Url {
+        self.gitlab_url
+            .clone()
+            .unwrap_or_else(|| "https://gitlab.com".parse().unwrap())
+    }
+
+    /// The Bitbucket instance to index from.
+    pub fn bitbucket_url(&self) -> reqwest::Url {
+        self.bitbucket_url
+            .clone()
+            .unwrap_or_else(|| "https://bitbucket.org".parse().unwrap())
+    }
+
+    /// The Bitbucket credentials given in the configuration, if any.
+    pub(crate) fn bitbucket_auth(&self) -> Option<crate::remotes::bitbucket::Auth> {
+        use crate::remotes::bitbucket::Auth;
+
+        match (
+            &self.bitbucket_token,
+            &self.bitbucket_username,
+            &self.bitbucket_app_password,
+        ) {
+            (Some(token), ..) => Some(Auth::Token(token.clone())),
+            (None, Some(username), Some(password)) => Some(Auth::AppPassword {
+                username: username.clone(),
+                password: password.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The base path with a leading slash and no trailing one, which is empty at the root.
+    pub fn base_path(&self) -> String {
+        match self.base_path.as_deref().map(|p| p.trim_matches('/')) {
+            Some(path) if !path.is_empty() => format!("/{path}"),
+            _ => String::new(),
+        }
+    }
 }
 
 pub fn serialize_secret_opt_str<S>(
@@ -380,6 +835,38 @@ where
     ser.serialize_str(secstr.expose_secret())
 }
 
+/// Trade-off between memory use and search accuracy in the vector store.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VectorQuantization {
+    /// Only store full precision vectors
+    #[default]
+    None,
+
+    /// Int8 scalar quantization. Roughly 4x less memory, with a negligible drop in accuracy
+    Scalar,
+
+    /// Product quantization. Roughly 16x less memory, at a noticeable cost in accuracy
+    Product,
+}
+
+/// The provider of embeddings.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmbedderKind {
+    /// The ONNX model in `model_dir`
+    #[default]
+    Local,
+
+    /// An OpenAI-compatible `/embeddings` API, defaulting to OpenAI's
+    #[clap(name = "openai")]
+    #[serde(rename = "openai")]
+    OpenAi,
+
+    /// An Ollama server, defaulting to one on this machine
+    Ollama,
+}
+
 //
 // Configuration defaults
 //
@@ -406,6 +893,26 @@ pub const fn minimum_parallelism() -> usize {
     1
 }
 
+fn default_interactive_threads() -> usize {
+    default_parallelism() / 4
+}
+
+fn default_min_free_disk_mb() -> u64 {
+    2048
+}
+
+fn default_conversation_trash_days() -> u64 {
+    30
+}
+
+fn default_channel_retention_days() -> u64 {
+    14
+}
+
+fn default_workspace_trash_days() -> u64 {
+    30
+}
+
 pub const fn default_buffer_size() -> usize {
     500_000_000
 }
@@ -426,6 +933,10 @@ fn default_qdrant_url() -> String {
     String::from("http://127.0.0.1:6334")
 }
 
+fn default_llm_context_window() -> usize {
+    8192
+}
+
 fn default_answer_api_url() -> String {
     String::from("http://127.0.0.1:7879")
 }
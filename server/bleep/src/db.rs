@@ -1,8 +1,8 @@
-use std::{path::Path, sync::Arc};
+use std::{fmt, path::Path, sync::Arc};
 
-use anyhow::{Context, Result};
-use sqlx::SqlitePool;
-use tracing::{debug, error};
+use anyhow::{bail, Context, Result};
+use sqlx::{migrate::Migrator, SqlitePool};
+use tracing::{debug, error, info, warn};
 
 use crate::Configuration;
 
@@ -11,46 +11,413 @@ pub use query_log::QueryLog;
 
 pub type SqlDb = Arc<SqlitePool>;
 
+/// The latest migration of the previous release, which has to be able to open databases that
+/// this version migrated, so that going back to it doesn't lose conversations.
+///
+/// Migrations after it can only add to the schema: they can't drop or rename tables or columns,
+/// and the columns they add need a default or to be nullable, so that the inserts of the
+/// previous release still work. Bump it to the latest migration when cutting a release.
+const COMPATIBLE_SINCE: i64 = 20231004101827;
+
+fn migrator() -> Migrator {
+    let mut migrator = sqlx::migrate!();
+
+    // Newer versions only add to the schema, up to the `compatible_since` they record
+    migrator.set_ignore_missing(true);
+    migrator
+}
+
 #[tracing::instrument(skip_all)]
 pub async fn initialize(config: &Configuration) -> Result<SqlitePool> {
     let data_dir = config.index_dir.to_string_lossy();
     let url = format!("sqlite://{data_dir}/bleep.db?mode=rwc");
 
-    match connect(&url).await {
+    match connect(config, &url).await {
         Ok(pool) => {
             debug!("connected");
             Ok(pool)
         }
+        // Resetting would throw away the database that `--require-migrate` protects
+        Err(e) if e.is::<MigrationRefused>() => Err(e),
         Err(e) => {
             error!(?e, "error while migrating, recreating database...");
 
             reset(&data_dir)?;
             debug!("reset complete");
 
-            Ok(connect(&data_dir)
+            Ok(connect(config, &url)
                 .await
                 .context("failed to recreate database")?)
         }
     }
 }
 
-#[tracing::instrument()]
-async fn connect(url: &str) -> Result<SqlitePool> {
+/// Print the migrations that starting would apply to the database, without applying them.
+pub async fn print_plan(config: &Configuration) -> Result<()> {
+    let db_path = config.index_dir.join("bleep.db");
+    if !db_path.exists() {
+        println!(
+            "there is no database at {}, it would be created",
+            db_path.display()
+        );
+        return Ok(());
+    }
+
+    let url = format!("sqlite://{}?mode=ro", db_path.to_string_lossy());
+    let pool = SqlitePool::connect(&url).await?;
+    let plan = Plan::load(&pool).await;
+    pool.close().await;
+
+    println!("{}", plan?);
+    Ok(())
+}
+
+#[tracing::instrument(skip(config))]
+async fn connect(config: &Configuration, url: &str) -> Result<SqlitePool> {
     let pool = SqlitePool::connect(url).await?;
 
-    if let Err(e) = sqlx::migrate!().run(&pool).await {
+    if let Err(e) = migrate(config, &pool).await {
         // We manually close the pool here to ensure file handles are properly cleaned up on
         // Windows.
         pool.close().await;
-        Err(e)?
+        Err(e)
     } else {
         Ok(pool)
     }
 }
 
+async fn migrate(config: &Configuration, pool: &SqlitePool) -> Result<()> {
+    let plan = Plan::load(pool).await?;
+
+    if !plan.pending.is_empty() || !plan.unknown.is_empty() {
+        info!(%plan, "migrating database");
+    }
+
+    // A newer version migrated the database beyond what this version can open
+    let downgraded = !plan.is_compatible();
+
+    if config.require_migrate {
+        // New databases have nothing to lose
+        let unapproved = plan.current.is_some() && !plan.pending.is_empty() && !config.migrate;
+
+        // Failing otherwise resets the database
+        if downgraded || unapproved {
+            return Err(MigrationRefused(plan.to_string()).into());
+        }
+    }
+
+    if downgraded {
+        bail!("the database was migrated by a newer version that this version can't open");
+    }
+
+    if let (Some(version), false) = (plan.current, plan.pending.is_empty()) {
+        let path = config.index_dir.join(format!("bleep.db.{version}.bk"));
+
+        // `VACUUM INTO` doesn't overwrite files
+        if path.exists() {
+            std::fs::remove_file(&path).context("failed to remove old database snapshot")?;
+        }
+
+        sqlx::query("VACUUM INTO ?")
+            .bind(path.to_string_lossy().into_owned())
+            .execute(pool)
+            .await
+            .context("failed to snapshot database before migrating")?;
+
+        info!(path = %path.display(), "saved database snapshot");
+    }
+
+    if !plan.unknown.is_empty() {
+        warn!(versions = ?plan.unknown, "database was migrated by a newer version");
+    }
+
+    migrator().run(pool).await?;
+
+    // Only the newest version knows how far back its schema is compatible
+    if plan.unknown.is_empty() {
+        sqlx::query!(
+            "INSERT INTO schema_compatibility (id, compatible_since) VALUES (0, ?)
+            ON CONFLICT (id) DO UPDATE SET compatible_since = excluded.compatible_since",
+            COMPATIBLE_SINCE,
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    Ok(())
+}
+
 #[tracing::instrument()]
 fn reset(data_dir: &str) -> Result<()> {
     let db_path = Path::new(data_dir).join("bleep.db");
     let bk_path = db_path.with_extension("db.bk");
     std::fs::rename(db_path, bk_path).context("failed to backup old database")
 }
+
+/// Migrating was refused with `--require-migrate`.
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "refusing to migrate the database, start with `--migrate` to apply pending migrations, or \
+    with a newer version if it was migrated by one\n{0}"
+)]
+struct MigrationRefused(String);
+
+/// What migrating a database would do.
+#[derive(Debug, PartialEq)]
+struct Plan {
+    /// The latest migration this version knows of
+    latest: i64,
+    /// The latest migration applied to the database, if any
+    current: Option<i64>,
+    /// The oldest migration a version needs to know of to open the database, as recorded by the
+    /// version that migrated it
+    compatible_since: Option<i64>,
+    /// The versions and descriptions of the migrations to apply, in order
+    pending: Vec<(i64, String)>,
+    /// Migrations applied to the database that this version doesn't know of
+    unknown: Vec<i64>,
+}
+
+impl Plan {
+    fn new(
+        known: impl IntoIterator<Item = (i64, String)>,
+        applied: &[i64],
+        compatible_since: Option<i64>,
+    ) -> Self {
+        let known = known.into_iter().collect::<Vec<_>>();
+
+        Self {
+            latest: known
+                .iter()
+                .map(|(version, _)| *version)
+                .max()
+                .unwrap_or_default(),
+            current: applied.iter().copied().max(),
+            compatible_since,
+            pending: known
+                .iter()
+                .filter(|(version, _)| !applied.contains(version))
+                .cloned()
+                .collect(),
+            unknown: applied
+                .iter()
+                .copied()
+                .filter(|version| !known.iter().any(|(known, _)| known == version))
+                .collect(),
+        }
+    }
+
+    /// Whether this version can open the database, even if a newer version migrated it.
+    fn is_compatible(&self) -> bool {
+        self.unknown.is_empty()
+            || self
+                .compatible_since
+                .map_or(false, |compatible_since| self.latest >= compatible_since)
+    }
+
+    async fn load(pool: &SqlitePool) -> Result<Self> {
+        let migrated = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (
+                SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations'
+            )",
+        )
+        .fetch_one(pool)
+        .await?;
+
+        let applied = if migrated {
+            sqlx::query_scalar::<_, i64>(
+                "SELECT version FROM _sqlx_migrations WHERE success ORDER BY version",
+            )
+            .fetch_all(pool)
+            .await?
+        } else {
+            vec![]
+        };
+
+        // Databases from before the compatibility window don't have the table
+        let compatible_since = sqlx::query_scalar::<_, i64>(
+            "SELECT compatible_since FROM schema_compatibility WHERE id = 0",
+        )
+        .fetch_optional(pool)
+        .await
+        .ok()
+        .flatten();
+
+        let known = migrator()
+            .iter()
+            .map(|migration| (migration.version, migration.description.to_string()))
+            .collect::<Vec<_>>();
+
+        Ok(Self::new(known, &applied, compatible_since))
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.current {
+            Some(version) => writeln!(f, "the database is at migration {version}")?,
+            None => writeln!(f, "the database has no migrations applied")?,
+        }
+
+        if self.pending.is_empty() {
+            writeln!(f, "there are no pending migrations")?;
+        } else {
+            writeln!(f, "pending migrations:")?;
+            for (version, description) in &self.pending {
+                writeln!(f, "  {version} {description}")?;
+            }
+        }
+
+        if !self.unknown.is_empty() {
+            let compatible = if self.is_compatible() {
+                "which this version can open"
+            } else {
+                "which this version can't open"
+            };
+
+            writeln!(f, "migrations from a newer version, {compatible}:")?;
+            for version in &self.unknown {
+                writeln!(f, "  {version}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A migrated database in memory, for tests of queries.
+#[cfg(test)]
+pub(crate) async fn test_pool() -> SqlitePool {
+    // Every connection to `:memory:` opens a database of its own
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+
+    migrator().run(&pool).await.unwrap();
+    pool
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plans_migrations() {
+        let known = || {
+            [
+                (1, "a".to_owned()),
+                (2, "b".to_owned()),
+                (3, "c".to_owned()),
+            ]
+        };
+
+        assert_eq!(
+            Plan::new(known(), &[1], None),
+            Plan {
+                latest: 3,
+                current: Some(1),
+                compatible_since: None,
+                pending: vec![(2, "b".to_owned()), (3, "c".to_owned())],
+                unknown: vec![],
+            }
+        );
+
+        assert_eq!(
+            Plan::new(known(), &[1, 2, 3, 4], None),
+            Plan {
+                latest: 3,
+                current: Some(4),
+                compatible_since: None,
+                pending: vec![],
+                unknown: vec![4],
+            }
+        );
+
+        assert_eq!(Plan::new(known(), &[], None).pending.len(), 3);
+    }
+
+    #[test]
+    fn opens_newer_databases_in_the_window() {
+        let known = || [(1, "a".to_owned()), (2, "b".to_owned())];
+
+        assert!(Plan::new(known(), &[1, 2], None).is_compatible());
+        assert!(Plan::new(known(), &[1, 2, 3], Some(2)).is_compatible());
+        assert!(!Plan::new(known(), &[1, 2, 3, 4], Some(3)).is_compatible());
+
+        // Versions from before the window didn't record it
+        assert!(!Plan::new(known(), &[1, 2, 3], None).is_compatible());
+    }
+
+    /// Tables and columns of a database, and whether each column can be left out of inserts.
+    async fn schema(pool: &SqlitePool) -> Vec<(String, String, bool)> {
+        sqlx::query_as::<_, (String, String, bool)>(
+            "SELECT m.name, c.name, c.\"notnull\" = 0 OR c.dflt_value IS NOT NULL OR c.pk > 0
+            FROM sqlite_master m, pragma_table_info(m.name) c
+            WHERE m.type = 'table' AND m.name NOT LIKE 'sqlite_%' AND m.name != '_sqlx_migrations'
+            ORDER BY m.name, c.cid",
+        )
+        .fetch_all(pool)
+        .await
+        .unwrap()
+    }
+
+    async fn memory() -> SqlitePool {
+        // Every connection to `:memory:` opens a database of its own
+        sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn previous_release_opens_current_database() {
+        let previous = || {
+            let migrations = migrator()
+                .iter()
+                .filter(|migration| migration.version <= COMPATIBLE_SINCE)
+                .cloned()
+                .collect::<Vec<_>>();
+
+            Migrator {
+                migrations: migrations.into(),
+                ignore_missing: true,
+                locking: true,
+            }
+        };
+
+        let current = memory().await;
+        migrator().run(&current).await.unwrap();
+
+        // Starting the previous release runs its migrations, which are all applied already
+        previous().run(&current).await.unwrap();
+
+        let old = memory().await;
+        previous().run(&old).await.unwrap();
+
+        let current_schema = schema(&current).await;
+        let old_schema = schema(&old).await;
+
+        for (table, column, _) in &old_schema {
+            assert!(
+                current_schema
+                    .iter()
+                    .any(|(t, c, _)| t == table && c == column),
+                "{table}.{column} was dropped or renamed since the previous release",
+            );
+        }
+
+        // Inserts by the previous release leave out the columns it doesn't know of
+        for (table, column, optional) in &current_schema {
+            let known_table = old_schema.iter().any(|(t, ..)| t == table);
+            let known_column = old_schema.iter().any(|(t, c, _)| t == table && c == column);
+
+            assert!(
+                !known_table || known_column || *optional,
+                "{table}.{column} was added without a default since the previous release",
+            );
+        }
+    }
+}
@@ -44,6 +44,11 @@ impl Embedder for RemoteEmbedder {
         self.embedder.tokenizer()
     }
 
+    // The server runs the configured local model
+    fn model_id(&self) -> &str {
+        self.embedder.model_id()
+    }
+
     async fn batch_embed(&self, sequence: Vec<&str>) -> anyhow::Result<Vec<Embedding>> {
         Ok(self
             .make_request(ServerRequest { sequence })
@@ -5,7 +5,7 @@ use axum::{
 
 use crate::{
     background::SyncConfig,
-    repo::FilterUpdate,
+    repo::{iterator::SparsePaths, FilterUpdate},
     webserver::{
         middleware::User,
         prelude::*,
@@ -22,7 +22,7 @@ pub(crate) async fn patch_repository(
     user: Extension<User>,
     State(app): State<Application>,
     Json(mut patch): Json<FilterUpdate>,
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse> {
     if let Some(ref file_filter) = patch.file_filter {
         _ = crate::repo::iterator::FileFilter::from(file_filter);
     }
@@ -31,16 +31,22 @@ pub(crate) async fn patch_repository(
         _ = crate::repo::iterator::BranchFilter::from(branch_filter);
     }
 
+    if let Some(ref paths) = patch.sparse_paths {
+        let sparse = SparsePaths::new(paths).map_err(Error::user)?;
+        patch.sparse_paths = Some(sparse.dirs().to_vec());
+    }
+
     if !user.paid_features(&app).await {
         patch.branch_filter = None;
     }
 
-    if patch.file_filter.is_some() || patch.branch_filter.is_some() {
+    if patch.file_filter.is_some() || patch.branch_filter.is_some() || patch.sparse_paths.is_some()
+    {
         app.write_index()
             .enqueue(SyncConfig::new(app, repo).filter_updates(patch.into()))
             .await;
-        json(ReposResponse::SyncQueued)
+        Ok(json(ReposResponse::SyncQueued))
     } else {
-        json(ReposResponse::Unchanged)
+        Ok(json(ReposResponse::Unchanged))
     }
 }
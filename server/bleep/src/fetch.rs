@@ -0,0 +1,387 @@
+//! Outbound HTTP requests to URLs provided by users.
+//!
+//! Every feature that makes requests to a user-provided URL should go through the [`Fetcher`], so
+//! that the server can't be used to reach into the network it is running in.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use reqwest::{
+    header::{CONTENT_TYPE, LOCATION},
+    redirect::Policy,
+    RequestBuilder, StatusCode,
+};
+use thiserror::Error;
+use url::{Host, Url};
+
+use crate::Configuration;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("refusing to fetch `{0}`: {1}")]
+    Blocked(Url, &'static str),
+
+    #[error("failed to resolve `{0}`: {1}")]
+    Resolve(Url, #[source] std::io::Error),
+
+    #[error("invalid redirect from `{0}`")]
+    InvalidRedirect(Url),
+
+    #[error("too many redirects fetching `{0}`")]
+    TooManyRedirects(Url),
+
+    #[error("network error: {0}")]
+    Network(#[from] reqwest::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A hardened HTTP client for user-provided URLs.
+///
+/// Requests are only made to `http` and `https` URLs, and every redirect is checked again. Hosts
+/// on the deny list are always rejected, and if an allow list is configured, only hosts on it can
+/// be fetched. Hosts that resolve to private addresses must be allowed explicitly, and link-local
+/// addresses (such as cloud metadata endpoints) can't be fetched at all.
+#[derive(Clone, Debug)]
+pub struct Fetcher {
+    allowlist: Vec<String>,
+    denylist: Vec<String>,
+    user_agent: String,
+    timeout: Duration,
+    max_redirects: usize,
+    max_bytes: usize,
+}
+
+#[derive(Debug)]
+pub struct Response {
+    /// The final URL, after following redirects.
+    pub url: Url,
+    pub status: StatusCode,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+
+    /// Whether the body was cut short at the size limit.
+    pub truncated: bool,
+}
+
+impl Fetcher {
+    pub fn new(config: &Configuration) -> Self {
+        Self {
+            allowlist: normalize_hosts(&config.fetch_allowlist),
+            denylist: normalize_hosts(&config.fetch_denylist),
+            user_agent: format!("bloop/{}", env!("CARGO_PKG_VERSION")),
+            timeout: Duration::from_secs(10),
+            max_redirects: 3,
+            max_bytes: 1024 * 1024,
+        }
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Check a URL against the scheme and host rules, without making any requests.
+    pub fn check(&self, url: &Url) -> Result<()> {
+        self.check_host(url).map(|_| ())
+    }
+
+    pub async fn get(&self, url: Url) -> Result<Response> {
+        let mut url = url;
+
+        for _ in 0..=self.max_redirects {
+            // We follow redirects manually, so that every hop is checked.
+            let response = self.send(&url, |client| client.get(url.clone())).await?;
+
+            if response.status().is_redirection() {
+                url = response
+                    .headers()
+                    .get(LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                    .and_then(|location| url.join(location).ok())
+                    .ok_or_else(|| Error::InvalidRedirect(url.clone()))?;
+
+                continue;
+            }
+
+            return self.read(url, response).await;
+        }
+
+        Err(Error::TooManyRedirects(url))
+    }
+
+    /// Make a `POST` request, with the same checks as [`Fetcher::get`].
+    ///
+    /// Redirects are returned rather than followed, so that the body and any credentials set by
+    /// `build` are never sent to another host.
+    pub async fn post(
+        &self,
+        url: Url,
+        build: impl FnOnce(RequestBuilder) -> RequestBuilder,
+    ) -> Result<Response> {
+        let response = self
+            .send(&url, |client| build(client.post(url.clone())))
+            .await?;
+
+        self.read(url, response).await
+    }
+
+    /// Send a single request, with a client that is pinned to the address we checked, so that a
+    /// second DNS lookup can't point it somewhere else.
+    async fn send(
+        &self,
+        url: &Url,
+        build: impl FnOnce(reqwest::Client) -> RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        let addr = self.resolve(url).await?;
+        let mut client = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .timeout(self.timeout)
+            .user_agent(&self.user_agent);
+
+        if let Some(Host::Domain(domain)) = url.host() {
+            client = client.resolve(domain, addr);
+        }
+
+        Ok(build(client.build()?).send().await?)
+    }
+
+    /// Read the body of a response, up to the size limit.
+    async fn read(&self, url: Url, mut response: reqwest::Response) -> Result<Response> {
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+
+        let mut body = Vec::new();
+        let mut truncated = false;
+        while let Some(chunk) = response.chunk().await? {
+            let remaining = self.max_bytes - body.len();
+            if chunk.len() > remaining {
+                body.extend_from_slice(&chunk[..remaining]);
+                truncated = true;
+                break;
+            }
+
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok(Response {
+            url,
+            status,
+            content_type,
+            body,
+            truncated,
+        })
+    }
+
+    /// Check the scheme & host of a URL, returning whether the host is explicitly allowed.
+    fn check_host(&self, url: &Url) -> Result<bool> {
+        let blocked = |reason| Err(Error::Blocked(url.clone(), reason));
+
+        if !matches!(url.scheme(), "http" | "https") {
+            return blocked("only http and https URLs can be fetched");
+        }
+
+        let Some(host) = url.host_str() else {
+            return blocked("URL does not have a host");
+        };
+
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if self.denylist.iter().any(|rule| host_matches(rule, host)) {
+            return blocked("host is on the deny list");
+        }
+
+        let allowlisted = self.allowlist.iter().any(|rule| host_matches(rule, host));
+        if !self.allowlist.is_empty() && !allowlisted {
+            return blocked("host is not on the allow list");
+        }
+
+        Ok(allowlisted)
+    }
+
+    /// Resolve the host of a URL, checking every address it resolves to.
+    async fn resolve(&self, url: &Url) -> Result<SocketAddr> {
+        let allowlisted = self.check_host(url)?;
+        let blocked = |reason| Err(Error::Blocked(url.clone(), reason));
+
+        let Some(port) = url.port_or_known_default() else {
+            return blocked("URL does not have a port");
+        };
+
+        let addrs = match url.host() {
+            Some(Host::Ipv4(ip)) => vec![SocketAddr::new(ip.into(), port)],
+            Some(Host::Ipv6(ip)) => vec![SocketAddr::new(ip.into(), port)],
+            Some(Host::Domain(domain)) => tokio::net::lookup_host((domain, port))
+                .await
+                .map_err(|e| Error::Resolve(url.clone(), e))?
+                .collect(),
+            None => return blocked("URL does not have a host"),
+        };
+
+        for addr in &addrs {
+            if is_link_local(addr.ip()) {
+                return blocked("host resolves to a link-local address");
+            }
+
+            if !allowlisted && !is_public(addr.ip()) {
+                return blocked("host resolves to a private address");
+            }
+        }
+
+        match addrs.first() {
+            Some(addr) => Ok(*addr),
+            None => blocked("host did not resolve to any address"),
+        }
+    }
+}
+
+/// Host rules are lowercase, and `*.example.com` is the same as `example.com`.
+fn normalize_hosts(hosts: &[String]) -> Vec<String> {
+    hosts
+        .iter()
+        .map(|host| host.trim().trim_start_matches("*.").to_ascii_lowercase())
+        .filter(|host| !host.is_empty())
+        .collect()
+}
+
+/// Whether a host is covered by a rule, which includes all of its subdomains.
+fn host_matches(rule: &str, host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    host == rule
+        || host
+            .strip_suffix(rule)
+            .map_or(false, |prefix| prefix.ends_with('.'))
+}
+
+fn is_link_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_link_local(),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => ip.is_link_local(),
+            None => (ip.segments()[0] & 0xffc0) == 0xfe80,
+        },
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            let shared = a == 100 && (b & 0xc0) == 64;
+
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || shared
+                || a == 0)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(ip.into());
+            }
+
+            let unique_local = (ip.segments()[0] & 0xfe00) == 0xfc00;
+
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || unique_local
+                || is_link_local(ip.into()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fetcher(allowlist: &[&str], denylist: &[&str]) -> Fetcher {
+        Fetcher {
+            allowlist: normalize_hosts(
+                &allowlist.iter().map(|h| h.to_string()).collect::<Vec<_>>(),
+            ),
+            denylist: normalize_hosts(&denylist.iter().map(|h| h.to_string()).collect::<Vec<_>>()),
+            user_agent: "test".into(),
+            timeout: Duration::from_secs(1),
+            max_redirects: 0,
+            max_bytes: 0,
+        }
+    }
+
+    #[test]
+    fn classifies_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+
+        for ip in ["1.1.1.1", "140.82.121.4", "2606:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+
+        for ip in ["169.254.169.254", "fe80::1", "::ffff:169.254.169.254"] {
+            assert!(is_link_local(ip.parse().unwrap()), "{ip}");
+        }
+
+        assert!(!is_link_local("10.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn matches_host_rules() {
+        assert!(host_matches("example.com", "example.com"));
+        assert!(host_matches("example.com", "docs.Example.com"));
+        assert!(!host_matches("example.com", "badexample.com"));
+        assert!(!host_matches("example.com", "example.com.evil.net"));
+    }
+
+    #[test]
+    fn checks_urls() {
+        let check = |fetcher: &Fetcher, url: &str| fetcher.check(&url.parse().unwrap()).is_ok();
+
+        let open = fetcher(&[], &["evil.net"]);
+        assert!(check(&open, "https://example.com/docs"));
+        assert!(!check(&open, "https://api.evil.net/"));
+        assert!(!check(&open, "file:///etc/passwd"));
+        assert!(!check(&open, "ftp://example.com/"));
+
+        let restricted = fetcher(&["*.example.com"], &["secret.example.com"]);
+        assert!(check(&restricted, "https://docs.example.com/"));
+        assert!(!check(&restricted, "https://secret.example.com/"));
+        assert!(!check(&restricted, "https://example.org/"));
+    }
+}
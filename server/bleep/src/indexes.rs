@@ -7,7 +7,7 @@ use tantivy::{
     collector::{Collector, MultiFruit},
     schema::Schema,
     tokenizer::NgramTokenizer,
-    DocAddress, Document, IndexReader, IndexWriter, Score,
+    DocAddress, Document, IndexReader, IndexWriter, Score, Searcher,
 };
 
 mod analytics;
@@ -24,6 +24,7 @@ use tracing::debug;
 
 use crate::{
     background::SyncHandle,
+    fetch::Fetcher,
     query::parser::Query,
     repo::{RepoError, RepoMetadata, Repository},
     Configuration,
@@ -108,6 +109,7 @@ impl Indexes {
                 config.index_path("doc").as_ref(),
                 config.buffer_size,
                 config.max_threads,
+                Fetcher::new(config),
             )?,
             write_mutex: Default::default(),
             was_index_reset,
@@ -288,14 +290,19 @@ impl<T: Indexable> Indexer<T> {
             .search(&compiled_query, &collector)
             .context("failed to execute search query")?;
 
+        let hits = top_k.iter().map(|(_score, addr)| *addr).collect();
+        let doc_searcher = searcher.clone();
         let iter = top_k.into_iter().map(move |(_score, addr)| {
-            let doc = searcher.doc(addr).unwrap();
+            let doc = doc_searcher.doc(addr).unwrap();
             doc_reader.read_document(&self.source, doc)
         });
 
         Ok(SearchResults {
             docs: Box::new(iter),
             metadata,
+            hits,
+            searcher,
+            query: compiled_query,
         })
     }
 }
@@ -303,4 +310,8 @@ impl<T: Indexable> Indexer<T> {
 pub struct SearchResults<'a, T> {
     pub docs: Box<dyn Iterator<Item = T> + Sync + Send + 'a>,
     pub metadata: MultiFruit,
+    /// The addresses of `docs`, in order, to explain their scores with
+    pub hits: Vec<DocAddress>,
+    pub searcher: Searcher,
+    pub query: Box<dyn tantivy::query::Query>,
 }
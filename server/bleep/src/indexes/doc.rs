@@ -13,6 +13,7 @@ use tracing::{error, info, trace};
 
 use crate::{
     db::SqlDb,
+    fetch::{self, Fetcher},
     indexes::schema,
     query::compiler::{case_permutations, trigrams},
     scraper::{self, Config, Scraper},
@@ -26,6 +27,7 @@ pub struct Doc {
     section_index: tantivy::Index,
     section_schema: schema::Section,
     buffer_size: usize,
+    fetcher: Fetcher,
 }
 
 static STATUS_DONE: &str = "done";
@@ -86,6 +88,9 @@ pub enum Error {
     #[error("network error: {0}")]
     Network(#[from] reqwest::Error),
 
+    #[error(transparent)]
+    Fetch(#[from] fetch::Error),
+
     #[error("no docs found at url: {0}")]
     EmptyDocs(url::Url),
 }
@@ -107,6 +112,7 @@ impl Doc {
         path: &std::path::Path,
         buffer_size: usize,
         max_threads: usize,
+        fetcher: Fetcher,
     ) -> Result<Self, Error> {
         std::fs::create_dir_all(path)?;
 
@@ -131,6 +137,7 @@ impl Doc {
             section_index,
             section_schema,
             buffer_size,
+            fetcher,
         })
     }
 
@@ -711,7 +718,8 @@ impl Doc {
         index_writer: Arc<Mutex<tantivy::IndexWriter>>,
     ) -> impl Stream<Item = Progress> {
         stream! {
-            let mut scraper = Scraper::with_config(Config::new(doc_source.clone()));
+            let config = Config::new(doc_source.clone(), self.fetcher.clone());
+            let mut scraper = Scraper::with_config(config);
             let mut stream = Box::pin(scraper.complete());
             let mut handles = Vec::new();
             let mut discovered_count = 0;
@@ -759,10 +767,8 @@ impl Doc {
         if self.contains_url(&url) {
             return Err(Error::DuplicateUrl(url));
         }
-        reqwest::get(url)
-            .await
-            .map(|r| r.status())
-            .map_err(Error::Network)
+        let fetcher = self.fetcher.clone().max_bytes(0);
+        Ok(fetcher.get(url).await?.status)
     }
 }
 
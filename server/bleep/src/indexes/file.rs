@@ -35,6 +35,7 @@ use crate::{
     background::SyncHandle,
     cache::{CacheKeys, FileCache, FileCacheSnapshot},
     intelligence::TreeSitterFile,
+    notebook,
     query::compiler::{case_permutations, trigrams},
     repo::{iterator::*, RepoMetadata, RepoRef, Repository},
     symbol::SymbolLocations,
@@ -102,6 +103,8 @@ impl Indexable for File {
             ref file_cache,
             ref pipes,
             ref app,
+            ref filter_updates,
+            force,
             ..
         }: &SyncHandle,
         repo: &Repository,
@@ -109,7 +112,9 @@ impl Indexable for File {
         writer: &IndexWriter,
     ) -> Result<()> {
         let file_filter = FileFilter::compile(&repo.file_filter)?;
+        let sparse = SparsePaths::new(&repo.sparse_paths)?;
         let cache = file_cache.retrieve(reporef).await;
+        file_cache.start_coverage(reporef);
         let repo_name = reporef.indexed_name();
         let processed = &AtomicU64::new(0);
         let mut stats_gatherer = StatsGatherer::for_repo(reporef.clone());
@@ -171,11 +176,33 @@ impl Indexable for File {
         let start = std::time::Instant::now();
 
         if reporef.is_remote() {
-            let walker = GitWalker::open_repository(
-                reporef,
-                &repo.disk_path,
-                repo.branch_filter.as_ref().map(Into::into),
-            )?;
+            let changes = match repo.indexed_commit {
+                Some(ref since) if !force && can_index_changes(repo, filter_updates, &cache) => {
+                    GitWalker::open_changes(reporef, &repo.disk_path, since, &sparse)
+                        .map_err(|err| warn!(?err, "can't diff against the last index"))
+                        .ok()
+                }
+                _ => None,
+            };
+
+            let walker = match changes {
+                Some((walker, unchanged)) => {
+                    info!(
+                        changed = walker.len(),
+                        unchanged = unchanged.len(),
+                        "indexing changes since the last index"
+                    );
+                    stats_gatherer.event.add_payload("incremental", &true);
+                    cache.keep_paths(&unchanged);
+                    walker
+                }
+                None => GitWalker::open_repository(
+                    reporef,
+                    &repo.disk_path,
+                    repo.branch_filter.as_ref().map(Into::into),
+                    &sparse,
+                )?,
+            };
             let count = walker.len();
             stats_gatherer.event.add_payload("file_count", &count);
             walker.for_each(pipes, file_worker(count));
@@ -196,7 +223,7 @@ impl Indexable for File {
                 })
                 .unwrap_or_else(|| "HEAD".to_owned());
 
-            let walker = FileWalker::index_directory(&repo.disk_path, branch);
+            let walker = FileWalker::index_directory(&repo.disk_path, branch, &sparse);
             let count = walker.len();
             stats_gatherer.event.add_payload("file_count", &count);
             walker.for_each(pipes, file_worker(count));
@@ -221,6 +248,10 @@ impl Indexable for File {
             })
             .await?;
 
+        if let Err(err) = file_cache.save_coverage(reporef).await {
+            warn!(?err, "failed to save embedding coverage");
+        }
+
         pipes.index_percent(100);
         Ok(())
     }
@@ -237,6 +268,24 @@ impl Indexable for File {
     }
 }
 
+/// Whether only the changes since the last index need to be walked.
+///
+/// That's only when HEAD is the only branch indexed, as other branches aren't diffed, when the
+/// filters that decide what is indexed stay the same, and when every cached entry knows its path,
+/// so that unchanged paths can be kept.
+fn can_index_changes(
+    repo: &Repository,
+    filter_updates: &FilterUpdate,
+    cache: &FileCacheSnapshot<'_>,
+) -> bool {
+    let head_only = matches!(repo.branch_filter, None | Some(BranchFilterConfig::Head));
+    let filters_unchanged = filter_updates.branch_filter.is_none()
+        && filter_updates.file_filter.is_none()
+        && filter_updates.sparse_paths.is_none();
+
+    head_only && filters_unchanged && cache.is_addressable()
+}
+
 impl Indexer<File> {
     /// Search this index for paths fuzzily matching a given string.
     ///
@@ -600,7 +649,10 @@ impl File {
         let last_commit = workload.repo_metadata.last_commit_unix_secs.unwrap_or(0);
 
         match dir_entry {
-            _ if workload.cache.is_fresh(&cache_keys) => {
+            _ if workload
+                .cache
+                .is_fresh(&cache_keys, &workload.relative_path.to_string_lossy()) =>
+            {
                 info!("fresh; skipping");
             }
             RepoDirEntry::Dir(dir) => {
@@ -788,6 +840,13 @@ impl RepoFile {
                 ""
             });
 
+        if notebook::is_notebook(relative_path) {
+            match notebook::render(&buffer) {
+                Some(rendered) => buffer = rendered,
+                None => warn!(?relative_path, "invalid notebook, indexing it as JSON"),
+            }
+        }
+
         let symbol_locations = {
             // build a syntax aware representation of the file
             let scope_graph = TreeSitterFile::try_build(buffer.as_bytes(), lang_str)
@@ -2,9 +2,13 @@ pub mod code_navigation;
 mod language;
 mod namespace;
 mod scope_resolution;
+pub mod structural;
 
 pub use {
-    language::{Language, MemoizedQuery, TSLanguage, TSLanguageConfig, ALL_LANGUAGES},
+    language::{
+        all_languages, custom_language, load_grammars, Grammar, Language, MemoizedQuery,
+        TSLanguage, TSLanguageConfig,
+    },
     namespace::*,
     scope_resolution::{NodeKind, ScopeGraph},
 };
@@ -48,7 +52,7 @@ impl<'a> TreeSitterFile<'a> {
 
         let mut parser = Parser::new();
         parser
-            .set_language((language.grammar)())
+            .set_language(language.grammar.language())
             .map_err(|_| TreeSitterFileError::LanguageMismatch)?;
 
         // do not permit files that take >1s to parse
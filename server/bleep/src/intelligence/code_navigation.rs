@@ -1,6 +1,8 @@
 //! Handlers for code-navigation:
 //! - scope-graph based handler that operates only in the owning file
 //! - search based handler that operates on any file belonging to the repo
+//! - definitions in the other repositories of a workspace, for imports of internal packages
+//! - every occurrence of a symbol, looked up by name rather than from a token
 
 use std::{collections::HashSet, ops::Not};
 
@@ -19,6 +21,10 @@ pub struct FileSymbols {
     /// The file to which the following occurrences belong
     pub file: String,
 
+    /// The repository of `file`, when it isn't the repository of the token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+
     /// A collection of symbol locations with context in this file
     pub data: Vec<Occurrence>,
 }
@@ -318,13 +324,43 @@ impl<'a, 'b> CodeNavigationContext<'a, 'b> {
 
         data.is_empty().not().then(|| FileSymbols {
             file: self.token.relative_path.to_owned(),
+            repo: None,
             data,
         })
     }
 
     fn repo_wide_definitions(&self) -> Vec<FileSymbols> {
-        self.non_source_documents()
-            .par_bridge()
+        self.top_level_definitions(self.non_source_documents())
+    }
+
+    /// Whether the token is an import, or a reference to one, that no file of this repository
+    /// defines, given the occurrences that were found for it.
+    pub fn is_unresolved(&self, data: &[FileSymbols]) -> bool {
+        let unresolved =
+            self.is_import() || (self.is_reference() && self.local_definitions().is_none());
+        let defined_elsewhere = data.iter().any(|f| {
+            f.file != self.token.relative_path && f.data.iter().any(Occurrence::is_definition)
+        });
+
+        unresolved && !defined_elsewhere
+    }
+
+    /// Top-level definitions of the token in the documents of another repository.
+    pub fn external_definitions(&self, repo: &str, docs: &[ContentDocument]) -> Vec<FileSymbols> {
+        self.top_level_definitions(docs.iter())
+            .into_iter()
+            .map(|f| FileSymbols {
+                repo: Some(repo.to_owned()),
+                ..f
+            })
+            .collect()
+    }
+
+    fn top_level_definitions<'d>(
+        &self,
+        docs: impl Iterator<Item = &'d ContentDocument> + Send,
+    ) -> Vec<FileSymbols> {
+        docs.par_bridge()
             .filter_map(|doc| {
                 let scope_graph = doc.symbol_locations.scope_graph()?;
                 let content = doc.content.as_bytes();
@@ -350,6 +386,7 @@ impl<'a, 'b> CodeNavigationContext<'a, 'b> {
 
                 data.is_empty().not().then(|| FileSymbols {
                     file: doc.relative_path.to_owned(),
+                    repo: None,
                     data,
                 })
             })
@@ -382,6 +419,7 @@ impl<'a, 'b> CodeNavigationContext<'a, 'b> {
 
         data.is_empty().not().then(|| FileSymbols {
             file: self.token.relative_path.to_owned(),
+            repo: None,
             data,
         })
     }
@@ -415,6 +453,7 @@ impl<'a, 'b> CodeNavigationContext<'a, 'b> {
 
                 data.is_empty().not().then(|| FileSymbols {
                     file: doc.relative_path.to_owned(),
+                    repo: None,
                     data,
                 })
             })
@@ -441,6 +480,7 @@ impl<'a, 'b> CodeNavigationContext<'a, 'b> {
 
         data.is_empty().not().then(|| FileSymbols {
             file: self.token.relative_path.to_owned(),
+            repo: None,
             data,
         })
     }
@@ -452,6 +492,66 @@ pub struct Token<'a> {
     pub end_byte: usize,
 }
 
+/// The top-level definitions of `name` in a document, its imports, and the references that resolve
+/// to either.
+///
+/// References that the scope graph can't resolve are left out, as are local definitions that only
+/// share the name. Imports are returned as references, as renaming the symbol renames them too.
+pub fn symbol_occurrences(
+    doc: &ContentDocument,
+    name: &str,
+    snipper: Option<Snipper>,
+) -> Option<FileSymbols> {
+    let scope_graph = doc.symbol_locations.scope_graph()?;
+    let content = doc.content.as_bytes();
+
+    let targets = scope_graph
+        .graph
+        .node_indices()
+        .filter(|&idx| scope_graph.is_top_level(idx))
+        .filter(|&idx| match &scope_graph.graph[idx] {
+            NodeKind::Def(d) => d.name(content) == name.as_bytes(),
+            NodeKind::Import(i) => i.name(content) == name.as_bytes(),
+            _ => false,
+        })
+        .collect::<Vec<_>>();
+
+    let mut data = targets
+        .iter()
+        .map(|&idx| {
+            let kind = if scope_graph.is_definition(idx) {
+                OccurrenceKind::Definition
+            } else {
+                OccurrenceKind::Reference
+            };
+            (kind, idx)
+        })
+        .chain(
+            targets
+                .iter()
+                .flat_map(|&idx| scope_graph.references(idx))
+                .map(|idx| (OccurrenceKind::Reference, idx)),
+        )
+        .map(|(kind, idx)| {
+            let range = scope_graph.graph[idx].range();
+            Occurrence {
+                kind,
+                range,
+                snippet: to_occurrence(doc, range, snipper),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    data.sort_by_key(|occurrence| occurrence.range.start.byte);
+    data.dedup_by_key(|occurrence| occurrence.range);
+
+    data.is_empty().not().then(|| FileSymbols {
+        file: doc.relative_path.to_owned(),
+        repo: None,
+        data,
+    })
+}
+
 fn to_occurrence(doc: &ContentDocument, range: TextRange, snipper: Option<Snipper>) -> Snippet {
     let src = &doc.content;
     let line_end_indices = &doc.line_end_indices;
@@ -2,25 +2,35 @@ mod c;
 mod c_sharp;
 mod cobol;
 mod cpp;
+mod custom;
+mod elixir;
 mod go;
 mod java;
 mod javascript;
+mod kotlin;
 mod php;
 mod python;
 mod r;
 mod ruby;
 mod rust;
+mod scala;
+mod swift;
 mod typescript;
+mod yaml;
 
 #[cfg(test)]
 mod test_utils;
 
+use std::{ffi::OsStr, fmt, path::Path};
+
 use once_cell::sync::OnceCell;
 
 use super::NameSpaces;
 
-/// A collection of all language definitions
-pub static ALL_LANGUAGES: &[&TSLanguageConfig] = &[
+pub use custom::load as load_grammars;
+
+/// The languages that are compiled into bleep
+static BUILTIN_LANGUAGES: &[&TSLanguageConfig] = &[
     &c::C,
     &go::GO,
     &javascript::JAVASCRIPT,
@@ -34,8 +44,40 @@ pub static ALL_LANGUAGES: &[&TSLanguageConfig] = &[
     &r::R,
     &php::PHP,
     &cobol::COBOL,
+    &scala::SCALA,
+    &elixir::ELIXIR,
+    &yaml::YAML,
+    &kotlin::KOTLIN,
+    &swift::SWIFT,
 ];
 
+/// The built-in languages, followed by the ones loaded with [`load_grammars`].
+static LANGUAGES: OnceCell<Vec<&'static TSLanguageConfig>> = OnceCell::new();
+
+/// A collection of all language definitions
+///
+/// Scope graphs refer to languages by their index in this list.
+pub fn all_languages() -> &'static [&'static TSLanguageConfig] {
+    LANGUAGES.get_or_init(|| BUILTIN_LANGUAGES.to_vec())
+}
+
+/// The user-supplied language that claims the extension of `path`, if any.
+///
+/// These take precedence over language detection, which doesn't know about them.
+pub fn custom_language(path: &Path) -> Option<&'static str> {
+    let extension = path.extension().and_then(OsStr::to_str)?;
+
+    all_languages()
+        .iter()
+        .filter(|l| matches!(l.grammar, Grammar::Loaded(_)))
+        .find(|l| {
+            l.file_extensions
+                .iter()
+                .any(|ext| ext.eq_ignore_ascii_case(extension))
+        })
+        .map(|l| l.language_ids[0])
+}
+
 /// A generic language wrapper type.
 ///
 /// The backing grammars/parser are supplied through the `Config` type.
@@ -58,7 +100,7 @@ pub struct TSLanguageConfig {
     pub file_extensions: &'static [&'static str],
 
     /// tree-sitter grammar for this language
-    pub grammar: fn() -> tree_sitter::Language,
+    pub grammar: Grammar,
 
     /// Compiled tree-sitter scope query for this language.
     pub scope_query: MemoizedQuery,
@@ -71,6 +113,33 @@ pub struct TSLanguageConfig {
     pub namespaces: NameSpaces,
 }
 
+#[derive(Clone, Copy)]
+pub enum Grammar {
+    /// A grammar compiled into bleep
+    Builtin(fn() -> tree_sitter::Language),
+
+    /// A grammar loaded from a shared library at runtime
+    Loaded(tree_sitter::Language),
+}
+
+impl Grammar {
+    pub fn language(self) -> tree_sitter::Language {
+        match self {
+            Self::Builtin(grammar) => grammar(),
+            Self::Loaded(language) => language,
+        }
+    }
+}
+
+impl fmt::Debug for Grammar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Builtin(_) => f.write_str("Builtin"),
+            Self::Loaded(_) => f.write_str("Loaded"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct MemoizedQuery {
     slot: OnceCell<tree_sitter::Query>,
@@ -88,12 +157,9 @@ impl MemoizedQuery {
     /// Get a reference to the relevant tree sitter compiled query.
     ///
     /// This method compiles the query if it has not already been compiled.
-    pub fn query(
-        &self,
-        grammar: fn() -> tree_sitter::Language,
-    ) -> Result<&tree_sitter::Query, tree_sitter::QueryError> {
+    pub fn query(&self, grammar: Grammar) -> Result<&tree_sitter::Query, tree_sitter::QueryError> {
         self.slot
-            .get_or_try_init(|| tree_sitter::Query::new(grammar(), self.scope_query))
+            .get_or_try_init(|| tree_sitter::Query::new(grammar.language(), self.scope_query))
     }
 }
 
@@ -106,7 +172,7 @@ impl TSLanguage {
     ///
     /// [0]: https://github.com/monkslc/hyperpolyglot/blob/master/src/codegen/languages.rs
     pub fn from_id(lang_id: &str) -> Self {
-        ALL_LANGUAGES
+        all_languages()
             .iter()
             .copied()
             .find(|target| {
@@ -134,7 +200,7 @@ mod tests {
     fn verify_all_symbol_kinds() {
         let mut failed_languages = Vec::new();
 
-        for language in ALL_LANGUAGES {
+        for language in all_languages() {
             let kinds = language.namespaces.all_symbols();
             if !has_valid_symbol_kinds(language.scope_query.query(language.grammar).unwrap(), kinds)
             {
@@ -1,9 +1,9 @@
-use crate::intelligence::{MemoizedQuery, TSLanguageConfig};
+use crate::intelligence::{Grammar, MemoizedQuery, TSLanguageConfig};
 
 pub static C: TSLanguageConfig = TSLanguageConfig {
     language_ids: &["C"],
     file_extensions: &["c", "h"],
-    grammar: tree_sitter_c::language,
+    grammar: Grammar::Builtin(tree_sitter_c::language),
     scope_query: MemoizedQuery::new(include_str!("./scopes.scm")),
     hoverable_query: MemoizedQuery::new(
         r"
@@ -1,9 +1,9 @@
-use crate::intelligence::{MemoizedQuery, TSLanguageConfig};
+use crate::intelligence::{Grammar, MemoizedQuery, TSLanguageConfig};
 
 pub static C_SHARP: TSLanguageConfig = TSLanguageConfig {
     language_ids: &["C#"],
     file_extensions: &["cs"],
-    grammar: tree_sitter_c_sharp::language,
+    grammar: Grammar::Builtin(tree_sitter_c_sharp::language),
     scope_query: MemoizedQuery::new(include_str!("./scopes.scm")),
     hoverable_query: MemoizedQuery::new(
         r#"
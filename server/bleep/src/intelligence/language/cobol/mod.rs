@@ -1,9 +1,9 @@
-use crate::intelligence::{MemoizedQuery, TSLanguageConfig};
+use crate::intelligence::{Grammar, MemoizedQuery, TSLanguageConfig};
 
 pub static COBOL: TSLanguageConfig = TSLanguageConfig {
     language_ids: &["COBOL"],
     file_extensions: &["cbl", "cpy", "cob", "ccp", "cobol"],
-    grammar: tree_sitter_COBOL::language,
+    grammar: Grammar::Builtin(tree_sitter_COBOL::language),
     scope_query: MemoizedQuery::new(include_str!("./scopes.scm")),
     hoverable_query: MemoizedQuery::new(
         r"
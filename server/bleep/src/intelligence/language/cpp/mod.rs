@@ -1,9 +1,9 @@
-use crate::intelligence::{MemoizedQuery, TSLanguageConfig};
+use crate::intelligence::{Grammar, MemoizedQuery, TSLanguageConfig};
 
 pub static CPP: TSLanguageConfig = TSLanguageConfig {
     language_ids: &["C++"],
     file_extensions: &["cpp", "cc", "h"],
-    grammar: tree_sitter_cpp::language,
+    grammar: Grammar::Builtin(tree_sitter_cpp::language),
     scope_query: MemoizedQuery::new(include_str!("./scopes.scm")),
     hoverable_query: MemoizedQuery::new(
         r#"
@@ -0,0 +1,235 @@
+//! Tree-sitter grammars supplied by the user, for languages bleep doesn't ship with.
+//!
+//! Every subdirectory of the grammar directory holds one language:
+//!
+//! ```text
+//! grammars/
+//!   mydsl/
+//!     language.json   name, file extensions and optionally namespaces
+//!     parser.so       the compiled grammar, exporting `tree_sitter_mydsl`
+//!     scopes.scm      scope query, like the ones of the built-in languages
+//!     hoverable.scm   optional, captures `@hoverable` nodes
+//! ```
+//!
+//! Grammars are loaded once at startup, before anything is parsed. Only native libraries can be
+//! loaded: the tree-sitter version we use has no runtime for grammars compiled to WebAssembly.
+//!
+//! Scope graphs refer to languages by position, and custom languages come after the built-in
+//! ones sorted by directory name. Reindex repositories after adding or removing grammars.
+
+use std::{
+    collections::BTreeSet,
+    env::consts::DLL_EXTENSION,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use super::{Grammar, MemoizedQuery, TSLanguageConfig, BUILTIN_LANGUAGES, LANGUAGES};
+use crate::intelligence::{NameSpace, NameSpaces};
+
+#[derive(Deserialize)]
+struct Manifest {
+    /// The language identifier, as stored with indexed files
+    name: String,
+
+    /// File extensions without the leading dot
+    extensions: Vec<String>,
+
+    /// Groups of symbol kinds that share a namespace, by default all kinds defined in
+    /// `scopes.scm` share one
+    #[serde(default)]
+    namespaces: Option<Vec<Vec<String>>>,
+
+    /// The function returning the grammar, by default `tree_sitter_<directory name>`
+    #[serde(default)]
+    symbol: Option<String>,
+}
+
+/// Load the grammars in `dir`, alongside the built-in languages.
+///
+/// Grammars that fail to load are skipped with a warning, so that a broken grammar doesn't keep
+/// bleep from starting.
+pub fn load(dir: &Path) {
+    let mut paths = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_dir())
+            .collect::<Vec<_>>(),
+        Err(err) => {
+            warn!(?dir, ?err, "failed to read grammar directory");
+            return;
+        }
+    };
+    paths.sort();
+
+    let mut languages = BUILTIN_LANGUAGES.to_vec();
+    for path in paths {
+        match load_language(&path, &languages) {
+            Ok(config) => {
+                info!(language = config.language_ids[0], "loaded custom grammar");
+                languages.push(Box::leak(Box::new(config)));
+            }
+            Err(err) => warn!(?path, "failed to load grammar: {err:#}"),
+        }
+    }
+
+    if LANGUAGES.set(languages).is_err() {
+        warn!("custom grammars were loaded after the first file was parsed, ignoring them");
+    }
+}
+
+fn load_language(path: &Path, loaded: &[&TSLanguageConfig]) -> Result<TSLanguageConfig> {
+    let dir_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .context("invalid directory name")?;
+
+    let manifest = std::fs::read(path.join("language.json")).context("missing language.json")?;
+    let manifest: Manifest = serde_json::from_slice(&manifest).context("invalid language.json")?;
+
+    let exists = loaded.iter().any(|l| {
+        l.language_ids
+            .iter()
+            .any(|id| id.eq_ignore_ascii_case(&manifest.name))
+    });
+    ensure!(!exists, "language `{}` is already supported", manifest.name);
+
+    let symbol = manifest
+        .symbol
+        .unwrap_or_else(|| format!("tree_sitter_{}", dir_name.replace('-', "_")));
+    let language = load_library(&parser_path(path)?, &symbol)?;
+
+    tree_sitter::Parser::new()
+        .set_language(language)
+        .context("grammar was built for an incompatible tree-sitter version")?;
+
+    let scopes = std::fs::read_to_string(path.join("scopes.scm")).context("missing scopes.scm")?;
+    let hoverable = match std::fs::read_to_string(path.join("hoverable.scm")) {
+        Ok(query) => query,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+
+    let scope_query = tree_sitter::Query::new(language, &scopes).context("invalid scopes.scm")?;
+    tree_sitter::Query::new(language, &hoverable).context("invalid hoverable.scm")?;
+
+    let defined = defined_symbols(scope_query.capture_names());
+    let namespaces = match manifest.namespaces {
+        Some(namespaces) => {
+            let declared = namespaces
+                .iter()
+                .flatten()
+                .cloned()
+                .collect::<BTreeSet<_>>();
+            ensure!(
+                declared == defined,
+                "namespaces must list exactly the symbol kinds defined in scopes.scm: {defined:?}"
+            );
+            namespaces
+        }
+        None if defined.is_empty() => vec![],
+        None => vec![defined.into_iter().collect()],
+    };
+
+    let extensions = manifest
+        .extensions
+        .into_iter()
+        .map(|ext| leak(ext.trim_start_matches('.').to_owned()))
+        .collect::<Vec<_>>();
+
+    Ok(TSLanguageConfig {
+        language_ids: Box::leak(Box::new([leak(manifest.name)])),
+        file_extensions: extensions.leak(),
+        grammar: Grammar::Loaded(language),
+        scope_query: MemoizedQuery::new(leak(scopes)),
+        hoverable_query: MemoizedQuery::new(leak(hoverable)),
+        namespaces: leak_namespaces(namespaces),
+    })
+}
+
+fn parser_path(dir: &Path) -> Result<PathBuf> {
+    let native = dir.join("parser").with_extension(DLL_EXTENSION);
+    if native.exists() {
+        return Ok(native);
+    }
+
+    if dir.join("parser.wasm").exists() {
+        bail!("WebAssembly grammars aren't supported, build parser.{DLL_EXTENSION} instead");
+    }
+
+    bail!("missing parser.{DLL_EXTENSION}")
+}
+
+fn load_library(path: &Path, symbol: &str) -> Result<tree_sitter::Language> {
+    // SAFETY: loading the library runs its initializers. The grammar directory is configured by
+    // the operator, and holds code that is as trusted as bleep itself.
+    let library = unsafe { libloading::Library::new(path) }.context("failed to load parser")?;
+
+    // SAFETY: tree-sitter grammars export a function with this signature, `Language` is a
+    // transparent wrapper around the returned pointer.
+    let language = unsafe {
+        let grammar = library
+            .get::<unsafe extern "C" fn() -> tree_sitter::Language>(symbol.as_bytes())
+            .with_context(|| format!("parser doesn't export `{symbol}`"))?;
+        grammar()
+    };
+
+    // The parse tables of the grammar live in the library, which has to stay loaded for as long
+    // as bleep runs.
+    std::mem::forget(library);
+
+    Ok(language)
+}
+
+/// The symbol kinds of the definitions captured by a scope query.
+fn defined_symbols(capture_names: &[String]) -> BTreeSet<String> {
+    capture_names
+        .iter()
+        .filter_map(
+            |name| match name.split('.').collect::<Vec<_>>().as_slice() {
+                [_, "definition", symbol] => Some(symbol.to_string()),
+                _ => None,
+            },
+        )
+        .collect()
+}
+
+fn leak(s: String) -> &'static str {
+    Box::leak(s.into_boxed_str())
+}
+
+fn leak_namespaces(namespaces: Vec<Vec<String>>) -> NameSpaces {
+    namespaces
+        .into_iter()
+        .map(|ns| -> NameSpace { ns.into_iter().map(leak).collect::<Vec<_>>().leak() })
+        .collect::<Vec<_>>()
+        .leak()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_definition_kinds() {
+        let captures = [
+            "local.scope",
+            "local.definition.function",
+            "hoist.definition.type",
+            "local.definition",
+            "global.definition.function",
+            "local.reference",
+            "_keyword",
+        ]
+        .map(String::from);
+
+        assert_eq!(
+            defined_symbols(&captures),
+            BTreeSet::from(["function".to_owned(), "type".to_owned()])
+        );
+    }
+}
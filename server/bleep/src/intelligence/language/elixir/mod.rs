@@ -0,0 +1,55 @@
+use crate::intelligence::{Grammar, MemoizedQuery, TSLanguageConfig};
+
+pub static ELIXIR: TSLanguageConfig = TSLanguageConfig {
+    language_ids: &["Elixir"],
+    file_extensions: &["ex", "exs"],
+    grammar: Grammar::Builtin(tree_sitter_elixir::language),
+    scope_query: MemoizedQuery::new(include_str!("./scopes.scm")),
+    hoverable_query: MemoizedQuery::new(
+        r#"
+        [(identifier)
+         (alias)] @hoverable
+        "#,
+    ),
+    namespaces: &[
+        // modules
+        &["module"],
+        // functions
+        &["function"],
+        // variables
+        &["variable", "parameter"],
+    ],
+};
+
+#[cfg(test)]
+mod tests {
+    use crate::intelligence::language::test_utils::*;
+
+    #[test]
+    fn declarations() {
+        let src = r#"
+            defmodule Greeter do
+              def hello(name) do
+                greeting = "Hello"
+                join(greeting, name)
+              end
+
+              defp join(a, b) when is_binary(a), do: a <> " " <> b
+            end
+            "#;
+
+        assert_eq_defs(
+            src.as_bytes(),
+            "Elixir",
+            vec![
+                ("Greeter", "module"),
+                ("hello", "function"),
+                ("name", "parameter"),
+                ("greeting", "variable"),
+                ("join", "function"),
+                ("a", "parameter"),
+                ("b", "parameter"),
+            ],
+        );
+    }
+}
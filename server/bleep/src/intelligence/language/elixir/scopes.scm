@@ -0,0 +1,75 @@
+;; scopes
+[
+ (do_block)
+ (stab_clause)
+ (anonymous_function)
+ ] @local.scope
+
+;; function definitions are regular calls, they get a scope of their own so that their
+;; parameters don't leak into the module
+((call
+   target: (identifier) @_keyword) @local.scope
+ (#match? @_keyword "^(def|defp|defmacro|defmacrop)$"))
+
+;; defs
+
+;; defmodule Foo do
+((call
+   target: (identifier) @_keyword
+   (arguments
+     (alias) @local.definition.module))
+ (#match? @_keyword "^(defmodule|defprotocol)$"))
+
+;; def foo do
+;; def foo(a, b) do
+;; def foo(a, b) when a > b do
+((call
+   target: (identifier) @_keyword
+   (arguments
+     [(identifier) @hoist.definition.function
+      (call
+        target: (identifier) @hoist.definition.function)
+      (binary_operator
+        left: (call
+                target: (identifier) @hoist.definition.function)
+        operator: "when")]))
+ (#match? @_keyword "^(def|defp|defmacro|defmacrop)$"))
+
+;; def foo(a, b)
+((call
+   target: (identifier) @_keyword
+   (arguments
+     [(call
+        (arguments
+          (identifier) @local.definition.parameter))
+      (binary_operator
+        left: (call
+                (arguments
+                  (identifier) @local.definition.parameter))
+        operator: "when")]))
+ (#match? @_keyword "^(def|defp|defmacro|defmacrop)$"))
+
+;; x = _
+;; {x, y} = _
+;; [x, y] = _
+(binary_operator
+  left: [(identifier) @local.definition.variable
+         (tuple
+           (identifier) @local.definition.variable)
+         (list
+           (identifier) @local.definition.variable)]
+  operator: "=")
+
+;; fn x -> _ end
+;; {:ok, x} -> _
+(stab_clause
+  left: (arguments
+          [(identifier) @local.definition.variable
+           (tuple
+             (identifier) @local.definition.variable)]))
+
+;; refs
+
+;; any other occurrence of a name, definitions are skipped when building the graph
+(identifier) @local.reference
+(alias) @local.reference
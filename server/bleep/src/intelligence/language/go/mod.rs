@@ -1,9 +1,9 @@
-use crate::intelligence::{MemoizedQuery, TSLanguageConfig};
+use crate::intelligence::{Grammar, MemoizedQuery, TSLanguageConfig};
 
 pub static GO: TSLanguageConfig = TSLanguageConfig {
     language_ids: &["Go"],
     file_extensions: &["go"],
-    grammar: tree_sitter_go::language,
+    grammar: Grammar::Builtin(tree_sitter_go::language),
     scope_query: MemoizedQuery::new(include_str!("./scopes.scm")),
     hoverable_query: MemoizedQuery::new(
         r#"
@@ -1,9 +1,9 @@
-use crate::intelligence::{MemoizedQuery, TSLanguageConfig};
+use crate::intelligence::{Grammar, MemoizedQuery, TSLanguageConfig};
 
 pub static JAVA: TSLanguageConfig = TSLanguageConfig {
     language_ids: &["Java"],
     file_extensions: &["java"],
-    grammar: tree_sitter_java::language,
+    grammar: Grammar::Builtin(tree_sitter_java::language),
     scope_query: MemoizedQuery::new(include_str!("./scopes.scm")),
     hoverable_query: MemoizedQuery::new(
         r#"
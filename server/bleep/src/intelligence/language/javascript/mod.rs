@@ -1,9 +1,9 @@
-use crate::intelligence::{MemoizedQuery, TSLanguageConfig};
+use crate::intelligence::{Grammar, MemoizedQuery, TSLanguageConfig};
 
 pub static JAVASCRIPT: TSLanguageConfig = TSLanguageConfig {
     language_ids: &["JavaScript", "JSX"],
     file_extensions: &["js", "jsx"],
-    grammar: tree_sitter_javascript::language,
+    grammar: Grammar::Builtin(tree_sitter_javascript::language),
     scope_query: MemoizedQuery::new(include_str!("./scopes.scm")),
     hoverable_query: MemoizedQuery::new(
         r#"
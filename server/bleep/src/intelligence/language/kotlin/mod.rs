@@ -0,0 +1,71 @@
+use crate::intelligence::{Grammar, MemoizedQuery, TSLanguageConfig};
+
+pub static KOTLIN: TSLanguageConfig = TSLanguageConfig {
+    language_ids: &["Kotlin"],
+    file_extensions: &["kt", "kts"],
+    grammar: Grammar::Builtin(tree_sitter_kotlin::language),
+    scope_query: MemoizedQuery::new(include_str!("./scopes.scm")),
+    hoverable_query: MemoizedQuery::new(
+        r#"
+        [(simple_identifier)
+         (type_identifier)] @hoverable
+        "#,
+    ),
+    namespaces: &[
+        // types
+        &["class", "interface", "object", "type"],
+        // values
+        &["function", "variable", "parameter", "enumerator"],
+    ],
+};
+
+#[cfg(test)]
+mod tests {
+    use crate::intelligence::language::test_utils::*;
+
+    #[test]
+    fn declarations() {
+        let src = r#"
+            import kotlin.math.max
+
+            typealias Id = Int
+
+            interface Shape
+
+            enum class Color { RED }
+
+            class Counter(val start: Int) : Shape {
+                var count = start
+            }
+
+            object Registry
+
+            fun twice(f: (Int) -> Int, x: Int): Int {
+                val once = f(x)
+                for (i in 0..1) {}
+                return listOf(once).map { y -> f(y) }.first()
+            }
+            "#;
+
+        assert_eq_defs(
+            src.as_bytes(),
+            "Kotlin",
+            vec![
+                ("Id", "type"),
+                ("Shape", "interface"),
+                ("Color", "class"),
+                ("RED", "enumerator"),
+                ("Counter", "class"),
+                ("start", "parameter"),
+                ("count", "variable"),
+                ("Registry", "object"),
+                ("twice", "function"),
+                ("f", "parameter"),
+                ("x", "parameter"),
+                ("once", "variable"),
+                ("i", "variable"),
+                ("y", "parameter"),
+            ],
+        );
+    }
+}
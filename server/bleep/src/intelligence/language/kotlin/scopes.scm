@@ -0,0 +1,77 @@
+;; scopes
+[
+ (class_declaration)
+ (object_declaration)
+ (class_body)
+ (function_declaration)
+ (function_body)
+ (lambda_literal)
+ (for_statement)
+ (catch_block)
+ ] @local.scope
+
+;; defs
+
+;; class, interface and object names belong to the enclosing scope
+(class_declaration
+  "class"
+  (type_identifier) @hoist.definition.class)
+(class_declaration
+  "interface"
+  (type_identifier) @hoist.definition.interface)
+(object_declaration
+  (type_identifier) @hoist.definition.object)
+
+;; typealias Id = Int
+(type_alias
+  (type_identifier) @local.definition.type)
+
+;; fun f(...)
+(function_declaration
+  (simple_identifier) @hoist.definition.function)
+
+;; enum class Color { RED }
+(enum_entry
+  (simple_identifier) @local.definition.enumerator)
+
+;; val x = _
+;; var x = _
+(property_declaration
+  (variable_declaration
+    (simple_identifier) @local.definition.variable))
+
+;; fun f(x: Int)
+(parameter
+  (simple_identifier) @local.definition.parameter)
+
+;; class C(val x: Int)
+(class_parameter
+  (simple_identifier) @local.definition.parameter)
+
+;; { x -> _ }
+(lambda_parameters
+  (variable_declaration
+    (simple_identifier) @local.definition.parameter))
+
+;; for (x in xs)
+(for_statement
+  (variable_declaration
+    (simple_identifier) @local.definition.variable))
+
+;; catch (e: Exception)
+(catch_block
+  (simple_identifier) @local.definition.variable)
+
+;; imports
+
+;; import kotlin.math.max
+;;                  ^^^ is an import
+(import_header
+  (identifier
+    (simple_identifier) @local.import .))
+
+;; refs
+
+;; any other occurrence of a name, definitions are skipped when building the graph
+(simple_identifier) @local.reference
+(type_identifier) @local.reference
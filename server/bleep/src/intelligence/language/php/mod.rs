@@ -1,9 +1,9 @@
-use crate::intelligence::{MemoizedQuery, TSLanguageConfig};
+use crate::intelligence::{Grammar, MemoizedQuery, TSLanguageConfig};
 
 pub static PHP: TSLanguageConfig = TSLanguageConfig {
     language_ids: &["PHP"],
     file_extensions: &["php"],
-    grammar: tree_sitter_php::language,
+    grammar: Grammar::Builtin(tree_sitter_php::language),
     scope_query: MemoizedQuery::new(include_str!("./scopes.scm")),
     hoverable_query: MemoizedQuery::new(
         r#"
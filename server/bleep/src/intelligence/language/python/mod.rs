@@ -1,9 +1,9 @@
-use crate::intelligence::{MemoizedQuery, TSLanguageConfig};
+use crate::intelligence::{Grammar, MemoizedQuery, TSLanguageConfig};
 
 pub static PYTHON: TSLanguageConfig = TSLanguageConfig {
     language_ids: &["Python"],
     file_extensions: &["py"],
-    grammar: tree_sitter_python::language,
+    grammar: Grammar::Builtin(tree_sitter_python::language),
     scope_query: MemoizedQuery::new(include_str!("./scopes.scm")),
     hoverable_query: MemoizedQuery::new(
         r#"
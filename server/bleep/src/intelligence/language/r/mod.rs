@@ -1,9 +1,9 @@
-use crate::intelligence::{MemoizedQuery, TSLanguageConfig};
+use crate::intelligence::{Grammar, MemoizedQuery, TSLanguageConfig};
 
 pub static R: TSLanguageConfig = TSLanguageConfig {
     language_ids: &["R"],
     file_extensions: &["R"],
-    grammar: tree_sitter_r::language,
+    grammar: Grammar::Builtin(tree_sitter_r::language),
     scope_query: MemoizedQuery::new(include_str!("./scopes.scm")),
     hoverable_query: MemoizedQuery::new(
         r#"
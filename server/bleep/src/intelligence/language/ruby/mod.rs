@@ -1,9 +1,9 @@
-use crate::intelligence::{MemoizedQuery, TSLanguageConfig};
+use crate::intelligence::{Grammar, MemoizedQuery, TSLanguageConfig};
 
 pub static RUBY: TSLanguageConfig = TSLanguageConfig {
     language_ids: &["Ruby"],
     file_extensions: &["rb"],
-    grammar: tree_sitter_ruby::language,
+    grammar: Grammar::Builtin(tree_sitter_ruby::language),
     scope_query: MemoizedQuery::new(include_str!("./scopes.scm")),
     hoverable_query: MemoizedQuery::new(
         r#"
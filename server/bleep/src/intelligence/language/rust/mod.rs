@@ -1,9 +1,9 @@
-use crate::intelligence::{MemoizedQuery, TSLanguageConfig};
+use crate::intelligence::{Grammar, MemoizedQuery, TSLanguageConfig};
 
 pub static RUST: TSLanguageConfig = TSLanguageConfig {
     language_ids: &["Rust"],
     file_extensions: &["rs"],
-    grammar: tree_sitter_rust::language,
+    grammar: Grammar::Builtin(tree_sitter_rust::language),
     scope_query: MemoizedQuery::new(include_str!("./scopes.scm")),
     hoverable_query: MemoizedQuery::new(
         r#"
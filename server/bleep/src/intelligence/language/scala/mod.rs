@@ -0,0 +1,59 @@
+use crate::intelligence::{Grammar, MemoizedQuery, TSLanguageConfig};
+
+pub static SCALA: TSLanguageConfig = TSLanguageConfig {
+    language_ids: &["Scala"],
+    file_extensions: &["scala", "sc", "sbt"],
+    grammar: Grammar::Builtin(tree_sitter_scala::language),
+    scope_query: MemoizedQuery::new(include_str!("./scopes.scm")),
+    hoverable_query: MemoizedQuery::new(
+        r#"
+        [(identifier)
+         (type_identifier)] @hoverable
+        "#,
+    ),
+    namespaces: &[
+        // types
+        &["class", "object", "trait", "type"],
+        // values
+        &["function", "variable", "parameter"],
+    ],
+};
+
+#[cfg(test)]
+mod tests {
+    use crate::intelligence::language::test_utils::*;
+
+    #[test]
+    fn declarations() {
+        let src = r#"
+            object Main {
+              type Id = Int
+
+              class Counter(start: Int) {
+                var count = start
+              }
+
+              def twice(f: Int => Int, x: Int): Int = {
+                val once = f(x)
+                f(once)
+              }
+            }
+            "#;
+
+        assert_eq_defs(
+            src.as_bytes(),
+            "Scala",
+            vec![
+                ("Main", "object"),
+                ("Id", "type"),
+                ("Counter", "class"),
+                ("start", "parameter"),
+                ("count", "variable"),
+                ("twice", "function"),
+                ("f", "parameter"),
+                ("x", "parameter"),
+                ("once", "variable"),
+            ],
+        );
+    }
+}
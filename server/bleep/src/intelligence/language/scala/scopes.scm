@@ -0,0 +1,67 @@
+;; scopes
+[
+ (template_body)
+ (block)
+ (class_definition)
+ (object_definition)
+ (trait_definition)
+ (function_definition)
+ (lambda_expression)
+ (case_clause)
+ ] @local.scope
+
+;; defs
+
+;; class, object and trait names belong to the enclosing scope
+(class_definition
+  name: (identifier) @hoist.definition.class)
+(object_definition
+  name: (identifier) @hoist.definition.object)
+(trait_definition
+  name: (identifier) @hoist.definition.trait)
+
+;; type Id = Int
+(type_definition
+  name: (type_identifier) @local.definition.type)
+
+;; def f(...)
+(function_definition
+  name: (identifier) @hoist.definition.function)
+
+;; val x = _
+;; var x = _
+(val_definition
+  pattern: (identifier) @local.definition.variable)
+(var_definition
+  pattern: (identifier) @local.definition.variable)
+
+;; val (a, b) = _
+(val_definition
+  pattern: (tuple_pattern
+             (identifier) @local.definition.variable))
+
+;; def f(x: Int)
+(parameter
+  name: (identifier) @local.definition.parameter)
+
+;; class C(x: Int)
+(class_parameter
+  name: (identifier) @local.definition.parameter)
+
+;; x => _
+(lambda_expression
+  parameters: (identifier) @local.definition.parameter)
+
+;; (x, y) => _
+(binding
+  name: (identifier) @local.definition.parameter)
+
+;; case x => _
+(case_clause
+  pattern: (identifier) @local.definition.variable)
+
+;; refs
+
+;; any other occurrence of a name, definitions are skipped when building the graph
+(identifier) @local.reference
+(type_identifier) @local.reference
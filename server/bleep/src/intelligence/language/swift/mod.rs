@@ -0,0 +1,80 @@
+use crate::intelligence::{Grammar, MemoizedQuery, TSLanguageConfig};
+
+pub static SWIFT: TSLanguageConfig = TSLanguageConfig {
+    language_ids: &["Swift"],
+    file_extensions: &["swift"],
+    grammar: Grammar::Builtin(tree_sitter_swift::language),
+    scope_query: MemoizedQuery::new(include_str!("./scopes.scm")),
+    hoverable_query: MemoizedQuery::new(
+        r#"
+        [(simple_identifier)
+         (type_identifier)] @hoverable
+        "#,
+    ),
+    namespaces: &[
+        // types
+        &["class", "struct", "enum", "actor", "protocol", "type"],
+        // values
+        &["function", "variable", "parameter", "enumerator"],
+    ],
+};
+
+#[cfg(test)]
+mod tests {
+    use crate::intelligence::language::test_utils::*;
+
+    #[test]
+    fn declarations() {
+        let src = r#"
+            import Foundation
+
+            typealias Id = Int
+
+            protocol Shape {}
+
+            enum Color {
+                case red
+            }
+
+            struct Point: Shape {
+                var x: Int
+            }
+
+            class Counter {
+                let start: Int
+
+                init(start: Int) {
+                    self.start = start
+                }
+            }
+
+            func twice(_ f: (Int) -> Int, at x: Int) -> Int {
+                let once = f(x)
+                for i in 0..<1 {}
+                return [once].map { y in f(y) }[0]
+            }
+            "#;
+
+        assert_eq_defs(
+            src.as_bytes(),
+            "Swift",
+            vec![
+                ("Id", "type"),
+                ("Shape", "protocol"),
+                ("Color", "enum"),
+                ("red", "enumerator"),
+                ("Point", "struct"),
+                ("x", "variable"),
+                ("Counter", "class"),
+                ("start", "variable"),
+                ("start", "parameter"),
+                ("twice", "function"),
+                ("f", "parameter"),
+                ("x", "parameter"),
+                ("once", "variable"),
+                ("i", "variable"),
+                ("y", "parameter"),
+            ],
+        );
+    }
+}
@@ -0,0 +1,74 @@
+;; scopes
+[
+ (class_declaration)
+ (protocol_declaration)
+ (class_body)
+ (enum_class_body)
+ (protocol_body)
+ (function_declaration)
+ (function_body)
+ (lambda_literal)
+ (for_statement)
+ ] @local.scope
+
+;; defs
+
+;; type names belong to the enclosing scope, extensions don't define a name
+(class_declaration
+  declaration_kind: "class"
+  name: (type_identifier) @hoist.definition.class)
+(class_declaration
+  declaration_kind: "struct"
+  name: (type_identifier) @hoist.definition.struct)
+(class_declaration
+  declaration_kind: "enum"
+  name: (type_identifier) @hoist.definition.enum)
+(class_declaration
+  declaration_kind: "actor"
+  name: (type_identifier) @hoist.definition.actor)
+(protocol_declaration
+  name: (type_identifier) @hoist.definition.protocol)
+
+;; typealias Id = Int
+(typealias_declaration
+  name: (type_identifier) @local.definition.type)
+
+;; func f(...)
+(function_declaration
+  name: (simple_identifier) @hoist.definition.function)
+
+;; case red
+(enum_entry
+  name: (simple_identifier) @local.definition.enumerator)
+
+;; let x = _
+;; var x = _
+(property_declaration
+  name: (pattern
+          (simple_identifier) @local.definition.variable))
+
+;; func f(label x: Int), only `x` is a name in the body
+(parameter
+  name: (simple_identifier) @local.definition.parameter)
+
+;; { x in _ }
+(lambda_parameter
+  name: (simple_identifier) @local.definition.parameter)
+
+;; for x in xs
+(for_statement
+  item: (pattern
+          (simple_identifier) @local.definition.variable))
+
+;; imports
+
+;; import Foundation
+(import_declaration
+  (identifier
+    (simple_identifier) @local.import .))
+
+;; refs
+
+;; any other occurrence of a name, definitions are skipped when building the graph
+(simple_identifier) @local.reference
+(type_identifier) @local.reference
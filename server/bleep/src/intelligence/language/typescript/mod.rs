@@ -1,9 +1,9 @@
-use crate::intelligence::{MemoizedQuery, TSLanguageConfig};
+use crate::intelligence::{Grammar, MemoizedQuery, TSLanguageConfig};
 
 pub static TYPESCRIPT: TSLanguageConfig = TSLanguageConfig {
     language_ids: &["TypeScript", "TSX"],
     file_extensions: &["ts", "tsx"],
-    grammar: tree_sitter_typescript::language_tsx,
+    grammar: Grammar::Builtin(tree_sitter_typescript::language_tsx),
     scope_query: MemoizedQuery::new(include_str!("./scopes.scm")),
     hoverable_query: MemoizedQuery::new(
         r#"
@@ -0,0 +1,47 @@
+use crate::intelligence::{Grammar, MemoizedQuery, TSLanguageConfig};
+
+pub static YAML: TSLanguageConfig = TSLanguageConfig {
+    language_ids: &["YAML"],
+    file_extensions: &["yaml", "yml"],
+    grammar: Grammar::Builtin(tree_sitter_yaml::language),
+    scope_query: MemoizedQuery::new(include_str!("./scopes.scm")),
+    hoverable_query: MemoizedQuery::new(
+        r#"
+        (string_scalar) @hoverable
+        "#,
+    ),
+    namespaces: &[&["resource"]],
+};
+
+#[cfg(test)]
+mod tests {
+    use crate::intelligence::language::test_utils::*;
+
+    #[test]
+    fn declarations() {
+        let src = r#"
+apiVersion: v1
+kind: Service
+metadata:
+  name: api
+spec:
+  selector:
+    app: api
+---
+apiVersion: networking.k8s.io/v1
+kind: Ingress
+metadata:
+  name: public
+spec:
+  defaultBackend:
+    service:
+      name: api
+"#;
+
+        assert_eq_defs(
+            src.as_bytes(),
+            "YAML",
+            vec![("api", "resource"), ("public", "resource")],
+        );
+    }
+}
@@ -0,0 +1,32 @@
+;; there are no scopes, Kubernetes resources refer to each other across documents
+
+;; defs
+
+;; metadata:
+;;   name: api
+((block_mapping_pair
+   key: (flow_node) @_metadata
+   value: (block_node
+            (block_mapping
+              (block_mapping_pair
+                key: (flow_node) @_name
+                value: (flow_node
+                         (plain_scalar
+                           (string_scalar) @local.definition.resource))))))
+ (#eq? @_metadata "metadata")
+ (#eq? @_name "name"))
+
+;; refs
+
+;; keys that refer to other resources by name, like
+;;
+;; service:
+;;   name: api
+;; persistentVolumeClaim:
+;;   claimName: data
+((block_mapping_pair
+   key: (flow_node) @_key
+   value: (flow_node
+            (plain_scalar
+              (string_scalar) @local.reference)))
+ (#match? @_key "^(name|app|serviceName|claimName|secretName|configMapName|serviceAccountName)$"))
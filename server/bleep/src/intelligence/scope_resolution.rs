@@ -10,10 +10,13 @@ pub use import::LocalImport;
 pub use reference::Reference;
 pub use scope::{LocalScope, ScopeStack};
 
-use super::{NameSpaceMethods, TSLanguageConfig, ALL_LANGUAGES};
+use super::{all_languages, NameSpaceMethods, TSLanguageConfig};
 use crate::{symbol::Symbol, text_range::TextRange};
 
-use std::{collections::HashMap, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
 
 use petgraph::{graph::Graph, visit::EdgeRef, Direction};
 use serde::{Deserialize, Serialize};
@@ -112,7 +115,7 @@ pub struct ScopeGraph {
     // encompasses the entire file: the global scope.
     root_idx: NodeIndex,
 
-    /// An index into ALL_LANGUAGES which corresponds to the language for this graph
+    /// An index into `all_languages()` which corresponds to the language for this graph
     lang_id: usize,
 }
 
@@ -392,7 +395,7 @@ impl ScopeGraph {
     }
 
     pub fn symbols(&self) -> Vec<Symbol> {
-        let namespaces = ALL_LANGUAGES[self.lang_id].namespaces;
+        let namespaces = all_languages()[self.lang_id].namespaces;
         self.graph
             .node_weights()
             .filter_map(|weight| match weight {
@@ -411,7 +414,7 @@ impl ScopeGraph {
 
     // produce a stringified name of a def/ref's symbol
     pub fn symbol_name_of(&self, idx: NodeIndex) -> Option<&'static str> {
-        let namespaces = ALL_LANGUAGES[self.lang_id].namespaces;
+        let namespaces = all_languages()[self.lang_id].namespaces;
         match &self.graph[idx] {
             NodeKind::Def(d) => d.symbol_id.map(|s| s.name(namespaces)),
             NodeKind::Ref(r) => r.symbol_id.map(|s| s.name(namespaces)),
@@ -565,7 +568,7 @@ fn scope_res_generic(
     let mut cursor = QueryCursor::new();
     let captures = cursor.captures(query, root_node, src);
 
-    let lang_id = ALL_LANGUAGES
+    let lang_id = all_languages()
         .iter()
         .position(|l| l.language_ids == language.language_ids)
         .unwrap();
@@ -598,6 +601,7 @@ fn scope_res_generic(
     }
 
     // followed by defs
+    let mut def_ranges = HashSet::new();
     for LocalDefCapture {
         index,
         symbol,
@@ -609,6 +613,7 @@ fn scope_res_generic(
                 // if the symbol is present, is it one of the supported symbols for this language?
                 let symbol_id = symbol.and_then(|s| namespaces.symbol_id_of(s));
                 let local_def = LocalDef::new(*range, symbol_id);
+                def_ranges.insert(*range);
 
                 match scoping {
                     Scoping::Hoisted => scope_graph.insert_hoisted_def(local_def),
@@ -623,6 +628,12 @@ fn scope_res_generic(
     for LocalRefCapture { index, symbol } in local_ref_captures {
         if let Some(ranges) = capture_map.get(&index) {
             for range in ranges {
+                // grammars where definitions are plain calls or identifiers can't tell them
+                // apart from references, so a node that defines a name never refers to one
+                if def_ranges.contains(range) {
+                    continue;
+                }
+
                 // if the symbol is present, is it one of the supported symbols for this language?
                 let symbol_id = symbol.and_then(|s| namespaces.symbol_id_of(s));
                 let ref_ = Reference::new(*range, symbol_id);
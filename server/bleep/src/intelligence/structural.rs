@@ -0,0 +1,394 @@
+//! Search the syntax trees of indexed files, rather than their text.
+//!
+//! A search either uses one of a few patterns that work across the languages we parse, like
+//! calls to a function, or a raw tree-sitter query for a single language. Files are parsed on
+//! every search, so a search is bounded by [`MAX_FILES`] and stops at the requested number of
+//! matches.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use tree_sitter::{Node, QueryCursor};
+
+use super::{all_languages, Language, TSLanguage, TSLanguageConfig, TreeSitterFile};
+use crate::{
+    indexes::{File, Indexer},
+    repo::RepoRef,
+    text_range::TextRange,
+};
+
+/// The most matches a search returns.
+pub const MAX_MATCHES: usize = 100;
+
+/// The most files a search parses.
+pub const MAX_FILES: usize = 5000;
+
+/// The name of the capture that marks the matching nodes of a raw query.
+const MATCH_CAPTURE: &str = "match";
+
+/// Node kinds of function and method calls, across grammars.
+const CALL_KINDS: &[&str] = &[
+    "call_expression",
+    "call",
+    "method_invocation",
+    "invocation_expression",
+    "function_call_expression",
+    "member_call_expression",
+    "scoped_call_expression",
+];
+
+/// Node kinds of function and method definitions, across grammars.
+const FUNCTION_KINDS: &[&str] = &[
+    "function_item",
+    "function_declaration",
+    "function_definition",
+    "method_declaration",
+    "method_definition",
+    "method",
+    "singleton_method",
+    "constructor_declaration",
+    "local_function_statement",
+    "arrow_function",
+    "function_expression",
+    "func_literal",
+];
+
+#[derive(Serialize, Deserialize, schemars::JsonSchema, Debug, Clone, PartialEq)]
+#[serde(tag = "pattern", rename_all = "snake_case")]
+pub enum Pattern {
+    /// Functions and methods that call a function or method with this name, in any language.
+    Calls { name: String },
+
+    /// Rust structs and enums that derive a trait, like `Serialize`.
+    Derives { name: String },
+
+    /// A tree-sitter query for the grammar of `lang`. The nodes captured as `@match` are
+    /// returned, or every captured node if nothing is captured as `@match`.
+    Query { query: String, lang: String },
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Match {
+    pub relative_path: String,
+    pub lang: String,
+    /// The kind of the syntax node, like `function_item`
+    pub kind: String,
+    /// The name of the function or type, when the node has one
+    pub name: Option<String>,
+    pub range: TextRange,
+    /// The first line of the node
+    pub snippet: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Results {
+    pub matches: Vec<Match>,
+    pub files_searched: usize,
+    /// Whether the search stopped before searching every file, after finding enough matches or
+    /// parsing [`MAX_FILES`] files
+    pub truncated: bool,
+}
+
+/// A pattern, ready to be matched against files.
+pub struct Matcher {
+    pattern: Pattern,
+    languages: Vec<&'static TSLanguageConfig>,
+    query: Option<tree_sitter::Query>,
+}
+
+impl Pattern {
+    /// Check the pattern, and compile its query if it has one.
+    pub fn compile(self) -> Result<Matcher, String> {
+        let languages = match &self {
+            Self::Calls { name } | Self::Derives { name } if name.trim().is_empty() => {
+                return Err("`name` can't be empty".to_owned());
+            }
+            Self::Calls { .. } => all_languages().to_vec(),
+            Self::Derives { .. } => vec![supported("rust")?],
+            Self::Query { lang, .. } => vec![supported(lang)?],
+        };
+
+        let query = match &self {
+            Self::Query { query, .. } => Some(
+                tree_sitter::Query::new(languages[0].grammar.language(), query)
+                    .map_err(|err| format!("invalid query: {err}"))?,
+            ),
+            _ => None,
+        };
+
+        Ok(Matcher {
+            pattern: self,
+            languages,
+            query,
+        })
+    }
+}
+
+impl Matcher {
+    /// The language IDs of the files this can match, as the file index stores them.
+    pub fn language_ids(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.languages
+            .iter()
+            .flat_map(|language| language.language_ids.iter().copied())
+    }
+
+    /// The matches in a file. Files that can't be parsed have none.
+    pub fn find(&self, relative_path: &str, src: &str, lang_id: &str) -> Vec<Match> {
+        let Ok(file) = TreeSitterFile::try_build(src.as_bytes(), lang_id) else {
+            return vec![];
+        };
+
+        if !self
+            .languages
+            .iter()
+            .any(|l| std::ptr::eq(*l, file.language))
+        {
+            return vec![];
+        }
+
+        let root = file.tree.root_node();
+        let nodes = match &self.pattern {
+            Pattern::Calls { name } => callers(root, src, name),
+            Pattern::Derives { name } => deriving(root, src, name),
+            Pattern::Query { .. } => {
+                let query = self.query.as_ref().expect("query patterns are compiled");
+                captures(query, root, src)
+            }
+        };
+
+        nodes
+            .into_iter()
+            .map(|node| Match {
+                relative_path: relative_path.to_owned(),
+                lang: lang_id.to_owned(),
+                kind: node.kind().to_owned(),
+                name: node
+                    .child_by_field_name("name")
+                    .and_then(|name| name.utf8_text(src.as_bytes()).ok())
+                    .map(str::to_owned),
+                range: node.range().into(),
+                snippet: node
+                    .utf8_text(src.as_bytes())
+                    .unwrap_or_default()
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_owned(),
+            })
+            .collect()
+    }
+
+    /// Search the indexed files of a repository, optionally only those under `path_prefix`.
+    pub async fn search(
+        &self,
+        files: &Indexer<File>,
+        repo_ref: &RepoRef,
+        branch: Option<&str>,
+        path_prefix: Option<&str>,
+        limit: usize,
+    ) -> Results {
+        let mut results = Results::default();
+
+        let mut docs = files.by_repo(repo_ref, self.language_ids(), branch).await;
+        docs.retain(|doc| path_prefix.map_or(true, |prefix| doc.relative_path.starts_with(prefix)));
+        docs.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+
+        for doc in docs {
+            if results.files_searched >= MAX_FILES || results.matches.len() >= limit {
+                results.truncated = true;
+                break;
+            }
+
+            let Some(lang) = doc.lang.as_deref() else {
+                continue;
+            };
+
+            results.files_searched += 1;
+            results
+                .matches
+                .extend(self.find(&doc.relative_path, &doc.content, lang));
+        }
+
+        results.truncated |= results.matches.len() > limit;
+        results.matches.truncate(limit);
+        results
+    }
+}
+
+fn supported(lang: &str) -> Result<&'static TSLanguageConfig, String> {
+    match TSLanguage::from_id(lang) {
+        Language::Supported(language) => Ok(language),
+        Language::Unsupported => Err(format!("`{lang}` isn't a language we can parse")),
+    }
+}
+
+/// Every node of a tree, in order.
+fn walk(root: Node<'_>) -> Vec<Node<'_>> {
+    let mut nodes = vec![];
+    let mut cursor = root.walk();
+
+    'walk: loop {
+        nodes.push(cursor.node());
+
+        if cursor.goto_first_child() {
+            continue;
+        }
+
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                break 'walk;
+            }
+        }
+    }
+
+    nodes
+}
+
+/// The last identifier of a callee, like `parse` in `self.parser.parse` or `Parser::parse`.
+fn callee_name<'a>(callee: Node<'_>, src: &'a str) -> Option<&'a str> {
+    // Generic calls, like `parse::<T>()`, wrap the callee
+    let callee = match callee.kind() {
+        "generic_function" => callee.child_by_field_name("function")?,
+        _ => callee,
+    };
+
+    callee
+        .utf8_text(src.as_bytes())
+        .ok()?
+        .rsplit(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .find(|segment| !segment.is_empty())
+}
+
+/// The functions containing a call to `name`, or the calls themselves outside of functions.
+fn callers<'tree>(root: Node<'tree>, src: &str, name: &str) -> Vec<Node<'tree>> {
+    let mut seen = HashSet::new();
+
+    walk(root)
+        .into_iter()
+        .filter(|node| CALL_KINDS.contains(&node.kind()))
+        .filter(|call| {
+            ["function", "method", "name"]
+                .into_iter()
+                .find_map(|field| call.child_by_field_name(field))
+                .and_then(|callee| callee_name(callee, src))
+                == Some(name)
+        })
+        .map(|call| {
+            std::iter::successors(call.parent(), Node::parent)
+                .find(|node| FUNCTION_KINDS.contains(&node.kind()))
+                .unwrap_or(call)
+        })
+        .filter(|node| seen.insert(node.id()))
+        .collect()
+}
+
+/// The Rust structs and enums with a `#[derive(..)]` of `name`.
+fn deriving<'tree>(root: Node<'tree>, src: &str, name: &str) -> Vec<Node<'tree>> {
+    walk(root)
+        .into_iter()
+        .filter(|node| node.kind() == "attribute_item")
+        .filter(|attribute| {
+            let text = attribute.utf8_text(src.as_bytes()).unwrap_or_default();
+            text.strip_prefix("#[derive(").map_or(false, |derives| {
+                derives
+                    .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+                    .any(|derive| derive.rsplit("::").next() == Some(name))
+            })
+        })
+        .filter_map(|attribute| {
+            // Other attributes and comments can sit between the derive and the item
+            std::iter::successors(attribute.next_named_sibling(), Node::next_named_sibling)
+                .find(|node| !matches!(node.kind(), "attribute_item" | "line_comment"))
+        })
+        .filter(|item| matches!(item.kind(), "struct_item" | "enum_item" | "union_item"))
+        .collect()
+}
+
+fn captures<'tree>(query: &tree_sitter::Query, root: Node<'tree>, src: &str) -> Vec<Node<'tree>> {
+    let marked = query.capture_index_for_name(MATCH_CAPTURE);
+    let mut cursor = QueryCursor::new();
+    let mut seen = HashSet::new();
+
+    cursor
+        .matches(query, root, src.as_bytes())
+        .flat_map(|m| m.captures)
+        .filter(|capture| marked.map_or(true, |index| capture.index == index))
+        .map(|capture| capture.node)
+        .filter(|node| seen.insert(node.id()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRC: &str = r#"
+#[derive(Debug, serde::Serialize)]
+/// A point
+struct Point {
+    x: i32,
+}
+
+#[derive(Clone)]
+enum Shape {}
+
+fn draw(p: Point) {
+    render(p);
+    canvas.render::<Point>(p);
+}
+
+fn clear() {
+    canvas.clear();
+}
+"#;
+
+    fn names(pattern: Pattern) -> Vec<Option<String>> {
+        pattern
+            .compile()
+            .unwrap()
+            .find("src/lib.rs", SRC, "Rust")
+            .into_iter()
+            .map(|m| m.name)
+            .collect()
+    }
+
+    #[test]
+    fn finds_callers() {
+        assert_eq!(
+            names(Pattern::Calls {
+                name: "render".into()
+            }),
+            [Some("draw".to_owned())]
+        );
+    }
+
+    #[test]
+    fn finds_derives() {
+        assert_eq!(
+            names(Pattern::Derives {
+                name: "Serialize".into()
+            }),
+            [Some("Point".to_owned())]
+        );
+        assert!(names(Pattern::Derives {
+            name: "Default".into()
+        })
+        .is_empty());
+    }
+
+    #[test]
+    fn runs_queries() {
+        let query = Pattern::Query {
+            query: "(function_item name: (identifier) @name (#eq? @name \"clear\")) @match".into(),
+            lang: "rust".into(),
+        };
+        assert_eq!(names(query), [Some("clear".to_owned())]);
+
+        let invalid = Pattern::Query {
+            query: "(function_item".into(),
+            lang: "rust".into(),
+        };
+        assert!(invalid.compile().is_err());
+    }
+}
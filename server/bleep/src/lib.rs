@@ -58,10 +58,14 @@ mod commits;
 mod config;
 mod db;
 mod env;
+mod fetch;
 mod llm_gateway;
+mod metrics;
+mod notebook;
 mod remotes;
 mod repo;
 mod scraper;
+mod storage;
 mod webserver;
 
 mod ee;
@@ -76,9 +80,11 @@ pub mod snippet;
 pub mod state;
 pub mod symbol;
 pub mod text_range;
+#[cfg(feature = "tui")]
+pub mod tui;
 pub mod user;
 
-pub use config::{default_parallelism, minimum_parallelism, Configuration};
+pub use config::{default_parallelism, minimum_parallelism, Configuration, VectorQuantization};
 pub use env::Environment;
 
 const LOG_ENV_VAR: &str = "BLOOP_LOG";
@@ -122,6 +128,15 @@ pub struct Application {
 
     /// Analytics backend -- may be unintialized
     pub analytics: Option<Arc<analytics::RudderHub>>,
+
+    /// Answers in flight, for other clients to follow
+    live_answers: webserver::answer::live::LiveAnswers,
+
+    /// Sign-ins with GitLab that were started, but haven't come back yet
+    gitlab_logins: remotes::gitlab::PendingLogins,
+
+    /// Sessions of users signed in to a cloud instance, which tokens act through
+    sessions: webserver::middleware::Sessions,
 }
 
 impl Application {
@@ -143,6 +158,10 @@ impl Application {
         let config = Arc::new(config);
         debug!(?config, "effective configuration");
 
+        if let Some(ref dir) = config.grammar_dir {
+            intelligence::load_grammars(dir);
+        }
+
         // Load repositories
         let repo_pool = config.source.initialize_pool()?;
 
@@ -196,12 +215,29 @@ impl Application {
             }
         };
 
+        let credentials = config
+            .source
+            .load_state_or("credentials", remotes::Backends::default())?;
+
+        // Configured credentials take over from any that were stored
+        if let Some(ref token) = config.gitlab_token {
+            credentials.set_gitlab(remotes::gitlab::State::with_auth(
+                config.gitlab_url(),
+                remotes::gitlab::Auth::Token(token.clone()),
+            ));
+        }
+
+        if let Some(auth) = config.bitbucket_auth() {
+            credentials.set_bitbucket(remotes::bitbucket::State::with_auth(
+                config.bitbucket_url(),
+                auth,
+            ));
+        }
+
         Ok(Self {
             sync_queue: SyncQueue::start(config.clone()),
             cookie_key: config.source.initialize_cookie_key()?,
-            credentials: config
-                .source
-                .load_state_or("credentials", remotes::Backends::default())?,
+            credentials,
             user_profiles: config.source.load_or_default("user_profiles")?,
             sql,
             indexes,
@@ -210,6 +246,9 @@ impl Application {
             semantic,
             config,
             env,
+            live_answers: Default::default(),
+            gitlab_logins: Default::default(),
+            sessions: Default::default(),
         })
     }
 
@@ -267,9 +306,22 @@ impl Application {
         LOGGER_INSTALLED.set(true).unwrap();
     }
 
+    /// Print the migrations that starting would apply to the database.
+    pub async fn print_migration_plan(config: &Configuration) -> Result<()> {
+        db::print_plan(config).await
+    }
+
     pub async fn run(self) -> Result<()> {
         Self::install_logging(&self.config);
 
+        if let Err(err) = background::jobs::recover(&self).await {
+            error!(?err, "failed to recover interrupted sync jobs");
+        }
+
+        if let Err(err) = background::reindex::recover(&self).await {
+            error!(?err, "failed to mark interrupted reindex campaigns");
+        }
+
         let mut joins = tokio::task::JoinSet::new();
 
         if self.config.index_only {
@@ -306,6 +358,9 @@ impl Application {
     }
 
     fn track_query(&self, user: &User, event: &analytics::QueryEvent) {
+        #[cfg(feature = "tui")]
+        tui::record_query(event);
+
         if let Some(analytics) = self.analytics.as_ref() {
             analytics.track_query(user, event.clone());
         }
@@ -343,6 +398,7 @@ impl Application {
                     } => Some(User::Desktop {
                         access_token: token.clone(),
                         login: user,
+                        workspace_id: None,
                         crab: Arc::new(move || Ok(gh.client()?)),
                     }),
                     _ => None,
@@ -412,7 +468,9 @@ impl FromRef<Application> for axum_extra::extract::cookie::Key {
 }
 
 fn tracing_subscribe(config: &Configuration) -> bool {
-    let env_filter_layer = fmt::layer().with_filter(EnvFilter::from_env(LOG_ENV_VAR));
+    // The dashboard owns the terminal, and shows the warnings itself
+    let env_filter_layer =
+        (!config.tui).then(|| fmt::layer().with_filter(EnvFilter::from_env(LOG_ENV_VAR)));
     let sentry_layer = sentry_layer();
     let log_writer_layer = (!config.disable_log_write).then(|| {
         let file_appender = tracing_appender::rolling::daily(config.log_dir(), "bloop.log");
@@ -436,11 +494,17 @@ fn tracing_subscribe(config: &Configuration) -> bool {
     let console_subscriber_layer: Option<Box<dyn tracing_subscriber::Layer<_> + Send + Sync>> =
         None;
 
+    #[cfg(feature = "tui")]
+    let tui_layer = config.tui.then(tui::log_layer);
+    #[cfg(not(feature = "tui"))]
+    let tui_layer: Option<Box<dyn tracing_subscriber::Layer<_> + Send + Sync>> = None;
+
     tracing_subscriber::registry()
         .with(log_writer_layer)
         .with(env_filter_layer)
         .with(sentry_layer)
         .with(console_subscriber_layer)
+        .with(tui_layer)
         .try_init()
         .is_ok()
 }
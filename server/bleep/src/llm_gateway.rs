@@ -1,14 +1,33 @@
 //! A Rust-friendly interface to Bloop's LLM Gateway service.
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, bail};
 use axum::http::StatusCode;
 use futures::{Stream, StreamExt};
+use once_cell::sync::Lazy;
 use reqwest_eventsource::EventSource;
 use tracing::{debug, error, warn};
 
 use self::api::FunctionCall;
+use crate::metrics;
+
+mod limiter;
+mod openai;
+
+pub use openai::Endpoint;
+
+/// A single connection pool for all gateway requests, so that connections are reused.
+///
+/// Setting up a TLS connection takes a few round trips, which users would otherwise wait for
+/// before the first token of every answer.
+static HTTP: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .pool_idle_timeout(Duration::from_secs(90))
+        .tcp_keepalive(Duration::from_secs(30))
+        .build()
+        .expect("failed to build LLM gateway HTTP client")
+});
 
 pub mod api {
     use std::collections::HashMap;
@@ -210,6 +229,49 @@ impl From<&api::Message> for tiktoken_rs::ChatCompletionRequestMessage {
     }
 }
 
+/// The tokens of a chat completion.
+///
+/// Streamed completions don't report their usage, so both sides are counted with the tokenizer of
+/// the model instead. Function definitions are counted as the JSON they are sent as.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+}
+
+impl Usage {
+    pub fn count(
+        tokenizer: &str,
+        messages: &[api::Message],
+        functions: Option<&[api::Function]>,
+        completion: &str,
+    ) -> anyhow::Result<Self> {
+        let bpe = tiktoken_rs::get_bpe_from_model(tokenizer)?;
+
+        let mut prompt_tokens = tiktoken_rs::num_tokens_from_messages(
+            tokenizer,
+            &messages.iter().map(Into::into).collect::<Vec<_>>(),
+        )?;
+        if let Some(functions) = functions {
+            prompt_tokens += bpe
+                .encode_ordinary(&serde_json::to_string(functions)?)
+                .len();
+        }
+
+        Ok(Self {
+            prompt_tokens,
+            completion_tokens: bpe.encode_ordinary(completion).len(),
+        })
+    }
+
+    /// The cost in USD, at prices per 1,000 prompt and completion tokens.
+    pub fn cost(&self, prompt_price: f64, completion_price: f64) -> f64 {
+        (self.prompt_tokens as f64 * prompt_price
+            + self.completion_tokens as f64 * completion_price)
+            / 1000.0
+    }
+}
+
 enum ChatError {
     BadRequest(String),
     TooManyRequests(String),
@@ -231,12 +293,21 @@ pub struct Client {
     pub model: Option<String>,
     pub session_reference_id: Option<String>,
     pub quota_gated: bool,
+
+    /// Requests are queued fairly between users when the provider is at capacity
+    pub user: Option<String>,
+
+    /// Requests time out at this point, including the time to stream the response
+    pub deadline: Option<Instant>,
+
+    /// An OpenAI-compatible API that requests go to, instead of the answer API
+    pub endpoint: Option<Endpoint>,
 }
 
 impl Client {
     pub fn new(base_url: &str) -> Self {
         Self {
-            http: reqwest::Client::new(),
+            http: HTTP.clone(),
             base_url: base_url.to_owned(),
             max_retries: 5,
 
@@ -249,6 +320,9 @@ impl Client {
             model: None,
             session_reference_id: None,
             quota_gated: false,
+            user: None,
+            deadline: None,
+            endpoint: None,
         }
     }
 
@@ -300,6 +374,26 @@ impl Client {
         self
     }
 
+    pub fn user(mut self, user: impl Into<Option<String>>) -> Self {
+        self.user = user.into();
+        self
+    }
+
+    pub fn deadline(mut self, deadline: impl Into<Option<Instant>>) -> Self {
+        self.deadline = deadline.into();
+        self
+    }
+
+    pub fn endpoint(mut self, endpoint: impl Into<Option<Endpoint>>) -> Self {
+        self.endpoint = endpoint.into();
+        self
+    }
+
+    /// Whether requests go to the answer API, rather than an OpenAI-compatible one.
+    pub fn is_gateway(&self) -> bool {
+        self.endpoint.is_none()
+    }
+
     pub async fn is_compatible(
         &self,
         version: semver::Version,
@@ -350,10 +444,35 @@ impl Client {
         const INITIAL_DELAY: Duration = Duration::from_millis(100);
         const SCALE_FACTOR: f32 = 1.5;
 
+        let limiter = limiter::for_provider(self.provider);
+        let user = self.user.as_deref().unwrap_or_default();
+
         let mut delay = INITIAL_DELAY;
         for _ in 0..self.max_retries {
-            match self.chat_stream_oneshot(messages, functions).await {
+            if matches!(self.deadline, Some(deadline) if deadline <= Instant::now()) {
+                bail!("request deadline exceeded");
+            }
+
+            let permit = limiter.acquire(user).await;
+            let started = Instant::now();
+            let result = self.chat_stream_oneshot(messages, functions).await;
+
+            let outcome = match &result {
+                Ok(_) => "ok",
+                Err(ChatError::TooManyRequests(_)) => "throttled",
+                Err(ChatError::BadRequest(_)) => "bad_request",
+                Err(ChatError::Other(_)) => "error",
+            };
+            metrics::LLM_REQUESTS.with_label_values(&[outcome]).inc();
+            metrics::LLM_REQUEST_SECONDS
+                .with_label_values(&[outcome])
+                .observe(started.elapsed().as_secs_f64());
+
+            match result {
                 Err(ChatError::TooManyRequests(_)) => {
+                    permit.throttled();
+                    drop(permit);
+
                     warn!(?delay, "too many LLM requests, retrying with delay...");
                     tokio::time::sleep(delay).await;
                     delay = Duration::from_millis((delay.as_millis() as f32 * SCALE_FACTOR) as u64);
@@ -372,7 +491,10 @@ impl Client {
                     error!("LLM request failed due to unknown reason: {e:?}");
                     return Err(e);
                 }
-                Ok(stream) => return Ok(stream),
+                Ok(stream) => {
+                    permit.opened();
+                    return Ok(permit.hold(stream));
+                }
             }
         }
 
@@ -387,29 +509,16 @@ impl Client {
     ) -> Result<impl Stream<Item = anyhow::Result<String>>, ChatError> {
         let mut event_source = Box::pin(
             EventSource::new({
-                let mut builder = self.http.post(format!("{}/v2/q", self.base_url));
+                let mut builder = match &self.endpoint {
+                    Some(endpoint) => endpoint.request(self, messages, functions),
+                    None => self.gateway_request(messages, functions),
+                };
 
-                if let Some(bearer) = &self.bearer_token {
-                    builder = builder.bearer_auth(bearer);
+                if let Some(deadline) = self.deadline {
+                    builder = builder.timeout(deadline.saturating_duration_since(Instant::now()));
                 }
 
-                builder.json(&api::Request {
-                    messages: api::Messages {
-                        messages: messages.to_owned(),
-                    },
-                    functions: functions.map(|funcs| api::Functions {
-                        functions: funcs.to_owned(),
-                    }),
-                    max_tokens: self.max_tokens,
-                    temperature: self.temperature,
-                    presence_penalty: self.presence_penalty,
-                    frequency_penalty: self.frequency_penalty,
-                    provider: self.provider,
-                    model: self.model.clone(),
-                    extra_stop_sequences: vec![],
-                    session_reference_id: self.session_reference_id.clone(),
-                    quota_gated: self.quota_gated,
-                })
+                builder
             })
             // We don't have a `Stream` body so this can't fail.
             .expect("couldn't clone requestbuilder")
@@ -453,7 +562,7 @@ impl Client {
             }
         }
 
-        Ok(event_source
+        let events = event_source
             .filter_map(|result| async move {
                 match result {
                     Ok(reqwest_eventsource::Event::Message(msg)) => Some(Ok(msg.data)),
@@ -463,8 +572,45 @@ impl Client {
                 }
             })
             .map(|result| match result {
-                Ok(s) => Ok(serde_json::from_str::<api::Result>(&s)??),
+                Ok(data) => Ok(data),
                 Err(e) => bail!("event source error {e:?}"),
-            }))
+            });
+
+        Ok(match &self.endpoint {
+            Some(endpoint) => endpoint.read(events, functions.is_some()),
+            None => events
+                .map(|data| Ok(serde_json::from_str::<api::Result>(&data?)??))
+                .boxed(),
+        })
+    }
+
+    fn gateway_request(
+        &self,
+        messages: &[api::Message],
+        functions: Option<&[api::Function]>,
+    ) -> reqwest::RequestBuilder {
+        let mut builder = self.http.post(format!("{}/v2/q", self.base_url));
+
+        if let Some(bearer) = &self.bearer_token {
+            builder = builder.bearer_auth(bearer);
+        }
+
+        builder.json(&api::Request {
+            messages: api::Messages {
+                messages: messages.to_owned(),
+            },
+            functions: functions.map(|funcs| api::Functions {
+                functions: funcs.to_owned(),
+            }),
+            max_tokens: self.max_tokens,
+            temperature: self.temperature,
+            presence_penalty: self.presence_penalty,
+            frequency_penalty: self.frequency_penalty,
+            provider: self.provider,
+            model: self.model.clone(),
+            extra_stop_sequences: vec![],
+            session_reference_id: self.session_reference_id.clone(),
+            quota_gated: self.quota_gated,
+        })
     }
 }
@@ -0,0 +1,282 @@
+//! Adaptive concurrency limits for requests to the LLM providers.
+//!
+//! Every provider has a window of requests that may be in flight at once. The window grows by one
+//! request for every window of fast responses, and shrinks when the provider rate-limits us or
+//! slows down (AIMD). Requests over the window are queued, and served round-robin across users, so
+//! that a single user's burst does not starve everybody else.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use futures::{Stream, StreamExt};
+use once_cell::sync::Lazy;
+use tokio::sync::oneshot;
+use tracing::debug;
+
+use super::api::Provider;
+
+const INITIAL_LIMIT: f64 = 16.0;
+const MIN_LIMIT: f64 = 1.0;
+const MAX_LIMIT: f64 = 128.0;
+
+/// Responses that take longer than this to start are a sign that the provider is overloaded.
+const LATENCY_TARGET: Duration = Duration::from_secs(10);
+
+static OPENAI: Lazy<Limiter> = Lazy::new(Limiter::new);
+static ANTHROPIC: Lazy<Limiter> = Lazy::new(Limiter::new);
+
+pub(super) fn for_provider(provider: Provider) -> &'static Limiter {
+    match provider {
+        Provider::OpenAi => &OPENAI,
+        Provider::Anthropic => &ANTHROPIC,
+    }
+}
+
+pub(super) struct Limiter {
+    state: Mutex<State>,
+}
+
+struct State {
+    window: Window,
+    in_flight: usize,
+    queue: FairQueue<oneshot::Sender<Permit>>,
+}
+
+impl Limiter {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                window: Window::new(),
+                in_flight: 0,
+                queue: FairQueue::default(),
+            }),
+        }
+    }
+
+    /// Wait for a free slot in the window, behind the requests already queued.
+    pub(super) async fn acquire(&'static self, user: &str) -> Permit {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            if state.queue.is_empty() && state.in_flight < state.window.capacity() {
+                state.in_flight += 1;
+                return Permit::new(self);
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            state.queue.push(user, sender);
+            debug!(
+                queued = state.queue.len(),
+                in_flight = state.in_flight,
+                "LLM request queued"
+            );
+            receiver
+        };
+
+        self.dispatch();
+
+        match receiver.await {
+            Ok(permit) => permit,
+            // The sender is only dropped after sending a permit.
+            Err(_) => unreachable!("LLM request queue was dropped"),
+        }
+    }
+
+    /// Hand out free slots to queued requests.
+    fn dispatch(&'static self) {
+        loop {
+            let sender = {
+                let mut state = self.state.lock().unwrap();
+                if state.in_flight >= state.window.capacity() {
+                    return;
+                }
+
+                let Some(sender) = state.queue.pop() else {
+                    return;
+                };
+
+                state.in_flight += 1;
+                sender
+            };
+
+            // If the request was cancelled while queued, dropping its permit frees the slot again.
+            _ = sender.send(Permit::new(self));
+        }
+    }
+
+    fn release(&'static self) {
+        self.state.lock().unwrap().in_flight -= 1;
+        self.dispatch();
+    }
+}
+
+/// A slot in the window of a provider, released on drop.
+pub(super) struct Permit {
+    limiter: &'static Limiter,
+    start: Instant,
+}
+
+impl Permit {
+    fn new(limiter: &'static Limiter) -> Self {
+        Self {
+            limiter,
+            start: Instant::now(),
+        }
+    }
+
+    /// Record that the provider started responding to this request.
+    pub(super) fn opened(&self) {
+        let latency = self.start.elapsed();
+        self.limiter
+            .state
+            .lock()
+            .unwrap()
+            .window
+            .on_response(latency);
+        self.limiter.dispatch();
+    }
+
+    /// Record that the provider rate-limited this request.
+    pub(super) fn throttled(&self) {
+        let mut state = self.limiter.state.lock().unwrap();
+        state.window.on_throttled();
+        debug!(limit = state.window.limit, "LLM request was throttled");
+    }
+
+    /// Keep this slot until the response stream is dropped.
+    pub(super) fn hold<S: Stream>(self, stream: S) -> impl Stream<Item = S::Item> {
+        stream.map(move |item| {
+            let _permit = &self;
+            item
+        })
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.limiter.release();
+    }
+}
+
+/// The number of requests that may be in flight, adjusted with AIMD.
+struct Window {
+    limit: f64,
+}
+
+impl Window {
+    fn new() -> Self {
+        Self {
+            limit: INITIAL_LIMIT,
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.limit.floor() as usize
+    }
+
+    fn on_response(&mut self, latency: Duration) {
+        self.limit = if latency > LATENCY_TARGET {
+            self.limit * 0.9
+        } else {
+            self.limit + 1.0 / self.limit
+        }
+        .clamp(MIN_LIMIT, MAX_LIMIT);
+    }
+
+    fn on_throttled(&mut self) {
+        self.limit = (self.limit * 0.5).max(MIN_LIMIT);
+    }
+}
+
+/// A queue that takes turns between users, serving each user's items in order.
+struct FairQueue<T> {
+    turns: VecDeque<String>,
+    waiting: HashMap<String, VecDeque<T>>,
+}
+
+impl<T> Default for FairQueue<T> {
+    fn default() -> Self {
+        Self {
+            turns: VecDeque::new(),
+            waiting: HashMap::new(),
+        }
+    }
+}
+
+impl<T> FairQueue<T> {
+    fn push(&mut self, user: &str, item: T) {
+        let items = self.waiting.entry(user.to_owned()).or_default();
+        if items.is_empty() {
+            self.turns.push_back(user.to_owned());
+        }
+        items.push_back(item);
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let user = self.turns.pop_front()?;
+        let items = self.waiting.get_mut(&user)?;
+        let item = items.pop_front();
+
+        if items.is_empty() {
+            self.waiting.remove(&user);
+        } else {
+            self.turns.push_back(user);
+        }
+
+        item
+    }
+
+    fn len(&self) -> usize {
+        self.waiting.values().map(VecDeque::len).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.turns.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_increases_additively_and_decreases_multiplicatively() {
+        let mut window = Window::new();
+
+        // It takes a full window of responses to grow the window by one.
+        for _ in 0..=INITIAL_LIMIT as usize {
+            window.on_response(Duration::from_secs(1));
+        }
+        assert_eq!(window.capacity(), INITIAL_LIMIT as usize + 1);
+
+        window.on_throttled();
+        assert_eq!(window.capacity(), 8);
+
+        window.on_response(LATENCY_TARGET * 2);
+        assert_eq!(window.capacity(), 7);
+
+        for _ in 0..10 {
+            window.on_throttled();
+        }
+        assert_eq!(window.capacity(), MIN_LIMIT as usize);
+    }
+
+    #[test]
+    fn queue_takes_turns_between_users() {
+        let mut queue = FairQueue::default();
+        queue.push("alice", 1);
+        queue.push("alice", 2);
+        queue.push("alice", 3);
+        queue.push("bob", 4);
+        queue.push("carol", 5);
+        queue.push("bob", 6);
+
+        assert_eq!(queue.len(), 6);
+
+        let order = std::iter::from_fn(|| queue.pop()).collect::<Vec<_>>();
+        assert_eq!(order, [1, 4, 5, 2, 6, 3]);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,247 @@
+//! Chat completions from an OpenAI-compatible API, like llama.cpp, vLLM or Ollama, instead of the
+//! answer API.
+//!
+//! Models that can't call functions are told about them in the prompt, and answer with the call as
+//! JSON. The answer is read back into the same function call that a model calling functions would
+//! stream, so the agent can't tell the difference.
+
+use anyhow::{anyhow, Context};
+use futures::{stream::BoxStream, FutureExt, Stream, StreamExt, TryStreamExt};
+use reqwest::{RequestBuilder, Url};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+
+use super::api::{self, FunctionCall};
+
+/// An OpenAI-compatible API, with the model that every request goes to.
+#[derive(Clone, Debug)]
+pub struct Endpoint {
+    pub url: Url,
+    pub model: Option<String>,
+    pub api_key: Option<SecretString>,
+    /// Describe functions in the prompt, instead of passing them to the API
+    pub prompted_tools: bool,
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<&'a str>,
+    messages: Vec<api::Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    functions: Option<&'a [api::Function]>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+}
+
+#[derive(Deserialize)]
+struct Chunk {
+    choices: Vec<Choice>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    delta: Delta,
+}
+
+#[derive(Deserialize, Default)]
+struct Delta {
+    content: Option<String>,
+    function_call: Option<FunctionCallDelta>,
+}
+
+#[derive(Deserialize)]
+struct FunctionCallDelta {
+    name: Option<String>,
+    #[serde(default)]
+    arguments: String,
+}
+
+impl Endpoint {
+    pub(super) fn request(
+        &self,
+        client: &super::Client,
+        messages: &[api::Message],
+        functions: Option<&[api::Function]>,
+    ) -> RequestBuilder {
+        // Joining onto a base without a trailing slash would replace its last segment
+        let mut base = self.url.clone();
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        let url = base.join("chat/completions").expect("valid URL");
+
+        let (messages, functions) = match functions {
+            Some(functions) if self.prompted_tools => (prompted(messages, functions), None),
+            functions => (messages.to_owned(), functions),
+        };
+
+        let mut builder = client.http.post(url).json(&Request {
+            model: self.model.as_deref(),
+            messages,
+            functions,
+            stream: true,
+            max_tokens: client.max_tokens,
+            temperature: client.temperature,
+            presence_penalty: client.presence_penalty,
+            frequency_penalty: client.frequency_penalty,
+        });
+
+        if let Some(key) = &self.api_key {
+            builder = builder.bearer_auth(key.expose_secret());
+        }
+
+        builder
+    }
+
+    /// Turn the streamed chunks into what the answer API streams: text, or function call JSON.
+    pub(super) fn read(
+        &self,
+        events: impl Stream<Item = anyhow::Result<String>> + Send + 'static,
+        functions: bool,
+    ) -> BoxStream<'static, anyhow::Result<String>> {
+        let deltas = events
+            .try_filter(|data| futures::future::ready(data.as_str() != "[DONE]"))
+            .map(|data| {
+                let chunk = serde_json::from_str::<Chunk>(&data?)?;
+                Ok(chunk
+                    .choices
+                    .into_iter()
+                    .next()
+                    .map(|choice| choice.delta)
+                    .unwrap_or_default())
+            });
+
+        if functions && self.prompted_tools {
+            // The call can only be read once the whole of it is there
+            let call = deltas
+                .try_fold(String::new(), |mut text, delta| async move {
+                    text += delta.content.as_deref().unwrap_or_default();
+                    Ok(text)
+                })
+                .map(|text: anyhow::Result<String>| -> anyhow::Result<String> {
+                    Ok(serde_json::to_string(&prompted_call(&text?)?)?)
+                });
+
+            return call.into_stream().boxed();
+        }
+
+        deltas
+            .try_filter_map(|delta| async move {
+                if let Some(call) = delta.function_call {
+                    let call = FunctionCall {
+                        name: call.name,
+                        arguments: call.arguments,
+                    };
+                    return Ok(Some(serde_json::to_string(&call)?));
+                }
+
+                Ok(delta.content.filter(|content| !content.is_empty()))
+            })
+            .boxed()
+    }
+}
+
+/// The messages for a model that can't call functions, with the functions described up front,
+/// and earlier calls and their results as plain text.
+fn prompted(messages: &[api::Message], functions: &[api::Function]) -> Vec<api::Message> {
+    let functions = serde_json::to_string_pretty(functions).expect("functions are serializable");
+    let instructions = format!(
+        "You can call these functions:\n\n{functions}\n\n\
+         To call one, respond with a single JSON object with the `name` of the function and its \
+         `arguments`, like {{\"name\": \"none\", \"arguments\": {{}}}}. Respond with nothing else."
+    );
+
+    let mut prompted = vec![api::Message::system(&instructions)];
+    prompted.extend(messages.iter().map(|message| match message {
+        api::Message::FunctionCall { function_call, .. } => {
+            api::Message::assistant(&serde_json::json!(function_call).to_string())
+        }
+        api::Message::FunctionReturn { name, content, .. } => {
+            api::Message::user(&format!("`{name}` returned:\n\n{content}"))
+        }
+        api::Message::PlainText { .. } => message.clone(),
+    }));
+
+    prompted
+}
+
+/// Read a function call out of a model's answer, which may have text around the JSON.
+fn prompted_call(text: &str) -> anyhow::Result<FunctionCall> {
+    let json = text
+        .find('{')
+        .zip(text.rfind('}'))
+        .filter(|(start, end)| start < end)
+        .map(|(start, end)| &text[start..=end])
+        .ok_or_else(|| anyhow!("the model didn't call a function: {text}"))?;
+
+    let call = serde_json::from_str::<serde_json::Value>(json)
+        .context("the model called a function with malformed JSON")?;
+
+    let name = call["name"]
+        .as_str()
+        .context("the model called a function without a name")?;
+
+    // Arguments are a JSON string with function calling, which some models imitate
+    let arguments = match &call["arguments"] {
+        serde_json::Value::String(arguments) => arguments.clone(),
+        serde_json::Value::Null => "{}".to_owned(),
+        arguments => arguments.to_string(),
+    };
+
+    Ok(FunctionCall {
+        name: Some(name.to_owned()),
+        arguments,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_prompted_calls() {
+        let call = prompted_call(
+            "Sure! {\"name\": \"code\", \"arguments\": {\"query\": \"retry {backoff}\"}} Done.",
+        )
+        .unwrap();
+        assert_eq!(call.name.as_deref(), Some("code"));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&call.arguments).unwrap(),
+            serde_json::json!({ "query": "retry {backoff}" })
+        );
+
+        let call = prompted_call(r#"{"name": "none", "arguments": "{\"paths\": [0]}"}"#).unwrap();
+        assert_eq!(call.arguments, r#"{"paths": [0]}"#);
+
+        assert!(prompted_call("I would search for the code.").is_err());
+    }
+
+    #[test]
+    fn prompts_with_calls_as_text() {
+        let messages = prompted(
+            &[
+                api::Message::user("where is foo"),
+                api::Message::function_call(&FunctionCall {
+                    name: Some("code".to_owned()),
+                    arguments: "{}".to_owned(),
+                }),
+                api::Message::function_return("code", "src/foo.rs"),
+            ],
+            &[],
+        );
+
+        assert_eq!(messages.len(), 4);
+        assert!(matches!(&messages[0], api::Message::PlainText { role, .. } if role == "system"));
+        assert!(
+            matches!(&messages[3], api::Message::PlainText { role, content } if role == "user" && content.contains("src/foo.rs"))
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! Operational metrics, served in the Prometheus text format when `--metrics` is set.
+//!
+//! Unlike [analytics](crate::analytics) events, which follow single users and conversations,
+//! these are aggregates for monitoring an instance. They are kept in memory, and start over
+//! when the server restarts.
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    exponential_buckets, register_histogram_vec, register_int_counter_vec, HistogramVec,
+    IntCounterVec, TextEncoder,
+};
+
+/// The latency of API requests, until the response headers are sent.
+pub(crate) static HTTP_REQUEST_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "bleep_http_request_duration_seconds",
+        "Latency of API requests, until the response headers are sent",
+        &["method", "route", "status"]
+    )
+    .expect("metrics are registered once")
+});
+
+/// How long each step of the agent takes, by the action it runs.
+pub(crate) static AGENT_STEP_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "bleep_agent_step_duration_seconds",
+        "Duration of agent steps, including the model calls they make",
+        &["action"],
+        exponential_buckets(0.1, 2.0, 12).expect("buckets are valid")
+    )
+    .expect("metrics are registered once")
+});
+
+/// LLM requests, by how they ended: `ok`, `throttled`, `bad_request` or `error`.
+pub(crate) static LLM_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "bleep_llm_requests_total",
+        "Requests to the LLM, including retries",
+        &["outcome"]
+    )
+    .expect("metrics are registered once")
+});
+
+/// The time until the LLM starts streaming a response.
+pub(crate) static LLM_REQUEST_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "bleep_llm_request_duration_seconds",
+        "Time until the LLM starts streaming a response",
+        &["outcome"],
+        exponential_buckets(0.05, 2.0, 12).expect("buckets are valid")
+    )
+    .expect("metrics are registered once")
+});
+
+/// Tokens sent to and generated by the LLM, by model and `prompt` or `completion`.
+pub(crate) static LLM_TOKENS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "bleep_llm_tokens_total",
+        "Tokens used by model calls",
+        &["model", "kind"]
+    )
+    .expect("metrics are registered once")
+});
+
+/// How long syncs of repositories take, by how their job ended.
+pub(crate) static INDEX_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "bleep_index_duration_seconds",
+        "Duration of repository syncs, from fetching to indexing",
+        &["state"],
+        exponential_buckets(1.0, 2.0, 14).expect("buckets are valid")
+    )
+    .expect("metrics are registered once")
+});
+
+/// How long Qdrant takes to answer searches, by the kind of search.
+pub(crate) static QDRANT_QUERY_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "bleep_qdrant_query_duration_seconds",
+        "Duration of searches of the semantic index",
+        &["query"],
+        exponential_buckets(0.001, 2.0, 14).expect("buckets are valid")
+    )
+    .expect("metrics are registered once")
+});
+
+/// Every metric that has been used, in the Prometheus text format.
+pub(crate) fn render() -> anyhow::Result<String> {
+    Ok(TextEncoder::new().encode_to_string(&prometheus::gather())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_used_metrics() {
+        // Labels of their own, as other tests share the registry
+        LLM_TOKENS
+            .with_label_values(&["test-model", "prompt"])
+            .inc_by(12);
+        INDEX_SECONDS.with_label_values(&["test"]).observe(3.0);
+
+        let text = render().unwrap();
+        assert!(text.contains(r#"bleep_llm_tokens_total{kind="prompt",model="test-model"} 12"#));
+        assert!(text.contains(r#"bleep_index_duration_seconds_count{state="test"} 1"#));
+        assert!(text.contains("# TYPE bleep_index_duration_seconds histogram"));
+    }
+}
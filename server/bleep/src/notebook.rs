@@ -0,0 +1,217 @@
+//! Jupyter notebooks, indexed as the text of their cells.
+//!
+//! The JSON of a notebook is mostly escaped source and outputs, like plots encoded as base64.
+//! Notebooks are indexed as their cells instead, each starting with a marker that names the cell,
+//! so that search results, chunks and citations point at cells:
+//!
+//! ```text
+//! # %% cell 0 [python]
+//! import pandas as pd
+//!
+//! # %% cell 1 [markdown]
+//! ## Loading the data
+//! ```
+//!
+//! Outputs are skipped.
+
+use std::{fmt::Write, path::Path};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::repo::iterator::MAX_FILE_LEN;
+
+/// Notebooks are larger than their cells, so they are allowed to be larger than other files.
+pub const MAX_LEN: u64 = 16 * MAX_FILE_LEN;
+
+static MARKER: Lazy<Regex> = Lazy::new(|| Regex::new(r"^# %% cell (\d+) \[([^\]]+)\]$").unwrap());
+
+pub fn is_notebook(path: impl AsRef<Path>) -> bool {
+    path.as_ref()
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("ipynb"))
+}
+
+#[derive(Deserialize)]
+struct Notebook {
+    #[serde(default)]
+    cells: Vec<RawCell>,
+    #[serde(default)]
+    metadata: Metadata,
+}
+
+#[derive(Deserialize, Default)]
+struct Metadata {
+    language_info: Option<LanguageInfo>,
+    kernelspec: Option<KernelSpec>,
+}
+
+#[derive(Deserialize)]
+struct LanguageInfo {
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct KernelSpec {
+    language: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawCell {
+    cell_type: String,
+    #[serde(default)]
+    source: Source,
+}
+
+/// Cell sources are either a single string, or a list of lines.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Source {
+    Text(String),
+    Lines(Vec<String>),
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Self::Text(String::new())
+    }
+}
+
+/// Render the cells of a notebook, or `None` if it isn't valid notebook JSON.
+pub fn render(src: &str) -> Option<String> {
+    let notebook: Notebook = serde_json::from_str(src).ok()?;
+    let language = notebook
+        .metadata
+        .language_info
+        .and_then(|l| l.name)
+        .or_else(|| notebook.metadata.kernelspec.and_then(|k| k.language))
+        .unwrap_or_else(|| "python".to_owned())
+        .to_lowercase();
+
+    let mut out = String::new();
+    for (index, cell) in notebook.cells.into_iter().enumerate() {
+        let lang = match cell.cell_type.as_str() {
+            "code" => language.as_str(),
+            "markdown" => "markdown",
+            _ => continue,
+        };
+
+        if !out.is_empty() {
+            out.push('\n');
+        }
+
+        _ = writeln!(out, "# %% cell {index} [{lang}]");
+        match cell.source {
+            Source::Text(text) => out.push_str(&text),
+            Source::Lines(lines) => lines.iter().for_each(|l| out.push_str(l)),
+        }
+
+        if !out.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+
+    Some(out)
+}
+
+/// A cell of a rendered notebook.
+#[derive(Debug, PartialEq)]
+pub struct Cell<'a> {
+    pub index: usize,
+    pub lang: &'a str,
+    /// The text of the cell, starting with its marker
+    pub text: &'a str,
+    pub start_byte: usize,
+    pub start_line: usize,
+}
+
+/// Split a rendered notebook into its cells.
+pub fn cells(rendered: &str) -> Vec<Cell<'_>> {
+    let mut cells: Vec<Cell<'_>> = vec![];
+    let mut byte = 0;
+
+    for (line_number, line) in rendered.split_inclusive('\n').enumerate() {
+        let marker = MARKER.captures(line.trim_end_matches('\n'));
+        if let Some((index, lang)) = marker.and_then(|c| Some((c[1].parse().ok()?, c.get(2)?))) {
+            cells.push(Cell {
+                index,
+                lang: lang.as_str(),
+                text: "",
+                start_byte: byte,
+                start_line: line_number,
+            });
+        }
+
+        byte += line.len();
+        if let Some(cell) = cells.last_mut() {
+            cell.text = &rendered[cell.start_byte..byte];
+        }
+    }
+
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTEBOOK: &str = r##"{
+        "cells": [
+            {"cell_type": "markdown", "metadata": {}, "source": ["# Loading\n", "the data"]},
+            {
+                "cell_type": "code",
+                "execution_count": 1,
+                "metadata": {},
+                "outputs": [{"output_type": "display_data", "data": {"image/png": "iVBORw0KG"}}],
+                "source": "import pandas as pd\ndf = pd.read_csv('data.csv')"
+            },
+            {"cell_type": "raw", "metadata": {}, "source": "ignored"},
+            {"cell_type": "code", "metadata": {}, "outputs": [], "source": []}
+        ],
+        "metadata": {"language_info": {"name": "python"}},
+        "nbformat": 4,
+        "nbformat_minor": 5
+    }"##;
+
+    #[test]
+    fn renders_cells_without_outputs() {
+        let rendered = render(NOTEBOOK).unwrap();
+        assert_eq!(
+            rendered,
+            "# %% cell 0 [markdown]\n\
+             # Loading\n\
+             the data\n\
+             \n\
+             # %% cell 1 [python]\n\
+             import pandas as pd\n\
+             df = pd.read_csv('data.csv')\n\
+             \n\
+             # %% cell 3 [python]\n"
+        );
+
+        assert!(render("{\"not\": [\"a notebook\"]}").unwrap().is_empty());
+        assert!(render("not json").is_none());
+    }
+
+    #[test]
+    fn splits_rendered_cells() {
+        let rendered = render(NOTEBOOK).unwrap();
+        let cells = cells(&rendered);
+
+        assert_eq!(
+            cells.iter().map(|c| (c.index, c.lang)).collect::<Vec<_>>(),
+            [(0, "markdown"), (1, "python"), (3, "python")]
+        );
+
+        let code = &cells[1];
+        assert_eq!(code.start_line, 4);
+        assert!(rendered[code.start_byte..].starts_with(code.text));
+        assert_eq!(
+            code.text,
+            "# %% cell 1 [python]\nimport pandas as pd\ndf = pd.read_csv('data.csv')\n\n"
+        );
+
+        assert!(super::cells("{\"cells\": []}").is_empty());
+    }
+}
@@ -1,8 +1,22 @@
+mod compression;
+mod disk;
+mod freshness;
+mod health;
 mod logrotate;
 mod remotes;
+mod retention;
+mod suggestions;
+mod warmup;
 
+use compression::*;
+use disk::*;
+use freshness::*;
+use health::*;
 use logrotate::*;
 pub(crate) use remotes::*;
+use retention::*;
+use suggestions::*;
+use warmup::*;
 
 use crate::Application;
 
@@ -26,6 +40,15 @@ pub(crate) fn start_background_jobs(app: Application) {
     }
 
     single_threaded_executor(&app, sync_github_status);
+    single_threaded_executor(&app, sync_gitlab_status);
+    single_threaded_executor(&app, sync_bitbucket_status);
     single_threaded_executor(&app, check_repo_updates);
     single_threaded_executor(&app, log_and_branch_rotate);
+    single_threaded_executor(&app, enforce_retention);
+    single_threaded_executor(&app, suggest_workspaces);
+    single_threaded_executor(&app, warm_llm_connections);
+    single_threaded_executor(&app, compress_conversations);
+    single_threaded_executor(&app, check_freshness);
+    single_threaded_executor(&app, watch_disk_usage);
+    single_threaded_executor(&app, report_repo_health);
 }
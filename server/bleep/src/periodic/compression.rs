@@ -0,0 +1,29 @@
+use tracing::{error, info};
+
+use crate::{webserver::answer::conversations, Application};
+
+/// Compress conversations that were stored before exchanges were compressed, and index those
+/// stored before the full-text index.
+///
+/// This runs once on startup. The freed pages stay in the database file until an admin vacuums it.
+pub(crate) async fn compress_conversations(app: Application) {
+    match conversations::index_stored(&app.sql).await {
+        Ok(0) => {}
+        Ok(indexed) => info!(indexed, "indexed stored conversations"),
+        Err(err) => error!(?err, "failed to index stored conversations"),
+    }
+
+    let compressed = match conversations::compress_stored(&app.sql).await {
+        Ok(compressed) => compressed,
+        Err(err) => {
+            error!(?err, "failed to compress stored conversations");
+            return;
+        }
+    };
+
+    if compressed == 0 {
+        return;
+    }
+
+    info!(compressed, "compressed stored conversations");
+}
@@ -0,0 +1,68 @@
+use std::collections::HashSet;
+
+use tracing::{debug, error, warn};
+
+use crate::{storage, Application};
+
+const KINDS: [&str; 2] = ["low_disk_space", "storage_limit"];
+
+/// Raise instance-wide alerts when disk space runs low, or bloop uses more of it than allowed.
+///
+/// Runs on startup and every 30 minutes thereafter.
+pub(crate) async fn watch_disk_usage(app: Application) {
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(30 * 60));
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = check_disk_usage(&app).await {
+            error!(?err, "failed to check disk usage");
+        }
+    }
+}
+
+async fn check_disk_usage(app: &Application) -> anyhow::Result<()> {
+    let report = storage::report(app).await?;
+    let warning_kinds = report
+        .warnings
+        .iter()
+        .map(|w| w.kind)
+        .collect::<HashSet<_>>();
+
+    for kind in KINDS.into_iter().filter(|k| !warning_kinds.contains(k)) {
+        sqlx::query!(
+            "UPDATE notifications SET resolved_at = datetime('now')
+            WHERE workspace_id IS NULL AND kind = ? AND resolved_at IS NULL",
+            kind,
+        )
+        .execute(&*app.sql)
+        .await?;
+    }
+
+    for warning in report.warnings {
+        let raised = sqlx::query!(
+            "INSERT INTO notifications (kind, message)
+            SELECT ?1, ?2
+            WHERE NOT EXISTS (
+                SELECT 1 FROM notifications
+                WHERE workspace_id IS NULL AND kind = ?1 AND resolved_at IS NULL
+            )",
+            warning.kind,
+            warning.message,
+        )
+        .execute(&*app.sql)
+        .await?
+        .rows_affected();
+
+        if raised > 0 {
+            warn!(kind = warning.kind, "{}", warning.message);
+        }
+    }
+
+    debug!(
+        total_bytes = report.total_bytes,
+        available_bytes = report.available_bytes,
+        "checked disk usage"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,212 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use tracing::{debug, error};
+
+use crate::{
+    repo::{Backend, RepoRef},
+    webserver::workspace::DEFAULT_STALE_AFTER_DAYS,
+    Application,
+};
+
+/// Check how far the indexes of workspace repositories are behind upstream, and alert workspace
+/// members about repositories that have gone stale.
+///
+/// Runs on startup and every hour thereafter.
+pub(crate) async fn check_freshness(app: Application) {
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600));
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = update_freshness(&app).await {
+            error!(?err, "failed to check repository freshness");
+        }
+
+        if let Err(err) = notify_stale(&app).await {
+            error!(?err, "failed to raise stale repository alerts");
+        }
+    }
+}
+
+/// The relevant parts of a GitHub commit comparison.
+#[derive(Deserialize)]
+struct Comparison {
+    ahead_by: i64,
+    commits: Vec<ComparedCommit>,
+}
+
+#[derive(Deserialize)]
+struct ComparedCommit {
+    commit: CommitDetails,
+}
+
+#[derive(Deserialize)]
+struct CommitDetails {
+    committer: Option<Signature>,
+}
+
+#[derive(Deserialize)]
+struct Signature {
+    date: Option<DateTime<Utc>>,
+}
+
+impl Comparison {
+    fn oldest_commit_at(&self) -> Option<DateTime<Utc>> {
+        self.commits
+            .iter()
+            .filter_map(|c| c.commit.committer.as_ref()?.date)
+            .min()
+    }
+}
+
+async fn update_freshness(app: &Application) -> anyhow::Result<()> {
+    let Some(gh) = app.credentials.github() else {
+        return Ok(());
+    };
+
+    let client = gh.client()?;
+    let repos = sqlx::query!("SELECT DISTINCT repo_ref FROM workspace_repos")
+        .fetch_all(&*app.sql)
+        .await?;
+
+    for row in repos {
+        let Ok(repo_ref) = row.repo_ref.parse::<RepoRef>() else {
+            continue;
+        };
+
+        if repo_ref.backend() != Backend::Github {
+            continue;
+        }
+
+        let Some(indexed_commit) = app
+            .repo_pool
+            .read_async(&repo_ref, |_, repo| repo.indexed_commit.clone())
+            .await
+            .flatten()
+        else {
+            continue;
+        };
+
+        let Some(default_branch) = gh
+            .repositories
+            .iter()
+            .find(|r| r.full_name.as_deref() == Some(repo_ref.name()))
+            .and_then(|r| r.default_branch.clone())
+        else {
+            continue;
+        };
+
+        let route = format!(
+            "/repos/{}/compare/{indexed_commit}...{default_branch}",
+            repo_ref.name()
+        );
+        let comparison = match client.get::<Comparison, _, ()>(route, None).await {
+            Ok(comparison) => comparison,
+            Err(err) => {
+                debug!(?err, %repo_ref, "failed to compare indexed commit with upstream");
+                continue;
+            }
+        };
+
+        let oldest_unindexed_at = comparison.oldest_commit_at().map(|at| at.naive_utc());
+        sqlx::query!(
+            "INSERT INTO repo_freshness (repo_ref, behind_commits, oldest_unindexed_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT (repo_ref) DO UPDATE SET
+                behind_commits = excluded.behind_commits,
+                oldest_unindexed_at = excluded.oldest_unindexed_at,
+                checked_at = excluded.checked_at",
+            row.repo_ref,
+            comparison.ahead_by,
+            oldest_unindexed_at,
+        )
+        .execute(&*app.sql)
+        .await?;
+    }
+
+    sqlx::query!(
+        "DELETE FROM repo_freshness
+        WHERE repo_ref NOT IN (SELECT repo_ref FROM workspace_repos)"
+    )
+    .execute(&*app.sql)
+    .await?;
+
+    Ok(())
+}
+
+/// Raise a notification for every repository that went stale in a workspace, and resolve the
+/// notifications of repositories that were brought up to date.
+async fn notify_stale(app: &Application) -> anyhow::Result<()> {
+    let resolved = sqlx::query!(
+        "UPDATE notifications SET resolved_at = datetime('now')
+        WHERE kind = 'stale_repo' AND resolved_at IS NULL AND NOT EXISTS (
+            SELECT 1
+            FROM repo_freshness f
+            INNER JOIN workspace_repos r ON r.repo_ref = f.repo_ref
+            INNER JOIN workspaces w ON w.id = r.workspace_id
+            WHERE w.id = notifications.workspace_id
+                AND f.repo_ref = notifications.repo_ref
+                AND julianday('now') - julianday(f.oldest_unindexed_at)
+                    >= COALESCE(w.stale_after_days, ?)
+        )",
+        DEFAULT_STALE_AFTER_DAYS,
+    )
+    .execute(&*app.sql)
+    .await?
+    .rows_affected();
+
+    let raised = sqlx::query!(
+        "INSERT INTO notifications (workspace_id, kind, repo_ref, message)
+        SELECT w.id, 'stale_repo', f.repo_ref,
+            printf('%s is %d commits behind upstream, the oldest from %d days ago',
+                f.repo_ref, f.behind_commits,
+                CAST(julianday('now') - julianday(f.oldest_unindexed_at) AS INTEGER))
+        FROM repo_freshness f
+        INNER JOIN workspace_repos r ON r.repo_ref = f.repo_ref
+        INNER JOIN workspaces w ON w.id = r.workspace_id
+        WHERE julianday('now') - julianday(f.oldest_unindexed_at)
+                >= COALESCE(w.stale_after_days, ?)
+            AND w.deleted_at IS NULL
+            AND NOT EXISTS (
+                SELECT 1 FROM notifications n
+                WHERE n.workspace_id = w.id
+                    AND n.kind = 'stale_repo'
+                    AND n.repo_ref = f.repo_ref
+                    AND n.resolved_at IS NULL
+            )",
+        DEFAULT_STALE_AFTER_DAYS,
+    )
+    .execute(&*app.sql)
+    .await?
+    .rows_affected();
+
+    debug!(raised, resolved, "updated stale repository alerts");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_oldest_unindexed_commit() {
+        let comparison: Comparison = serde_json::from_value(serde_json::json!({
+            "ahead_by": 3,
+            "commits": [
+                { "commit": { "committer": { "date": "2023-10-02T10:00:00Z" } } },
+                { "commit": { "committer": { "date": "2023-10-01T10:00:00Z" } } },
+                { "commit": { "committer": null } },
+            ],
+        }))
+        .unwrap();
+
+        assert_eq!(
+            comparison.oldest_commit_at().unwrap().to_rfc3339(),
+            "2023-10-01T10:00:00+00:00"
+        );
+
+        let up_to_date: Comparison =
+            serde_json::from_value(serde_json::json!({ "ahead_by": 0, "commits": [] })).unwrap();
+        assert!(up_to_date.oldest_commit_at().is_none());
+    }
+}
@@ -0,0 +1,116 @@
+use tracing::{debug, error, warn};
+
+use crate::{agent::prompts, llm_gateway, repo::health::HealthReport, repo::RepoRef, Application};
+
+/// How many reports we keep per repository.
+const MAX_REPORTS: i64 = 30;
+
+/// Write a health report for every indexed repository whose index changed since its last report.
+///
+/// Runs on startup and every day thereafter.
+pub(crate) async fn report_repo_health(app: Application) {
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(24 * 3600));
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = update_health_reports(&app).await {
+            error!(?err, "failed to update repository health reports");
+        }
+    }
+}
+
+async fn update_health_reports(app: &Application) -> anyhow::Result<()> {
+    let mut repos = vec![];
+    app.repo_pool
+        .scan_async(|repo_ref, repo| {
+            if let Some(commit) = &repo.indexed_commit {
+                repos.push((repo_ref.clone(), commit.clone()));
+            }
+        })
+        .await;
+
+    for (repo_ref, indexed_commit) in repos {
+        let repo_str = repo_ref.to_string();
+        let reported_commit = sqlx::query_scalar!(
+            "SELECT indexed_commit FROM repo_health_reports \
+             WHERE repo_ref = ? \
+             ORDER BY created_at DESC, id DESC \
+             LIMIT 1",
+            repo_str,
+        )
+        .fetch_optional(&*app.sql)
+        .await?
+        .flatten();
+
+        if reported_commit.as_deref() == Some(indexed_commit.as_str()) {
+            continue;
+        }
+
+        debug!(%repo_ref, "writing health report");
+        let report = HealthReport::for_repo(&app.indexes.file, &repo_ref).await;
+        if report.files == 0 {
+            continue;
+        }
+
+        let report_json = serde_json::to_string(&report)?;
+        let summary = match summarize(app, &repo_ref, &report_json).await {
+            Ok(summary) => Some(summary),
+            Err(err) => {
+                warn!(?err, %repo_ref, "failed to summarize health report");
+                None
+            }
+        };
+
+        let mut tx = app.sql.begin().await?;
+        sqlx::query!(
+            "INSERT INTO repo_health_reports (repo_ref, indexed_commit, report, summary) \
+             VALUES (?, ?, ?, ?)",
+            repo_str,
+            indexed_commit,
+            report_json,
+            summary,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query!(
+            "DELETE FROM repo_health_reports \
+             WHERE repo_ref = ? AND id NOT IN ( \
+                 SELECT id FROM repo_health_reports \
+                 WHERE repo_ref = ? \
+                 ORDER BY created_at DESC, id DESC \
+                 LIMIT ? \
+             )",
+            repo_str,
+            repo_str,
+            MAX_REPORTS,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
+
+async fn summarize(
+    app: &Application,
+    repo_ref: &RepoRef,
+    report_json: &str,
+) -> anyhow::Result<String> {
+    let llm_gateway = app
+        .user()
+        .await
+        .llm_gateway(app)
+        .await?
+        .model("gpt-3.5-turbo-16k-0613")
+        .temperature(0.0);
+
+    let messages = &[llm_gateway::api::Message::system(
+        &prompts::repo_health_summary_prompt(&repo_ref.display_name(), report_json),
+    )];
+
+    let summary = llm_gateway.chat(messages, None).await?;
+    Ok(summary.trim().to_owned())
+}
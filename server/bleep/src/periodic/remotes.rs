@@ -94,6 +94,88 @@ pub(crate) async fn update_repo_list(app: &Application) {
     }
 }
 
+pub(crate) async fn sync_gitlab_status(app: Application) {
+    const POLL_PERIOD: Duration = POLL_INTERVAL_MINUTE[0];
+
+    loop {
+        refresh_gitlab_token(&app).await;
+        update_gitlab_repo_list(&app).await;
+        sleep_systime(POLL_PERIOD).await;
+    }
+}
+
+/// Refresh the OAuth token of GitLab before it expires, signing out if it was revoked.
+async fn refresh_gitlab_token(app: &Application) {
+    let Some(gl) = app.credentials.gitlab() else {
+        return;
+    };
+
+    match gl.expiry() {
+        Some(expiry) if expiry < Utc::now() + chrono::Duration::minutes(10) => {}
+        _ => return,
+    }
+
+    match gl.refresh(&app.config).await {
+        Ok(refreshed) => {
+            app.credentials.set_gitlab(refreshed);
+            info!("GitLab access token refreshed");
+        }
+        Err(remotes::RemoteError::PermissionDenied) => {
+            warn!("GitLab refresh token was revoked; signing out");
+            app.credentials.remove(&Backend::Gitlab);
+        }
+        Err(err) => {
+            error!(?err, "failed to refresh GitLab access token");
+            return;
+        }
+    }
+
+    if let Err(err) = app.credentials.store() {
+        error!(?err, "failed to save GitLab credentials");
+    }
+}
+
+pub(crate) async fn update_gitlab_repo_list(app: &Application) {
+    if let Some(gl) = app.credentials.gitlab() {
+        let projects = match gl.current_repo_list().await {
+            Ok(projects) => projects,
+            Err(err) => {
+                debug!(?err, "failed to update GitLab project list");
+                return;
+            }
+        };
+
+        app.credentials.set_gitlab(gl.update_repositories(projects));
+    }
+}
+
+pub(crate) async fn sync_bitbucket_status(app: Application) {
+    const POLL_PERIOD: Duration = POLL_INTERVAL_MINUTE[0];
+
+    loop {
+        update_bitbucket_repo_list(&app).await;
+        sleep_systime(POLL_PERIOD).await;
+    }
+}
+
+/// Refresh the repositories on Bitbucket, signing out if the credentials were revoked.
+pub(crate) async fn update_bitbucket_repo_list(app: &Application) {
+    let Some(bb) = app.credentials.bitbucket() else {
+        return;
+    };
+
+    match bb.current_repo_list().await {
+        Ok(repos) => app.credentials.set_bitbucket(bb.update_repositories(repos)),
+        Err(remotes::RemoteError::PermissionDenied) => {
+            warn!("Bitbucket credentials were revoked; removing them");
+            if app.credentials.remove(&Backend::Bitbucket).is_some() {
+                app.credentials.store().unwrap();
+            }
+        }
+        Err(err) => debug!(?err, "failed to update Bitbucket repo list"),
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 struct RefreshedAccessToken {
     access_token: String,
@@ -235,7 +317,7 @@ pub(crate) async fn validate_github_credentials(app: &Application) {
 }
 
 pub(crate) async fn check_repo_updates(app: Application) {
-    while app.credentials.github().is_none() {
+    while app.credentials.is_empty() {
         sleep_systime(Duration::from_millis(100)).await
     }
 
@@ -0,0 +1,216 @@
+use sqlx::SqlitePool;
+use tracing::{debug, error};
+
+use crate::{agent::response_cache::RETENTION_DAYS, webserver::workspace};
+
+/// Delete conversations that are older than the retention period of their workspace, and
+/// conversations and workspaces that were deleted longer ago than they can be restored.
+///
+/// Runs on startup and every hour thereafter
+pub(crate) async fn enforce_retention(app: crate::Application) {
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600));
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = prune_conversations(&app).await {
+            error!(?err, "failed to enforce conversation retention");
+        }
+    }
+}
+
+async fn prune_conversations(app: &crate::Application) -> anyhow::Result<()> {
+    let deleted = delete_expired(&app.sql).await?;
+    debug!(deleted, "pruned expired conversations");
+
+    let trash_days = app.config.conversation_trash_days as i64;
+    let purged = sqlx::query!(
+        "DELETE FROM conversations
+        WHERE deleted_at < strftime('%s', 'now') - ? * 86400",
+        trash_days,
+    )
+    .execute(&*app.sql)
+    .await?
+    .rows_affected();
+
+    debug!(purged, "purged deleted conversations");
+
+    let trash_days = app.config.workspace_trash_days as i64;
+    let workspaces = sqlx::query_scalar!(
+        r#"DELETE FROM workspaces
+        WHERE deleted_at < datetime('now', printf('-%d days', ?))
+        RETURNING id AS "id!: i64""#,
+        trash_days,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    for &id in &workspaces {
+        workspace::audit(&*app.sql, id, None, "workspace_purged", None).await?;
+    }
+
+    debug!(purged = workspaces.len(), "purged deleted workspaces");
+
+    sqlx::query!("DELETE FROM workspace_usage WHERE day < date('now')")
+        .execute(&*app.sql)
+        .await?;
+
+    sqlx::query!("DELETE FROM quotas WHERE day < date('now')")
+        .execute(&*app.sql)
+        .await?;
+
+    // Finished jobs are only kept to see what happened to recent syncs
+    sqlx::query!(
+        "DELETE FROM sync_jobs
+        WHERE finished_at < strftime('%s', 'now') - 30 * 86400"
+    )
+    .execute(&*app.sql)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM reindex_campaigns
+        WHERE finished_at < strftime('%s', 'now') - 30 * 86400
+            AND NOT EXISTS (SELECT 1 FROM sync_jobs j WHERE j.campaign_id = reindex_campaigns.id)"
+    )
+    .execute(&*app.sql)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM conversation_reads WHERE NOT EXISTS (
+            SELECT 1 FROM conversations c
+            WHERE c.user_id = conversation_reads.user_id
+                AND c.thread_id = conversation_reads.thread_id
+        )"
+    )
+    .execute(&*app.sql)
+    .await?;
+
+    // Scratchpads can be written before their conversation is first stored, so we give orphans
+    // a grace period.
+    sqlx::query!(
+        "DELETE FROM conversation_scratchpads
+        WHERE updated_at < strftime('%s', 'now') - 86400
+            AND NOT EXISTS (
+                SELECT 1 FROM conversations c
+                WHERE c.user_id = conversation_scratchpads.user_id
+                    AND c.thread_id = conversation_scratchpads.thread_id
+            )"
+    )
+    .execute(&*app.sql)
+    .await?;
+
+    // Drafts are usually written before their conversation is first stored, and may be picked up
+    // on another device later.
+    sqlx::query!(
+        "DELETE FROM conversation_drafts
+        WHERE updated_at < strftime('%s', 'now') - 7 * 86400
+            AND NOT EXISTS (
+                SELECT 1 FROM conversations c
+                WHERE c.user_id = conversation_drafts.user_id
+                    AND c.thread_id = conversation_drafts.thread_id
+            )"
+    )
+    .execute(&*app.sql)
+    .await?;
+
+    let channel_days = app.config.channel_retention_days as i64;
+    sqlx::query!(
+        "DELETE FROM repo_channel_messages WHERE created_at < strftime('%s', 'now') - ? * 86400",
+        channel_days,
+    )
+    .execute(&*app.sql)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM llm_response_cache WHERE last_used_at < strftime('%s', 'now') - ? * 86400",
+        RETENTION_DAYS,
+    )
+    .execute(&*app.sql)
+    .await?;
+
+    Ok(())
+}
+
+/// Delete the conversations that members had in a workspace, once they are older than its
+/// retention period.
+///
+/// The retention of a workspace only covers its members, as a repository can be attached to
+/// workspaces that other users of the instance have nothing to do with.
+async fn delete_expired(db: &SqlitePool) -> sqlx::Result<u64> {
+    let deleted = sqlx::query!(
+        "DELETE FROM conversations WHERE id IN (
+            SELECT c.id
+            FROM conversations c
+            INNER JOIN workspace_repos r ON r.repo_ref = c.repo_ref
+            INNER JOIN workspaces w ON w.id = r.workspace_id
+            INNER JOIN workspace_members m ON m.workspace_id = w.id AND m.user_id = c.user_id
+            WHERE w.retention_days IS NOT NULL
+                AND w.deleted_at IS NULL
+                AND c.created_at < strftime('%s', 'now') - w.retention_days * 86400
+        )"
+    )
+    .execute(db)
+    .await?
+    .rows_affected();
+
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn conversation(db: &SqlitePool, user_id: &str, days_ago: i64) {
+        sqlx::query(
+            "INSERT INTO conversations (created_at, user_id, thread_id, repo_ref, title, exchanges)
+            VALUES (strftime('%s', 'now') - ? * 86400, ?, ?, 'github.com/org/repo', 'title', '[]')",
+        )
+        .bind(days_ago)
+        .bind(user_id)
+        .bind(uuid::Uuid::new_v4().to_string())
+        .execute(db)
+        .await
+        .unwrap();
+    }
+
+    async fn remaining(db: &SqlitePool) -> Vec<(String, i64)> {
+        sqlx::query_as(
+            "SELECT user_id, (strftime('%s', 'now') - created_at) / 86400 FROM conversations
+            ORDER BY user_id, created_at",
+        )
+        .fetch_all(db)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn expires_conversations_of_members() {
+        let db = crate::db::test_pool().await;
+
+        for query in [
+            "INSERT INTO workspaces (id, name, retention_days) VALUES (1, 'team', 30)",
+            "INSERT INTO workspace_members (workspace_id, user_id, role) VALUES (1, 'member', 'owner')",
+            "INSERT INTO workspace_repos (workspace_id, repo_ref) VALUES (1, 'github.com/org/repo')",
+        ] {
+            sqlx::query(query).execute(&db).await.unwrap();
+        }
+
+        conversation(&db, "member", 60).await;
+        conversation(&db, "member", 10).await;
+        conversation(&db, "outsider", 60).await;
+
+        assert_eq!(delete_expired(&db).await.unwrap(), 1);
+        assert_eq!(
+            remaining(&db).await,
+            [("member".to_owned(), 10), ("outsider".to_owned(), 60)]
+        );
+
+        // Deleted workspaces stop enforcing their retention
+        conversation(&db, "member", 60).await;
+        sqlx::query("UPDATE workspaces SET deleted_at = datetime('now')")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        assert_eq!(delete_expired(&db).await.unwrap(), 0);
+    }
+}
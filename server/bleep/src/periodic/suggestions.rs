@@ -0,0 +1,274 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use tracing::{debug, error};
+
+use crate::{remotes::github::Auth, Application};
+
+/// How many of the most recently pushed repositories we read activity from.
+const MAX_REPOS: usize = 30;
+
+/// How many suggestions we keep per user.
+const MAX_SUGGESTIONS: usize = 5;
+
+/// Suggest workspaces to users, based on the recent GitHub activity in the app installation.
+///
+/// Runs every 6 hours. This is a no-op unless bloop is running as a GitHub App installation.
+pub(crate) async fn suggest_workspaces(app: Application) {
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(6 * 3600));
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = update_suggestions(&app).await {
+            error!(?err, "failed to update workspace suggestions");
+        }
+    }
+}
+
+async fn update_suggestions(app: &Application) -> anyhow::Result<()> {
+    let Some(gh) = app.credentials.github() else {
+        return Ok(());
+    };
+
+    let Auth::App { ref org, .. } = gh.auth else {
+        return Ok(());
+    };
+
+    let mut repos = gh
+        .repositories
+        .iter()
+        .filter(|r| r.pushed_at.is_some())
+        .collect::<Vec<_>>();
+    repos.sort_by_key(|r| std::cmp::Reverse(r.pushed_at));
+
+    let client = gh.client()?;
+    let mut activity = vec![];
+    for repo in repos.into_iter().take(MAX_REPOS) {
+        let events = match client
+            .repos(org, &repo.name)
+            .events()
+            .per_page(100)
+            .send()
+            .await
+        {
+            Ok(events) => events.value.map(|page| page.items).unwrap_or_default(),
+            Err(err) => {
+                debug!(?err, repo = %repo.name, "failed to list repository events");
+                continue;
+            }
+        };
+
+        let repo_ref = format!("github.com/{org}/{}", repo.name);
+        activity.extend(
+            events
+                .into_iter()
+                .map(|event| (event.actor.login, repo_ref.clone())),
+        );
+    }
+
+    let suggestions = suggest(&activity);
+
+    let mut transaction = app.sql.begin().await?;
+    sqlx::query!("DELETE FROM workspace_suggestions")
+        .execute(&mut transaction)
+        .await?;
+
+    for (user_id, suggestions) in &suggestions {
+        for suggestion in suggestions {
+            let repos = serde_json::to_string(&suggestion.repos)?;
+            let activity = suggestion.activity as i64;
+            sqlx::query!(
+                "INSERT INTO workspace_suggestions (user_id, name, repos, activity)
+                VALUES (?, ?, ?, ?)",
+                user_id,
+                suggestion.name,
+                repos,
+                activity,
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+    }
+
+    transaction.commit().await?;
+    debug!(users = suggestions.len(), "updated workspace suggestions");
+
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+struct Suggestion {
+    name: String,
+    repos: Vec<String>,
+    /// The number of events by the user in these repositories
+    activity: usize,
+}
+
+/// Group the repositories every user is active in, from a list of `(user, repo)` events.
+///
+/// Two repositories of a user end up in the same group when somebody else is active in both of
+/// them too, so that groups follow the teams working together.
+fn suggest(activity: &[(String, String)]) -> BTreeMap<String, Vec<Suggestion>> {
+    let mut events = BTreeMap::<&str, BTreeMap<&str, usize>>::new();
+    let mut actors = HashMap::<&str, BTreeSet<&str>>::new();
+    for (user, repo) in activity {
+        *events
+            .entry(user.as_str())
+            .or_default()
+            .entry(repo.as_str())
+            .or_default() += 1;
+        actors
+            .entry(repo.as_str())
+            .or_default()
+            .insert(user.as_str());
+    }
+
+    events
+        .iter()
+        .map(|(user, counts)| {
+            let repos = counts.keys().copied().collect::<Vec<_>>();
+            let mut group = (0..repos.len()).collect::<Vec<_>>();
+
+            fn root(group: &mut [usize], mut i: usize) -> usize {
+                while group[i] != i {
+                    group[i] = group[group[i]];
+                    i = group[i];
+                }
+                i
+            }
+
+            for a in 0..repos.len() {
+                for b in a + 1..repos.len() {
+                    let shared = actors[repos[a]]
+                        .intersection(&actors[repos[b]])
+                        .any(|other| other != user);
+
+                    if shared {
+                        let (ra, rb) = (root(&mut group, a), root(&mut group, b));
+                        group[rb] = ra;
+                    }
+                }
+            }
+
+            let mut groups = BTreeMap::<usize, Vec<&str>>::new();
+            for (i, repo) in repos.iter().enumerate() {
+                groups.entry(root(&mut group, i)).or_default().push(*repo);
+            }
+
+            let mut suggestions = groups
+                .into_values()
+                .map(|repos| {
+                    let activity = repos.iter().map(|r| counts[r]).sum();
+                    Suggestion {
+                        name: group_name(&repos, counts),
+                        repos: repos.into_iter().map(str::to_owned).collect(),
+                        activity,
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            suggestions.sort_by(|a, b| b.activity.cmp(&a.activity).then(a.name.cmp(&b.name)));
+            suggestions.truncate(MAX_SUGGESTIONS);
+
+            (user.to_string(), suggestions)
+        })
+        .collect()
+}
+
+/// Name a group after the common prefix of its repository names, such as `bloop` for
+/// `bloop-server` and `bloop-client`, or after its most active repository.
+fn group_name(repos: &[&str], counts: &BTreeMap<&str, usize>) -> String {
+    let names = repos
+        .iter()
+        .map(|r| r.rsplit('/').next().unwrap_or(r))
+        .collect::<Vec<_>>();
+
+    if names.len() > 1 {
+        let words = |name: &str| {
+            name.split(['-', '_', '.'])
+                .map(str::to_owned)
+                .collect::<Vec<_>>()
+        };
+
+        let first = words(names[0]);
+        let common = names[1..].iter().fold(first.len(), |len, name| {
+            first
+                .iter()
+                .zip(words(name))
+                .take(len)
+                .take_while(|(a, b)| *a == b)
+                .count()
+        });
+
+        if common > 0 {
+            return first[..common].join("-");
+        }
+    }
+
+    let most_active = repos
+        .iter()
+        .zip(&names)
+        .max_by(|(a, _), (b, _)| counts[**a].cmp(&counts[**b]).then(b.cmp(a)))
+        .map(|(_, name)| *name)
+        .unwrap_or_default();
+
+    most_active.to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(events: &[(&str, &str, usize)]) -> Vec<(String, String)> {
+        events
+            .iter()
+            .flat_map(|(user, repo, n)| {
+                std::iter::repeat((user.to_string(), format!("github.com/acme/{repo}"))).take(*n)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn groups_repos_by_shared_actors() {
+        let activity = events(&[
+            ("alice", "bloop-server", 5),
+            ("alice", "bloop-client", 2),
+            ("alice", "infra", 1),
+            ("bob", "bloop-server", 1),
+            ("bob", "bloop-client", 1),
+            ("carol", "infra", 4),
+        ]);
+
+        let suggestions = suggest(&activity);
+
+        assert_eq!(
+            suggestions["alice"],
+            [
+                Suggestion {
+                    name: "bloop".into(),
+                    repos: vec![
+                        "github.com/acme/bloop-client".into(),
+                        "github.com/acme/bloop-server".into(),
+                    ],
+                    activity: 7,
+                },
+                Suggestion {
+                    name: "infra".into(),
+                    repos: vec!["github.com/acme/infra".into()],
+                    activity: 1,
+                },
+            ]
+        );
+
+        // Bob's only collaborator is Alice, and she works on both repositories.
+        assert_eq!(suggestions["bob"].len(), 1);
+        assert_eq!(suggestions["carol"][0].name, "infra");
+    }
+
+    #[test]
+    fn names_groups_without_common_prefix_after_most_active_repo() {
+        let counts = BTreeMap::from([("github.com/acme/api", 1), ("github.com/acme/web", 3)]);
+        let repos = counts.keys().copied().collect::<Vec<_>>();
+
+        assert_eq!(group_name(&repos, &counts), "web");
+    }
+}
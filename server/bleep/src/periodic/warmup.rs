@@ -0,0 +1,27 @@
+use std::time::Duration;
+
+use tracing::debug;
+
+use crate::{llm_gateway, Application};
+
+/// Keep a warm connection to the LLM gateway, so that answers don't wait for a TLS handshake.
+///
+/// This runs more often than the gateway's idle timeout.
+pub(crate) async fn warm_llm_connections(app: Application) {
+    // Answers don't go through the gateway with a self-hosted model
+    if app.config.llm_url.is_some() {
+        return;
+    }
+
+    let client = llm_gateway::Client::new(&app.config.answer_api_url);
+    let version: semver::Version = env!("CARGO_PKG_VERSION").parse().unwrap();
+
+    let mut interval = tokio::time::interval(Duration::from_secs(45));
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = client.is_compatible(version.clone()).await {
+            debug!(?err, "failed to warm up LLM gateway connection");
+        }
+    }
+}
@@ -4,4 +4,5 @@ pub mod languages;
 pub mod parser;
 pub mod planner;
 pub mod ranking;
+pub mod rules;
 pub mod stopwords;
@@ -1,9 +1,15 @@
 use std::{
     collections::{HashMap, HashSet},
+    mem,
+    ops::Range,
     sync::Arc,
 };
 
-use super::{parser, ranking::DocumentTweaker};
+use super::{
+    parser, planner,
+    ranking::{DocumentTweaker, ScoreBreakdown},
+    rules::{Boost, LexicalRules},
+};
 use crate::{
     collector::{BytesFilterCollector, FrequencyCollector},
     indexes::{
@@ -11,12 +17,14 @@ use crate::{
         DocumentRead, File, Indexable, Indexer, Indexes, Repo,
     },
     repo::RepoRef,
+    semantic::Fusion,
     snippet::{HighlightedString, SnippedFile, Snipper},
 };
 
 use anyhow::{bail, Result};
 use async_trait::async_trait;
 use regex::{bytes::RegexBuilder as ByteRegexBuilder, RegexBuilder};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 use tantivy::collector::{MultiCollector, TopDocs};
@@ -40,15 +48,44 @@ fn div_ceil(a: usize, b: usize) -> usize {
     d + usize::from(r > 0)
 }
 
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMode {
+    /// Terms are literal text, unless written as `/regex/` or `regex:`
+    #[default]
+    Literal,
+    /// Every content term is a regex, as if written with `regex:`
+    Regex,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ApiQuery {
     /// A query written in the bloop query language
     pub q: String,
 
+    /// How to read the content terms of `q`
+    #[serde(default)]
+    pub mode: SearchMode,
+
     /// Optional RepoRef to constrain the search. If not provided, search all repos
+    ///
+    /// The lexical rules of this repository are applied to content searches.
     #[serde(default)]
     pub repo_ref: Option<RepoRef>,
 
+    /// Explain how results were ranked
+    #[serde(default)]
+    pub explain: bool,
+
+    /// The lexical rules to apply, loaded for `repo_ref`
+    #[serde(skip)]
+    pub rules: Arc<LexicalRules>,
+
+    /// Search this branch, unless the query names one with `branch:`. Branches that aren't
+    /// indexed search the default branch instead.
+    #[serde(default)]
+    pub branch: Option<String>,
+
     #[serde(default)]
     pub page: usize,
 
@@ -75,7 +112,7 @@ pub struct ApiQuery {
     context_after: usize,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
 pub struct QueryResponse {
     /// Number of search results in this response
     pub count: usize,
@@ -85,12 +122,41 @@ pub struct QueryResponse {
     pub data: Vec<QueryResult>,
     /// Stats for nerds
     pub stats: ResultStats,
+    /// How results were ranked, if asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explain: Option<Explanation>,
+}
+
+/// How results were ranked, for debugging why a result ranks where it does.
+#[derive(Serialize, JsonSchema, Default, Debug)]
+pub struct Explanation {
+    /// Words that were dropped from the query as stop-words
+    pub stop_words: Vec<String>,
+    /// In the order of the results
+    pub results: Vec<ResultExplanation>,
+}
+
+#[derive(Serialize, JsonSchema, Default, Debug)]
+pub struct ResultExplanation {
+    pub relative_path: String,
+    /// The lines of the snippet, for semantic results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_range: Option<Range<usize>>,
+    /// How the lexical score was computed, for content results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<ScoreBreakdown>,
+    /// The boosts of the repository that apply, which make up `score.factors.boost`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub boosts: Vec<Boost>,
+    /// How the semantic and lexical rankings were fused, for semantic results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fusion: Option<Fusion>,
 }
 
 impl crate::webserver::ApiResponse for QueryResponse {}
 
 /// Metadata pertaining to the query response, such as paging info
-#[derive(Default, Serialize)]
+#[derive(Default, Serialize, JsonSchema)]
 #[non_exhaustive]
 pub struct PagingMetadata {
     /// Page number passed in the request
@@ -107,13 +173,13 @@ pub struct PagingMetadata {
     total_count: Option<usize>,
 }
 
-#[derive(Default, Serialize, Deserialize, Debug)]
+#[derive(Default, Serialize, Deserialize, JsonSchema, Debug)]
 pub struct ResultStats {
     pub lang: HashMap<String, usize>,
     pub repo: HashMap<String, usize>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
 #[non_exhaustive]
 #[serde(tag = "kind", content = "data")]
 pub enum QueryResult {
@@ -140,13 +206,13 @@ pub enum QueryResult {
     Lang(String),
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
 pub struct RepositoryResultData {
     name: HighlightedString,
     repo_ref: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
 pub struct FileResultData {
     repo_name: String,
     relative_path: HighlightedString,
@@ -179,7 +245,7 @@ impl FileResultData {
     }
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, JsonSchema, Debug)]
 pub struct FileData {
     repo_name: String,
     relative_path: String,
@@ -193,7 +259,7 @@ pub struct FileData {
     sloc: usize,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
 pub struct DirectoryData {
     repo_name: String,
     relative_path: String,
@@ -201,13 +267,13 @@ pub struct DirectoryData {
     entries: Vec<DirEntry>,
 }
 
-#[derive(Serialize, PartialEq, Eq, Hash, Clone, Debug)]
+#[derive(Serialize, JsonSchema, PartialEq, Eq, Hash, Clone, Debug)]
 pub struct DirEntry {
     name: String,
     entry_data: EntryData,
 }
 
-#[derive(Serialize, PartialEq, Eq, Hash, Clone, Debug)]
+#[derive(Serialize, JsonSchema, PartialEq, Eq, Hash, Clone, Debug)]
 enum EntryData {
     Directory,
     File { lang: Option<String>, indexed: bool },
@@ -226,11 +292,52 @@ pub trait ExecuteQuery {
 }
 
 impl ApiQuery {
+    /// Apply `branch` to the queries that don't filter by branch themselves.
+    pub(crate) fn default_branch(&self, queries: &mut [parser::Query<'_>]) {
+        let Some(branch) = &self.branch else {
+            return;
+        };
+
+        for q in queries {
+            q.branch
+                .get_or_insert_with(|| parser::Literal::Plain(branch.clone().into()));
+        }
+    }
+
     pub async fn query(self: Arc<Self>, indexes: Arc<Indexes>) -> Result<QueryResponse> {
-        let query = self.q.clone();
-        let compiled = parser::parse(&query)?;
+        // Stop-words are part of the pattern in regex mode
+        let (query, stop_words) = match self.mode {
+            SearchMode::Literal => self.rules.strip_stop_words(&self.q),
+            SearchMode::Regex => (self.q.as_str().into(), vec![]),
+        };
+        let query = query.into_owned();
+
+        let mut compiled = match self.mode {
+            SearchMode::Literal => parser::parse(&query)?,
+            SearchMode::Regex => parser::parse_regex(&query)?,
+        };
+
+        for q in &compiled {
+            if let Some(parser::Literal::Regex(regex)) = q.target.as_ref().and_then(|t| t.content())
+            {
+                match planner::check(regex) {
+                    // Regexes in literal mode could be searched without a literal before regex
+                    // mode was added, and still can
+                    Err(planner::Error::Unindexed) if self.mode == SearchMode::Literal => {}
+                    result => result?,
+                }
+            }
+        }
+
+        self.default_branch(&mut compiled);
         tracing::debug!("compiled query as {compiled:?}");
-        self.query_with(indexes, compiled).await
+
+        let mut response = self.query_with(indexes, compiled).await?;
+        if let Some(explain) = &mut response.explain {
+            explain.stop_words = stop_words;
+        }
+
+        Ok(response)
     }
 
     pub async fn query_with(
@@ -366,9 +473,10 @@ impl ExecuteQuery for ContentReader {
         let lang_field = indexer.source.lang;
 
         // our results will consist of the top-k docs...
+        let tweaker = DocumentTweaker(indexer.source.clone(), Arc::clone(&q.rules));
         let top_k = TopDocs::with_limit(q.limit())
             .and_offset(q.offset())
-            .tweak_score(DocumentTweaker(indexer.source.clone()));
+            .tweak_score(tweaker.clone());
 
         // ...plus some rich search metadata
         let total_count_collector = tantivy::collector::Count;
@@ -389,9 +497,11 @@ impl ExecuteQuery for ContentReader {
         );
 
         let mut results = indexer.query(queries.iter(), self, collector).await?;
+        let mut explained = vec![];
         let data = results
             .docs
-            .filter_map(|doc| {
+            .zip(mem::take(&mut results.hits))
+            .filter_map(|(doc, addr)| {
                 let snipper = Snipper::default().context(q.context_before, q.context_after);
                 let mut all_snippets = None::<SnippedFile>;
 
@@ -415,7 +525,23 @@ impl ExecuteQuery for ContentReader {
                     }
                 }
 
-                Some(QueryResult::Snippets(all_snippets?))
+                let snippets = all_snippets?;
+
+                if q.explain {
+                    let score = tweaker
+                        .explain(&results.searcher, &*results.query, addr)
+                        .map_err(|err| tracing::warn!(?err, "failed to explain score"))
+                        .ok();
+
+                    explained.push(ResultExplanation {
+                        relative_path: doc.relative_path.clone(),
+                        score,
+                        boosts: q.rules.apply(&doc.relative_path).boosts,
+                        ..Default::default()
+                    });
+                }
+
+                Some(QueryResult::Snippets(snippets))
             })
             .collect::<Vec<QueryResult>>();
 
@@ -433,6 +559,10 @@ impl ExecuteQuery for ContentReader {
             metadata,
             data,
             stats,
+            explain: q.explain.then(|| Explanation {
+                results: explained,
+                ..Default::default()
+            }),
         };
         Ok(response)
     }
@@ -523,6 +653,7 @@ impl ExecuteQuery for FileReader {
             data,
             metadata,
             stats,
+            explain: None,
         };
 
         Ok(response)
@@ -602,6 +733,7 @@ impl ExecuteQuery for RepoReader {
             data,
             metadata,
             stats,
+            explain: None,
         };
 
         Ok(response)
@@ -783,6 +915,7 @@ impl ExecuteQuery for OpenReader {
             data,
             metadata: PagingMetadata::default(),
             stats: ResultStats::default(),
+            explain: None,
         };
 
         Ok(response)
@@ -867,4 +1000,19 @@ mod tests {
 
         assert_eq!(expected, observed);
     }
+
+    #[test]
+    fn branch_defaults_to_parameter() {
+        let api: ApiQuery =
+            serde_json::from_value(serde_json::json!({ "q": "", "branch": "dev" })).unwrap();
+
+        let mut queries = parser::parse("foo branch:main or bar").unwrap();
+        api.default_branch(&mut queries);
+
+        let branches = queries
+            .iter()
+            .map(|q| q.branch.as_ref().and_then(|b| b.as_plain()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(branches, ["main", "dev"]);
+    }
 }
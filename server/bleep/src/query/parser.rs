@@ -23,7 +23,9 @@ pub enum Target<'a> {
     Content(Literal<'a>),
 }
 
-#[derive(Default, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[derive(
+    Default, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
 pub struct SemanticQuery<'a> {
     pub raw_query: String,
     pub repos: Vec<Literal<'a>>,
@@ -223,13 +225,17 @@ pub enum ParseError {
     MultiMode,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[derive(
+    Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
 pub enum Literal<'a> {
     Plain(LiteralInner<'a>),
     Regex(LiteralInner<'a>),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[derive(
+    Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize, schemars::JsonSchema,
+)]
 pub struct LiteralInner<'a> {
     start: usize,
     end: usize,
@@ -485,6 +491,11 @@ impl<'a> Expr<'a> {
             | Rule::regex_quoted_literal => Content(Literal::from(pair)),
 
             Rule::content => Content(Literal::from(pair.into_inner().next().unwrap())),
+            Rule::regex => {
+                let mut lit = Literal::from(pair.into_inner().next().unwrap());
+                lit.make_regex();
+                Content(lit)
+            }
             Rule::path => Path(Literal::from(pair.into_inner().next().unwrap())),
             Rule::repo => Repo(Literal::from(pair.into_inner().next().unwrap())),
             Rule::symbol => Symbol(Literal::from(pair.into_inner().next().unwrap())),
@@ -562,17 +573,44 @@ impl<'a> Expr<'a> {
             _ => Err(pair)?,
         })
     }
+
+    /// Turn every content term into a regex, including terms in groups.
+    fn make_content_regex(&mut self) {
+        match self {
+            Self::Or(exprs) | Self::And(exprs) => {
+                exprs.iter_mut().for_each(Self::make_content_regex)
+            }
+            Self::Content(lit) => lit.make_regex(),
+            _ => {}
+        }
+    }
 }
 
 /// Parse an input query string into a list of top-level `Query`s.
 pub fn parse(query: &str) -> Result<Vec<Query<'_>>, ParseError> {
+    parse_with(query, false)
+}
+
+/// Parse an input query string whose content terms are all regexes.
+///
+/// Unlike `global_regex:true`, this leaves other filters like `path:` as they are. Terms are turned
+/// into regexes before they are joined, so that `fn\s+\w+ Result` isn't escaped.
+pub fn parse_regex(query: &str) -> Result<Vec<Query<'_>>, ParseError> {
+    parse_with(query, true)
+}
+
+fn parse_with(query: &str, regex: bool) -> Result<Vec<Query<'_>>, ParseError> {
     let pair = PestParser::parse(Rule::query, query)
         .map_err(Box::new)?
         .next()
         .unwrap();
-    let root =
+    let mut root =
         Expr::parse(pair, true).map_err(|pair| ParseError::UnparsedToken(pair.to_string()))?;
 
+    if regex {
+        root.make_content_regex();
+    }
+
     let mut qs = flatten(root);
 
     // Find and redistribute global options.
@@ -1604,4 +1642,39 @@ mod tests {
             }],
         );
     }
+
+    #[test]
+    fn regex_mode() {
+        assert_eq!(
+            parse("regex:fn\\s+\\w+ path:src").unwrap(),
+            vec![Query {
+                path: Some(Literal::Plain(LiteralInner {
+                    start: 20,
+                    end: 23,
+                    content: "src".into()
+                })),
+                target: Some(Target::Content(Literal::Regex(LiteralInner {
+                    start: 6,
+                    end: 14,
+                    content: "fn\\s+\\w+".into()
+                }))),
+                ..Query::default()
+            }],
+        );
+
+        assert_eq!(
+            parse_regex("fn\\s+\\w+ Result path:src").unwrap(),
+            vec![Query {
+                path: Some(Literal::Plain(LiteralInner {
+                    start: 21,
+                    end: 24,
+                    content: "src".into()
+                })),
+                target: Some(Target::Content(Literal::Regex(
+                    "fn\\s+\\w+\\s+Result".into()
+                ))),
+                ..Query::default()
+            }],
+        );
+    }
 }
@@ -7,12 +7,51 @@ mod optimize;
 /// The maximum number of characters allowed in a character class before a break occurs.
 const MAX_CLASS_RANGE_LEN: u32 = 10;
 
+/// The longest regex we search content for.
+const MAX_REGEX_LEN: usize = 1000;
+
+/// The most memory a compiled content regex can use, in bytes.
+const MAX_REGEX_SIZE: usize = 1 << 20;
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("encountered unexpected byte literal")]
     LiteralByte,
     #[error("got invalid regex")]
     InvalidRegex(#[from] Box<regex_syntax::Error>),
+    #[error("regex is longer than {MAX_REGEX_LEN} characters")]
+    TooLong,
+    #[error("regex is too complex to search for")]
+    TooComplex,
+    #[error("regex must contain a literal string, so it can be looked up in the index")]
+    Unindexed,
+}
+
+/// Check that a content regex can be searched for without scanning every file.
+///
+/// The regex engine runs in linear time, but patterns like `(a{100}){100}` compile to huge
+/// automata, and patterns without any literal like `.*` match every document in the index.
+/// Callers decide whether [`Error::Unindexed`] rejects the search.
+pub fn check(regex: &str) -> Result<(), Error> {
+    if regex.len() > MAX_REGEX_LEN {
+        return Err(Error::TooLong);
+    }
+
+    // Planning fails on invalid syntax, so compiling can only fail on size
+    let plan = plan(regex)?;
+
+    regex::RegexBuilder::new(regex)
+        .size_limit(MAX_REGEX_SIZE)
+        .dfa_size_limit(MAX_REGEX_SIZE)
+        .build()
+        .map_err(|_| Error::TooComplex)?;
+
+    // Checked last, so that a regex that is rejected for this alone is safe to run
+    if !plan.uses_index() {
+        return Err(Error::Unindexed);
+    }
+
+    Ok(())
 }
 
 pub fn plan(regex: &str) -> Result<Fragment, Error> {
@@ -182,6 +221,16 @@ impl Fragment {
         }
     }
 
+    /// Whether this fragment narrows down the documents that can match.
+    fn uses_index(&self) -> bool {
+        match self {
+            Self::Literal(s) => !s.is_empty(),
+            Self::Dense(Op::And, children) => children.iter().any(Self::uses_index),
+            Self::Dense(Op::Or, children) => children.iter().all(Self::uses_index),
+            Self::Break => false,
+        }
+    }
+
     fn as_literal(&self) -> Option<&String> {
         if let Self::Literal(s) = self {
             Some(s)
@@ -414,4 +463,19 @@ mod tests {
             ),
         );
     }
+
+    #[test]
+    fn check_guards() {
+        assert!(check("fn\\s+\\w+").is_ok());
+        assert!(check("(foo|bar).*baz").is_ok());
+
+        assert!(matches!(check(".*"), Err(Error::Unindexed)));
+        assert!(matches!(check("foo|.*"), Err(Error::Unindexed)));
+        assert!(matches!(check("(foo"), Err(Error::InvalidRegex(_))));
+        assert!(matches!(
+            check("foo(\\w{100}){100}"),
+            Err(Error::TooComplex)
+        ));
+        assert!(matches!(check(&"a".repeat(1001)), Err(Error::TooLong)));
+    }
 }
@@ -1,40 +1,120 @@
-use std::time::SystemTime;
+use std::{sync::Arc, time::SystemTime};
 
+use serde::Serialize;
 use tantivy::{
     collector::{ScoreSegmentTweaker, ScoreTweaker},
     fastfield::Column,
-    DocId, Score,
+    query::Query,
+    DocAddress, DocId, Score, Searcher,
 };
 use tantivy_columnar::{column_values::ColumnValues, BytesColumn};
 
+use super::rules::LexicalRules;
 use crate::indexes::file::File;
 
-pub struct DocumentTweaker(pub File);
+#[derive(Clone)]
+pub struct DocumentTweaker(pub File, pub Arc<LexicalRules>);
 pub struct SegmentScorer {
     line_length: Column<f64>,
     lang: BytesColumn,
     last_commit: Column<u64>,
+    relative_path: Option<BytesColumn>,
+    rules: Arc<LexicalRules>,
 }
 
-impl ScoreSegmentTweaker<Score> for SegmentScorer {
-    fn score(&mut self, doc: DocId, mut score: Score) -> Score {
-        // * 1000 if it's a language we understand
+/// What the BM25 score of a document is multiplied and divided by.
+#[derive(Serialize, schemars::JsonSchema, Debug, Clone, Copy, PartialEq)]
+pub struct Factors {
+    /// 1000 if it's a language we understand, 1 otherwise
+    pub language: f32,
+    /// The average line length, between 20 and 1000, that the score is divided by
+    pub line_length: f32,
+    /// Seconds since the last commit, up to 5,000,000, that the score is divided by
+    pub age: f32,
+    /// The combined weight of the repository's boosts
+    pub boost: f32,
+}
+
+impl Factors {
+    fn apply(&self, score: Score) -> Score {
+        score * self.language / self.line_length / self.age * self.boost
+    }
+}
+
+/// How the score of a content result came to be.
+#[derive(Serialize, schemars::JsonSchema, Debug)]
+pub struct ScoreBreakdown {
+    /// The score of the query alone
+    pub bm25: f32,
+    /// The term frequencies, document frequencies and field norms that make up `bm25`
+    pub bm25_details: serde_json::Value,
+    pub factors: Factors,
+    /// The score results are ordered by
+    pub score: f32,
+}
+
+impl SegmentScorer {
+    fn factors(&self, doc: DocId) -> Factors {
         let mut bytes = Vec::new();
         self.lang.ords().values_for_doc(doc).for_each(|ord| {
             self.lang.ord_to_bytes(ord, &mut bytes).unwrap();
         });
-        score *= 1.0 + bytes.len().min(1) as f32 * 999.0;
+        let language = 1.0 + bytes.len().min(1) as f32 * 999.0;
 
-        // Penalty for lines that are too long
-        score /= self.line_length.values.get_val(doc).clamp(20.0, 1000.0) as f32;
-        score /= SystemTime::now()
+        let line_length = self.line_length.values.get_val(doc).clamp(20.0, 1000.0) as f32;
+        let age = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
             .as_secs()
             .saturating_sub(self.last_commit.values.get_val(doc))
             .min(5_000_000) as f32;
 
-        score
+        let boost = match self.relative_path {
+            Some(ref relative_path) => {
+                let mut bytes = Vec::new();
+                relative_path.ords().values_for_doc(doc).for_each(|ord| {
+                    relative_path.ord_to_bytes(ord, &mut bytes).unwrap();
+                });
+                self.rules.apply(&String::from_utf8_lossy(&bytes)).weight
+            }
+            None => 1.0,
+        };
+
+        Factors {
+            language,
+            line_length,
+            age,
+            boost,
+        }
+    }
+}
+
+impl ScoreSegmentTweaker<Score> for SegmentScorer {
+    fn score(&mut self, doc: DocId, score: Score) -> Score {
+        // * 1000 if it's a language we understand, penalty for lines that are too long and for
+        // files that haven't changed in a while, and the boosts of the repository's lexical rules
+        self.factors(doc).apply(score)
+    }
+}
+
+impl DocumentTweaker {
+    /// Break down the score of a document found by `query`.
+    pub fn explain(
+        &self,
+        searcher: &Searcher,
+        query: &dyn Query,
+        addr: DocAddress,
+    ) -> tantivy::Result<ScoreBreakdown> {
+        let bm25 = query.explain(searcher, addr)?;
+        let scorer = self.segment_tweaker(searcher.segment_reader(addr.segment_ord))?;
+        let factors = scorer.factors(addr.doc_id);
+
+        Ok(ScoreBreakdown {
+            bm25: bm25.value(),
+            bm25_details: serde_json::to_value(&bm25).unwrap_or_default(),
+            score: factors.apply(bm25.value()),
+            factors,
+        })
     }
 }
 
@@ -45,17 +125,28 @@ impl ScoreTweaker<Score> for DocumentTweaker {
         &self,
         segment_reader: &tantivy::SegmentReader,
     ) -> tantivy::Result<Self::Child> {
-        let Self(file) = self;
+        let Self(file, rules) = self;
         let schema = file.schema();
         let avg_line_length_field = schema.get_field_name(file.avg_line_length);
         let lang_field = schema.get_field_name(file.lang);
         let last_commit_unix_seconds_field = schema.get_field_name(file.last_commit_unix_seconds);
+        let relative_path_field = schema.get_field_name(file.raw_relative_path);
+
+        // Reading paths is only worth it when there are boosts to apply
+        let relative_path = if rules.path_boosts.is_empty() && rules.filename_boosts.is_empty() {
+            None
+        } else {
+            segment_reader.fast_fields().bytes(relative_path_field)?
+        };
+
         Ok(SegmentScorer {
             line_length: segment_reader.fast_fields().f64(avg_line_length_field)?,
             lang: segment_reader.fast_fields().bytes(lang_field)?.unwrap(),
             last_commit: segment_reader
                 .fast_fields()
                 .u64(last_commit_unix_seconds_field)?,
+            relative_path,
+            rules: Arc::clone(rules),
         })
     }
 }
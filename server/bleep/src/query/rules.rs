@@ -0,0 +1,218 @@
+//! Per-repository rules for lexical search: words to ignore in queries, and paths and file names to
+//! rank higher or lower.
+//!
+//! Stop-words are dropped from the query before it is parsed, so that `the parser` searches for
+//! `parser` when `the` is a stop-word. Words in quotes or regexes, and filters like `path:`, are
+//! kept as they are. Boosts multiply the score of every result whose path matches.
+
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+/// The most rules of each kind a repository can have.
+const MAX_RULES: usize = 100;
+
+#[derive(Serialize, Deserialize, schemars::JsonSchema, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct LexicalRules {
+    /// Words to ignore in queries, regardless of case.
+    pub stop_words: Vec<String>,
+
+    /// Weights of results whose path starts with a prefix, like `src/` or `tests/fixtures/`.
+    pub path_boosts: Vec<Boost>,
+
+    /// Weights of results by file name. A pattern like `*.md` matches by suffix, other patterns
+    /// match the whole file name.
+    pub filename_boosts: Vec<Boost>,
+}
+
+#[derive(Serialize, Deserialize, schemars::JsonSchema, Debug, Clone, PartialEq)]
+pub struct Boost {
+    pub pattern: String,
+    /// The score of a matching result is multiplied by this. Weights below 1 demote results.
+    pub weight: f32,
+}
+
+/// The boosts that apply to a result, and their combined weight.
+#[derive(Debug, PartialEq)]
+pub struct Applied {
+    pub relative_path: String,
+    pub weight: f32,
+    pub boosts: Vec<Boost>,
+}
+
+impl LexicalRules {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if [
+            self.stop_words.len(),
+            self.path_boosts.len(),
+            self.filename_boosts.len(),
+        ]
+        .into_iter()
+        .any(|len| len > MAX_RULES)
+        {
+            return Err("there can be at most 100 rules of each kind");
+        }
+
+        if self.stop_words.iter().any(|w| w.trim().is_empty()) {
+            return Err("stop-words can't be empty");
+        }
+
+        let boosts = self.path_boosts.iter().chain(&self.filename_boosts);
+        for boost in boosts {
+            if boost.pattern.is_empty() {
+                return Err("boost patterns can't be empty");
+            }
+
+            if !(boost.weight.is_finite() && boost.weight > 0.0) {
+                return Err("boost weights must be greater than zero");
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stop_words.is_empty() && self.path_boosts.is_empty() && self.filename_boosts.is_empty()
+    }
+
+    /// The query without stop-words, and the words that were dropped.
+    ///
+    /// A query made only of stop-words is kept as it is, as it would otherwise match nothing.
+    pub fn strip_stop_words<'a>(&self, query: &'a str) -> (Cow<'a, str>, Vec<String>) {
+        if self.stop_words.is_empty() {
+            return (query.into(), vec![]);
+        }
+
+        let mut kept = vec![];
+        let mut dropped = vec![];
+        let mut quote = None::<char>;
+
+        for word in query.split_whitespace() {
+            let in_quotes = quote.is_some();
+            if let Some(q) = quote {
+                if word.ends_with(q) {
+                    quote = None;
+                }
+            } else if let Some(q) = word
+                .chars()
+                .next()
+                .filter(|c| matches!(c, '"' | '\'' | '/'))
+            {
+                if word.len() == 1 || !word.ends_with(q) {
+                    quote = Some(q);
+                }
+            }
+
+            let is_stop_word = !in_quotes
+                && !word.contains(':')
+                && self
+                    .stop_words
+                    .iter()
+                    .any(|stop| stop.eq_ignore_ascii_case(word));
+
+            if is_stop_word {
+                dropped.push(word.to_owned());
+            } else {
+                kept.push(word);
+            }
+        }
+
+        if dropped.is_empty() || kept.iter().all(|w| w.contains(':')) {
+            return (query.into(), vec![]);
+        }
+
+        (kept.join(" ").into(), dropped)
+    }
+
+    /// The boosts that apply to a path.
+    pub fn apply(&self, relative_path: &str) -> Applied {
+        let name = relative_path.rsplit('/').next().unwrap_or(relative_path);
+
+        let boosts = self
+            .path_boosts
+            .iter()
+            .filter(|b| relative_path.starts_with(b.pattern.trim_start_matches('/')))
+            .chain(
+                self.filename_boosts
+                    .iter()
+                    .filter(|b| match b.pattern.strip_prefix('*') {
+                        Some(suffix) => name.ends_with(suffix),
+                        None => name == b.pattern,
+                    }),
+            )
+            .cloned()
+            .collect::<Vec<_>>();
+
+        Applied {
+            relative_path: relative_path.to_owned(),
+            weight: boosts.iter().map(|b| b.weight).product(),
+            boosts,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> LexicalRules {
+        LexicalRules {
+            stop_words: vec!["the".into(), "of".into()],
+            path_boosts: vec![
+                Boost {
+                    pattern: "src/".into(),
+                    weight: 2.0,
+                },
+                Boost {
+                    pattern: "/tests/fixtures/".into(),
+                    weight: 0.1,
+                },
+            ],
+            filename_boosts: vec![Boost {
+                pattern: "*.md".into(),
+                weight: 0.5,
+            }],
+        }
+    }
+
+    #[test]
+    fn strips_stop_words_outside_quotes_and_filters() {
+        let rules = rules();
+
+        let (query, dropped) = rules.strip_stop_words("The parser of repo:the");
+        assert_eq!(query, "parser repo:the");
+        assert_eq!(dropped, ["The", "of"]);
+
+        let (query, dropped) = rules.strip_stop_words("\"end of line\" the");
+        assert_eq!(query, "\"end of line\"");
+        assert_eq!(dropped, ["the"]);
+
+        let (query, dropped) = rules.strip_stop_words("the lang:rust");
+        assert_eq!(query, "the lang:rust");
+        assert!(dropped.is_empty());
+    }
+
+    #[test]
+    fn multiplies_matching_boosts() {
+        let rules = rules();
+
+        assert_eq!(rules.apply("src/README.md").weight, 1.0);
+        assert_eq!(rules.apply("src/main.rs").weight, 2.0);
+        assert_eq!(rules.apply("tests/fixtures/a.rs").weight, 0.1);
+        assert!(rules.apply("lib/main.rs").boosts.is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        assert!(rules().validate().is_ok());
+
+        let mut invalid = rules();
+        invalid.path_boosts[0].weight = 0.0;
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = rules();
+        invalid.stop_words.push(" ".into());
+        assert!(invalid.validate().is_err());
+    }
+}
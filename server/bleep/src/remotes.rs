@@ -20,7 +20,10 @@ use crate::{
     Application,
 };
 
+pub(crate) mod bitbucket;
 pub mod github;
+pub(crate) mod gitlab;
+pub(crate) mod objects;
 
 type GitCreds = Account;
 
@@ -66,6 +69,12 @@ pub(crate) enum RemoteError {
     #[error("github access error: {0}")]
     GitHub(#[from] octocrab::Error),
 
+    #[error("gitlab access error: {0}")]
+    GitLab(reqwest::Error),
+
+    #[error("bitbucket access error: {0}")]
+    Bitbucket(reqwest::Error),
+
     #[error("anyhow: {0:?}")]
     Anyhow(#[from] anyhow::Error),
 
@@ -132,6 +141,8 @@ macro_rules! creds_callback(($auth:ident) => {{
     }
 }});
 
+pub(crate) use creds_callback;
+
 async fn git_clone(
     auth: &Option<GitCreds>,
     url: &str,
@@ -326,10 +337,12 @@ impl Backends {
     }
 
     pub(crate) fn github(&self) -> Option<github::State> {
-        self.backends.read(&Backend::Github, |_, v| {
-            let BackendCredential::Github(ref github) = v.inner;
-            github.clone()
-        })
+        self.backends
+            .read(&Backend::Github, |_, v| match v.inner {
+                BackendCredential::Github(ref github) => Some(github.clone()),
+                _ => None,
+            })
+            .flatten()
     }
 
     pub(crate) fn set_github(&self, gh: impl Into<github::State>) {
@@ -342,6 +355,47 @@ impl Backends {
             .or_insert_with(|| BackendCredential::Github(gh).into());
     }
 
+    pub(crate) fn gitlab(&self) -> Option<gitlab::State> {
+        self.backends
+            .read(&Backend::Gitlab, |_, v| match v.inner {
+                BackendCredential::Gitlab(ref gitlab) => Some(gitlab.clone()),
+                _ => None,
+            })
+            .flatten()
+    }
+
+    pub(crate) fn set_gitlab(&self, gl: gitlab::State) {
+        self.backends
+            .entry(Backend::Gitlab)
+            .and_modify(|existing| {
+                existing.inner = BackendCredential::Gitlab(gl.clone());
+            })
+            .or_insert_with(|| BackendCredential::Gitlab(gl).into());
+    }
+
+    pub(crate) fn bitbucket(&self) -> Option<bitbucket::State> {
+        self.backends
+            .read(&Backend::Bitbucket, |_, v| match v.inner {
+                BackendCredential::Bitbucket(ref bitbucket) => Some(bitbucket.clone()),
+                _ => None,
+            })
+            .flatten()
+    }
+
+    pub(crate) fn set_bitbucket(&self, bb: bitbucket::State) {
+        self.backends
+            .entry(Backend::Bitbucket)
+            .and_modify(|existing| {
+                existing.inner = BackendCredential::Bitbucket(bb.clone());
+            })
+            .or_insert_with(|| BackendCredential::Bitbucket(bb).into());
+    }
+
+    /// Whether there are no credentials for any remote.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.backends.is_empty()
+    }
+
     pub(crate) async fn remove_user(&self) {
         *self.authenticated_user.write().unwrap() = None;
     }
@@ -358,9 +412,54 @@ impl Backends {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub(crate) enum BackendCredential {
     Github(github::State),
+    Gitlab(gitlab::State),
+    Bitbucket(bitbucket::State),
 }
 
 impl BackendCredential {
+    /// The commit that `HEAD` of the remote points at, looked up without fetching anything.
+    pub(crate) async fn remote_head(&self, repo: Repository) -> anyhow::Result<Option<String>> {
+        use gix::remote::{ref_map, Direction};
+        use BackendCredential::*;
+
+        let creds = match self {
+            Github(gh) => gh.auth.creds(&repo).await?,
+            Gitlab(gl) => gl.creds(&repo).await?,
+            Bitbucket(bb) => bb.creds(&repo).await?,
+        };
+
+        tokio::task::spawn_blocking(move || {
+            let git = gix::open(&repo.disk_path)?;
+            let remote = git
+                .find_default_remote(Direction::Fetch)
+                .context("no remote found")??;
+
+            let connection = {
+                let c = remote.connect(Direction::Fetch)?;
+                match creds {
+                    Some(auth) => c.with_credentials(creds_callback!(auth)),
+                    None => c,
+                }
+            };
+
+            // The refspecs would otherwise filter `HEAD` out of the advertised refs.
+            let options = ref_map::Options {
+                prefix_from_spec_as_filter_on_remote: false,
+                ..Default::default()
+            };
+            let (ref_map, _) = connection.ref_map(gix::progress::Discard, options)?;
+
+            Ok(ref_map.remote_refs.iter().find_map(|r| {
+                let (name, target, peeled) = r.unpack();
+                (name == "HEAD")
+                    .then(|| peeled.or(target))
+                    .flatten()
+                    .map(|id| id.to_string())
+            }))
+        })
+        .await?
+    }
+
     #[tracing::instrument(fields(repo=%handle.reporef), skip_all)]
     pub(crate) async fn clone_or_pull(
         &self,
@@ -368,9 +467,12 @@ impl BackendCredential {
         repo: Repository,
     ) -> Result<SyncStatus> {
         use BackendCredential::*;
-        let Github(gh) = self;
 
-        let creds = gh.auth.creds(&repo).await?;
+        let creds = match self {
+            Github(gh) => gh.auth.creds(&repo).await?,
+            Gitlab(gl) => gl.creds(&repo).await?,
+            Bitbucket(bb) => bb.creds(&repo).await?,
+        };
         let clone = || async {
             handle.set_status(|_| SyncStatus::Syncing);
             git_clone(
@@ -386,6 +488,49 @@ impl BackendCredential {
             git_pull(&creds, &repo, &handle.pipes, handle.shallow_config.clone()).await
         };
 
+        let config = &handle.app.config;
+        if objects::is_shared(config, &repo.disk_path) {
+            if !repo.disk_path.exists() {
+                handle.set_status(|_| SyncStatus::Syncing);
+            }
+
+            let shared = objects::sync(
+                config,
+                &handle.reporef,
+                &creds,
+                &repo.remote.to_string(),
+                &repo.disk_path,
+                &handle.pipes,
+                handle.shallow_config.clone(),
+            )
+            .await;
+
+            match shared {
+                Ok(()) => return Ok(SyncStatus::Queued),
+                Err(_) if handle.pipes.is_cancelled() => return Err(RemoteError::Interrupted),
+                // A corrupt store would fail every sync, so the repository gets objects of its own
+                Err(err) => {
+                    warn!(
+                        ?err,
+                        "failed to sync through the shared object store, cloning again"
+                    );
+                    if let Err(err) = tokio::fs::remove_dir_all(&repo.disk_path).await {
+                        if err.kind() != std::io::ErrorKind::NotFound {
+                            return Err(err.into());
+                        }
+                    }
+
+                    return clone().await.map(|_| SyncStatus::Queued).map_err(|e| {
+                        if handle.pipes.is_cancelled() {
+                            RemoteError::Interrupted
+                        } else {
+                            e
+                        }
+                    });
+                }
+            }
+        }
+
         let synced = if repo.last_index_unix_secs == 0 && repo.disk_path.exists() {
             // it is possible syncing was killed, but the repo is
             // intact. pull if the dir exists, then quietly revert
@@ -0,0 +1,321 @@
+//! Bitbucket, on bitbucket.org (Cloud) or a self-hosted Bitbucket Server.
+//!
+//! The two have different REST APIs, which are read into the same [`RemoteRepo`]. Cloud is used
+//! with a username and an app password, and Server with either those or an HTTP access token.
+
+use chrono::{DateTime, Utc};
+use reqwest::{RequestBuilder, StatusCode, Url};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+
+use crate::repo::{GitRemote, RepoRemote, Repository};
+
+use super::*;
+
+const CLOUD_HOST: &str = "bitbucket.org";
+
+const CLOUD_API: &str = "https://api.bitbucket.org/2.0/";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct State {
+    /// `https://bitbucket.org`, or the address of a Bitbucket Server
+    pub base_url: Url,
+    pub auth: Auth,
+    #[serde(skip)]
+    pub repositories: Arc<Vec<RemoteRepo>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) enum Auth {
+    /// An app password on Cloud, or the password of the user on Server.
+    AppPassword {
+        username: String,
+        #[serde(serialize_with = "crate::config::serialize_secret_str")]
+        password: SecretString,
+    },
+    /// An HTTP access token of Bitbucket Server.
+    Token(#[serde(serialize_with = "crate::config::serialize_secret_str")] SecretString),
+}
+
+/// A repository that can be indexed, from either API.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct RemoteRepo {
+    /// `workspace/repo` on Cloud, or `PROJECT/repo` on Server
+    pub path: String,
+    pub clone_urls: Vec<String>,
+    pub private: bool,
+    /// Bitbucket Server doesn't report when repositories were last pushed to
+    pub updated_on: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct Link {
+    href: String,
+}
+
+#[derive(Deserialize)]
+struct CloneLinks {
+    clone: Vec<Link>,
+}
+
+#[derive(Deserialize)]
+struct CloudPage {
+    values: Vec<CloudRepo>,
+    next: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CloudRepo {
+    full_name: String,
+    is_private: bool,
+    updated_on: DateTime<Utc>,
+    links: CloneLinks,
+}
+
+impl From<CloudRepo> for RemoteRepo {
+    fn from(repo: CloudRepo) -> Self {
+        Self {
+            path: repo.full_name,
+            clone_urls: repo.links.clone.into_iter().map(|link| link.href).collect(),
+            private: repo.is_private,
+            updated_on: Some(repo.updated_on),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ServerPage {
+    values: Vec<ServerRepo>,
+    is_last_page: bool,
+    next_page_start: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ServerRepo {
+    slug: String,
+    project: ServerProject,
+    public: bool,
+    links: CloneLinks,
+}
+
+#[derive(Deserialize)]
+struct ServerProject {
+    key: String,
+}
+
+impl From<ServerRepo> for RemoteRepo {
+    fn from(repo: ServerRepo) -> Self {
+        Self {
+            path: format!("{}/{}", repo.project.key, repo.slug),
+            clone_urls: repo.links.clone.into_iter().map(|link| link.href).collect(),
+            private: !repo.public,
+            updated_on: None,
+        }
+    }
+}
+
+impl State {
+    pub(crate) fn with_auth(base_url: Url, auth: Auth) -> Self {
+        Self {
+            base_url,
+            auth,
+            repositories: Arc::default(),
+        }
+    }
+
+    pub(crate) fn is_cloud(&self) -> bool {
+        self.host() == CLOUD_HOST
+    }
+
+    /// The host of the instance, with the port if it isn't the default one.
+    pub(crate) fn host(&self) -> String {
+        let host = self.base_url.host_str().unwrap_or_default();
+        match self.base_url.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_owned(),
+        }
+    }
+
+    /// The repository on this instance at `path`.
+    pub(crate) fn repo_ref(&self, path: &str) -> std::result::Result<RepoRef, RepoError> {
+        RepoRef::new(Backend::Bitbucket, &format!("{}/{path}", self.host()))
+    }
+
+    /// Check that the credentials are still accepted.
+    pub(crate) async fn validate(&self) -> Result<()> {
+        let request = if self.is_cloud() {
+            self.get("user")?
+        } else {
+            self.get("repos")?.query(&[("limit", "1")])
+        };
+
+        send(request).await.map(|_| ())
+    }
+
+    /// Repositories the credentials can read, which can be indexed.
+    pub(crate) async fn current_repo_list(&self) -> Result<Vec<RemoteRepo>> {
+        let mut repos = vec![];
+
+        if self.is_cloud() {
+            let mut request = self
+                .get("repositories")?
+                .query(&[("role", "member"), ("pagelen", "100")]);
+
+            loop {
+                let page: CloudPage = send(request)
+                    .await?
+                    .json()
+                    .await
+                    .map_err(RemoteError::Bitbucket)?;
+                repos.extend(page.values.into_iter().map(RemoteRepo::from));
+
+                // The next page is a full URL, with the query of the first one
+                let Some(next) = page.next else {
+                    break;
+                };
+
+                request = self.authorize(reqwest::Client::new().get(next));
+            }
+        } else {
+            let mut start = 0;
+
+            loop {
+                let request = self
+                    .get("repos")?
+                    .query(&[("limit", "100"), ("start", &start.to_string())]);
+
+                let page: ServerPage = send(request)
+                    .await?
+                    .json()
+                    .await
+                    .map_err(RemoteError::Bitbucket)?;
+                repos.extend(page.values.into_iter().map(RemoteRepo::from));
+
+                match page.next_page_start {
+                    Some(next) if !page.is_last_page => start = next,
+                    _ => break,
+                }
+            }
+        }
+
+        Ok(repos)
+    }
+
+    /// Create a new object with the updated repositories list.
+    pub(crate) fn update_repositories(self, repos: Vec<RemoteRepo>) -> Self {
+        Self {
+            repositories: repos.into(),
+            ..self
+        }
+    }
+
+    /// Return credentials for private repositories, and no credentials for public ones.
+    pub(crate) async fn creds(&self, repo: &Repository) -> Result<Option<GitCreds>> {
+        let RepoRemote::Git(GitRemote { ref address, .. }) = repo.remote else {
+            return Err(RemoteError::NotSupported("bitbucket without git backend"));
+        };
+
+        let private = if self.is_cloud() {
+            let details: CloudRepo = send(self.get(&format!("repositories/{address}"))?)
+                .await?
+                .json()
+                .await
+                .map_err(RemoteError::Bitbucket)?;
+            details.is_private
+        } else {
+            let (project, slug) = address
+                .trim_start_matches("scm/")
+                .split_once('/')
+                .ok_or(RemoteError::NotSupported("invalid repo address"))?;
+
+            let details: ServerRepo = send(self.get(&format!("projects/{project}/repos/{slug}"))?)
+                .await?
+                .json()
+                .await
+                .map_err(RemoteError::Bitbucket)?;
+            !details.public
+        };
+
+        Ok(private.then(|| self.git_cred()))
+    }
+
+    fn git_cred(&self) -> GitCreds {
+        match self.auth {
+            Auth::AppPassword {
+                ref username,
+                ref password,
+            } => GitCreds {
+                username: username.clone(),
+                password: password.expose_secret().into(),
+            },
+            Auth::Token(ref token) => GitCreds {
+                username: "x-token-auth".into(),
+                password: token.expose_secret().into(),
+            },
+        }
+    }
+
+    fn get(&self, endpoint: &str) -> Result<RequestBuilder> {
+        let api = if self.is_cloud() {
+            CLOUD_API.parse().expect("valid URL")
+        } else {
+            self.base_url
+                .join("rest/api/1.0/")
+                .map_err(|_| RemoteError::NotSupported("invalid Bitbucket URL"))?
+        };
+
+        let url = api
+            .join(endpoint)
+            .map_err(|_| RemoteError::NotSupported("invalid Bitbucket URL"))?;
+
+        Ok(self.authorize(reqwest::Client::new().get(url)))
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match self.auth {
+            Auth::AppPassword {
+                ref username,
+                ref password,
+            } => request.basic_auth(username, Some(password.expose_secret())),
+            Auth::Token(ref token) => request.bearer_auth(token.expose_secret()),
+        }
+    }
+}
+
+/// Turn Bitbucket's status codes into the errors that syncing understands.
+async fn send(request: RequestBuilder) -> Result<reqwest::Response> {
+    let response = request.send().await.map_err(RemoteError::Bitbucket)?;
+
+    match response.status() {
+        StatusCode::NOT_FOUND => Err(RemoteError::RemoteNotFound),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(RemoteError::PermissionDenied),
+        _ => response.error_for_status().map_err(RemoteError::Bitbucket),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_server_repos() {
+        let page: ServerPage = serde_json::from_value(serde_json::json!({
+            "values": [{
+                "slug": "repo",
+                "project": { "key": "PROJ" },
+                "public": false,
+                "links": { "clone": [
+                    { "href": "https://git.example.com/scm/proj/repo.git", "name": "http" },
+                ]},
+            }],
+            "isLastPage": true,
+        }))
+        .unwrap();
+
+        let repo = RemoteRepo::from(page.values.into_iter().next().unwrap());
+        assert_eq!(repo.path, "PROJ/repo");
+        assert!(repo.private);
+        assert!(page.is_last_page);
+    }
+}
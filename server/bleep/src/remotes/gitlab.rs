@@ -0,0 +1,377 @@
+//! GitLab, on gitlab.com or a self-hosted instance.
+//!
+//! GitLab is reached through its REST API, either with the OAuth token of signing in with GitLab,
+//! or with a personal, group or project access token. Repositories are cloned and fetched with the
+//! same git machinery as GitHub, with the token as the password.
+
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use reqwest::{RequestBuilder, StatusCode, Url};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    repo::{GitRemote, RepoRemote, Repository},
+    Configuration,
+};
+
+use super::*;
+
+/// The scopes to ask for when signing in, to list projects and clone them.
+const SCOPES: &str = "read_api read_repository read_user";
+
+/// How long a sign-in may take, from opening the GitLab page to coming back.
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct State {
+    /// The instance, like `https://gitlab.com`
+    pub base_url: Url,
+    pub auth: Auth,
+    #[serde(skip)]
+    pub projects: Arc<Vec<Project>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) enum Auth {
+    OAuth {
+        #[serde(serialize_with = "crate::config::serialize_secret_str")]
+        access_token: SecretString,
+        #[serde(serialize_with = "crate::config::serialize_secret_str")]
+        refresh_token: SecretString,
+        expires_at: DateTime<Utc>,
+    },
+    /// A personal, group or project access token.
+    Token(#[serde(serialize_with = "crate::config::serialize_secret_str")] SecretString),
+}
+
+/// The fields we use of a GitLab project.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct Project {
+    pub path_with_namespace: String,
+    pub http_url_to_repo: String,
+    pub ssh_url_to_repo: String,
+    /// One of `private`, `internal` or `public`
+    pub visibility: String,
+    pub last_activity_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct User {
+    username: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: SecretString,
+    refresh_token: SecretString,
+    expires_in: i64,
+}
+
+impl State {
+    pub(crate) fn with_auth(base_url: Url, auth: Auth) -> Self {
+        Self {
+            base_url,
+            auth,
+            projects: Arc::default(),
+        }
+    }
+
+    /// The host of the instance, with the port if it isn't the default one.
+    pub(crate) fn host(&self) -> String {
+        host(&self.base_url)
+    }
+
+    /// The repository of a project on this instance, by its path with namespace.
+    pub(crate) fn repo_ref(&self, path: &str) -> std::result::Result<RepoRef, RepoError> {
+        RepoRef::new(Backend::Gitlab, &format!("{}/{path}", self.host()))
+    }
+
+    pub(crate) fn expiry(&self) -> Option<DateTime<Utc>> {
+        match self.auth {
+            Auth::OAuth { expires_at, .. } => Some(expires_at),
+            Auth::Token(_) => None,
+        }
+    }
+
+    /// The username of the token, which fails if the token was revoked.
+    pub(crate) async fn validate(&self) -> Result<String> {
+        let user: User = send(self.get("user")?)
+            .await?
+            .json()
+            .await
+            .map_err(RemoteError::GitLab)?;
+
+        Ok(user.username)
+    }
+
+    /// Projects the token is a member of, which can be indexed.
+    pub(crate) async fn current_repo_list(&self) -> Result<Vec<Project>> {
+        let mut projects = vec![];
+
+        for page in 1.. {
+            let request = self.get("projects")?.query(&[
+                ("membership", "true"),
+                ("archived", "false"),
+                ("per_page", "100"),
+                ("page", &page.to_string()),
+            ]);
+
+            let batch: Vec<Project> = send(request)
+                .await?
+                .json()
+                .await
+                .map_err(RemoteError::GitLab)?;
+
+            if batch.is_empty() {
+                break;
+            }
+
+            projects.extend(batch);
+        }
+
+        Ok(projects)
+    }
+
+    /// Create a new object with the updated list of projects.
+    pub(crate) fn update_repositories(self, projects: Vec<Project>) -> Self {
+        Self {
+            projects: projects.into(),
+            ..self
+        }
+    }
+
+    /// Return credentials for private and internal projects, and no credentials for public ones.
+    pub(crate) async fn creds(&self, repo: &Repository) -> Result<Option<GitCreds>> {
+        let RepoRemote::Git(GitRemote { ref address, .. }) = repo.remote else {
+            return Err(RemoteError::NotSupported("gitlab without git backend"));
+        };
+
+        let id = url::form_urlencoded::byte_serialize(address.as_bytes()).collect::<String>();
+        let project: Project = send(self.get(&format!("projects/{id}"))?)
+            .await?
+            .json()
+            .await
+            .map_err(RemoteError::GitLab)?;
+
+        Ok((project.visibility != "public").then(|| self.git_cred()))
+    }
+
+    /// Exchange the refresh token for a new access token, if signed in with OAuth.
+    pub(crate) async fn refresh(self, config: &Configuration) -> Result<Self> {
+        let Auth::OAuth {
+            ref refresh_token, ..
+        } = self.auth
+        else {
+            return Ok(self);
+        };
+
+        let auth = request_token(
+            config,
+            &[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token.expose_secret()),
+            ],
+        )
+        .await?;
+
+        Ok(Self { auth, ..self })
+    }
+
+    fn git_cred(&self) -> GitCreds {
+        let token = match self.auth {
+            Auth::OAuth {
+                ref access_token, ..
+            } => access_token,
+            Auth::Token(ref token) => token,
+        };
+
+        // GitLab takes any username with access tokens, and requires this one with OAuth tokens
+        GitCreds {
+            username: "oauth2".into(),
+            password: token.expose_secret().into(),
+        }
+    }
+
+    fn get(&self, endpoint: &str) -> Result<RequestBuilder> {
+        let url = self
+            .base_url
+            .join(&format!("api/v4/{endpoint}"))
+            .map_err(|_| RemoteError::NotSupported("invalid GitLab URL"))?;
+
+        let request = reqwest::Client::new().get(url);
+        Ok(match self.auth {
+            Auth::OAuth {
+                ref access_token, ..
+            } => request.bearer_auth(access_token.expose_secret()),
+            Auth::Token(ref token) => request.header("PRIVATE-TOKEN", token.expose_secret()),
+        })
+    }
+}
+
+/// Turn GitLab's status codes into the errors that syncing understands.
+async fn send(request: RequestBuilder) -> Result<reqwest::Response> {
+    let response = request.send().await.map_err(RemoteError::GitLab)?;
+
+    match response.status() {
+        StatusCode::NOT_FOUND => Err(RemoteError::RemoteNotFound),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(RemoteError::PermissionDenied),
+        _ => response.error_for_status().map_err(RemoteError::GitLab),
+    }
+}
+
+fn host(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_owned(),
+    }
+}
+
+/// Sign-ins that were started, by their `state`, to tell them apart from forged redirects.
+#[derive(Clone, Default)]
+pub(crate) struct PendingLogins(Arc<scc::HashMap<String, Instant>>);
+
+impl PendingLogins {
+    pub(crate) fn start(&self) -> String {
+        self.0
+            .retain(|_, started| started.elapsed() < LOGIN_TIMEOUT);
+
+        let state = uuid::Uuid::new_v4().to_string();
+        _ = self.0.insert(state.clone(), Instant::now());
+        state
+    }
+
+    pub(crate) fn finish(&self, state: &str) -> bool {
+        self.0
+            .remove(state)
+            .map_or(false, |(_, started)| started.elapsed() < LOGIN_TIMEOUT)
+    }
+}
+
+/// The address users reach this instance at, which GitLab sends them back to after signing in.
+pub(crate) fn instance_url(config: &Configuration) -> Result<String> {
+    let domain = config
+        .instance_domain
+        .as_deref()
+        .ok_or(RemoteError::NotSupported(
+            "signing in with GitLab needs an instance domain",
+        ))?;
+
+    let domain = domain.trim_end_matches('/');
+    let scheme = if domain.contains("://") {
+        ""
+    } else {
+        "https://"
+    };
+
+    Ok(format!("{scheme}{domain}{}", config.base_path()))
+}
+
+fn redirect_uri(config: &Configuration) -> Result<String> {
+    Ok(format!(
+        "{}/api/auth/gitlab/complete",
+        instance_url(config)?
+    ))
+}
+
+/// The GitLab page to sign in on.
+pub(crate) fn authorize_url(config: &Configuration, state: &str) -> Result<Url> {
+    let client_id = config
+        .gitlab_client_id
+        .as_deref()
+        .ok_or(RemoteError::NotSupported("GitLab OAuth is not configured"))?;
+
+    let mut url = config
+        .gitlab_url()
+        .join("oauth/authorize")
+        .map_err(|_| RemoteError::NotSupported("invalid GitLab URL"))?;
+
+    url.query_pairs_mut().extend_pairs(&[
+        ("client_id", client_id),
+        ("redirect_uri", &redirect_uri(config)?),
+        ("response_type", "code"),
+        ("state", state),
+        ("scope", SCOPES),
+    ]);
+
+    Ok(url)
+}
+
+/// Finish signing in, with the code GitLab redirected back with.
+pub(crate) async fn exchange_code(config: &Configuration, code: &str) -> Result<State> {
+    let auth = request_token(
+        config,
+        &[("grant_type", "authorization_code"), ("code", code)],
+    )
+    .await?;
+
+    Ok(State::with_auth(config.gitlab_url(), auth))
+}
+
+async fn request_token(config: &Configuration, grant: &[(&str, &str)]) -> Result<Auth> {
+    let (Some(client_id), Some(client_secret)) = (
+        config.gitlab_client_id.as_deref(),
+        config.gitlab_client_secret.as_ref(),
+    ) else {
+        return Err(RemoteError::NotSupported("GitLab OAuth is not configured"));
+    };
+
+    let url = config
+        .gitlab_url()
+        .join("oauth/token")
+        .map_err(|_| RemoteError::NotSupported("invalid GitLab URL"))?;
+
+    let redirect_uri = redirect_uri(config)?;
+    let mut form = vec![
+        ("client_id", client_id),
+        ("client_secret", client_secret.expose_secret()),
+        ("redirect_uri", &redirect_uri),
+    ];
+    form.extend_from_slice(grant);
+
+    let response = reqwest::Client::new()
+        .post(url)
+        .form(&form)
+        .send()
+        .await
+        .map_err(RemoteError::GitLab)?;
+
+    // Expired and revoked grants are turned down with `invalid_grant`
+    if response.status() == StatusCode::BAD_REQUEST {
+        return Err(RemoteError::PermissionDenied);
+    }
+
+    let token: TokenResponse = response
+        .error_for_status()
+        .map_err(RemoteError::GitLab)?
+        .json()
+        .await
+        .map_err(RemoteError::GitLab)?;
+
+    Ok(Auth::OAuth {
+        access_token: token.access_token,
+        refresh_token: token.refresh_token,
+        expires_at: Utc::now() + chrono::Duration::seconds(token.expires_in),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_repos_by_host() {
+        let state = State::with_auth(
+            "https://git.example.com:8443/".parse().unwrap(),
+            Auth::Token(SecretString::new("token".into())),
+        );
+
+        assert_eq!(
+            state.repo_ref("group/sub/project").unwrap().to_string(),
+            "gitlab/git.example.com:8443/group/sub/project"
+        );
+        assert_eq!(host(&"https://gitlab.com".parse().unwrap()), "gitlab.com");
+    }
+}
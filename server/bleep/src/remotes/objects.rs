@@ -0,0 +1,371 @@
+//! A content-addressed object store shared by all remote repositories.
+//!
+//! Git objects are addressed by their hash, so forks and vendored copies of a repository have
+//! mostly identical objects. Instead of every clone keeping its own copy, remote repositories are
+//! fetched into a single bare repository, with their refs under a namespace per repository. The
+//! clones themselves are thin repositories that only hold refs, and read objects through
+//! `objects/info/alternates`.
+//!
+//! Fetches negotiate with the refs of every repository in the store, so a fork only downloads the
+//! objects its parent doesn't already have.
+//!
+//! Git only tracks a single shallow boundary per object store, so thin clones share the
+//! boundary of the store. Repositories may see less history than they asked for when a fork was
+//! fetched with a smaller depth, but no dangling parents. For the same reason, fetches into the
+//! store run one at a time.
+//!
+//! The store is opt-in, with `--shared-objects`. Objects that only removed repositories used are
+//! pruned with `git gc`, as gitoxide can't repack yet, so reclaiming their space needs `git` on
+//! the `PATH`.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use anyhow::Context;
+use gix::{
+    bstr::ByteSlice,
+    protocol::handshake,
+    refs::{
+        transaction::{Change, LogChange, PreviousValue, RefEdit},
+        FullName, Target,
+    },
+    remote::{fetch::Shallow, Direction},
+};
+use tracing::{debug, warn};
+
+use super::{creds_callback, GitCreds, Result};
+use crate::{background::SyncPipes, repo::RepoRef, Configuration};
+
+const STORE_DIR: &str = ".objects";
+
+/// Held while the store is written to. There is a single store per instance.
+static STORE_LOCK: Mutex<()> = Mutex::new(());
+
+fn lock_store() -> MutexGuard<'static, ()> {
+    STORE_LOCK.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The bare repository holding the objects of all remote repositories.
+pub(crate) fn store_path(config: &Configuration) -> PathBuf {
+    config.source.repo_path_for_name(STORE_DIR)
+}
+
+/// Whether a repository should be synced through the shared store.
+///
+/// Existing clones that have their own objects are left as they are, until they are cloned
+/// again. Thin clones keep using the store even if it is disabled later, as they have no objects
+/// of their own.
+pub(crate) fn is_shared(config: &Configuration, disk_path: &Path) -> bool {
+    if disk_path.exists() {
+        return alternates_path(disk_path).exists();
+    }
+
+    config.shared_objects
+}
+
+fn alternates_path(disk_path: &Path) -> PathBuf {
+    disk_path.join("objects").join("info").join("alternates")
+}
+
+/// The prefix of the refs of a repository in the shared store.
+///
+/// Repository names can contain characters that aren't valid in ref names, so namespaces use
+/// their hash instead.
+fn namespace(reporef: &RepoRef) -> String {
+    let hash = blake3::hash(reporef.to_string().as_bytes()).to_hex();
+    format!("refs/namespaces/{}/", &hash[..16])
+}
+
+/// Fetch a repository into the shared store, and point its thin clone at the new refs.
+pub(crate) async fn sync(
+    config: &Configuration,
+    reporef: &RepoRef,
+    auth: &Option<GitCreds>,
+    url: &str,
+    target: &Path,
+    pipes: &SyncPipes,
+    shallow: Shallow,
+) -> Result<()> {
+    let store = store_path(config);
+    let namespace = namespace(reporef);
+    let url = url.to_owned();
+    let target = target.to_owned();
+    let auth = auth.clone();
+
+    let git_status = pipes.git_sync_progress();
+    let interrupt = pipes.is_interrupted();
+
+    tokio::task::spawn_blocking(move || {
+        let _store = lock_store();
+        let shared = open_or_init(&store)?;
+        let remote = shared
+            .remote_at(url.as_str())
+            .context("invalid remote url")?
+            .with_refspecs(
+                [
+                    format!("+HEAD:{namespace}HEAD"),
+                    format!("+refs/heads/*:{namespace}refs/heads/*"),
+                ]
+                .iter()
+                .map(String::as_str),
+                Direction::Fetch,
+            )
+            .context("invalid refspecs")?;
+
+        let connection = {
+            let c = remote.connect(Direction::Fetch)?;
+            match auth {
+                Some(auth) => c.with_credentials(creds_callback!(auth)),
+                None => c,
+            }
+        };
+
+        let outcome = connection
+            .prepare_fetch(gix::progress::Discard, Default::default())?
+            .with_shallow(shallow)
+            .receive(git_status, &interrupt)?;
+
+        let head = outcome.ref_map.remote_refs.iter().find_map(|r| match r {
+            handshake::Ref::Symbolic {
+                full_ref_name,
+                target,
+                ..
+            } if full_ref_name == "HEAD" => Some(target.to_str_lossy().into_owned()),
+            _ => None,
+        });
+
+        update_thin_clone(&shared, &namespace, &store, &target, head)?;
+        Ok(())
+    })
+    .await?
+}
+
+/// Drop the refs of a repository from the shared store, and prune the objects that no other
+/// repository uses.
+pub(crate) async fn forget(config: &Configuration, reporef: &RepoRef) -> Result<()> {
+    let store = store_path(config);
+    let namespace = namespace(reporef);
+
+    if !store.exists() {
+        return Ok(());
+    }
+
+    tokio::task::spawn_blocking(move || {
+        let _store = lock_store();
+        let empty = delete_refs(&store, &namespace)?;
+
+        if empty {
+            debug!(?store, "removing empty shared object store");
+            std::fs::remove_dir_all(&store)?;
+        } else {
+            prune(&store);
+        }
+
+        Ok(())
+    })
+    .await?
+}
+
+/// Delete the refs under a namespace, returning whether the store has no refs left.
+fn delete_refs(store: &Path, namespace: &str) -> Result<bool> {
+    let shared = gix::open(store)?;
+    let refs = shared.references().context("failed to read refs")?;
+
+    for r in refs
+        .prefixed(namespace)
+        .context("failed to read refs")?
+        .filter_map(std::result::Result::ok)
+    {
+        r.delete().context("failed to delete ref")?;
+    }
+
+    let remaining = shared.references().context("failed to read refs")?;
+    let empty = remaining
+        .all()
+        .context("failed to read refs")?
+        .next()
+        .is_none();
+
+    Ok(empty)
+}
+
+/// Drop the objects that no ref of the store reaches any more.
+fn prune(store: &Path) {
+    let gc = std::process::Command::new("git")
+        .arg("-C")
+        .arg(store)
+        .args(["gc", "--prune=now", "--quiet"])
+        .status();
+
+    match gc {
+        Ok(status) if status.success() => debug!(?store, "pruned shared object store"),
+        Ok(status) => warn!(?store, ?status, "failed to prune shared object store"),
+        Err(err) => warn!(
+            ?err,
+            "can't run `git gc`, objects of removed repositories stay in the shared store"
+        ),
+    }
+}
+
+fn open_or_init(store: &Path) -> Result<gix::Repository> {
+    if store.exists() {
+        return Ok(gix::open(store)?);
+    }
+
+    debug!(?store, "creating shared object store");
+    Ok(gix::init_bare(store).context("failed to create shared object store")?)
+}
+
+/// Make the refs of a thin clone match its namespace in the shared store.
+fn update_thin_clone(
+    shared: &gix::Repository,
+    namespace: &str,
+    store: &Path,
+    target: &Path,
+    head: Option<String>,
+) -> Result<()> {
+    let clone = if target.exists() {
+        gix::open(target)?
+    } else {
+        let clone = gix::init_bare(target).context("failed to create clone")?;
+        let objects = store.canonicalize()?.join("objects");
+        std::fs::write(
+            alternates_path(target),
+            format!("{}\n", objects.to_string_lossy()),
+        )?;
+        clone
+    };
+
+    match std::fs::read(store.join("shallow")) {
+        Ok(boundary) => std::fs::write(target.join("shallow"), boundary)?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    let fetched = shared
+        .references()
+        .context("failed to read refs")?
+        .prefixed(namespace)
+        .context("failed to read refs")?
+        .filter_map(std::result::Result::ok)
+        .filter_map(|r| {
+            let name = r.name().as_bstr().to_str().ok()?;
+            let name = name.strip_prefix(namespace)?.to_owned();
+            Some((name, r.try_id()?.detach()))
+        })
+        .collect::<Vec<_>>();
+
+    // Lay the refs out like a bare clone: remote branches under `origin`, and the default branch
+    // checked out locally.
+    let mut refs = fetched
+        .iter()
+        .filter_map(|(name, id)| {
+            let branch = name.strip_prefix("refs/heads/")?;
+            Some((format!("refs/remotes/origin/{branch}"), *id))
+        })
+        .collect::<Vec<_>>();
+
+    let head_id = fetched
+        .iter()
+        .find_map(|(name, id)| (name == "HEAD").then_some(*id));
+
+    if let (Some(head), Some(id)) = (&head, head_id) {
+        refs.push((head.clone(), id));
+    }
+
+    for (name, id) in &refs {
+        clone
+            .reference(
+                name.as_str(),
+                *id,
+                PreviousValue::Any,
+                "sync from shared store",
+            )
+            .context("failed to update ref")?;
+    }
+
+    let stale = clone
+        .references()
+        .context("failed to read refs")?
+        .all()
+        .context("failed to read refs")?
+        .filter_map(std::result::Result::ok)
+        .filter(|r| {
+            let name = r.name().as_bstr();
+            !refs.iter().any(|(kept, _)| name == kept.as_str())
+        })
+        .collect::<Vec<_>>();
+
+    for r in stale {
+        r.delete().context("failed to delete ref")?;
+    }
+
+    if let Some(head) = head {
+        let head: FullName = head.as_str().try_into().context("invalid HEAD")?;
+        clone
+            .edit_reference(RefEdit {
+                change: Change::Update {
+                    log: LogChange::default(),
+                    expected: PreviousValue::Any,
+                    new: Target::Symbolic(head),
+                },
+                name: "HEAD".try_into().context("invalid ref name")?,
+                deref: false,
+            })
+            .context("failed to update HEAD")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespaces_are_valid_ref_names() {
+        let reporef = "github.com/BloopAI/.github".parse::<RepoRef>().unwrap();
+        let namespace = namespace(&reporef);
+
+        assert!(namespace.starts_with("refs/namespaces/"));
+        assert_eq!(namespace, super::namespace(&reporef));
+        assert_ne!(
+            namespace,
+            super::namespace(&"github.com/BloopAI/bloop".parse().unwrap())
+        );
+
+        let name = format!("{namespace}refs/heads/main");
+        assert!(FullName::try_from(name.as_str()).is_ok());
+    }
+
+    #[test]
+    fn forgets_refs_of_one_repository() {
+        let dir = tempdir::TempDir::new("objects").unwrap();
+        let store = dir.path().join(STORE_DIR);
+        let shared = open_or_init(&store).unwrap();
+        let tree = shared.empty_tree().id;
+
+        let [bloop, fork] = ["github.com/BloopAI/bloop", "github.com/fork/bloop"]
+            .map(|name| namespace(&name.parse().unwrap()));
+
+        for namespace in [&bloop, &fork] {
+            shared
+                .reference(
+                    format!("{namespace}refs/heads/main"),
+                    tree,
+                    PreviousValue::Any,
+                    "test",
+                )
+                .unwrap();
+        }
+
+        assert!(!delete_refs(&store, &fork).unwrap());
+        assert!(shared
+            .find_reference(format!("{bloop}refs/heads/main").as_str())
+            .is_ok());
+
+        assert!(delete_refs(&store, &bloop).unwrap());
+    }
+}
@@ -2,6 +2,7 @@ use anyhow::Context;
 use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
 use std::{
     fmt::{self, Display},
+    num::NonZeroU32,
     path::{Path, PathBuf},
     str::FromStr,
     sync::Arc,
@@ -9,13 +10,21 @@ use std::{
 };
 use tracing::debug;
 
-use crate::state::get_relative_path;
+use crate::state::{get_relative_path, RepositoryPool};
 
+pub(crate) mod api_surface;
+pub(crate) mod changes;
+pub(crate) mod health;
+pub(crate) mod history;
 pub(crate) mod iterator;
+pub(crate) mod sql_schema;
 use iterator::language;
 
 pub use iterator::{BranchFilter, BranchFilterConfig, FileFilter, FileFilterConfig, FilterUpdate};
 
+/// The history fetched when a shallow clone is turned into a full one.
+pub(crate) const DEFAULT_CLONE_DEPTH: u32 = 1000;
+
 #[derive(thiserror::Error, Debug)]
 #[error("repository locked")]
 pub struct RepoLocked;
@@ -26,6 +35,8 @@ pub struct RepoLocked;
 pub enum Backend {
     Local,
     Github,
+    Gitlab,
+    Bitbucket,
 }
 
 // Repository identifier
@@ -44,6 +55,14 @@ impl RepoRef {
                 backend,
                 name: name.as_ref().to_owned(),
             }),
+            // GitLab and Bitbucket can be self-hosted, so the name starts with the host
+            Gitlab | Bitbucket => match name.as_ref().split_once('/') {
+                Some((host, path)) if !host.is_empty() && !path.is_empty() => Ok(RepoRef {
+                    backend,
+                    name: name.as_ref().to_owned(),
+                }),
+                _ => Err(RepoError::InvalidBackend),
+            },
             Local => {
                 let path = Path::new(name.as_ref());
 
@@ -71,6 +90,14 @@ impl RepoRef {
         let refstr = components.join("/");
         let pathstr = match refstr.trim_start_matches('/').split_once('/') {
             Some(("github.com", name)) => return RepoRef::new(Backend::Github, name),
+            Some(("gitlab.com", _)) => {
+                return RepoRef::new(Backend::Gitlab, refstr.trim_start_matches('/'))
+            }
+            Some(("gitlab", name)) => return RepoRef::new(Backend::Gitlab, name),
+            Some(("bitbucket.org", _)) => {
+                return RepoRef::new(Backend::Bitbucket, refstr.trim_start_matches('/'))
+            }
+            Some(("bitbucket", name)) => return RepoRef::new(Backend::Bitbucket, name),
             Some(("local", name)) => name,
             _ => &refstr,
         };
@@ -98,13 +125,15 @@ impl RepoRef {
     pub fn indexed_name(&self) -> String {
         // Local repos indexed as: dirname
         // Github repos indexed as: github.com/org/repo
+        // Gitlab repos indexed as: gitlab.com/group/project
+        // Bitbucket repos indexed as: bitbucket.org/workspace/repo
         match self.backend {
             Backend::Local => Path::new(&self.name)
                 .file_name()
                 .expect("last component is `..`")
                 .to_string_lossy()
                 .into(),
-            Backend::Github => format!("{}", self),
+            Backend::Github | Backend::Gitlab | Backend::Bitbucket => format!("{}", self),
         }
     }
 
@@ -112,11 +141,27 @@ impl RepoRef {
         match self.backend {
             // org_name/repo_name
             Backend::Github => self.name.to_owned(),
+            // group/project, with any subgroups
+            Backend::Gitlab => self.host_and_path().1.to_owned(),
+            // workspace/repo on Bitbucket Cloud, or project/repo on Bitbucket Server
+            Backend::Bitbucket => self.host_and_path().1.to_owned(),
             // repo_name
             Backend::Local => self.indexed_name(),
         }
     }
 
+    /// The host and path of a GitLab or Bitbucket repository.
+    ///
+    /// # Panics
+    ///
+    /// When used with refs of other backends
+    pub fn host_and_path(&self) -> (&str, &str) {
+        assert!(matches!(self.backend, Backend::Gitlab | Backend::Bitbucket));
+        self.name
+            .split_once('/')
+            .expect("self-hostable refs always have a host")
+    }
+
     pub fn local_path(&self) -> Option<PathBuf> {
         match self.backend {
             Backend::Local => Some(PathBuf::from(&self.name)),
@@ -147,6 +192,8 @@ impl From<&str> for RepoRef {
     }
 }
 
+/// Unlike deserializing, parsing doesn't check the tenant of the request being handled. Handlers
+/// parse repositories with `tenant::parse_repo` or `tenant::stored_repo` instead.
 impl FromStr for RepoRef {
     type Err = RepoError;
 
@@ -154,6 +201,18 @@ impl FromStr for RepoRef {
         match refstr.trim_start_matches('/').split_once('/') {
             // github.com/...
             Some(("github.com", name)) => RepoRef::new(Backend::Github, name),
+            // gitlab.com/...
+            Some(("gitlab.com", _)) => {
+                RepoRef::new(Backend::Gitlab, refstr.trim_start_matches('/'))
+            }
+            // gitlab/<self-hosted host>/...
+            Some(("gitlab", name)) => RepoRef::new(Backend::Gitlab, name),
+            // bitbucket.org/...
+            Some(("bitbucket.org", _)) => {
+                RepoRef::new(Backend::Bitbucket, refstr.trim_start_matches('/'))
+            }
+            // bitbucket/<Bitbucket Server host>/...
+            Some(("bitbucket", name)) => RepoRef::new(Backend::Bitbucket, name),
             // local/...
             Some(("local", name)) => RepoRef::new(Backend::Local, name),
             _ => Err(RepoError::InvalidBackend),
@@ -165,6 +224,14 @@ impl Display for RepoRef {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.backend() {
             Backend::Github => write!(f, "github.com/{}", self.name()),
+            Backend::Gitlab => match self.host_and_path() {
+                ("gitlab.com", _) => write!(f, "{}", self.name()),
+                _ => write!(f, "gitlab/{}", self.name()),
+            },
+            Backend::Bitbucket => match self.host_and_path() {
+                ("bitbucket.org", _) => write!(f, "{}", self.name()),
+                _ => write!(f, "bitbucket/{}", self.name()),
+            },
             Backend::Local => write!(f, "local/{}", self.name()),
         }
     }
@@ -184,9 +251,16 @@ impl<'de> Deserialize<'de> for RepoRef {
     where
         D: Deserializer<'de>,
     {
-        String::deserialize(deserializer).and_then(|s| {
+        let repo = String::deserialize(deserializer).and_then(|s| {
             RepoRef::from_str(s.as_str()).map_err(|e| D::Error::custom(e.to_string()))
-        })
+        })?;
+
+        // Requests can only name repositories of their tenant
+        if !crate::webserver::tenant::visible(&repo) {
+            return Err(D::Error::custom("Can't find repository"));
+        }
+
+        Ok(repo)
     }
 }
 
@@ -207,6 +281,10 @@ pub struct Repository {
     /// Time of last successful index
     pub last_index_unix_secs: u64,
 
+    /// Commit `HEAD` pointed to at the last successful index
+    #[serde(default)]
+    pub indexed_commit: Option<String>,
+
     /// Most common language
     pub most_common_lang: Option<String>,
 
@@ -227,6 +305,22 @@ pub struct Repository {
     #[serde(default)]
     pub shallow: bool,
 
+    /// How many commits of history to fetch, for repositories that are too large to clone
+    /// completely.
+    ///
+    /// This is used instead of a blobless clone (`--filter=blob:none`). gitoxide can't request
+    /// partial clones yet, and has no way of fetching the missing blobs on demand. Indexing also
+    /// reads every blob of the indexed branches, so a blobless clone would only leave out the
+    /// blobs of older commits, which a depth limit leaves out too.
+    ///
+    /// Defaults to the full history, which clearing the depth fetches again on the next sync.
+    #[serde(default)]
+    pub clone_depth: Option<NonZeroU32>,
+
+    /// Directories to index, like a sparse checkout. Empty for the whole repository.
+    #[serde(default)]
+    pub sparse_paths: Vec<String>,
+
     /// Sync lock
     #[serde(skip)]
     pub locked: bool,
@@ -269,11 +363,14 @@ impl Repository {
             pub_sync_status: SyncStatus::Queued,
             last_index_unix_secs: 0,
             last_commit_unix_secs: 0,
+            indexed_commit: None,
             most_common_lang: None,
             branch_filter: None,
             file_filter: Default::default(),
             locked: false,
             shallow: false,
+            clone_depth: None,
+            sparse_paths: vec![],
             disk_path,
             remote,
         }
@@ -291,15 +388,19 @@ impl Repository {
     /// Pre-scan the repository to provide supporting metadata for a
     /// new indexing operation
     pub async fn get_repo_metadata(&self) -> Arc<RepoMetadata> {
-        let last_commit_unix_secs = gix::open(&self.disk_path)
+        let (last_commit_unix_secs, head_commit) = gix::open(&self.disk_path)
             .context("failed to open git repo")
-            .and_then(|repo| Ok(repo.head()?.peel_to_commit_in_place()?.time()?.seconds))
-            .ok();
+            .and_then(|repo| {
+                let commit = indexed_commit(&repo, &self.remote)?;
+                Ok((commit.time()?.seconds, commit.id.to_string()))
+            })
+            .map_or((None, None), |(time, id)| (Some(time), Some(id)));
 
         let langs = Default::default();
 
         RepoMetadata {
             last_commit_unix_secs,
+            head_commit,
             langs,
         }
         .into()
@@ -339,6 +440,7 @@ impl Repository {
     ) {
         self.last_index_unix_secs = get_unix_time(SystemTime::now());
         self.last_commit_unix_secs = metadata.last_commit_unix_secs.unwrap_or(0);
+        self.indexed_commit = metadata.head_commit.clone();
         self.most_common_lang = metadata
             .langs
             .most_common_lang()
@@ -349,6 +451,10 @@ impl Repository {
             self.branch_filter = bf.patch_into(self.branch_filter.as_ref());
         }
 
+        if let Some(ref paths) = filter_update.sparse_paths {
+            self.sparse_paths = paths.clone();
+        }
+
         self.shallow = shallow;
         self.locked = false;
 
@@ -362,6 +468,35 @@ impl Repository {
             self.sync_status = SyncStatus::Done
         };
     }
+
+    /// Whether a branch, named without its remote, is indexed.
+    pub(crate) fn indexes_branch(&self, reporef: &RepoRef, branch: &str) -> bool {
+        let Ok(git) = gix::open(&self.disk_path) else {
+            return false;
+        };
+
+        let is_head = git
+            .head_name()
+            .ok()
+            .flatten()
+            .map_or(false, |head| head.shorten() == branch);
+
+        // Remote branches are indexed under the name of the remote
+        let name = if reporef.is_local() {
+            branch.to_owned()
+        } else {
+            format!("origin/{branch}")
+        };
+
+        let exists = is_head || matches!(git.try_find_reference(name.as_str()), Ok(Some(_)));
+        let filter = self
+            .branch_filter
+            .as_ref()
+            .map(BranchFilter::from)
+            .unwrap_or_default();
+
+        exists && filter.filter(is_head, &name)
+    }
 }
 
 fn get_unix_time(time: SystemTime) -> u64 {
@@ -373,6 +508,7 @@ fn get_unix_time(time: SystemTime) -> u64 {
 #[derive(Debug)]
 pub struct RepoMetadata {
     pub last_commit_unix_secs: Option<i64>,
+    pub head_commit: Option<String>,
     pub langs: language::LanguageInfo,
 }
 
@@ -440,6 +576,62 @@ pub enum GitProtocol {
     Ssh,
 }
 
+/// The commit of a repository that gets indexed.
+///
+/// Clones are indexed from the remote-tracking branch of `HEAD`, which is what fetching advances,
+/// rather than from `HEAD` itself.
+pub(crate) fn indexed_commit<'repo>(
+    repo: &'repo gix::Repository,
+    remote: &RepoRemote,
+) -> anyhow::Result<gix::Commit<'repo>> {
+    let mut head = repo.head()?;
+
+    let tracking = match (remote, head.referent_name()) {
+        (RepoRemote::Git(_), Some(name)) => repo
+            .find_reference(&format!("refs/remotes/origin/{}", name.shorten()))
+            .ok(),
+        _ => None,
+    };
+
+    Ok(match tracking {
+        Some(mut tracking) => tracking
+            .peel_to_id_in_place()?
+            .object()?
+            .try_into_commit()?,
+        None => head.peel_to_commit_in_place()?,
+    })
+}
+
+/// The branch to search, or `None` if no repository indexes it, so that the default branch is
+/// searched instead.
+///
+/// Only `repo_ref` is checked, if it's given.
+pub(crate) async fn indexed_branch(
+    pool: &RepositoryPool,
+    repo_ref: Option<&RepoRef>,
+    branch: Option<String>,
+) -> Option<String> {
+    let branch = branch?;
+    let indexed = match repo_ref {
+        Some(repo_ref) => pool
+            .read_async(repo_ref, |key, repo| repo.indexes_branch(key, &branch))
+            .await
+            .unwrap_or_default(),
+        None => {
+            let mut indexed = false;
+            pool.scan_async(|key, repo| indexed = indexed || repo.indexes_branch(key, &branch))
+                .await;
+            indexed
+        }
+    };
+
+    if !indexed {
+        debug!(%branch, "branch isn't indexed, searching the default branch");
+    }
+
+    indexed.then_some(branch)
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum RepoRemote {
@@ -458,6 +650,34 @@ impl<T: AsRef<RepoRef>> From<T> for RepoRemote {
                 host: "github.com".to_owned(),
                 address: name.to_owned(),
             }),
+            repo @ RepoRef {
+                backend: Backend::Gitlab,
+                ..
+            } => {
+                let (host, path) = repo.host_and_path();
+                RepoRemote::Git(GitRemote {
+                    protocol: GitProtocol::Https,
+                    host: host.to_owned(),
+                    address: path.to_owned(),
+                })
+            }
+            repo @ RepoRef {
+                backend: Backend::Bitbucket,
+                ..
+            } => {
+                let (host, path) = repo.host_and_path();
+                // Bitbucket Server serves git under `/scm`
+                let address = match host {
+                    "bitbucket.org" => path.to_owned(),
+                    _ => format!("scm/{path}"),
+                };
+
+                RepoRemote::Git(GitRemote {
+                    protocol: GitProtocol::Https,
+                    host: host.to_owned(),
+                    address,
+                })
+            }
             RepoRef {
                 backend: Backend::Local,
                 name: _name,
@@ -486,27 +706,29 @@ impl FromStr for RepoRemote {
     type Err = ();
 
     fn from_str(value: &str) -> Result<Self, Self::Err> {
-        if let Some(stripped) = value.strip_prefix("https://github.com/") {
-            return Ok(RepoRemote::Git(GitRemote {
-                protocol: GitProtocol::Https,
-                host: "github.com".to_owned(),
-                address: stripped
-                    .trim_end_matches('/')
-                    .trim_end_matches(".git")
-                    .to_owned(),
-            }));
-        }
+        for host in ["github.com", "gitlab.com", "bitbucket.org"] {
+            if let Some(stripped) = value.strip_prefix(&format!("https://{host}/")) {
+                return Ok(RepoRemote::Git(GitRemote {
+                    protocol: GitProtocol::Https,
+                    host: host.to_owned(),
+                    address: stripped
+                        .trim_end_matches('/')
+                        .trim_end_matches(".git")
+                        .to_owned(),
+                }));
+            }
 
-        if let Some(stripped) = value.strip_prefix("git@github.com:") {
-            return Ok(RepoRemote::Git(GitRemote {
-                protocol: GitProtocol::Ssh,
-                host: "github.com".to_owned(),
-                address: stripped
-                    .trim_start_matches('/')
-                    .trim_end_matches('/')
-                    .trim_end_matches(".git")
-                    .to_owned(),
-            }));
+            if let Some(stripped) = value.strip_prefix(&format!("git@{host}:")) {
+                return Ok(RepoRemote::Git(GitRemote {
+                    protocol: GitProtocol::Ssh,
+                    host: host.to_owned(),
+                    address: stripped
+                        .trim_start_matches('/')
+                        .trim_end_matches('/')
+                        .trim_end_matches(".git")
+                        .to_owned(),
+                }));
+            }
         }
 
         Err(())
@@ -558,6 +780,21 @@ mod test {
             "local//tmp/repository".parse::<RepoRef>().unwrap(),
             RepoRef::new(Backend::Local, "/tmp/repository").unwrap()
         );
+        assert_eq!(
+            "gitlab.com/group/sub/project".parse::<RepoRef>().unwrap(),
+            RepoRef::new(Backend::Gitlab, "gitlab.com/group/sub/project").unwrap()
+        );
+        assert_eq!(
+            "gitlab/git.example.com/group/project"
+                .parse::<RepoRef>()
+                .unwrap(),
+            RepoRef::new(Backend::Gitlab, "git.example.com/group/project").unwrap()
+        );
+        assert!("gitlab/git.example.com".parse::<RepoRef>().is_err());
+        assert_eq!(
+            "bitbucket.org/workspace/repo".parse::<RepoRef>().unwrap(),
+            RepoRef::new(Backend::Bitbucket, "bitbucket.org/workspace/repo").unwrap()
+        );
         if "repository".parse::<RepoRef>().is_ok() {
             panic!("non-absolute local allowed")
         }
@@ -573,6 +810,24 @@ mod test {
             r#""local//org/repo""#,
             &serde_json::to_string(&RepoRef::new(Backend::Local, "/org/repo").unwrap()).unwrap()
         );
+
+        let self_hosted = RepoRef::new(Backend::Gitlab, "git.example.com/group/project").unwrap();
+        assert_eq!(
+            self_hosted.to_string(),
+            "gitlab/git.example.com/group/project"
+        );
+        assert_eq!(self_hosted.display_name(), "group/project");
+        assert_eq!(
+            RepoRemote::from(&self_hosted).to_string(),
+            "https://git.example.com/group/project.git"
+        );
+
+        let server = RepoRef::new(Backend::Bitbucket, "git.example.com/PROJ/repo").unwrap();
+        assert_eq!(server.to_string(), "bitbucket/git.example.com/PROJ/repo");
+        assert_eq!(
+            RepoRemote::from(&server).to_string(),
+            "https://git.example.com/scm/PROJ/repo.git"
+        );
     }
 
     #[test]
@@ -0,0 +1,716 @@
+//! The API surface of a repository: the services, endpoints and messages declared in its OpenAPI
+//! specs and protobuf files.
+//!
+//! Schemas are parsed from the file index on demand, so that questions like "which services
+//! expose an endpoint returning `CustomerId`" can be answered without reading every spec. Type
+//! names are reduced to their last segment, `google.protobuf.Timestamp` and
+//! `#/components/schemas/Customer` become `Timestamp` and `Customer`.
+
+use std::{collections::HashSet, path::Path, sync::Arc};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+use serde_yaml::Value;
+
+use crate::{
+    indexes::{File, Indexer},
+    repo::RepoRef,
+};
+
+/// The languages of the files that can declare an API, as detected by the file index.
+pub const LANGUAGES: [&str; 3] = ["Protocol Buffer", "YAML", "JSON"];
+
+const HTTP_METHODS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+static PROTO_COMMENT: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)//[^\n]*|/\*.*?\*/").unwrap());
+static PROTO_PACKAGE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\bpackage\s+([\w.]+)\s*;").unwrap());
+static PROTO_MESSAGE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\bmessage\s+(\w+)\s*\{").unwrap());
+static PROTO_SERVICE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\bservice\s+(\w+)\s*\{").unwrap());
+static PROTO_RPC: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"\brpc\s+(\w+)\s*\(\s*(?:stream\s+)?([\w.]+)\s*\)\s*returns\s*\(\s*(?:stream\s+)?([\w.]+)\s*\)",
+    )
+    .unwrap()
+});
+static PROTO_FIELD: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"^\s*(?:(?:repeated|optional|required)\s+)?(?:map\s*<\s*[\w.]+\s*,\s*([\w.]+)\s*>|([\w.]+))\s+(\w+)\s*=\s*\d+",
+    )
+    .unwrap()
+});
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct ApiSurface {
+    pub services: Vec<Service>,
+    pub messages: Vec<Message>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceKind {
+    OpenApi,
+    Grpc,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Service {
+    pub name: String,
+    pub kind: ServiceKind,
+    pub path: String,
+    pub line: usize,
+    pub endpoints: Vec<Endpoint>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Endpoint {
+    /// The operation ID or RPC name
+    pub name: String,
+    /// The HTTP method, or `rpc` for gRPC methods
+    pub method: String,
+    pub route: String,
+    pub request: Vec<String>,
+    pub response: Vec<String>,
+    pub line: usize,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Message {
+    pub name: String,
+    pub path: String,
+    pub line: usize,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+}
+
+/// A filter over an API surface. Empty filters match everything.
+#[derive(Debug, Default)]
+pub struct ApiQuery<'a> {
+    /// Terms matched against names, routes and types, ignoring case
+    pub terms: &'a str,
+
+    /// Only keep endpoints whose response is, or contains, this type
+    pub returns: Option<&'a str>,
+}
+
+/// The surfaces that were parsed, by repository, with the commit they were parsed at.
+static SURFACES: Lazy<scc::HashMap<RepoRef, (String, Arc<ApiSurface>)>> =
+    Lazy::new(Default::default);
+
+impl ApiSurface {
+    /// The API surface of an indexed repository at `commit`, the commit it was indexed at.
+    ///
+    /// Specs only change when the repository is indexed again, so they're only parsed once per
+    /// indexed commit. Repositories without a commit are parsed every time.
+    pub async fn for_repo(
+        files: &Indexer<File>,
+        repo_ref: &RepoRef,
+        commit: Option<&str>,
+    ) -> Arc<Self> {
+        let Some(commit) = commit else {
+            return Arc::new(Self::parse(files, repo_ref).await);
+        };
+
+        let cached = SURFACES
+            .read_async(repo_ref, |_, (parsed_at, surface)| {
+                (parsed_at == commit).then(|| Arc::clone(surface))
+            })
+            .await
+            .flatten();
+
+        if let Some(surface) = cached {
+            return surface;
+        }
+
+        let surface = Arc::new(Self::parse(files, repo_ref).await);
+        *SURFACES
+            .entry_async(repo_ref.clone())
+            .await
+            .or_default()
+            .get_mut() = (commit.to_owned(), Arc::clone(&surface));
+
+        surface
+    }
+
+    /// Parse the API surface of an indexed repository.
+    async fn parse(files: &Indexer<File>, repo_ref: &RepoRef) -> Self {
+        let mut surface = Self::default();
+        for doc in files.by_repo(repo_ref, LANGUAGES.iter(), None).await {
+            surface.add_file(&doc.relative_path, doc.lang.as_deref(), &doc.content);
+        }
+
+        surface
+            .services
+            .sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+        surface
+            .messages
+            .sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+        surface
+    }
+
+    /// Add the declarations of a file, if it is a protobuf file or an OpenAPI spec.
+    pub fn add_file(&mut self, path: &str, lang: Option<&str>, content: &str) {
+        let lang = lang.unwrap_or_default();
+        if lang.eq_ignore_ascii_case("Protocol Buffer") || path.ends_with(".proto") {
+            self.add_proto(path, content);
+        } else if LANGUAGES.iter().any(|l| l.eq_ignore_ascii_case(lang)) {
+            self.add_openapi(path, content);
+        }
+    }
+
+    fn add_proto(&mut self, path: &str, content: &str) {
+        let src = PROTO_COMMENT.replace_all(content, |c: &regex::Captures<'_>| {
+            // Keep offsets and line numbers intact
+            c[0].chars()
+                .map(|ch| if ch == '\n' { '\n' } else { ' ' })
+                .collect::<String>()
+        });
+        let line = |offset: usize| src[..offset].matches('\n').count() + 1;
+        let package = PROTO_PACKAGE
+            .captures(&src)
+            .map(|c| format!("{}.", &c[1]))
+            .unwrap_or_default();
+
+        for c in PROTO_MESSAGE.captures_iter(&src) {
+            let whole = c.get(0).unwrap();
+            let fields = top_level_lines(block(&src, whole.end()))
+                .filter_map(|l| PROTO_FIELD.captures(l))
+                .map(|f| Field {
+                    name: f[3].to_owned(),
+                    ty: base(f.get(1).or_else(|| f.get(2)).unwrap().as_str()).to_owned(),
+                })
+                .collect();
+
+            self.messages.push(Message {
+                name: c[1].to_owned(),
+                path: path.to_owned(),
+                line: line(whole.start()),
+                fields,
+            });
+        }
+
+        for c in PROTO_SERVICE.captures_iter(&src) {
+            let whole = c.get(0).unwrap();
+            let body = block(&src, whole.end());
+            let endpoints = PROTO_RPC
+                .captures_iter(body)
+                .map(|rpc| Endpoint {
+                    name: rpc[1].to_owned(),
+                    method: "rpc".to_owned(),
+                    route: format!("/{package}{}/{}", &c[1], &rpc[1]),
+                    request: vec![base(&rpc[2]).to_owned()],
+                    response: vec![base(&rpc[3]).to_owned()],
+                    line: line(whole.end() + rpc.get(0).unwrap().start()),
+                })
+                .collect();
+
+            self.services.push(Service {
+                name: c[1].to_owned(),
+                kind: ServiceKind::Grpc,
+                path: path.to_owned(),
+                line: line(whole.start()),
+                endpoints,
+            });
+        }
+    }
+
+    fn add_openapi(&mut self, path: &str, content: &str) {
+        if !content.contains("openapi") && !content.contains("swagger") {
+            return;
+        }
+
+        // JSON is valid YAML, so both are parsed the same way.
+        let Ok(doc) = serde_yaml::from_str::<Value>(content) else {
+            return;
+        };
+
+        if doc.get("openapi").is_none() && doc.get("swagger").is_none() {
+            return;
+        }
+
+        let name = doc
+            .get("info")
+            .and_then(|i| i.get("title"))
+            .and_then(Value::as_str)
+            .map(str::to_owned)
+            .or_else(|| {
+                Path::new(path)
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().into_owned())
+            })
+            .unwrap_or_default();
+
+        let mut endpoints = vec![];
+        for (route, item) in mapping(doc.get("paths")) {
+            let Some(route) = route.as_str() else {
+                continue;
+            };
+            let route_line = line_of(content, route, 0);
+
+            for (method, op) in mapping(Some(item)) {
+                let Some(method) = method.as_str().filter(|m| HTTP_METHODS.contains(m)) else {
+                    continue;
+                };
+
+                let mut request = vec![];
+                if let Some(body) = op.get("requestBody") {
+                    content_types(body, &mut request);
+                }
+                for param in op
+                    .get("parameters")
+                    .and_then(Value::as_sequence)
+                    .into_iter()
+                    .flatten()
+                {
+                    if param.get("in").and_then(Value::as_str) == Some("body") {
+                        content_types(param, &mut request);
+                    }
+                }
+
+                let mut response = vec![];
+                for (status, res) in mapping(op.get("responses")) {
+                    let status = match status {
+                        Value::Number(n) => n.to_string(),
+                        Value::String(s) => s.clone(),
+                        _ => continue,
+                    };
+                    if status.starts_with('2') || status == "default" {
+                        content_types(res, &mut response);
+                    }
+                }
+
+                let operation_id = op.get("operationId").and_then(Value::as_str);
+                endpoints.push(Endpoint {
+                    name: operation_id
+                        .map(str::to_owned)
+                        .unwrap_or_else(|| format!("{} {route}", method.to_uppercase())),
+                    method: method.to_uppercase(),
+                    route: route.to_owned(),
+                    request,
+                    response,
+                    line: operation_id
+                        .map(|id| line_of(content, id, route_line))
+                        .unwrap_or(route_line),
+                });
+            }
+        }
+
+        let schemas = doc
+            .get("components")
+            .and_then(|c| c.get("schemas"))
+            .or_else(|| doc.get("definitions"));
+
+        for (schema_name, schema) in mapping(schemas) {
+            let Some(schema_name) = schema_name.as_str() else {
+                continue;
+            };
+
+            let fields = mapping(schema.get("properties"))
+                .filter_map(|(field, prop)| {
+                    let mut types = vec![];
+                    schema_types(prop, &mut types);
+                    Some(Field {
+                        name: field.as_str()?.to_owned(),
+                        ty: types.into_iter().next().unwrap_or_else(|| "object".into()),
+                    })
+                })
+                .collect();
+
+            self.messages.push(Message {
+                name: schema_name.to_owned(),
+                path: path.to_owned(),
+                line: line_of(content, schema_name, 0),
+                fields,
+            });
+        }
+
+        self.services.push(Service {
+            name,
+            kind: ServiceKind::OpenApi,
+            path: path.to_owned(),
+            line: 1,
+            endpoints,
+        });
+    }
+
+    /// The services, endpoints and messages that match a query.
+    ///
+    /// Services are kept with only their matching endpoints. With `returns`, the matching
+    /// messages are the returned type and every message that contains it, directly or through
+    /// other messages.
+    pub fn search(&self, query: &ApiQuery<'_>) -> ApiSurface {
+        let terms = query
+            .terms
+            .split_whitespace()
+            .map(str::to_lowercase)
+            .collect::<Vec<_>>();
+        let matches = |s: &str| {
+            let s = s.to_lowercase();
+            terms.is_empty() || terms.iter().any(|t| s.contains(t))
+        };
+
+        let containing = query.returns.map(|ty| self.containing(ty));
+        let returns = |e: &Endpoint| match &containing {
+            Some(types) => e.response.iter().any(|r| types.contains(r.as_str())),
+            None => true,
+        };
+
+        let services = self
+            .services
+            .iter()
+            .filter_map(|s| {
+                let endpoints = s
+                    .endpoints
+                    .iter()
+                    .filter(|e| returns(e))
+                    .filter(|e| {
+                        matches(&s.name)
+                            || matches(&e.name)
+                            || matches(&e.route)
+                            || e.request.iter().chain(&e.response).any(|t| matches(t))
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                (!endpoints.is_empty()).then(|| Service {
+                    endpoints,
+                    ..s.clone()
+                })
+            })
+            .collect();
+
+        let messages = self
+            .messages
+            .iter()
+            .filter(|m| match &containing {
+                Some(types) => types.contains(m.name.as_str()),
+                None => matches(&m.name) || m.fields.iter().any(|f| matches(&f.ty)),
+            })
+            .cloned()
+            .collect();
+
+        ApiSurface { services, messages }
+    }
+
+    /// The type itself, and the names of all messages that contain it.
+    fn containing<'a>(&'a self, ty: &'a str) -> HashSet<&'a str> {
+        let mut types = HashSet::from([base(ty)]);
+        loop {
+            let before = types.len();
+            for m in &self.messages {
+                if m.fields.iter().any(|f| types.contains(f.ty.as_str())) {
+                    types.insert(m.name.as_str());
+                }
+            }
+
+            if types.len() == before {
+                return types;
+            }
+        }
+    }
+}
+
+/// The body of a block whose opening brace ends right before `start`.
+fn block(src: &str, start: usize) -> &str {
+    let mut depth = 1;
+    for (i, c) in src[start..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => {}
+        }
+
+        if depth == 0 {
+            return &src[start..start + i];
+        }
+    }
+
+    &src[start..]
+}
+
+/// The lines of a message body outside nested messages and enums.
+///
+/// `oneof` groups aren't nested types, their fields belong to the message.
+fn top_level_lines(body: &str) -> impl Iterator<Item = &str> {
+    let mut depth = 0usize;
+    body.lines().filter(move |line| {
+        let top_level = depth == 0;
+        if !line.trim_start().starts_with("oneof") {
+            depth += line.matches('{').count();
+        }
+        depth = depth.saturating_sub(line.matches('}').count());
+        top_level
+    })
+}
+
+fn base(ty: &str) -> &str {
+    ty.rsplit(['.', '/']).next().unwrap_or(ty)
+}
+
+fn mapping(value: Option<&Value>) -> impl Iterator<Item = (&Value, &Value)> {
+    value.and_then(Value::as_mapping).into_iter().flatten()
+}
+
+/// The types of a request body, parameter or response: `content.*.schema` in OpenAPI 3, or
+/// `schema` in Swagger 2.
+fn content_types(value: &Value, out: &mut Vec<String>) {
+    if let Some(schema) = value.get("schema") {
+        schema_types(schema, out);
+    }
+
+    for (_, media) in mapping(value.get("content")) {
+        if let Some(schema) = media.get("schema") {
+            schema_types(schema, out);
+        }
+    }
+}
+
+fn schema_types(schema: &Value, out: &mut Vec<String>) {
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        push_unique(out, base(reference));
+        return;
+    }
+
+    if let Some(items) = schema.get("items") {
+        schema_types(items, out);
+        return;
+    }
+
+    let composed = ["allOf", "oneOf", "anyOf"]
+        .iter()
+        .filter_map(|k| schema.get(k).and_then(Value::as_sequence))
+        .flatten()
+        .collect::<Vec<_>>();
+
+    if !composed.is_empty() {
+        composed.into_iter().for_each(|s| schema_types(s, out));
+    } else if let Some(ty) = schema.get("type").and_then(Value::as_str) {
+        push_unique(out, ty);
+    }
+}
+
+fn push_unique(out: &mut Vec<String>, ty: &str) {
+    if !out.iter().any(|t| t == ty) {
+        out.push(ty.to_owned());
+    }
+}
+
+/// The 1-based line of the first occurrence of `needle` after line `from`, or `from` if there is
+/// none.
+fn line_of(content: &str, needle: &str, from: usize) -> usize {
+    content
+        .lines()
+        .enumerate()
+        .skip(from.saturating_sub(1))
+        .find(|(_, l)| l.contains(needle))
+        .map_or(from, |(i, _)| i + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROTO: &str = r#"
+syntax = "proto3";
+package shop.v1;
+
+// The customer service.
+service Customers {
+  rpc GetCustomer (GetCustomerRequest) returns (Customer);
+  rpc WatchOrders (stream OrderQuery) returns (stream Order) {}
+}
+
+message Customer {
+  CustomerId id = 1;
+  map<string, Address> addresses = 2;
+  message Address { string street = 1; }
+  oneof contact {
+    string email = 3;
+  }
+}
+
+message Order {
+  repeated Customer buyers = 1; /* who paid */
+}
+"#;
+
+    const OPENAPI: &str = r##"
+openapi: 3.0.0
+info:
+  title: Billing
+paths:
+  /invoices/{id}:
+    get:
+      operationId: getInvoice
+      responses:
+        "200":
+          content:
+            application/json:
+              schema:
+                $ref: "#/components/schemas/Invoice"
+        "404":
+          description: not found
+  /customers:
+    post:
+      requestBody:
+        content:
+          application/json:
+            schema:
+              $ref: "#/components/schemas/NewCustomer"
+      responses:
+        201:
+          content:
+            application/json:
+              schema:
+                type: array
+                items:
+                  $ref: "#/components/schemas/CustomerId"
+components:
+  schemas:
+    Invoice:
+      properties:
+        total:
+          type: number
+    NewCustomer:
+      properties:
+        name:
+          type: string
+"##;
+
+    fn surface() -> ApiSurface {
+        let mut surface = ApiSurface::default();
+        surface.add_file("proto/shop.proto", Some("Protocol Buffer"), PROTO);
+        surface.add_file("api/billing.yaml", Some("YAML"), OPENAPI);
+        surface.add_file("config.yaml", Some("YAML"), "replicas: 3");
+        surface
+    }
+
+    #[test]
+    fn parses_schemas() {
+        let surface = surface();
+
+        let grpc = &surface.services[0];
+        assert_eq!(
+            (grpc.name.as_str(), grpc.kind, grpc.line),
+            ("Customers", ServiceKind::Grpc, 6)
+        );
+        assert_eq!(
+            grpc.endpoints
+                .iter()
+                .map(|e| (
+                    e.route.as_str(),
+                    e.request[0].as_str(),
+                    e.response[0].as_str(),
+                    e.line
+                ))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    "/shop.v1.Customers/GetCustomer",
+                    "GetCustomerRequest",
+                    "Customer",
+                    7
+                ),
+                ("/shop.v1.Customers/WatchOrders", "OrderQuery", "Order", 8),
+            ]
+        );
+
+        let fields = |name: &str| {
+            surface
+                .messages
+                .iter()
+                .find(|m| m.name == name)
+                .unwrap()
+                .fields
+                .iter()
+                .map(|f| (f.name.clone(), f.ty.clone()))
+                .collect::<Vec<_>>()
+        };
+        let owned = |f: &[(&str, &str)]| {
+            f.iter()
+                .map(|(n, t)| (n.to_string(), t.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            fields("Customer"),
+            owned(&[
+                ("id", "CustomerId"),
+                ("addresses", "Address"),
+                ("email", "string")
+            ])
+        );
+        assert_eq!(fields("Order"), owned(&[("buyers", "Customer")]));
+        assert_eq!(fields("Invoice"), owned(&[("total", "number")]));
+
+        let http = &surface.services[1];
+        assert_eq!(
+            (http.name.as_str(), http.kind),
+            ("Billing", ServiceKind::OpenApi)
+        );
+        assert_eq!(
+            http.endpoints
+                .iter()
+                .map(|e| (
+                    e.name.as_str(),
+                    e.method.as_str(),
+                    e.request.clone(),
+                    e.response.clone(),
+                    e.line
+                ))
+                .collect::<Vec<_>>(),
+            [
+                ("getInvoice", "GET", vec![], vec!["Invoice".to_owned()], 8),
+                (
+                    "POST /customers",
+                    "POST",
+                    vec!["NewCustomer".to_owned()],
+                    vec!["CustomerId".to_owned()],
+                    17
+                ),
+            ]
+        );
+        assert_eq!(surface.services.len(), 2);
+    }
+
+    #[test]
+    fn finds_endpoints_returning_a_type() {
+        let surface = surface();
+        let found = surface.search(&ApiQuery {
+            terms: "",
+            returns: Some("shop.v1.CustomerId"),
+        });
+
+        assert_eq!(
+            found
+                .services
+                .iter()
+                .flat_map(|s| s.endpoints.iter().map(|e| e.name.as_str()))
+                .collect::<Vec<_>>(),
+            ["GetCustomer", "WatchOrders", "POST /customers"]
+        );
+
+        let mut messages = found
+            .messages
+            .iter()
+            .map(|m| m.name.as_str())
+            .collect::<Vec<_>>();
+        messages.sort();
+        assert_eq!(messages, ["Customer", "Order"]);
+
+        let found = surface.search(&ApiQuery {
+            terms: "invoice",
+            returns: None,
+        });
+        assert_eq!(found.services.len(), 1);
+        assert_eq!(found.services[0].endpoints[0].name, "getInvoice");
+        assert_eq!(found.messages[0].name, "Invoice");
+    }
+}
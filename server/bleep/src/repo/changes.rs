@@ -0,0 +1,278 @@
+//! Search over local changes that haven't been indexed yet.
+//!
+//! Git repositories are indexed from their commits, and local folders are indexed periodically,
+//! so files that were edited recently can be out of date in the index. This module greps the
+//! working tree of a repository directly, but only over the files that differ from what was
+//! indexed.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use anyhow::Result;
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+use tracing::{debug, warn};
+
+/// The maximum number of matching lines returned by a single search.
+pub const MAX_MATCHES: usize = 100;
+
+/// Files larger than this are assumed to be generated, and are skipped.
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+/// Matching lines longer than this are cut short.
+const MAX_LINE_CHARS: usize = 300;
+
+#[derive(Serialize, Debug, Default)]
+pub struct Changes {
+    /// Paths of all files that changed since the repository was indexed, relative to its root.
+    pub changed_files: Vec<String>,
+    pub matches: Vec<Match>,
+
+    /// Whether there were more matches than [`MAX_MATCHES`].
+    pub truncated: bool,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub path: String,
+    pub line_number: usize,
+    pub line: String,
+}
+
+/// Search the unindexed changes in a repository working tree.
+///
+/// A line matches if it contains any of the whitespace-separated terms in `query`, ignoring case.
+/// The search can be scoped to files under `path_prefix`.
+///
+/// Files are considered changed if their contents differ from `indexed_commit`, when the
+/// repository was indexed from git, or if they were modified after `last_index_unix_secs`.
+pub fn search(
+    disk_path: &Path,
+    indexed_commit: Option<&str>,
+    last_index_unix_secs: u64,
+    query: &str,
+    path_prefix: Option<&str>,
+) -> Result<Changes> {
+    let matcher = matcher(query)?;
+    let indexed = IndexedState::new(disk_path, indexed_commit, last_index_unix_secs);
+
+    let mut changes = Changes::default();
+    for (relative_path, disk_path) in walk(disk_path, path_prefix) {
+        let Ok(buffer) = std::fs::read(&disk_path) else {
+            continue;
+        };
+
+        if !indexed.is_changed(&relative_path, &disk_path, &buffer) {
+            continue;
+        }
+
+        // Binary files will not be in the index either
+        let Ok(text) = std::str::from_utf8(&buffer) else {
+            continue;
+        };
+
+        if let Some(matcher) = &matcher {
+            changes.truncated |= grep(&relative_path, text, matcher, &mut changes.matches);
+        }
+
+        changes.changed_files.push(relative_path);
+    }
+
+    changes.changed_files.sort();
+    debug!(
+        changed_files = changes.changed_files.len(),
+        matches = changes.matches.len(),
+        "searched unindexed changes"
+    );
+
+    Ok(changes)
+}
+
+/// Build a case-insensitive matcher for any of the terms in a query.
+///
+/// Returns `None` for an empty query, which only lists changed files.
+fn matcher(query: &str) -> Result<Option<Regex>> {
+    let terms = query
+        .split_whitespace()
+        .map(regex::escape)
+        .collect::<Vec<_>>();
+
+    if terms.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        RegexBuilder::new(&terms.join("|"))
+            .case_insensitive(true)
+            .build()?,
+    ))
+}
+
+/// Append the lines of `text` that match, returning `true` if the match limit was hit.
+fn grep(path: &str, text: &str, matcher: &Regex, matches: &mut Vec<Match>) -> bool {
+    for (i, line) in text.lines().enumerate() {
+        if !matcher.is_match(line) {
+            continue;
+        }
+
+        if matches.len() >= MAX_MATCHES {
+            return true;
+        }
+
+        matches.push(Match {
+            path: path.to_owned(),
+            line_number: i + 1,
+            line: line.trim_end().chars().take(MAX_LINE_CHARS).collect(),
+        });
+    }
+
+    false
+}
+
+/// Walk all files in the working tree, yielding their relative and absolute paths.
+fn walk<'a>(
+    dir: &'a Path,
+    path_prefix: Option<&'a str>,
+) -> impl Iterator<Item = (String, PathBuf)> + 'a {
+    let path_prefix = path_prefix.map(|p| p.trim_start_matches('/'));
+
+    ignore::WalkBuilder::new(dir)
+        .standard_filters(true)
+        .hidden(false)
+        .build()
+        .filter_map(|de| match de {
+            Ok(de) => Some(de),
+            Err(err) => {
+                warn!(%err, "access failure; skipping");
+                None
+            }
+        })
+        .filter(|de| de.file_type().map_or(false, |ft| ft.is_file()))
+        .filter(|de| {
+            de.metadata()
+                .map_or(false, |meta| meta.len() <= MAX_FILE_BYTES)
+        })
+        .filter_map(move |de| {
+            let relative = de.path().strip_prefix(dir).ok()?;
+            if relative.starts_with(".git") {
+                return None;
+            }
+
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            match path_prefix {
+                Some(prefix) if !relative.starts_with(prefix) => None,
+                _ => Some((relative, de.into_path())),
+            }
+        })
+}
+
+/// What the index knows about a repository, used to tell which files have changed since.
+enum IndexedState {
+    /// The blobs that were indexed from a commit, by relative path.
+    Commit {
+        hash_kind: gix::hash::Kind,
+        blobs: HashMap<String, gix::ObjectId>,
+    },
+
+    /// The time at which a plain directory was last indexed.
+    Modified { since_unix_secs: u64 },
+}
+
+impl IndexedState {
+    fn new(disk_path: &Path, indexed_commit: Option<&str>, last_index_unix_secs: u64) -> Self {
+        let from_commit = indexed_commit.and_then(|commit| {
+            let git = gix::open(disk_path).ok()?;
+            let tree = git
+                .rev_parse_single(commit)
+                .ok()?
+                .object()
+                .ok()?
+                .peel_to_tree()
+                .ok()?;
+
+            let blobs = tree
+                .traverse()
+                .breadthfirst
+                .files()
+                .ok()?
+                .into_iter()
+                .filter(|entry| entry.mode.is_blob())
+                .map(|entry| {
+                    let path = String::from_utf8_lossy(entry.filepath.as_ref()).to_string();
+                    (path, entry.oid)
+                })
+                .collect();
+
+            Some(Self::Commit {
+                hash_kind: git.object_hash(),
+                blobs,
+            })
+        });
+
+        from_commit.unwrap_or(Self::Modified {
+            since_unix_secs: last_index_unix_secs,
+        })
+    }
+
+    fn is_changed(&self, relative_path: &str, disk_path: &Path, buffer: &[u8]) -> bool {
+        match self {
+            Self::Commit { hash_kind, blobs } => match blobs.get(relative_path) {
+                Some(oid) => {
+                    gix::objs::compute_hash(*hash_kind, gix::objs::Kind::Blob, buffer) != *oid
+                }
+                None => true,
+            },
+            Self::Modified { since_unix_secs } => std::fs::metadata(disk_path)
+                .and_then(|meta| meta.modified())
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(false, |modified| modified.as_secs() > *since_unix_secs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_any_term() {
+        let matcher = matcher("  retry   Backoff ").unwrap().unwrap();
+        let mut matches = vec![];
+
+        let text = "fn retry() {\n    let backoff = 2;\n    sleep(1);\n}";
+        assert!(!grep("src/lib.rs", text, &matcher, &mut matches));
+        assert_eq!(
+            matches,
+            [
+                Match {
+                    path: "src/lib.rs".into(),
+                    line_number: 1,
+                    line: "fn retry() {".into(),
+                },
+                Match {
+                    path: "src/lib.rs".into(),
+                    line_number: 2,
+                    line: "    let backoff = 2;".into(),
+                },
+            ]
+        );
+
+        assert!(matcher("").unwrap().is_none());
+        assert!(matcher("a.b").unwrap().unwrap().is_match("a.b"));
+        assert!(!matcher("a.b").unwrap().unwrap().is_match("axb"));
+    }
+
+    #[test]
+    fn stops_at_match_limit() {
+        let matcher = matcher("x").unwrap().unwrap();
+        let text = "x\n".repeat(MAX_MATCHES + 1);
+
+        let mut matches = vec![];
+        assert!(grep("a", &text, &matcher, &mut matches));
+        assert_eq!(matches.len(), MAX_MATCHES);
+    }
+}
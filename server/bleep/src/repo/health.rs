@@ -0,0 +1,350 @@
+//! A health report of a repository: how much of its code is documented and tested, how many
+//! TODOs it has, and which files have grown largest.
+//!
+//! Everything is measured on the file index, with heuristics that work across languages rather
+//! than a parser per language. Modules are the directories that code is in, up to two levels deep,
+//! so that `server/bleep/src/...` is reported as `server/bleep`.
+
+use std::collections::BTreeMap;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    indexes::{File, Indexer},
+    repo::RepoRef,
+};
+
+/// The languages of the files that count as code, as detected by the file index.
+pub const LANGUAGES: [&str; 18] = [
+    "Rust",
+    "Python",
+    "JavaScript",
+    "TypeScript",
+    "TSX",
+    "Go",
+    "Java",
+    "Kotlin",
+    "Scala",
+    "Swift",
+    "C",
+    "C++",
+    "C#",
+    "Ruby",
+    "PHP",
+    "Elixir",
+    "Haskell",
+    "Shell",
+];
+
+/// How many of the largest files to report.
+const MAX_HOTSPOTS: usize = 10;
+
+/// A definition at the start of a line, in the languages above.
+static DEFINITION: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"^\s*(?:(?:pub(?:\([^)]*\))?|export|default|public|private|protected|internal|static|abstract|final|async|unsafe|const|open|override)\s+)*(?:fn|struct|enum|trait|impl|class|interface|def|defp|defmodule|func|function|type|object|module)\b",
+    )
+    .unwrap()
+});
+
+static TODO: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b(?:TODO|FIXME)\b").unwrap());
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct HealthReport {
+    pub files: usize,
+    pub code_lines: usize,
+    pub test_lines: usize,
+    /// Test lines per line of code that isn't a test
+    pub test_ratio: f64,
+    pub todos: usize,
+    /// TODOs and FIXMEs per thousand lines
+    pub todo_density: f64,
+    /// Documented definitions out of all definitions, from 0 to 1
+    pub doc_coverage: f64,
+    pub modules: Vec<ModuleHealth>,
+    /// The largest files, largest first
+    pub hotspots: Vec<Hotspot>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ModuleHealth {
+    pub path: String,
+    pub definitions: usize,
+    pub documented: usize,
+    pub doc_coverage: f64,
+    pub code_lines: usize,
+    pub test_lines: usize,
+    pub todos: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hotspot {
+    pub path: String,
+    pub lines: usize,
+    pub todos: usize,
+}
+
+/// What a single file adds to a report.
+#[derive(Debug, Default, PartialEq)]
+struct FileStats {
+    code_lines: usize,
+    test_lines: usize,
+    todos: usize,
+    definitions: usize,
+    documented: usize,
+}
+
+impl HealthReport {
+    /// Measure an indexed repository.
+    pub async fn for_repo(files: &Indexer<File>, repo_ref: &RepoRef) -> Self {
+        let mut report = Self::default();
+        let mut modules = BTreeMap::new();
+
+        for doc in files.by_repo(repo_ref, LANGUAGES.iter(), None).await {
+            report.add_file(&mut modules, &doc.relative_path, &doc.content);
+        }
+
+        report.finish(modules);
+        report
+    }
+
+    fn add_file(
+        &mut self,
+        modules: &mut BTreeMap<String, ModuleHealth>,
+        path: &str,
+        content: &str,
+    ) {
+        let stats = FileStats::measure(path, content);
+
+        let module = modules
+            .entry(module_of(path))
+            .or_insert_with_key(|path| ModuleHealth {
+                path: path.clone(),
+                ..Default::default()
+            });
+        module.definitions += stats.definitions;
+        module.documented += stats.documented;
+        module.code_lines += stats.code_lines;
+        module.test_lines += stats.test_lines;
+        module.todos += stats.todos;
+
+        self.files += 1;
+        self.code_lines += stats.code_lines;
+        self.test_lines += stats.test_lines;
+        self.todos += stats.todos;
+        self.hotspots.push(Hotspot {
+            path: path.to_owned(),
+            lines: stats.code_lines + stats.test_lines,
+            todos: stats.todos,
+        });
+    }
+
+    fn finish(&mut self, modules: BTreeMap<String, ModuleHealth>) {
+        let lines = self.code_lines + self.test_lines;
+        self.test_ratio = ratio(self.test_lines, self.code_lines);
+        self.todo_density = ratio(self.todos * 1000, lines);
+
+        let (definitions, documented) = modules.values().fold((0, 0), |(d, doc), m| {
+            (d + m.definitions, doc + m.documented)
+        });
+        self.doc_coverage = ratio(documented, definitions);
+
+        self.modules = modules
+            .into_values()
+            .map(|mut m| {
+                m.doc_coverage = ratio(m.documented, m.definitions);
+                m
+            })
+            .collect();
+
+        self.hotspots
+            .sort_by(|a, b| b.lines.cmp(&a.lines).then_with(|| a.path.cmp(&b.path)));
+        self.hotspots.truncate(MAX_HOTSPOTS);
+    }
+}
+
+impl FileStats {
+    fn measure(path: &str, content: &str) -> Self {
+        let mut stats = Self::default();
+        let lines = content.lines().collect::<Vec<_>>();
+        let whole_file_is_test = is_test_path(path);
+
+        // Rust keeps unit tests in the file they test, after `#[cfg(test)]`
+        let tests_from = lines
+            .iter()
+            .position(|line| line.trim() == "#[cfg(test)]")
+            .filter(|_| path.ends_with(".rs"))
+            .unwrap_or(lines.len());
+
+        for (i, line) in lines.iter().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if whole_file_is_test || i >= tests_from {
+                stats.test_lines += 1;
+            } else {
+                stats.code_lines += 1;
+            }
+
+            if TODO.is_match(line) {
+                stats.todos += 1;
+            }
+
+            if !whole_file_is_test && i < tests_from && DEFINITION.is_match(line) {
+                stats.definitions += 1;
+                if is_documented(&lines, i) {
+                    stats.documented += 1;
+                }
+            }
+        }
+
+        stats
+    }
+}
+
+/// Whether the definition on line `i` has a comment right above it, or a docstring right below.
+fn is_documented(lines: &[&str], i: usize) -> bool {
+    let above = lines[..i]
+        .iter()
+        .rev()
+        .map(|line| line.trim())
+        // Attributes and decorators go between a definition and its docs
+        .find(|line| !line.starts_with("#[") && !line.starts_with('@'));
+
+    let documented_above = above.map_or(false, |line| {
+        ["//", "/*", "*", "#", "--"]
+            .iter()
+            .any(|prefix| line.starts_with(prefix))
+    });
+
+    let documented_below = lines
+        .get(i + 1)
+        .map(|line| line.trim())
+        .map_or(false, |line| {
+            line.starts_with("\"\"\"") || line.starts_with("'''")
+        });
+
+    documented_above || documented_below
+}
+
+fn is_test_path(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    let name = path.rsplit('/').next().unwrap_or_default();
+
+    path.split('/')
+        .rev()
+        .skip(1)
+        .any(|dir| matches!(dir, "test" | "tests" | "spec" | "specs" | "__tests__"))
+        || name.starts_with("test_")
+        || [
+            "_test.",
+            "_tests.",
+            ".test.",
+            ".spec.",
+            "_spec.",
+            "test.java",
+            "tests.cs",
+        ]
+        .iter()
+        .any(|marker| name.contains(marker))
+}
+
+/// The directory of a file, up to two levels deep, or `.` for files at the root.
+fn module_of(path: &str) -> String {
+    let dirs = path.split('/').collect::<Vec<_>>();
+    let dirs = &dirs[..dirs.len() - 1];
+
+    match dirs {
+        [] => ".".to_owned(),
+        dirs => dirs[..dirs.len().min(2)].join("/"),
+    }
+}
+
+fn ratio(num: usize, denom: usize) -> f64 {
+    if denom == 0 {
+        return 0.0;
+    }
+
+    num as f64 / denom as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_documentation_and_tests() {
+        let stats = FileStats::measure(
+            "src/lib.rs",
+            "/// Documented\n\
+             #[derive(Debug)]\n\
+             pub struct Foo;\n\
+             \n\
+             pub(crate) async fn bar() {} // TODO: baz\n\
+             \n\
+             #[cfg(test)]\n\
+             mod tests {\n\
+                 fn bar() {}\n\
+             }\n",
+        );
+
+        assert_eq!(
+            stats,
+            FileStats {
+                code_lines: 4,
+                test_lines: 4,
+                todos: 1,
+                definitions: 2,
+                documented: 1,
+            }
+        );
+
+        let stats = FileStats::measure(
+            "app/models.py",
+            "class Foo:\n    \"\"\"Documented.\"\"\"\n\n    def bar(self):\n        pass\n",
+        );
+        assert_eq!((stats.definitions, stats.documented), (2, 1));
+    }
+
+    #[test]
+    fn tells_tests_from_code() {
+        assert!(is_test_path("tests/integration.rs"));
+        assert!(is_test_path("src/__tests__/App.tsx"));
+        assert!(is_test_path("pkg/server/server_test.go"));
+        assert!(is_test_path("client/src/App.test.tsx"));
+        assert!(is_test_path("app/test_models.py"));
+        assert!(!is_test_path("src/testing.rs"));
+        assert!(!is_test_path("src/contest/mod.rs"));
+    }
+
+    #[test]
+    fn reports_by_module() {
+        let mut report = HealthReport::default();
+        let mut modules = BTreeMap::new();
+        report.add_file(&mut modules, "build.rs", "fn main() {}\n");
+        report.add_file(
+            &mut modules,
+            "server/bleep/src/lib.rs",
+            "// FIXME\nfn a() {}\nfn b() {}\n",
+        );
+        report.add_file(&mut modules, "server/bleep/tests/a.rs", "fn t() {}\n");
+        report.finish(modules);
+
+        assert_eq!(report.files, 3);
+        assert_eq!((report.code_lines, report.test_lines), (4, 1));
+        assert_eq!(report.test_ratio, 0.25);
+        assert_eq!(report.todo_density, 200.0);
+        assert_eq!(
+            report
+                .modules
+                .iter()
+                .map(|m| (m.path.as_str(), m.definitions, m.documented))
+                .collect::<Vec<_>>(),
+            [(".", 1, 0), ("server/bleep", 2, 1)]
+        );
+        assert_eq!(report.hotspots[0].path, "server/bleep/src/lib.rs");
+    }
+}
@@ -0,0 +1,563 @@
+//! Read-only queries over the git history of a repository: the commits that touched a path, who
+//! last changed each line of a file, and what a commit changed.
+//!
+//! Only the first parent of each commit is followed, so changes made on a merged branch are
+//! attributed to the merge commit. Walks stop after [`MAX_WALK`] commits, which bounds the cost of
+//! a query on repositories with a long history.
+
+use std::{fmt::Write, ops::Range, path::Path};
+
+use anyhow::{anyhow, bail, Context, Result};
+use gix::{
+    bstr::ByteSlice,
+    diff::blob::{intern::InternedInput, Algorithm, UnifiedDiffBuilder},
+    object::tree::diff::Action,
+    objs::tree::EntryMode,
+    Commit, ObjectId, Repository,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{indexed_commit, RepoRemote};
+
+/// The most commits visited by a single query.
+pub const MAX_WALK: usize = 5000;
+
+/// The most commits returned by `log`.
+pub const MAX_LOG: usize = 20;
+
+/// The most lines that can be blamed at once.
+pub const MAX_BLAME_LINES: usize = 200;
+
+/// Diffs of a commit are cut short after this many characters.
+const MAX_DIFF_CHARS: usize = 12_000;
+
+/// Length of the abbreviated commit hashes that are shown, and cited in answers.
+const SHORT_ID_LEN: usize = 8;
+
+#[derive(Serialize, Deserialize, schemars::JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Command {
+    /// The recent commits, optionally only those that touched a path
+    Log,
+    /// The commits that last changed each line of a path
+    Blame,
+    /// The diff of a commit
+    Show,
+}
+
+/// A history query, as the agent makes it.
+#[derive(Serialize, Deserialize, schemars::JsonSchema, Debug, Clone, PartialEq)]
+pub struct Query {
+    pub command: Command,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// 1-based, inclusive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_line: Option<usize>,
+    /// 1-based, inclusive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_line: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CommitInfo {
+    pub id: String,
+    pub author: String,
+    /// Seconds since the epoch
+    pub time: i64,
+    pub summary: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct BlameHunk {
+    pub commit: CommitInfo,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub lines: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Blame {
+    pub hunks: Vec<BlameHunk>,
+
+    /// Whether the walk stopped before finding the origin of every line. The remaining lines are
+    /// attributed to the oldest commit that was visited.
+    pub truncated: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Show {
+    pub commit: CommitInfo,
+    pub message: String,
+    pub changed_files: Vec<String>,
+    pub diff: String,
+    pub truncated: bool,
+}
+
+impl CommitInfo {
+    fn new(commit: &Commit<'_>) -> Result<Self> {
+        let author = commit.author()?;
+
+        Ok(Self {
+            id: commit.id.to_string(),
+            author: author.name.to_str_lossy().into_owned(),
+            time: author.time.seconds,
+            summary: commit.message()?.summary().to_str_lossy().into_owned(),
+        })
+    }
+
+    pub fn short_id(&self) -> &str {
+        &self.id[..SHORT_ID_LEN.min(self.id.len())]
+    }
+
+    pub fn date(&self) -> String {
+        chrono::NaiveDateTime::from_timestamp_opt(self.time, 0)
+            .map(|time| time.format("%Y-%m-%d").to_string())
+            .unwrap_or_default()
+    }
+}
+
+impl Query {
+    /// Run the query against the repository checked out at `disk_path`, and format the results
+    /// for a prompt.
+    ///
+    /// The history is walked from the commit that was indexed, so that it agrees with the code
+    /// that is searched.
+    pub fn run(&self, disk_path: &Path, remote: &RepoRemote) -> Result<String> {
+        let repo = gix::open(disk_path).context("the repository has no git history")?;
+
+        match self.command {
+            Command::Log => {
+                let start = indexed_commit(&repo, remote)?;
+                let commits = log(start, self.path.as_deref(), MAX_LOG)?;
+                if commits.is_empty() {
+                    return Ok("No commits found".to_owned());
+                }
+
+                Ok(commits
+                    .iter()
+                    .map(format_commit)
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }
+            Command::Blame => {
+                let path = self
+                    .path
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("blame needs a path"))?;
+                let start = self.start_line.unwrap_or(1);
+                let last = start.saturating_add(MAX_BLAME_LINES - 1);
+                let end = self.end_line.unwrap_or(last).min(last);
+
+                let commit = indexed_commit(&repo, remote)?;
+                Ok(format_blame(&blame(
+                    &repo,
+                    commit,
+                    path,
+                    start..end.saturating_add(1),
+                )?))
+            }
+            Command::Show => {
+                let commit = self
+                    .commit
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("show needs a commit"))?;
+
+                Ok(format_show(&show(&repo, commit, self.path.as_deref())?))
+            }
+        }
+    }
+}
+
+/// The most recent commits reachable from `commit`, newest first. When a path is given, only
+/// commits that changed, added or removed it are returned.
+pub fn log(mut commit: Commit<'_>, path: Option<&str>, limit: usize) -> Result<Vec<CommitInfo>> {
+    let mut commits = vec![];
+
+    for _ in 0..MAX_WALK {
+        let parent = first_parent(&commit)?;
+
+        let touched = match path {
+            None => true,
+            Some(path) => {
+                blob_at(&commit, path)?
+                    != parent
+                        .as_ref()
+                        .map(|p| blob_at(p, path))
+                        .transpose()?
+                        .flatten()
+            }
+        };
+
+        if touched {
+            commits.push(CommitInfo::new(&commit)?);
+            if commits.len() >= limit {
+                break;
+            }
+        }
+
+        match parent {
+            Some(parent) => commit = parent,
+            None => break,
+        }
+    }
+
+    Ok(commits)
+}
+
+/// The commits that last changed each line of a file at `commit`, in the 1-based range `lines`.
+pub fn blame(
+    repo: &Repository,
+    mut commit: Commit<'_>,
+    path: &str,
+    lines: Range<usize>,
+) -> Result<Blame> {
+    let Some(blob) = blob_at(&commit, path)? else {
+        bail!("{path} does not exist in the indexed commit");
+    };
+
+    let content = blob_text(repo, blob)?;
+    let head_lines = content.lines().collect::<Vec<_>>();
+
+    let start = lines.start.max(1);
+    let end = lines.end.min(head_lines.len() + 1);
+    if start >= end {
+        bail!("{path} has {} lines", head_lines.len());
+    }
+
+    // For every blamed line, its line number in the version of the file being looked at, until
+    // the commit that introduced it is found.
+    let mut pending = (start..end)
+        .map(|line| (line, line as u32 - 1))
+        .collect::<Vec<_>>();
+    let mut origins = Vec::<(usize, CommitInfo)>::new();
+    let mut truncated = true;
+
+    for _ in 0..MAX_WALK {
+        let parent = first_parent(&commit)?;
+        let current = blob_at(&commit, path)?;
+        let previous = parent
+            .as_ref()
+            .map(|p| blob_at(p, path))
+            .transpose()?
+            .flatten();
+
+        if current != previous {
+            let info = CommitInfo::new(&commit)?;
+            let hunks = match (previous, current) {
+                (Some(previous), Some(current)) => {
+                    line_changes(&blob_text(repo, previous)?, &blob_text(repo, current)?)
+                }
+                // The file was added here, so every remaining line was too
+                _ => vec![(0..0, 0..u32::MAX)],
+            };
+
+            pending.retain_mut(
+                |(line, current_line)| match map_line(&hunks, *current_line) {
+                    Some(previous_line) => {
+                        *current_line = previous_line;
+                        true
+                    }
+                    None => {
+                        origins.push((*line, info.clone()));
+                        false
+                    }
+                },
+            );
+        }
+
+        if pending.is_empty() {
+            truncated = false;
+            break;
+        }
+
+        match parent {
+            Some(parent) => commit = parent,
+            None => break,
+        }
+    }
+
+    if !pending.is_empty() {
+        let info = CommitInfo::new(&commit)?;
+        origins.extend(pending.into_iter().map(|(line, _)| (line, info.clone())));
+    }
+
+    origins.sort_by_key(|(line, _)| *line);
+
+    Ok(Blame {
+        hunks: group_lines(origins, &head_lines),
+        truncated,
+    })
+}
+
+/// The changes made by a commit, compared to its first parent, optionally only to one path.
+pub fn show(repo: &Repository, spec: &str, path: Option<&str>) -> Result<Show> {
+    let commit = repo
+        .rev_parse_single(spec)
+        .with_context(|| format!("unknown commit {spec}"))?
+        .object()?
+        .peel_to_commit()?;
+
+    let parent_tree = match first_parent(&commit)? {
+        Some(parent) => parent.tree()?,
+        None => repo.empty_tree(),
+    };
+
+    let mut changed_files = vec![];
+    let mut diff = String::new();
+    let mut truncated = false;
+
+    parent_tree
+        .changes()?
+        .track_path()
+        .for_each_to_obtain_tree(&commit.tree()?, |change| {
+            let location = change.location.to_str_lossy().into_owned();
+            if path.is_some_and(|path| !location.starts_with(path)) {
+                return Ok::<_, std::convert::Infallible>(Action::Continue);
+            }
+
+            changed_files.push(location.clone());
+
+            if truncated {
+                return Ok(Action::Continue);
+            }
+
+            use gix::object::tree::diff::change::Event;
+            let (old, new) = match change.event {
+                Event::Addition {
+                    entry_mode: EntryMode::Blob,
+                    id,
+                } => (None, Some(id)),
+                Event::Deletion {
+                    entry_mode: EntryMode::Blob,
+                    id,
+                } => (Some(id), None),
+                Event::Modification {
+                    previous_entry_mode: EntryMode::Blob,
+                    previous_id,
+                    entry_mode: EntryMode::Blob,
+                    id,
+                } => (Some(previous_id), Some(id)),
+                _ => return Ok(Action::Continue),
+            };
+
+            let text = |id: Option<gix::Id<'_>>| {
+                id.and_then(|id| id.object().ok())
+                    .map(|object| object.data.to_str_lossy().into_owned())
+                    .unwrap_or_default()
+            };
+
+            let (old, new) = (text(old), text(new));
+            let input = InternedInput::new(old.as_str(), new.as_str());
+            let hunks = gix::diff::blob::diff(
+                Algorithm::Histogram,
+                &input,
+                UnifiedDiffBuilder::new(&input),
+            );
+
+            _ = write!(diff, "--- a/{location}\n+++ b/{location}\n{hunks}");
+
+            if diff.len() > MAX_DIFF_CHARS {
+                let mut end = MAX_DIFF_CHARS;
+                while !diff.is_char_boundary(end) {
+                    end -= 1;
+                }
+
+                diff.truncate(end);
+                truncated = true;
+            }
+
+            Ok(Action::Continue)
+        })?;
+
+    Ok(Show {
+        message: commit.message_raw()?.to_str_lossy().trim().to_owned(),
+        commit: CommitInfo::new(&commit)?,
+        changed_files,
+        diff,
+        truncated,
+    })
+}
+
+fn first_parent<'repo>(commit: &Commit<'repo>) -> Result<Option<Commit<'repo>>> {
+    commit
+        .parent_ids()
+        .next()
+        .map(|id| Ok(id.object()?.into_commit()))
+        .transpose()
+}
+
+/// The blob at a path of the tree of a commit, if there is one.
+fn blob_at(commit: &Commit<'_>, path: &str) -> Result<Option<ObjectId>> {
+    Ok(commit
+        .tree()?
+        .peel_to_entry_by_path(path)?
+        .filter(|entry| entry.mode().is_blob())
+        .map(|entry| entry.object_id()))
+}
+
+fn blob_text(repo: &Repository, id: ObjectId) -> Result<String> {
+    Ok(repo.find_object(id)?.data.to_str_lossy().into_owned())
+}
+
+/// The ranges of lines that differ between two versions of a file, as `(old, new)` pairs of
+/// 0-based line ranges.
+fn line_changes(old: &str, new: &str) -> Vec<(Range<u32>, Range<u32>)> {
+    let input = InternedInput::new(old, new);
+    let mut hunks = vec![];
+
+    gix::diff::blob::diff(Algorithm::Histogram, &input, |before, after| {
+        hunks.push((before, after))
+    });
+
+    hunks
+}
+
+/// The line number in the old version of a file of a line of the new version, or `None` if the
+/// line was added or changed. `hunks` must be sorted, as they are when produced by a diff.
+fn map_line(hunks: &[(Range<u32>, Range<u32>)], line: u32) -> Option<u32> {
+    let mut offset = 0i64;
+
+    for (before, after) in hunks {
+        if after.contains(&line) {
+            return None;
+        }
+
+        if after.start > line {
+            break;
+        }
+
+        offset += before.len() as i64 - after.len() as i64;
+    }
+
+    Some((line as i64 + offset) as u32)
+}
+
+/// Group consecutive lines that were last changed by the same commit.
+fn group_lines(origins: Vec<(usize, CommitInfo)>, lines: &[&str]) -> Vec<BlameHunk> {
+    let mut hunks: Vec<BlameHunk> = vec![];
+
+    for (line, commit) in origins {
+        let text = lines[line - 1].to_owned();
+
+        match hunks.last_mut() {
+            Some(hunk) if hunk.commit.id == commit.id && hunk.end_line + 1 == line => {
+                hunk.end_line = line;
+                hunk.lines.push(text);
+            }
+            _ => hunks.push(BlameHunk {
+                commit,
+                start_line: line,
+                end_line: line,
+                lines: vec![text],
+            }),
+        }
+    }
+
+    hunks
+}
+
+fn format_commit(commit: &CommitInfo) -> String {
+    format!(
+        "{} {} {}: {}",
+        commit.short_id(),
+        commit.date(),
+        commit.author,
+        commit.summary
+    )
+}
+
+fn format_blame(blame: &Blame) -> String {
+    let mut s = String::new();
+
+    for hunk in &blame.hunks {
+        _ = writeln!(
+            s,
+            "### L{}-L{}, {} ###",
+            hunk.start_line,
+            hunk.end_line,
+            format_commit(&hunk.commit)
+        );
+
+        for (i, line) in hunk.lines.iter().enumerate() {
+            _ = writeln!(s, "{} {line}", hunk.start_line + i);
+        }
+    }
+
+    if blame.truncated {
+        s += "(the history was too long to trace every line; the oldest lines are attributed to the oldest commit searched)\n";
+    }
+
+    s
+}
+
+fn format_show(show: &Show) -> String {
+    let mut s = format!(
+        "{}\n\n{}\n\nChanged files:\n{}\n\n{}",
+        format_commit(&show.commit),
+        show.message,
+        show.changed_files.join("\n"),
+        show.diff
+    );
+
+    if show.truncated {
+        s += "\n(diff truncated)";
+    }
+
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(id: &str) -> CommitInfo {
+        CommitInfo {
+            id: id.into(),
+            author: "Ada".into(),
+            time: 1_697_000_000,
+            summary: "Change things".into(),
+        }
+    }
+
+    #[test]
+    fn maps_lines_across_changes() {
+        let old = "a\nb\nc\nd\n";
+        let new = "a\nx\ny\nc\nd\ne\n";
+        let hunks = line_changes(old, new);
+
+        let mapped = (0..6)
+            .map(|line| map_line(&hunks, line))
+            .collect::<Vec<_>>();
+        assert_eq!(mapped, [Some(0), None, None, Some(2), Some(3), None]);
+    }
+
+    #[test]
+    fn groups_consecutive_lines_by_commit() {
+        let lines = ["fn main() {", "    run();", "}"];
+        let origins = vec![
+            (1, commit("aaaaaaaaaa")),
+            (2, commit("bbbbbbbbbb")),
+            (3, commit("aaaaaaaaaa")),
+        ];
+
+        let hunks = group_lines(origins, &lines);
+        assert_eq!(hunks.len(), 3);
+        assert_eq!(hunks[1].lines, ["    run();"]);
+
+        let origins = vec![(1, commit("aaaaaaaaaa")), (2, commit("aaaaaaaaaa"))];
+        let hunks = group_lines(origins, &lines);
+        assert_eq!(hunks.len(), 1);
+        assert_eq!((hunks[0].start_line, hunks[0].end_line), (1, 2));
+    }
+
+    #[test]
+    fn formats_commits_with_short_ids() {
+        assert_eq!(
+            format_commit(&commit("0123456789abcdef")),
+            "01234567 2023-10-11 Ada: Change things"
+        );
+    }
+}
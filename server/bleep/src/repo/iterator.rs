@@ -103,7 +103,13 @@ pub struct RepoFile {
 
 impl RepoFile {
     pub fn should_index(&self) -> bool {
-        should_index_path(&self.path) && self.len < MAX_FILE_LEN
+        let max_len = if crate::notebook::is_notebook(&self.path) {
+            crate::notebook::MAX_LEN
+        } else {
+            MAX_FILE_LEN
+        };
+
+        should_index_path(&self.path) && self.len < max_len
     }
 
     pub fn buffer(&self) -> std::io::Result<String> {
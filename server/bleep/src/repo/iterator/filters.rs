@@ -11,6 +11,8 @@ use serde::{Deserialize, Serialize};
 pub struct FilterUpdate {
     pub branch_filter: Option<BranchFilterConfig>,
     pub file_filter: Option<FileFilterConfig>,
+    /// Replaces the sparse paths of the repository, an empty list indexes everything
+    pub sparse_paths: Option<Vec<String>>,
 }
 
 /// Configure branch filters
@@ -197,3 +199,94 @@ impl From<&FileFilterConfig> for FileFilter {
         Self::compile(value).unwrap()
     }
 }
+
+/// Directories to index in a repository, like a cone-mode sparse checkout.
+///
+/// Everything under the directories is included, along with the directories leading up to them.
+/// An empty list includes the whole repository.
+#[derive(Debug, Clone, Default)]
+pub struct SparsePaths {
+    dirs: Vec<String>,
+}
+
+impl SparsePaths {
+    pub fn new(specs: &[String]) -> anyhow::Result<Self> {
+        let mut dirs = specs
+            .iter()
+            .map(|spec| {
+                let dir = spec.trim().trim_matches('/');
+                if dir
+                    .split('/')
+                    .any(|part| part.is_empty() || part == "." || part == "..")
+                {
+                    anyhow::bail!("invalid sparse path `{spec}`");
+                }
+
+                Ok(dir.to_owned())
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        dirs.sort();
+        dirs.dedup();
+
+        Ok(Self { dirs })
+    }
+
+    /// The normalized directories, sorted.
+    pub fn dirs(&self) -> &[String] {
+        &self.dirs
+    }
+
+    /// Check a path relative to the repository root.
+    pub fn contains(&self, path: &str, is_dir: bool) -> bool {
+        if self.dirs.is_empty() || path.is_empty() {
+            return true;
+        }
+
+        self.dirs.iter().any(|dir| {
+            let inside = path
+                .strip_prefix(dir.as_str())
+                .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'));
+
+            let leads_to = is_dir
+                && dir
+                    .strip_prefix(path)
+                    .map_or(false, |rest| rest.starts_with('/'));
+
+            inside || leads_to
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sparse_paths_are_normalized() {
+        let specs = ["/services/api/", "libs", "libs/"].map(String::from);
+        assert_eq!(
+            SparsePaths::new(&specs).unwrap().dirs(),
+            ["libs", "services/api"]
+        );
+
+        assert!(SparsePaths::new(&["../secrets".into()]).is_err());
+        assert!(SparsePaths::new(&["/".into()]).is_err());
+        assert!(SparsePaths::new(&["a//b".into()]).is_err());
+    }
+
+    #[test]
+    fn sparse_paths_include_directories_and_their_parents() {
+        let sparse = SparsePaths::new(&["services/api".into()]).unwrap();
+
+        assert!(sparse.contains("services/api", true));
+        assert!(sparse.contains("services/api/main.rs", false));
+        assert!(sparse.contains("services", true));
+        assert!(!sparse.contains("services/api-gateway/main.rs", false));
+        assert!(!sparse.contains("services/web", true));
+        assert!(!sparse.contains("services", false));
+        assert!(!sparse.contains("README.md", false));
+
+        assert!(SparsePaths::default().contains("README.md", false));
+    }
+}
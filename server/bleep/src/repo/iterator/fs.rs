@@ -14,11 +14,30 @@ pub struct FileWalker {
 }
 
 impl FileWalker {
-    pub fn index_directory(dir: impl AsRef<Path>, branch: String) -> impl FileSource {
+    pub fn index_directory(
+        dir: impl AsRef<Path>,
+        branch: String,
+        sparse: &SparsePaths,
+    ) -> impl FileSource {
+        let root = dir.as_ref().to_owned();
+        let sparse = sparse.clone();
+
         // note: this WILL observe .gitignore files for the respective repos.
         let walker = ignore::WalkBuilder::new(&dir)
             .standard_filters(true)
             .hidden(false)
+            .filter_entry(move |de| {
+                let Ok(relative) = de.path().strip_prefix(&root) else {
+                    return true;
+                };
+
+                let relative = relative.to_string_lossy();
+                #[cfg(windows)]
+                let relative = relative.replace('\\', "/");
+
+                let is_dir = de.file_type().map_or(false, |t| t.is_dir());
+                sparse.contains(&relative, is_dir)
+            })
             .build();
 
         let file_list = walker
@@ -48,6 +67,7 @@ impl FileSource for FileWalker {
             self.file_list
                 .into_par_iter()
                 .filter_map(|entry_disk_path| {
+                    background::yield_to_interactive();
                     if entry_disk_path.is_file() {
                         let path = entry_disk_path.clone();
                         let buffer = Box::new(move || match std::fs::read_to_string(&path) {
@@ -1,13 +1,16 @@
 use crate::{background, repo::RepoRef};
 
-use super::{filters::BranchFilter, *};
+use super::{
+    filters::{BranchFilter, SparsePaths},
+    *,
+};
 
 use anyhow::Result;
 use gix::ThreadSafeRepository;
 use tracing::trace;
 
 use std::{
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeSet, HashMap, HashSet},
     path::Path,
 };
 
@@ -26,6 +29,7 @@ impl GitWalker {
         reporef: &RepoRef,
         dir: impl AsRef<Path>,
         branch_filter: impl Into<Option<BranchFilter>>,
+        sparse: &SparsePaths,
     ) -> Result<Self> {
         let root_dir = dir.as_ref();
 
@@ -111,18 +115,23 @@ impl GitWalker {
             .flat_map(|(is_head, branch, tree)| {
                 let files = tree.traverse().breadthfirst.files().unwrap().into_iter();
 
-                files.map(move |entry| {
-                    let strpath = String::from_utf8_lossy(entry.filepath.as_ref());
-                    let full_path = root_dir.join(strpath.as_ref());
-                    trace!(?strpath, ?full_path, "got path from gix");
-                    (
-                        is_head,
-                        branch.clone(),
-                        full_path.to_string_lossy().to_string(),
-                        entry.mode,
-                        entry.oid,
-                    )
-                })
+                files
+                    .filter(move |entry| {
+                        let strpath = String::from_utf8_lossy(entry.filepath.as_ref());
+                        sparse.contains(&strpath, entry.mode.is_tree())
+                    })
+                    .map(move |entry| {
+                        let strpath = String::from_utf8_lossy(entry.filepath.as_ref());
+                        let full_path = root_dir.join(strpath.as_ref());
+                        trace!(?strpath, ?full_path, "got path from gix");
+                        (
+                            is_head,
+                            branch.clone(),
+                            full_path.to_string_lossy().to_string(),
+                            entry.mode,
+                            entry.oid,
+                        )
+                    })
             })
             .fold(
                 HashMap::new(),
@@ -147,6 +156,61 @@ impl GitWalker {
 
         Ok(Self { git, entries })
     }
+
+    /// Walk only the entries of HEAD that changed since the tree of the commit `since`.
+    ///
+    /// Also returns the relative paths of entries that are the same in both trees, whose documents
+    /// can be kept as they are. Paths that were removed are in neither.
+    pub fn open_changes(
+        reporef: &RepoRef,
+        dir: impl AsRef<Path>,
+        since: &str,
+        sparse: &SparsePaths,
+    ) -> Result<(Self, HashSet<String>)> {
+        let root_dir = dir.as_ref();
+        let mut walker = Self::open_repository(reporef, root_dir, BranchFilter::Head, sparse)?;
+
+        let since = gix::ObjectId::from_hex(since.as_bytes())?;
+        let previous = walker
+            .git
+            .to_thread_local()
+            .find_object(since)?
+            .peel_to_tree()?
+            .traverse()
+            .breadthfirst
+            .files()?
+            .into_iter()
+            .filter(|entry| {
+                let strpath = String::from_utf8_lossy(entry.filepath.as_ref());
+                sparse.contains(&strpath, entry.mode.is_tree())
+            })
+            .map(|entry| {
+                let strpath = String::from_utf8_lossy(entry.filepath.as_ref());
+                let full_path = root_dir.join(strpath.as_ref());
+                (full_path.to_string_lossy().to_string(), entry.oid)
+            })
+            .collect::<HashSet<_>>();
+
+        let mut unchanged = HashSet::new();
+        walker.entries.retain(|(path, _, oid), _| {
+            if !previous.contains(&(path.clone(), *oid)) {
+                return true;
+            }
+
+            let path = Path::new(path);
+            let relative = path.strip_prefix(root_dir).unwrap_or(path);
+            unchanged.insert(relative.to_string_lossy().to_string());
+            false
+        });
+
+        trace!(
+            changed = walker.entries.len(),
+            unchanged = unchanged.len(),
+            "diffed against the last indexed commit"
+        );
+
+        Ok((walker, unchanged))
+    }
 }
 
 impl FileSource for GitWalker {
@@ -160,6 +224,7 @@ impl FileSource for GitWalker {
             self.entries
                 .into_par_iter()
                 .filter_map(|((path, kind, oid), branches)| {
+                    background::yield_to_interactive();
                     trace!(?path, "walking over path");
                     let git = self.git.to_thread_local();
                     let Ok(Some(object)) = git.try_find_object(oid) else {
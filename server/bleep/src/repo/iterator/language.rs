@@ -43,6 +43,10 @@ impl LanguageInfo {
 }
 
 fn detect_language(path: &Path, buf: &[u8]) -> Option<&'static str> {
+    if let Some(lang) = crate::intelligence::custom_language(path) {
+        return Some(lang);
+    }
+
     detect_buffer(path, |_| Ok(Cursor::new(buf)))
         .ok()
         .flatten()
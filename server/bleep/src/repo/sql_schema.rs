@@ -0,0 +1,554 @@
+//! The data model of a repository, as declared by its SQL migrations and schema dumps.
+//!
+//! SQL files are replayed in path order, which is the order of timestamped or numbered
+//! migrations, so that the model reflects the latest state of every table. `CREATE TABLE`,
+//! `ALTER TABLE` and `DROP TABLE` statements are understood, everything else is skipped. Each
+//! table keeps the statements that changed it as its history.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{
+    indexes::{File, Indexer},
+    repo::RepoRef,
+};
+
+/// The SQL dialects of the files that can declare tables, as detected by the file index.
+pub const LANGUAGES: [&str; 5] = ["SQL", "PLpgSQL", "PLSQL", "TSQL", "SQLPL"];
+
+/// History entries are cut to this many characters.
+const MAX_SUMMARY_CHARS: usize = 120;
+
+const IDENT: &str = r#"[\w."`\[\]]+"#;
+
+static COMMENT: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)--[^\n]*|/\*.*?\*/").unwrap());
+static CREATE_TABLE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(&format!(
+        r"(?is)^CREATE\s+(?:OR\s+REPLACE\s+)?(?:(?:GLOBAL\s+|LOCAL\s+)?(?:TEMP|TEMPORARY)\s+|UNLOGGED\s+)?TABLE\s+(?:IF\s+NOT\s+EXISTS\s+)?({IDENT})\s*\("
+    ))
+    .unwrap()
+});
+static ALTER_TABLE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(&format!(
+        r"(?is)^ALTER\s+TABLE\s+(?:IF\s+EXISTS\s+)?(?:ONLY\s+)?({IDENT})\s+(.*)$"
+    ))
+    .unwrap()
+});
+static DROP_TABLE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?is)^DROP\s+TABLE\s+(?:IF\s+EXISTS\s+)?(.*)$").unwrap());
+
+static ADD: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?is)^ADD\s+(?:COLUMN\s+)?(?:IF\s+NOT\s+EXISTS\s+)?(.*)$").unwrap());
+static DROP_COLUMN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(&format!(
+        r"(?is)^DROP\s+(?:COLUMN\s+)?(?:IF\s+EXISTS\s+)?({IDENT})"
+    ))
+    .unwrap()
+});
+static RENAME_COLUMN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(&format!(
+        r"(?is)^RENAME\s+(?:COLUMN\s+)?({IDENT})\s+TO\s+({IDENT})"
+    ))
+    .unwrap()
+});
+static RENAME_TABLE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(&format!(r"(?is)^RENAME\s+TO\s+({IDENT})")).unwrap());
+static ALTER_TYPE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(&format!(
+        r"(?is)^ALTER\s+(?:COLUMN\s+)?({IDENT})\s+(?:SET\s+DATA\s+)?TYPE\s+(.+)$"
+    ))
+    .unwrap()
+});
+static MODIFY: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?is)^MODIFY\s+(?:COLUMN\s+)?(.*)$").unwrap());
+
+static CONSTRAINT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)^(?:CONSTRAINT\s+\S+\s+)?(?:PRIMARY|FOREIGN|UNIQUE|CHECK|EXCLUDE)\b|^(?:KEY|INDEX)\s+[^\s(]*\s*\(",
+    )
+    .unwrap()
+});
+static FOREIGN_KEY: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(&format!(
+        r"(?is)FOREIGN\s+KEY\s*\(([^)]*)\)\s*REFERENCES\s+({IDENT})\s*(?:\(([^)]*)\))?"
+    ))
+    .unwrap()
+});
+static REFERENCES: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(&format!(
+        r"(?is)\bREFERENCES\s+({IDENT})\s*(?:\(\s*({IDENT})\s*\))?"
+    ))
+    .unwrap()
+});
+static COLUMN_OPTION: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)\s+(?:NOT\s+NULL|NULL|DEFAULT|PRIMARY|REFERENCES|UNIQUE|CHECK|CONSTRAINT|GENERATED|COLLATE|AUTO_INCREMENT|AUTOINCREMENT|IDENTITY)\b",
+    )
+    .unwrap()
+});
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct Schema {
+    pub tables: Vec<Table>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Table {
+    pub name: String,
+    /// Where the table was created
+    pub path: String,
+    pub line: usize,
+    pub columns: Vec<Column>,
+    /// The statements that created and altered the table, oldest first
+    pub history: Vec<Change>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+    /// The referenced table, and column if it was named, as `table.column`
+    pub references: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Change {
+    pub path: String,
+    pub line: usize,
+    pub statement: String,
+}
+
+impl Schema {
+    /// Replay the SQL files of an indexed repository.
+    pub async fn for_repo(files: &Indexer<File>, repo_ref: &RepoRef) -> Self {
+        let mut docs = files.by_repo(repo_ref, LANGUAGES.iter(), None).await;
+        docs.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+
+        let mut schema = Self::default();
+        for doc in docs {
+            schema.apply_file(&doc.relative_path, &doc.content);
+        }
+
+        schema
+    }
+
+    /// Apply the statements of a SQL file, in order.
+    pub fn apply_file(&mut self, path: &str, content: &str) {
+        let src = COMMENT.replace_all(content, |c: &regex::Captures<'_>| {
+            // Keep offsets and line numbers intact
+            c[0].chars()
+                .map(|ch| if ch == '\n' { '\n' } else { ' ' })
+                .collect::<String>()
+        });
+
+        let mut start = 0;
+        for (i, end) in src
+            .match_indices(';')
+            .map(|(i, _)| (i, i + 1))
+            .chain([(src.len(), src.len())])
+        {
+            let raw = &src[start..i];
+            let statement = raw.trim_start();
+            let offset = start + raw.len() - statement.len();
+            start = end;
+
+            let statement = statement.trim_end();
+            if statement.is_empty() {
+                continue;
+            }
+
+            let change = Change {
+                path: path.to_owned(),
+                line: src[..offset].matches('\n').count() + 1,
+                statement: summary(statement),
+            };
+            self.apply(statement, change);
+        }
+    }
+
+    fn apply(&mut self, statement: &str, change: Change) {
+        if let Some(c) = CREATE_TABLE.captures(statement) {
+            let name = ident(&c[1]);
+            let mut columns = vec![];
+            for item in split_top_level(parenthesized(statement, c.get(0).unwrap().end())) {
+                if CONSTRAINT.is_match(item) {
+                    foreign_keys(item, &mut columns);
+                } else if let Some(column) = column(item) {
+                    columns.push(column);
+                }
+            }
+
+            // A table that is created again, like in a schema dump, is replaced.
+            let mut history = match self.position(&name) {
+                Some(i) => self.tables.remove(i).history,
+                None => vec![],
+            };
+
+            let (path, line) = (change.path.clone(), change.line);
+            history.push(change);
+            self.tables.push(Table {
+                name,
+                path,
+                line,
+                columns,
+                history,
+            });
+        } else if let Some(c) = ALTER_TABLE.captures(statement) {
+            let Some(i) = self.position(&ident(&c[1])) else {
+                return;
+            };
+
+            let table = &mut self.tables[i];
+            for action in split_top_level(c.get(2).unwrap().as_str()) {
+                alter(table, action);
+            }
+            table.history.push(change);
+        } else if let Some(c) = DROP_TABLE.captures(statement) {
+            for name in c[1].split(',') {
+                let name = name.split_whitespace().next().unwrap_or_default();
+                if let Some(i) = self.position(&ident(name)) {
+                    self.tables.remove(i);
+                }
+            }
+        }
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.tables
+            .iter()
+            .position(|t| t.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The tables that match any of the whitespace-separated terms, ignoring case.
+    ///
+    /// Tables whose name matches are kept whole. Other tables are kept with only the columns
+    /// whose name, type or referenced table matches, so that `user_id` finds every table with a
+    /// column named after it or referencing it.
+    pub fn search(&self, terms: &str) -> Schema {
+        let terms = terms
+            .split_whitespace()
+            .map(str::to_lowercase)
+            .collect::<Vec<_>>();
+        if terms.is_empty() {
+            return Schema {
+                tables: self.tables.clone(),
+            };
+        }
+
+        let matches = |s: &str| {
+            let s = s.to_lowercase();
+            terms.iter().any(|t| s.contains(t))
+        };
+
+        let tables = self
+            .tables
+            .iter()
+            .filter_map(|t| {
+                if matches(&t.name) {
+                    return Some(t.clone());
+                }
+
+                let columns = t
+                    .columns
+                    .iter()
+                    .filter(|c| {
+                        matches(&c.name)
+                            || matches(&c.ty)
+                            || c.references.as_deref().map_or(false, matches)
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                (!columns.is_empty()).then(|| Table {
+                    columns,
+                    ..t.clone()
+                })
+            })
+            .collect();
+
+        Schema { tables }
+    }
+}
+
+fn alter(table: &mut Table, action: &str) {
+    let position = |table: &Table, name: &str| {
+        table
+            .columns
+            .iter()
+            .position(|c| c.name.eq_ignore_ascii_case(name))
+    };
+
+    if let Some(c) = ADD.captures(action) {
+        let def = c.get(1).unwrap().as_str();
+        if CONSTRAINT.is_match(def) {
+            foreign_keys(def, &mut table.columns);
+        } else if let Some(column) = column(def) {
+            match position(table, &column.name) {
+                Some(i) => table.columns[i] = column,
+                None => table.columns.push(column),
+            }
+        }
+    } else if let Some(c) = DROP_COLUMN.captures(action) {
+        if let Some(i) = position(table, &ident(&c[1])) {
+            table.columns.remove(i);
+        }
+    } else if let Some(c) = RENAME_TABLE.captures(action) {
+        table.name = ident(&c[1]);
+    } else if let Some(c) = RENAME_COLUMN.captures(action) {
+        if let Some(i) = position(table, &ident(&c[1])) {
+            table.columns[i].name = ident(&c[2]);
+        }
+    } else if let Some(c) = ALTER_TYPE.captures(action) {
+        if let Some(i) = position(table, &ident(&c[1])) {
+            let ty = c[2].split_whitespace().collect::<Vec<_>>().join(" ");
+            let end = ty.to_ascii_uppercase().find(" USING ").unwrap_or(ty.len());
+            table.columns[i].ty = ty[..end].to_owned();
+        }
+    } else if let Some(c) = MODIFY.captures(action) {
+        if let Some(column) = column(c.get(1).unwrap().as_str()) {
+            if let Some(i) = position(table, &column.name) {
+                table.columns[i] = column;
+            }
+        }
+    }
+}
+
+/// Parse a column definition, like `user_id bigint NOT NULL REFERENCES users (id)`.
+fn column(def: &str) -> Option<Column> {
+    let def = def.trim();
+    let (name, rest) = def.split_once(char::is_whitespace)?;
+    let rest = rest.trim_start();
+    let ty = match COLUMN_OPTION.find(&format!(" {rest}")) {
+        Some(option) => &rest[..option.start().saturating_sub(1)],
+        None => rest,
+    };
+
+    Some(Column {
+        name: ident(name),
+        ty: ty.split_whitespace().collect::<Vec<_>>().join(" "),
+        references: REFERENCES
+            .captures(rest)
+            .map(|c| reference(&c[1], c.get(2).map(|c| c.as_str()))),
+    })
+}
+
+/// Record a `FOREIGN KEY (...) REFERENCES ...` constraint on the columns it applies to.
+fn foreign_keys(constraint: &str, columns: &mut [Column]) {
+    let Some(c) = FOREIGN_KEY.captures(constraint) else {
+        return;
+    };
+
+    let targets = c
+        .get(3)
+        .map(|t| t.as_str().split(',').collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    for (i, name) in c[1].split(',').enumerate() {
+        let name = ident(name);
+        if let Some(column) = columns
+            .iter_mut()
+            .find(|c| c.name.eq_ignore_ascii_case(&name))
+        {
+            column.references = Some(reference(&c[2], targets.get(i).copied()));
+        }
+    }
+}
+
+fn reference(table: &str, column: Option<&str>) -> String {
+    match column {
+        Some(column) => format!("{}.{}", ident(table), ident(column)),
+        None => ident(table),
+    }
+}
+
+/// An identifier without its schema and quotes.
+fn ident(name: &str) -> String {
+    name.trim()
+        .rsplit('.')
+        .next()
+        .unwrap_or_default()
+        .trim_matches(|c| matches!(c, '"' | '`' | '[' | ']'))
+        .to_owned()
+}
+
+/// The text inside the parenthesis that opens right before `start`.
+fn parenthesized(src: &str, start: usize) -> &str {
+    let mut depth = 1;
+    for (i, c) in src[start..].char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+
+        if depth == 0 {
+            return &src[start..start + i];
+        }
+    }
+
+    &src[start..]
+}
+
+/// Split a list on commas outside of parentheses, like the columns of `numeric(10, 2)`.
+fn split_top_level(list: &str) -> Vec<&str> {
+    let mut items = vec![];
+    let mut depth = 0usize;
+    let mut start = 0;
+
+    for (i, c) in list.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                items.push(list[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    items.push(list[start..].trim());
+    items.retain(|i| !i.is_empty());
+    items
+}
+
+fn summary(statement: &str) -> String {
+    let statement = statement.split_whitespace().collect::<Vec<_>>().join(" ");
+    match statement.char_indices().nth(MAX_SUMMARY_CHARS) {
+        Some((i, _)) => format!("{}...", &statement[..i]),
+        None => statement,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CREATE: &str = r#"
+-- Users and their orders
+CREATE TABLE IF NOT EXISTS "public"."users" (
+    id BIGSERIAL PRIMARY KEY,
+    email VARCHAR(255) NOT NULL UNIQUE,
+    balance NUMERIC(10, 2) DEFAULT 0
+);
+
+CREATE TABLE orders (
+    id INTEGER NOT NULL,
+    user_id BIGINT NOT NULL,
+    note TEXT, /* free-form; optional */
+    PRIMARY KEY (id),
+    CONSTRAINT fk_user FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE INDEX orders_user ON orders (user_id);
+"#;
+
+    const ALTER: &str = r#"
+ALTER TABLE orders ADD COLUMN coupon TEXT, DROP COLUMN note;
+ALTER TABLE users RENAME COLUMN email TO login;
+ALTER TABLE users ALTER COLUMN balance TYPE BIGINT USING balance::bigint;
+CREATE TABLE sessions (token TEXT, user_id BIGINT REFERENCES users ON DELETE CASCADE);
+DROP TABLE IF EXISTS sessions CASCADE;
+CREATE TABLE audit (user_id BIGINT REFERENCES users(id))
+"#;
+
+    fn columns(table: &Table) -> Vec<(&str, &str, Option<&str>)> {
+        table
+            .columns
+            .iter()
+            .map(|c| (c.name.as_str(), c.ty.as_str(), c.references.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn replays_migrations() {
+        let mut schema = Schema::default();
+        schema.apply_file("migrations/001_init.sql", CREATE);
+
+        let users = &schema.tables[0];
+        assert_eq!((users.name.as_str(), users.line), ("users", 3));
+        assert_eq!(
+            columns(users),
+            [
+                ("id", "BIGSERIAL", None),
+                ("email", "VARCHAR(255)", None),
+                ("balance", "NUMERIC(10, 2)", None),
+            ]
+        );
+        assert_eq!(
+            columns(&schema.tables[1]),
+            [
+                ("id", "INTEGER", None),
+                ("user_id", "BIGINT", Some("users.id")),
+                ("note", "TEXT", None),
+            ]
+        );
+
+        schema.apply_file("migrations/002_changes.sql", ALTER);
+        assert_eq!(
+            schema
+                .tables
+                .iter()
+                .map(|t| t.name.as_str())
+                .collect::<Vec<_>>(),
+            ["users", "orders", "audit"]
+        );
+        assert_eq!(
+            columns(&schema.tables[0]),
+            [
+                ("id", "BIGSERIAL", None),
+                ("login", "VARCHAR(255)", None),
+                ("balance", "BIGINT", None),
+            ]
+        );
+        assert_eq!(
+            columns(&schema.tables[1]),
+            [
+                ("id", "INTEGER", None),
+                ("user_id", "BIGINT", Some("users.id")),
+                ("coupon", "TEXT", None),
+            ]
+        );
+
+        let history = &schema.tables[1].history;
+        assert_eq!(
+            history
+                .iter()
+                .map(|c| (c.path.as_str(), c.line))
+                .collect::<Vec<_>>(),
+            [
+                ("migrations/001_init.sql", 9),
+                ("migrations/002_changes.sql", 2)
+            ]
+        );
+        assert_eq!(
+            history[1].statement,
+            "ALTER TABLE orders ADD COLUMN coupon TEXT, DROP COLUMN note"
+        );
+    }
+
+    #[test]
+    fn finds_referencing_tables() {
+        let mut schema = Schema::default();
+        schema.apply_file("schema.sql", CREATE);
+        schema.apply_file("schema_2.sql", ALTER);
+
+        let found = schema.search("user_id");
+        assert_eq!(
+            found
+                .tables
+                .iter()
+                .map(|t| (t.name.as_str(), columns(t)))
+                .collect::<Vec<_>>(),
+            [
+                ("orders", vec![("user_id", "BIGINT", Some("users.id"))]),
+                ("audit", vec![("user_id", "BIGINT", Some("users.id"))]),
+            ]
+        );
+
+        assert_eq!(schema.search("USERS").tables.len(), 3);
+        assert_eq!(schema.search("").tables.len(), 3);
+    }
+}
@@ -6,6 +6,8 @@ use tokio::{sync::RwLock, task};
 use tracing::{trace, warn};
 use url::Url;
 
+use crate::fetch::Fetcher;
+
 use std::{
     collections::{HashMap, HashSet, VecDeque},
     path::PathBuf,
@@ -15,7 +17,7 @@ use std::{
 mod article;
 pub mod chunk;
 
-use article::Article;
+pub use article::Article;
 
 pub struct Scraper {
     pub queued_requests: Arc<RwLock<VecDeque<ScraperRequest>>>,
@@ -131,7 +133,8 @@ impl Scraper {
                         if !self.visited_links.contains(&request.url.to_string()) {
                             trace!("{} queued", request.url.as_str());
                             self.visited_links.insert(request.url.to_string());
-                            let handle = task::spawn(async { visit(request).await });
+                            let fetcher = self.config.fetcher.clone();
+                            let handle = task::spawn(async move { visit(request, &fetcher).await });
                             self.handles.push(handle);
                         }
                     }
@@ -161,13 +164,15 @@ pub struct Config {
     pub base_url: Url,
     _delay: std::time::Duration,
     max_concurrency: usize,
+    fetcher: Fetcher,
 }
 
 impl Config {
-    pub fn new(base_url: Url) -> Self {
+    pub fn new(base_url: Url, fetcher: Fetcher) -> Self {
         Self {
             max_depth: 5,
             base_url,
+            fetcher,
             _delay: std::time::Duration::from_millis(0),
             max_concurrency: std::thread::available_parallelism()
                 .map(|t| t.get())
@@ -212,7 +217,10 @@ impl Meta {
     }
 }
 
-async fn visit(ScraperRequest { url, depth }: ScraperRequest) -> Result<ScraperResult> {
+async fn visit(
+    ScraperRequest { url, depth }: ScraperRequest,
+    fetcher: &Fetcher,
+) -> Result<ScraperResult> {
     trace!("visited - {}", url);
 
     // calculate the location on disk to store this url
@@ -226,7 +234,7 @@ async fn visit(ScraperRequest { url, depth }: ScraperRequest) -> Result<ScraperR
     // tokio::time::sleep(self.config.delay).await;
 
     // fetch and parse article
-    let article = Article::builder(url.clone())?.get().await?;
+    let article = Article::builder(url.clone())?.get(fetcher).await?;
     let url = article.url; // in the case of a redirect - we store the updated url
 
     // scrape all relative links from this doc and add onto stack
@@ -1,11 +1,7 @@
 use anyhow::{Context, Result};
 use once_cell::sync::Lazy;
 use regex::Regex;
-use reqwest::{
-    header::{HeaderMap, USER_AGENT},
-    redirect::Policy,
-    IntoUrl,
-};
+use reqwest::IntoUrl;
 use select::{
     document::Document,
     node::{Descendants, Node},
@@ -13,7 +9,10 @@ use select::{
 };
 use url::Url;
 
-use crate::query::languages::{EXT_MAP, PROPER_CASE_MAP};
+use crate::{
+    fetch::Fetcher,
+    query::languages::{EXT_MAP, PROPER_CASE_MAP},
+};
 
 use std::{
     borrow::Cow,
@@ -246,6 +245,21 @@ impl Article {
     pub fn builder<T: IntoUrl>(url: T) -> Result<ArticleBuilder> {
         ArticleBuilder::new(url)
     }
+
+    /// Extract an article from an HTML document that was already fetched.
+    pub fn from_html(url: Url, html: &str) -> Self {
+        let doc = Document::from(html);
+        let content = DefaultExtractor { url: url.clone() }
+            .article_content(&doc, None)
+            .into_owned();
+
+        Article {
+            url,
+            doc,
+            content,
+            language: Language::default(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -374,56 +388,47 @@ impl ArticleBuilder {
         })
     }
 
-    pub async fn get(self) -> Result<Article> {
+    pub async fn get(self, fetcher: &Fetcher) -> Result<Article> {
         let url = self.url.clone();
-        self.get_with_extractor(&DefaultExtractor {
-            url: url.context("url not initialized")?,
-        })
+        self.get_with_extractor(
+            fetcher,
+            &DefaultExtractor {
+                url: url.context("url not initialized")?,
+            },
+        )
         .await
     }
 
     async fn get_with_extractor<TExtract: Extractor>(
         self,
+        fetcher: &Fetcher,
         extractor: &TExtract,
     ) -> Result<Article> {
         let url = self
             .url
             .context("Url of the article must be initialized.")?;
 
-        let builder = {
-            let timeout = self.timeout.unwrap_or_else(|| Duration::from_secs(5));
-
-            let mut headers = HeaderMap::with_capacity(1);
-
-            headers.insert(
-                USER_AGENT,
-                self.browser_user_agent
-                    .map(|x| x.parse())
-                    .unwrap_or_else(|| {
-                        format!("bloop/{} bloop-doc-scraper", env!("CARGO_PKG_VERSION")).parse()
-                    })
-                    .context("Failed to parse user agent header.")?,
-            );
-
-            reqwest::Client::builder()
-                .default_headers(headers)
-                .redirect(Policy::limited(2))
-                .timeout(timeout)
-        };
+        let fetcher = fetcher
+            .clone()
+            .timeout(self.timeout.unwrap_or_else(|| Duration::from_secs(5)))
+            .max_redirects(2)
+            .max_bytes(8 * 1024 * 1024)
+            .user_agent(self.browser_user_agent.unwrap_or_else(|| {
+                format!("bloop/{} bloop-doc-scraper", env!("CARGO_PKG_VERSION"))
+            }));
 
-        let client = builder.build()?;
-        let resp = client.get(url).send().await?;
+        let resp = fetcher.get(url).await?;
 
-        if !resp.status().is_success() {
+        if !resp.status.is_success() {
             return Err(anyhow::anyhow!(
                 "Unsuccessful request to {:?} ({})",
-                resp.url(),
-                resp.status()
+                resp.url,
+                resp.status
             ));
         }
 
-        let url = resp.url().to_owned();
-        let doc = Document::from_read(&*resp.bytes().await?)
+        let url = resp.url;
+        let doc = Document::from_read(&*resp.body)
             .context(format!("Failed to read {:?} html as document.", url))?;
 
         let content = extractor
@@ -1,15 +1,28 @@
-use std::{borrow::Cow, collections::HashMap, env, path::Path, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    env,
+    path::Path,
+    sync::{Arc, RwLock},
+};
 
-use crate::{query::parser::SemanticQuery, Configuration};
+use crate::{
+    config::{EmbedderKind, VectorQuantization},
+    metrics::QDRANT_QUERY_SECONDS,
+    notebook,
+    query::parser::SemanticQuery,
+    Configuration,
+};
 
-use anyhow::bail;
+use anyhow::{bail, Context};
 use qdrant_client::{
     prelude::{QdrantClient, QdrantClientConfig},
     qdrant::{
-        point_id::PointIdOptions, r#match::MatchValue, vectors::VectorsOptions,
-        with_payload_selector, with_vectors_selector, CollectionOperationResponse, FieldCondition,
-        FieldType, Filter, Match, PointId, PointsOperationResponse, RetrievedPoint, ScoredPoint,
-        SearchParams, SearchPoints, Value, Vectors, WithPayloadSelector, WithVectorsSelector,
+        point_id::PointIdOptions, r#match::MatchValue, vectors::VectorsOptions, vectors_config,
+        with_payload_selector, with_vectors_selector, CollectionOperationResponse, CountPoints,
+        FieldCondition, FieldType, Filter, Match, PointId, PointsOperationResponse,
+        QuantizationSearchParams, RetrievedPoint, ScoredPoint, SearchParams, SearchPoints,
+        UpdateCollection, Value, Vectors, WithPayloadSelector, WithVectorsSelector,
     },
 };
 
@@ -19,13 +32,18 @@ use thiserror::Error;
 use tracing::{debug, error, info, trace, warn};
 
 pub mod chunk;
+pub mod coverage;
 pub mod embedder;
 pub mod execute;
+pub mod migration;
 mod schema;
 
 pub use embedder::Embedder;
 use embedder::LocalEmbedder;
-use schema::{create_collection, create_lexical_index, EMBEDDING_DIM};
+use migration::{Generation, Generations, LoadedGeneration, Persisted, Status};
+use schema::{
+    create_collection, create_lexical_index, quantization_config_diff, quantization_profile,
+};
 pub use schema::{Embedding, Payload};
 
 use itertools::Itertools;
@@ -43,6 +61,17 @@ pub enum SemanticError {
         error: ort::OrtError,
     },
 
+    #[error(
+        "collection `{collection}` was embedded with `{indexed}`, but `{configured}` is configured. \
+         Vectors of different models can't be mixed: configure the original embedder, or re-index \
+         into a new index directory"
+    )]
+    EmbedderMismatch {
+        collection: String,
+        indexed: String,
+        configured: String,
+    },
+
     #[error("semantic error")]
     Anyhow {
         #[from]
@@ -58,10 +87,53 @@ pub struct SemanticSearchParams {
     pub exact_match: bool, // keyword match for all filters
 }
 
+/// How a snippet was ranked by fusing the semantic and lexical rankings.
+///
+/// Each ranking a snippet appears in adds `1 / (rank + k)` to its score.
+#[derive(serde::Serialize, schemars::JsonSchema, Debug, Clone, Default, PartialEq)]
+pub struct Fusion {
+    /// The similarity of the snippet to the query's embedding
+    pub similarity: Option<f32>,
+    /// The rank among the closest snippets, from 1
+    pub semantic_rank: Option<usize>,
+    pub semantic_weight: Option<f32>,
+    /// The rank among snippets that contain the query's words, from 1
+    pub lexical_rank: Option<usize>,
+    /// 10 per query word the snippet contains, plus 1 per occurrence
+    pub lexical_score: Option<f32>,
+    pub lexical_weight: Option<f32>,
+    pub k: usize,
+    /// The score results are ordered by
+    pub score: f32,
+}
+
+impl Fusion {
+    fn new(k: usize) -> Self {
+        Self {
+            k,
+            ..Default::default()
+        }
+    }
+
+    fn merge(self, other: &Self) -> Self {
+        Self {
+            similarity: self.similarity.or(other.similarity),
+            semantic_rank: self.semantic_rank.or(other.semantic_rank),
+            semantic_weight: self.semantic_weight.or(other.semantic_weight),
+            lexical_rank: self.lexical_rank.or(other.lexical_rank),
+            lexical_score: self.lexical_score.or(other.lexical_score),
+            lexical_weight: self.lexical_weight.or(other.lexical_weight),
+            k: self.k,
+            score: self.score + other.score,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Semantic {
     qdrant: Arc<QdrantClient>,
-    embedder: Arc<dyn Embedder>,
+    generations: Arc<RwLock<Generations>>,
+    writes: Arc<migration::Writes>,
     pub(crate) config: Arc<Configuration>,
 }
 
@@ -161,6 +233,7 @@ fn parse_payload(
         id: Some(id),
         score: Some(score),
         embedding,
+        fusion: None,
     }
 }
 
@@ -196,6 +269,142 @@ async fn create_indexes(collection_name: &str, qdrant: &QdrantClient) -> anyhow:
     Ok(())
 }
 
+/// Bring the quantization of an existing collection in line with the configured profile.
+///
+/// Qdrant rebuilds the quantized vectors in the background, so search keeps working
+/// while the optimizer catches up.
+async fn migrate_quantization(
+    collection_name: &str,
+    quantization: VectorQuantization,
+    qdrant: &QdrantClient,
+) -> anyhow::Result<()> {
+    let current = qdrant
+        .collection_info(collection_name)
+        .await?
+        .result
+        .and_then(|info| info.config)
+        .and_then(|config| config.quantization_config);
+
+    let current = quantization_profile(current.as_ref());
+    if current == quantization {
+        return Ok(());
+    }
+
+    info!(from = ?current, to = ?quantization, "migrating collection quantization");
+
+    let update = UpdateCollection {
+        collection_name: collection_name.to_owned(),
+        quantization_config: Some(quantization_config_diff(quantization)),
+        ..Default::default()
+    };
+
+    qdrant
+        .with_collections_client(|mut client| {
+            let update = update.clone();
+            async move { client.update(update).await }
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// How often to ask an unreachable embedder for the size of its vectors.
+const PROBE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// The generation of a collection that `embeddings.json` doesn't record.
+///
+/// Collections from before `embeddings.json` were all embedded by the local model, and know the
+/// size of their vectors. New collections ask the embedder, and have a size of 0 if it can't be
+/// reached yet.
+async fn unrecorded_generation(
+    qdrant: &QdrantClient,
+    collection: &str,
+    model_dir: &Path,
+    embedder: &dyn Embedder,
+) -> Result<Generation, SemanticError> {
+    let exists = qdrant
+        .has_collection(collection)
+        .await
+        .map_err(|_| SemanticError::QdrantInitializationError)?;
+
+    if exists {
+        let dim = qdrant
+            .collection_info(collection)
+            .await?
+            .result
+            .and_then(|info| info.config)
+            .and_then(|config| config.params)
+            .and_then(|params| params.vectors_config)
+            .and_then(|vectors| vectors.config)
+            .and_then(|config| match config {
+                vectors_config::Config::Params(params) => Some(params.size as usize),
+                vectors_config::Config::ParamsMap(_) => None,
+            })
+            .context("collection has no vector size")?;
+
+        return Ok(Generation {
+            collection: collection.to_owned(),
+            model_dir: model_dir.to_owned(),
+            embedder: None,
+            dim,
+        });
+    }
+
+    let dim = match migration::dimension(embedder).await {
+        Ok(dim) => dim,
+        Err(err) => {
+            warn!(
+                ?err,
+                "failed to reach the embedder, deferring collection creation"
+            );
+            0
+        }
+    };
+
+    Ok(Generation::new(
+        collection,
+        model_dir,
+        embedder.model_id(),
+        dim,
+    ))
+}
+
+/// Create the collection of a generation if it doesn't exist, and bring it up to date otherwise.
+async fn prepare_collection(
+    generation: &Generation,
+    qdrant: &QdrantClient,
+    quantization: VectorQuantization,
+) -> Result<(), SemanticError> {
+    match qdrant.has_collection(&generation.collection).await {
+        Ok(false) => {
+            let CollectionOperationResponse { result, time } =
+                create_collection(&generation.collection, generation.dim, qdrant, quantization)
+                    .await
+                    .unwrap();
+
+            debug!(time, created = result, "collection created");
+            assert!(result);
+            let PointsOperationResponse { result, time: _ } =
+                create_lexical_index(&generation.collection, qdrant)
+                    .await
+                    .unwrap();
+
+            debug!("lexical index created");
+            debug!("{:?}", result);
+        }
+        Ok(true) => {
+            debug!("collection already exists");
+            migrate_quantization(&generation.collection, quantization, qdrant).await?;
+        }
+        Err(_) => return Err(SemanticError::QdrantInitializationError),
+    }
+
+    create_indexes(&generation.collection, qdrant).await?;
+    debug!("indexes created");
+
+    Ok(())
+}
+
 impl Semantic {
     #[tracing::instrument(fields(collection=%config.collection_name, %qdrant_url), skip_all)]
     pub async fn initialize(
@@ -206,32 +415,6 @@ impl Semantic {
         let qdrant = QdrantClient::new(Some(QdrantClientConfig::from_url(qdrant_url))).unwrap();
         debug!("initialized client");
 
-        match qdrant.has_collection(&config.collection_name).await {
-            Ok(false) => {
-                let CollectionOperationResponse { result, time } =
-                    create_collection(&config.collection_name, &qdrant)
-                        .await
-                        .unwrap();
-
-                debug!(time, created = result, "collection created");
-                assert!(result);
-                let PointsOperationResponse { result, time: _ } =
-                    create_lexical_index(&config.collection_name, &qdrant)
-                        .await
-                        .unwrap();
-
-                debug!("lexical index created");
-                debug!("{:?}", result);
-            }
-            Ok(true) => {
-                debug!("collection already exists");
-            }
-            Err(_) => return Err(SemanticError::QdrantInitializationError),
-        }
-
-        create_indexes(&config.collection_name, &qdrant).await?;
-        debug!("indexes created");
-
         if let Some(dylib_dir) = config.dylib_dir.as_ref() {
             init_ort_dylib(dylib_dir);
             debug!(
@@ -240,53 +423,175 @@ impl Semantic {
             );
         }
 
-        #[cfg(feature = "ee-cloud")]
-        let embedder: Arc<dyn Embedder> = if let Some(ref url) = config.embedding_server_url {
-            let embedder = Arc::new(embedder::RemoteEmbedder::new(url.clone(), model_dir)?);
-            debug!("using remote embedder");
-            embedder
-        } else {
-            let embedder = Arc::new(LocalEmbedder::new(model_dir)?);
-            debug!("using local embedder");
-            embedder
+        // A finished migration overrides the configured collection and model
+        let path = Generations::persisted_path(&config);
+        let persisted: Persisted =
+            crate::state::read_file_or_default(&path).map_err(anyhow::Error::from)?;
+
+        let embedder = load_embedder(
+            &config,
+            persisted
+                .active
+                .as_ref()
+                .map_or(model_dir, |active| &active.model_dir),
+        )?;
+
+        let active = match persisted.active {
+            Some(active) => active,
+            None => {
+                unrecorded_generation(&qdrant, &config.collection_name, model_dir, &*embedder)
+                    .await?
+            }
         };
 
-        #[cfg(not(feature = "ee-cloud"))]
-        let embedder: Arc<dyn Embedder> = Arc::new(LocalEmbedder::new(model_dir)?);
-        debug!("using local embedder");
+        active.accepts(&*embedder)?;
 
-        Ok(Self {
-            qdrant: qdrant.into(),
+        // Without the size of the vectors, the collection is created once the embedder answers
+        let deferred = active.dim == 0;
+        if !deferred {
+            prepare_collection(&active, &qdrant, config.vector_quantization).await?;
+        }
+
+        let active = LoadedGeneration {
             embedder,
+            generation: active,
+        };
+
+        let previous = persisted.previous.and_then(|generation| {
+            let loaded = load_embedder(&config, &generation.model_dir).and_then(|embedder| {
+                generation.accepts(&*embedder)?;
+                Ok(embedder)
+            });
+
+            match loaded {
+                Ok(embedder) => Some(LoadedGeneration {
+                    generation,
+                    embedder,
+                }),
+                Err(err) => {
+                    warn!(
+                        ?err,
+                        "failed to load the previous embedding model, rollback is unavailable"
+                    );
+                    None
+                }
+            }
+        });
+
+        // The copy doesn't resume, so whatever was written so far is useless
+        let status = match persisted.target {
+            Some(target) => {
+                warn!(collection = %target.collection, "embedding migration was interrupted");
+                _ = qdrant.delete_collection(&target.collection).await;
+                Status::Failed {
+                    error: "interrupted by a restart".into(),
+                }
+            }
+            None => Status::Idle,
+        };
+
+        let generations = Generations {
+            path,
+            active,
+            target: None,
+            previous,
+            status,
+        };
+
+        let semantic = Self {
+            qdrant: qdrant.into(),
+            generations: RwLock::new(generations).into(),
+            writes: Default::default(),
             config,
-        })
+        };
+
+        if deferred {
+            tokio::spawn(semantic.clone().create_deferred_collection());
+        } else {
+            semantic.generations.read().unwrap().store()?;
+        }
+
+        Ok(semantic)
     }
 
-    pub fn collection_name(&self) -> &str {
-        &self.config.collection_name
+    /// Ask the embedder for the size of its vectors until it answers, and create the collection
+    /// that couldn't be created on startup.
+    async fn create_deferred_collection(self) {
+        let embedder = self.active().embedder;
+        let dim = loop {
+            match migration::dimension(&*embedder).await {
+                Ok(dim) => break dim,
+                Err(err) => {
+                    debug!(?err, "embedder is still unreachable");
+                    tokio::time::sleep(PROBE_INTERVAL).await;
+                }
+            }
+        };
+
+        let generation = {
+            let mut generations = self.generations.write().unwrap();
+            generations.active.generation.dim = dim;
+            generations.active.generation.clone()
+        };
+
+        let prepared =
+            prepare_collection(&generation, &self.qdrant, self.config.vector_quantization).await;
+        if let Err(err) = prepared {
+            error!(?err, "failed to create the deferred collection");
+            return;
+        }
+
+        info!(collection = %generation.collection, dim, "created deferred collection");
+        if let Err(err) = self.generations.read().unwrap().store() {
+            error!(?err, "failed to record the embedding generation");
+        }
+    }
+
+    /// The generation searches go to.
+    pub(crate) fn active(&self) -> LoadedGeneration {
+        self.generations.read().unwrap().active.clone()
     }
 
     pub fn qdrant_client(&self) -> &QdrantClient {
         &self.qdrant
     }
 
-    pub fn embedder(&self) -> &dyn Embedder {
-        self.embedder.as_ref()
+    pub fn embedder(&self) -> Arc<dyn Embedder> {
+        self.active().embedder
     }
 
-    pub async fn reset_collection_blocking(&self) -> anyhow::Result<()> {
-        _ = self
+    /// Look up a single chunk by its point ID.
+    pub async fn get_chunk(&self, id: uuid::Uuid) -> anyhow::Result<Option<Payload>> {
+        let response = self
             .qdrant
-            .delete_collection(&self.config.collection_name)
+            .get_points(
+                &self.active().generation.collection,
+                &[PointId::from(id.to_string())],
+                Some(WithVectorsSelector {
+                    selector_options: Some(with_vectors_selector::SelectorOptions::Enable(false)),
+                }),
+                Some(WithPayloadSelector {
+                    selector_options: Some(with_payload_selector::SelectorOptions::Enable(true)),
+                }),
+                None,
+            )
             .await?;
 
+        Ok(response.result.into_iter().next().map(Payload::from_scroll))
+    }
+
+    pub async fn reset_collection_blocking(&self) -> anyhow::Result<()> {
+        self.drop_inactive_generations().await?;
+
+        let Generation {
+            collection, dim, ..
+        } = self.active().generation;
+
+        _ = self.qdrant.delete_collection(&collection).await?;
+
         let deleted = 'deleted: {
             for _ in 0..60 {
-                match self
-                    .qdrant
-                    .has_collection(&self.config.collection_name)
-                    .await
-                {
+                match self.qdrant.has_collection(&collection).await {
                     Ok(true) => {
                         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
                     }
@@ -306,15 +611,19 @@ impl Semantic {
             bail!("deletion failed")
         }
 
-        let CollectionOperationResponse { result, .. } =
-            create_collection(&self.config.collection_name, &self.qdrant)
-                .await
-                .unwrap();
+        let CollectionOperationResponse { result, .. } = create_collection(
+            &collection,
+            dim,
+            &self.qdrant,
+            self.config.vector_quantization,
+        )
+        .await
+        .unwrap();
 
         assert!(result);
 
         let PointsOperationResponse { result, time: _ } =
-            create_lexical_index(&self.config.collection_name, &self.qdrant)
+            create_lexical_index(&collection, &self.qdrant)
                 .await
                 .unwrap();
 
@@ -324,6 +633,16 @@ impl Semantic {
         Ok(())
     }
 
+    /// Rescore quantized search results with the original vectors.
+    fn quantization_params(&self) -> Option<QuantizationSearchParams> {
+        (self.config.vector_quantization != VectorQuantization::None).then(|| {
+            QuantizationSearchParams {
+                rescore: Some(true),
+                ..Default::default()
+            }
+        })
+    }
+
     pub async fn health_check(&self) -> anyhow::Result<()> {
         self.qdrant.health_check().await?;
         Ok(())
@@ -332,6 +651,7 @@ impl Semantic {
     // Search Qdrant snippets (payload)
     pub async fn search_lexical<'a>(
         &self,
+        collection: &str,
         parsed_query: &SemanticQuery<'a>,
         vector: Embedding,
         limit: u64,
@@ -345,17 +665,24 @@ impl Semantic {
             ..Default::default()
         });
 
+        let _timer = QDRANT_QUERY_SECONDS
+            .with_label_values(&["lexical"])
+            .start_timer();
         let response = self
             .qdrant
             .search_points(&SearchPoints {
                 limit,
                 vector,
-                collection_name: self.config.collection_name.to_string(),
+                collection_name: collection.to_string(),
                 offset: Some(offset),
                 score_threshold: Some(threshold),
                 with_payload: Some(true.into()),
                 filter: hybrid_filter,
                 with_vectors: Some(true.into()),
+                params: Some(SearchParams {
+                    quantization: self.quantization_params(),
+                    ..Default::default()
+                }),
                 ..Default::default()
             })
             .await?;
@@ -365,6 +692,7 @@ impl Semantic {
 
     pub async fn search_with<'a>(
         &self,
+        collection: &str,
         parsed_query: &SemanticQuery<'a>,
         vector: Embedding,
         limit: u64,
@@ -372,12 +700,15 @@ impl Semantic {
         threshold: f32,
         exact: bool,
     ) -> anyhow::Result<Vec<ScoredPoint>> {
+        let _timer = QDRANT_QUERY_SECONDS
+            .with_label_values(&["search"])
+            .start_timer();
         let response = self
             .qdrant
             .search_points(&SearchPoints {
                 limit,
                 vector,
-                collection_name: self.config.collection_name.to_string(),
+                collection_name: collection.to_string(),
                 offset: Some(offset),
                 score_threshold: Some(threshold),
                 with_payload: Some(WithPayloadSelector {
@@ -392,6 +723,7 @@ impl Semantic {
                 }),
                 params: Some(SearchParams {
                     indexed_only: Some(true),
+                    quantization: self.quantization_params(),
                     ..Default::default()
                 }),
                 ..Default::default()
@@ -403,6 +735,7 @@ impl Semantic {
 
     pub async fn batch_search_with<'a>(
         &self,
+        collection: &str,
         parsed_queries: &[&SemanticQuery<'a>],
         vectors: Vec<Embedding>,
         limit: u64,
@@ -430,7 +763,7 @@ impl Semantic {
                 let points = SearchPoints {
                     limit,
                     vector,
-                    collection_name: self.config.collection_name.to_string(),
+                    collection_name: collection.to_string(),
                     offset: Some(offset),
                     score_threshold: Some(threshold),
                     with_payload: Some(WithPayloadSelector {
@@ -447,9 +780,16 @@ impl Semantic {
                             true,
                         )),
                     }),
+                    params: Some(SearchParams {
+                        quantization: self.quantization_params(),
+                        ..Default::default()
+                    }),
                     ..Default::default()
                 };
 
+                let _timer = QDRANT_QUERY_SECONDS
+                    .with_label_values(&["batch_search"])
+                    .start_timer();
                 self.qdrant.search_points(&points).await
             })
             .buffered(10)
@@ -462,7 +802,7 @@ impl Semantic {
     // Rank Qdrant lexical results by counts of word matches
     // Multiple word hits count more
     // Score is 10 * (# of query words matched) + (total number of hits)
-    fn rank_lexical(payloads: Vec<Payload>, query: &str) -> Vec<Payload> {
+    fn rank_lexical(payloads: Vec<Payload>, query: &str) -> Vec<(f32, Payload)> {
         let keywords: Vec<&str> = query.split_whitespace().collect();
         let counts = payloads.iter().map(|p| {
             (
@@ -482,23 +822,49 @@ impl Semantic {
             })
             .collect();
         scores.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
-        scores.into_iter().map(|(_, payload)| payload).collect()
+        scores
     }
 
     // Join semantic and lexical results using Reciprocal Rank Fusion (RRF)
     // For item i present in j rankings (rank_i,j) score_i = sum_j (1/ (rank_i,j + k))
     // k controls how much weight to give to lower ranks and is set to 60 which is
     // the default value in multiple implementations and in http://plg.uwaterloo.ca/~gvcormac/cormacksigir09-rrf.pdf
-    fn merge_rrf(payloads_lexical: Vec<Payload>, payloads_semantic: Vec<Payload>) -> Vec<Payload> {
+    fn merge_rrf(
+        payloads_lexical: Vec<(f32, Payload)>,
+        payloads_semantic: Vec<Payload>,
+    ) -> Vec<Payload> {
         let k = 60;
-        let lexical_scores = payloads_lexical
-            .into_iter()
-            .enumerate()
-            .map(|(rank, payload)| (1.0 / ((rank + 1 + k) as f32), payload.clone()));
-        let payload_scores = payloads_semantic
-            .into_iter()
-            .enumerate()
-            .map(|(rank, payload)| (1.0 / ((rank + 1 + k) as f32), payload.clone()));
+        let lexical_scores =
+            payloads_lexical
+                .into_iter()
+                .enumerate()
+                .map(|(rank, (lexical_score, mut payload))| {
+                    let weight = 1.0 / ((rank + 1 + k) as f32);
+                    payload.fusion = Some(Fusion {
+                        similarity: payload.score,
+                        lexical_rank: Some(rank + 1),
+                        lexical_score: Some(lexical_score),
+                        lexical_weight: Some(weight),
+                        score: weight,
+                        ..Fusion::new(k)
+                    });
+                    (weight, payload)
+                });
+        let payload_scores =
+            payloads_semantic
+                .into_iter()
+                .enumerate()
+                .map(|(rank, mut payload)| {
+                    let weight = 1.0 / ((rank + 1 + k) as f32);
+                    payload.fusion = Some(Fusion {
+                        similarity: payload.score,
+                        semantic_rank: Some(rank + 1),
+                        semantic_weight: Some(weight),
+                        score: weight,
+                        ..Fusion::new(k)
+                    });
+                    (weight, payload)
+                });
         let mut concatenated: Vec<(f32, Payload)> = lexical_scores.chain(payload_scores).collect();
         // group by requires pre-sorting
         concatenated.sort_by(|a, b| {
@@ -514,11 +880,19 @@ impl Semantic {
                 let group_vec: Vec<_> = group.collect();
 
                 let sum: f32 = group_vec.iter().map(|(score, _payload)| score).sum();
-                let payload = group_vec
+                let fusion = group_vec
+                    .iter()
+                    .filter_map(|(_score, payload)| payload.fusion.as_ref())
+                    .fold(Fusion::new(k), Fusion::merge);
+                let mut payload = group_vec
                     .into_iter()
                     .map(|(_score, payload)| payload)
-                    .next();
-                (sum, payload.cloned().unwrap())
+                    .next()
+                    .cloned()
+                    .unwrap();
+
+                payload.fusion = Some(fusion);
+                (sum, payload)
             })
             .collect();
         merged.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
@@ -533,7 +907,10 @@ impl Semantic {
         let Some(query) = parsed_query.target() else {
             anyhow::bail!("no search target for query");
         };
-        let vector = self.embedder.embed(&query).await?;
+        // The query has to be embedded by the model that produced the collection
+        let active = self.active();
+        let collection = &active.generation.collection;
+        let vector = active.embedder.embed(&query).await?;
         let SemanticSearchParams {
             limit,
             offset,
@@ -546,6 +923,7 @@ impl Semantic {
         // In /answer we want to retrieve `limit` results exactly
         let results = self
             .search_with(
+                collection,
                 parsed_query,
                 vector.clone(),
                 limit * 2, // Retrieve double `limit` and deduplicate
@@ -563,6 +941,7 @@ impl Semantic {
 
         let results_lexical = self
             .search_lexical(
+                collection,
                 parsed_query,
                 vector.clone(),
                 limit * 2, // Retrieve double `limit` and deduplicate
@@ -597,10 +976,11 @@ impl Semantic {
             anyhow::bail!("no search target for query");
         };
 
+        let active = self.active();
         let vectors = futures::future::join_all(
             parsed_queries
                 .iter()
-                .map(|q| async { self.embedder.embed(&q.target().unwrap()).await }),
+                .map(|q| async { active.embedder.embed(&q.target().unwrap()).await }),
         )
         .await
         .into_iter()
@@ -617,6 +997,7 @@ impl Semantic {
 
         let result = self
             .batch_search_with(
+                &active.generation.collection,
                 parsed_queries,
                 vectors.clone(),
                 limit * 2, // Retrieve double `limit` and deduplicate
@@ -653,25 +1034,67 @@ impl Semantic {
     ) -> impl ParallelIterator<Item = (String, Payload)> + 'a {
         const MIN_CHUNK_TOKENS: usize = 50;
 
-        let chunks = chunk::by_tokens(
-            repo_name,
-            relative_path,
-            buffer,
-            self.embedder.tokenizer(),
-            MIN_CHUNK_TOKENS..self.config.max_chunk_tokens,
-            chunk::OverlapStrategy::default(),
-        );
+        // Notebook cells and Terraform blocks are often short, but make sense on their own
+        const MIN_SECTION_TOKENS: usize = 10;
+
+        let embedder = self.embedder();
+        let sections = if notebook::is_notebook(relative_path) {
+            notebook::cells(buffer)
+                .into_iter()
+                .map(|cell| chunk::Section {
+                    lang: cell.lang,
+                    text: cell.text,
+                    start_byte: cell.start_byte,
+                    start_line: cell.start_line,
+                })
+                .collect()
+        } else {
+            chunk::sections(lang_str, buffer)
+        };
+
+        let chunks = if sections.is_empty() {
+            chunk::by_tokens(
+                repo_name,
+                relative_path,
+                buffer,
+                embedder.tokenizer(),
+                MIN_CHUNK_TOKENS..self.config.max_chunk_tokens,
+                chunk::OverlapStrategy::default(),
+            )
+            .into_iter()
+            .map(|chunk| (lang_str, chunk))
+            .collect::<Vec<_>>()
+        } else {
+            sections
+                .into_iter()
+                .flat_map(|section| {
+                    chunk::by_tokens(
+                        repo_name,
+                        relative_path,
+                        section.text,
+                        embedder.tokenizer(),
+                        MIN_SECTION_TOKENS..self.config.max_chunk_tokens,
+                        chunk::OverlapStrategy::default(),
+                    )
+                    .into_iter()
+                    .map(move |chunk| {
+                        let chunk = chunk.offset(section.start_byte, section.start_line);
+                        (section.lang, chunk)
+                    })
+                })
+                .collect()
+        };
         trace!(chunk_count = chunks.len(), "found chunks");
 
-        chunks.into_par_iter().map(move |chunk| {
-            let data = format!("{repo_name}\t{relative_path}\n{}", chunk.data);
+        chunks.into_par_iter().map(move |(lang, chunk)| {
+            let data = embedding_input(repo_name, relative_path, chunk.data);
             let payload = Payload {
                 repo_name: repo_name.to_owned(),
                 repo_ref: repo_ref.to_owned(),
                 relative_path: relative_path.to_owned(),
                 content_hash: file_cache_key.to_string(),
                 text: chunk.data.to_owned(),
-                lang: lang_str.to_ascii_lowercase(),
+                lang: lang.to_ascii_lowercase(),
                 branches: branches.to_owned(),
                 start_line: chunk.range.start.line as u64,
                 end_line: chunk.range.end.line as u64,
@@ -684,14 +1107,38 @@ impl Semantic {
         })
     }
 
+    /// The number of points stored for a repository.
+    pub async fn count_points_for_repo(&self, repo_ref: &str) -> anyhow::Result<u64> {
+        let response = self
+            .qdrant
+            .count(&CountPoints {
+                collection_name: self.active().generation.collection,
+                filter: Some(Filter {
+                    must: vec![make_kv_keyword_filter("repo_ref", repo_ref).into()],
+                    ..Default::default()
+                }),
+                exact: Some(true),
+            })
+            .await?;
+
+        Ok(response.result.map_or(0, |r| r.count))
+    }
+
+    /// The uncompressed size of a single point's vector.
+    pub fn vector_bytes(&self) -> u64 {
+        (self.active().generation.dim * std::mem::size_of::<f32>()) as u64
+    }
+
     pub async fn delete_points_for_hash(
         &self,
         repo_ref: &str,
         paths: impl Iterator<Item = String>,
     ) {
+        let paths = paths.collect::<Vec<_>>();
         let repo_filter = make_kv_keyword_filter("repo_ref", repo_ref).into();
         let file_filter = paths
-            .map(|p| make_kv_keyword_filter("content_hash", &p).into())
+            .iter()
+            .map(|p| make_kv_keyword_filter("content_hash", p).into())
             .collect::<Vec<_>>();
 
         let selector = Filter {
@@ -701,11 +1148,68 @@ impl Semantic {
         }
         .into();
 
-        let _ = self
-            .qdrant
-            .delete_points(&self.config.collection_name, &selector, None)
-            .await;
+        let _writes = self.lock_writes().await;
+        self.touch_files(repo_ref, &paths);
+
+        for collection in self.write_collections() {
+            let _ = self
+                .qdrant
+                .delete_points(&collection, &selector, None)
+                .await;
+        }
+    }
+}
+
+/// The text that gets embedded for a chunk.
+///
+/// Chunks are prefixed with their location, which makes it easier to find files by name.
+fn embedding_input(repo_name: &str, relative_path: &str, text: &str) -> String {
+    format!("{repo_name}\t{relative_path}\n{text}")
+}
+
+fn load_embedder(
+    config: &Configuration,
+    model_dir: &Path,
+) -> Result<Arc<dyn Embedder>, SemanticError> {
+    match config.embedder {
+        EmbedderKind::Local => {}
+        EmbedderKind::OpenAi => {
+            let embedder = Arc::new(embedder::OpenAiEmbedder::new(
+                config.embedder_url.clone(),
+                config.embedder_model.clone(),
+                config.embedder_api_key.clone(),
+                model_dir,
+            )?);
+            debug!(
+                model = embedder.model_id(),
+                "using OpenAI-compatible embedder"
+            );
+            return Ok(embedder);
+        }
+        EmbedderKind::Ollama => {
+            let embedder = Arc::new(embedder::OllamaEmbedder::new(
+                config.embedder_url.clone(),
+                config.embedder_model.clone(),
+                model_dir,
+            )?);
+            debug!(model = embedder.model_id(), "using Ollama embedder");
+            return Ok(embedder);
+        }
     }
+
+    // The embedding server only serves the configured model
+    #[cfg(feature = "ee-cloud")]
+    if let Some(ref url) = config.embedding_server_url {
+        if model_dir == config.model_dir {
+            let embedder = Arc::new(embedder::RemoteEmbedder::new(url.clone(), model_dir)?);
+            debug!("using remote embedder");
+            return Ok(embedder);
+        }
+    }
+
+    let embedder = Arc::new(LocalEmbedder::new(model_dir)?);
+    debug!("using local embedder");
+    Ok(embedder)
 }
 
 /// Initialize the `ORT_DYLIB_PATH` variable, consumed by the `ort` crate.
@@ -882,7 +1386,8 @@ fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
 // Calculate the element-wise mean of the embeddings
 fn mean_pool(embeddings: Vec<Vec<f32>>) -> Vec<f32> {
     let len = embeddings.len() as f32;
-    let mut result = vec![0.0; EMBEDDING_DIM];
+    let dim = embeddings.first().map_or(0, Vec::len);
+    let mut result = vec![0.0; dim];
     for embedding in embeddings {
         for (i, v) in embedding.iter().enumerate() {
             result[i] += v;
@@ -36,11 +36,65 @@ impl<'a> Chunk<'a> {
         self.data.len()
     }
 
+    /// Move a chunk of a part of a file to its position in the whole file.
+    pub fn offset(mut self, byte: usize, line: usize) -> Self {
+        for point in [&mut self.range.start, &mut self.range.end] {
+            point.byte += byte;
+            point.line += line;
+        }
+        self
+    }
+
     pub fn is_empty(&self) -> bool {
         self.data.len() < 1
     }
 }
 
+/// A part of a file that is chunked on its own, like a notebook cell or a Terraform block.
+#[derive(Debug, PartialEq)]
+pub struct Section<'a> {
+    pub lang: &'a str,
+    pub text: &'a str,
+    pub start_byte: usize,
+    pub start_line: usize,
+}
+
+/// Split files whose top-level items stand on their own into sections.
+///
+/// Terraform files are split into their top-level blocks, and YAML files into their documents,
+/// so that every Kubernetes resource of a manifest gets its own chunks. Other files aren't split.
+pub fn sections<'a>(lang: &'a str, src: &'a str) -> Vec<Section<'a>> {
+    let starts_section: fn(&str) -> bool = match lang {
+        "HCL" => |line| line.starts_with(|c: char| c.is_ascii_alphabetic()),
+        "YAML" => |line| line.trim_end() == "---",
+        _ => return vec![],
+    };
+
+    let mut sections: Vec<Section<'a>> = vec![];
+    let mut byte = 0;
+    for (line_number, line) in src.split_inclusive('\n').enumerate() {
+        if sections.is_empty() || starts_section(line) {
+            sections.push(Section {
+                lang,
+                text: "",
+                start_byte: byte,
+                start_line: line_number,
+            });
+        }
+
+        byte += line.len();
+        if let Some(section) = sections.last_mut() {
+            section.text = &src[section.start_byte..byte];
+        }
+    }
+
+    if sections.len() < 2 {
+        return vec![];
+    }
+
+    sections
+}
+
 /// This calculates the line and column for a given byte position. The last_line and last_byte
 /// parameters can be used to reduce the amount of searching for the line position from quadratic
 /// to linear. If in doubt, just use `0` for last_line and `0` for last_byte.
@@ -501,6 +555,33 @@ mod tests {
         }
     }
 
+    #[test]
+    pub fn sections_of_manifests() {
+        let manifest = "kind: Service\nmetadata:\n  name: api\n---\nkind: Ingress\n";
+        let sections = super::sections("YAML", manifest);
+
+        assert_eq!(
+            sections
+                .iter()
+                .map(|s| (s.text, s.start_line))
+                .collect::<Vec<_>>(),
+            [
+                ("kind: Service\nmetadata:\n  name: api\n", 0),
+                ("---\nkind: Ingress\n", 3)
+            ]
+        );
+        assert_eq!(sections[1].start_byte, manifest.find("---").unwrap());
+
+        let terraform = "# storage\nresource \"aws_s3_bucket\" \"prod\" {\n  bucket = \"x\"\n}\n\noutput \"arn\" {}\n";
+        let sections = super::sections("HCL", terraform);
+        assert_eq!(sections.len(), 3);
+        assert!(sections[1].text.starts_with("resource"));
+        assert!(sections[2].text.starts_with("output"));
+
+        assert!(super::sections("YAML", "key: value\n").is_empty());
+        assert!(super::sections("Rust", terraform).is_empty());
+    }
+
     static SRC: &str = r#"
 use crate::{semantic::chunk::OverlapStrategy, state::StateSource};
 use anyhow::{Context, Result};
@@ -0,0 +1,196 @@
+//! How much of a repository was embedded by its last index, so that failing embeddings show up
+//! somewhere other than in bad answers.
+//!
+//! Counts are gathered while a repository is indexed, and saved when the index finishes. An index
+//! that only walks the files changed since the last one only counts the chunks of those files.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use qdrant_client::qdrant::{value::Kind, Value};
+use serde::{Deserialize, Serialize};
+
+/// Upper bounds of the buckets that chunk lengths are counted in, in bytes. Longer chunks are
+/// counted in a last, unbounded bucket.
+const BUCKETS: [usize; 5] = [256, 512, 1024, 2048, 4096];
+
+/// The counts of the repositories being indexed, by repository.
+#[derive(Default)]
+pub(crate) struct Tracker {
+    repos: scc::HashMap<String, Arc<Counters>>,
+}
+
+#[derive(Default)]
+pub(crate) struct Counters {
+    embedded: AtomicUsize,
+    too_short: AtomicUsize,
+    embed_failed: AtomicUsize,
+    upsert_failed: AtomicUsize,
+    bytes: AtomicUsize,
+    lengths: [AtomicUsize; BUCKETS.len() + 1],
+}
+
+/// The chunks of one index of a repository.
+#[derive(Serialize, Deserialize, schemars::JsonSchema, Debug, Default, PartialEq)]
+pub struct Run {
+    /// Chunks that were embedded and stored
+    pub embedded: usize,
+    pub skipped: Skipped,
+    /// The average length of the chunks sent to the embedder, in bytes
+    pub avg_chunk_bytes: Option<f64>,
+    pub chunk_bytes: Vec<Bucket>,
+}
+
+/// Chunks that aren't searchable semantically, by reason.
+#[derive(Serialize, Deserialize, schemars::JsonSchema, Debug, Default, PartialEq)]
+pub struct Skipped {
+    /// Files with content, but too little of it to make a single chunk
+    pub too_short: usize,
+    /// Chunks the embedder returned an error for
+    pub embed_failed: usize,
+    /// Chunks that were embedded, but couldn't be written to Qdrant
+    pub upsert_failed: usize,
+}
+
+#[derive(Serialize, Deserialize, schemars::JsonSchema, Debug, PartialEq)]
+pub struct Bucket {
+    /// Chunks up to this many bytes long, and longer than the previous bucket. The last bucket
+    /// has no bound.
+    pub up_to: Option<usize>,
+    pub chunks: usize,
+}
+
+impl Tracker {
+    /// Start counting an index of a repository, dropping the counts of any previous one.
+    pub(crate) fn start(&self, repo_ref: &str) {
+        _ = self.repos.remove(repo_ref);
+        _ = self.repos.insert(repo_ref.to_owned(), Default::default());
+    }
+
+    /// The counts of a repository. Chunks of repositories that aren't being indexed are counted
+    /// towards nothing.
+    pub(crate) fn counters(&self, repo_ref: &str) -> Arc<Counters> {
+        self.repos
+            .read(repo_ref, |_, counters| counters.clone())
+            .unwrap_or_default()
+    }
+
+    /// Stop counting an index of a repository.
+    pub(crate) fn finish(&self, repo_ref: &str) -> Option<Run> {
+        self.repos
+            .remove(repo_ref)
+            .map(|(_, counters)| counters.snapshot())
+    }
+
+    /// Count the points written to, or that failed to be written to, the active collection, by
+    /// the repository of each point.
+    pub(crate) fn upserted(&self, repos: &[String], success: bool) {
+        for repo_ref in repos {
+            let counters = self.counters(repo_ref);
+            if success {
+                counters.embedded.fetch_add(1, Ordering::Relaxed);
+            } else {
+                counters.upsert_failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl Counters {
+    pub(crate) fn too_short(&self) {
+        self.too_short.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn embed_failed(&self) {
+        self.embed_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a chunk that was sent to the embedder.
+    pub(crate) fn chunk(&self, len: usize) {
+        let bucket = BUCKETS
+            .iter()
+            .position(|&up_to| len <= up_to)
+            .unwrap_or(BUCKETS.len());
+
+        self.bytes.fetch_add(len, Ordering::Relaxed);
+        self.lengths[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Run {
+        let chunk_bytes = self
+            .lengths
+            .iter()
+            .enumerate()
+            .map(|(i, count)| Bucket {
+                up_to: BUCKETS.get(i).copied(),
+                chunks: count.load(Ordering::Relaxed),
+            })
+            .collect::<Vec<_>>();
+
+        let chunks = chunk_bytes.iter().map(|b| b.chunks).sum::<usize>();
+        let avg_chunk_bytes =
+            (chunks > 0).then(|| self.bytes.load(Ordering::Relaxed) as f64 / chunks as f64);
+
+        Run {
+            embedded: self.embedded.load(Ordering::Relaxed),
+            skipped: Skipped {
+                too_short: self.too_short.load(Ordering::Relaxed),
+                embed_failed: self.embed_failed.load(Ordering::Relaxed),
+                upsert_failed: self.upsert_failed.load(Ordering::Relaxed),
+            },
+            avg_chunk_bytes,
+            chunk_bytes,
+        }
+    }
+}
+
+impl Skipped {
+    /// Chunks that would have been embedded, if not for an error.
+    pub fn failed(&self) -> usize {
+        self.embed_failed + self.upsert_failed
+    }
+}
+
+/// The repository of a chunk, from its Qdrant payload.
+pub(crate) fn repo_of(payload: &HashMap<String, Value>) -> Option<&str> {
+    match payload.get("repo_ref")?.kind.as_ref()? {
+        Kind::StringValue(repo_ref) => Some(repo_ref),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_chunk_lengths_in_buckets() {
+        let tracker = Tracker::default();
+        tracker.start("local//repo");
+
+        let counters = tracker.counters("local//repo");
+        for len in [100, 256, 300, 5000] {
+            counters.chunk(len);
+        }
+        counters.embed_failed();
+
+        // Chunks of repositories that aren't being indexed are dropped
+        tracker.counters("local//other").chunk(100);
+
+        let run = tracker.finish("local//repo").unwrap();
+        assert_eq!(run.avg_chunk_bytes, Some(1414.0));
+        assert_eq!(run.skipped.failed(), 1);
+        assert_eq!(
+            run.chunk_bytes.iter().map(|b| b.chunks).collect::<Vec<_>>(),
+            [2, 1, 0, 0, 0, 1]
+        );
+        assert_eq!(run.chunk_bytes.last().unwrap().up_to, None);
+
+        assert!(tracker.finish("local//repo").is_none());
+    }
+}
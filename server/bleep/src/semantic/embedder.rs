@@ -15,6 +15,9 @@ use super::Embedding;
 #[cfg(feature = "ee-cloud")]
 pub use crate::ee::embedder::*;
 
+mod http;
+pub use http::{OllamaEmbedder, OpenAiEmbedder};
+
 #[derive(Default)]
 pub struct EmbedQueue {
     log: scc::Queue<Mutex<Option<EmbedChunk>>>,
@@ -61,6 +64,25 @@ pub trait Embedder: Send + Sync {
     async fn embed(&self, data: &str) -> anyhow::Result<Embedding>;
     fn tokenizer(&self) -> &Tokenizer;
     async fn batch_embed(&self, log: Vec<&str>) -> anyhow::Result<Vec<Embedding>>;
+
+    /// The provider and model, like `openai/text-embedding-ada-002`.
+    ///
+    /// Vectors are only comparable to those with the same identifier.
+    fn model_id(&self) -> &str;
+}
+
+/// The identifier of a local model, by the name of its directory.
+pub fn local_model_id(model_dir: &Path) -> String {
+    let name = model_dir
+        .canonicalize()
+        .ok()
+        .and_then(|dir| {
+            dir.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| model_dir.to_string_lossy().into_owned());
+
+    format!("local/{name}")
 }
 
 #[cfg(all(not(feature = "metal"), feature = "onnx"))]
@@ -83,6 +105,7 @@ mod cpu {
     pub struct LocalEmbedder {
         session: ort::Session,
         tokenizer: Tokenizer,
+        id: String,
     }
 
     impl LocalEmbedder {
@@ -109,7 +132,11 @@ mod cpu {
 
             let tokenizer = Tokenizer::from_file(model_dir.join("tokenizer.json")).unwrap();
 
-            Ok(Self { session, tokenizer })
+            Ok(Self {
+                session,
+                tokenizer,
+                id: local_model_id(model_dir),
+            })
         }
     }
 
@@ -167,6 +194,10 @@ mod cpu {
             &self.tokenizer
         }
 
+        fn model_id(&self) -> &str {
+            &self.id
+        }
+
         async fn batch_embed(&self, log: Vec<&str>) -> anyhow::Result<Vec<Embedding>> {
             log.into_iter()
                 .map(|entry| {
@@ -189,6 +220,7 @@ mod gpu {
         model: Box<dyn llm::Model>,
         sessions: Vec<Arc<tokio::sync::Mutex<llm::InferenceSession>>>,
         tokenizer: Tokenizer,
+        id: String,
         permits: Arc<tokio::sync::Semaphore>,
     }
 
@@ -242,6 +274,7 @@ mod gpu {
                 model,
                 sessions,
                 tokenizer,
+                id: local_model_id(model_dir),
                 permits: Arc::new(tokio::sync::Semaphore::new(session_count)),
             })
         }
@@ -280,6 +313,10 @@ mod gpu {
             &self.tokenizer
         }
 
+        fn model_id(&self) -> &str {
+            &self.id
+        }
+
         async fn batch_embed(&self, log: Vec<&str>) -> anyhow::Result<Vec<Embedding>> {
             // do not send empty batches to the model
             if log.is_empty() {
@@ -312,10 +349,10 @@ mod gpu {
                             &query_token_ids,
                             &mut output_request,
                         );
-                        let embedding: Vec<Vec<f32>> = output_request
-                            .embeddings
-                            .unwrap()
-                            .chunks(crate::semantic::schema::EMBEDDING_DIM)
+                        let embeddings = output_request.embeddings.unwrap();
+                        let dim = embeddings.len() / query_token_ids.len();
+                        let embedding: Vec<Vec<f32>> = embeddings
+                            .chunks(dim)
                             .inspect(|chunk| {
                                 if chunk.iter().any(|f| f.is_nan()) {
                                     error!("found nan in sequence");
@@ -0,0 +1,235 @@
+//! Embeddings from HTTP APIs, instead of a local model.
+//!
+//! Chunks are still cut to size with the tokenizer in the model directory, which only approximates
+//! how the API counts tokens. `max_chunk_tokens` should leave some room for the difference.
+
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use async_trait::async_trait;
+use reqwest::Url;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use tokenizers::Tokenizer;
+
+use super::{Embedder, Embedding};
+
+const OPENAI_API: &str = "https://api.openai.com/v1/";
+
+const OPENAI_MODEL: &str = "text-embedding-ada-002";
+
+const OLLAMA_API: &str = "http://localhost:11434/";
+
+const OLLAMA_MODEL: &str = "nomic-embed-text";
+
+/// An OpenAI-compatible `/embeddings` API.
+pub struct OpenAiEmbedder {
+    url: Url,
+    model: String,
+    api_key: Option<SecretString>,
+    session: reqwest::Client,
+    tokenizer: Tokenizer,
+    id: String,
+}
+
+#[derive(Serialize)]
+struct OpenAiRequest<'a> {
+    model: &'a str,
+    input: Vec<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiResponse {
+    data: Vec<OpenAiEmbedding>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbedding {
+    index: usize,
+    embedding: Embedding,
+}
+
+impl OpenAiResponse {
+    /// The embeddings in the order of the inputs, which the API doesn't have to keep.
+    fn into_embeddings(mut self, expected: usize) -> anyhow::Result<Vec<Embedding>> {
+        if self.data.len() != expected {
+            bail!(
+                "the embedding API returned {} embeddings for {expected} inputs",
+                self.data.len()
+            );
+        }
+
+        self.data.sort_by_key(|e| e.index);
+        Ok(self.data.into_iter().map(|e| e.embedding).collect())
+    }
+}
+
+impl OpenAiEmbedder {
+    pub fn new(
+        url: Option<Url>,
+        model: Option<String>,
+        api_key: Option<SecretString>,
+        model_dir: &Path,
+    ) -> anyhow::Result<Self> {
+        let url = url.unwrap_or_else(|| OPENAI_API.parse().expect("valid URL"));
+        let model = model.unwrap_or_else(|| OPENAI_MODEL.into());
+
+        Ok(Self {
+            url: url.join("embeddings")?,
+            id: format!("openai/{model}"),
+            model,
+            api_key,
+            session: reqwest::Client::new(),
+            tokenizer: load_tokenizer(model_dir)?,
+        })
+    }
+}
+
+#[async_trait]
+impl Embedder for OpenAiEmbedder {
+    async fn embed(&self, data: &str) -> anyhow::Result<Embedding> {
+        self.batch_embed(vec![data])
+            .await?
+            .pop()
+            .context("the embedding API returned no embedding")
+    }
+
+    fn tokenizer(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
+
+    async fn batch_embed(&self, sequence: Vec<&str>) -> anyhow::Result<Vec<Embedding>> {
+        let expected = sequence.len();
+        let mut request = self.session.post(self.url.clone()).json(&OpenAiRequest {
+            model: &self.model,
+            input: sequence,
+        });
+
+        if let Some(ref key) = self.api_key {
+            request = request.bearer_auth(key.expose_secret());
+        }
+
+        request
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<OpenAiResponse>()
+            .await?
+            .into_embeddings(expected)
+    }
+
+    fn model_id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// An Ollama server, which embeds one input per request.
+pub struct OllamaEmbedder {
+    url: Url,
+    model: String,
+    session: reqwest::Client,
+    tokenizer: Tokenizer,
+    id: String,
+}
+
+#[derive(Serialize)]
+struct OllamaRequest<'a> {
+    model: &'a str,
+    prompt: &'a str,
+}
+
+#[derive(Deserialize)]
+struct OllamaResponse {
+    embedding: Embedding,
+}
+
+impl OllamaEmbedder {
+    pub fn new(url: Option<Url>, model: Option<String>, model_dir: &Path) -> anyhow::Result<Self> {
+        let url = url.unwrap_or_else(|| OLLAMA_API.parse().expect("valid URL"));
+        let model = model.unwrap_or_else(|| OLLAMA_MODEL.into());
+
+        Ok(Self {
+            url: url.join("api/embeddings")?,
+            id: format!("ollama/{model}"),
+            model,
+            session: reqwest::Client::new(),
+            tokenizer: load_tokenizer(model_dir)?,
+        })
+    }
+}
+
+#[async_trait]
+impl Embedder for OllamaEmbedder {
+    async fn embed(&self, data: &str) -> anyhow::Result<Embedding> {
+        let response: OllamaResponse = self
+            .session
+            .post(self.url.clone())
+            .json(&OllamaRequest {
+                model: &self.model,
+                prompt: data,
+            })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(response.embedding)
+    }
+
+    fn tokenizer(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
+
+    async fn batch_embed(&self, sequence: Vec<&str>) -> anyhow::Result<Vec<Embedding>> {
+        let mut embeddings = Vec::with_capacity(sequence.len());
+        for data in sequence {
+            embeddings.push(self.embed(data).await?);
+        }
+
+        Ok(embeddings)
+    }
+
+    fn model_id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// The tokenizer of the bundled model, which is in `ggml/` in GPU builds.
+fn load_tokenizer(model_dir: &Path) -> anyhow::Result<Tokenizer> {
+    let path = [
+        model_dir.join("tokenizer.json"),
+        model_dir.join("ggml").join("tokenizer.json"),
+    ]
+    .into_iter()
+    .find(|path| path.exists())
+    .context("no tokenizer in the model directory")?;
+
+    let mut tokenizer = Tokenizer::from_file(path)
+        .map_err(|err| anyhow::anyhow!("failed to load the tokenizer: {err}"))?;
+
+    // Only used for chunking, which shouldn't pad or truncate
+    let _ = tokenizer.with_padding(None).with_truncation(None);
+    Ok(tokenizer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_openai_embeddings_by_input() {
+        let response: OpenAiResponse = serde_json::from_value(serde_json::json!({
+            "object": "list",
+            "data": [
+                { "object": "embedding", "index": 1, "embedding": [0.5, 0.5] },
+                { "object": "embedding", "index": 0, "embedding": [1.0, 0.0] },
+            ],
+            "model": "text-embedding-ada-002",
+        }))
+        .unwrap();
+
+        let embeddings = response.into_embeddings(2).unwrap();
+        assert_eq!(embeddings, vec![vec![1.0, 0.0], vec![0.5, 0.5]]);
+    }
+}
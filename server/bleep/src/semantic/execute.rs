@@ -2,7 +2,10 @@ use std::collections::HashMap;
 
 use crate::{
     query::{
-        execute::{ApiQuery, PagingMetadata, QueryResponse, QueryResult, ResultStats},
+        execute::{
+            ApiQuery, Explanation, PagingMetadata, QueryResponse, QueryResult, ResultExplanation,
+            ResultStats,
+        },
         parser::SemanticQuery,
     },
     semantic::SemanticSearchParams,
@@ -30,6 +33,19 @@ pub async fn execute(
         )
         .await?;
 
+    let explain = params.explain.then(|| Explanation {
+        results: results
+            .iter()
+            .map(|payload| ResultExplanation {
+                relative_path: payload.relative_path.clone(),
+                line_range: Some(payload.start_line as usize..payload.end_line as usize),
+                fusion: payload.fusion.clone(),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    });
+
     let data = results
         .into_iter()
         .fold(HashMap::<_, Vec<_>>::new(), |mut acc, payload| {
@@ -65,5 +81,6 @@ pub async fn execute(
         metadata: PagingMetadata::new(params.page, params.page_size, None),
         stats: ResultStats::default(),
         data,
+        explain,
     })
 }
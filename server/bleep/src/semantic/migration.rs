@@ -0,0 +1,685 @@
+//! Moving the semantic index to a different embedding model.
+//!
+//! Vectors from two models can't be compared, so a new model gets its own
+//! collection. Existing chunks are re-embedded into it in the background, while
+//! newly indexed chunks are written to both collections. Searches keep using the
+//! active collection until the copy completes, at which point the new collection
+//! takes over. Points that are written while the copy runs are copied as they are
+//! afterwards, so that deleted chunks and old branches don't come back.
+//!
+//! The collection that was replaced is kept up to date as well, so that a
+//! migration can be rolled back without re-embedding anything. It is dropped
+//! when the migration is finalized, or when the next migration starts.
+
+use std::{
+    collections::HashSet,
+    mem,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Context};
+use qdrant_client::qdrant::{
+    with_payload_selector, with_vectors_selector, CountPoints, PointId, PointStruct,
+    RetrievedPoint, ScrollPoints, WithPayloadSelector, WithVectorsSelector,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use super::{
+    create_indexes, embedding_input,
+    schema::{create_collection, create_lexical_index},
+    Embedder, Payload, Semantic,
+};
+use crate::Configuration;
+
+/// A collection, and the model that produced its vectors.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Generation {
+    pub collection: String,
+    pub model_dir: PathBuf,
+    /// The [`Embedder::model_id`] of the vectors. Generations from before the embedder could be
+    /// configured don't have one, and were all embedded by the local model.
+    #[serde(default)]
+    pub embedder: Option<String>,
+    /// The size of the vectors, which is 0 until the embedder could be asked for it.
+    pub dim: usize,
+}
+
+impl Generation {
+    pub(super) fn new(collection: &str, model_dir: &Path, embedder: &str, dim: usize) -> Self {
+        Self {
+            collection: collection.to_owned(),
+            model_dir: model_dir.to_owned(),
+            embedder: Some(embedder.to_owned()),
+            dim,
+        }
+    }
+
+    pub(super) fn model_id(&self) -> String {
+        self.embedder
+            .clone()
+            .unwrap_or_else(|| super::embedder::local_model_id(&self.model_dir))
+    }
+
+    /// Check that `embedder` produces vectors that can be mixed with the collection's.
+    pub(super) fn accepts(&self, embedder: &dyn Embedder) -> Result<(), super::SemanticError> {
+        let indexed = self.model_id();
+        if indexed == embedder.model_id() {
+            return Ok(());
+        }
+
+        Err(super::SemanticError::EmbedderMismatch {
+            collection: self.collection.clone(),
+            indexed,
+            configured: embedder.model_id().to_owned(),
+        })
+    }
+}
+
+/// The size of the vectors of `embedder`.
+pub(super) async fn dimension(embedder: &dyn Embedder) -> anyhow::Result<usize> {
+    Ok(embedder
+        .embed("dimension probe")
+        .await
+        .context("failed to run the embedding model")?
+        .len())
+}
+
+#[derive(Clone)]
+pub(crate) struct LoadedGeneration {
+    pub(crate) generation: Generation,
+    pub(crate) embedder: Arc<dyn Embedder>,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "state")]
+pub enum Status {
+    #[default]
+    Idle,
+    Starting,
+    Migrating {
+        copied: u64,
+        total: u64,
+    },
+    Completed {
+        copied: u64,
+    },
+    Failed {
+        error: String,
+    },
+    Cancelled,
+    RolledBack,
+}
+
+/// What's written to disk, so a finished migration survives a restart.
+#[derive(Serialize, Deserialize, Default)]
+pub(super) struct Persisted {
+    pub(super) active: Option<Generation>,
+    pub(super) target: Option<Generation>,
+    pub(super) previous: Option<Generation>,
+}
+
+pub(super) struct Generations {
+    pub(super) path: PathBuf,
+    pub(super) active: LoadedGeneration,
+    pub(super) target: Option<LoadedGeneration>,
+    pub(super) previous: Option<LoadedGeneration>,
+    pub(super) status: Status,
+}
+
+impl Generations {
+    pub(super) fn persisted_path(config: &Configuration) -> PathBuf {
+        config.source.directory().join("embeddings.json")
+    }
+
+    pub(super) fn store(&self) -> anyhow::Result<()> {
+        let persisted = Persisted {
+            active: Some(self.active.generation.clone()),
+            target: self.target.as_ref().map(|t| t.generation.clone()),
+            previous: self.previous.as_ref().map(|p| p.generation.clone()),
+        };
+
+        Ok(crate::state::pretty_write_file(&self.path, &persisted)?)
+    }
+
+    /// Every collection that has to see new points, deletions and payload updates.
+    fn writable(&self) -> Vec<LoadedGeneration> {
+        std::iter::once(&self.active)
+            .chain(&self.target)
+            .chain(&self.previous)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Writes to the collections while points are being copied, which the copy must not undo.
+///
+/// Pages are embedded outside of any lock, so their points can be deleted or updated in the
+/// meantime. Writers hold the lock shared while they write to every collection, and record what
+/// they are about to touch. The copy takes it exclusively to write a page, and re-reads touched
+/// points from the active collection instead of writing what it read before.
+#[derive(Default)]
+pub(super) struct Writes {
+    lock: tokio::sync::RwLock<()>,
+    touched: Mutex<Option<Touched>>,
+}
+
+/// What was written since the page being copied was read.
+#[derive(Default)]
+struct Touched {
+    ids: HashSet<String>,
+    /// Files whose points were deleted by filter, by `(repo_ref, content_hash)`
+    files: HashSet<(String, String)>,
+}
+
+impl Touched {
+    fn contains(&self, payload: &Payload) -> bool {
+        payload.id.as_ref().map_or(true, |id| self.ids.contains(id))
+            || self
+                .files
+                .contains(&(payload.repo_ref.clone(), payload.content_hash.clone()))
+    }
+}
+
+impl Writes {
+    fn record(&self, record: impl FnOnce(&mut Touched)) {
+        if let Some(touched) = self.touched.lock().unwrap().as_mut() {
+            record(touched);
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub active: Generation,
+    pub target: Option<Generation>,
+    pub previous: Option<Generation>,
+    pub status: Status,
+}
+
+/// Collections are named after the configured one, so they are easy to tell apart in Qdrant.
+fn collection_for(base: &str, timestamp: i64) -> String {
+    format!("{base}_{timestamp}")
+}
+
+impl Semantic {
+    pub(crate) fn write_generations(&self) -> Vec<LoadedGeneration> {
+        self.generations.read().unwrap().writable()
+    }
+
+    pub(crate) fn write_collections(&self) -> Vec<String> {
+        self.write_generations()
+            .into_iter()
+            .map(|g| g.generation.collection)
+            .collect()
+    }
+
+    /// Hold this while writing to [`Self::write_collections`], so that a copy in progress sees
+    /// the writes to every collection at once.
+    pub(crate) async fn lock_writes(&self) -> tokio::sync::RwLockReadGuard<'_, ()> {
+        self.writes.lock.read().await
+    }
+
+    /// Record points that are about to be written or deleted, while holding
+    /// [`Self::lock_writes`].
+    pub(crate) fn touch_points<'a>(&self, ids: impl IntoIterator<Item = &'a str>) {
+        self.writes
+            .record(|touched| touched.ids.extend(ids.into_iter().map(str::to_owned)));
+    }
+
+    /// Record files whose points are about to be deleted, while holding [`Self::lock_writes`].
+    pub(crate) fn touch_files(&self, repo_ref: &str, content_hashes: &[String]) {
+        self.writes.record(|touched| {
+            touched.files.extend(
+                content_hashes
+                    .iter()
+                    .map(|hash| (repo_ref.to_owned(), hash.clone())),
+            )
+        });
+    }
+
+    pub fn migration_report(&self) -> Report {
+        let generations = self.generations.read().unwrap();
+        Report {
+            active: generations.active.generation.clone(),
+            target: generations.target.as_ref().map(|t| t.generation.clone()),
+            previous: generations.previous.as_ref().map(|p| p.generation.clone()),
+            status: generations.status.clone(),
+        }
+    }
+
+    /// Start re-embedding the index with the model in `model_dir`.
+    ///
+    /// Returns once the new collection exists, the copy itself runs in the background.
+    pub async fn start_migration(&self, model_dir: PathBuf) -> anyhow::Result<Generation> {
+        {
+            let mut generations = self.generations.write().unwrap();
+            if generations.target.is_some() || generations.status == Status::Starting {
+                bail!("a migration is already running");
+            }
+
+            if generations.active.generation.model_dir == model_dir {
+                bail!("the index already uses this model");
+            }
+
+            generations.status = Status::Starting;
+        }
+
+        let started = self.prepare_target(model_dir).await;
+        let mut generations = self.generations.write().unwrap();
+
+        let target = match started {
+            Ok(target) => target,
+            Err(err) => {
+                generations.status = Status::Failed {
+                    error: err.to_string(),
+                };
+                return Err(err);
+            }
+        };
+
+        // Keeping a third collection in sync would mean embedding every chunk three times
+        if let Some(previous) = generations.previous.take() {
+            self.drop_collection(previous.generation.collection);
+        }
+
+        generations.target = Some(target.clone());
+        generations.status = Status::Migrating {
+            copied: 0,
+            total: 0,
+        };
+        generations.store()?;
+
+        info!(
+            collection = %target.generation.collection,
+            model_dir = ?target.generation.model_dir,
+            "starting embedding migration"
+        );
+
+        let generation = target.generation.clone();
+        tokio::spawn(self.clone().run_migration(target));
+
+        Ok(generation)
+    }
+
+    /// Cancel a running migration, or switch back to the collection a finished one replaced.
+    pub async fn rollback_migration(&self) -> anyhow::Result<()> {
+        let abandoned = {
+            let mut generations = self.generations.write().unwrap();
+            let abandoned = if let Some(target) = generations.target.take() {
+                generations.status = Status::Cancelled;
+                target
+            } else if let Some(previous) = generations.previous.take() {
+                generations.status = Status::RolledBack;
+                mem::replace(&mut generations.active, previous)
+            } else {
+                bail!("there is no migration to roll back");
+            };
+
+            generations.store()?;
+            abandoned
+        };
+
+        info!(
+            collection = %abandoned.generation.collection,
+            "rolled back embedding migration"
+        );
+
+        self.qdrant
+            .delete_collection(&abandoned.generation.collection)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Drop the collection kept for rollback, after a migration turned out fine.
+    pub async fn finalize_migration(&self) -> anyhow::Result<()> {
+        let previous = {
+            let mut generations = self.generations.write().unwrap();
+            let Some(previous) = generations.previous.take() else {
+                bail!("there is no previous collection to drop");
+            };
+
+            generations.status = Status::Idle;
+            generations.store()?;
+            previous
+        };
+
+        self.qdrant
+            .delete_collection(&previous.generation.collection)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Forget about every collection but the active one.
+    pub(super) async fn drop_inactive_generations(&self) -> anyhow::Result<()> {
+        let inactive = {
+            let mut generations = self.generations.write().unwrap();
+            let inactive = [generations.target.take(), generations.previous.take()];
+            generations.status = Status::Idle;
+            generations.store()?;
+            inactive
+        };
+
+        for generation in inactive.into_iter().flatten() {
+            self.qdrant
+                .delete_collection(&generation.generation.collection)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn prepare_target(&self, model_dir: PathBuf) -> anyhow::Result<LoadedGeneration> {
+        let embedder = {
+            let config = self.config.clone();
+            let model_dir = model_dir.clone();
+            tokio::task::spawn_blocking(move || super::load_embedder(&config, &model_dir)).await??
+        };
+
+        if embedder.model_id() == self.active().generation.model_id() {
+            bail!("the index already uses this model");
+        }
+
+        let dim = dimension(&*embedder).await?;
+        let generation = Generation::new(
+            &collection_for(&self.config.collection_name, chrono::Utc::now().timestamp()),
+            &model_dir,
+            embedder.model_id(),
+            dim,
+        );
+
+        create_collection(
+            &generation.collection,
+            dim,
+            &self.qdrant,
+            self.config.vector_quantization,
+        )
+        .await?;
+        create_lexical_index(&generation.collection, &self.qdrant).await?;
+        create_indexes(&generation.collection, &self.qdrant).await?;
+
+        Ok(LoadedGeneration {
+            generation,
+            embedder,
+        })
+    }
+
+    async fn run_migration(self, target: LoadedGeneration) {
+        *self.writes.touched.lock().unwrap() = Some(Touched::default());
+        let result = self.copy_points(&target).await;
+        *self.writes.touched.lock().unwrap() = None;
+
+        let mut generations = self.generations.write().unwrap();
+        if generations.target.as_ref().map(|t| &t.generation) != Some(&target.generation) {
+            // rolled back while copying
+            return;
+        }
+
+        match result {
+            Ok(copied) => {
+                info!(
+                    collection = %target.generation.collection,
+                    copied, "embedding migration complete, switching collections"
+                );
+
+                generations.target = None;
+                let replaced = mem::replace(&mut generations.active, target);
+                generations.previous = Some(replaced);
+                generations.status = Status::Completed { copied };
+            }
+            Err(err) => {
+                error!(?err, "embedding migration failed");
+
+                generations.target = None;
+                generations.status = Status::Failed {
+                    error: err.to_string(),
+                };
+                self.drop_collection(target.generation.collection);
+            }
+        }
+
+        if let Err(err) = generations.store() {
+            error!(?err, "failed to persist embedding generations");
+        }
+    }
+
+    /// Re-embed every point of the active collection into `target`, keeping IDs and payloads.
+    async fn copy_points(&self, target: &LoadedGeneration) -> anyhow::Result<u64> {
+        let source = self.active().generation.collection;
+        let total = self
+            .qdrant
+            .count(&CountPoints {
+                collection_name: source.clone(),
+                filter: None,
+                exact: Some(true),
+            })
+            .await?
+            .result
+            .map_or(0, |r| r.count);
+
+        self.set_progress(&target.generation, 0, total);
+
+        let mut copied = 0;
+        let mut offset = None;
+        loop {
+            let page = {
+                let _writes = self.writes.lock.write().await;
+                self.writes.record(|touched| *touched = Touched::default());
+
+                self.qdrant
+                    .scroll(&ScrollPoints {
+                        collection_name: source.clone(),
+                        offset: offset.take(),
+                        limit: Some(self.config.embedding_batch_size.get() as u32),
+                        with_payload: Some(true.into()),
+                        with_vectors: Some(false.into()),
+                        ..Default::default()
+                    })
+                    .await?
+            };
+
+            if !page.result.is_empty() {
+                let mut points = self.embed_points(target, page.result).await?;
+
+                let _writes = self.writes.lock.write().await;
+                let stale = {
+                    let touched = self.writes.touched.lock().unwrap();
+                    let touched = touched.as_ref().context("copy is not being tracked")?;
+                    let (stale, fresh) = points
+                        .drain(..)
+                        .partition::<Vec<_>, _>(|(payload, _)| touched.contains(payload));
+                    points = fresh;
+                    stale
+                };
+
+                // Deleted points are gone from the active collection, and updated ones have to
+                // be copied as they are now
+                if !stale.is_empty() {
+                    let ids = stale
+                        .into_iter()
+                        .filter_map(|(_, point)| point.id)
+                        .collect::<Vec<_>>();
+                    let current = self.get_points(&source, &ids).await?;
+                    points.extend(self.embed_points(target, current).await?);
+                }
+
+                let points = points
+                    .into_iter()
+                    .map(|(_, point)| point)
+                    .collect::<Vec<_>>();
+
+                copied += points.len() as u64;
+                if !points.is_empty() {
+                    self.qdrant
+                        .upsert_points(&target.generation.collection, points, None)
+                        .await?;
+                }
+
+                if !self.set_progress(&target.generation, copied, total) {
+                    bail!("migration was cancelled");
+                }
+            }
+
+            match page.next_page_offset {
+                Some(next) => offset = Some(next),
+                None => return Ok(copied),
+            }
+        }
+    }
+
+    /// Embed points of the active collection for `target`, keeping IDs and payloads.
+    async fn embed_points(
+        &self,
+        target: &LoadedGeneration,
+        points: Vec<RetrievedPoint>,
+    ) -> anyhow::Result<Vec<(Payload, PointStruct)>> {
+        if points.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let payloads = points
+            .iter()
+            .map(|point| Payload::from_scroll(point.clone()))
+            .collect::<Vec<_>>();
+
+        let inputs = payloads
+            .iter()
+            .map(|p| embedding_input(&p.repo_name, &p.relative_path, &p.text))
+            .collect::<Vec<_>>();
+
+        let embeddings = target
+            .embedder
+            .batch_embed(inputs.iter().map(String::as_str).collect())
+            .await?;
+
+        Ok(payloads
+            .into_iter()
+            .zip(points.into_iter().zip(embeddings))
+            .map(|(payload, (point, embedding))| {
+                let point = PointStruct {
+                    id: point.id,
+                    vectors: Some(embedding.into()),
+                    payload: point.payload,
+                };
+                (payload, point)
+            })
+            .collect())
+    }
+
+    async fn get_points(
+        &self,
+        collection: &str,
+        ids: &[PointId],
+    ) -> anyhow::Result<Vec<RetrievedPoint>> {
+        Ok(self
+            .qdrant
+            .get_points(
+                collection,
+                ids,
+                Some(WithVectorsSelector {
+                    selector_options: Some(with_vectors_selector::SelectorOptions::Enable(false)),
+                }),
+                Some(WithPayloadSelector {
+                    selector_options: Some(with_payload_selector::SelectorOptions::Enable(true)),
+                }),
+                None,
+            )
+            .await?
+            .result)
+    }
+
+    /// Returns `false` if `target` is no longer being migrated to.
+    fn set_progress(&self, target: &Generation, copied: u64, total: u64) -> bool {
+        let mut generations = self.generations.write().unwrap();
+        if generations.target.as_ref().map(|t| &t.generation) != Some(target) {
+            return false;
+        }
+
+        // points indexed during the copy are counted, but weren't there to begin with
+        generations.status = Status::Migrating {
+            copied,
+            total: total.max(copied),
+        };
+        true
+    }
+
+    fn drop_collection(&self, collection: String) {
+        let qdrant = self.qdrant.clone();
+        tokio::spawn(async move {
+            if let Err(err) = qdrant.delete_collection(&collection).await {
+                warn!(?err, %collection, "failed to delete abandoned collection");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_serialization() {
+        let status = serde_json::to_value(Status::Migrating {
+            copied: 10,
+            total: 40,
+        })
+        .unwrap();
+
+        assert_eq!(
+            status,
+            serde_json::json!({ "state": "migrating", "copied": 10, "total": 40 })
+        );
+        assert_eq!(
+            collection_for("documents", 1697000000),
+            "documents_1697000000"
+        );
+    }
+
+    #[test]
+    fn older_generations_are_local() {
+        let generation: Generation = serde_json::from_value(serde_json::json!({
+            "collection": "documents",
+            "model_dir": "/nonexistent/model",
+            "dim": 384,
+        }))
+        .unwrap();
+
+        assert_eq!(generation.model_id(), "local/model");
+
+        let generation = Generation::new("documents", "model".as_ref(), "ollama/nomic", 768);
+        assert_eq!(generation.model_id(), "ollama/nomic");
+    }
+
+    #[test]
+    fn writes_are_only_recorded_while_copying() {
+        let writes = Writes::default();
+        writes.record(|touched| {
+            touched.ids.insert("ignored".into());
+        });
+        assert!(writes.touched.lock().unwrap().is_none());
+
+        *writes.touched.lock().unwrap() = Some(Touched::default());
+        writes.record(|touched| {
+            touched.ids.insert("deleted".into());
+            touched
+                .files
+                .insert(("github.com/foo/bar".into(), "hash".into()));
+        });
+
+        let chunk = |id: &str, content_hash: &str| Payload {
+            id: Some(id.into()),
+            repo_ref: "github.com/foo/bar".into(),
+            content_hash: content_hash.into(),
+            ..Default::default()
+        };
+
+        let touched = writes.touched.lock().unwrap();
+        let touched = touched.as_ref().unwrap();
+        assert!(touched.contains(&chunk("deleted", "other")));
+        assert!(touched.contains(&chunk("unchanged", "hash")));
+        assert!(!touched.contains(&chunk("unchanged", "other")));
+    }
+}
@@ -5,13 +5,16 @@ use qdrant_client::{
     prelude::QdrantClient,
     qdrant::payload_index_params::IndexParams,
     qdrant::{
-        vectors_config, CollectionOperationResponse, CreateCollection, Distance, FieldType,
-        PayloadIndexParams, PointsOperationResponse, TextIndexParams, TokenizerType, VectorParams,
+        quantization_config, quantization_config_diff, vectors_config, CollectionOperationResponse,
+        CompressionRatio, CreateCollection, Disabled, Distance, FieldType, PayloadIndexParams,
+        PointsOperationResponse, ProductQuantization, QuantizationConfig, QuantizationConfigDiff,
+        QuantizationType, ScalarQuantization, TextIndexParams, TokenizerType, VectorParams,
         VectorsConfig,
     },
 };
 
-pub(super) const EMBEDDING_DIM: usize = 384;
+use crate::config::VectorQuantization;
+
 pub type Embedding = Vec<f32>;
 
 #[derive(Default, Clone, Debug, serde::Deserialize, serde::Serialize)]
@@ -34,6 +37,9 @@ pub struct Payload {
     pub embedding: Option<Embedding>,
     #[serde(skip)]
     pub score: Option<f32>,
+    /// How this was ranked by `Semantic::search`
+    #[serde(skip)]
+    pub fusion: Option<super::Fusion>,
 }
 
 impl PartialEq for Payload {
@@ -57,25 +63,79 @@ impl PartialEq for Payload {
 
 pub(super) async fn create_collection(
     name: &str,
+    dim: usize,
     qdrant: &QdrantClient,
+    quantization: VectorQuantization,
 ) -> anyhow::Result<CollectionOperationResponse> {
     qdrant
         .create_collection(&CreateCollection {
             collection_name: name.to_string(),
             vectors_config: Some(VectorsConfig {
                 config: Some(vectors_config::Config::Params(VectorParams {
-                    size: EMBEDDING_DIM as u64,
+                    size: dim as u64,
                     distance: Distance::Cosine.into(),
                     on_disk: Some(true),
                     ..Default::default()
                 })),
             }),
             on_disk_payload: Some(true),
+            quantization_config: quantization_config(quantization),
             ..Default::default()
         })
         .await
 }
 
+/// The quantized vectors are always kept in RAM. The original vectors
+/// are stored on disk, and only touched when rescoring.
+pub(super) fn quantization_config(quantization: VectorQuantization) -> Option<QuantizationConfig> {
+    let quantization = match quantization {
+        VectorQuantization::None => return None,
+        VectorQuantization::Scalar => quantization_config::Quantization::Scalar(scalar()),
+        VectorQuantization::Product => quantization_config::Quantization::Product(product()),
+    };
+
+    Some(QuantizationConfig {
+        quantization: Some(quantization),
+    })
+}
+
+/// Used to migrate existing collections to a different quantization profile.
+pub(super) fn quantization_config_diff(quantization: VectorQuantization) -> QuantizationConfigDiff {
+    let quantization = match quantization {
+        VectorQuantization::None => quantization_config_diff::Quantization::Disabled(Disabled {}),
+        VectorQuantization::Scalar => quantization_config_diff::Quantization::Scalar(scalar()),
+        VectorQuantization::Product => quantization_config_diff::Quantization::Product(product()),
+    };
+
+    QuantizationConfigDiff {
+        quantization: Some(quantization),
+    }
+}
+
+/// Determine the quantization profile an existing collection was created with.
+pub(super) fn quantization_profile(config: Option<&QuantizationConfig>) -> VectorQuantization {
+    match config.and_then(|c| c.quantization.as_ref()) {
+        Some(quantization_config::Quantization::Scalar(_)) => VectorQuantization::Scalar,
+        Some(quantization_config::Quantization::Product(_)) => VectorQuantization::Product,
+        None => VectorQuantization::None,
+    }
+}
+
+fn scalar() -> ScalarQuantization {
+    ScalarQuantization {
+        r#type: QuantizationType::Int8.into(),
+        quantile: Some(0.99),
+        always_ram: Some(true),
+    }
+}
+
+fn product() -> ProductQuantization {
+    ProductQuantization {
+        compression: CompressionRatio::X16.into(),
+        always_ram: Some(true),
+    }
+}
+
 pub(super) async fn create_lexical_index(
     name: &str,
     qdrant: &QdrantClient,
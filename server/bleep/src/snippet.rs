@@ -1,12 +1,13 @@
 use anyhow::Result;
 use regex::{Regex, RegexBuilder};
+use schemars::JsonSchema;
 use serde::Serialize;
 use smallvec::{smallvec, SmallVec};
 
 use crate::{indexes, symbol::Symbol};
 use std::ops::Range;
 
-#[derive(Serialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, JsonSchema, Debug, PartialEq, Eq)]
 pub struct SnippedFile {
     pub relative_path: String,
     pub repo_name: String,
@@ -15,7 +16,7 @@ pub struct SnippedFile {
     pub snippets: Vec<Snippet>,
 }
 
-#[derive(Serialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, JsonSchema, Debug, PartialEq, Eq)]
 pub struct Snippet {
     pub data: String,
     pub highlights: Vec<Range<usize>>,
@@ -293,7 +294,7 @@ impl Snipper {
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
 pub struct HighlightedString {
     pub text: String,
 
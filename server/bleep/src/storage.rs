@@ -0,0 +1,220 @@
+//! Disk usage of everything bloop stores, broken down by component and repository.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{remotes, Application, Configuration};
+
+const MB: u64 = 1024 * 1024;
+
+#[derive(Serialize, Debug)]
+pub(crate) struct StorageReport {
+    pub(crate) components: Components,
+    pub(crate) repos: Vec<RepoUsage>,
+    pub(crate) total_bytes: u64,
+    /// Free space on the disk holding the index directory, if it could be determined
+    pub(crate) available_bytes: Option<u64>,
+    pub(crate) warnings: Vec<Warning>,
+}
+
+/// Bytes used by each store.
+#[derive(Serialize, Default, Debug)]
+pub(crate) struct Components {
+    /// Includes the object store shared between clones
+    pub(crate) clones: u64,
+    pub(crate) tantivy: u64,
+    /// Qdrant may run on another machine, so this is estimated from the number of points, without
+    /// quantization and indexes.
+    pub(crate) qdrant: u64,
+    /// The database, including the file and chunk caches
+    pub(crate) sqlite: u64,
+    pub(crate) logs: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct RepoUsage {
+    pub(crate) repo_ref: String,
+    pub(crate) clone_bytes: u64,
+    pub(crate) vector_points: u64,
+    pub(crate) vector_bytes: u64,
+    pub(crate) cached_files: i64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct Warning {
+    /// One of `low_disk_space` or `storage_limit`
+    pub(crate) kind: &'static str,
+    pub(crate) message: String,
+}
+
+pub(crate) async fn report(app: &Application) -> Result<StorageReport> {
+    let mut repos = vec![];
+    let mut components = Components::default();
+
+    let mut pool = vec![];
+    app.repo_pool
+        .scan_async(|repo_ref, repo| pool.push((repo_ref.clone(), repo.disk_path.clone())))
+        .await;
+
+    for (repo_ref, disk_path) in pool {
+        let name = repo_ref.to_string();
+        // Local repositories are the user's own directories, which we don't count as our usage.
+        let clone_bytes = if repo_ref.is_local() {
+            0
+        } else {
+            dir_size(disk_path).await
+        };
+
+        let vector_points = app.semantic.count_points_for_repo(&name).await?;
+        let vector_bytes = vector_points * app.semantic.vector_bytes();
+
+        let cached_files = sqlx::query_scalar!(
+            r#"SELECT count(*) AS "count!: i64" FROM file_cache WHERE repo_ref = ?"#,
+            name,
+        )
+        .fetch_one(&*app.sql)
+        .await?;
+
+        components.clones += clone_bytes;
+        components.qdrant += vector_bytes;
+
+        repos.push(RepoUsage {
+            repo_ref: name,
+            clone_bytes,
+            vector_points,
+            vector_bytes,
+            cached_files,
+        });
+    }
+
+    // Objects shared between forks are only counted once, for all repositories together.
+    components.clones += dir_size(remotes::objects::store_path(&app.config)).await;
+
+    repos.sort_by_key(|r| std::cmp::Reverse(r.clone_bytes + r.vector_bytes));
+
+    for name in ["repo", "content", "doc"] {
+        components.tantivy += dir_size(app.config.index_path(name).as_ref().to_owned()).await;
+    }
+
+    for name in ["bleep.db", "bleep.db-wal", "bleep.db-shm"] {
+        components.sqlite += tokio::fs::metadata(app.config.index_dir.join(name))
+            .await
+            .map_or(0, |meta| meta.len());
+    }
+
+    components.logs = dir_size(app.config.log_dir()).await;
+
+    let total_bytes = components.clones
+        + components.tantivy
+        + components.qdrant
+        + components.sqlite
+        + components.logs;
+
+    let index_dir = app.config.index_dir.clone();
+    let available_bytes = tokio::task::spawn_blocking(move || fs4::available_space(index_dir))
+        .await?
+        .ok();
+
+    Ok(StorageReport {
+        warnings: warnings(&app.config, total_bytes, available_bytes),
+        components,
+        repos,
+        total_bytes,
+        available_bytes,
+    })
+}
+
+/// Check the usage against the thresholds in the configuration.
+pub(crate) fn warnings(
+    config: &Configuration,
+    total_bytes: u64,
+    available_bytes: Option<u64>,
+) -> Vec<Warning> {
+    let mut warnings = vec![];
+
+    if let Some(available) = available_bytes {
+        if available < config.min_free_disk_mb * MB {
+            warnings.push(Warning {
+                kind: "low_disk_space",
+                message: format!(
+                    "only {} MB of disk space left, indexing may fail",
+                    available / MB
+                ),
+            });
+        }
+    }
+
+    if let Some(max) = config.max_storage_mb {
+        if total_bytes > max * MB {
+            warnings.push(Warning {
+                kind: "storage_limit",
+                message: format!(
+                    "bloop is using {} MB of disk space, more than the limit of {max} MB",
+                    total_bytes / MB
+                ),
+            });
+        }
+    }
+
+    warnings
+}
+
+/// The total size of the files in a directory, or 0 if it can't be read.
+pub(crate) async fn dir_size(path: PathBuf) -> u64 {
+    tokio::task::spawn_blocking(move || walk(&path))
+        .await
+        .unwrap_or_default()
+}
+
+fn walk(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => walk(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn warns_about_thresholds() {
+        let mut config = Configuration::parse_from(["bleep"]);
+        config.min_free_disk_mb = 100;
+        config.max_storage_mb = Some(500);
+
+        assert!(warnings(&config, 10 * MB, Some(200 * MB)).is_empty());
+        assert!(warnings(&config, 10 * MB, None).is_empty());
+
+        let kinds = |w: Vec<Warning>| w.into_iter().map(|w| w.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds(warnings(&config, 600 * MB, Some(50 * MB))),
+            ["low_disk_space", "storage_limit"]
+        );
+
+        config.max_storage_mb = None;
+        assert!(warnings(&config, 600 * MB, Some(200 * MB)).is_empty());
+    }
+
+    #[test]
+    fn sums_nested_directories() {
+        let dir = tempdir::TempDir::new("storage").unwrap();
+        std::fs::create_dir(dir.path().join("nested")).unwrap();
+        std::fs::write(dir.path().join("a"), [0; 10]).unwrap();
+        std::fs::write(dir.path().join("nested/b"), [0; 5]).unwrap();
+
+        assert_eq!(walk(dir.path()), 15);
+        assert_eq!(walk(&dir.path().join("missing")), 0);
+    }
+}
@@ -1,8 +1,9 @@
 use crate::{intelligence::ScopeGraph, text_range::TextRange};
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct Symbol {
     pub kind: String,
     pub range: TextRange,
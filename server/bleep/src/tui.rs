@@ -0,0 +1,542 @@
+//! A dashboard for running the server in a terminal, with `bleep --tui`.
+//!
+//! Indexing is followed on the progress stream of the sync queue, and asks on the query events
+//! that are also sent to analytics. Warnings and errors are captured from the logs, which are not
+//! written to the terminal while the dashboard is up.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::{self, Write as _},
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use once_cell::sync::Lazy;
+use ratatui::{
+    backend::{Backend, CrosstermBackend},
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, Paragraph},
+    Frame, Terminal,
+};
+use serde_json::Value;
+use sysinfo::{ProcessExt, System, SystemExt};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{
+    field::{Field, Visit},
+    Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+use crate::{
+    analytics::QueryEvent,
+    background::{Progress, ProgressEvent},
+    repo::SyncStatus,
+    Application,
+};
+
+const MAX_ASKS: usize = 50;
+const MAX_LOGS: usize = 200;
+const REDRAW_EVERY: Duration = Duration::from_millis(250);
+const SAMPLE_EVERY: Duration = Duration::from_secs(1);
+
+static ASKS: Lazy<Mutex<Asks>> = Lazy::new(Default::default);
+static LOGS: Lazy<Mutex<VecDeque<LogLine>>> = Lazy::new(Default::default);
+
+type Repos = Arc<Mutex<BTreeMap<String, RepoState>>>;
+
+/// Run the server behind the dashboard, until either of them quits.
+pub async fn run(app: Application) -> Result<()> {
+    let repos = Repos::default();
+    app.repo_pool
+        .scan_async(|reporef, repo| {
+            repos.lock().unwrap().insert(
+                reporef.display_name(),
+                RepoState {
+                    status: repo.sync_status.clone(),
+                    percent: None,
+                },
+            );
+        })
+        .await;
+
+    tokio::spawn(follow_progress(app.sync_queue.subscribe(), repos.clone()));
+
+    let address = match &app.config.unix_socket {
+        Some(path) => path.display().to_string(),
+        None => {
+            let scheme = if app.config.tls_cert.is_some() {
+                "https"
+            } else {
+                "http"
+            };
+            format!("{scheme}://{}:{}", app.config.host, app.config.port)
+        }
+    };
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut dashboard = tokio::task::spawn_blocking({
+        let stop = stop.clone();
+        move || dashboard(&address, &repos, &stop)
+    });
+
+    let mut server = tokio::spawn(app.run());
+    let result = tokio::select! {
+        result = &mut server => result,
+        result = &mut dashboard => return result?,
+    };
+
+    // Give the terminal back before reporting why the server stopped
+    stop.store(true, Ordering::Relaxed);
+    dashboard.await??;
+    result?
+}
+
+/// Capture warnings and errors for the dashboard.
+pub(crate) fn log_layer<S: Subscriber>() -> impl Layer<S> {
+    Capture
+}
+
+pub(crate) fn record_query(event: &QueryEvent) {
+    ASKS.lock().unwrap().record(event, Instant::now());
+}
+
+#[derive(Default)]
+struct RepoState {
+    status: SyncStatus,
+    percent: Option<u8>,
+}
+
+async fn follow_progress(mut progress: broadcast::Receiver<Progress>, repos: Repos) {
+    loop {
+        let progress = match progress.recv().await {
+            Ok(progress) => progress,
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        };
+
+        let mut repos = repos.lock().unwrap();
+        let state = repos.entry(progress.reporef.display_name()).or_default();
+        match progress.event {
+            ProgressEvent::IndexPercent(percent) => state.percent = percent,
+            ProgressEvent::StatusChange(status) => {
+                state.status = status;
+                state.percent = None;
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Asks(VecDeque<Ask>);
+
+struct Ask {
+    query_id: uuid::Uuid,
+    repo: Option<String>,
+    query: String,
+    started: Instant,
+    outcome: Outcome,
+}
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Running,
+    Answered(Duration),
+    Failed(String),
+    Cancelled,
+}
+
+impl Asks {
+    fn record(&mut self, event: &QueryEvent, now: Instant) {
+        let data = &event.data;
+        let known = self.0.iter_mut().find(|a| a.query_id == event.query_id);
+
+        let ask = match (data.name(), known) {
+            ("query", None) => {
+                self.0.push_front(Ask {
+                    query_id: event.query_id,
+                    repo: event.repo_ref.as_ref().map(|r| r.display_name()),
+                    query: data
+                        .payload("q")
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_owned(),
+                    started: now,
+                    outcome: Outcome::Running,
+                });
+                self.0.truncate(MAX_ASKS);
+                return;
+            }
+            (_, Some(ask)) if ask.outcome == Outcome::Running => ask,
+            _ => return,
+        };
+
+        ask.outcome = match data.name() {
+            "answer_article" => Outcome::Answered(now - ask.started),
+            "error" => Outcome::Failed(
+                data.payload("message")
+                    .and_then(Value::as_str)
+                    .unwrap_or("timed out")
+                    .to_owned(),
+            ),
+            "cancelled" => Outcome::Cancelled,
+            _ => return,
+        };
+    }
+}
+
+struct LogLine {
+    at: DateTime<Utc>,
+    level: Level,
+    target: String,
+    message: String,
+}
+
+struct Capture;
+
+impl<S: Subscriber> Layer<S> for Capture {
+    fn on_event(&self, event: &tracing::Event<'_>, _: Context<'_, S>) {
+        let meta = event.metadata();
+
+        // Warnings from dependencies are rarely actionable, so only their errors are shown
+        let shown = match *meta.level() {
+            Level::ERROR => true,
+            Level::WARN => meta.target().starts_with("bleep"),
+            _ => false,
+        };
+
+        if !shown {
+            return;
+        }
+
+        let mut message = Message::default();
+        event.record(&mut message);
+
+        let mut logs = LOGS.lock().unwrap();
+        logs.push_front(LogLine {
+            at: Utc::now(),
+            level: *meta.level(),
+            target: meta.target().to_owned(),
+            message: message.message + &message.fields,
+        });
+        logs.truncate(MAX_LOGS);
+    }
+}
+
+#[derive(Default)]
+struct Message {
+    message: String,
+    fields: String,
+}
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{value:?}");
+        } else {
+            _ = write!(self.fields, " {}={value:?}", field.name());
+        }
+    }
+}
+
+struct Resources {
+    system: System,
+    pid: Option<sysinfo::Pid>,
+    sampled: Option<Instant>,
+    cpu: f32,
+    memory: u64,
+}
+
+impl Resources {
+    fn new() -> Self {
+        Self {
+            system: System::new(),
+            pid: sysinfo::get_current_pid().ok(),
+            sampled: None,
+            cpu: 0.0,
+            memory: 0,
+        }
+    }
+
+    /// CPU usage is measured between samples, so they're taken at a steady pace.
+    fn refresh(&mut self) {
+        if matches!(self.sampled, Some(at) if at.elapsed() < SAMPLE_EVERY) {
+            return;
+        }
+
+        self.sampled = Some(Instant::now());
+        self.system.refresh_memory();
+
+        let Some(pid) = self.pid else {
+            return;
+        };
+
+        if self.system.refresh_process(pid) {
+            if let Some(process) = self.system.process(pid) {
+                self.cpu = process.cpu_usage();
+                self.memory = process.memory();
+            }
+        }
+    }
+}
+
+/// Restores the terminal, even if drawing fails.
+struct Restore;
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        _ = terminal::disable_raw_mode();
+        _ = execute!(io::stdout(), LeaveAlternateScreen);
+    }
+}
+
+fn dashboard(address: &str, repos: &Repos, stop: &AtomicBool) -> Result<()> {
+    terminal::enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    let _restore = Restore;
+
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    let mut resources = Resources::new();
+    let started = Instant::now();
+
+    while !stop.load(Ordering::Relaxed) {
+        resources.refresh();
+        terminal.draw(|f| {
+            let header = header(address, started.elapsed(), &resources);
+            draw(f, header, &repos.lock().unwrap())
+        })?;
+
+        if !event::poll(REDRAW_EVERY)? {
+            continue;
+        }
+
+        // Raw mode swallows Ctrl-C, so it has to be handled like any other key
+        if let Event::Key(key) = event::read()? {
+            let interrupt =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+
+            if key.kind == KeyEventKind::Press
+                && (interrupt || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc))
+            {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn header(address: &str, uptime: Duration, resources: &Resources) -> Line<'static> {
+    let uptime = uptime.as_secs();
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+
+    Line::from(vec![
+        Span::styled("bloop ", bold),
+        Span::raw(format!("listening on {address}   ")),
+        Span::raw(format!(
+            "up {}:{:02}:{:02}   ",
+            uptime / 3600,
+            uptime / 60 % 60,
+            uptime % 60
+        )),
+        Span::raw(format!(
+            "cpu {:.0}%   memory {} MiB of {} MiB   ",
+            resources.cpu,
+            resources.memory >> 20,
+            resources.system.total_memory() >> 20
+        )),
+        Span::styled("q to quit", Style::default().fg(Color::DarkGray)),
+    ])
+}
+
+fn draw<B: Backend>(f: &mut Frame<B>, header: Line<'static>, repos: &BTreeMap<String, RepoState>) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Percentage(50),
+            Constraint::Min(5),
+        ])
+        .split(f.size());
+
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
+        .split(rows[1]);
+
+    f.render_widget(
+        Paragraph::new(header).block(Block::default().borders(Borders::ALL)),
+        rows[0],
+    );
+
+    let repos = repos
+        .iter()
+        .map(|(name, state)| {
+            let (status, color) = status_label(state);
+            ListItem::new(Line::from(vec![
+                Span::raw(format!("{name}  ")),
+                Span::styled(status, Style::default().fg(color)),
+            ]))
+        })
+        .collect::<Vec<_>>();
+
+    f.render_widget(
+        List::new(repos).block(Block::default().borders(Borders::ALL).title("Index")),
+        columns[0],
+    );
+
+    let asks = ASKS
+        .lock()
+        .unwrap()
+        .0
+        .iter()
+        .map(|ask| {
+            let (outcome, color) = match &ask.outcome {
+                Outcome::Running => (
+                    format!("{}s", ask.started.elapsed().as_secs()),
+                    Color::Yellow,
+                ),
+                Outcome::Answered(took) => (format!("{:.1}s", took.as_secs_f32()), Color::Green),
+                Outcome::Failed(message) => (format!("failed: {message}"), Color::Red),
+                Outcome::Cancelled => ("cancelled".to_owned(), Color::DarkGray),
+            };
+
+            let repo = ask.repo.as_deref().unwrap_or("-");
+            ListItem::new(Line::from(vec![
+                Span::raw(format!("{repo}  {}  ", ask.query)),
+                Span::styled(outcome, Style::default().fg(color)),
+            ]))
+        })
+        .collect::<Vec<_>>();
+
+    f.render_widget(
+        List::new(asks).block(Block::default().borders(Borders::ALL).title("Asks")),
+        columns[1],
+    );
+
+    let logs = LOGS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|log| {
+            let color = if log.level == Level::ERROR {
+                Color::Red
+            } else {
+                Color::Yellow
+            };
+
+            ListItem::new(Line::from(vec![
+                Span::raw(format!("{} ", log.at.format("%H:%M:%S"))),
+                Span::styled(format!("{:<5} ", log.level), Style::default().fg(color)),
+                Span::styled(
+                    format!("{} ", log.target),
+                    Style::default().fg(Color::DarkGray),
+                ),
+                Span::raw(log.message.clone()),
+            ]))
+        })
+        .collect::<Vec<_>>();
+
+    f.render_widget(
+        List::new(logs).block(Block::default().borders(Borders::ALL).title("Errors")),
+        rows[2],
+    );
+}
+
+fn status_label(state: &RepoState) -> (String, Color) {
+    match (&state.status, state.percent) {
+        (SyncStatus::Indexing, Some(percent)) => (format!("indexing {percent}%"), Color::Yellow),
+        (SyncStatus::Indexing | SyncStatus::Syncing | SyncStatus::Queued, _) => {
+            (label(&state.status), Color::Yellow)
+        }
+        (SyncStatus::Error { message }, _) => (format!("error: {message}"), Color::Red),
+        (SyncStatus::Done, _) => ("done".to_owned(), Color::Green),
+        (status, _) => (label(status), Color::DarkGray),
+    }
+}
+
+/// The name the API uses for a status, in words.
+fn label(status: &SyncStatus) -> String {
+    serde_json::to_value(status)
+        .ok()
+        .and_then(|v| v.as_str().map(|s| s.replace('_', " ")))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analytics::EventData;
+
+    fn event(query_id: u128, data: EventData) -> QueryEvent {
+        QueryEvent {
+            query_id: uuid::Uuid::from_u128(query_id),
+            thread_id: uuid::Uuid::nil(),
+            repo_ref: Some("github.com/bloopai/bloop".parse().unwrap()),
+            data,
+        }
+    }
+
+    #[test]
+    fn follows_asks_to_their_outcome() {
+        let mut asks = Asks::default();
+        let start = Instant::now();
+
+        let query = |id| event(id, EventData::input_stage("query").with_payload("q", "why"));
+        asks.record(&query(1), start);
+        asks.record(&query(2), start);
+        asks.record(&query(3), start);
+
+        // steps in between don't change anything
+        asks.record(&event(1, EventData::output_stage("llm_reply")), start);
+        assert_eq!(asks.0[2].outcome, Outcome::Running);
+
+        let later = start + Duration::from_secs(3);
+        asks.record(&event(1, EventData::output_stage("answer_article")), later);
+        asks.record(
+            &event(
+                2,
+                EventData::output_stage("error").with_payload("message", "no model"),
+            ),
+            later,
+        );
+        asks.record(&event(3, EventData::output_stage("error")), later);
+
+        // the agent is dropped after answering, which doesn't cancel the answer
+        asks.record(&event(1, EventData::output_stage("cancelled")), later);
+
+        let outcomes = asks.0.iter().map(|a| &a.outcome).collect::<Vec<_>>();
+        assert_eq!(
+            outcomes,
+            [
+                &Outcome::Failed("timed out".to_owned()),
+                &Outcome::Failed("no model".to_owned()),
+                &Outcome::Answered(Duration::from_secs(3)),
+            ]
+        );
+        assert_eq!(asks.0[0].query, "why");
+        assert_eq!(asks.0[0].repo.as_deref(), Some("bloopai/bloop"));
+    }
+
+    #[test]
+    fn labels_statuses_like_the_api() {
+        assert_eq!(label(&SyncStatus::RemoteRemoved), "remote removed");
+
+        let state = RepoState {
+            status: SyncStatus::Indexing,
+            percent: Some(42),
+        };
+        assert_eq!(status_label(&state).0, "indexing 42%");
+    }
+}
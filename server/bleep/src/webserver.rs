@@ -3,34 +3,49 @@ use crate::{env::Feature, Application};
 use axum::{
     extract::State,
     http::StatusCode,
-    response::IntoResponse,
-    routing::{delete, get, post},
+    response::{IntoResponse, Redirect},
+    routing::{delete, get, patch, post, put},
     Extension, Json,
 };
-use std::{borrow::Cow, fmt, net::SocketAddr};
-use tower::Service;
-use tower_http::services::{ServeDir, ServeFile};
+use std::{borrow::Cow, fmt, time::Duration};
 use tower_http::{catch_panic::CatchPanicLayer, cors::CorsLayer};
-use tracing::info;
 
 pub mod aaa;
+mod admin;
 pub mod answer;
+#[cfg(test)]
+mod api_schema;
 mod autocomplete;
+mod bitbucket;
+mod chunk;
 mod commits;
 mod config;
 mod docs;
+mod dry_run;
 mod file;
+mod frontend;
 mod github;
+mod gitlab;
 pub mod hoverable;
 mod index;
 pub mod intelligence;
+mod jobs;
+mod limits;
+mod listen;
+mod metrics;
 pub mod middleware;
 mod query;
+mod question_template;
 mod quota;
 pub mod repos;
 mod search;
 mod studio;
 mod template;
+pub(crate) mod tenant;
+mod tls;
+mod tokens;
+mod usage;
+pub mod workspace;
 
 pub type Router<S = Application> = axum::Router<S>;
 
@@ -44,16 +59,23 @@ pub(crate) mod prelude {
 }
 
 pub async fn start(app: Application) -> anyhow::Result<()> {
-    let bind = SocketAddr::new(app.config.host.parse()?, app.config.port);
-
     let mut api = Router::new()
         .route("/config", get(config::get).put(config::put))
         // querying
         .route("/q", get(query::handle))
+        .route(
+            "/q/rules",
+            get(query::get_rules)
+                .put(query::put_rules)
+                .delete(query::delete_rules),
+        )
         // autocomplete
         .route("/autocomplete", get(autocomplete::handle))
         // indexing
         .route("/index", get(index::handle))
+        .route("/jobs", get(jobs::list))
+        .route("/jobs/:id/cancel", post(jobs::cancel))
+        .route("/jobs/:id/retry", post(jobs::retry))
         // repo management
         .nest("/repos", repos::router())
         // docs management
@@ -84,8 +106,11 @@ pub async fn start(app: Application) -> anyhow::Result<()> {
         // misc
         .route("/search/code", get(search::semantic_code))
         .route("/search/path", get(search::fuzzy_path))
+        .route("/search/structural", post(search::structural))
         .route("/file", get(file::handle))
+        .route("/chunks/:id", get(chunk::provenance))
         .route("/answer", get(answer::answer))
+        .route("/answer/ws", get(answer::socket::answer))
         .route("/answer/explain", get(answer::explain))
         .route(
             "/answer/conversations",
@@ -93,9 +118,115 @@ pub async fn start(app: Application) -> anyhow::Result<()> {
         )
         .route(
             "/answer/conversations/:thread_id",
-            get(answer::conversations::thread),
+            get(answer::conversations::thread).patch(answer::conversations::patch),
+        )
+        .route(
+            "/answer/conversations/:thread_id/exchanges",
+            get(answer::conversations::exchanges),
+        )
+        .route(
+            "/answer/conversations/:thread_id/draft",
+            get(answer::drafts::get).patch(answer::drafts::patch),
+        )
+        .route(
+            "/answer/conversations/import",
+            post(answer::conversations::document::import),
+        )
+        .route(
+            "/answer/conversations/:thread_id/export",
+            get(answer::conversations::document::export),
+        )
+        .route(
+            "/answer/conversations/:thread_id/live",
+            get(answer::live::follow),
+        )
+        .route(
+            "/answer/conversations/:thread_id/restore",
+            post(answer::conversations::restore),
+        )
+        .route(
+            "/answer/conversations/:thread_id/fork",
+            post(answer::conversations::fork),
+        )
+        .route(
+            "/answer/conversations/:thread_id/related",
+            get(answer::conversations::related),
+        )
+        .route(
+            "/answer/conversations/:thread_id/delta",
+            get(answer::conversations::delta),
+        )
+        .route(
+            "/answer/conversations/:thread_id/exchanges/:exchange_id",
+            get(answer::conversations::exchange),
+        )
+        .route(
+            "/answer/conversations/:thread_id/exchanges/:exchange_id/export",
+            post(answer::export::export),
+        )
+        .route(
+            "/answer/conversations/:thread_id/exchanges/:exchange_id/retry",
+            post(answer::retry),
+        )
+        .route(
+            "/answer/conversations/:thread_id/title/regenerate",
+            post(answer::conversations::regenerate_title),
+        )
+        .route(
+            "/answer/conversations/:thread_id/scratchpads",
+            get(answer::scratchpads::list),
+        )
+        .route(
+            "/answer/conversations/:thread_id/scratchpads/:name",
+            put(answer::scratchpads::put).delete(answer::scratchpads::delete),
+        )
+        .route(
+            "/answer/conversations/:thread_id/share",
+            post(answer::shares::create),
+        )
+        .route(
+            "/answer/conversations/:thread_id/shares",
+            get(answer::shares::list),
+        )
+        .route(
+            "/answer/conversations/:thread_id/shares/:share_id",
+            delete(answer::shares::revoke),
+        )
+        .route(
+            "/answer/conversations/:thread_id/usage",
+            get(usage::conversation),
+        )
+        .route(
+            "/answer/conversations/:thread_id/plan",
+            post(answer::conversations::review_plan),
         )
+        .route("/answer/plan/execute", get(answer::execute_plan))
         .route("/answer/vote", post(answer::vote))
+        .route("/reviews", get(answer::reviews::list))
+        .route("/reviews/claim", post(answer::reviews::claim))
+        .route("/reviews/summary", get(answer::reviews::summary))
+        .route("/reviews/:id/grade", post(answer::reviews::grade))
+        .route("/reviews/:id/comment", put(answer::reviews::comment))
+        .route(
+            "/answer/settings",
+            get(answer::settings::get)
+                .put(answer::settings::put)
+                .delete(answer::settings::delete),
+        )
+        .route(
+            "/answer/preferences",
+            get(answer::preferences::get)
+                .put(answer::preferences::put)
+                .delete(answer::preferences::delete),
+        )
+        .route(
+            "/answer/experiments",
+            get(answer::experiments::list).post(answer::experiments::create),
+        )
+        .route(
+            "/answer/experiments/:id/stop",
+            post(answer::experiments::stop),
+        )
         .route("/studio", post(studio::create))
         .route("/studio", get(studio::list))
         .route(
@@ -127,6 +258,160 @@ pub async fn start(app: Application) -> anyhow::Result<()> {
                 .patch(template::patch)
                 .delete(template::delete),
         )
+        .route(
+            "/question-templates",
+            get(question_template::list).post(question_template::create),
+        )
+        .route("/question-templates/:id", delete(question_template::delete))
+        .route(
+            "/question-templates/:id/instantiate",
+            post(question_template::instantiate),
+        )
+        .route("/workspace", post(workspace::create))
+        .route("/workspace", get(workspace::list))
+        .route("/workspace/suggestions", get(workspace::suggestions))
+        .route("/workspace/invites", get(workspace::invites))
+        .route(
+            "/workspace/:id/invite",
+            post(workspace::accept_invite).delete(workspace::decline_invite),
+        )
+        .route(
+            "/workspace/:id",
+            get(workspace::get)
+                .patch(workspace::patch)
+                .delete(workspace::delete),
+        )
+        .route(
+            "/workspace/:id/members/:user_id",
+            put(workspace::put_member).delete(workspace::delete_member),
+        )
+        .route(
+            "/workspace/:id/repos",
+            post(workspace::add_repo)
+                .patch(workspace::repo_paths::patch)
+                .delete(workspace::remove_repo),
+        )
+        .route(
+            "/workspace/:id/repo-filters",
+            get(workspace::repo_filters::list).post(workspace::repo_filters::create),
+        )
+        .route(
+            "/workspace/:id/repo-filters/:filter_id",
+            delete(workspace::repo_filters::delete),
+        )
+        .route(
+            "/workspace/:id/repo-filters/evaluate",
+            post(workspace::repo_filters::evaluate),
+        )
+        .route(
+            "/workspace/:id/conversations",
+            get(workspace::conversations),
+        )
+        .route(
+            "/workspace/:id/conversations/stats",
+            get(workspace::conversation_stats),
+        )
+        .route(
+            "/workspace/:id/conversations/:thread_id",
+            get(workspace::conversation),
+        )
+        .route(
+            "/workspace/:id/conversations/:thread_id/share",
+            put(workspace::share_conversation).delete(workspace::unshare_conversation),
+        )
+        .route(
+            "/workspace/:id/conversations/:thread_id/annotations",
+            get(workspace::annotations::list).post(workspace::annotations::create),
+        )
+        .route(
+            "/workspace/:id/conversations/:thread_id/annotations/:annotation_id",
+            patch(workspace::annotations::patch).delete(workspace::annotations::delete),
+        )
+        .route("/workspace/:id/tool-denials", get(workspace::tool_denials))
+        .route(
+            "/workspace/:id/answer/estimate",
+            post(workspace::estimate::estimate),
+        )
+        .route(
+            "/workspace/:id/integrations",
+            get(workspace::integrations::list).put(workspace::integrations::put),
+        )
+        .route(
+            "/workspace/:id/integrations/:kind",
+            delete(workspace::integrations::delete),
+        )
+        .route(
+            "/workspace/:id/integrations/:kind/tickets",
+            post(workspace::integrations::create_ticket),
+        )
+        .route(
+            "/workspace/:id/notifications",
+            get(workspace::notifications),
+        )
+        .route(
+            "/workspace/:id/keys",
+            get(workspace::keys::list).post(workspace::keys::create),
+        )
+        .route(
+            "/workspace/:id/keys/:key_id",
+            delete(workspace::keys::revoke),
+        )
+        .route("/admin/storage", get(admin::storage))
+        .route("/admin/storage/vacuum", post(admin::vacuum))
+        .route("/admin/notifications", get(admin::notifications))
+        .route("/admin/workspaces/trash", get(admin::workspace_trash))
+        .route(
+            "/admin/workspaces/:id/restore",
+            post(admin::restore_workspace),
+        )
+        .route("/admin/audit-log", get(admin::audit_log))
+        .route(
+            "/admin/answer-footer",
+            get(answer::footer::get)
+                .put(answer::footer::put)
+                .delete(answer::footer::delete),
+        )
+        .route("/admin/conversation-views", get(admin::conversation_views))
+        .route("/admin/usage", get(usage::by_user))
+        .route("/admin/embeddings", get(admin::embeddings))
+        .route("/admin/embeddings/migrate", post(admin::migrate_embeddings))
+        .route(
+            "/admin/embeddings/rollback",
+            post(admin::rollback_embeddings),
+        )
+        .route(
+            "/admin/embeddings/finalize",
+            post(admin::finalize_embeddings),
+        )
+        .route("/admin/reindex", get(admin::reindexes).post(admin::reindex))
+        .route("/admin/reindex/:id", get(admin::reindex_progress))
+        .route("/admin/reindex/:id/cancel", post(admin::cancel_reindex))
+        .route("/admin/tenants", get(tenant::list).post(tenant::create))
+        .route("/admin/tenants/:id", delete(tenant::delete))
+        .route(
+            "/admin/tenants/:id/members/:user_id",
+            put(tenant::put_member).delete(tenant::delete_member),
+        )
+        .route(
+            "/admin/tenants/:id/repos",
+            post(tenant::add_repo).delete(tenant::remove_repo),
+        )
+        .route("/tenant", get(tenant::get_own))
+        .route(
+            "/tenant/members/:user_id",
+            put(tenant::put_own_member).delete(tenant::delete_own_member),
+        )
+        .route("/auth/gitlab/login", get(gitlab::login))
+        .route("/auth/gitlab/complete", get(gitlab::complete))
+        .route("/auth/gitlab/token", put(gitlab::put_token))
+        .route("/auth/gitlab", delete(gitlab::logout))
+        .route(
+            "/auth/bitbucket",
+            put(bitbucket::put).delete(bitbucket::delete),
+        )
+        .route("/tokens", get(tokens::list).post(tokens::create))
+        .route("/tokens/:id", delete(tokens::revoke))
+        .route("/tokens/:id/usage", get(tokens::usage))
         .route("/quota", get(quota::get))
         .route(
             "/quota/create-checkout-session",
@@ -145,6 +430,19 @@ pub async fn start(app: Application) -> anyhow::Result<()> {
 
     api = api.route("/panic", get(|| async { panic!("dead") }));
 
+    api = dry_run::guard(api);
+
+    // These check the user, so they have to run after the middlewares below.
+    api = admin::guard(api, app.clone());
+    api = tenant::isolate(api, app.clone());
+
+    // Limits are per user too.
+    api = limits::rate_limit(api, app.clone());
+
+    // Requests made with a personal access token act as the user who created it, and skip the
+    // middlewares below.
+    let token_api = middleware::sentry_layer(api.clone());
+
     // Note: all routes above this point must be authenticated.
     // These middlewares MUST provide the `middleware::User` extension.
     if app.env.allow(Feature::AuthorizationRequired) {
@@ -153,7 +451,20 @@ pub async fn start(app: Application) -> anyhow::Result<()> {
         api = middleware::local_user(middleware::sentry_layer(api), app.clone());
     }
 
-    api = api.route("/health", get(health));
+    api = tokens::authenticate(api, token_api, app.clone());
+
+    api = api
+        .route("/health", get(health))
+        // shared conversations are read by anyone with the link
+        .route("/shared/:token", get(answer::shares::view))
+        // remotes can't sign in, and are checked against their webhook secrets instead
+        .route("/webhooks/gitlab", post(repos::webhooks::gitlab))
+        .route("/repos/webhooks/github", post(repos::webhooks::github))
+        .route("/repos/sync-hook", post(repos::webhooks::sync_hook));
+
+    if app.config.metrics {
+        api = metrics::track(api);
+    }
 
     let api = api
         .layer(Extension(app.indexes.clone()))
@@ -163,28 +474,43 @@ pub async fn start(app: Application) -> anyhow::Result<()> {
         .layer(CorsLayer::permissive())
         .layer(CatchPanicLayer::new());
 
-    let mut router = Router::new().nest("/api", api);
-
-    if let Some(frontend_dist) = app.config.frontend_dist.clone() {
-        router = router.nest_service(
-            "/",
-            tower::service_fn(move |req| {
-                let frontend_dist = frontend_dist.clone();
-                async move {
-                    Ok(ServeDir::new(&frontend_dist)
-                        .fallback(ServeFile::new(frontend_dist.join("index.html")))
-                        .call(req)
-                        .await
-                        .unwrap())
-                }
-            }),
-        );
+    let base_path = app.config.base_path();
+    let mut router = Router::new().nest(&format!("{base_path}/api"), api);
+
+    if app.config.metrics {
+        router = router.route(&format!("{base_path}/metrics"), get(metrics::render));
+    }
+
+    if let Some(frontend) = frontend::router(&app.config)? {
+        if base_path.is_empty() {
+            router = router.nest_service("/", frontend);
+        } else {
+            let home = format!("{base_path}/");
+            router = router
+                .nest_service(&base_path, frontend)
+                .route("/", get(|| async move { Redirect::temporary(&home) }));
+        }
     }
 
-    info!(%bind, "starting webserver");
-    axum::Server::bind(&bind)
-        .serve(router.into_make_service())
-        .await?;
+    match listen::Listener::bind(&app.config)? {
+        // Rate limits are per IP address as well
+        listen::Listener::Tcp(listener) => {
+            axum::Server::from_tcp(listener)?
+                .serve(router.into_make_service_with_connect_info::<listen::PeerAddr>())
+                .await?
+        }
+        listen::Listener::Tls(listener) => {
+            axum::Server::builder(listener)
+                .serve(router.into_make_service_with_connect_info::<listen::PeerAddr>())
+                .await?
+        }
+        #[cfg(unix)]
+        listen::Listener::Unix(listener) => {
+            axum::Server::builder(listener)
+                .serve(router.into_make_service_with_connect_info::<listen::PeerAddr>())
+                .await?
+        }
+    }
 
     Ok(())
 }
@@ -202,6 +528,8 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 pub struct Error {
     status: StatusCode,
     body: EndpointError<'static>,
+    /// Sent as `Retry-After`, in seconds
+    retry_after: Option<u64>,
 }
 
 impl fmt::Display for Error {
@@ -227,7 +555,11 @@ impl Error {
             message: message.into(),
         };
 
-        Error { status, body }
+        Error {
+            status,
+            body,
+            retry_after: None,
+        }
     }
 
     fn with_status(mut self, status_code: StatusCode) -> Self {
@@ -235,6 +567,13 @@ impl Error {
         self
     }
 
+    /// Ask the client to wait before trying again, for `429 Too Many Requests`.
+    fn with_retry_after(mut self, wait: Duration) -> Self {
+        // Round up, so that retrying right after the wait succeeds
+        self.retry_after = Some(wait.as_secs() + u64::from(wait.subsec_nanos() > 0));
+        self
+    }
+
     fn internal<S: std::fmt::Display>(message: S) -> Self {
         Error {
             status: StatusCode::INTERNAL_SERVER_ERROR,
@@ -242,16 +581,18 @@ impl Error {
                 kind: ErrorKind::Internal,
                 message: message.to_string().into(),
             },
+            retry_after: None,
         }
     }
 
-    fn user<S: std::fmt::Display>(message: S) -> Self {
+    pub(crate) fn user<S: std::fmt::Display>(message: S) -> Self {
         Error {
             status: StatusCode::BAD_REQUEST,
             body: EndpointError {
                 kind: ErrorKind::User,
                 message: message.to_string().into(),
             },
+            retry_after: None,
         }
     }
 
@@ -262,6 +603,7 @@ impl Error {
                 kind: ErrorKind::NotFound,
                 message: message.to_string().into(),
             },
+            retry_after: None,
         }
     }
 
@@ -272,6 +614,7 @@ impl Error {
                 kind: ErrorKind::User,
                 message: message.to_string().into(),
             },
+            retry_after: None,
         }
     }
 
@@ -294,12 +637,20 @@ impl From<sqlx::Error> for Error {
 
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
-        (self.status, Json(Response::from(self.body))).into_response()
+        let mut response = (self.status, Json(Response::from(self.body))).into_response();
+
+        if let Some(secs) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(axum::http::header::RETRY_AFTER, secs.into());
+        }
+
+        response
     }
 }
 
 /// The response upon encountering an error
-#[derive(serde::Serialize, PartialEq, Eq, Debug)]
+#[derive(serde::Serialize, schemars::JsonSchema, PartialEq, Eq, Debug)]
 pub struct EndpointError<'a> {
     /// The kind of this error
     kind: ErrorKind,
@@ -310,7 +661,7 @@ pub struct EndpointError<'a> {
 
 /// The kind of an error
 #[allow(unused)]
-#[derive(serde::Serialize, PartialEq, Eq, Debug)]
+#[derive(serde::Serialize, schemars::JsonSchema, PartialEq, Eq, Debug)]
 #[serde(rename_all = "snake_case")]
 #[non_exhaustive]
 pub enum ErrorKind {
@@ -0,0 +1,383 @@
+//! Endpoints about the instance as a whole, rather than a single user or workspace.
+
+use std::path::PathBuf;
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{Request, StatusCode},
+    middleware::{from_fn_with_state, Next},
+    response::Response,
+    Extension, Json,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use super::{middleware::User, workspace, Error, ErrorKind, Result, Router};
+use crate::{background::reindex, semantic::migration, storage, Application};
+
+/// The most syncs a reindex campaign can run at once.
+const MAX_REINDEX_PARALLELISM: usize = 16;
+
+/// Only let instance admins use the `/admin` endpoints, whether tenants are isolated or not.
+///
+/// The handlers in this module don't check for admins themselves.
+pub(super) fn guard(router: Router, app: Application) -> Router {
+    router.layer(from_fn_with_state(app, guard_mw))
+}
+
+async fn guard_mw(
+    State(app): State<Application>,
+    Extension(user): Extension<User>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Result<Response> {
+    if request.uri().path().starts_with("/admin") && !is_admin(&app, &user) {
+        return Err(
+            Error::user("only instance admins can do this").with_status(StatusCode::FORBIDDEN)
+        );
+    }
+
+    Ok(next.run(request).await)
+}
+
+fn is_admin(app: &Application, user: &User) -> bool {
+    user.username().map_or(false, |user_id| {
+        app.config
+            .instance_admins
+            .iter()
+            .any(|admin| admin == user_id)
+    })
+}
+
+/// Disk usage of every store, per component and per repository.
+pub(super) async fn storage(
+    State(app): State<Application>,
+) -> Result<Json<storage::StorageReport>> {
+    storage::report(&app)
+        .await
+        .map(Json)
+        .map_err(Error::internal)
+}
+
+/// Rebuild the database file, to give back the disk space freed by deletions and compression.
+///
+/// This locks the database until it's done, so it's only run when an admin asks for it.
+pub(super) async fn vacuum(State(app): State<Application>) -> Result<Json<storage::StorageReport>> {
+    sqlx::query("VACUUM").execute(&*app.sql).await?;
+    storage(State(app)).await
+}
+
+#[derive(Serialize)]
+pub(super) struct Notification {
+    id: i64,
+    kind: String,
+    message: String,
+    created_at: NaiveDateTime,
+    resolved_at: Option<NaiveDateTime>,
+}
+
+/// Alerts that concern the whole instance, such as low disk space, newest first.
+pub(super) async fn notifications(
+    State(app): State<Application>,
+) -> Result<Json<Vec<Notification>>> {
+    let notifications = sqlx::query_as!(
+        Notification,
+        "SELECT id, kind, message, created_at, resolved_at
+        FROM notifications
+        WHERE workspace_id IS NULL
+        ORDER BY id DESC
+        LIMIT 100",
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    Ok(Json(notifications))
+}
+
+/// The embedding model behind the semantic index, and the progress of any migration.
+pub(super) async fn embeddings(State(app): State<Application>) -> Json<migration::Report> {
+    Json(app.semantic.migration_report())
+}
+
+#[derive(Deserialize)]
+pub(super) struct Migrate {
+    model_dir: PathBuf,
+}
+
+/// Re-embed the semantic index with another model, switching over once it's done.
+pub(super) async fn migrate_embeddings(
+    State(app): State<Application>,
+    Json(params): Json<Migrate>,
+) -> Result<Json<migration::Report>> {
+    app.semantic
+        .start_migration(params.model_dir)
+        .await
+        .map_err(Error::user)?;
+
+    Ok(Json(app.semantic.migration_report()))
+}
+
+/// Cancel a running migration, or go back to the model used before the last one.
+pub(super) async fn rollback_embeddings(
+    State(app): State<Application>,
+) -> Result<Json<migration::Report>> {
+    app.semantic
+        .rollback_migration()
+        .await
+        .map_err(Error::user)?;
+
+    Ok(Json(app.semantic.migration_report()))
+}
+
+/// Drop the collection kept around for rolling back the last migration.
+pub(super) async fn finalize_embeddings(
+    State(app): State<Application>,
+) -> Result<Json<migration::Report>> {
+    app.semantic
+        .finalize_migration()
+        .await
+        .map_err(Error::user)?;
+
+    Ok(Json(app.semantic.migration_report()))
+}
+
+#[derive(Deserialize)]
+pub(super) struct Reindex {
+    #[serde(default = "default_reindex_filter")]
+    filter: reindex::Filter,
+    /// How many repositories are synced at once
+    #[serde(default = "default_parallelism")]
+    parallelism: usize,
+}
+
+fn default_reindex_filter() -> reindex::Filter {
+    reindex::Filter::All
+}
+
+fn default_parallelism() -> usize {
+    2
+}
+
+/// Rebuild the index of every repository that matches a filter, a few at a time.
+pub(super) async fn reindex(
+    State(app): State<Application>,
+    Extension(user): Extension<User>,
+    Json(params): Json<Reindex>,
+) -> Result<(StatusCode, Json<reindex::Progress>)> {
+    if !(1..=MAX_REINDEX_PARALLELISM).contains(&params.parallelism) {
+        return Err(Error::user(format!(
+            "`parallelism` must be between 1 and {MAX_REINDEX_PARALLELISM}"
+        )));
+    }
+
+    let id = reindex::start(&app, params.filter, params.parallelism, user.username()).await?;
+    let progress = reindex::get(&app, id)
+        .await?
+        .ok_or_else(|| Error::internal("campaign was not recorded"))?;
+
+    Ok((StatusCode::ACCEPTED, Json(progress)))
+}
+
+/// The latest reindex campaigns, with their progress.
+pub(super) async fn reindexes(
+    State(app): State<Application>,
+) -> Result<Json<Vec<reindex::Progress>>> {
+    Ok(Json(reindex::list(&app).await?))
+}
+
+/// The progress of a reindex campaign, and an estimate of when it will be done.
+pub(super) async fn reindex_progress(
+    State(app): State<Application>,
+    Path(id): Path<i64>,
+) -> Result<Json<reindex::Progress>> {
+    reindex::get(&app, id)
+        .await?
+        .map(Json)
+        .ok_or_else(|| Error::not_found("campaign was not found"))
+}
+
+/// Stop a reindex campaign, cancelling the syncs it queued.
+pub(super) async fn cancel_reindex(
+    State(app): State<Application>,
+    Path(id): Path<i64>,
+) -> Result<Json<reindex::Progress>> {
+    let cancelled = reindex::cancel(&app, id).await?;
+    let progress = reindex::get(&app, id)
+        .await?
+        .ok_or_else(|| Error::not_found("campaign was not found"))?;
+
+    if !cancelled {
+        return Err(
+            Error::user("the campaign has already finished").with_status(StatusCode::CONFLICT)
+        );
+    }
+
+    Ok(Json(progress))
+}
+
+#[derive(Serialize)]
+pub(super) struct TrashedWorkspace {
+    id: i64,
+    name: String,
+    created_at: NaiveDateTime,
+    deleted_at: NaiveDateTime,
+    deleted_by: Option<String>,
+    /// When the workspace will be purged, after which it can't be restored
+    purge_at: NaiveDateTime,
+}
+
+/// Deleted workspaces that can still be restored, most recently deleted first.
+pub(super) async fn workspace_trash(
+    State(app): State<Application>,
+) -> Result<Json<Vec<TrashedWorkspace>>> {
+    let trash_days = app.config.workspace_trash_days as i64;
+    let workspaces = sqlx::query_as!(
+        TrashedWorkspace,
+        r#"SELECT id, name, created_at, deleted_at AS "deleted_at!: NaiveDateTime", deleted_by,
+            datetime(deleted_at, printf('+%d days', ?)) AS "purge_at!: NaiveDateTime"
+        FROM workspaces
+        WHERE deleted_at IS NOT NULL
+        ORDER BY deleted_at DESC"#,
+        trash_days,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    Ok(Json(workspaces))
+}
+
+/// Take a workspace out of the trash, with its members, repositories and settings.
+pub(super) async fn restore_workspace(
+    State(app): State<Application>,
+    Extension(user): Extension<User>,
+    Path(id): Path<i64>,
+) -> Result<()> {
+    let mut transaction = app.sql.begin().await?;
+
+    let restored = sqlx::query!(
+        "UPDATE workspaces SET deleted_at = NULL, deleted_by = NULL
+        WHERE id = ? AND deleted_at IS NOT NULL",
+        id,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+
+    if restored == 0 {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            "no deleted workspace with this ID",
+        ));
+    }
+
+    workspace::audit(
+        &mut transaction,
+        id,
+        user.username(),
+        "workspace_restored",
+        None,
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub(super) struct AuditLogParams {
+    workspace_id: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub(super) struct AuditEntry {
+    id: i64,
+    workspace_id: Option<i64>,
+    user_id: Option<String>,
+    action: String,
+    detail: Option<String>,
+    created_at: NaiveDateTime,
+}
+
+/// Changes to workspaces, such as deletions and restores, newest first.
+pub(super) async fn audit_log(
+    State(app): State<Application>,
+    Query(params): Query<AuditLogParams>,
+) -> Result<Json<Vec<AuditEntry>>> {
+    let entries = sqlx::query_as!(
+        AuditEntry,
+        "SELECT id, workspace_id, user_id, action, detail, created_at
+        FROM audit_log
+        WHERE ?1 IS NULL OR workspace_id = ?1
+        ORDER BY id DESC
+        LIMIT 500",
+        params.workspace_id,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    Ok(Json(entries))
+}
+
+#[derive(Deserialize)]
+pub(super) struct ConversationViewParams {
+    /// Only views at or after this time
+    from: Option<NaiveDateTime>,
+    /// Only views before this time
+    to: Option<NaiveDateTime>,
+    workspace_id: Option<i64>,
+    /// Only views of this conversation
+    thread_id: Option<uuid::Uuid>,
+    viewer_id: Option<String>,
+}
+
+#[derive(Serialize)]
+pub(super) struct ConversationView {
+    /// The workspace the conversation was read in, if any
+    workspace_id: Option<i64>,
+    /// The owner of the conversation
+    user_id: String,
+    thread_id: String,
+    /// The title of the conversation, unless it has since been deleted
+    title: Option<String>,
+    /// Who read the conversation, unless it was opened with a shared link
+    viewer_id: Option<String>,
+    /// The shared link the conversation was opened with
+    share_id: Option<i64>,
+    /// One of `workspace`, `link`, `export` or `api`
+    source: String,
+    exchanges_seen: i64,
+    viewed_at: NaiveDateTime,
+}
+
+/// Who read which conversation, where, and when, newest first.
+pub(super) async fn conversation_views(
+    State(app): State<Application>,
+    Query(params): Query<ConversationViewParams>,
+) -> Result<Json<Vec<ConversationView>>> {
+    let thread_id = params.thread_id.map(|id| id.to_string());
+    let views = sqlx::query_as!(
+        ConversationView,
+        r#"SELECT v.workspace_id, v.user_id, v.thread_id, c.title AS "title?",
+            v.viewer_id, v.share_id, v.source, v.exchanges_seen, v.viewed_at
+        FROM conversation_views v
+        LEFT JOIN conversations c ON c.user_id = v.user_id AND c.thread_id = v.thread_id
+        WHERE (?1 IS NULL OR v.viewed_at >= ?1)
+            AND (?2 IS NULL OR v.viewed_at < ?2)
+            AND (?3 IS NULL OR v.workspace_id = ?3)
+            AND (?4 IS NULL OR v.thread_id = ?4)
+            AND (?5 IS NULL OR v.viewer_id = ?5)
+        ORDER BY v.id DESC
+        LIMIT 1000"#,
+        params.from,
+        params.to,
+        params.workspace_id,
+        thread_id,
+        params.viewer_id,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    Ok(Json(views))
+}
@@ -1,39 +1,95 @@
-use std::{panic::AssertUnwindSafe, time::Duration};
+use std::{panic::AssertUnwindSafe, pin::Pin, time::Duration};
 
 use anyhow::{anyhow, Context, Result};
 use axum::{
-    extract::Query,
+    extract::{Path, Query},
     response::{
         sse::{self, Sse},
         IntoResponse,
     },
     Extension, Json,
 };
-use futures::{future::Either, stream, StreamExt};
+use futures::{future::Either, stream, Stream, StreamExt};
 use reqwest::StatusCode;
 use serde_json::json;
 use tracing::{debug, error, info, warn};
 
 use self::conversations::ConversationId;
 
-use super::middleware::User;
+use super::{
+    limits::{self, Usage},
+    middleware::User,
+    workspace,
+};
 use crate::{
     agent::{
-        self,
-        exchange::{CodeChunk, Exchange, FocusedChunk},
-        Action, Agent, ExchangeState,
+        self, attachment,
+        deadline::Deadline,
+        exchange::{self, CodeChunk, Exchange, FocusedChunk, IndexFreshness, PlanStatus},
+        experiment, Action, Agent, ExchangeState,
     },
     analytics::{EventData, QueryEvent},
     db::QueryLog,
+    fetch::Fetcher,
     query::parser::{self, Literal},
-    repo::RepoRef,
+    repo::{self, RepoRef, SyncStatus},
     Application,
 };
 
 pub mod conversations;
+pub mod drafts;
+pub mod experiments;
+pub mod export;
+pub mod footer;
+pub(crate) mod live;
+pub mod preferences;
+pub mod reviews;
+pub mod scratchpads;
+pub mod settings;
+pub mod shares;
+pub mod socket;
 
 const TIMEOUT_SECS: u64 = 60;
 
+/// How long to wait for a re-index when a question requires a fresh index.
+const FRESH_INDEX_TIMEOUT_SECS: u64 = 30;
+
+/// The default time budget of a whole answer, and the most a client can ask for.
+const DEFAULT_DEADLINE_SECS: u64 = 300;
+const MAX_DEADLINE_SECS: u64 = 600;
+
+/// The frames of an answer, whichever transport streams them.
+type AnswerStream = Pin<Box<dyn Stream<Item = Result<Frame>> + Send>>;
+
+/// A frame of an answer stream, sent as the data of an event over SSE, or as a text message
+/// over a [socket](socket).
+enum Frame {
+    Json(serde_json::Value),
+    /// The end of the answer
+    Done,
+}
+
+impl Frame {
+    fn json(value: impl serde::Serialize) -> Result<Self> {
+        Ok(Self::Json(serde_json::to_value(value)?))
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Self::Json(value) => value.to_string(),
+            Self::Done => "[DONE]".to_owned(),
+        }
+    }
+}
+
+fn into_sse(stream: AnswerStream) -> Sse<impl Stream<Item = Result<sse::Event>>> {
+    let events = stream.map(|frame| frame.map(|frame| sse::Event::default().data(frame.text())));
+
+    // Keep-alive events make sure that a disconnected client is noticed while the agent is still
+    // working, dropping the stream and cancelling the work in flight.
+    Sse::new(events).keep_alive(sse::KeepAlive::default())
+}
+
 #[derive(Clone, Debug, serde::Deserialize)]
 pub struct Vote {
     pub feedback: VoteFeedback,
@@ -54,6 +110,11 @@ pub(super) async fn vote(
     Extension(user): Extension<User>,
     Json(params): Json<Vote>,
 ) {
+    let positive = matches!(params.feedback, VoteFeedback::Positive);
+    if let Err(err) = experiments::record_vote(&app.sql, params.query_id, positive).await {
+        warn!(?err, "failed to record vote for experiments");
+    }
+
     app.track_query(
         &user,
         &QueryEvent {
@@ -78,25 +139,74 @@ pub struct Answer {
     /// Optional id of the parent of the exchange to overwrite
     /// If this UUID is nil, then overwrite the first exchange in the thread
     pub parent_exchange_id: Option<uuid::Uuid>,
+    /// Sync the repository with its upstream before answering
+    #[serde(default)]
+    pub require_fresh_index: bool,
+    /// Whitespace-separated URLs of external documents to use as context
+    #[serde(default, deserialize_with = "deserialize_urls")]
+    pub urls: Vec<url::Url>,
+    /// Draft a plan for the query, and wait for it to be approved before executing it
+    #[serde(default)]
+    pub plan: bool,
+    /// Give up on the answer after this many seconds, at most `MAX_DEADLINE_SECS`
+    #[serde(default = "default_deadline_secs")]
+    pub deadline_secs: u64,
+    /// Answer from this branch, unless the query names one with `branch:`, or it isn't indexed
+    pub branch: Option<String>,
+    /// Before starting a new conversation, look for an answered question that this one repeats.
+    ///
+    /// If there is one, the stream only has a `Duplicate` event pointing to it, and nothing is
+    /// answered. Ask again without this to answer anyway.
+    #[serde(default)]
+    pub check_duplicates: bool,
+    /// Search every repository of the workspace `repo_ref` belongs to, not only `repo_ref`
+    #[serde(default)]
+    pub workspace: bool,
+    /// The exchanges after the one being answered, which are kept as they are
+    ///
+    /// This is only set when retrying an exchange, see `retry`.
+    #[serde(skip)]
+    pub later_exchanges: Vec<Exchange>,
+}
+
+fn deserialize_urls<'de, D>(deserializer: D) -> Result<Vec<url::Url>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let urls = <String as serde::Deserialize>::deserialize(deserializer)?;
+    attachment::parse_urls(&urls).map_err(serde::de::Error::custom)
 }
 
 fn default_thread_id() -> uuid::Uuid {
     uuid::Uuid::new_v4()
 }
 
-fn default_answer_model() -> agent::model::LLMModel {
+pub(crate) fn default_answer_model() -> agent::model::LLMModel {
     agent::model::GPT_4_TURBO_24K
 }
 
-fn default_agent_model() -> agent::model::LLMModel {
+pub(crate) fn default_agent_model() -> agent::model::LLMModel {
     agent::model::GPT_4
 }
 
+fn default_deadline_secs() -> u64 {
+    DEFAULT_DEADLINE_SECS
+}
+
 pub(super) async fn answer(
     Query(params): Query<Answer>,
     Extension(app): Extension<Application>,
     Extension(user): Extension<User>,
 ) -> super::Result<impl IntoResponse> {
+    start_answer(params, app, user).await.map(into_sse)
+}
+
+/// Check and prepare a question, returning the stream that answers it once polled.
+async fn start_answer(
+    mut params: Answer,
+    app: Application,
+    user: User,
+) -> super::Result<AnswerStream> {
     info!(?params.q, "handling /answer query");
     let query_id = uuid::Uuid::new_v4();
 
@@ -112,6 +222,42 @@ pub(super) async fn answer(
         .await?
         .unwrap_or_else(|| (params.repo_ref.clone(), Vec::new()));
 
+    // Follow-ups depend on the rest of their conversation, so only new questions can be repeats
+    if params.check_duplicates && exchanges.is_empty() {
+        let duplicates = conversations::duplicates::find(
+            &app.sql,
+            &conversation_id.user_id,
+            &params.repo_ref,
+            &params.q,
+        )
+        .await?;
+
+        if !duplicates.is_empty() {
+            info!(
+                count = duplicates.len(),
+                "found earlier answers to the question"
+            );
+            return Ok(duplicate_hint(duplicates));
+        }
+    }
+
+    limits::record(&app, &user, Usage::LlmCall).await?;
+
+    // Repositories in a workspace inherit its model policy and quotas
+    let policy = workspace::Policy::for_repo(&app.sql, &user, &params.repo_ref).await?;
+
+    if let Some(policy) = policy {
+        policy
+            .record_answer(&app.sql, &conversation_id.user_id)
+            .await?;
+
+        apply_model_policy(&mut params, &policy);
+    }
+
+    // A branch that isn't indexed is answered from the default branch
+    let branch =
+        repo::indexed_branch(&app.repo_pool, Some(&params.repo_ref), params.branch.take()).await;
+
     let Answer {
         parent_exchange_id,
         q,
@@ -132,7 +278,10 @@ pub(super) async fn answer(
         exchanges.truncate(truncate_from_index);
     }
 
-    let query = parser::parse_nl(q).context("parse error")?.into_owned();
+    let mut query = parser::parse_nl(q).context("parse error")?.into_owned();
+    if let (Some(branch), true) = (branch, query.branch.is_empty()) {
+        query.branch.push(Literal::Plain(branch.into()));
+    }
     let query_target = query
         .target
         .as_ref()
@@ -144,8 +293,31 @@ pub(super) async fn answer(
 
     debug!(?query_target, "parsed query target");
 
-    let action = Action::Query(query_target);
-    exchanges.push(Exchange::new(query_id, query));
+    let action = if params.plan {
+        Action::Plan(query_target)
+    } else {
+        Action::Query(query_target)
+    };
+    let mut exchange = Exchange::new(query_id, query);
+    exchange.retrieval = Some(settings::load(&app.sql, &params.repo_ref).await?);
+    exchange.experiments = experiment::assign(
+        &experiments::running(&app.sql).await?,
+        params.thread_id,
+        exchanges
+            .last()
+            .map_or(&[][..], |e| e.experiments.as_slice()),
+    );
+    if let Some(retrieval) = exchange.variant().and_then(|v| v.retrieval) {
+        exchange.retrieval = Some(retrieval);
+    }
+    experiments::record(&app.sql, query_id, &exchange.experiments).await?;
+
+    // Attachments are fetched once the answer stream has started, see `try_execute_agent`.
+    let fetcher = Fetcher::new(&app.config);
+    for url in &params.urls {
+        fetcher.check(url).map_err(super::Error::user)?;
+    }
+    exchanges.push(exchange);
 
     execute_agent(
         params.clone(),
@@ -159,6 +331,321 @@ pub(super) async fn answer(
     .await
 }
 
+/// A stream that points to earlier answers, instead of answering.
+fn duplicate_hint(duplicates: Vec<conversations::duplicates::Duplicate>) -> AnswerStream {
+    let hint = Frame::json(json!({ "Duplicate": { "conversations": duplicates } }));
+
+    Box::pin(futures::stream::iter([hint, Ok(Frame::Done)]))
+}
+
+fn apply_model_policy(params: &mut Answer, policy: &workspace::Policy) {
+    if let Some(Ok(model)) = policy.answer_model.as_deref().map(str::parse) {
+        params.answer_model = model;
+    }
+
+    if let Some(Ok(model)) = policy.agent_model.as_deref().map(str::parse) {
+        params.agent_model = model;
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ExecutePlan {
+    pub thread_id: uuid::Uuid,
+    /// Search every repository of the workspace, as in `Answer`
+    #[serde(default)]
+    pub workspace: bool,
+    /// Give up on the answer after this many seconds, as in `Answer`
+    #[serde(default = "default_deadline_secs")]
+    pub deadline_secs: u64,
+}
+
+/// Execute the approved plan of the last exchange in a conversation, and answer its query.
+///
+/// Progress is streamed in the same format as `/answer`, with the status of every plan step.
+pub(super) async fn execute_plan(
+    Query(params): Query<ExecutePlan>,
+    Extension(app): Extension<Application>,
+    Extension(user): Extension<User>,
+) -> super::Result<impl IntoResponse> {
+    let conversation_id = ConversationId {
+        user_id: user
+            .username()
+            .ok_or_else(|| super::Error::user("didn't have user ID"))?
+            .to_string(),
+        thread_id: params.thread_id,
+    };
+
+    let (repo_ref, exchanges) = conversations::load(&app.sql, &conversation_id)
+        .await?
+        .ok_or_else(|| super::Error::new(super::ErrorKind::NotFound, "thread was not found"))?;
+
+    let exchange = exchanges
+        .last()
+        .ok_or_else(|| super::Error::user("thread does not have any exchanges"))?;
+
+    let approved = matches!(&exchange.plan, Some(plan) if plan.status == PlanStatus::Approved);
+    if !approved {
+        return Err(super::Error::user(
+            "the last exchange does not have an approved plan",
+        ));
+    }
+
+    let query_id = exchange.id;
+    let mut virtual_req = Answer {
+        q: exchange.query().unwrap_or_default(),
+        repo_ref,
+        thread_id: params.thread_id,
+        parent_exchange_id: None,
+        require_fresh_index: false,
+        check_duplicates: false,
+        urls: Vec::new(),
+        plan: false,
+        answer_model: default_answer_model(),
+        agent_model: default_agent_model(),
+        deadline_secs: params.deadline_secs,
+        // The branch was kept with the query of the exchange
+        branch: None,
+        workspace: params.workspace,
+        later_exchanges: Vec::new(),
+    };
+
+    // Usage was already recorded when the plan was drafted.
+    let policy = workspace::Policy::for_repo(&app.sql, &user, &virtual_req.repo_ref).await?;
+    if let Some(policy) = policy {
+        apply_model_policy(&mut virtual_req, &policy);
+    }
+
+    execute_agent(
+        virtual_req,
+        app,
+        user,
+        query_id,
+        conversation_id,
+        exchanges,
+        Action::ExecutePlan,
+    )
+    .await
+    .map(into_sse)
+}
+
+#[derive(serde::Deserialize)]
+pub struct Retry {
+    #[serde(flatten)]
+    pub overrides: exchange::Retry,
+    /// Search every repository of the workspace, as in `Answer`
+    #[serde(default)]
+    pub workspace: bool,
+    /// Give up on the answer after this many seconds, as in `Answer`
+    #[serde(default = "default_deadline_secs")]
+    pub deadline_secs: u64,
+}
+
+/// Answer an exchange of a conversation again, with other retrieval settings or from another
+/// branch.
+///
+/// The earlier answer is kept as a revision of the exchange, along with the settings it was
+/// answered with, and the exchanges after it are kept as they are. Progress is streamed in the
+/// same format as `/answer`.
+pub(super) async fn retry(
+    Path((thread_id, exchange_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+    Extension(app): Extension<Application>,
+    Extension(user): Extension<User>,
+    Json(params): Json<Retry>,
+) -> super::Result<impl IntoResponse> {
+    let conversation_id = ConversationId {
+        user_id: user
+            .username()
+            .ok_or_else(|| super::Error::user("didn't have user ID"))?
+            .to_string(),
+        thread_id,
+    };
+
+    let (repo_ref, mut exchanges) = conversations::load(&app.sql, &conversation_id)
+        .await?
+        .ok_or_else(|| super::Error::new(super::ErrorKind::NotFound, "thread was not found"))?;
+
+    let index = exchanges
+        .iter()
+        .position(|e| e.id == exchange_id)
+        .ok_or_else(|| super::Error::new(super::ErrorKind::NotFound, "exchange was not found"))?;
+    let later_exchanges = exchanges.split_off(index + 1);
+    let previous = exchanges.pop().expect("the exchange was found");
+
+    let mut query = previous.query.clone();
+    if let Some(branch) = &params.overrides.branch {
+        query.branch = vec![Literal::Plain(branch.clone().into())];
+    }
+    let query_target = query
+        .target
+        .as_ref()
+        .and_then(|target| target.as_plain())
+        .ok_or_else(|| super::Error::user("the exchange doesn't have a plain text query"))?
+        .clone()
+        .into_owned();
+
+    let retrieval = match previous.retrieval {
+        Some(retrieval) => retrieval,
+        None => settings::load(&app.sql, &repo_ref).await?,
+    };
+    let retrieval = params.overrides.apply(retrieval);
+    retrieval.validate().map_err(super::Error::user)?;
+
+    let mut virtual_req = Answer {
+        q: query_target.to_string(),
+        repo_ref,
+        thread_id,
+        parent_exchange_id: None,
+        require_fresh_index: false,
+        check_duplicates: false,
+        urls: Vec::new(),
+        plan: false,
+        answer_model: default_answer_model(),
+        agent_model: default_agent_model(),
+        deadline_secs: params.deadline_secs,
+        // The branch is kept with the query
+        branch: None,
+        workspace: params.workspace,
+        later_exchanges,
+    };
+
+    limits::record(&app, &user, Usage::LlmCall).await?;
+
+    let policy = workspace::Policy::for_repo(&app.sql, &user, &virtual_req.repo_ref).await?;
+    if let Some(policy) = policy {
+        policy
+            .record_answer(&app.sql, &conversation_id.user_id)
+            .await?;
+
+        apply_model_policy(&mut virtual_req, &policy);
+    }
+
+    let mut exchange = Exchange::new(exchange_id, query);
+    exchange.retrieval = Some(retrieval);
+    exchange.retry = Some(params.overrides);
+    exchange.attachments = previous.attachments.clone();
+    exchange.experiments = previous.experiments.clone();
+    exchange.revisions = previous.into_revisions();
+    exchanges.push(exchange);
+
+    execute_agent(
+        virtual_req,
+        app,
+        user,
+        exchange_id,
+        conversation_id,
+        exchanges,
+        Action::Query(query_target),
+    )
+    .await
+    .map(into_sse)
+}
+
+/// Sync & index the repository, waiting at most `FRESH_INDEX_TIMEOUT_SECS`.
+///
+/// Only changed files are re-indexed, so this is usually quick. If the index could not be
+/// brought up to date in time, we answer from the existing index, and flag it as stale.
+///
+/// Indexes that are already at the remote `HEAD` aren't synced at all, and a sync that is already
+/// queued or running is waited for rather than queueing another. New syncs count against the
+/// indexing quota of the user.
+async fn refresh_index(
+    app: &Application,
+    user: &User,
+    repo_ref: &RepoRef,
+    deadline: Deadline,
+) -> super::Result<IndexFreshness> {
+    let timeout = deadline.timeout(Duration::from_secs(FRESH_INDEX_TIMEOUT_SECS));
+    let started = std::time::Instant::now();
+
+    let indexed_commit = || async {
+        app.repo_pool
+            .read_async(repo_ref, |_, repo| repo.indexed_commit.clone())
+            .await
+            .flatten()
+    };
+
+    let up_to_date = tokio::time::timeout(timeout, is_up_to_date(app, repo_ref)).await;
+    if let Ok(true) = up_to_date {
+        return Ok(IndexFreshness {
+            indexed_commit: indexed_commit().await,
+            stale: false,
+        });
+    }
+
+    let timeout = timeout.saturating_sub(started.elapsed());
+    let synced = if app.write_index().is_pending(repo_ref).await {
+        tokio::time::timeout(timeout, wait_until_synced(app, repo_ref)).await
+    } else {
+        limits::record(app, user, Usage::IndexingJob).await?;
+        tokio::time::timeout(
+            timeout,
+            app.write_index().block_until_synced(repo_ref.clone()),
+        )
+        .await
+    };
+
+    let stale = match synced {
+        Ok(Ok(SyncStatus::Done | SyncStatus::Shallow)) => false,
+        Ok(Ok(status)) => {
+            warn!(?status, %repo_ref, "repo was not indexed before answering");
+            true
+        }
+        Ok(Err(err)) => {
+            warn!(?err, %repo_ref, "failed to sync repo before answering");
+            true
+        }
+        Err(_) => {
+            warn!(%repo_ref, ?timeout, "timed out waiting for repo to be indexed");
+            true
+        }
+    };
+
+    Ok(IndexFreshness {
+        indexed_commit: indexed_commit().await,
+        stale,
+    })
+}
+
+/// Whether the index of a repository is at the commit `HEAD` of its remote points at.
+///
+/// Local repositories have no remote to compare with, so they are never up to date.
+async fn is_up_to_date(app: &Application, repo_ref: &RepoRef) -> bool {
+    let Some(repo) = app
+        .repo_pool
+        .read_async(repo_ref, |_, repo| repo.clone())
+        .await
+    else {
+        return false;
+    };
+
+    let (Some(indexed_commit), Some(creds)) = (
+        repo.indexed_commit.clone(),
+        app.credentials.for_repo(repo_ref),
+    ) else {
+        return false;
+    };
+
+    match creds.remote_head(repo).await {
+        Ok(head) => head.as_deref() == Some(indexed_commit.as_str()),
+        Err(err) => {
+            debug!(?err, %repo_ref, "failed to look up the remote HEAD");
+            false
+        }
+    }
+}
+
+/// Wait for the sync that is already queued or running for a repository, returning how it ended.
+async fn wait_until_synced(app: &Application, repo_ref: &RepoRef) -> anyhow::Result<SyncStatus> {
+    while app.write_index().is_pending(repo_ref).await {
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+
+    app.repo_pool
+        .read_async(repo_ref, |_, repo| repo.sync_status.clone())
+        .await
+        .context("repo was removed while syncing")
+}
+
 /// Like `try_execute_agent`, but additionally logs errors in our analytics.
 async fn execute_agent(
     params: Answer,
@@ -168,9 +655,7 @@ async fn execute_agent(
     conversation_id: ConversationId,
     exchanges: Vec<Exchange>,
     action: Action,
-) -> super::Result<
-    Sse<std::pin::Pin<Box<dyn tokio_stream::Stream<Item = Result<sse::Event>> + Send>>>,
-> {
+) -> super::Result<AnswerStream> {
     let response = try_execute_agent(
         params.clone(),
         app.clone(),
@@ -209,55 +694,108 @@ async fn try_execute_agent(
     conversation_id: ConversationId,
     exchanges: Vec<Exchange>,
     mut action: Action,
-) -> super::Result<
-    Sse<std::pin::Pin<Box<dyn tokio_stream::Stream<Item = Result<sse::Event>> + Send>>>,
-> {
-    QueryLog::new(&app.sql).insert(&params.q).await?;
+) -> super::Result<AnswerStream> {
     let Answer {
         thread_id,
         repo_ref,
         answer_model,
         agent_model,
+        deadline_secs,
         ..
     } = params.clone();
 
+    // A self-hosted model answers whatever model was asked for, within its own context window
+    let (answer_model, agent_model) = match app.config.llm_url {
+        Some(_) => {
+            let model = agent::model::custom(app.config.llm_context_window);
+            (model, model)
+        }
+        None => (answer_model, agent_model),
+    };
+
+    // Everything downstream has to finish by this deadline, so that no work is done for an
+    // answer that the client has given up on.
+    let deadline = Deadline::after(Duration::from_secs(deadline_secs.min(MAX_DEADLINE_SECS)));
+
     let llm_gateway = user
         .llm_gateway(&app)
         .await?
+        .deadline(deadline.instant())
         .temperature(0.0)
         .session_reference_id(conversation_id.to_string())
         .model(agent_model.model_name);
 
-    // confirm client compatibility with answer-api
-    match llm_gateway
-        .is_compatible(env!("CARGO_PKG_VERSION").parse().unwrap())
-        .await
-    {
-        Ok(res) if res.status() == StatusCode::OK => (),
-        Ok(res) if res.status() == StatusCode::NOT_ACCEPTABLE => {
-            let out_of_date = futures::stream::once(async {
-                Ok(sse::Event::default()
-                    .json_data(serde_json::json!({"Err": "incompatible client"}))
-                    .unwrap())
-            });
-            return Ok(Sse::new(Box::pin(out_of_date)));
+    // Other clients with the conversation open follow along, for as long as the answer stream lives.
+    let publisher = app.live_answers.publish(conversation_id.clone(), query_id);
+
+    // Everything below runs after the client has received the `Thinking` event, so that slow
+    // preparation, like refreshing the index, doesn't delay the first response.
+    let stream = async_stream::try_stream! {
+        // Background indexing yields to us until the answer is done.
+        let _interactive = crate::background::interactive();
+
+        QueryLog::new(&app.sql).insert(&params.q).await?;
+
+        // confirm client compatibility with answer-api
+        if llm_gateway.is_gateway() {
+            match llm_gateway
+                .is_compatible(env!("CARGO_PKG_VERSION").parse().unwrap())
+                .await
+            {
+                Ok(res) if res.status() == StatusCode::OK => (),
+                Ok(res) if res.status() == StatusCode::NOT_ACCEPTABLE => {
+                    Err(anyhow!("incompatible client"))?;
+                }
+                Ok(_) => unreachable!(),
+                Err(err) => {
+                    warn!(
+                        ?err,
+                        "failed to check compatibility ... defaulting to `incompatible`"
+                    );
+                    Err(anyhow!("failed to check compatibility"))?;
+                }
+            };
         }
-        Ok(_) => unreachable!(),
-        Err(err) => {
-            warn!(
-                ?err,
-                "failed to check compatibility ... defaulting to `incompatible`"
-            );
-            let failed_to_check = futures::stream::once(async {
-                Ok(sse::Event::default()
-                    .json_data(serde_json::json!({"Err": "failed to check compatibility"}))
-                    .unwrap())
+
+        let scratchpads = scratchpads::load(&app.sql, &conversation_id).await?;
+        let workspace_policy =
+            workspace::Policy::for_repo(&app.sql, &user, &repo_ref).await?;
+        let tool_rules = workspace::ToolRules::for_repo(&app.sql, &repo_ref).await?;
+
+        let mut workspace_repos = vec![];
+        if let Some(policy) = workspace_policy.as_ref().filter(|_| params.workspace) {
+            for repo in policy.repos(&app.sql).await? {
+                if repo != repo_ref && app.repo_pool.contains_async(&repo).await {
+                    workspace_repos.push(repo);
+                }
+            }
+        }
+
+        let mut exchanges = exchanges;
+        if let Some(exchange) = exchanges.last_mut() {
+            if params.require_fresh_index {
+                exchange.index_freshness =
+                    Some(refresh_index(&app, &user, &repo_ref, deadline).await?);
+            }
+
+            if !params.urls.is_empty() {
+                let fetcher = Fetcher::new(&app.config);
+                exchange.attachments = attachment::fetch_all(&fetcher, &params.urls).await;
+            }
+
+            exchange.comment_language = preferences::load(&app.sql, &conversation_id.user_id)
+                .await?
+                .comment_language;
+
+            exchange.footer = footer::load(&app.sql).await?.map(|template| {
+                footer::render(
+                    &template,
+                    answer_model.model_name,
+                    chrono::Utc::now().date_naive(),
+                )
             });
-            return Ok(Sse::new(Box::pin(failed_to_check)));
         }
-    };
 
-    let stream = async_stream::try_stream! {
         let (exchange_tx, exchange_rx) = tokio::sync::mpsc::channel(10);
 
         let mut agent = Agent {
@@ -271,7 +809,12 @@ async fn try_execute_agent(
             query_id,
             exchange_state: ExchangeState::Pending,
             answer_model,
-            agent_model
+            agent_model,
+            scratchpads,
+            tool_rules,
+            workspace_repos,
+            later_exchanges: params.later_exchanges.clone(),
+            deadline,
         };
 
         let mut exchange_rx = tokio_stream::wrappers::ReceiverStream::new(exchange_rx);
@@ -290,7 +833,7 @@ async fn try_execute_agent(
                 .into_stream()
                 .map(Either::Right);
 
-            let timeout = Duration::from_secs(TIMEOUT_SECS);
+            let timeout = deadline.timeout(Duration::from_secs(TIMEOUT_SECS));
 
             let mut next = None;
             for await item in tokio_stream::StreamExt::timeout(
@@ -345,30 +888,34 @@ async fn try_execute_agent(
     };
 
     let init_stream = futures::stream::once(async move {
-        Ok(sse::Event::default()
-            .json_data(json!({
-                "thread_id": params.thread_id.to_string(),
-                "query_id": query_id,
-            }))
-            // This should never happen, so we force an unwrap.
-            .expect("failed to serialize initialization object"))
+        Frame::json(json!({
+            "thread_id": thread_id.to_string(),
+            "query_id": query_id,
+        }))
+    });
+
+    // Let the client show that we're working on it, before any preparation is done.
+    let thinking_stream = futures::stream::once(async {
+        Frame::json(json!({ "Thinking": { "stage": "preparing" } }))
     });
 
     // We know the stream is unwind safe as it doesn't use synchronization primitives like locks.
     let answer_stream = AssertUnwindSafe(stream)
         .catch_unwind()
         .map(|res| res.unwrap_or_else(|_| Err(anyhow!("stream panicked"))))
-        .map(|ex: Result<Exchange>| {
-            sse::Event::default()
-                .json_data(ex.map_err(|e| e.to_string()))
-                .map_err(anyhow::Error::new)
+        .map(move |ex: Result<Exchange>| {
+            publisher.update(&ex);
+            Frame::json(ex.map_err(|e| e.to_string()))
         });
 
-    let done_stream = futures::stream::once(async { Ok(sse::Event::default().data("[DONE]")) });
-
-    let stream = init_stream.chain(answer_stream).chain(done_stream);
+    let done_stream = futures::stream::once(async { Ok(Frame::Done) });
 
-    Ok(Sse::new(Box::pin(stream)))
+    Ok(Box::pin(
+        init_stream
+            .chain(thinking_stream)
+            .chain(answer_stream)
+            .chain(done_stream),
+    ))
 }
 
 #[derive(serde::Deserialize)]
@@ -383,11 +930,13 @@ pub struct Explain {
 }
 
 pub async fn explain(
-    Query(params): Query<Explain>,
+    Query(mut params): Query<Explain>,
     Extension(app): Extension<Application>,
     Extension(user): Extension<User>,
 ) -> super::Result<impl IntoResponse> {
     let query_id = uuid::Uuid::new_v4();
+    params.branch =
+        repo::indexed_branch(&app.repo_pool, Some(&params.repo_ref), params.branch).await;
 
     // We synthesize a virtual `/answer` request.
     let virtual_req = Answer {
@@ -400,8 +949,16 @@ pub async fn explain(
         repo_ref: params.repo_ref,
         thread_id: params.thread_id,
         parent_exchange_id: None,
+        require_fresh_index: false,
+        check_duplicates: false,
+        urls: Vec::new(),
+        plan: false,
         answer_model: agent::model::GPT_4_TURBO_24K,
         agent_model: agent::model::GPT_4,
+        deadline_secs: DEFAULT_DEADLINE_SECS,
+        branch: params.branch.clone(),
+        workspace: false,
+        later_exchanges: Vec::new(),
     };
 
     let conversation_id = ConversationId {
@@ -423,7 +980,11 @@ pub async fn explain(
     let file_content = app
         .indexes
         .file
-        .by_path(&virtual_req.repo_ref, &params.relative_path, None)
+        .by_path(
+            &virtual_req.repo_ref,
+            &params.relative_path,
+            query.first_branch().as_deref(),
+        )
         .await
         .context("file retrieval failed")?
         .context("did not find requested file")?
@@ -467,4 +1028,5 @@ pub async fn explain(
         action,
     )
     .await
+    .map(into_sse)
 }
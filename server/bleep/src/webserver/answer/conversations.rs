@@ -1,24 +1,47 @@
 use anyhow::{Context, Result};
 use axum::{
     extract::{Path, Query, State},
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     Extension, Json,
 };
 use reqwest::StatusCode;
-use std::{fmt, str::FromStr};
-use tracing::info;
+use serde::Deserialize;
+use std::fmt;
+use tracing::{debug, info};
 
 use crate::{
-    agent::exchange::Exchange,
+    agent::{
+        exchange::{CitationStatus, Exchange, Plan, PlanStatus, PlanStep, PlanStepStatus},
+        prompts,
+    },
     db::SqlDb,
     repo::RepoRef,
-    webserver::{self, middleware::User, Error, ErrorKind},
+    webserver::{
+        self,
+        dry_run::{Deletion, DryRun, Preview},
+        limits::{self, Usage},
+        middleware::User,
+        Error, ErrorKind,
+    },
     Application,
 };
 
+pub(in crate::webserver) mod document;
+pub(in crate::webserver) mod duplicates;
+mod sarif;
+mod search;
+
+pub(crate) use search::{index_stored, search_expr};
+
 type Conversation = (RepoRef, Vec<Exchange>);
 
-#[derive(Hash, PartialEq, Eq, Clone)]
+/// Exchanges are mostly repetitive markdown, which compresses well.
+const ZSTD_LEVEL: i32 = 9;
+
+/// How many conversations stored before compression was introduced to compress at once.
+const COMPRESS_BATCH_SIZE: i64 = 100;
+
+#[derive(Hash, PartialEq, Eq, Clone, Debug)]
 pub struct ConversationId {
     pub thread_id: uuid::Uuid,
     pub user_id: String,
@@ -30,53 +53,256 @@ impl fmt::Display for ConversationId {
     }
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, schemars::JsonSchema)]
 pub struct ConversationPreview {
     pub thread_id: String,
     pub created_at: i64,
+    pub updated_at: i64,
     pub title: String,
+    pub pinned: bool,
+    pub sort_order: Option<i64>,
+    /// The thread this conversation was forked from, if it was
+    pub forked_from: Option<String>,
+    /// How many exchanges of `forked_from` this conversation started with
+    pub forked_at: Option<i64>,
+}
+
+/// The conversation another was forked from, and how many of its exchanges were copied.
+#[derive(Clone, Debug)]
+pub struct Fork {
+    pub thread_id: uuid::Uuid,
+    pub exchange_count: i64,
 }
 
+/// The most conversations that can be listed on a single page.
+const MAX_PER_PAGE: usize = 100;
+
 #[derive(serde::Deserialize)]
 pub(in crate::webserver) struct List {
     repo_ref: Option<RepoRef>,
+    #[serde(default)]
+    order_by: OrderBy,
+    /// Zero-based, like the pages of search results
+    page: Option<usize>,
+    /// Without this, every conversation is listed on a single page
+    per_page: Option<usize>,
+    /// Only list conversations with every word of this in their title, queries or answers
+    #[serde(default)]
+    q: String,
+    /// List the deleted conversations that can still be restored, rather than the others
+    #[serde(default)]
+    deleted: bool,
+}
+
+/// The conversations that were asked for with `page` or `per_page`. Without either, the list
+/// is a bare array, as it was before pagination.
+#[derive(serde::Serialize, schemars::JsonSchema)]
+pub(in crate::webserver) struct ConversationPage {
+    conversations: Vec<ConversationPreview>,
+    page: usize,
+    per_page: Option<usize>,
+    page_count: usize,
+    /// The number of conversations on every page, after filtering by `q`
+    total_count: usize,
+}
+
+#[derive(serde::Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum OrderBy {
+    #[default]
+    #[serde(alias = "created_at")]
+    Created,
+    /// Most recently active first
+    #[serde(alias = "updated_at")]
+    Updated,
+    Title,
+}
+
+impl OrderBy {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Updated => "updated",
+            Self::Title => "title",
+        }
+    }
 }
 
 pub(in crate::webserver) async fn list(
     Extension(user): Extension<User>,
     Query(query): Query<List>,
     State(app): State<Application>,
-) -> webserver::Result<impl IntoResponse> {
+) -> webserver::Result<Response> {
     let db = app.sql.as_ref();
     let user_id = user
         .username()
         .ok_or_else(|| Error::user("missing user ID"))?;
 
-    let conversations = if let Some(repo_ref) = query.repo_ref {
-        let repo_ref = repo_ref.to_string();
-        sqlx::query_as! {
-            ConversationPreview,
-            "SELECT thread_id, created_at, title \
-             FROM conversations \
-             WHERE user_id = ? AND repo_ref = ? \
-             ORDER BY created_at DESC",
+    let paginated = query.page.is_some() || query.per_page.is_some();
+    let page = query.page.unwrap_or_default();
+
+    if query.per_page.map_or(false, |n| n == 0 || n > MAX_PER_PAGE) {
+        return Err(Error::user(format!(
+            "`per_page` must be between 1 and {MAX_PER_PAGE}"
+        )));
+    }
+
+    let repo_ref = query.repo_ref.map(|r| r.to_string());
+    let search = search::search_expr(&query.q);
+    let (limit, offset) = match query.per_page {
+        Some(per_page) => {
+            let offset = page
+                .checked_mul(per_page)
+                .and_then(|offset| i64::try_from(offset).ok())
+                .ok_or_else(|| Error::user("`page` is out of range"))?;
+            (per_page as i64, offset)
+        }
+        None => (-1, 0),
+    };
+
+    // Pinned conversations always come first, followed by those with a manual sort order.
+    let order_by = query.order_by.as_str();
+    let conversations = sqlx::query_as! {
+        ConversationPreview,
+        "SELECT thread_id, created_at, updated_at, title, pinned, sort_order, forked_from, \
+            forked_at \
+         FROM conversations \
+         WHERE user_id = ? AND (? IS NULL OR repo_ref = ?) \
+            AND (deleted_at IS NOT NULL) = ? \
+            AND (? IS NULL OR id IN \
+                (SELECT rowid FROM conversations_fts WHERE conversations_fts MATCH ?)) \
+         ORDER BY \
+            pinned DESC, \
+            sort_order IS NULL, \
+            sort_order, \
+            CASE WHEN ? = 'title' THEN title END, \
+            CASE WHEN ? = 'updated' THEN updated_at END DESC, \
+            created_at DESC \
+         LIMIT ? OFFSET ?",
+        user_id,
+        repo_ref,
+        repo_ref,
+        query.deleted,
+        search,
+        search,
+        order_by,
+        order_by,
+        limit,
+        offset,
+    }
+    .fetch_all(db)
+    .await
+    .map_err(Error::internal)?;
+
+    if !paginated {
+        return Ok(Json(conversations).into_response());
+    }
+
+    let total_count = if query.per_page.is_some() {
+        sqlx::query_scalar! {
+            r#"SELECT COUNT(*) AS "count!: i64" FROM conversations
+            WHERE user_id = ? AND (? IS NULL OR repo_ref = ?)
+                AND (deleted_at IS NOT NULL) = ?
+                AND (? IS NULL OR id IN
+                    (SELECT rowid FROM conversations_fts WHERE conversations_fts MATCH ?))"#,
             user_id,
             repo_ref,
+            repo_ref,
+            query.deleted,
+            search,
+            search,
         }
-        .fetch_all(db)
+        .fetch_one(db)
         .await
+        .map_err(Error::internal)? as usize
     } else {
-        sqlx::query_as! {
-            ConversationPreview,
-            "SELECT thread_id, created_at, title \
-             FROM conversations \
-             WHERE user_id = ? \
-             ORDER BY created_at DESC",
-            user_id,
-        }
-        .fetch_all(db)
-        .await
+        conversations.len()
+    };
+
+    let page_count = match query.per_page {
+        Some(per_page) => (total_count + per_page - 1) / per_page,
+        None => usize::from(total_count > 0),
+    };
+
+    Ok(Json(ConversationPage {
+        conversations,
+        page,
+        per_page: query.per_page,
+        page_count,
+        total_count,
+    })
+    .into_response())
+}
+
+#[derive(serde::Deserialize)]
+pub(in crate::webserver) struct Related {
+    #[serde(default = "default_related_limit")]
+    limit: i64,
+}
+
+fn default_related_limit() -> i64 {
+    5
+}
+
+/// Other conversations of the user that share the most distinctive words with this one.
+pub(in crate::webserver) async fn related(
+    Extension(user): Extension<User>,
+    Path(thread_id): Path<uuid::Uuid>,
+    Query(params): Query<Related>,
+    State(app): State<Application>,
+) -> webserver::Result<Json<Vec<ConversationPreview>>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("missing user ID"))?
+        .to_string();
+
+    if !(1..=MAX_PER_PAGE as i64).contains(&params.limit) {
+        return Err(Error::user(format!(
+            "`limit` must be between 1 and {MAX_PER_PAGE}"
+        )));
+    }
+
+    let thread_id = thread_id.to_string();
+    let row = sqlx::query! {
+        r#"SELECT c.id, f.title AS "title: String", f.content AS "content: String"
+        FROM conversations c
+        INNER JOIN conversations_fts f ON f.rowid = c.id
+        WHERE c.user_id = ? AND c.thread_id = ? AND c.deleted_at IS NULL"#,
+        user_id,
+        thread_id,
+    }
+    .fetch_optional(app.sql.as_ref())
+    .await
+    .map_err(Error::internal)?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "thread was not found"))?;
+
+    let text = format!(
+        "{}\n{}",
+        row.title.unwrap_or_default(),
+        row.content.unwrap_or_default()
+    );
+    let Some(expr) = search::related_expr(&text) else {
+        return Ok(Json(vec![]));
+    };
+
+    // Titles are short and deliberate, so a shared word there counts for more.
+    let conversations = sqlx::query_as! {
+        ConversationPreview,
+        "SELECT c.thread_id, c.created_at, c.updated_at, c.title, c.pinned, c.sort_order, \
+            c.forked_from, c.forked_at \
+         FROM conversations_fts f \
+         INNER JOIN conversations c ON c.id = f.rowid \
+         WHERE conversations_fts MATCH ? AND c.user_id = ? AND c.id != ? \
+            AND c.deleted_at IS NULL \
+         ORDER BY bm25(conversations_fts, 2.0, 1.0) \
+         LIMIT ?",
+        expr,
+        user_id,
+        row.id,
+        params.limit,
     }
+    .fetch_all(app.sql.as_ref())
+    .await
     .map_err(Error::internal)?;
 
     Ok(Json(conversations))
@@ -87,22 +313,28 @@ pub(in crate::webserver) struct Delete {
     thread_id: String,
 }
 
+/// Move a conversation to the trash, from which it can be restored until it is purged.
+///
+/// Scratchpads and drafts are kept along with it, and go once the conversation is purged.
 pub(in crate::webserver) async fn delete(
     Query(params): Query<Delete>,
     Extension(user): Extension<User>,
+    Extension(dry_run): Extension<DryRun>,
     State(app): State<Application>,
-) -> webserver::Result<()> {
-    let db = app.sql.as_ref();
+) -> webserver::Result<Json<Preview>> {
     let user_id = user
         .username()
         .ok_or_else(|| Error::user("missing user ID"))?;
 
+    let mut deletion = Deletion::begin(&app.sql, dry_run).await?;
+
     let result = sqlx::query! {
-        "DELETE FROM conversations WHERE user_id = ? AND thread_id = ?",
+        "UPDATE conversations SET deleted_at = strftime('%s', 'now') \
+         WHERE user_id = ? AND thread_id = ? AND deleted_at IS NULL",
         user_id,
         params.thread_id,
     }
-    .execute(db)
+    .execute(&mut *deletion.transaction())
     .await
     .map_err(Error::internal)?;
 
@@ -110,9 +342,407 @@ pub(in crate::webserver) async fn delete(
         return Err(Error::user("conversation not found").with_status(StatusCode::NOT_FOUND));
     }
 
+    deletion.removed("conversations", result.rows_affected());
+    deletion.finish().await
+}
+
+/// Bring back a deleted conversation that wasn't purged yet.
+pub(in crate::webserver) async fn restore(
+    Path(thread_id): Path<uuid::Uuid>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+) -> webserver::Result<()> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("missing user ID"))?;
+
+    let thread_id = thread_id.to_string();
+    let result = sqlx::query! {
+        "UPDATE conversations SET deleted_at = NULL \
+         WHERE user_id = ? AND thread_id = ? AND deleted_at IS NOT NULL",
+        user_id,
+        thread_id,
+    }
+    .execute(app.sql.as_ref())
+    .await
+    .map_err(Error::internal)?;
+
+    if result.rows_affected() == 0 {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            "no deleted conversation was found",
+        ));
+    }
+
     Ok(())
 }
 
+#[derive(serde::Deserialize, Default)]
+pub(in crate::webserver) struct Patch {
+    pinned: Option<bool>,
+    /// `null` clears the manual sort order
+    #[serde(default, deserialize_with = "deserialize_some")]
+    sort_order: Option<Option<i64>>,
+    /// Rename the conversation. The title is kept when more exchanges are added.
+    title: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub(in crate::webserver) struct PatchParams {
+    /// Rename the conversation with a title summarising its exchanges, instead of `title`
+    #[serde(default)]
+    regenerate: bool,
+}
+
+/// The longest title a conversation can be renamed to, in characters.
+const MAX_TITLE_CHARS: usize = 200;
+
+fn deserialize_some<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Pin, reorder or rename a conversation, returning it as it is listed.
+///
+/// Renaming bumps `updated_at`, unlike pinning and reordering.
+pub(in crate::webserver) async fn patch(
+    Path(thread_id): Path<uuid::Uuid>,
+    Query(params): Query<PatchParams>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+    patch: Option<Json<Patch>>,
+) -> webserver::Result<Json<ConversationPreview>> {
+    let Json(patch) = patch.unwrap_or_default();
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("missing user ID"))?;
+
+    let title = match (patch.title, params.regenerate) {
+        (Some(_), true) => {
+            return Err(Error::user(
+                "a conversation can't be renamed and have its title regenerated at once",
+            ))
+        }
+        (Some(title), false) => {
+            let title = title.trim();
+            if title.is_empty() || title.chars().count() > MAX_TITLE_CHARS {
+                return Err(Error::user(format!(
+                    "titles must have between 1 and {MAX_TITLE_CHARS} characters"
+                )));
+            }
+
+            Some(title.to_owned())
+        }
+        (None, true) => Some(generate_title(&app, &user, user_id, thread_id).await?),
+        (None, false) => None,
+    };
+
+    let thread_id = thread_id.to_string();
+    let mut transaction = app.sql.begin().await?;
+
+    let exists = sqlx::query! {
+        "SELECT id FROM conversations \
+         WHERE user_id = ? AND thread_id = ? AND deleted_at IS NULL",
+        user_id,
+        thread_id,
+    }
+    .fetch_optional(&mut transaction)
+    .await?
+    .is_some();
+
+    if !exists {
+        return Err(Error::new(ErrorKind::NotFound, "thread was not found"));
+    }
+
+    if let Some(pinned) = patch.pinned {
+        sqlx::query! {
+            "UPDATE conversations SET pinned = ? WHERE user_id = ? AND thread_id = ?",
+            pinned,
+            user_id,
+            thread_id,
+        }
+        .execute(&mut transaction)
+        .await?;
+    }
+
+    if let Some(sort_order) = patch.sort_order {
+        sqlx::query! {
+            "UPDATE conversations SET sort_order = ? WHERE user_id = ? AND thread_id = ?",
+            sort_order,
+            user_id,
+            thread_id,
+        }
+        .execute(&mut transaction)
+        .await?;
+    }
+
+    if let Some(title) = title {
+        sqlx::query! {
+            "UPDATE conversations \
+             SET title = ?, title_generated = TRUE, updated_at = strftime('%s', 'now') \
+             WHERE user_id = ? AND thread_id = ?",
+            title,
+            user_id,
+            thread_id,
+        }
+        .execute(&mut transaction)
+        .await?;
+    }
+
+    let preview = preview(&mut transaction, user_id, &thread_id).await?;
+    transaction.commit().await?;
+
+    Ok(Json(preview))
+}
+
+async fn preview(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    user_id: &str,
+    thread_id: &str,
+) -> webserver::Result<ConversationPreview> {
+    let preview = sqlx::query_as! {
+        ConversationPreview,
+        "SELECT thread_id, created_at, updated_at, title, pinned, sort_order, forked_from, \
+            forked_at \
+         FROM conversations \
+         WHERE user_id = ? AND thread_id = ? AND deleted_at IS NULL",
+        user_id,
+        thread_id,
+    }
+    .fetch_one(&mut *transaction)
+    .await?;
+
+    Ok(preview)
+}
+
+#[derive(serde::Deserialize)]
+pub(in crate::webserver) struct ForkParams {
+    /// Index of the last exchange the fork starts with
+    exchange: usize,
+}
+
+/// Start a new conversation with the exchanges of this one, up to and including `exchange`.
+///
+/// The original conversation is left as it is. Scratchpads and drafts aren't copied.
+pub(in crate::webserver) async fn fork(
+    Path(thread_id): Path<uuid::Uuid>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+    Json(params): Json<ForkParams>,
+) -> webserver::Result<Json<ConversationPreview>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("missing user ID"))?
+        .to_owned();
+
+    let (repo_ref, mut exchanges) = load(
+        &app.sql,
+        &ConversationId {
+            thread_id,
+            user_id: user_id.clone(),
+        },
+    )
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "thread was not found"))?;
+
+    fork_at(&mut exchanges, params.exchange)?;
+
+    let fork = Fork {
+        thread_id,
+        exchange_count: exchanges.len() as i64,
+    };
+    let id = ConversationId {
+        thread_id: uuid::Uuid::new_v4(),
+        user_id,
+    };
+
+    store_with_parent(&app.sql, id.clone(), (repo_ref, exchanges), Some(fork)).await?;
+
+    let mut transaction = app.sql.begin().await?;
+    let preview = preview(&mut transaction, &id.user_id, &id.thread_id.to_string()).await?;
+    transaction.commit().await?;
+
+    Ok(Json(preview))
+}
+
+/// Keep the exchanges up to and including `exchange`.
+fn fork_at(exchanges: &mut Vec<Exchange>, exchange: usize) -> webserver::Result<()> {
+    if exchange >= exchanges.len() {
+        return Err(Error::user(format!(
+            "`exchange` must be less than {}, the number of exchanges",
+            exchanges.len()
+        )));
+    }
+
+    exchanges.truncate(exchange + 1);
+    Ok(())
+}
+
+/// The maximum length of the conversation history we send when generating a title.
+const TITLE_HISTORY_MAX_CHARS: usize = 16_000;
+
+/// Replace the title of a conversation with one generated from its full history.
+pub(in crate::webserver) async fn regenerate_title(
+    Path(thread_id): Path<uuid::Uuid>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+) -> webserver::Result<impl IntoResponse> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("missing user ID"))?;
+
+    let title = generate_title(&app, &user, user_id, thread_id).await?;
+
+    let thread_id = thread_id.to_string();
+    sqlx::query! {
+        "UPDATE conversations \
+         SET title = ?, title_generated = TRUE, updated_at = strftime('%s', 'now') \
+         WHERE user_id = ? AND thread_id = ?",
+        title,
+        user_id,
+        thread_id,
+    }
+    .execute(&*app.sql)
+    .await?;
+
+    Ok(Json(title))
+}
+
+/// A title for a conversation, summarising its full history.
+async fn generate_title(
+    app: &Application,
+    user: &User,
+    user_id: &str,
+    thread_id: uuid::Uuid,
+) -> webserver::Result<String> {
+    let (.., exchanges) = load(
+        &app.sql,
+        &ConversationId {
+            thread_id,
+            user_id: user_id.to_owned(),
+        },
+    )
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "thread was not found"))?;
+
+    let history = exchanges
+        .iter()
+        .filter_map(|exchange| {
+            let query = exchange.query()?;
+            let answer = exchange.answer().unwrap_or_default();
+            Some(format!("Q: {query}\nA: {answer}\n"))
+        })
+        .collect::<String>()
+        .chars()
+        .take(TITLE_HISTORY_MAX_CHARS)
+        .collect::<String>();
+
+    let llm_gateway = user
+        .llm_gateway(app)
+        .await
+        .map_err(|e| Error::user(e).with_status(StatusCode::UNAUTHORIZED))?
+        .model("gpt-3.5-turbo-16k-0613")
+        .temperature(0.0);
+
+    let messages = &[llm_gateway::api::Message::system(
+        &prompts::conversation_title_prompt(&history),
+    )];
+
+    limits::record(app, user, Usage::LlmCall).await?;
+
+    let title = llm_gateway.chat(messages, None).await?;
+    let title = title.trim().trim_matches('"').trim();
+    if title.is_empty() {
+        return Err(Error::internal("generated title was empty"));
+    }
+
+    debug!("regenerated title of thread `{thread_id}`: `{title}`");
+    Ok(title.to_owned())
+}
+
+#[derive(serde::Deserialize)]
+pub(in crate::webserver) struct PlanReview {
+    exchange_id: uuid::Uuid,
+    approve: bool,
+    /// Replace the drafted steps with an edited list before approving them
+    steps: Option<Vec<String>>,
+}
+
+/// Approve or reject the plan drafted for an exchange.
+///
+/// Approved plans are executed with `/answer/plan/execute`.
+pub(in crate::webserver) async fn review_plan(
+    Path(thread_id): Path<uuid::Uuid>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+    Json(review): Json<PlanReview>,
+) -> webserver::Result<impl IntoResponse> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("missing user ID"))?
+        .to_owned();
+    let conversation_id = ConversationId { thread_id, user_id };
+
+    let (repo_ref, mut exchanges) = load(&app.sql, &conversation_id)
+        .await?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "thread was not found"))?;
+
+    let plan = exchanges
+        .iter_mut()
+        .find(|e| e.id == review.exchange_id)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "exchange was not found"))?
+        .plan
+        .as_mut()
+        .ok_or_else(|| Error::user("exchange does not have a plan"))?;
+
+    if plan.status != PlanStatus::AwaitingApproval {
+        return Err(Error::user("plan is not awaiting approval"));
+    }
+
+    if let Some(steps) = review.steps {
+        if !review.approve {
+            return Err(Error::user("only approved plans can be edited"));
+        }
+
+        let steps = steps
+            .into_iter()
+            .map(|step| step.trim().to_owned())
+            .filter(|step| !step.is_empty())
+            .map(|description| PlanStep {
+                description,
+                status: PlanStepStatus::Pending,
+            })
+            .collect::<Vec<_>>();
+
+        if steps.is_empty() {
+            return Err(Error::user("plan must have at least one step"));
+        }
+
+        if steps.len() > Plan::MAX_STEPS {
+            return Err(Error::user(format!(
+                "plans can't have more than {} steps",
+                Plan::MAX_STEPS
+            )));
+        }
+
+        plan.steps = steps;
+    }
+
+    plan.status = if review.approve {
+        PlanStatus::Approved
+    } else {
+        PlanStatus::Rejected
+    };
+
+    let plan = plan.clone();
+    store(&app.sql, conversation_id, (repo_ref, exchanges)).await?;
+
+    Ok(Json(plan))
+}
+
 pub(in crate::webserver) async fn thread(
     Path(thread_id): Path<uuid::Uuid>,
     Extension(user): Extension<User>,
@@ -135,12 +765,227 @@ pub(in crate::webserver) async fn thread(
     Ok(Json(exchanges))
 }
 
+#[derive(serde::Deserialize)]
+pub(in crate::webserver) struct ExchangeRange {
+    /// Index of the first exchange to return
+    #[serde(default)]
+    from: usize,
+    /// Index after the last exchange to return, defaulting to the end of the thread
+    to: Option<usize>,
+}
+
+#[derive(serde::Serialize)]
+pub(in crate::webserver) struct Exchanges {
+    /// The number of exchanges in the whole thread
+    total: usize,
+    from: usize,
+    exchanges: Vec<Exchange>,
+}
+
+/// Fetch a range of exchanges of a thread, so that long threads can be loaded lazily.
+pub(in crate::webserver) async fn exchanges(
+    Path(thread_id): Path<uuid::Uuid>,
+    Query(params): Query<ExchangeRange>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+) -> webserver::Result<Json<Exchanges>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("missing user ID"))?
+        .to_owned();
+
+    let id = ConversationId { thread_id, user_id };
+    let (.., exchanges) = load(&app.sql, &id)
+        .await?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "thread was not found"))?;
+
+    let total = exchanges.len();
+    let range = clamp_range(total, params.from, params.to).map_err(Error::user)?;
+    let from = range.start;
+
+    let exchanges = exchanges
+        .into_iter()
+        .skip(range.start)
+        .take(range.len())
+        .map(Exchange::compressed)
+        .collect();
+
+    log_view(
+        &app.sql,
+        &id,
+        ViewSource::Api,
+        Some(&id.user_id),
+        range.len(),
+    )
+    .await?;
+
+    Ok(Json(Exchanges {
+        total,
+        from,
+        exchanges,
+    }))
+}
+
+/// Fetch a single exchange of a thread.
+pub(in crate::webserver) async fn exchange(
+    Path((thread_id, exchange_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+) -> webserver::Result<Json<Exchange>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("missing user ID"))?
+        .to_owned();
+
+    let id = ConversationId { thread_id, user_id };
+    let (.., exchanges) = load(&app.sql, &id)
+        .await?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "thread was not found"))?;
+
+    let exchange = exchanges
+        .into_iter()
+        .find(|e| e.id == exchange_id)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "exchange was not found"))?;
+
+    log_view(&app.sql, &id, ViewSource::Api, Some(&id.user_id), 1).await?;
+
+    Ok(Json(exchange.compressed()))
+}
+
+#[derive(serde::Deserialize)]
+pub(in crate::webserver) struct Delta {
+    /// The revision the client last synced, or `None` to fetch every exchange
+    since: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+pub(in crate::webserver) struct DeltaResponse {
+    revision: i64,
+    /// The number of exchanges in the thread. Clients truncate their copy to this length.
+    total: usize,
+    changed: Vec<ChangedExchange>,
+}
+
+#[derive(serde::Serialize)]
+pub(in crate::webserver) struct ChangedExchange {
+    index: usize,
+    exchange: Exchange,
+}
+
+/// Fetch the exchanges that were added or changed since a revision of a thread.
+pub(in crate::webserver) async fn delta(
+    Path(thread_id): Path<uuid::Uuid>,
+    Query(params): Query<Delta>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+) -> webserver::Result<Json<DeltaResponse>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("missing user ID"))?
+        .to_owned();
+
+    let id = ConversationId { thread_id, user_id };
+    let thread_id = thread_id.to_string();
+    let row = sqlx::query!(
+        "SELECT exchanges, exchanges_zstd, exchange_count, revision, exchange_revisions
+        FROM conversations
+        WHERE user_id = ? AND thread_id = ? AND deleted_at IS NULL",
+        id.user_id,
+        thread_id,
+    )
+    .fetch_optional(&*app.sql)
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "thread was not found"))?;
+
+    // A client that is up to date doesn't need the exchanges to be decompressed.
+    if matches!(params.since, Some(since) if since >= row.revision) {
+        return Ok(Json(DeltaResponse {
+            revision: row.revision,
+            total: row.exchange_count as usize,
+            changed: Vec::new(),
+        }));
+    }
+
+    let revisions = serde_json::from_str::<Vec<ExchangeRevision>>(&row.exchange_revisions)
+        .map_err(Error::internal)?;
+    let exchanges = deserialize_exchanges(row.exchanges, row.exchanges_zstd.as_deref())
+        .map_err(Error::internal)?;
+
+    let total = exchanges.len();
+    let changed = exchanges
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| {
+            // Conversations stored before revisions were introduced have none.
+            let revision = revisions.get(*index).map_or(0, |r| r.revision);
+            params.since.map_or(true, |since| revision > since)
+        })
+        .map(|(index, exchange)| ChangedExchange {
+            index,
+            exchange: exchange.compressed(),
+        })
+        .collect::<Vec<_>>();
+
+    log_view(
+        &app.sql,
+        &id,
+        ViewSource::Api,
+        Some(&id.user_id),
+        changed.len(),
+    )
+    .await?;
+
+    Ok(Json(DeltaResponse {
+        revision: row.revision,
+        total,
+        changed,
+    }))
+}
+
+/// The range of exchanges to return out of `len`, with `to` capped at the end of the thread.
+fn clamp_range(len: usize, from: usize, to: Option<usize>) -> Result<std::ops::Range<usize>> {
+    let to = to.unwrap_or(len).min(len);
+    if from > to {
+        anyhow::bail!("`from` must not be after `to`");
+    }
+
+    Ok(from..to)
+}
+
 pub async fn store(db: &SqlDb, id: ConversationId, conversation: Conversation) -> Result<()> {
+    store_with_parent(db, id, conversation, None).await
+}
+
+/// Like `store`, but also link the conversation to the one it was forked from.
+///
+/// Without a `parent`, the link of a conversation that is stored again is kept.
+pub async fn store_with_parent(
+    db: &SqlDb,
+    id: ConversationId,
+    conversation: Conversation,
+    parent: Option<Fork>,
+) -> Result<()> {
     info!("writing conversation {}-{}", id.user_id, id.thread_id);
     let mut transaction = db.begin().await?;
 
     // Delete the old conversation for simplicity. This also deletes all its messages.
+    //
+    // A deleted conversation that is answered in again is replaced by a new one.
+    //
+    // We keep the creation time and list ordering of the original. The update time is only
+    // bumped when an exchange was added, not when existing exchanges are amended.
     let (user_id, thread_id) = (id.user_id.clone(), id.thread_id.to_string());
+    let previous = sqlx::query! {
+        "SELECT created_at, updated_at, pinned, sort_order, title, title_generated, \
+            exchange_count, revision, exchange_revisions, forked_from, forked_at \
+            FROM conversations \
+            WHERE user_id = ? AND thread_id = ? AND deleted_at IS NULL",
+        user_id,
+        thread_id,
+    }
+    .fetch_optional(&mut transaction)
+    .await?;
+
     sqlx::query! {
         "DELETE FROM conversations \
             WHERE user_id = ? AND thread_id = ?",
@@ -152,26 +997,94 @@ pub async fn store(db: &SqlDb, id: ConversationId, conversation: Conversation) -
 
     let (repo_ref, exchanges) = conversation;
     let repo_ref = repo_ref.to_string();
-    let title = exchanges
-        .first()
-        .and_then(|list| list.query())
-        .and_then(|q| q.split('\n').next().map(|s| s.to_string()))
-        .context("couldn't find conversation title")?;
 
-    let exchanges = serde_json::to_string(&exchanges)?;
-    sqlx::query! {
+    let (created_at, updated_at, pinned, sort_order) = match &previous {
+        Some(row) => {
+            let updated_at = if row.exchange_count < exchanges.len() as i64 {
+                None
+            } else {
+                Some(row.updated_at)
+            };
+
+            (Some(row.created_at), updated_at, row.pinned, row.sort_order)
+        }
+        None => (None, None, false, None),
+    };
+
+    let title = match &previous {
+        Some(row) if row.title_generated => row.title.clone(),
+        _ => exchanges
+            .first()
+            .and_then(|list| list.query())
+            .and_then(|q| q.split('\n').next().map(|s| s.to_string()))
+            .context("couldn't find conversation title")?,
+    };
+    let title_generated = matches!(&previous, Some(row) if row.title_generated);
+
+    let (forked_from, forked_at) = match (parent, &previous) {
+        (Some(parent), _) => (
+            Some(parent.thread_id.to_string()),
+            Some(parent.exchange_count),
+        ),
+        (None, Some(row)) => (row.forked_from.clone(), row.forked_at),
+        (None, None) => (None, None),
+    };
+
+    let (revision, exchange_revisions) = match &previous {
+        Some(row) => revise(
+            row.revision,
+            &serde_json::from_str::<Vec<ExchangeRevision>>(&row.exchange_revisions)?,
+            &exchanges,
+        )?,
+        None => revise(0, &[], &exchanges)?,
+    };
+    let exchange_revisions = serde_json::to_string(&exchange_revisions)?;
+
+    let exchange_count = exchanges.len() as i64;
+    let answer_latencies_ms = serde_json::to_string(
+        &exchanges
+            .iter()
+            .filter_map(Exchange::answer_latency)
+            .map(|latency| latency.num_milliseconds())
+            .collect::<Vec<_>>(),
+    )?;
+    let content = search::content(&exchanges);
+    let exchanges = compress(&serde_json::to_string(&exchanges)?)?;
+
+    let id = sqlx::query! {
         "INSERT INTO conversations (\
-            user_id, thread_id, repo_ref, title, exchanges, created_at\
+            user_id, thread_id, repo_ref, title, exchanges, exchanges_zstd, \
+            exchange_count, answer_latencies_ms, revision, exchange_revisions, \
+            created_at, updated_at, pinned, sort_order, title_generated, forked_from, forked_at\
             ) \
-            VALUES (?, ?, ?, ?, ?, strftime('%s', 'now'))",
+            VALUES (\
+                ?, ?, ?, ?, '', ?, ?, ?, ?, ?, \
+                COALESCE(?, strftime('%s', 'now')), COALESCE(?, strftime('%s', 'now')), ?, ?, ?, \
+                ?, ?\
+            )",
         user_id,
         thread_id,
         repo_ref,
         title,
         exchanges,
+        exchange_count,
+        answer_latencies_ms,
+        revision,
+        exchange_revisions,
+        created_at,
+        updated_at,
+        pinned,
+        sort_order,
+        title_generated,
+        forked_from,
+        forked_at,
     }
     .execute(&mut transaction)
-    .await?;
+    .await?
+    .last_insert_rowid();
+
+    // The previous row went out of the index with its deletion.
+    search::index(&mut transaction, id, &title, &content).await?;
 
     transaction.commit().await?;
 
@@ -182,8 +1095,8 @@ pub async fn load(db: &SqlDb, id: &ConversationId) -> Result<Option<Conversation
     let (user_id, thread_id) = (id.user_id.clone(), id.thread_id.to_string());
 
     let row = sqlx::query! {
-        "SELECT repo_ref, exchanges FROM conversations \
-         WHERE user_id = ? AND thread_id = ?",
+        "SELECT repo_ref, exchanges, exchanges_zstd FROM conversations \
+         WHERE user_id = ? AND thread_id = ? AND deleted_at IS NULL",
         user_id,
         thread_id,
     }
@@ -195,8 +1108,393 @@ pub async fn load(db: &SqlDb, id: &ConversationId) -> Result<Option<Conversation
         None => return Ok(None),
     };
 
-    let repo_ref = RepoRef::from_str(&row.repo_ref).context("failed to parse repo ref")?;
-    let exchanges = serde_json::from_str(&row.exchanges)?;
+    let Some(repo_ref) =
+        webserver::tenant::stored_repo(&row.repo_ref).context("failed to parse repo ref")?
+    else {
+        return Ok(None);
+    };
+    let exchanges = deserialize_exchanges(row.exchanges, row.exchanges_zstd.as_deref())?;
 
     Ok(Some((repo_ref, exchanges)))
 }
+
+/// Where a conversation was read outside of its workspaces, for `/admin/conversation-views`.
+pub(crate) enum ViewSource {
+    /// A shared link, which is opened without signing in
+    Link {
+        share_id: i64,
+    },
+    Export,
+    /// The endpoints that page through the exchanges of a conversation
+    Api,
+}
+
+impl ViewSource {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Link { .. } => "link",
+            Self::Export => "export",
+            Self::Api => "api",
+        }
+    }
+}
+
+/// Log a read of a conversation, so that admins can review who saw what.
+pub(crate) async fn log_view(
+    db: &SqlDb,
+    id: &ConversationId,
+    source: ViewSource,
+    viewer_id: Option<&str>,
+    exchanges_seen: usize,
+) -> Result<()> {
+    let thread_id = id.thread_id.to_string();
+    let share_id = match source {
+        ViewSource::Link { share_id } => Some(share_id),
+        _ => None,
+    };
+    let (source, exchanges_seen) = (source.as_str(), exchanges_seen as i64);
+
+    sqlx::query!(
+        "INSERT INTO conversation_views
+            (user_id, thread_id, viewer_id, share_id, source, exchanges_seen)
+        VALUES (?, ?, ?, ?, ?, ?)",
+        id.user_id,
+        thread_id,
+        viewer_id,
+        share_id,
+        source,
+        exchanges_seen,
+    )
+    .execute(db.as_ref())
+    .await?;
+
+    Ok(())
+}
+
+/// The revision at which an exchange last changed.
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+struct ExchangeRevision {
+    id: uuid::Uuid,
+    hash: String,
+    revision: i64,
+}
+
+/// Bump the revision of a conversation if its exchanges changed, marking the exchanges that
+/// changed with the new revision.
+fn revise(
+    revision: i64,
+    previous: &[ExchangeRevision],
+    exchanges: &[Exchange],
+) -> Result<(i64, Vec<ExchangeRevision>)> {
+    let next = revision + 1;
+
+    let mut changed = previous.len() != exchanges.len();
+    let mut revisions = Vec::with_capacity(exchanges.len());
+    for (i, exchange) in exchanges.iter().enumerate() {
+        let hash = blake3::hash(serde_json::to_string(exchange)?.as_bytes()).to_string();
+        let revision = match previous.get(i) {
+            Some(prev) if prev.id == exchange.id && prev.hash == hash => prev.revision,
+            _ => {
+                changed = true;
+                next
+            }
+        };
+
+        revisions.push(ExchangeRevision {
+            id: exchange.id,
+            hash,
+            revision,
+        });
+    }
+
+    Ok((if changed { next } else { revision }, revisions))
+}
+
+fn compress(json: &str) -> Result<Vec<u8>> {
+    Ok(zstd::encode_all(json.as_bytes(), ZSTD_LEVEL)?)
+}
+
+/// The JSON of stored exchanges, from whichever column it is stored in.
+///
+/// Conversations that were stored before compression was introduced keep plain JSON in
+/// `exchanges`, until [`compress_stored`] gets to them.
+pub(crate) fn exchanges_json(json: String, compressed: Option<&[u8]>) -> Result<String> {
+    match compressed {
+        Some(compressed) => {
+            let json = zstd::decode_all(compressed).context("failed to decompress exchanges")?;
+            Ok(String::from_utf8(json)?)
+        }
+        None => Ok(json),
+    }
+}
+
+pub(crate) fn deserialize_exchanges(
+    json: String,
+    compressed: Option<&[u8]>,
+) -> Result<Vec<Exchange>> {
+    let json = exchanges_json(json, compressed)?;
+    serde_json::from_str(&json).context("couldn't deserialize exchange list")
+}
+
+/// Compress the exchanges of conversations that were stored as plain JSON, returning how many
+/// conversations were compressed.
+pub(crate) async fn compress_stored(db: &SqlDb) -> Result<usize> {
+    let mut compressed = 0;
+
+    loop {
+        let rows = sqlx::query!(
+            "SELECT id, exchanges FROM conversations WHERE exchanges_zstd IS NULL LIMIT ?",
+            COMPRESS_BATCH_SIZE,
+        )
+        .fetch_all(db.as_ref())
+        .await?;
+
+        if rows.is_empty() {
+            return Ok(compressed);
+        }
+
+        for row in rows {
+            let exchanges = compress(&row.exchanges)?;
+            sqlx::query!(
+                "UPDATE conversations SET exchanges = '', exchanges_zstd = ? WHERE id = ?",
+                exchanges,
+                row.id,
+            )
+            .execute(db.as_ref())
+            .await?;
+
+            compressed += 1;
+        }
+    }
+}
+
+/// Flag the citations of conversations about a repository as unverifiable, returning how many
+/// conversations were changed.
+///
+/// This is for repositories that were removed, whose files can't be opened from the citations
+/// any more. The conversations themselves are kept.
+pub(crate) async fn unverify_citations(db: &SqlDb, repo_ref: &str, reason: &str) -> Result<usize> {
+    let rows = sqlx::query!(
+        "SELECT id, exchanges, exchanges_zstd, revision, exchange_revisions
+        FROM conversations WHERE repo_ref = ?",
+        repo_ref,
+    )
+    .fetch_all(db.as_ref())
+    .await?;
+
+    let mut changed = 0;
+    for row in rows {
+        let mut exchanges = deserialize_exchanges(row.exchanges, row.exchanges_zstd.as_deref())?;
+        if !unverify(&mut exchanges, reason) {
+            continue;
+        }
+
+        let (revision, exchange_revisions) = revise(
+            row.revision,
+            &serde_json::from_str::<Vec<ExchangeRevision>>(&row.exchange_revisions)?,
+            &exchanges,
+        )?;
+        let exchange_revisions = serde_json::to_string(&exchange_revisions)?;
+        let exchanges = compress(&serde_json::to_string(&exchanges)?)?;
+
+        sqlx::query!(
+            "UPDATE conversations
+            SET exchanges = '', exchanges_zstd = ?, revision = ?, exchange_revisions = ?
+            WHERE id = ?",
+            exchanges,
+            revision,
+            exchange_revisions,
+            row.id,
+        )
+        .execute(db.as_ref())
+        .await?;
+
+        changed += 1;
+    }
+
+    Ok(changed)
+}
+
+/// Flag every citation of the exchanges as unverifiable, returning whether any was changed.
+fn unverify(exchanges: &mut [Exchange], reason: &str) -> bool {
+    let mut changed = false;
+    for citation in exchanges.iter_mut().flat_map(|e| &mut e.citations) {
+        let status = CitationStatus::Unverifiable {
+            reason: reason.to_owned(),
+        };
+
+        if citation.status != status {
+            citation.status = status;
+            changed = true;
+        }
+    }
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::exchange::{Citation, Update};
+
+    #[test]
+    fn only_revises_changed_exchanges() {
+        let mut exchanges = vec![Exchange::default(), Exchange::default()];
+        exchanges[0].id = uuid::Uuid::new_v4();
+        exchanges[1].id = uuid::Uuid::new_v4();
+
+        let (revision, revisions) = revise(0, &[], &exchanges).unwrap();
+        assert_eq!(revision, 1);
+        assert!(revisions.iter().all(|r| r.revision == 1));
+
+        // Storing the same exchanges again is not a change.
+        let (revision, revisions) = revise(revision, &revisions, &exchanges).unwrap();
+        assert_eq!(revision, 1);
+
+        exchanges[1].apply_update(Update::Article("the answer".to_owned()));
+        let (revision, revisions) = revise(revision, &revisions, &exchanges).unwrap();
+        assert_eq!(revision, 2);
+        assert_eq!(
+            revisions.iter().map(|r| r.revision).collect::<Vec<_>>(),
+            [1, 2]
+        );
+
+        // Truncating the thread is a change, even though no remaining exchange changed.
+        let (revision, revisions) = revise(revision, &revisions, &exchanges[..1]).unwrap();
+        assert_eq!(revision, 3);
+        assert_eq!(revisions.len(), 1);
+    }
+
+    #[test]
+    fn clamps_exchange_ranges() {
+        assert_eq!(clamp_range(5, 0, None).unwrap(), 0..5);
+        assert_eq!(clamp_range(5, 2, Some(4)).unwrap(), 2..4);
+        assert_eq!(clamp_range(5, 3, Some(10)).unwrap(), 3..5);
+        assert_eq!(clamp_range(5, 5, None).unwrap(), 5..5);
+        assert!(clamp_range(5, 6, None).is_err());
+        assert!(clamp_range(5, 3, Some(2)).is_err());
+    }
+
+    #[test]
+    fn reads_plain_and_compressed_exchanges() {
+        let json = r#"[{"id":"00000000-0000-0000-0000-000000000000"}]"#.to_owned();
+        let compressed = compress(&json).unwrap();
+
+        assert_eq!(exchanges_json(json.clone(), None).unwrap(), json);
+        assert_eq!(
+            exchanges_json(String::new(), Some(&compressed)).unwrap(),
+            json
+        );
+    }
+
+    fn exchanges(queries: &[&str]) -> Vec<Exchange> {
+        queries
+            .iter()
+            .map(|q| {
+                let query = crate::query::parser::parse_nl(q).unwrap().into_owned();
+                Exchange::new(uuid::Uuid::new_v4(), query)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn forks_up_to_an_exchange() {
+        let mut forked = exchanges(&["first", "second", "third"]);
+        let ids = forked.iter().map(|e| e.id).collect::<Vec<_>>();
+
+        fork_at(&mut forked, 1).unwrap();
+        assert_eq!(forked.iter().map(|e| e.id).collect::<Vec<_>>(), ids[..2]);
+
+        let mut forked = exchanges(&["first", "second"]);
+        let err = fork_at(&mut forked, 2).unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+        assert_eq!(forked.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_parent_of_a_fork() {
+        let db: SqlDb = std::sync::Arc::new(crate::db::test_pool().await);
+        let repo_ref: RepoRef = "github.com/org/repo".parse().unwrap();
+        let id = ConversationId {
+            thread_id: uuid::Uuid::new_v4(),
+            user_id: "user".to_owned(),
+        };
+        let parent = uuid::Uuid::new_v4();
+
+        let fork = Fork {
+            thread_id: parent,
+            exchange_count: 1,
+        };
+        let conversation = (repo_ref.clone(), exchanges(&["first"]));
+        store_with_parent(&db, id.clone(), conversation, Some(fork))
+            .await
+            .unwrap();
+
+        // Answering in the fork stores it again, without a parent
+        let conversation = (repo_ref, exchanges(&["first", "second"]));
+        store(&db, id.clone(), conversation).await.unwrap();
+
+        let (forked_from, forked_at): (Option<String>, Option<i64>) =
+            sqlx::query_as("SELECT forked_from, forked_at FROM conversations WHERE thread_id = ?")
+                .bind(id.thread_id.to_string())
+                .fetch_one(&*db)
+                .await
+                .unwrap();
+
+        assert_eq!(forked_from, Some(parent.to_string()));
+        assert_eq!(forked_at, Some(1));
+    }
+
+    #[test]
+    fn unverifies_citations_once() {
+        let mut exchanges = exchanges(&["first", "second"]);
+        exchanges[1].citations.push(Citation {
+            path: "src/main.rs".to_owned(),
+            start_line: 0,
+            end_line: 2,
+            status: CitationStatus::Verified,
+        });
+
+        assert!(unverify(&mut exchanges, "the repository was removed"));
+        assert_eq!(
+            exchanges[1].citations[0].status,
+            CitationStatus::Unverifiable {
+                reason: "the repository was removed".to_owned()
+            }
+        );
+
+        // Exchanges that are already flagged aren't stored again.
+        assert!(!unverify(&mut exchanges, "the repository was removed"));
+    }
+
+    #[tokio::test]
+    async fn logs_views_of_links_without_a_viewer() {
+        let db: SqlDb = std::sync::Arc::new(crate::db::test_pool().await);
+        let id = ConversationId {
+            thread_id: uuid::Uuid::new_v4(),
+            user_id: "owner".to_owned(),
+        };
+
+        log_view(&db, &id, ViewSource::Link { share_id: 7 }, None, 3)
+            .await
+            .unwrap();
+        log_view(&db, &id, ViewSource::Export, Some("owner"), 1)
+            .await
+            .unwrap();
+
+        let views: Vec<(Option<i64>, Option<String>, Option<i64>, String, i64)> = sqlx::query_as(
+            "SELECT workspace_id, viewer_id, share_id, source, exchanges_seen
+            FROM conversation_views ORDER BY id",
+        )
+        .fetch_all(db.as_ref())
+        .await
+        .unwrap();
+        assert_eq!(
+            views,
+            [
+                (None, None, Some(7), "link".to_owned(), 3),
+                (None, Some("owner".to_owned()), None, "export".to_owned(), 1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,343 @@
+//! Conversations as portable documents, to move them to another repository or bloop instance.
+//!
+//! JSON documents carry the full exchanges and can be imported again. They are versioned, and
+//! every version that was ever exported stays importable. Markdown is only meant for reading, and
+//! SARIF for CI systems to annotate the code that answers cite.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{ConversationId, Exchange, ViewSource};
+use crate::{
+    repo::RepoRef,
+    webserver::{
+        self,
+        middleware::User,
+        tenant::{self, Tenant},
+        Error, ErrorKind,
+    },
+    Application,
+};
+
+/// The version of the document format, to be bumped whenever older versions can't be read as is.
+const VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct Document {
+    version: u32,
+    title: String,
+    /// The repository the conversation is about, which it is imported into by default
+    repository: RepoRef,
+    exported_at: DateTime<Utc>,
+    exchanges: Vec<Exchange>,
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub(in crate::webserver) enum Format {
+    #[default]
+    Json,
+    #[serde(alias = "markdown")]
+    Md,
+    Sarif,
+}
+
+#[derive(Deserialize)]
+pub(in crate::webserver) struct Export {
+    #[serde(default)]
+    format: Format,
+}
+
+/// Download a conversation with all of its exchanges.
+pub(in crate::webserver) async fn export(
+    Path(thread_id): Path<uuid::Uuid>,
+    Query(params): Query<Export>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+) -> webserver::Result<Response> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("missing user ID"))?
+        .to_owned();
+
+    let thread = thread_id.to_string();
+    let title = sqlx::query_scalar! {
+        "SELECT title FROM conversations \
+         WHERE user_id = ? AND thread_id = ? AND deleted_at IS NULL",
+        user_id,
+        thread,
+    }
+    .fetch_optional(app.sql.as_ref())
+    .await
+    .map_err(Error::internal)?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "thread was not found"))?;
+
+    let id = ConversationId { thread_id, user_id };
+    let (repository, exchanges) = super::load(&app.sql, &id)
+        .await?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "thread was not found"))?;
+
+    super::log_view(
+        &app.sql,
+        &id,
+        ViewSource::Export,
+        Some(&id.user_id),
+        exchanges.len(),
+    )
+    .await?;
+
+    let document = Document {
+        version: VERSION,
+        title,
+        repository,
+        exported_at: Utc::now(),
+        exchanges,
+    };
+
+    let (content_type, extension, body) = match params.format {
+        Format::Json => (
+            "application/json",
+            "json",
+            serde_json::to_string_pretty(&document).map_err(Error::internal)?,
+        ),
+        Format::Md => ("text/markdown; charset=utf-8", "md", markdown(&document)),
+        Format::Sarif => {
+            let revision = document
+                .exchanges
+                .iter()
+                .rev()
+                .find_map(|e| e.index_freshness.as_ref()?.indexed_commit.as_deref());
+
+            let log = super::sarif::log(&document.repository, revision, &document.exchanges);
+            (
+                "application/sarif+json",
+                "sarif",
+                serde_json::to_string_pretty(&log).map_err(Error::internal)?,
+            )
+        }
+    };
+
+    let disposition = format!(
+        "attachment; filename=\"{}.{extension}\"",
+        file_stem(&document.title)
+    );
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_owned()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+#[derive(Deserialize)]
+pub(in crate::webserver) struct Import {
+    /// Import into this repository, rather than the one the conversation was exported from
+    repo_ref: Option<RepoRef>,
+}
+
+/// Restore an exported JSON document as a new conversation of the user, returning its thread ID.
+pub(in crate::webserver) async fn import(
+    Extension(user): Extension<User>,
+    tenant: Option<Extension<Tenant>>,
+    Query(params): Query<Import>,
+    State(app): State<Application>,
+    Json(document): Json<serde_json::Value>,
+) -> webserver::Result<String> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("missing user ID"))?
+        .to_owned();
+
+    let document = upgrade(document)?;
+    let repo_ref = params.repo_ref.unwrap_or(document.repository);
+
+    if !tenant::allows(&tenant, &repo_ref) || !app.repo_pool.contains_async(&repo_ref).await {
+        return Err(Error::new(ErrorKind::NotFound, "Can't find repository"));
+    }
+
+    if document
+        .exchanges
+        .first()
+        .and_then(Exchange::query)
+        .is_none()
+    {
+        return Err(Error::user("the document has no exchanges"));
+    }
+
+    let thread_id = uuid::Uuid::new_v4();
+    let id = ConversationId {
+        thread_id,
+        user_id: user_id.clone(),
+    };
+    super::store(&app.sql, id, (repo_ref, document.exchanges)).await?;
+
+    // The exported title may have been renamed or generated, so it is kept over the first query
+    let thread = thread_id.to_string();
+    sqlx::query! {
+        "UPDATE conversations SET title = ?, title_generated = TRUE \
+         WHERE user_id = ? AND thread_id = ?",
+        document.title,
+        user_id,
+        thread,
+    }
+    .execute(app.sql.as_ref())
+    .await
+    .map_err(Error::internal)?;
+
+    Ok(thread)
+}
+
+/// Read a document of any version that was ever exported.
+fn upgrade(document: serde_json::Value) -> webserver::Result<Document> {
+    let version = document
+        .get("version")
+        .and_then(serde_json::Value::as_u64)
+        .ok_or_else(|| Error::user("not an exported conversation"))?;
+
+    match version {
+        1 => serde_json::from_value(document)
+            .map_err(|err| Error::user(format!("malformed conversation: {err}"))),
+        _ => Err(Error::user(format!(
+            "the conversation was exported by a newer version of bloop, in version {version}"
+        ))),
+    }
+}
+
+fn markdown(document: &Document) -> String {
+    let mut out = format!(
+        "# {}\n\n_A conversation about `{}`, exported from bloop on {}._\n",
+        document.title,
+        document.repository.display_name(),
+        document.exported_at.format("%Y-%m-%d"),
+    );
+
+    for exchange in &document.exchanges {
+        if let Some(query) = exchange.query() {
+            out += &format!("\n## {}\n", query.replace('\n', " "));
+        }
+
+        if let Some(answer) = exchange.answer() {
+            out += &format!("\n{}\n", answer.trim_end());
+        }
+
+        if !exchange.code_chunks.is_empty() {
+            out += "\n### Cited code\n";
+        }
+
+        for chunk in &exchange.code_chunks {
+            // A fence longer than any in the snippet, so that it can't be closed early
+            let fence = "`".repeat(longest_backtick_run(&chunk.snippet).max(2) + 1);
+            out += &format!(
+                "\n`{}`, lines {}-{}\n\n{fence}\n{}\n{fence}\n",
+                chunk.path,
+                chunk.start_line,
+                chunk.end_line,
+                chunk.snippet.trim_end_matches('\n'),
+            );
+        }
+    }
+
+    out
+}
+
+fn longest_backtick_run(text: &str) -> usize {
+    text.split(|c| c != '`').map(str::len).max().unwrap_or(0)
+}
+
+/// A file name made of the title, that is safe to use on any file system.
+fn file_stem(title: &str) -> String {
+    // Header values have to be ASCII
+    let stem = title
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+        .chars()
+        .take(64)
+        .collect::<String>();
+
+    if stem.is_empty() {
+        "conversation".to_owned()
+    } else {
+        stem.trim_end_matches('-').to_lowercase()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        agent::exchange::{CodeChunk, Update},
+        query::parser,
+    };
+
+    #[test]
+    fn renders_markdown() {
+        let query = parser::parse_nl("where is foo").unwrap().into_owned();
+        let mut exchange = Exchange::new(uuid::Uuid::nil(), query);
+        exchange.apply_update(Update::Article(
+            "In [`foo`](src/foo.rs#L1-L2).\n".to_owned(),
+        ));
+        exchange.code_chunks.push(CodeChunk {
+            path: "src/foo.rs".to_owned(),
+            alias: 0,
+            snippet: "/// ```\nfn foo() {}\n".to_owned(),
+            start_line: 1,
+            end_line: 2,
+            start_byte: None,
+            end_byte: None,
+        });
+
+        let document = Document {
+            version: VERSION,
+            title: "Finding foo".to_owned(),
+            repository: "github.com/o/r".parse().unwrap(),
+            exported_at: "2023-10-16T12:00:00Z".parse().unwrap(),
+            exchanges: vec![exchange],
+        };
+
+        assert_eq!(
+            markdown(&document),
+            "# Finding foo\n\n\
+             _A conversation about `o/r`, exported from bloop on 2023-10-16._\n\
+             \n## where is foo\n\
+             \nIn [`foo`](src/foo.rs#L1-L2).\n\
+             \n### Cited code\n\
+             \n`src/foo.rs`, lines 1-2\n\n````\n/// ```\nfn foo() {}\n````\n"
+        );
+    }
+
+    #[test]
+    fn reads_known_versions_only() {
+        assert!(upgrade(serde_json::json!({ "title": "no version" })).is_err());
+        assert!(upgrade(serde_json::json!({ "version": 2 })).is_err());
+
+        let document = upgrade(serde_json::json!({
+            "version": 1,
+            "title": "t",
+            "repository": "github.com/o/r",
+            "exported_at": "2023-10-16T12:00:00Z",
+            "exchanges": [],
+        }))
+        .unwrap();
+        assert_eq!(document.repository.display_name(), "o/r");
+    }
+
+    #[test]
+    fn makes_safe_file_names() {
+        assert_eq!(
+            file_stem("How does `Foo::bar` work?"),
+            "how-does-foo-bar-work"
+        );
+        assert_eq!(file_stem("../.."), "conversation");
+    }
+}
@@ -0,0 +1,139 @@
+//! Earlier questions that a new one repeats.
+//!
+//! Candidates come from the full-text index, and are then compared question by question, by the
+//! distinctive words they share. Only answered questions count, as an unanswered one doesn't save
+//! asking again.
+
+use std::collections::HashSet;
+
+use anyhow::Result;
+use tracing::warn;
+
+use super::{deserialize_exchanges, search};
+use crate::{db::SqlDb, repo::RepoRef};
+
+/// The share of distinctive words two questions have in common to be taken for the same one.
+const MIN_SIMILARITY: f32 = 0.75;
+
+/// How many conversations to compare questions with, by their full-text rank.
+const CANDIDATES: i64 = 20;
+
+/// The most duplicates to point to.
+const MAX_DUPLICATES: usize = 3;
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub(in crate::webserver) struct Duplicate {
+    pub thread_id: String,
+    pub exchange_id: uuid::Uuid,
+    pub title: String,
+    pub query: String,
+    pub similarity: f32,
+}
+
+/// Answered questions of the user about the same repository that are very much like `q`, most
+/// similar first.
+pub(in crate::webserver) async fn find(
+    db: &SqlDb,
+    user_id: &str,
+    repo_ref: &RepoRef,
+    q: &str,
+) -> Result<Vec<Duplicate>> {
+    let Some(expr) = search::related_expr(q) else {
+        return Ok(vec![]);
+    };
+
+    let repo_ref = repo_ref.to_string();
+    let rows = sqlx::query! {
+        "SELECT c.thread_id, c.title, c.exchanges, c.exchanges_zstd \
+         FROM conversations_fts f \
+         INNER JOIN conversations c ON c.id = f.rowid \
+         WHERE conversations_fts MATCH ? AND c.user_id = ? AND c.repo_ref = ? \
+            AND c.deleted_at IS NULL \
+         ORDER BY bm25(conversations_fts, 2.0, 1.0) \
+         LIMIT ?",
+        expr,
+        user_id,
+        repo_ref,
+        CANDIDATES,
+    }
+    .fetch_all(db.as_ref())
+    .await?;
+
+    let words = distinctive(q);
+    let mut duplicates = vec![];
+
+    for row in rows {
+        let exchanges = match deserialize_exchanges(row.exchanges, row.exchanges_zstd.as_deref()) {
+            Ok(exchanges) => exchanges,
+            Err(err) => {
+                warn!(
+                    ?err,
+                    thread_id = %row.thread_id,
+                    "failed to read conversation"
+                );
+                continue;
+            }
+        };
+
+        let best = exchanges
+            .iter()
+            .filter(|exchange| exchange.answer().is_some())
+            .filter_map(|exchange| {
+                let query = exchange.query()?;
+                let similarity = similarity(&words, &distinctive(&query));
+                Some((exchange.id, query, similarity))
+            })
+            .max_by(|a, b| a.2.total_cmp(&b.2));
+
+        if let Some((exchange_id, query, similarity)) = best {
+            if similarity >= MIN_SIMILARITY {
+                duplicates.push(Duplicate {
+                    thread_id: row.thread_id,
+                    exchange_id,
+                    title: row.title,
+                    query,
+                    similarity,
+                });
+            }
+        }
+    }
+
+    duplicates.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+    duplicates.truncate(MAX_DUPLICATES);
+    Ok(duplicates)
+}
+
+fn distinctive(text: &str) -> HashSet<String> {
+    search::words(text)
+        .into_iter()
+        .filter(|word| word.chars().count() > 3)
+        .collect()
+}
+
+/// The Jaccard index of two sets of words.
+fn similarity(a: &HashSet<String>, b: &HashSet<String>) -> f32 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+
+    a.intersection(b).count() as f32 / union as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rephrasings_are_similar() {
+        let asked = distinctive("How does the repo index get synced?");
+
+        let rephrased = distinctive("how is the repo index synced");
+        assert!(similarity(&asked, &rephrased) >= MIN_SIMILARITY);
+
+        let different = distinctive("How is the repo index built?");
+        assert!(similarity(&asked, &different) < MIN_SIMILARITY);
+
+        assert_eq!(similarity(&distinctive("why?"), &distinctive("how?")), 0.0);
+    }
+}
@@ -0,0 +1,187 @@
+//! Conversations as SARIF, for code scanning and CI systems to annotate the code answers cite.
+//!
+//! Every line of code that an answer links to becomes a result at that location, with the
+//! sentence that cited it as the message. Results are notes, as answers don't rate what they
+//! point to.
+
+use std::collections::HashSet;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::{json, Value};
+
+use super::Exchange;
+use crate::{
+    repo::{Backend, RepoRef},
+    webserver::answer::export::LINK,
+};
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+const RULE_ID: &str = "bloop/citation";
+
+/// The fragment of a link to lines, like `L1-L5`, `L26-53` or `L138`.
+static LINES: Lazy<Regex> = Lazy::new(|| Regex::new(r"^L(\d+)(?:-L?(\d+))?$").unwrap());
+
+/// A line range that an answer cited.
+#[derive(Debug, PartialEq)]
+struct Citation {
+    path: String,
+    start_line: usize,
+    end_line: Option<usize>,
+    /// The line of the answer that the link is on
+    context: String,
+}
+
+/// A SARIF log with a single run, of every citation in the answers of the exchanges.
+///
+/// `revision` is the commit the answers were based on, if known.
+pub(super) fn log(repository: &RepoRef, revision: Option<&str>, exchanges: &[Exchange]) -> Value {
+    let results = exchanges
+        .iter()
+        .flat_map(|exchange| {
+            let query = exchange.query().unwrap_or_default();
+            citations(exchange.answer().unwrap_or_default())
+                .into_iter()
+                .map(move |citation| result(&query, citation))
+        })
+        .collect::<Vec<_>>();
+
+    let mut run = json!({
+        "tool": {
+            "driver": {
+                "name": "bloop",
+                "informationUri": "https://bloop.ai",
+                "version": env!("CARGO_PKG_VERSION"),
+                "rules": [{
+                    "id": RULE_ID,
+                    "name": "Citation",
+                    "shortDescription": { "text": "Code cited in an answer" },
+                    "defaultConfiguration": { "level": "note" },
+                }],
+            },
+        },
+        "results": results,
+    });
+
+    if let Some(revision) = revision {
+        run["versionControlProvenance"] = json!([{
+            "repositoryUri": repository_uri(repository),
+            "revisionId": revision,
+        }]);
+    }
+
+    json!({
+        "$schema": SCHEMA,
+        "version": "2.1.0",
+        "runs": [run],
+    })
+}
+
+fn result(query: &str, citation: Citation) -> Value {
+    let mut region = json!({ "startLine": citation.start_line });
+    if let Some(end_line) = citation.end_line {
+        region["endLine"] = end_line.into();
+    }
+
+    json!({
+        "ruleId": RULE_ID,
+        "level": "note",
+        "message": { "text": format!("{query}\n\n{}", citation.context) },
+        "locations": [{
+            "physicalLocation": {
+                "artifactLocation": { "uri": citation.path },
+                "region": region,
+            },
+        }],
+    })
+}
+
+/// Links to lines of files in an answer, outside of code blocks, every range only once.
+fn citations(answer: &str) -> Vec<Citation> {
+    let mut seen = HashSet::new();
+    let mut citations = vec![];
+    let mut in_code = false;
+
+    for line in answer.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+        }
+
+        if in_code {
+            continue;
+        }
+
+        for caps in LINK.captures_iter(line) {
+            let Some((path, fragment)) = caps[1].split_once('#') else {
+                continue;
+            };
+
+            if path.is_empty() || path.contains("://") || path.starts_with("mailto:") {
+                continue;
+            }
+
+            let Some(lines) = LINES.captures(fragment) else {
+                continue;
+            };
+
+            // SARIF lines start at 1, and a range can't end before it starts
+            let start_line = lines[1].parse::<usize>().unwrap_or(1).max(1);
+            let end_line = lines
+                .get(2)
+                .and_then(|end| end.as_str().parse::<usize>().ok())
+                .filter(|&end| end > start_line);
+
+            let path = path.trim_start_matches('/').to_owned();
+            if seen.insert((path.clone(), start_line, end_line)) {
+                citations.push(Citation {
+                    path,
+                    start_line,
+                    end_line,
+                    context: line.trim().to_owned(),
+                });
+            }
+        }
+    }
+
+    citations
+}
+
+fn repository_uri(repository: &RepoRef) -> String {
+    match repository.backend() {
+        Backend::Github => format!("https://github.com/{}", repository.name()),
+        Backend::Gitlab | Backend::Bitbucket => {
+            let (host, path) = repository.host_and_path();
+            format!("https://{host}/{path}")
+        }
+        Backend::Local => format!("file://{}", repository.name()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cites_linked_lines() {
+        let citations = citations(
+            "The compiler starts in [`main`](src/main.rs#L50-L78), via [`new`](/src/bar.rs#L26-53).\n\
+             ```md\n[`skipped`](src/foo.rs#L1)\n```\n\
+             See [`foo`](src/foo.rs#L138-L138), [`main`](src/main.rs#L50-L78) and [docs](https://docs.rs#L1).\n\
+             The whole [file](src/baz.rs) isn't a location.",
+        );
+
+        assert_eq!(
+            citations
+                .iter()
+                .map(|c| (c.path.as_str(), c.start_line, c.end_line))
+                .collect::<Vec<_>>(),
+            [
+                ("src/main.rs", 50, Some(78)),
+                ("src/bar.rs", 26, Some(53)),
+                ("src/foo.rs", 138, None),
+            ]
+        );
+        assert!(citations[2].context.starts_with("See [`foo`]"));
+    }
+}
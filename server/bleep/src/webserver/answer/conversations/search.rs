@@ -0,0 +1,153 @@
+//! The full-text index of conversations, in `conversations_fts`.
+//!
+//! Rows share their `rowid` with the `id` of the conversation. The text of a conversation is
+//! indexed whenever it is stored, and triggers take care of renames and deletions, so the index
+//! never has to be rebuilt.
+
+use anyhow::Result;
+use tracing::warn;
+
+use super::{exchanges_json, Exchange};
+use crate::db::SqlDb;
+
+/// How many conversations stored before the index existed to index at once.
+const INDEX_BATCH_SIZE: i64 = 100;
+
+/// Finding related conversations by every word of a long thread would match nearly anything.
+const MAX_RELATED_TERMS: usize = 32;
+
+/// The text of a conversation that can be searched for.
+pub(super) fn content(exchanges: &[Exchange]) -> String {
+    exchanges
+        .iter()
+        .flat_map(|exchange| [exchange.query(), exchange.answer().map(str::to_owned)])
+        .flatten()
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+pub(super) async fn index(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    id: i64,
+    title: &str,
+    content: &str,
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO conversations_fts (rowid, title, content) VALUES (?, ?, ?)",
+        id,
+        title,
+        content,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// A `MATCH` expression for conversations with every word of `q`, read as a prefix while the
+/// last word is still being typed.
+///
+/// Words are quoted, so that no input is taken for FTS5 syntax.
+pub(crate) fn search_expr(q: &str) -> Option<String> {
+    let words = words(q);
+    let (last, rest) = words.split_last()?;
+
+    let mut expr = rest
+        .iter()
+        .map(|word| format!("\"{word}\" "))
+        .collect::<String>();
+    expr.push_str(&format!("\"{last}\"*"));
+
+    Some(expr)
+}
+
+/// A `MATCH` expression for conversations with any of the distinctive words of `text`, which
+/// are ranked by how many they share.
+pub(super) fn related_expr(text: &str) -> Option<String> {
+    let mut seen = std::collections::HashSet::new();
+    let words = words(text)
+        .into_iter()
+        .filter(|word| word.chars().count() > 3)
+        .filter(|word| seen.insert(word.clone()))
+        .take(MAX_RELATED_TERMS)
+        .map(|word| format!("\"{word}\""))
+        .collect::<Vec<_>>();
+
+    (!words.is_empty()).then(|| words.join(" OR "))
+}
+
+pub(super) fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Index the conversations that were stored before the index existed, returning how many were
+/// indexed.
+pub(crate) async fn index_stored(db: &SqlDb) -> Result<usize> {
+    let mut indexed = 0;
+
+    loop {
+        let rows = sqlx::query!(
+            "SELECT id, title, exchanges, exchanges_zstd FROM conversations
+            WHERE id NOT IN (SELECT rowid FROM conversations_fts)
+            LIMIT ?",
+            INDEX_BATCH_SIZE,
+        )
+        .fetch_all(db.as_ref())
+        .await?;
+
+        if rows.is_empty() {
+            return Ok(indexed);
+        }
+
+        let mut transaction = db.begin().await?;
+        for row in rows {
+            // A conversation that can't be read is still indexed by title, to not retry it forever
+            let content = exchanges_json(row.exchanges, row.exchanges_zstd.as_deref())
+                .and_then(|json| Ok(serde_json::from_str::<Vec<Exchange>>(&json)?))
+                .map(|exchanges| content(&exchanges))
+                .unwrap_or_else(|err| {
+                    warn!(
+                        ?err,
+                        id = row.id,
+                        "failed to read conversation for indexing"
+                    );
+                    String::new()
+                });
+
+            index(&mut transaction, row.id, &row.title, &content).await?;
+
+            indexed += 1;
+        }
+        transaction.commit().await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_every_word() {
+        assert_eq!(
+            search_expr("retry Backoff::ret").unwrap(),
+            r#""retry" "backoff" "ret"*"#
+        );
+        assert_eq!(
+            search_expr(r#"NEAR("a" OR b)"#).unwrap(),
+            r#""near" "a" "or" "b"*"#
+        );
+        assert!(search_expr(" :: ").is_none());
+    }
+
+    #[test]
+    fn relates_by_distinctive_words() {
+        assert_eq!(
+            related_expr("How is the index of a repo synced? The index, again.").unwrap(),
+            r#""index" OR "repo" OR "synced" OR "again""#
+        );
+        assert!(related_expr("is it a bug?").is_none());
+    }
+}
@@ -0,0 +1,121 @@
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+
+use super::conversations::ConversationId;
+use crate::{
+    webserver::{self, middleware::User, Error},
+    Application,
+};
+
+const MAX_CONTENT_CHARS: usize = 10_000;
+
+/// An unsent question in a conversation, shared between the user's devices.
+#[derive(serde::Serialize, Debug, Default)]
+pub struct Draft {
+    pub content: String,
+    /// Bumped on every write, starting at 1. A conversation without a draft has revision 0.
+    pub revision: i64,
+    pub updated_at: i64,
+}
+
+#[derive(serde::Deserialize)]
+pub(in crate::webserver) struct Patch {
+    content: String,
+    /// The revision of the draft this edit was based on
+    revision: i64,
+}
+
+#[derive(serde::Serialize)]
+pub(in crate::webserver) struct Saved {
+    #[serde(flatten)]
+    draft: Draft,
+    /// This write replaced a newer revision than it was based on, which was written on another
+    /// device. The last write always wins, but the client may want to tell the user.
+    conflict: bool,
+}
+
+pub(in crate::webserver) async fn get(
+    Path(thread_id): Path<uuid::Uuid>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+) -> webserver::Result<Json<Draft>> {
+    let ConversationId { thread_id, user_id } = conversation_id(&user, thread_id)?;
+    let thread_id = thread_id.to_string();
+
+    let draft = sqlx::query_as! {
+        Draft,
+        "SELECT content, revision, updated_at FROM conversation_drafts \
+         WHERE user_id = ? AND thread_id = ?",
+        user_id,
+        thread_id,
+    }
+    .fetch_optional(app.sql.as_ref())
+    .await?;
+
+    Ok(Json(draft.unwrap_or_default()))
+}
+
+/// Replace the draft of a conversation. An empty draft is kept, so that its revision carries on.
+pub(in crate::webserver) async fn patch(
+    Path(thread_id): Path<uuid::Uuid>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+    Json(Patch { content, revision }): Json<Patch>,
+) -> webserver::Result<Json<Saved>> {
+    let ConversationId { thread_id, user_id } = conversation_id(&user, thread_id)?;
+    let thread_id = thread_id.to_string();
+
+    if content.chars().count() > MAX_CONTENT_CHARS {
+        return Err(Error::user(format!(
+            "drafts can be at most {MAX_CONTENT_CHARS} characters"
+        )));
+    }
+
+    let mut transaction = app.sql.begin().await?;
+
+    let current = sqlx::query_scalar! {
+        "SELECT revision FROM conversation_drafts WHERE user_id = ? AND thread_id = ?",
+        user_id,
+        thread_id,
+    }
+    .fetch_optional(&mut transaction)
+    .await?
+    .unwrap_or(0);
+
+    let draft = sqlx::query_as! {
+        Draft,
+        "INSERT INTO conversation_drafts (user_id, thread_id, content) \
+         VALUES (?, ?, ?) \
+         ON CONFLICT (user_id, thread_id) DO UPDATE SET \
+            content = excluded.content, \
+            revision = revision + 1, \
+            updated_at = strftime('%s', 'now') \
+         RETURNING content AS \"content!\", revision AS \"revision!\", \
+            updated_at AS \"updated_at!\"",
+        user_id,
+        thread_id,
+        content,
+    }
+    .fetch_one(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(Saved {
+        draft,
+        conflict: current > revision,
+    }))
+}
+
+fn conversation_id(user: &User, thread_id: uuid::Uuid) -> webserver::Result<ConversationId> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("missing user ID"))?;
+
+    Ok(ConversationId {
+        thread_id,
+        user_id: user_id.to_owned(),
+    })
+}
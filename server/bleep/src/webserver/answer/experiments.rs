@@ -0,0 +1,247 @@
+use anyhow::Result;
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    agent::experiment::{Assignment, Experiment, Variant},
+    db::SqlDb,
+    webserver::{self, Error, ErrorKind},
+    Application,
+};
+
+#[derive(Deserialize)]
+pub(in crate::webserver) struct NewExperiment {
+    name: String,
+    traffic_percent: u8,
+    variant: Variant,
+}
+
+#[derive(Serialize)]
+pub struct ExperimentReport {
+    id: i64,
+    name: String,
+    traffic_percent: i64,
+    variant: Variant,
+    created_at: NaiveDateTime,
+    stopped_at: Option<NaiveDateTime>,
+    arms: Vec<ArmReport>,
+}
+
+/// How the queries answered with one arm of an experiment fared.
+#[derive(Serialize)]
+pub struct ArmReport {
+    arm: String,
+    queries: i64,
+    answered: i64,
+    avg_latency_ms: Option<f64>,
+    positive_votes: i64,
+    negative_votes: i64,
+    /// The share of votes that were positive, if there were any.
+    positive_rate: Option<f64>,
+}
+
+/// All experiments, newest first, comparing every variant with its control group.
+pub(in crate::webserver) async fn list(
+    State(app): State<Application>,
+) -> webserver::Result<impl IntoResponse> {
+    let experiments = sqlx::query!(
+        "SELECT id, name, traffic_percent, variant, created_at, stopped_at
+        FROM experiments
+        ORDER BY id DESC",
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    let mut reports = vec![];
+    for experiment in experiments {
+        let arms = sqlx::query!(
+            r#"SELECT arm,
+                count(*) AS "queries!: i64",
+                count(latency_ms) AS "answered!: i64",
+                avg(latency_ms) AS "avg_latency_ms?: f64",
+                sum(CASE WHEN vote = 'positive' THEN 1 ELSE 0 END) AS "positive_votes!: i64",
+                sum(CASE WHEN vote = 'negative' THEN 1 ELSE 0 END) AS "negative_votes!: i64"
+            FROM experiment_assignments
+            WHERE experiment_id = ?
+            GROUP BY arm
+            ORDER BY arm"#,
+            experiment.id,
+        )
+        .fetch_all(&*app.sql)
+        .await?
+        .into_iter()
+        .map(|row| {
+            let votes = row.positive_votes + row.negative_votes;
+            ArmReport {
+                arm: row.arm,
+                queries: row.queries,
+                answered: row.answered,
+                avg_latency_ms: row.avg_latency_ms,
+                positive_votes: row.positive_votes,
+                negative_votes: row.negative_votes,
+                positive_rate: (votes > 0).then(|| row.positive_votes as f64 / votes as f64),
+            }
+        })
+        .collect();
+
+        reports.push(ExperimentReport {
+            id: experiment.id,
+            name: experiment.name,
+            traffic_percent: experiment.traffic_percent,
+            variant: serde_json::from_str(&experiment.variant).map_err(Error::internal)?,
+            created_at: experiment.created_at,
+            stopped_at: experiment.stopped_at,
+            arms,
+        });
+    }
+
+    Ok(Json(reports))
+}
+
+/// Start an experiment, which takes effect with the next query.
+pub(in crate::webserver) async fn create(
+    State(app): State<Application>,
+    Json(params): Json<NewExperiment>,
+) -> webserver::Result<impl IntoResponse> {
+    let name = params.name.trim();
+    if name.is_empty() {
+        return Err(Error::user("experiments need a name"));
+    }
+
+    if !(1..=100).contains(&params.traffic_percent) {
+        return Err(Error::user("`traffic_percent` must be between 1 and 100"));
+    }
+
+    params.variant.validate().map_err(Error::user)?;
+
+    // Variants don't overlap, so the running experiments can't take more than all the traffic
+    let taken = running(&app.sql)
+        .await?
+        .iter()
+        .map(|e| u32::from(e.traffic_percent))
+        .sum::<u32>();
+
+    if taken + u32::from(params.traffic_percent) > 100 {
+        return Err(Error::user(format!(
+            "running experiments already take {taken}% of the traffic"
+        )));
+    }
+
+    let exists = sqlx::query_scalar!("SELECT id FROM experiments WHERE name = ?", name)
+        .fetch_optional(&*app.sql)
+        .await?
+        .is_some();
+
+    if exists {
+        return Err(Error::user("an experiment with this name already exists"));
+    }
+
+    let variant = serde_json::to_string(&params.variant).map_err(Error::internal)?;
+    let id = sqlx::query!(
+        "INSERT INTO experiments (name, traffic_percent, variant) VALUES (?, ?, ?)",
+        name,
+        params.traffic_percent,
+        variant,
+    )
+    .execute(&*app.sql)
+    .await?
+    .last_insert_rowid();
+
+    Ok(Json(id))
+}
+
+/// Stop assigning queries to an experiment. Its results are kept.
+pub(in crate::webserver) async fn stop(
+    State(app): State<Application>,
+    Path(id): Path<i64>,
+) -> webserver::Result<impl IntoResponse> {
+    let stopped = sqlx::query!(
+        "UPDATE experiments SET stopped_at = datetime('now')
+        WHERE id = ? AND stopped_at IS NULL",
+        id,
+    )
+    .execute(&*app.sql)
+    .await?
+    .rows_affected();
+
+    if stopped == 0 {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            "no running experiment with this ID",
+        ));
+    }
+
+    Ok(Json(id))
+}
+
+/// The running experiments, in the order their traffic slices are laid out.
+pub async fn running(db: &SqlDb) -> Result<Vec<Experiment>> {
+    sqlx::query!(
+        "SELECT id, name, traffic_percent, variant
+        FROM experiments
+        WHERE stopped_at IS NULL
+        ORDER BY id",
+    )
+    .fetch_all(db.as_ref())
+    .await?
+    .into_iter()
+    .map(|row| {
+        Ok(Experiment {
+            id: row.id,
+            name: row.name,
+            traffic_percent: row.traffic_percent as u8,
+            variant: serde_json::from_str(&row.variant)?,
+        })
+    })
+    .collect()
+}
+
+pub async fn record(db: &SqlDb, query_id: uuid::Uuid, assignments: &[Assignment]) -> Result<()> {
+    let query_id = query_id.to_string();
+    for assignment in assignments {
+        let arm = assignment.arm.as_str();
+        sqlx::query!(
+            "INSERT INTO experiment_assignments (experiment_id, query_id, arm) VALUES (?, ?, ?)
+            ON CONFLICT DO NOTHING",
+            assignment.experiment_id,
+            query_id,
+            arm,
+        )
+        .execute(db.as_ref())
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn record_latency(db: &SqlDb, query_id: uuid::Uuid, latency_ms: i64) -> Result<()> {
+    let query_id = query_id.to_string();
+    sqlx::query!(
+        "UPDATE experiment_assignments SET latency_ms = ? WHERE query_id = ?",
+        latency_ms,
+        query_id,
+    )
+    .execute(db.as_ref())
+    .await?;
+
+    Ok(())
+}
+
+pub async fn record_vote(db: &SqlDb, query_id: uuid::Uuid, positive: bool) -> Result<()> {
+    let query_id = query_id.to_string();
+    let vote = if positive { "positive" } else { "negative" };
+    sqlx::query!(
+        "UPDATE experiment_assignments SET vote = ? WHERE query_id = ?",
+        vote,
+        query_id,
+    )
+    .execute(db.as_ref())
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,269 @@
+//! Posting an answer to a GitHub issue or pull request, as a comment.
+//!
+//! The rendering is shared with the tickets that workspaces file in their issue trackers.
+
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::conversations::{self, ConversationId, ViewSource};
+use crate::{
+    agent::exchange::Exchange,
+    repo::{Backend, RepoRef},
+    webserver::{self, middleware::User, Error, ErrorKind},
+    Application,
+};
+
+/// Markdown link targets, like the `src/foo.rs#L1-L5` of ``[`foo`](src/foo.rs#L1-L5)``.
+pub(in crate::webserver) static LINK: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\]\(([^)\s]+)\)").unwrap());
+
+#[derive(Deserialize)]
+pub(in crate::webserver) struct Export {
+    /// The number of the issue or pull request to comment on.
+    number: u64,
+    /// Where the conversation can be opened, to link back to it.
+    conversation_url: Option<url::Url>,
+}
+
+#[derive(Serialize)]
+pub(in crate::webserver) struct Exported {
+    url: String,
+}
+
+/// Post an exchange as a comment on an issue or pull request of the conversation's repository.
+///
+/// The comment is made with the GitHub credentials of this instance, so with a GitHub App
+/// installation it shows up as the app.
+pub(in crate::webserver) async fn export(
+    Path((thread_id, exchange_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+    Json(params): Json<Export>,
+) -> webserver::Result<Json<Exported>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("missing user ID"))?
+        .to_owned();
+
+    let id = ConversationId { thread_id, user_id };
+    let (repo_ref, exchanges) = conversations::load(&app.sql, &id)
+        .await?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "thread was not found"))?;
+
+    let exchange = exchanges
+        .into_iter()
+        .find(|e| e.id == exchange_id)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "exchange was not found"))?;
+
+    if exchange.answer().is_none() {
+        return Err(Error::user("the exchange has not been answered"));
+    }
+
+    let Some((owner, repo)) = github_repo(&repo_ref) else {
+        return Err(Error::user("only GitHub repositories can be exported to"));
+    };
+
+    let github = app
+        .credentials
+        .github()
+        .ok_or_else(|| Error::user("GitHub is not connected"))?;
+
+    let body = render(
+        &exchange,
+        blob_url(&app, &repo_ref, &exchange).await.as_deref(),
+        conversation_url(&app, thread_id, params.conversation_url).as_deref(),
+    );
+
+    let comment = github
+        .client()
+        .map_err(Error::internal)?
+        .issues(owner, repo)
+        .create_comment(params.number, body)
+        .await
+        .map_err(|err| Error::new(ErrorKind::UpstreamService, err.to_string()))?;
+
+    conversations::log_view(&app.sql, &id, ViewSource::Export, Some(&id.user_id), 1).await?;
+
+    Ok(Json(Exported {
+        url: comment.html_url.to_string(),
+    }))
+}
+
+fn github_repo(repo_ref: &RepoRef) -> Option<(&str, &str)> {
+    match repo_ref.backend() {
+        Backend::Github => repo_ref.name().split_once('/'),
+        Backend::Gitlab | Backend::Bitbucket | Backend::Local => None,
+    }
+}
+
+/// The base URL of permalinks to files of a remote repository, or `None` if it has none, like
+/// local repositories.
+///
+/// Citations are pinned to the commit the answer was based on, so that they keep pointing at the
+/// right lines.
+pub(in crate::webserver) async fn blob_url(
+    app: &Application,
+    repo_ref: &RepoRef,
+    exchange: &Exchange,
+) -> Option<String> {
+    if repo_ref.is_local() {
+        return None;
+    }
+
+    let revision = match exchange
+        .index_freshness
+        .as_ref()
+        .and_then(|f| f.indexed_commit.clone())
+    {
+        Some(commit) => commit,
+        None => app
+            .repo_pool
+            .read_async(repo_ref, |_, repo| repo.indexed_commit.clone())
+            .await
+            .flatten()
+            .unwrap_or_else(|| "HEAD".to_owned()),
+    };
+
+    match repo_ref.backend() {
+        Backend::Github => Some(format!(
+            "https://github.com/{}/blob/{revision}",
+            repo_ref.name()
+        )),
+        Backend::Gitlab => {
+            let (host, path) = repo_ref.host_and_path();
+            Some(format!("https://{host}/{path}/-/blob/{revision}"))
+        }
+        // Bitbucket Server takes the revision after the file path, so only Cloud has permalinks
+        Backend::Bitbucket => match repo_ref.host_and_path() {
+            ("bitbucket.org", path) => Some(format!("https://bitbucket.org/{path}/src/{revision}")),
+            _ => None,
+        },
+        Backend::Local => None,
+    }
+}
+
+/// Where a conversation can be opened, preferring the URL the client knows it by.
+pub(in crate::webserver) fn conversation_url(
+    app: &Application,
+    thread_id: uuid::Uuid,
+    explicit: Option<url::Url>,
+) -> Option<String> {
+    explicit.map(|url| url.to_string()).or_else(|| {
+        app.config
+            .instance_domain
+            .as_ref()
+            .map(|domain| format!("{domain}/conversations/{thread_id}"))
+    })
+}
+
+/// Render an exchange as markdown, with links to files turned into permalinks under `blob_url`.
+pub(in crate::webserver) fn render(
+    exchange: &Exchange,
+    blob_url: Option<&str>,
+    conversation_url: Option<&str>,
+) -> String {
+    let mut references = vec![];
+    let mut in_code = false;
+
+    let answer = exchange
+        .answer()
+        .unwrap_or_default()
+        .lines()
+        .map(|line| {
+            if line.trim_start().starts_with("```") {
+                in_code = !in_code;
+            }
+
+            if in_code {
+                return line.to_owned();
+            }
+
+            LINK.replace_all(line, |caps: &regex::Captures| {
+                let target = &caps[1];
+                let Some(blob_url) = blob_url else {
+                    return caps[0].to_owned();
+                };
+
+                if target.contains("://")
+                    || target.starts_with('#')
+                    || target.starts_with("mailto:")
+                {
+                    return caps[0].to_owned();
+                }
+
+                let permalink = format!("{blob_url}/{}", target.trim_start_matches('/'));
+                if !references.contains(&(target.to_owned(), permalink.clone())) {
+                    references.push((target.to_owned(), permalink.clone()));
+                }
+
+                format!("]({permalink})")
+            })
+            .into_owned()
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut body = String::new();
+    if let Some(query) = exchange.query() {
+        body += &format!("> {}\n\n", query.replace('\n', "\n> "));
+    }
+
+    body += &answer;
+
+    if !references.is_empty() {
+        body += "\n\n<details><summary>References</summary>\n\n";
+        for (target, permalink) in &references {
+            body += &format!("- [`{target}`]({permalink})\n");
+        }
+        body += "\n</details>";
+    }
+
+    match conversation_url {
+        Some(url) => {
+            body +=
+                &format!("\n\n---\n<sub>Answered by bloop. [View the conversation]({url})</sub>")
+        }
+        None => body += "\n\n---\n<sub>Answered by bloop.</sub>",
+    }
+
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{agent::exchange::Update, query::parser};
+
+    #[test]
+    fn turns_citations_into_permalinks() {
+        let query = parser::parse_nl("where is foo").unwrap().into_owned();
+        let mut exchange = Exchange::new(uuid::Uuid::nil(), query);
+        exchange.apply_update(Update::Article(
+            "[`foo`](src/foo.rs#L1-L5) calls [bar](/src/bar.rs), see [docs](https://docs.rs).\n\
+             ```md\n[`foo`](src/foo.rs)\n```\n\
+             Again, [`foo`](src/foo.rs#L1-L5)"
+                .to_owned(),
+        ));
+
+        let body = render(
+            &exchange,
+            Some("https://github.com/o/r/blob/abc"),
+            Some("https://bloop.example/conversations/1"),
+        );
+
+        assert!(body.starts_with("> where is foo\n\n"));
+        assert!(body.contains("[`foo`](https://github.com/o/r/blob/abc/src/foo.rs#L1-L5) calls"));
+        assert!(body.contains("[bar](https://github.com/o/r/blob/abc/src/bar.rs)"));
+        assert!(body.contains("[docs](https://docs.rs)"));
+        assert!(body.contains("```md\n[`foo`](src/foo.rs)\n```"));
+        assert_eq!(body.matches("- [`").count(), 2);
+        assert!(
+            body.ends_with("[View the conversation](https://bloop.example/conversations/1)</sub>")
+        );
+    }
+}
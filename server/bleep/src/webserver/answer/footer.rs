@@ -0,0 +1,139 @@
+//! A footer that the server appends to every answer, such as a disclaimer that answers need to be
+//! checked before they are relied upon.
+//!
+//! The footer is part of the answer text, so it is shown, shared and exported along with it.
+
+use axum::{extract::State, Extension, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::SqlDb,
+    webserver::{self, middleware::User, Error},
+    Application,
+};
+
+/// The longest a footer can be, in characters.
+const MAX_CHARS: usize = 500;
+
+/// The placeholders a template can use, filled in when an answer is written.
+const PLACEHOLDERS: &[&str] = &["model", "date"];
+
+#[derive(Serialize, Deserialize)]
+pub(in crate::webserver) struct Footer {
+    /// The text of the footer, in which `{model}` and `{date}` are replaced with the model that
+    /// wrote the answer and the day it was written
+    template: String,
+}
+
+/// The footer template, or `null` if answers don't have one.
+pub(in crate::webserver) async fn get(
+    State(app): State<Application>,
+) -> webserver::Result<Json<Option<Footer>>> {
+    Ok(Json(
+        load(&app.sql).await?.map(|template| Footer { template }),
+    ))
+}
+
+pub(in crate::webserver) async fn put(
+    State(app): State<Application>,
+    Extension(user): Extension<User>,
+    Json(footer): Json<Footer>,
+) -> webserver::Result<Json<Footer>> {
+    let template = validate(&footer.template).map_err(Error::user)?;
+    let user_id = user.username();
+
+    sqlx::query!(
+        "INSERT INTO answer_footer (id, template, updated_by) VALUES (1, ?, ?)
+        ON CONFLICT (id) DO UPDATE SET
+            template = excluded.template,
+            updated_by = excluded.updated_by,
+            updated_at = strftime('%s', 'now')",
+        template,
+        user_id,
+    )
+    .execute(&*app.sql)
+    .await?;
+
+    Ok(Json(Footer {
+        template: template.to_owned(),
+    }))
+}
+
+/// Stop appending a footer to answers.
+pub(in crate::webserver) async fn delete(State(app): State<Application>) -> webserver::Result<()> {
+    sqlx::query!("DELETE FROM answer_footer")
+        .execute(&*app.sql)
+        .await?;
+
+    Ok(())
+}
+
+pub(in crate::webserver) async fn load(db: &SqlDb) -> webserver::Result<Option<String>> {
+    Ok(
+        sqlx::query_scalar!("SELECT template FROM answer_footer WHERE id = 1")
+            .fetch_optional(db.as_ref())
+            .await?,
+    )
+}
+
+/// Fill in the placeholders of a template.
+pub(in crate::webserver) fn render(template: &str, model: &str, date: chrono::NaiveDate) -> String {
+    template
+        .replace("{model}", model)
+        .replace("{date}", &date.format("%Y-%m-%d").to_string())
+}
+
+fn validate(template: &str) -> Result<&str, String> {
+    let template = template.trim();
+    if template.is_empty() || template.chars().count() > MAX_CHARS {
+        return Err(format!("footers must be 1 to {MAX_CHARS} characters long"));
+    }
+
+    for placeholder in template
+        .split('{')
+        .skip(1)
+        .filter_map(|rest| rest.split_once('}'))
+        .map(|(name, _)| name)
+    {
+        if !PLACEHOLDERS.contains(&placeholder) {
+            return Err(format!(
+                "unknown placeholder `{{{placeholder}}}`, expected one of {}",
+                PLACEHOLDERS
+                    .iter()
+                    .map(|p| format!("`{{{p}}}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+    }
+
+    Ok(template)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_placeholders() {
+        let date = chrono::NaiveDate::from_ymd_opt(2023, 10, 19).unwrap();
+
+        assert_eq!(
+            render(
+                "_Written by {model} on {date}, verify before use._",
+                "gpt-4",
+                date
+            ),
+            "_Written by gpt-4 on 2023-10-19, verify before use._"
+        );
+    }
+
+    #[test]
+    fn validates_templates() {
+        assert_eq!(validate("  AI generated  "), Ok("AI generated"));
+        assert!(validate("By {model} on {date}").is_ok());
+        assert!(validate("By {author}").is_err());
+        assert!(validate(" ").is_err());
+        assert!(validate(&"a".repeat(MAX_CHARS + 1)).is_err());
+    }
+}
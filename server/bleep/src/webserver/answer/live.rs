@@ -0,0 +1,175 @@
+//! Following an answer in flight from more than one client.
+//!
+//! Every update of an answer is a whole exchange, so followers only need the latest one. Each
+//! answer publishes to a `watch` channel keyed by its conversation, which a client joining late
+//! reads the current state from right away.
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::Path,
+    response::{
+        sse::{self, Sse},
+        IntoResponse,
+    },
+    Extension,
+};
+use serde_json::json;
+use tokio::sync::watch;
+
+use super::conversations::ConversationId;
+use crate::{
+    agent::exchange::Exchange,
+    webserver::{self, middleware::User, Error, ErrorKind},
+    Application,
+};
+
+#[derive(Clone, Default)]
+struct State {
+    query_id: uuid::Uuid,
+    exchange: Option<Exchange>,
+    error: Option<String>,
+    done: bool,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct LiveAnswers(Arc<scc::HashMap<ConversationId, Arc<watch::Sender<State>>>>);
+
+impl LiveAnswers {
+    /// Start publishing a new answer, taking over from any other answer in the same conversation.
+    pub(super) fn publish(&self, id: ConversationId, query_id: uuid::Uuid) -> Publisher {
+        let (sender, _) = watch::channel(State {
+            query_id,
+            ..Default::default()
+        });
+        let sender = Arc::new(sender);
+
+        let previous = self.0.remove(&id);
+        _ = self.0.insert(id.clone(), sender.clone());
+
+        if let Some((_, previous)) = previous {
+            previous.send_modify(|state| state.done = true);
+        }
+
+        Publisher {
+            answers: self.clone(),
+            id,
+            sender,
+        }
+    }
+
+    fn subscribe(&self, id: &ConversationId) -> Option<watch::Receiver<State>> {
+        self.0.read(id, |_, sender| sender.subscribe())
+    }
+}
+
+/// Publishes the updates of one answer, until dropped along with the answer stream.
+pub(super) struct Publisher {
+    answers: LiveAnswers,
+    id: ConversationId,
+    sender: Arc<watch::Sender<State>>,
+}
+
+impl Publisher {
+    pub(super) fn update(&self, update: &anyhow::Result<Exchange>) {
+        self.sender.send_modify(|state| match update {
+            Ok(exchange) => state.exchange = Some(exchange.clone()),
+            Err(err) => state.error = Some(err.to_string()),
+        });
+    }
+}
+
+impl Drop for Publisher {
+    fn drop(&mut self) {
+        self.sender.send_modify(|state| state.done = true);
+
+        // A newer answer in the same conversation has its own sender by now
+        self.answers
+            .0
+            .remove_if(&self.id, |sender| Arc::ptr_eq(sender, &self.sender));
+    }
+}
+
+/// Follow the answer in flight in a conversation, with the same events as `/answer`.
+pub(in crate::webserver) async fn follow(
+    Path(thread_id): Path<uuid::Uuid>,
+    Extension(app): Extension<Application>,
+    Extension(user): Extension<User>,
+) -> webserver::Result<impl IntoResponse> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    let mut receiver = app
+        .live_answers
+        .subscribe(&ConversationId { thread_id, user_id })
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "no answer is in progress"))?;
+
+    let stream = async_stream::stream! {
+        let query_id = receiver.borrow().query_id;
+        yield sse::Event::default().json_data(json!({
+            "thread_id": thread_id.to_string(),
+            "query_id": query_id,
+        }));
+
+        loop {
+            let state = receiver.borrow_and_update().clone();
+
+            if let Some(exchange) = state.exchange {
+                yield sse::Event::default().json_data(Ok::<_, String>(exchange));
+            }
+
+            if let Some(err) = state.error {
+                yield sse::Event::default().json_data(Err::<Exchange, _>(err));
+            }
+
+            if state.done || receiver.changed().await.is_err() {
+                break;
+            }
+        }
+
+        yield Ok(sse::Event::default().data("[DONE]"));
+    };
+
+    Ok(Sse::new(stream).keep_alive(
+        sse::KeepAlive::new()
+            .interval(Duration::from_secs(5))
+            .event(sse::Event::default().event("heartbeat")),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id() -> ConversationId {
+        ConversationId {
+            thread_id: uuid::Uuid::nil(),
+            user_id: "user".to_owned(),
+        }
+    }
+
+    #[test]
+    fn newer_answers_take_over() {
+        let answers = LiveAnswers::default();
+
+        let first = answers.publish(id(), uuid::Uuid::new_v4());
+        let follower = answers.subscribe(&id()).unwrap();
+
+        let second = answers.publish(id(), uuid::Uuid::new_v4());
+        assert!(follower.borrow().done);
+
+        // The first answer going away leaves the second one in place
+        drop(first);
+        let follower = answers.subscribe(&id()).unwrap();
+        assert!(!follower.borrow().done);
+
+        second.update(&Ok(Exchange::default()));
+        assert!(follower.borrow().exchange.is_some());
+
+        drop(second);
+        assert!(follower.borrow().done);
+        assert!(answers.subscribe(&id()).is_none());
+    }
+}
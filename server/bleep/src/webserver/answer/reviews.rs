@@ -0,0 +1,436 @@
+//! Human review of a sample of answered exchanges, for tracking the quality of answers.
+//!
+//! With `--review-sample-percent`, that share of completed exchanges is copied into a queue.
+//! Reviewers claim exchanges from the queue one at a time, grade them from 1 to 5, and can leave
+//! a comment. Claims that aren't graded within [`CLAIM_TIMEOUT_SECS`] go back to the queue.
+//!
+//! Grades are sent to analytics as they are given, and summed up by `/reviews/summary`, along with
+//! the arms of the experiments the exchanges were answered with.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::{
+    analytics::ReviewEvent,
+    db::SqlDb,
+    webserver::{middleware::User, Error, Result},
+    Application,
+};
+
+/// How long a claim lasts, after which another reviewer can claim the exchange.
+const CLAIM_TIMEOUT_SECS: i64 = 60 * 60;
+
+/// The most reviews listed at once.
+const MAX_REVIEWS: i64 = 100;
+
+/// A completed exchange that can be sampled for review.
+pub(crate) struct Candidate {
+    pub(crate) user_id: String,
+    pub(crate) thread_id: uuid::Uuid,
+    pub(crate) exchange_id: uuid::Uuid,
+    pub(crate) query_id: uuid::Uuid,
+    pub(crate) repo_ref: String,
+    pub(crate) query: String,
+    pub(crate) answer: String,
+}
+
+/// Whether an exchange is sampled, given a `roll` between 0 and 1.
+pub(crate) fn sampled(percent: f64, roll: f64) -> bool {
+    roll * 100.0 < percent
+}
+
+/// Queue an exchange for review.
+pub(crate) async fn sample(db: SqlDb, candidate: Candidate) {
+    let Candidate {
+        user_id,
+        thread_id,
+        exchange_id,
+        query_id,
+        repo_ref,
+        query,
+        answer,
+    } = candidate;
+
+    let thread_id = thread_id.to_string();
+    let exchange_id = exchange_id.to_string();
+    let query_id = query_id.to_string();
+
+    // Retried exchanges keep their ID, and are only reviewed once
+    let result = sqlx::query!(
+        "INSERT INTO reviews (user_id, thread_id, exchange_id, query_id, repo_ref, query, answer)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT (exchange_id) DO NOTHING",
+        user_id,
+        thread_id,
+        exchange_id,
+        query_id,
+        repo_ref,
+        query,
+        answer,
+    )
+    .execute(db.as_ref())
+    .await;
+
+    if let Err(err) = result {
+        error!(?err, "failed to sample exchange for review");
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub(in crate::webserver) struct Review {
+    id: i64,
+    user_id: String,
+    thread_id: String,
+    exchange_id: String,
+    query_id: String,
+    repo_ref: String,
+    query: String,
+    answer: String,
+    created_at: i64,
+    reviewer_id: Option<String>,
+    claimed_at: Option<i64>,
+    grade: Option<i64>,
+    comment: Option<String>,
+    graded_at: Option<i64>,
+}
+
+/// The login of the user, if they can review exchanges.
+fn reviewer<'a>(app: &Application, user: &'a User) -> Result<&'a str> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID").with_status(StatusCode::UNAUTHORIZED))?;
+
+    // Sampled exchanges come from every tenant
+    let reviewers = match &app.config.reviewers {
+        reviewers if reviewers.is_empty() && app.config.tenant_isolation => {
+            &app.config.instance_admins
+        }
+        reviewers => reviewers,
+    };
+
+    if (!reviewers.is_empty() || app.config.tenant_isolation)
+        && !reviewers.iter().any(|reviewer| reviewer == user_id)
+    {
+        return Err(Error::user("only reviewers can do this").with_status(StatusCode::FORBIDDEN));
+    }
+
+    Ok(user_id)
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(in crate::webserver) enum ReviewState {
+    /// Waiting to be claimed
+    Pending,
+    Claimed,
+    Graded,
+}
+
+#[derive(Deserialize)]
+pub(in crate::webserver) struct ListParams {
+    state: Option<ReviewState>,
+}
+
+/// The latest sampled exchanges, newest first.
+pub(in crate::webserver) async fn list(
+    State(app): State<Application>,
+    Extension(user): Extension<User>,
+    Query(params): Query<ListParams>,
+) -> Result<Json<Vec<Review>>> {
+    reviewer(&app, &user)?;
+
+    let state = params.state.map(|state| match state {
+        ReviewState::Pending => "pending",
+        ReviewState::Claimed => "claimed",
+        ReviewState::Graded => "graded",
+    });
+
+    let reviews = sqlx::query_as!(
+        Review,
+        r#"SELECT id AS "id!", user_id, thread_id, exchange_id, query_id, repo_ref, query, answer,
+            created_at, reviewer_id, claimed_at, grade, comment, graded_at
+        FROM reviews
+        WHERE ?1 IS NULL
+            OR (?1 = 'graded' AND graded_at IS NOT NULL)
+            OR (?1 = 'claimed' AND graded_at IS NULL AND claimed_at >= strftime('%s', 'now') - ?2)
+            OR (?1 = 'pending' AND graded_at IS NULL
+                AND (claimed_at IS NULL OR claimed_at < strftime('%s', 'now') - ?2))
+        ORDER BY id DESC
+        LIMIT ?3"#,
+        state,
+        CLAIM_TIMEOUT_SECS,
+        MAX_REVIEWS,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    Ok(Json(reviews))
+}
+
+/// Claim the next exchange waiting for review.
+///
+/// Reviewers get the exchange they already claimed back, until they grade it.
+pub(in crate::webserver) async fn claim(
+    State(app): State<Application>,
+    Extension(user): Extension<User>,
+) -> Result<Json<Review>> {
+    let reviewer_id = reviewer(&app, &user)?;
+
+    let review = sqlx::query_as!(
+        Review,
+        r#"UPDATE reviews SET reviewer_id = ?1, claimed_at = strftime('%s', 'now')
+        WHERE id = (
+            SELECT id FROM reviews
+            WHERE graded_at IS NULL
+                AND (reviewer_id IS NULL
+                    OR reviewer_id = ?1
+                    OR claimed_at < strftime('%s', 'now') - ?2)
+            ORDER BY reviewer_id IS NOT ?1, id
+            LIMIT 1
+        )
+        RETURNING id AS "id!", user_id AS "user_id!", thread_id AS "thread_id!",
+            exchange_id AS "exchange_id!", query_id AS "query_id!", repo_ref AS "repo_ref!",
+            query AS "query!", answer AS "answer!", created_at AS "created_at!", reviewer_id,
+            claimed_at, grade, comment, graded_at"#,
+        reviewer_id,
+        CLAIM_TIMEOUT_SECS,
+    )
+    .fetch_optional(&*app.sql)
+    .await?
+    .ok_or_else(|| Error::not_found("no exchanges are waiting for review"))?;
+
+    Ok(Json(review))
+}
+
+#[derive(Deserialize)]
+pub(in crate::webserver) struct Grade {
+    /// From 1, the worst, to 5
+    grade: u8,
+    comment: Option<String>,
+}
+
+/// Grade a claimed exchange, or change the grade given to it.
+pub(in crate::webserver) async fn grade(
+    State(app): State<Application>,
+    Extension(user): Extension<User>,
+    Path(id): Path<i64>,
+    Json(params): Json<Grade>,
+) -> Result<Json<Review>> {
+    let reviewer_id = reviewer(&app, &user)?;
+
+    if !(1..=5).contains(&params.grade) {
+        return Err(Error::user("`grade` must be between 1 and 5"));
+    }
+
+    let grade = i64::from(params.grade);
+    let comment = params.comment.as_deref().map(str::trim);
+
+    let review = sqlx::query_as!(
+        Review,
+        r#"UPDATE reviews
+        SET grade = ?, comment = COALESCE(?, comment), graded_at = strftime('%s', 'now')
+        WHERE id = ? AND reviewer_id = ?
+        RETURNING id AS "id!", user_id AS "user_id!", thread_id AS "thread_id!",
+            exchange_id AS "exchange_id!", query_id AS "query_id!", repo_ref AS "repo_ref!",
+            query AS "query!", answer AS "answer!", created_at AS "created_at!", reviewer_id,
+            claimed_at, grade, comment, graded_at"#,
+        grade,
+        comment,
+        id,
+        reviewer_id,
+    )
+    .fetch_optional(&*app.sql)
+    .await?;
+
+    let Some(review) = review else {
+        return Err(unclaimed(&app.sql, id).await);
+    };
+
+    let event = ReviewEvent {
+        review_id: review.id,
+        query_id: review.query_id.clone(),
+        thread_id: review.thread_id.clone(),
+        repo_ref: review.repo_ref.clone(),
+        grade: params.grade,
+        commented: review.comment.is_some(),
+    };
+    app.with_analytics(|analytics| analytics.track_review(&user, event));
+
+    Ok(Json(review))
+}
+
+#[derive(Deserialize)]
+pub(in crate::webserver) struct Comment {
+    comment: String,
+}
+
+/// Comment on a claimed exchange, before or after grading it.
+pub(in crate::webserver) async fn comment(
+    State(app): State<Application>,
+    Extension(user): Extension<User>,
+    Path(id): Path<i64>,
+    Json(params): Json<Comment>,
+) -> Result<Json<Review>> {
+    let reviewer_id = reviewer(&app, &user)?;
+
+    let comment = params.comment.trim();
+    let comment = (!comment.is_empty()).then_some(comment);
+
+    let review = sqlx::query_as!(
+        Review,
+        r#"UPDATE reviews SET comment = ?
+        WHERE id = ? AND reviewer_id = ?
+        RETURNING id AS "id!", user_id AS "user_id!", thread_id AS "thread_id!",
+            exchange_id AS "exchange_id!", query_id AS "query_id!", repo_ref AS "repo_ref!",
+            query AS "query!", answer AS "answer!", created_at AS "created_at!", reviewer_id,
+            claimed_at, grade, comment, graded_at"#,
+        comment,
+        id,
+        reviewer_id,
+    )
+    .fetch_optional(&*app.sql)
+    .await?;
+
+    match review {
+        Some(review) => Ok(Json(review)),
+        None => Err(unclaimed(&app.sql, id).await),
+    }
+}
+
+/// The error for a change to a review that the reviewer hasn't claimed.
+async fn unclaimed(db: &SqlDb, id: i64) -> Error {
+    match sqlx::query_scalar!("SELECT id FROM reviews WHERE id = ?", id)
+        .fetch_optional(db.as_ref())
+        .await
+    {
+        Ok(Some(_)) => Error::user("the exchange is claimed by another reviewer, or not at all")
+            .with_status(StatusCode::CONFLICT),
+        Ok(None) => Error::not_found("review was not found"),
+        Err(err) => err.into(),
+    }
+}
+
+fn default_days() -> u32 {
+    30
+}
+
+#[derive(Deserialize)]
+pub(in crate::webserver) struct SummaryParams {
+    /// Sum up the exchanges sampled in this many days
+    #[serde(default = "default_days")]
+    days: u32,
+}
+
+#[derive(Serialize, Debug)]
+pub(in crate::webserver) struct Summary {
+    days: u32,
+    sampled: i64,
+    graded: i64,
+    avg_grade: Option<f64>,
+    /// The number of exchanges given each grade, from 1 to 5
+    grades: [i64; 5],
+    arms: Vec<ArmSummary>,
+}
+
+/// The grades of the exchanges answered with one arm of an experiment.
+#[derive(Serialize, Debug)]
+pub(in crate::webserver) struct ArmSummary {
+    experiment: String,
+    arm: String,
+    graded: i64,
+    avg_grade: Option<f64>,
+}
+
+/// The grades given to the exchanges sampled recently.
+pub(in crate::webserver) async fn summary(
+    State(app): State<Application>,
+    Extension(user): Extension<User>,
+    Query(params): Query<SummaryParams>,
+) -> Result<Json<Summary>> {
+    reviewer(&app, &user)?;
+
+    let window_secs = i64::from(params.days) * 86400;
+
+    let totals = sqlx::query!(
+        r#"SELECT COUNT(*) AS "sampled!: i64", COUNT(graded_at) AS "graded!: i64",
+            AVG(grade) AS "avg_grade: f64"
+        FROM reviews
+        WHERE created_at >= strftime('%s', 'now') - ?"#,
+        window_secs,
+    )
+    .fetch_one(&*app.sql)
+    .await?;
+
+    let counts = sqlx::query!(
+        r#"SELECT grade AS "grade!", COUNT(*) AS "count!: i64"
+        FROM reviews
+        WHERE created_at >= strftime('%s', 'now') - ? AND grade IS NOT NULL
+        GROUP BY grade"#,
+        window_secs,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    let arms = sqlx::query_as!(
+        ArmSummary,
+        r#"SELECT e.name AS experiment, a.arm, COUNT(*) AS "graded!: i64",
+            AVG(r.grade) AS "avg_grade: f64"
+        FROM reviews r
+        JOIN experiment_assignments a ON a.query_id = r.query_id
+        JOIN experiments e ON e.id = a.experiment_id
+        WHERE r.created_at >= strftime('%s', 'now') - ? AND r.grade IS NOT NULL
+        GROUP BY e.id, a.arm
+        ORDER BY e.id DESC, a.arm"#,
+        window_secs,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    Ok(Json(Summary {
+        days: params.days,
+        sampled: totals.sampled,
+        graded: totals.graded,
+        avg_grade: totals.avg_grade,
+        grades: distribution(counts.into_iter().map(|row| (row.grade, row.count))),
+        arms,
+    }))
+}
+
+/// The number of exchanges with each grade, from counts of the grades that were given.
+fn distribution(counts: impl IntoIterator<Item = (i64, i64)>) -> [i64; 5] {
+    let mut grades = [0; 5];
+    for (grade, count) in counts {
+        if let Some(slot) = usize::try_from(grade - 1)
+            .ok()
+            .and_then(|i| grades.get_mut(i))
+        {
+            *slot += count;
+        }
+    }
+
+    grades
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_a_share_of_exchanges() {
+        assert!(!sampled(0.0, 0.0));
+        assert!(sampled(5.0, 0.049));
+        assert!(!sampled(5.0, 0.05));
+        assert!(sampled(100.0, 0.999));
+    }
+
+    #[test]
+    fn distributes_grades() {
+        assert_eq!(distribution([(1, 2), (5, 3)]), [2, 0, 0, 0, 3]);
+        assert_eq!(distribution([(0, 1), (6, 1)]), [0; 5]);
+    }
+}
@@ -0,0 +1,206 @@
+use anyhow::{bail, Result};
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use reqwest::StatusCode;
+
+use super::conversations::ConversationId;
+use crate::{
+    db::SqlDb,
+    webserver::{self, middleware::User, Error},
+    Application,
+};
+
+/// The maximum number of scratchpads a single conversation can have.
+pub const MAX_SCRATCHPADS: usize = 5;
+
+const MAX_NAME_CHARS: usize = 64;
+const MAX_CONTENT_CHARS: usize = 2_000;
+
+/// A named note attached to a conversation, such as a running plan or a TODO list.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct Scratchpad {
+    pub name: String,
+    pub content: String,
+    pub updated_at: i64,
+}
+
+#[derive(serde::Deserialize)]
+pub(in crate::webserver) struct Put {
+    content: String,
+}
+
+pub(in crate::webserver) async fn list(
+    Path(thread_id): Path<uuid::Uuid>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+) -> webserver::Result<impl IntoResponse> {
+    let conversation_id = conversation_id(&user, thread_id)?;
+    Ok(Json(load(&app.sql, &conversation_id).await?))
+}
+
+pub(in crate::webserver) async fn put(
+    Path((thread_id, name)): Path<(uuid::Uuid, String)>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+    Json(Put { content }): Json<Put>,
+) -> webserver::Result<impl IntoResponse> {
+    let conversation_id = conversation_id(&user, thread_id)?;
+    validate(&name, &content).map_err(Error::user)?;
+
+    let scratchpad = save(&app.sql, &conversation_id, &name, &content)
+        .await?
+        .ok_or_else(|| {
+            Error::user(format!(
+                "a conversation can have at most {MAX_SCRATCHPADS} scratchpads"
+            ))
+        })?;
+
+    Ok(Json(scratchpad))
+}
+
+pub(in crate::webserver) async fn delete(
+    Path((thread_id, name)): Path<(uuid::Uuid, String)>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+) -> webserver::Result<()> {
+    let ConversationId { thread_id, user_id } = conversation_id(&user, thread_id)?;
+    let thread_id = thread_id.to_string();
+
+    let result = sqlx::query! {
+        "DELETE FROM conversation_scratchpads WHERE user_id = ? AND thread_id = ? AND name = ?",
+        user_id,
+        thread_id,
+        name,
+    }
+    .execute(app.sql.as_ref())
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(Error::user("scratchpad not found").with_status(StatusCode::NOT_FOUND));
+    }
+
+    Ok(())
+}
+
+fn conversation_id(user: &User, thread_id: uuid::Uuid) -> webserver::Result<ConversationId> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("missing user ID"))?;
+
+    Ok(ConversationId {
+        thread_id,
+        user_id: user_id.to_owned(),
+    })
+}
+
+/// Load all scratchpads of a conversation, ordered by name.
+pub async fn load(db: &SqlDb, conversation_id: &ConversationId) -> Result<Vec<Scratchpad>> {
+    let ConversationId { thread_id, user_id } = conversation_id;
+    let thread_id = thread_id.to_string();
+
+    let scratchpads = sqlx::query_as! {
+        Scratchpad,
+        "SELECT name, content, updated_at \
+         FROM conversation_scratchpads \
+         WHERE user_id = ? AND thread_id = ? \
+         ORDER BY name",
+        user_id,
+        thread_id,
+    }
+    .fetch_all(db.as_ref())
+    .await?;
+
+    Ok(scratchpads)
+}
+
+/// Create or replace a scratchpad.
+///
+/// Returns `None` if this would create a new scratchpad, and the conversation already has
+/// [`MAX_SCRATCHPADS`].
+pub async fn save(
+    db: &SqlDb,
+    conversation_id: &ConversationId,
+    name: &str,
+    content: &str,
+) -> Result<Option<Scratchpad>> {
+    let name = validate(name, content)?;
+    let ConversationId { thread_id, user_id } = conversation_id;
+    let thread_id = thread_id.to_string();
+
+    let mut transaction = db.begin().await?;
+
+    let existing = sqlx::query! {
+        "SELECT name FROM conversation_scratchpads WHERE user_id = ? AND thread_id = ?",
+        user_id,
+        thread_id,
+    }
+    .fetch_all(&mut transaction)
+    .await?;
+
+    if existing.len() >= MAX_SCRATCHPADS && !existing.iter().any(|row| row.name == name) {
+        return Ok(None);
+    }
+
+    let scratchpad = sqlx::query_as! {
+        Scratchpad,
+        "INSERT INTO conversation_scratchpads (user_id, thread_id, name, content) \
+         VALUES (?, ?, ?, ?) \
+         ON CONFLICT (user_id, thread_id, name) DO UPDATE SET \
+            content = excluded.content, \
+            updated_at = strftime('%s', 'now') \
+         RETURNING name AS \"name!\", content AS \"content!\", updated_at AS \"updated_at!\"",
+        user_id,
+        thread_id,
+        name,
+        content,
+    }
+    .fetch_one(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Some(scratchpad))
+}
+
+/// Check the limits of a scratchpad, returning its normalized name.
+pub fn validate<'a>(name: &'a str, content: &str) -> Result<&'a str> {
+    let name = name.trim();
+
+    if name.is_empty() {
+        bail!("scratchpad name cannot be empty");
+    }
+
+    if name.chars().count() > MAX_NAME_CHARS {
+        bail!("scratchpad name can be at most {MAX_NAME_CHARS} characters");
+    }
+
+    if name.contains(['/', '\n']) {
+        bail!("scratchpad name cannot contain slashes or newlines");
+    }
+
+    if content.chars().count() > MAX_CONTENT_CHARS {
+        bail!("scratchpad content can be at most {MAX_CONTENT_CHARS} characters");
+    }
+
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_scratchpads() {
+        assert_eq!(validate("  plan ", "1. read the code").unwrap(), "plan");
+        assert_eq!(validate("TODO list", "").unwrap(), "TODO list");
+
+        assert!(validate(" ", "").is_err());
+        assert!(validate("a/b", "").is_err());
+        assert!(validate("line\nbreak", "").is_err());
+        assert!(validate(&"x".repeat(MAX_NAME_CHARS + 1), "").is_err());
+        assert!(validate("notes", &"x".repeat(MAX_CONTENT_CHARS + 1)).is_err());
+    }
+}
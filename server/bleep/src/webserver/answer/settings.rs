@@ -0,0 +1,105 @@
+use anyhow::Result;
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+
+use crate::{
+    agent::retrieval::RetrievalSettings,
+    db::SqlDb,
+    repo::RepoRef,
+    webserver::{self, Error},
+    Application,
+};
+
+#[derive(serde::Deserialize)]
+pub(in crate::webserver) struct Params {
+    repo_ref: RepoRef,
+}
+
+pub(in crate::webserver) async fn get(
+    Query(params): Query<Params>,
+    State(app): State<Application>,
+) -> webserver::Result<impl IntoResponse> {
+    Ok(Json(load(&app.sql, &params.repo_ref).await?))
+}
+
+pub(in crate::webserver) async fn put(
+    Query(params): Query<Params>,
+    State(app): State<Application>,
+    Json(settings): Json<RetrievalSettings>,
+) -> webserver::Result<impl IntoResponse> {
+    settings.validate().map_err(Error::user)?;
+
+    let repo_ref = params.repo_ref.to_string();
+    let lexical_k = settings.lexical_k as i64;
+    let semantic_k = settings.semantic_k as i64;
+    let min_similarity = settings.min_similarity as f64;
+    let max_chunks_per_file = settings.max_chunks_per_file.map(|max| max as i64);
+    let max_chunks_per_repo = settings.max_chunks_per_repo.map(|max| max as i64);
+
+    sqlx::query! {
+        "INSERT INTO retrieval_settings (\
+            repo_ref, lexical_k, semantic_k, min_similarity, max_chunks_per_file, \
+            max_chunks_per_repo\
+         ) \
+         VALUES (?, ?, ?, ?, ?, ?) \
+         ON CONFLICT (repo_ref) DO UPDATE SET \
+            lexical_k = excluded.lexical_k, \
+            semantic_k = excluded.semantic_k, \
+            min_similarity = excluded.min_similarity, \
+            max_chunks_per_file = excluded.max_chunks_per_file, \
+            max_chunks_per_repo = excluded.max_chunks_per_repo",
+        repo_ref,
+        lexical_k,
+        semantic_k,
+        min_similarity,
+        max_chunks_per_file,
+        max_chunks_per_repo,
+    }
+    .execute(&*app.sql)
+    .await?;
+
+    Ok(Json(settings))
+}
+
+/// Reset the settings of a repository to the defaults.
+pub(in crate::webserver) async fn delete(
+    Query(params): Query<Params>,
+    State(app): State<Application>,
+) -> webserver::Result<impl IntoResponse> {
+    let repo_ref = params.repo_ref.to_string();
+
+    sqlx::query!(
+        "DELETE FROM retrieval_settings WHERE repo_ref = ?",
+        repo_ref
+    )
+    .execute(&*app.sql)
+    .await?;
+
+    Ok(Json(RetrievalSettings::default()))
+}
+
+pub async fn load(db: &SqlDb, repo_ref: &RepoRef) -> Result<RetrievalSettings> {
+    let repo_ref = repo_ref.to_string();
+
+    let row = sqlx::query! {
+        "SELECT lexical_k, semantic_k, min_similarity, max_chunks_per_file, max_chunks_per_repo \
+         FROM retrieval_settings \
+         WHERE repo_ref = ?",
+        repo_ref,
+    }
+    .fetch_optional(db.as_ref())
+    .await?;
+
+    Ok(row
+        .map(|row| RetrievalSettings {
+            lexical_k: row.lexical_k as u64,
+            semantic_k: row.semantic_k as u64,
+            min_similarity: row.min_similarity as f32,
+            max_chunks_per_file: row.max_chunks_per_file.map(|max| max as u64),
+            max_chunks_per_repo: row.max_chunks_per_repo.map(|max| max as u64),
+        })
+        .unwrap_or_default())
+}
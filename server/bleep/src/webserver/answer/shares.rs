@@ -0,0 +1,247 @@
+//! Read-only links to a conversation, for people who can't sign in to this instance.
+//!
+//! A link carries a random token, which is only shown once, when the share is created. Opening a
+//! link serves the conversation as it is now, so exchanges added after sharing are shared too.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use rand::Rng;
+use sqlx::SqlitePool;
+
+use super::conversations::{self, ConversationId, ViewSource};
+use crate::{
+    agent::exchange::Exchange,
+    webserver::{self, middleware::User, Error, ErrorKind},
+    Application,
+};
+
+/// The longest a share can be valid for, in days.
+const MAX_EXPIRY_DAYS: i64 = 365;
+
+#[derive(serde::Deserialize, Default)]
+pub(in crate::webserver) struct Create {
+    /// Stop serving the conversation after this many days, instead of until revoked
+    expires_in_days: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+pub(in crate::webserver) struct Created {
+    #[serde(flatten)]
+    share: Share,
+    /// The path of the shared view, relative to the host. It can't be shown again.
+    url: String,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub(in crate::webserver) struct Share {
+    id: i64,
+    created_at: i64,
+    expires_at: Option<i64>,
+}
+
+/// Create a link to a conversation of the user.
+pub(in crate::webserver) async fn create(
+    Path(thread_id): Path<uuid::Uuid>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+    params: Option<Json<Create>>,
+) -> webserver::Result<Json<Created>> {
+    let id = conversation_id(&user, thread_id)?;
+    let Json(Create { expires_in_days }) = params.unwrap_or_default();
+
+    if let Some(days) = expires_in_days {
+        if !(1..=MAX_EXPIRY_DAYS).contains(&days) {
+            return Err(Error::user(format!(
+                "shares expire after 1 to {MAX_EXPIRY_DAYS} days"
+            )));
+        }
+    }
+
+    conversations::load(&app.sql, &id)
+        .await?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "thread was not found"))?;
+
+    let (share, token) = insert(&app.sql, &id, expires_in_days).await?;
+
+    Ok(Json(Created {
+        share,
+        url: format!("{}/api/shared/{token}", app.config.base_path()),
+    }))
+}
+
+/// The shares of a conversation of the user that haven't been revoked or expired, newest first.
+pub(in crate::webserver) async fn list(
+    Path(thread_id): Path<uuid::Uuid>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+) -> webserver::Result<Json<Vec<Share>>> {
+    let ConversationId { thread_id, user_id } = conversation_id(&user, thread_id)?;
+    let thread_id = thread_id.to_string();
+
+    let shares = sqlx::query_as! {
+        Share,
+        "SELECT id, created_at, expires_at FROM conversation_shares \
+         WHERE user_id = ? AND thread_id = ? AND revoked_at IS NULL \
+            AND (expires_at IS NULL OR expires_at > strftime('%s', 'now')) \
+         ORDER BY id DESC",
+        user_id,
+        thread_id,
+    }
+    .fetch_all(&*app.sql)
+    .await?;
+
+    Ok(Json(shares))
+}
+
+/// Revoke a share, so that its link stops working.
+pub(in crate::webserver) async fn revoke(
+    Path((thread_id, share_id)): Path<(uuid::Uuid, i64)>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+) -> webserver::Result<StatusCode> {
+    let ConversationId { thread_id, user_id } = conversation_id(&user, thread_id)?;
+    let thread_id = thread_id.to_string();
+
+    let revoked = sqlx::query! {
+        "UPDATE conversation_shares SET revoked_at = strftime('%s', 'now') \
+         WHERE id = ? AND user_id = ? AND thread_id = ? AND revoked_at IS NULL",
+        share_id,
+        user_id,
+        thread_id,
+    }
+    .execute(&*app.sql)
+    .await?
+    .rows_affected();
+
+    if revoked == 0 {
+        return Err(Error::new(ErrorKind::NotFound, "share was not found"));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The exchanges of a shared conversation. This is served without signing in.
+pub(in crate::webserver) async fn view(
+    Path(token): Path<String>,
+    State(app): State<Application>,
+) -> webserver::Result<Json<Vec<Exchange>>> {
+    let (share_id, id) = resolve(&app.sql, &token)
+        .await?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "share was not found"))?;
+
+    let (.., exchanges) = conversations::load(&app.sql, &id)
+        .await?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "share was not found"))?;
+
+    let source = ViewSource::Link { share_id };
+    conversations::log_view(&app.sql, &id, source, None, exchanges.len()).await?;
+
+    Ok(Json(
+        exchanges.into_iter().map(Exchange::compressed).collect(),
+    ))
+}
+
+/// Store a share, returning it with the token of its link.
+async fn insert(
+    db: &SqlitePool,
+    id: &ConversationId,
+    expires_in_days: Option<i64>,
+) -> webserver::Result<(Share, String)> {
+    let token = hex::encode(rand::thread_rng().gen::<[u8; 32]>());
+    let token_hash = hash(&token);
+    let expires_in = expires_in_days.map(|days| days * 24 * 3600);
+    let thread_id = id.thread_id.to_string();
+
+    let share = sqlx::query_as! {
+        Share,
+        "INSERT INTO conversation_shares (user_id, thread_id, token_hash, expires_at) \
+         VALUES (?, ?, ?, strftime('%s', 'now') + ?) \
+         RETURNING id AS \"id!\", created_at AS \"created_at!\", expires_at",
+        id.user_id,
+        thread_id,
+        token_hash,
+        expires_in,
+    }
+    .fetch_one(db)
+    .await?;
+
+    Ok((share, token))
+}
+
+/// The share of a token and the conversation it shares, unless the share was revoked or expired.
+async fn resolve(db: &SqlitePool, token: &str) -> webserver::Result<Option<(i64, ConversationId)>> {
+    let token_hash = hash(token);
+    let Some(row) = sqlx::query! {
+        "SELECT id AS \"id!\", user_id, thread_id FROM conversation_shares \
+         WHERE token_hash = ? AND revoked_at IS NULL \
+            AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))",
+        token_hash,
+    }
+    .fetch_optional(db)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let id = ConversationId {
+        thread_id: row.thread_id.parse().map_err(Error::internal)?,
+        user_id: row.user_id,
+    };
+
+    Ok(Some((row.id, id)))
+}
+
+fn hash(token: &str) -> String {
+    blake3::hash(token.as_bytes()).to_string()
+}
+
+fn conversation_id(user: &User, thread_id: uuid::Uuid) -> webserver::Result<ConversationId> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("missing user ID"))?;
+
+    Ok(ConversationId {
+        thread_id,
+        user_id: user_id.to_owned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn tokens_open_their_share_until_revoked() {
+        let db = crate::db::test_pool().await;
+        let id = ConversationId {
+            thread_id: uuid::Uuid::new_v4(),
+            user_id: "user".to_owned(),
+        };
+
+        let (share, token) = insert(&db, &id, None).await.unwrap();
+        assert_eq!(
+            resolve(&db, &token).await.unwrap(),
+            Some((share.id, id.clone()))
+        );
+        assert_eq!(resolve(&db, "unknown").await.unwrap(), None);
+
+        // Only the hash of the token is stored
+        let (stored,): (String,) =
+            sqlx::query_as("SELECT token_hash FROM conversation_shares WHERE id = ?")
+                .bind(share.id)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_ne!(stored, token);
+        assert_eq!(stored, hash(&token));
+
+        sqlx::query("UPDATE conversation_shares SET revoked_at = strftime('%s', 'now')")
+            .execute(&db)
+            .await
+            .unwrap();
+        assert_eq!(resolve(&db, &token).await.unwrap(), None);
+    }
+}
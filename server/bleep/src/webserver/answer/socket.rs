@@ -0,0 +1,117 @@
+//! Answers streamed over a WebSocket, for networks whose proxies buffer or cut event streams.
+//!
+//! The socket carries the same frames as `/answer`, one per text message, up to `[DONE]`. Clients
+//! can cancel the answer mid-generation by sending `{"type": "cancel"}`, or by closing the socket.
+//! Either drops the answer stream, which stops the agent like a disconnected event stream does.
+
+use std::{borrow::Cow, time::Duration};
+
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        Query,
+    },
+    response::Response,
+    Extension,
+};
+use futures::StreamExt;
+use serde::Deserialize;
+use tracing::{debug, error};
+
+use super::{Answer, AnswerStream};
+use crate::{webserver::middleware::User, Application};
+
+/// How often to ping the client, which keeps idle proxies from closing the socket while the
+/// agent is working.
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Messages that clients can send while an answer is streamed.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    /// Stop answering, keeping what was answered so far
+    Cancel,
+}
+
+/// Answer a question over a WebSocket, with the same parameters as `/answer`.
+pub async fn answer(
+    ws: WebSocketUpgrade,
+    Query(params): Query<Answer>,
+    Extension(app): Extension<Application>,
+    Extension(user): Extension<User>,
+) -> crate::webserver::Result<Response> {
+    // Questions that can't be answered fail the upgrade, with the same errors as `/answer`.
+    let stream = super::start_answer(params, app, user).await?;
+
+    Ok(ws.on_upgrade(|socket| drive(socket, stream)))
+}
+
+async fn drive(mut socket: WebSocket, mut stream: AnswerStream) {
+    let start = tokio::time::Instant::now() + PING_INTERVAL;
+    let mut ping = tokio::time::interval_at(start, PING_INTERVAL);
+
+    let close = loop {
+        tokio::select! {
+            frame = stream.next() => match frame {
+                Some(Ok(frame)) => {
+                    if socket.send(Message::Text(frame.text())).await.is_err() {
+                        return;
+                    }
+                }
+                Some(Err(err)) => {
+                    error!(?err, "failed to stream answer over socket");
+                    break CloseFrame {
+                        code: close_code::ERROR,
+                        reason: Cow::Borrowed("failed to stream answer"),
+                    };
+                }
+                None => {
+                    break CloseFrame {
+                        code: close_code::NORMAL,
+                        reason: Cow::Borrowed("done"),
+                    };
+                }
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(ClientMessage::Cancel) => {
+                        debug!("client cancelled the answer");
+                        break CloseFrame {
+                            code: close_code::NORMAL,
+                            reason: Cow::Borrowed("cancelled"),
+                        };
+                    }
+                    Err(err) => debug!(?err, "ignoring unknown message from client"),
+                },
+                // Pings are answered by the socket itself
+                Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Binary(_))) => {}
+                // The client has gone, and dropping the stream stops the agent
+                Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+            },
+            _ = ping.tick() => {
+                if socket.send(Message::Ping(Vec::new())).await.is_err() {
+                    return;
+                }
+            }
+        }
+    };
+
+    // Stop the agent before acknowledging a cancellation, so that no more work is done for it
+    drop(stream);
+    _ = socket.send(Message::Close(Some(close))).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_client_messages() {
+        assert_eq!(
+            serde_json::from_str::<ClientMessage>(r#"{"type": "cancel"}"#).unwrap(),
+            ClientMessage::Cancel
+        );
+        assert!(serde_json::from_str::<ClientMessage>(r#"{"type": "pause"}"#).is_err());
+        assert!(serde_json::from_str::<ClientMessage>("cancel").is_err());
+    }
+}
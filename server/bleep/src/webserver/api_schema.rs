@@ -0,0 +1,35 @@
+//! The JSON schema of the types that the API sends, which the Rust and TypeScript clients are
+//! generated from.
+//!
+//! The schema is checked in with the Rust client as `bleep-client/api-schema.json`, and this test
+//! fails whenever it is out of date. Run `UPDATE_EXPECT=1 cargo test -p bleep api_schema` to write
+//! it again, then `npm --prefix client run generate:api-types` for the TypeScript types.
+
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{RootSchema, SchemaObject},
+};
+
+use super::{answer::conversations::ConversationPage, EndpointError};
+use crate::{agent::exchange::Exchange, query::execute::QueryResponse};
+
+fn schema() -> RootSchema {
+    let mut gen = SchemaGenerator::default();
+
+    gen.subschema_for::<ConversationPage>();
+    gen.subschema_for::<Exchange>();
+    gen.subschema_for::<QueryResponse>();
+    gen.subschema_for::<EndpointError<'static>>();
+
+    RootSchema {
+        meta_schema: gen.settings().meta_schema.clone(),
+        schema: SchemaObject::default(),
+        definitions: gen.take_definitions(),
+    }
+}
+
+#[test]
+fn api_schema_is_up_to_date() {
+    let schema = serde_json::to_string_pretty(&schema()).unwrap();
+    expect_test::expect_file!["../../../bleep-client/api-schema.json"].assert_eq(&(schema + "\n"));
+}
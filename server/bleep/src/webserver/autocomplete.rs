@@ -11,9 +11,14 @@ use crate::{
         languages, parser,
         parser::{Literal, Target},
     },
+    repo, Application,
 };
 
-use axum::{extract::Query, response::IntoResponse as IntoAxumResponse, Extension};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse as IntoAxumResponse,
+    Extension,
+};
 use futures::{stream, StreamExt, TryStreamExt};
 use serde::Serialize;
 
@@ -37,15 +42,22 @@ pub(super) async fn handle(
     Query(mut api_params): Query<ApiQuery>,
     Query(ac_params): Query<AutocompleteParams>,
     Extension(indexes): Extension<Arc<Indexes>>,
+    State(app): State<Application>,
 ) -> Result<impl IntoAxumResponse> {
     // Override page_size and set to low value
     api_params.page = 0;
     api_params.page_size = 8;
+    api_params.branch = repo::indexed_branch(
+        &app.repo_pool,
+        api_params.repo_ref.as_ref(),
+        api_params.branch.take(),
+    )
+    .await;
 
     let mut partial_lang = None;
     let mut has_target = false;
 
-    let queries = parser::parse(&api_params.q)
+    let mut queries = parser::parse(&api_params.q)
         .map_err(Error::user)?
         .into_iter()
         .map(|mut q| {
@@ -99,6 +111,8 @@ pub(super) async fn handle(
             q
         })
         .collect::<Vec<_>>();
+    api_params.default_branch(&mut queries);
+
     let mut autocomplete_results = vec![];
 
     // Only execute prefix search on flag names if there is a non-regex content target.
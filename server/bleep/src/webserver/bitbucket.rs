@@ -0,0 +1,74 @@
+use axum::extract::State;
+use secrecy::SecretString;
+use tracing::{error, warn};
+
+use super::{
+    aaa::{AuthResponse, CredentialStatus},
+    prelude::*,
+};
+use crate::{
+    periodic::update_bitbucket_repo_list,
+    remotes::{bitbucket, RemoteError},
+    repo::Backend,
+    Application,
+};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(super) enum PutCredentials {
+    AppPassword {
+        username: String,
+        password: SecretString,
+    },
+    Token {
+        token: SecretString,
+    },
+}
+
+/// Connect to Bitbucket with an app password, or an HTTP access token of Bitbucket Server
+//
+pub(super) async fn put(
+    State(app): State<Application>,
+    Json(credentials): Json<PutCredentials>,
+) -> Result<impl IntoResponse> {
+    let auth = match credentials {
+        PutCredentials::AppPassword { username, password } => {
+            bitbucket::Auth::AppPassword { username, password }
+        }
+        PutCredentials::Token { token } => bitbucket::Auth::Token(token),
+    };
+
+    let bb = bitbucket::State::with_auth(app.config.bitbucket_url(), auth);
+    match bb.validate().await {
+        Ok(()) => {}
+        Err(RemoteError::PermissionDenied) => {
+            return Err(Error::user("Bitbucket didn't accept the credentials"));
+        }
+        Err(err) => {
+            warn!(?err, "failed to validate Bitbucket credentials");
+            return Err(Error::new(ErrorKind::UpstreamService, err.to_string()));
+        }
+    }
+
+    app.credentials.set_bitbucket(bb);
+    app.credentials.store().map_err(|err| {
+        error!(?err, "Failed to save credentials to disk");
+        Error::internal("failed to save changes")
+    })?;
+
+    update_bitbucket_repo_list(&app).await;
+    Ok(json(AuthResponse::Status(CredentialStatus::Ok)))
+}
+
+/// Remove Bitbucket credentials
+//
+pub(super) async fn delete(State(app): State<Application>) -> Result<impl IntoResponse> {
+    if app.credentials.remove(Backend::Bitbucket).is_some() {
+        app.credentials.store().map_err(|err| {
+            error!(?err, "Failed to delete credentials from disk");
+            Error::internal("failed to save changes")
+        })?;
+    }
+
+    Ok(json(AuthResponse::Status(CredentialStatus::Missing)))
+}
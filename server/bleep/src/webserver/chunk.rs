@@ -0,0 +1,90 @@
+use std::path::PathBuf;
+
+use axum::extract::{Path, State};
+
+use super::{prelude::*, tenant};
+use crate::Application;
+
+#[derive(Serialize)]
+pub(super) struct Provenance {
+    id: uuid::Uuid,
+    repo_ref: String,
+    repo_name: String,
+    branches: Vec<String>,
+
+    /// The commit the repository was last indexed at.
+    commit: Option<String>,
+
+    /// The git blob of the file at `commit`.
+    ///
+    /// This can be missing if the file was indexed from uncommitted changes, or has changed
+    /// since the chunk was indexed.
+    blob: Option<String>,
+
+    path: String,
+    lang: String,
+    start_line: u64,
+    end_line: u64,
+    start_byte: u64,
+    end_byte: u64,
+    content_hash: String,
+}
+
+impl super::ApiResponse for Provenance {}
+
+/// Resolve a semantic index chunk to its exact source.
+pub(super) async fn provenance(
+    Path(id): Path<uuid::Uuid>,
+    State(app): State<Application>,
+) -> Result<impl IntoResponse> {
+    let payload = app
+        .semantic
+        .get_chunk(id)
+        .await
+        .map_err(Error::internal)?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown chunk ID"))?;
+
+    // Chunk IDs don't name their repository, so it's only known once the chunk is found
+    let repo_ref = tenant::stored_repo(&payload.repo_ref)
+        .map_err(Error::internal)?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown chunk ID"))?;
+    let (commit, disk_path) = app
+        .repo_pool
+        .read_async(&repo_ref, |_, repo| {
+            (repo.indexed_commit.clone(), repo.disk_path.clone())
+        })
+        .await
+        .unwrap_or_default();
+
+    let blob = match &commit {
+        Some(commit) => {
+            let spec = format!("{commit}:{}", payload.relative_path);
+            tokio::task::spawn_blocking(move || find_blob(disk_path, &spec))
+                .await
+                .map_err(Error::internal)?
+        }
+        None => None,
+    };
+
+    Ok(json(Provenance {
+        id,
+        repo_ref: payload.repo_ref,
+        repo_name: payload.repo_name,
+        branches: payload.branches,
+        commit,
+        blob,
+        path: payload.relative_path,
+        lang: payload.lang,
+        start_line: payload.start_line,
+        end_line: payload.end_line,
+        start_byte: payload.start_byte,
+        end_byte: payload.end_byte,
+        content_hash: payload.content_hash,
+    }))
+}
+
+fn find_blob(disk_path: PathBuf, spec: &str) -> Option<String> {
+    let git = gix::open(disk_path).ok()?;
+    let id = git.rev_parse_single(spec).ok()?;
+    Some(id.to_string())
+}
@@ -10,6 +10,7 @@ use tracing::error;
 
 use crate::{
     analytics::DocEvent,
+    fetch,
     indexes::doc,
     webserver::{middleware::User, Error, Result},
     Application,
@@ -157,11 +158,13 @@ impl From<doc::Error> for Error {
             | doc::Error::Io(..)
             | doc::Error::Tantivy(..)
             | doc::Error::Network(..)
+            | doc::Error::Fetch(fetch::Error::Network(..) | fetch::Error::Resolve(..))
             | doc::Error::Initialize(_) => {
                 error!(%value, "internal docs error");
                 Self::internal(value)
             } // TODO: log these to sentry
             doc::Error::InvalidUrl(..)
+            | doc::Error::Fetch(..)
             | doc::Error::DuplicateUrl(..)
             | doc::Error::EmptyDocs(..) => Self::user(value),
             doc::Error::InvalidDocId(_) => Self::not_found(value),
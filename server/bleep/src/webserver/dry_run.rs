@@ -0,0 +1,157 @@
+//! Previewing destructive operations with `?dry_run=true`.
+//!
+//! The flag is read once, by [`guard`], which hands it to handlers as a [`DryRun`] extension and
+//! turns it down on endpoints that can't preview. Handlers delete through a [`Deletion`], which
+//! counts what goes and only commits when it isn't a dry run, so a preview removes exactly what
+//! the real operation would, and then rolls it back.
+
+use std::collections::BTreeMap;
+
+use axum::{
+    body::Body,
+    http::{Method, Request},
+    middleware::{from_fn, Next},
+    response::Response,
+    Json,
+};
+
+use super::prelude::*;
+use crate::db::SqlDb;
+
+/// Endpoints that preview with `?dry_run=true`, where `:` segments match anything.
+const SUPPORTED: &[(Method, &str)] = &[
+    (Method::DELETE, "/workspace/:id"),
+    (Method::DELETE, "/workspace/:id/repos"),
+    (Method::DELETE, "/repos/purge"),
+    (Method::DELETE, "/answer/conversations"),
+];
+
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct DryRun {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+impl DryRun {
+    pub(crate) fn is_set(self) -> bool {
+        self.dry_run
+    }
+}
+
+pub(super) fn guard(router: Router) -> Router {
+    router.layer(from_fn(guard_mw))
+}
+
+async fn guard_mw(mut request: Request<Body>, next: Next<Body>) -> Result<Response> {
+    let dry_run = Query::<DryRun>::try_from_uri(request.uri())
+        .map(|Query(dry_run)| dry_run)
+        .unwrap_or_default();
+
+    if dry_run.is_set() && !supports(request.method(), request.uri().path()) {
+        return Err(Error::user("this endpoint can't do a dry run"));
+    }
+
+    request.extensions_mut().insert(dry_run);
+    Ok(next.run(request).await)
+}
+
+fn supports(method: &Method, path: &str) -> bool {
+    SUPPORTED
+        .iter()
+        .any(|(supported, pattern)| supported == method && route_matches(pattern, path))
+}
+
+/// Whether a request path matches a route pattern, where `:` segments match anything.
+pub(super) fn route_matches(pattern: &str, path: &str) -> bool {
+    let segments = path.trim_end_matches('/').split('/').collect::<Vec<_>>();
+    let pattern = pattern.split('/').collect::<Vec<_>>();
+
+    pattern.len() == segments.len()
+        && pattern
+            .iter()
+            .zip(&segments)
+            .all(|(p, s)| p.starts_with(':') || p == s)
+}
+
+/// What an operation removed, or would remove in a dry run.
+#[derive(Serialize, Default, Debug)]
+pub(crate) struct Preview {
+    dry_run: bool,
+    /// Rows removed by the handler, by the kind of thing they are
+    removed: BTreeMap<&'static str, u64>,
+    /// Every row removed, including those of other tables that go along with them
+    rows: u64,
+}
+
+/// The deletions of one operation, in a transaction that is only committed outside a dry run.
+pub(crate) struct Deletion {
+    transaction: sqlx::Transaction<'static, sqlx::Sqlite>,
+    preview: Preview,
+    changes_before: i64,
+}
+
+impl Deletion {
+    pub(crate) async fn begin(db: &SqlDb, dry_run: DryRun) -> Result<Self> {
+        let mut transaction = db.begin().await.map_err(Error::internal)?;
+        let changes_before = total_changes(&mut transaction).await?;
+
+        Ok(Self {
+            transaction,
+            preview: Preview {
+                dry_run: dry_run.is_set(),
+                ..Default::default()
+            },
+            changes_before,
+        })
+    }
+
+    pub(crate) fn is_dry_run(&self) -> bool {
+        self.preview.dry_run
+    }
+
+    pub(crate) fn transaction(&mut self) -> &mut sqlx::Transaction<'static, sqlx::Sqlite> {
+        &mut self.transaction
+    }
+
+    pub(crate) fn removed(&mut self, kind: &'static str, count: u64) {
+        *self.preview.removed.entry(kind).or_default() += count;
+    }
+
+    pub(crate) async fn finish(mut self) -> Result<Json<Preview>> {
+        // Cascades and triggers count towards the changes of the connection, but not the statement
+        let changes = total_changes(&mut self.transaction).await? - self.changes_before;
+        self.preview.rows = changes.max(0) as u64;
+
+        if self.preview.dry_run {
+            self.transaction.rollback().await
+        } else {
+            self.transaction.commit().await
+        }
+        .map_err(Error::internal)?;
+
+        Ok(Json(self.preview))
+    }
+}
+
+async fn total_changes(transaction: &mut sqlx::Transaction<'_, sqlx::Sqlite>) -> Result<i64> {
+    sqlx::query_scalar!(r#"SELECT total_changes() AS "changes!: i64""#)
+        .fetch_one(&mut *transaction)
+        .await
+        .map_err(Error::internal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_supported_endpoints() {
+        assert!(supports(&Method::DELETE, "/workspace/12"));
+        assert!(supports(&Method::DELETE, "/workspace/12/repos/"));
+        assert!(supports(&Method::DELETE, "/answer/conversations"));
+
+        assert!(!supports(&Method::GET, "/workspace/12"));
+        assert!(!supports(&Method::DELETE, "/workspace/12/members/someone"));
+        assert!(!supports(&Method::DELETE, "/answer/conversations/trash"));
+    }
+}
@@ -0,0 +1,145 @@
+//! The web client, served from `--frontend-dist` or from the binary itself.
+//!
+//! The client is built with relative asset paths, and the `<base>` tag of its index is pointed at
+//! `--base-path`, so it can be served under any prefix.
+
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::{Context, Result};
+use axum::{
+    body::{boxed, Body},
+    extract::State,
+    http::{header, Request, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tower::ServiceExt;
+use tower_http::services::ServeDir;
+
+use crate::Configuration;
+
+static BASE_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<base\s[^>]*>").unwrap());
+
+/// The client built into `client/dist`, with the `embed-frontend` feature.
+#[cfg(feature = "embed-frontend")]
+#[derive(rust_embed::RustEmbed)]
+#[folder = "../../client/dist"]
+struct Embedded;
+
+enum Source {
+    Dir(PathBuf),
+    #[cfg(feature = "embed-frontend")]
+    Embedded,
+}
+
+struct Frontend {
+    source: Source,
+    index: String,
+}
+
+impl Frontend {
+    fn index(&self) -> Response {
+        // Assets are hashed, but the index has to be fetched again to pick up new ones
+        (
+            [(header::CACHE_CONTROL, "no-cache")],
+            Html(self.index.clone()),
+        )
+            .into_response()
+    }
+}
+
+/// A router for the front-end, unless there is none to serve.
+pub(super) fn router(config: &Configuration) -> Result<Option<axum::Router>> {
+    let source = match config.frontend_dist.clone() {
+        Some(dir) => Source::Dir(dir),
+        #[cfg(feature = "embed-frontend")]
+        None => Source::Embedded,
+        #[cfg(not(feature = "embed-frontend"))]
+        None => return Ok(None),
+    };
+
+    let index = match &source {
+        Source::Dir(dir) => std::fs::read_to_string(dir.join("index.html"))
+            .with_context(|| format!("failed to read the front-end in {}", dir.display()))?,
+        #[cfg(feature = "embed-frontend")]
+        Source::Embedded => {
+            let index = Embedded::get("index.html").context("the front-end was not embedded")?;
+            String::from_utf8(index.data.into_owned())?
+        }
+    };
+
+    let frontend = Frontend {
+        source,
+        index: with_base(&index, &config.base_path()),
+    };
+
+    Ok(Some(
+        axum::Router::new()
+            .fallback(serve)
+            .with_state(Arc::new(frontend)),
+    ))
+}
+
+async fn serve(State(frontend): State<Arc<Frontend>>, request: Request<Body>) -> Response {
+    let path = request.uri().path().trim_start_matches('/').to_owned();
+
+    // The index is rewritten for the base path, so it is never served as it is on disk
+    if path.is_empty() || path == "index.html" {
+        return frontend.index();
+    }
+
+    let asset = match &frontend.source {
+        Source::Dir(dir) => match ServeDir::new(dir)
+            .append_index_html_on_directories(false)
+            .oneshot(request)
+            .await
+        {
+            Ok(response) if response.status() != StatusCode::NOT_FOUND => Some(response.map(boxed)),
+            _ => None,
+        },
+        #[cfg(feature = "embed-frontend")]
+        Source::Embedded => Embedded::get(&path).map(|file| {
+            (
+                [(header::CONTENT_TYPE, file.metadata.mimetype().to_owned())],
+                file.data,
+            )
+                .into_response()
+        }),
+    };
+
+    // Anything else is a route of the client
+    asset.unwrap_or_else(|| frontend.index())
+}
+
+/// Point the `<base>` of the index at the base path, adding one if the index has none.
+fn with_base(index: &str, base_path: &str) -> String {
+    let tag = format!(r#"<base href="{base_path}/" />"#);
+
+    if BASE_TAG.is_match(index) {
+        BASE_TAG.replace(index, regex::NoExpand(&tag)).into_owned()
+    } else {
+        index.replacen("<head>", &format!("<head>{tag}"), 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn points_the_index_at_the_base_path() {
+        let index = r#"<head><base href="/" /><script src="./assets/index.js"></script></head>"#;
+
+        assert_eq!(
+            with_base(index, "/bloop"),
+            r#"<head><base href="/bloop/" /><script src="./assets/index.js"></script></head>"#
+        );
+        assert_eq!(with_base(index, ""), index);
+
+        assert_eq!(
+            with_base("<html><head><title>bloop</title></head>", "/a/b"),
+            r#"<html><head><base href="/a/b/" /><title>bloop</title></head>"#
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use axum::{extract::State, response::Redirect};
+use secrecy::SecretString;
+use tracing::{error, warn};
+
+use super::{
+    aaa::{AuthResponse, CredentialStatus},
+    prelude::*,
+};
+use crate::{
+    periodic::update_gitlab_repo_list,
+    remotes::{gitlab, RemoteError},
+    repo::Backend,
+    Application,
+};
+
+/// Sign in with GitLab, on the configured instance
+//
+pub(super) async fn login(State(app): State<Application>) -> Result<impl IntoResponse> {
+    let state = app.gitlab_logins.start();
+    let url = gitlab::authorize_url(&app.config, &state).map_err(Error::user)?;
+
+    Ok(json(AuthResponse::AuthenticationNeeded {
+        url: url.to_string(),
+    }))
+}
+
+#[derive(Deserialize)]
+pub(super) struct Complete {
+    code: String,
+    state: String,
+}
+
+/// Where GitLab redirects to after signing in
+//
+pub(super) async fn complete(
+    Query(Complete { code, state }): Query<Complete>,
+    State(app): State<Application>,
+) -> Result<impl IntoResponse> {
+    if !app.gitlab_logins.finish(&state) {
+        return Err(Error::user("unknown or expired sign-in").with_status(StatusCode::FORBIDDEN));
+    }
+
+    let gl = gitlab::exchange_code(&app.config, &code)
+        .await
+        .map_err(|err| Error::new(ErrorKind::UpstreamService, err.to_string()))?;
+
+    save(&app, gl).await?;
+
+    let home = gitlab::instance_url(&app.config).map_err(Error::user)?;
+    Ok(Redirect::to(&format!("{home}/")))
+}
+
+#[derive(Deserialize)]
+pub(super) struct PutToken {
+    token: SecretString,
+}
+
+/// Use a personal, group or project access token, instead of signing in
+//
+pub(super) async fn put_token(
+    State(app): State<Application>,
+    Json(PutToken { token }): Json<PutToken>,
+) -> Result<impl IntoResponse> {
+    let gl = gitlab::State::with_auth(app.config.gitlab_url(), gitlab::Auth::Token(token));
+
+    match gl.validate().await {
+        Ok(_) => {}
+        Err(RemoteError::PermissionDenied) => {
+            return Err(Error::user("GitLab didn't accept the token"));
+        }
+        Err(err) => {
+            warn!(?err, "failed to validate GitLab token");
+            return Err(Error::new(ErrorKind::UpstreamService, err.to_string()));
+        }
+    }
+
+    save(&app, gl).await?;
+    Ok(json(AuthResponse::Status(CredentialStatus::Ok)))
+}
+
+/// Remove GitLab credentials
+//
+pub(super) async fn logout(State(app): State<Application>) -> Result<impl IntoResponse> {
+    if app.credentials.remove(Backend::Gitlab).is_none() {
+        return Ok(json(AuthResponse::Status(CredentialStatus::Missing)));
+    }
+
+    app.credentials.store().map_err(|err| {
+        error!(?err, "Failed to delete credentials from disk");
+        Error::internal("failed to save changes")
+    })?;
+
+    Ok(json(AuthResponse::Status(CredentialStatus::Missing)))
+}
+
+async fn save(app: &Application, gl: gitlab::State) -> Result<()> {
+    app.credentials.set_gitlab(gl);
+    app.credentials.store().map_err(|err| {
+        error!(?err, "Failed to save credentials to disk");
+        Error::internal("failed to save changes")
+    })?;
+
+    update_gitlab_repo_list(app).await;
+    Ok(())
+}
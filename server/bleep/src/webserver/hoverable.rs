@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
-use super::prelude::*;
-use crate::{indexes::Indexes, repo::RepoRef, text_range::TextRange};
+use super::{prelude::*, tenant};
+use crate::{indexes::Indexes, text_range::TextRange};
 
 use axum::{extract::Query, response::IntoResponse, Extension};
 use serde::{Deserialize, Serialize};
@@ -31,7 +31,7 @@ pub(super) async fn handle(
     Query(payload): Query<HoverableRequest>,
     Extension(indexes): Extension<Arc<Indexes>>,
 ) -> impl IntoResponse {
-    let repo_ref = &payload.repo_ref.parse::<RepoRef>().map_err(Error::user)?;
+    let repo_ref = &tenant::parse_repo(&payload.repo_ref)?;
 
     let document = match indexes
         .file
@@ -1,9 +1,10 @@
 use std::{ops::Not, sync::Arc};
 
-use super::prelude::*;
+use super::{prelude::*, tenant, workspace};
 use crate::{
     indexes::{reader::ContentDocument, Indexes},
     intelligence::{
+        all_languages,
         code_navigation::{
             self, CodeNavigationContext, FileSymbols, Occurrence, OccurrenceKind, Token,
         },
@@ -12,9 +13,15 @@ use crate::{
     repo::RepoRef,
     snippet::Snipper,
     text_range::TextRange,
+    Application,
 };
 
-use axum::{extract::Query, response::IntoResponse, Extension};
+use axum::{
+    extract::{Path, Query},
+    response::IntoResponse,
+    Extension,
+};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
 /// The request made to the `local-intel` endpoint.
@@ -50,9 +57,10 @@ impl super::ApiResponse for TokenInfoResponse {}
 
 pub(super) async fn handle(
     Query(payload): Query<TokenInfoRequest>,
-    Extension(indexes): Extension<Arc<Indexes>>,
+    Extension(app): Extension<Application>,
 ) -> Result<impl IntoResponse> {
-    let repo_ref = payload.repo_ref.parse::<RepoRef>().map_err(Error::user)?;
+    let repo_ref = tenant::parse_repo(&payload.repo_ref)?;
+    let indexes = &app.indexes;
 
     let source_doc = indexes
         .file
@@ -76,17 +84,9 @@ pub(super) async fn handle(
             .await
     };
 
-    let symbols = get_token_info(
-        payload,
-        &repo_ref,
-        indexes,
-        &source_doc,
-        &all_docs,
-        None,
-        None,
-    )
-    .await
-    .map_err(Error::internal)?;
+    let symbols = get_token_info(payload, &repo_ref, &app, &source_doc, &all_docs, None, None)
+        .await
+        .map_err(Error::internal)?;
 
     Ok(json(TokenInfoResponse::new(symbols)))
 }
@@ -315,10 +315,81 @@ pub(super) async fn token_value(
     Ok(json(TokenValueResponse { range, content }))
 }
 
+/// The request made to the `symbols/:name/references` endpoint.
+#[derive(Debug, Deserialize)]
+pub(super) struct SymbolReferencesRequest {
+    /// The repo_ref of the repository to search
+    repo_ref: RepoRef,
+
+    /// Branch name to use for the lookup,
+    branch: Option<String>,
+
+    /// Whether to also search the other repositories of the workspaces of `repo_ref`
+    #[serde(default)]
+    workspace: bool,
+}
+
+/// The response from the `symbols/:name/references` endpoint.
+#[derive(Serialize, Debug)]
+pub(super) struct SymbolReferencesResponse {
+    /// The files with an occurrence of the symbol, those of `repo_ref` first
+    data: Vec<FileSymbols>,
+}
+
+impl super::ApiResponse for SymbolReferencesResponse {}
+
+/// Every definition and reference of a top-level symbol, by file, such as to rename it.
+///
+/// Unlike `token-info`, this looks up the symbol by its name, so it finds definitions that are
+/// not referenced from any particular file.
+pub(super) async fn symbol_references(
+    Path(name): Path<String>,
+    Query(payload): Query<SymbolReferencesRequest>,
+    Extension(app): Extension<Application>,
+) -> Result<impl IntoResponse> {
+    if name.is_empty() {
+        return Err(Error::user("symbol name can't be empty"));
+    }
+
+    let mut repos = vec![(payload.repo_ref.clone(), payload.branch.as_deref())];
+    if payload.workspace {
+        let siblings = workspace::sibling_repos(&app.sql, &payload.repo_ref).await?;
+        repos.extend(siblings.into_iter().map(|repo| (repo, None)));
+    }
+
+    let lang_ids = all_languages()
+        .iter()
+        .flat_map(|config| config.language_ids.iter().copied())
+        .collect::<Vec<_>>();
+
+    let mut data = vec![];
+    for (repo, branch) in repos {
+        let mut docs = app
+            .indexes
+            .file
+            .by_repo(&repo, lang_ids.iter(), branch)
+            .await;
+        docs.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+
+        let other_repo = (repo != payload.repo_ref).then(|| repo.to_string());
+        data.extend(
+            docs.par_iter()
+                .filter_map(|doc| code_navigation::symbol_occurrences(doc, &name, None))
+                .map(|file| FileSymbols {
+                    repo: other_repo.clone(),
+                    ..file
+                })
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    Ok(json(SymbolReferencesResponse { data }))
+}
+
 pub async fn get_token_info(
     params: TokenInfoRequest,
     repo_ref: &RepoRef,
-    indexes: Arc<Indexes>,
+    app: &Application,
     source_doc: &ContentDocument,
     all_docs: &Vec<ContentDocument>,
     context_before: Option<usize>,
@@ -343,10 +414,15 @@ pub async fn get_token_info(
         snipper,
     };
 
-    let data = ctx.token_info();
+    let mut data = ctx.token_info();
+    if ctx.is_unresolved(&data) {
+        let external = external_definitions(app, repo_ref, &ctx).await?;
+        data.splice(0..0, external);
+    }
+
     if data.is_empty() {
         search_nav(
-            Arc::clone(&indexes),
+            Arc::clone(&app.indexes),
             repo_ref,
             ctx.active_token_text(),
             ctx.active_token_range(),
@@ -360,6 +436,73 @@ pub async fn get_token_info(
     }
 }
 
+/// Definitions of an unresolved import in the other repositories of the workspaces of
+/// `repo_ref`, like an internal package that lives in a repository of its own.
+///
+/// Repositories whose name is mentioned in the source document, usually in the import itself,
+/// are searched first. The others are only searched if none of those define the symbol.
+async fn external_definitions(
+    app: &Application,
+    repo_ref: &RepoRef,
+    ctx: &CodeNavigationContext<'_, '_>,
+) -> anyhow::Result<Vec<FileSymbols>> {
+    let siblings = workspace::sibling_repos(&app.sql, repo_ref).await?;
+    if siblings.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let source_doc = &ctx.all_docs[ctx.source_document_idx];
+    let associated_langs = match source_doc.lang.as_deref().map(TSLanguage::from_id) {
+        Some(Language::Supported(config)) => config.language_ids,
+        _ => return Ok(vec![]),
+    };
+
+    let (mentioned, others) = siblings
+        .into_iter()
+        .partition::<Vec<_>, _>(|repo| is_mentioned(&source_doc.content, repo));
+
+    for candidates in [mentioned, others] {
+        let mut found = vec![];
+        for repo in candidates {
+            let docs = app
+                .indexes
+                .file
+                .by_repo(&repo, associated_langs.iter(), None)
+                .await;
+            found.extend(ctx.external_definitions(&repo.to_string(), &docs));
+        }
+
+        if !found.is_empty() {
+            return Ok(found);
+        }
+    }
+
+    Ok(vec![])
+}
+
+/// Whether a document mentions the package name of a repository, in any of the spellings that
+/// languages use for it: `my-lib`, `my_lib`, or `mylib`.
+fn is_mentioned(content: &str, repo: &RepoRef) -> bool {
+    let name = repo
+        .display_name()
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    if name.is_empty() {
+        return false;
+    }
+
+    let content = content.to_lowercase();
+    [
+        name.clone(),
+        name.replace('-', "_"),
+        name.replace(['-', '_'], ""),
+    ]
+    .iter()
+    .any(|spelling| content.contains(spelling.as_str()))
+}
+
 async fn search_nav(
     indexes: Arc<Indexes>,
     repo_ref: &RepoRef,
@@ -512,6 +655,7 @@ async fn search_nav(
 
             data.is_empty().not().then(|| FileSymbols {
                 file: file.clone(),
+                repo: None,
                 data,
             })
         })
@@ -525,6 +669,22 @@ mod tests {
     use super::*;
     use crate::{snippet::Snippet, text_range::Point};
 
+    #[test]
+    fn mentions_package_names() {
+        let repo = "github.com/acme/billing-core".parse::<RepoRef>().unwrap();
+
+        assert!(is_mentioned("import billing_core.invoices", &repo));
+        assert!(is_mentioned(
+            "const { Invoice } = require('@acme/billing-core');",
+            &repo
+        ));
+        assert!(is_mentioned(
+            "import \"github.com/acme/billingcore/invoice\"",
+            &repo
+        ));
+        assert!(!is_mentioned("import billing", &repo));
+    }
+
     #[test]
     fn serialize_response() {
         let expected = serde_json::json!({
@@ -569,6 +729,7 @@ mod tests {
             data: vec![
                 FileSymbols {
                     file: "server/bleep/src/symbol.rs".into(),
+                    repo: None,
                     data: vec![Occurrence {
                     kind: OccurrenceKind::Definition,
                     range: TextRange {
@@ -594,6 +755,7 @@ mod tests {
                 },
                 FileSymbols {
                     file: "server/bleep/src/intelligence/scope_resolution.rs".into(),
+                    repo: None,
                     data: vec![Occurrence {
                         kind: OccurrenceKind::Reference,
                         range: TextRange {
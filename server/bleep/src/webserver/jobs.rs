@@ -0,0 +1,127 @@
+//! The syncs of repositories, as jobs that can be followed, cancelled and run again.
+//!
+//! Jobs are kept in the database, see [`crate::background::jobs`]. Progress is only known while
+//! a job runs.
+
+use axum::extract::{Path, Query, State};
+
+use super::{
+    limits::{self, Usage},
+    middleware::User,
+    prelude::*,
+    tenant,
+};
+use crate::{
+    background::{
+        jobs::{self, Job, MAX_JOBS},
+        SyncConfig,
+    },
+    repo::RepoRef,
+    Application,
+};
+
+#[derive(Deserialize)]
+pub(super) struct ListParams {
+    /// Only list jobs in this state, like `failed`
+    state: Option<String>,
+    repo: Option<RepoRef>,
+}
+
+/// The latest jobs, newest first.
+pub(super) async fn list(
+    State(app): State<Application>,
+    Query(params): Query<ListParams>,
+) -> Result<Json<Vec<Job>>> {
+    let mut jobs = jobs::list(&app.sql, MAX_JOBS).await?;
+
+    jobs.retain(|job| {
+        let Ok(Some(reporef)) = tenant::stored_repo(&job.repo_ref) else {
+            return false;
+        };
+
+        params.repo.as_ref().map_or(true, |repo| repo == &reporef)
+            && params
+                .state
+                .as_ref()
+                .map_or(true, |state| state == &job.state)
+    });
+
+    for job in &mut jobs {
+        job.progress = app.sync_queue.job_progress(job.id).await;
+    }
+
+    Ok(Json(jobs))
+}
+
+/// Cancel a queued or running job.
+pub(super) async fn cancel(
+    State(app): State<Application>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode> {
+    let (job, _) = load(&app, id).await?;
+
+    if jobs::is_finished(&job.state) {
+        return Err(Error::user("the job has already finished").with_status(StatusCode::CONFLICT));
+    }
+
+    if !app.write_index().cancel_job(id).await {
+        return Err(Error::user("the job is not queued or running anymore")
+            .with_status(StatusCode::CONFLICT));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Run a finished job again, with the same settings.
+pub(super) async fn retry(
+    State(app): State<Application>,
+    Extension(user): Extension<User>,
+    Path(id): Path<i64>,
+) -> Result<Json<Job>> {
+    let (job, reporef) = load(&app, id).await?;
+
+    if !jobs::is_finished(&job.state) {
+        return Err(
+            Error::user("the job is still queued or running").with_status(StatusCode::CONFLICT)
+        );
+    }
+
+    // Syncing a removed repository would add it again
+    if !app.repo_pool.contains(&reporef) {
+        return Err(Error::not_found("the repository was removed"));
+    }
+
+    if app.write_index().is_pending(&reporef).await {
+        return Err(Error::user("the repository is already queued or syncing")
+            .with_status(StatusCode::CONFLICT));
+    }
+
+    limits::record(&app, &user, Usage::IndexingJob).await?;
+
+    let retry_id = app
+        .write_index()
+        .enqueue(
+            SyncConfig::new(&app, reporef)
+                .shallow(job.shallow)
+                .force(job.force)
+                .retry_of(Some(job.id)),
+        )
+        .await
+        .ok_or_else(|| Error::internal("failed to record the job"))?;
+
+    jobs::get(&app.sql, retry_id)
+        .await?
+        .map(Json)
+        .ok_or_else(|| Error::internal("failed to read the job"))
+}
+
+/// A job with its repository, as long as the tenant of the request can use the repository.
+async fn load(app: &Application, id: i64) -> Result<(Job, RepoRef)> {
+    jobs::get(&app.sql, id)
+        .await?
+        .and_then(|job| {
+            let reporef = tenant::stored_repo(&job.repo_ref).ok().flatten()?;
+            Some((job, reporef))
+        })
+        .ok_or_else(|| Error::not_found("job was not found"))
+}
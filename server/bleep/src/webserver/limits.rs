@@ -0,0 +1,288 @@
+//! Limits on how much a single user can use an instance.
+//!
+//! Requests are rate limited with a token bucket per user and one per IP address, in front of
+//! every authenticated endpoint. Calls to the LLM and indexing jobs are counted per user and day
+//! in the `quotas` table, by the handlers that start them, so that one user can't use up the
+//! budget of everyone else.
+//!
+//! Both limits answer with `429 Too Many Requests`, and a `Retry-After` header.
+
+use std::{
+    net::IpAddr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::Request,
+    middleware::{from_fn_with_state, Next},
+    response::Response,
+};
+use chrono::{Days, Utc};
+
+use super::{listen::PeerAddr, middleware::User, prelude::*};
+use crate::Application;
+
+/// The number of buckets kept before the full ones are dropped.
+const MAX_BUCKETS: usize = 10_000;
+
+/// Something the daily quotas count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Usage {
+    /// A request that makes calls to the LLM, like an answer
+    LlmCall,
+    IndexingJob,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    User(String),
+    Ip(IpAddr),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(capacity: u32, now: Instant) -> Self {
+        Self {
+            tokens: capacity.into(),
+            updated: now,
+        }
+    }
+
+    /// Take a token for a request, or return how long until one is available.
+    ///
+    /// The bucket holds up to `capacity` tokens, and refills at `capacity` tokens a minute.
+    fn take(&mut self, capacity: u32, now: Instant) -> std::result::Result<(), Duration> {
+        let capacity = f64::from(capacity);
+        let per_sec = capacity / 60.0;
+
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_sec).min(capacity);
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / per_sec))
+        }
+    }
+
+    fn is_full(&self, capacity: u32, now: Instant) -> bool {
+        let refilled =
+            now.saturating_duration_since(self.updated).as_secs_f64() * f64::from(capacity) / 60.0;
+
+        self.tokens + refilled >= f64::from(capacity)
+    }
+}
+
+#[derive(Default)]
+struct Limiter {
+    buckets: scc::HashMap<Key, Bucket>,
+    requests: AtomicUsize,
+}
+
+impl Limiter {
+    async fn take(
+        &self,
+        key: Key,
+        capacity: u32,
+        now: Instant,
+    ) -> std::result::Result<(), Duration> {
+        self.buckets
+            .entry_async(key)
+            .await
+            .or_insert_with(|| Bucket::full(capacity, now))
+            .get_mut()
+            .take(capacity, now)
+    }
+
+    /// Drop the buckets of clients that have been idle long enough to have a full bucket, which
+    /// is the same as having none.
+    async fn prune(&self, capacity: u32, now: Instant) {
+        if self.requests.fetch_add(1, Ordering::Relaxed) % 1000 != 0
+            || self.buckets.len() < MAX_BUCKETS
+        {
+            return;
+        }
+
+        self.buckets
+            .retain_async(|_, bucket| !bucket.is_full(capacity, now))
+            .await;
+    }
+}
+
+/// Rate limit the requests to `router`, if `--rate-limit-per-minute` is set.
+pub(super) fn rate_limit(router: Router, app: Application) -> Router {
+    let limiter = Arc::new(Limiter::default());
+    router.layer(from_fn_with_state((app, limiter), rate_limit_mw))
+}
+
+async fn rate_limit_mw(
+    State((app, limiter)): State<(Application, Arc<Limiter>)>,
+    Extension(user): Extension<User>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Result<Response> {
+    let Some(capacity) = app.config.rate_limit_per_minute.filter(|&c| c > 0) else {
+        return Ok(next.run(request).await);
+    };
+
+    let now = Instant::now();
+    limiter.prune(capacity, now).await;
+
+    let keys = [
+        user.username().map(|user| Key::User(user.to_owned())),
+        client_ip(&request, app.config.trust_forwarded_for).map(Key::Ip),
+    ];
+
+    for key in keys.into_iter().flatten() {
+        if let Err(wait) = limiter.take(key, capacity, now).await {
+            return Err(Error::user("too many requests, try again later")
+                .with_status(StatusCode::TOO_MANY_REQUESTS)
+                .with_retry_after(wait));
+        }
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// The address of the client, which is the address of the proxy in front of the server unless
+/// `X-Forwarded-For` is trusted.
+///
+/// Clients can send `X-Forwarded-For` themselves, which proxies append to, so only the last hop,
+/// added by the trusted proxy, is the address of the client.
+fn client_ip(request: &Request<Body>, trust_forwarded_for: bool) -> Option<IpAddr> {
+    let forwarded = trust_forwarded_for
+        .then(|| {
+            let value = request.headers().get_all("x-forwarded-for").iter().last()?;
+            value.to_str().ok()?.rsplit(',').next()?.trim().parse().ok()
+        })
+        .flatten();
+
+    forwarded.or_else(|| {
+        request
+            .extensions()
+            .get::<ConnectInfo<PeerAddr>>()
+            .and_then(|ConnectInfo(PeerAddr(addr))| addr.map(|addr| addr.ip()))
+    })
+}
+
+/// Count a use against the daily quota of a user, failing if the quota has been used up.
+///
+/// Users that aren't signed in aren't counted.
+pub(crate) async fn record(app: &Application, user: &User, usage: Usage) -> Result<()> {
+    let limit = match usage {
+        Usage::LlmCall => app.config.daily_llm_calls_per_user,
+        Usage::IndexingJob => app.config.daily_indexing_jobs_per_user,
+    };
+
+    let (Some(limit), Some(user_id)) = (limit, user.username()) else {
+        return Ok(());
+    };
+
+    let (llm_calls, indexing_jobs) = match usage {
+        Usage::LlmCall => (1, 0),
+        Usage::IndexingJob => (0, 1),
+    };
+
+    let mut transaction = app.sql.begin().await?;
+
+    let used = sqlx::query!(
+        r#"INSERT INTO quotas (user_id, day, llm_calls, indexing_jobs)
+        VALUES (?, date('now'), ?, ?)
+        ON CONFLICT (user_id, day) DO UPDATE SET
+            llm_calls = llm_calls + excluded.llm_calls,
+            indexing_jobs = indexing_jobs + excluded.indexing_jobs
+        RETURNING llm_calls AS "llm_calls!", indexing_jobs AS "indexing_jobs!""#,
+        user_id,
+        llm_calls,
+        indexing_jobs,
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
+    let used = match usage {
+        Usage::LlmCall => used.llm_calls,
+        Usage::IndexingJob => used.indexing_jobs,
+    };
+
+    // Dropping the transaction rolls back the use that went over the quota
+    if used > i64::from(limit) {
+        let what = match usage {
+            Usage::LlmCall => "LLM call",
+            Usage::IndexingJob => "indexing job",
+        };
+
+        return Err(Error::user(format!("daily {what} quota exceeded"))
+            .with_status(StatusCode::TOO_MANY_REQUESTS)
+            .with_retry_after(until_tomorrow()));
+    }
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// The time until quotas reset, at midnight UTC.
+fn until_tomorrow() -> Duration {
+    let now = Utc::now();
+    let tomorrow = now
+        .date_naive()
+        .checked_add_days(Days::new(1))
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        .map(|midnight| midnight.and_utc());
+
+    tomorrow
+        .and_then(|midnight| (midnight - now).to_std().ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_refill_over_time() {
+        let start = Instant::now();
+        let mut bucket = Bucket::full(2, start);
+
+        assert_eq!(bucket.take(2, start), Ok(()));
+        assert_eq!(bucket.take(2, start), Ok(()));
+
+        let wait = bucket.take(2, start).unwrap_err();
+        assert_eq!(wait.as_secs_f64().round(), 30.0);
+
+        assert_eq!(bucket.take(2, start + Duration::from_secs(31)), Ok(()));
+        assert!(!bucket.is_full(2, start + Duration::from_secs(31)));
+        assert!(bucket.is_full(2, start + Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn trusts_the_last_forwarded_hop() {
+        let mut request = Request::builder()
+            .header("x-forwarded-for", "10.0.0.1, 10.0.0.2")
+            .header("x-forwarded-for", "192.0.2.1, 192.0.2.7")
+            .body(Body::empty())
+            .unwrap();
+        request.extensions_mut().insert(ConnectInfo(PeerAddr(Some(
+            "127.0.0.1:7878".parse().unwrap(),
+        ))));
+
+        assert_eq!(
+            client_ip(&request, true),
+            Some("192.0.2.7".parse().unwrap())
+        );
+        assert_eq!(
+            client_ip(&request, false),
+            Some("127.0.0.1".parse().unwrap())
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! The socket the webserver listens on.
+//!
+//! That's usually a TCP port, but when bloop is fronted by a proxy on the same host it can bind a
+//! unix socket instead, or take one from systemd socket activation.
+
+use std::net::{SocketAddr, TcpListener};
+
+use anyhow::{bail, Context, Result};
+use axum::extract::connect_info::Connected;
+use hyper::server::conn::AddrStream;
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
+use tracing::{info, warn};
+
+use super::tls::TlsListener;
+use crate::Configuration;
+
+pub(super) enum Listener {
+    Tcp(TcpListener),
+    Tls(TlsListener),
+    #[cfg(unix)]
+    Unix(unix::UnixListener),
+}
+
+/// The address of the peer of a connection, which unix sockets don't have.
+#[derive(Clone, Copy, Debug)]
+pub(in crate::webserver) struct PeerAddr(pub(in crate::webserver) Option<SocketAddr>);
+
+impl Connected<&AddrStream> for PeerAddr {
+    fn connect_info(target: &AddrStream) -> Self {
+        Self(Some(target.remote_addr()))
+    }
+}
+
+impl Connected<&TlsStream<TcpStream>> for PeerAddr {
+    fn connect_info(target: &TlsStream<TcpStream>) -> Self {
+        Self(target.get_ref().0.peer_addr().ok())
+    }
+}
+
+#[cfg(unix)]
+impl Connected<&tokio::net::UnixStream> for PeerAddr {
+    fn connect_info(_: &tokio::net::UnixStream) -> Self {
+        Self(None)
+    }
+}
+
+impl Listener {
+    /// Must be called within the runtime, which the TLS handshakes are spawned on.
+    pub(super) fn bind(config: &Configuration) -> Result<Self> {
+        let listener = Self::bind_plain(config)?;
+
+        match (listener, &config.tls_cert, &config.tls_key) {
+            (listener, None, None) => Ok(listener),
+            (Self::Tcp(listener), Some(cert), Some(key)) => {
+                TlsListener::new(listener, cert, key).map(Self::Tls)
+            }
+            (_, Some(_), Some(_)) => bail!("TLS can only be served over TCP"),
+            _ => bail!("`--tls-cert` and `--tls-key` have to be given together"),
+        }
+    }
+
+    fn bind_plain(config: &Configuration) -> Result<Self> {
+        #[cfg(unix)]
+        if let Some(listener) = unix::from_systemd()? {
+            if let Self::Unix(_) = listener {
+                warn_unix_rate_limits(config);
+            }
+
+            return Ok(listener);
+        }
+
+        #[cfg(unix)]
+        if let Some(path) = &config.unix_socket {
+            let mode = config
+                .unix_socket_mode
+                .as_deref()
+                .map(unix::parse_mode)
+                .transpose()?;
+
+            warn_unix_rate_limits(config);
+            return unix::bind(path, mode).map(Self::Unix);
+        }
+
+        #[cfg(not(unix))]
+        if config.unix_socket.is_some() {
+            bail!("unix sockets are not supported on this platform");
+        }
+
+        let bind = SocketAddr::new(config.host.parse()?, config.port);
+        let listener = TcpListener::bind(bind).with_context(|| format!("failed to bind {bind}"))?;
+        info!(%bind, "starting webserver");
+
+        Ok(Self::Tcp(listener))
+    }
+}
+
+/// The peers of unix sockets have no address, so only users are rate limited unless the proxy in
+/// front tells us the address of the client.
+#[cfg(unix)]
+fn warn_unix_rate_limits(config: &Configuration) {
+    if config.rate_limit_per_minute.is_some() && !config.trust_forwarded_for {
+        warn!(
+            "rate limits over a unix socket only apply per user, without `--trust-forwarded-for`"
+        );
+    }
+}
+
+#[cfg(unix)]
+pub(super) mod unix {
+    use std::{
+        fs::{self, Permissions},
+        io,
+        os::unix::{
+            fs::{FileTypeExt, PermissionsExt},
+            io::{FromRawFd, IntoRawFd, RawFd},
+            net,
+        },
+        path::Path,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use anyhow::{bail, Context as _, Result};
+    use tracing::info;
+
+    use super::Listener;
+
+    /// The first file descriptor that systemd passes, from `sd_listen_fds(3)`.
+    const SD_LISTEN_FDS_START: RawFd = 3;
+
+    pub(in crate::webserver) struct UnixListener(tokio::net::UnixListener);
+
+    impl UnixListener {
+        fn new(listener: net::UnixListener) -> Result<Self> {
+            listener.set_nonblocking(true)?;
+            Ok(Self(tokio::net::UnixListener::from_std(listener)?))
+        }
+    }
+
+    impl hyper::server::accept::Accept for UnixListener {
+        type Conn = tokio::net::UnixStream;
+        type Error = io::Error;
+
+        fn poll_accept(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+            self.0
+                .poll_accept(cx)
+                .map(|accepted| Some(accepted.map(|(stream, _)| stream)))
+        }
+    }
+
+    pub(super) fn parse_mode(mode: &str) -> Result<u32> {
+        u32::from_str_radix(mode, 8)
+            .ok()
+            .filter(|mode| *mode <= 0o777)
+            .with_context(|| format!("`{mode}` is not an octal file mode, like `660`"))
+    }
+
+    pub(super) fn bind(path: &Path, mode: Option<u32>) -> Result<UnixListener> {
+        // A socket left behind by a previous run would fail the bind
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                bail!("{} exists, and is not a socket", path.display());
+            }
+
+            fs::remove_file(path)?;
+        }
+
+        let listener = net::UnixListener::bind(path)
+            .with_context(|| format!("failed to bind {}", path.display()))?;
+
+        if let Some(mode) = mode {
+            fs::set_permissions(path, Permissions::from_mode(mode))?;
+        }
+
+        info!(path = %path.display(), "starting webserver");
+        UnixListener::new(listener)
+    }
+
+    /// Take the socket that systemd passed to this process, if any.
+    pub(super) fn from_systemd() -> Result<Option<Listener>> {
+        let pid = std::env::var("LISTEN_PID").ok();
+        let fds = std::env::var("LISTEN_FDS").ok();
+
+        // These are meant for this process only, not for git and the other children
+        for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            std::env::remove_var(var);
+        }
+
+        let (Some(pid), Some(fds)) = (pid, fds) else {
+            return Ok(None);
+        };
+
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return Ok(None);
+        }
+
+        match fds.parse::<u32>() {
+            Ok(0) => return Ok(None),
+            Ok(1) => {}
+            _ => bail!("systemd passed `LISTEN_FDS={fds}`, but bloop listens on a single socket"),
+        }
+
+        // SAFETY: systemd hands the descriptor over to this process, which is the only one
+        // to take it
+        let unix = unsafe { net::UnixListener::from_raw_fd(SD_LISTEN_FDS_START) };
+
+        // The descriptor is either kind of socket, and only a unix one has a unix address
+        if let Ok(addr) = unix.local_addr() {
+            info!(?addr, "starting webserver on a socket from systemd");
+            return UnixListener::new(unix).map(|l| Some(Listener::Unix(l)));
+        }
+
+        // SAFETY: the descriptor was released by the unix listener
+        let tcp = unsafe { std::net::TcpListener::from_raw_fd(unix.into_raw_fd()) };
+        let addr = tcp
+            .local_addr()
+            .context("systemd passed a socket that is neither TCP nor unix")?;
+
+        info!(%addr, "starting webserver on a socket from systemd");
+        Ok(Some(Listener::Tcp(tcp)))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn parses_octal_modes() {
+            assert_eq!(parse_mode("660").unwrap(), 0o660);
+            assert_eq!(parse_mode("0700").unwrap(), 0o700);
+            assert!(parse_mode("778").is_err());
+            assert!(parse_mode("7777").is_err());
+            assert!(parse_mode("rw").is_err());
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! The Prometheus endpoint, and the latency of API requests it reports.
+
+use std::time::Instant;
+
+use axum::{
+    body::Body,
+    extract::MatchedPath,
+    http::{header, Request},
+    middleware::{from_fn, Next},
+    response::Response,
+};
+
+use super::prelude::*;
+use crate::metrics::{self, HTTP_REQUEST_SECONDS};
+
+/// Time the requests to `router`, by the route that matched them.
+pub(super) fn track(router: Router) -> Router {
+    router.layer(from_fn(track_mw))
+}
+
+async fn track_mw(request: Request<Body>, next: Next<Body>) -> Response {
+    let method = request.method().to_string();
+
+    // Labelling by path would make a series for every repository and file
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str)
+        .to_owned();
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+
+    HTTP_REQUEST_SECONDS
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .observe(started.elapsed().as_secs_f64());
+
+    response
+}
+
+pub(super) async fn render() -> Result<impl IntoResponse> {
+    let text = metrics::render().map_err(Error::internal)?;
+
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text))
+}
@@ -9,6 +9,7 @@ use axum::{
     response::Response,
 };
 use axum_extra::extract::CookieJar;
+use chrono::{DateTime, Utc};
 use jwt_authorizer::JwtClaims;
 use sentry::{Hub, SentryFutureExt};
 use tracing::error;
@@ -19,6 +20,9 @@ pub enum User {
     Desktop {
         access_token: String,
         login: String,
+        /// The workspace of the key the request was authenticated with
+        #[serde(skip)]
+        workspace_id: Option<i64>,
         #[serde(skip)]
         crab: Arc<dyn Fn() -> anyhow::Result<octocrab::Octocrab> + Send + Sync>,
     },
@@ -26,6 +30,9 @@ pub enum User {
         org_name: String,
         access_token: String,
         login: String,
+        /// The workspace of the key the request was authenticated with
+        #[serde(skip)]
+        workspace_id: Option<i64>,
         #[serde(skip)]
         crab: Arc<dyn Fn() -> anyhow::Result<octocrab::Octocrab> + Send + Sync>,
     },
@@ -40,6 +47,15 @@ impl User {
         }
     }
 
+    /// The workspace that a workspace key acts in, instead of the workspaces of a member.
+    pub(crate) fn workspace_id(&self) -> Option<i64> {
+        match self {
+            User::Unknown => None,
+            User::Desktop { workspace_id, .. } => *workspace_id,
+            User::Cloud { workspace_id, .. } => *workspace_id,
+        }
+    }
+
     pub(crate) fn org_name(&self) -> Option<&str> {
         let User::Cloud { org_name, .. } = self else {
             return None;
@@ -75,7 +91,10 @@ impl User {
         }
 
         let access_token = self.access_token().map(str::to_owned);
-        Ok(llm_gateway::Client::new(&app.config.answer_api_url).bearer(access_token))
+        Ok(llm_gateway::Client::new(&app.config.answer_api_url)
+            .bearer(access_token)
+            .user(self.username().map(str::to_owned))
+            .endpoint(app.config.llm_endpoint()))
     }
 
     pub(crate) async fn paid_features(&self, app: &Application) -> bool {
@@ -180,11 +199,16 @@ pub async fn cloud_user_layer_mw<B>(
             })
             .expect("misconfigured instance");
 
+        // not doing an `ok()` here to ensure this exists, or blow up
+        let access_token = jar.get(super::aaa::COOKIE_NAME).unwrap().to_string();
+        app.sessions
+            .record(&login, &access_token, DateTime::<Utc>::from(claims.exp));
+
         User::Cloud {
             login,
             org_name,
-            // not doing an `ok()` here to ensure this exists, or blow up
-            access_token: jar.get(super::aaa::COOKIE_NAME).unwrap().to_string(),
+            access_token,
+            workspace_id: None,
             crab: Arc::new(move || {
                 let gh = app.credentials.github().context("no github")?;
                 Ok(gh.client()?)
@@ -194,3 +218,40 @@ pub async fn cloud_user_layer_mw<B>(
 
     next.run(request).await
 }
+
+/// The latest session of each user signed in to a cloud instance, by login.
+///
+/// Personal access tokens and workspace keys call upstream services with the session of the
+/// user they act for, so that the tokens themselves never leave the instance.
+#[derive(Clone, Default)]
+pub(crate) struct Sessions(Arc<scc::HashMap<String, Session>>);
+
+#[derive(Clone)]
+struct Session {
+    access_token: String,
+    expires_at: DateTime<Utc>,
+}
+
+impl Sessions {
+    fn record(&self, login: &str, access_token: &str, expires_at: DateTime<Utc>) {
+        let session = Session {
+            access_token: access_token.to_owned(),
+            expires_at,
+        };
+
+        match self.0.entry(login.to_owned()) {
+            scc::hash_map::Entry::Occupied(mut occupied) => *occupied.get_mut() = session,
+            scc::hash_map::Entry::Vacant(vacant) => {
+                vacant.insert_entry(session);
+            }
+        }
+    }
+
+    /// The access token of a user's session, unless it has expired.
+    pub(crate) fn access_token(&self, login: &str) -> Option<String> {
+        self.0
+            .read(login, |_, session| session.clone())
+            .filter(|session| session.expires_at > Utc::now())
+            .map(|session| session.access_token)
+    }
+}
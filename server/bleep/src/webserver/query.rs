@@ -1,18 +1,103 @@
-use axum::extract::State;
+use axum::{extract::State, Json};
 
 use super::prelude::*;
-use crate::{db::QueryLog, query::execute::ApiQuery, Application};
+use crate::{
+    db::{QueryLog, SqlDb},
+    query::{execute::ApiQuery, planner, rules::LexicalRules},
+    repo::{self, RepoRef},
+    Application,
+};
 
 pub(super) async fn handle(
-    Query(api_params): Query<ApiQuery>,
+    Query(mut api_params): Query<ApiQuery>,
     Extension(indexes): Extension<Arc<Indexes>>,
     State(app): State<Application>,
 ) -> impl IntoResponse {
+    let _interactive = crate::background::interactive();
     QueryLog::new(&app.sql).insert(&api_params.q).await?;
 
+    if let Some(repo_ref) = &api_params.repo_ref {
+        api_params.rules = load_rules(&app.sql, repo_ref).await?.into();
+    }
+
+    api_params.branch = repo::indexed_branch(
+        &app.repo_pool,
+        api_params.repo_ref.as_ref(),
+        api_params.branch.take(),
+    )
+    .await;
+
     Arc::new(api_params)
         .query(indexes)
         .await
         .map(json)
-        .map_err(super::Error::from)
+        .map_err(|err| match err.downcast_ref::<planner::Error>() {
+            Some(err) => Error::user(err),
+            None => Error::from(err),
+        })
+}
+
+#[derive(Deserialize)]
+pub(super) struct RulesParams {
+    repo_ref: RepoRef,
+}
+
+/// The lexical search rules of a repository.
+pub(super) async fn get_rules(
+    Query(params): Query<RulesParams>,
+    State(app): State<Application>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(load_rules(&app.sql, &params.repo_ref).await?))
+}
+
+pub(super) async fn put_rules(
+    Query(params): Query<RulesParams>,
+    State(app): State<Application>,
+    Json(rules): Json<LexicalRules>,
+) -> Result<impl IntoResponse> {
+    rules.validate().map_err(Error::user)?;
+
+    let repo_ref = params.repo_ref.to_string();
+    let rules_json = serde_json::to_string(&rules).map_err(Error::internal)?;
+
+    sqlx::query! {
+        "INSERT INTO lexical_rules (repo_ref, rules) VALUES (?, ?) \
+         ON CONFLICT (repo_ref) DO UPDATE SET rules = excluded.rules",
+        repo_ref,
+        rules_json,
+    }
+    .execute(&*app.sql)
+    .await?;
+
+    Ok(Json(rules))
+}
+
+/// Remove the lexical search rules of a repository.
+pub(super) async fn delete_rules(
+    Query(params): Query<RulesParams>,
+    State(app): State<Application>,
+) -> Result<impl IntoResponse> {
+    let repo_ref = params.repo_ref.to_string();
+
+    sqlx::query!("DELETE FROM lexical_rules WHERE repo_ref = ?", repo_ref)
+        .execute(&*app.sql)
+        .await?;
+
+    Ok(Json(LexicalRules::default()))
+}
+
+async fn load_rules(db: &SqlDb, repo_ref: &RepoRef) -> anyhow::Result<LexicalRules> {
+    let repo_ref = repo_ref.to_string();
+
+    let rules = sqlx::query_scalar! {
+        "SELECT rules FROM lexical_rules WHERE repo_ref = ?",
+        repo_ref,
+    }
+    .fetch_optional(db.as_ref())
+    .await?;
+
+    Ok(match rules {
+        Some(rules) => serde_json::from_str(&rules)?,
+        None => LexicalRules::default(),
+    })
 }
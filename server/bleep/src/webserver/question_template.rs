@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+
+use super::{
+    middleware::User,
+    tenant::{self, Tenant},
+    Error, ErrorKind,
+};
+use crate::{repo::RepoRef, webserver, Application};
+use axum::extract::{Extension, Json, Path, Query};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct Create {
+    repo_ref: RepoRef,
+    name: String,
+    template: String,
+}
+
+pub async fn create(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Json(params): Json<Create>,
+) -> webserver::Result<String> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    let parameters = parameters(&params.template);
+    if parameters.is_empty() {
+        return Err(Error::user("template does not have any `{parameters}`"));
+    }
+
+    let repo_ref = params.repo_ref.to_string();
+    let id = sqlx::query!(
+        "INSERT INTO question_templates (repo_ref, user_id, name, template) VALUES (?, ?, ?, ?)",
+        repo_ref,
+        user_id,
+        params.name,
+        params.template,
+    )
+    .execute(&*app.sql)
+    .await?
+    .last_insert_rowid();
+
+    Ok(id.to_string())
+}
+
+#[derive(Deserialize)]
+pub struct List {
+    repo_ref: RepoRef,
+}
+
+#[derive(Serialize)]
+pub struct QuestionTemplate {
+    id: i64,
+    name: String,
+    template: String,
+    parameters: Vec<String>,
+    user_id: String,
+    modified_at: NaiveDateTime,
+}
+
+pub async fn list(
+    app: Extension<Application>,
+    tenant: Option<Extension<Tenant>>,
+    Query(params): Query<List>,
+) -> webserver::Result<Json<Vec<QuestionTemplate>>> {
+    if !tenant::allows(&tenant, &params.repo_ref) {
+        return Err(Error::new(ErrorKind::NotFound, "Can't find repository"));
+    }
+
+    let repo_ref = params.repo_ref.to_string();
+
+    let templates = sqlx::query!(
+        "SELECT id, name, template, user_id, modified_at
+        FROM question_templates
+        WHERE repo_ref = ?
+        ORDER BY name",
+        repo_ref,
+    )
+    .fetch_all(&*app.sql)
+    .await?
+    .into_iter()
+    .map(|row| QuestionTemplate {
+        id: row.id,
+        parameters: parameters(&row.template),
+        name: row.name,
+        template: row.template,
+        user_id: row.user_id,
+        modified_at: row.modified_at,
+    })
+    .collect();
+
+    Ok(Json(templates))
+}
+
+pub async fn delete(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(id): Path<i64>,
+) -> webserver::Result<()> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    sqlx::query!(
+        "DELETE FROM question_templates WHERE id = ? AND user_id = ? RETURNING id",
+        id,
+        user_id
+    )
+    .fetch_optional(&*app.sql)
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown template ID"))
+    .map(|_| ())
+}
+
+#[derive(Serialize)]
+pub struct Ask {
+    q: String,
+    repo_ref: RepoRef,
+}
+
+/// Fill in the parameters of a template, returning a query for `/answer`.
+pub async fn instantiate(
+    app: Extension<Application>,
+    tenant: Option<Extension<Tenant>>,
+    Path(id): Path<i64>,
+    Json(values): Json<HashMap<String, String>>,
+) -> webserver::Result<Json<Ask>> {
+    let row = sqlx::query!(
+        "SELECT repo_ref, template FROM question_templates WHERE id = ?",
+        id,
+    )
+    .fetch_optional(&*app.sql)
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown template ID"))?;
+
+    // Template IDs are sequential, so the templates of other tenants must look like unknown ones.
+    let repo_ref = tenant::parse_repo(&row.repo_ref)
+        .ok()
+        .filter(|repo| tenant::allows(&tenant, repo))
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown template ID"))?;
+
+    let q = render(&row.template, &values)
+        .map_err(|missing| Error::user(format!("missing value for parameter `{missing}`")))?;
+
+    Ok(Json(Ask { q, repo_ref }))
+}
+
+/// Split a template into literal text and `{parameter}` references.
+fn tokenize(template: &str) -> impl Iterator<Item = Result<&str, &str>> {
+    let mut rest = template;
+
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+
+        let parameter = rest.strip_prefix('{').and_then(|tail| {
+            let end = tail.find('}')?;
+            let name = &tail[..end];
+            let valid =
+                !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+            valid.then_some(name)
+        });
+
+        if let Some(name) = parameter {
+            rest = &rest[name.len() + 2..];
+            return Some(Err(name));
+        }
+
+        // Advance to the next possible parameter, always consuming at least one character.
+        let first = rest.chars().next().map_or(0, char::len_utf8);
+        let end = rest[first..].find('{').map_or(rest.len(), |i| i + first);
+        let (literal, tail) = rest.split_at(end);
+        rest = tail;
+        Some(Ok(literal))
+    })
+}
+
+/// The unique parameter names of a template, in order of appearance.
+fn parameters(template: &str) -> Vec<String> {
+    let mut parameters = Vec::<String>::new();
+
+    for name in tokenize(template).filter_map(Result::err) {
+        if !parameters.iter().any(|p| p == name) {
+            parameters.push(name.to_owned());
+        }
+    }
+
+    parameters
+}
+
+/// Substitute parameter values into a template.
+///
+/// Returns the name of the first missing parameter on failure.
+fn render(template: &str, values: &HashMap<String, String>) -> Result<String, String> {
+    tokenize(template)
+        .map(|token| match token {
+            Ok(literal) => Ok(literal),
+            Err(name) => values
+                .get(name)
+                .map(String::as_str)
+                .ok_or_else(|| name.to_owned()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_parameters() {
+        assert_eq!(
+            parameters("How does {service} handle {event}? Does {service} retry?"),
+            ["service", "event"]
+        );
+
+        assert_eq!(
+            parameters("No {} params {with spaces} here {"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn renders_templates() {
+        let values = HashMap::from([
+            ("service".to_owned(), "billing".to_owned()),
+            ("event".to_owned(), "refunds".to_owned()),
+        ]);
+
+        assert_eq!(
+            render("How does {service} handle {event}?", &values).unwrap(),
+            "How does billing handle refunds?"
+        );
+
+        assert_eq!(
+            render("Keeps {} and {not valid} as-is, {service}", &values).unwrap(),
+            "Keeps {} and {not valid} as-is, billing"
+        );
+
+        assert_eq!(
+            render("What calls {function}?", &values).unwrap_err(),
+            "function"
+        );
+    }
+}
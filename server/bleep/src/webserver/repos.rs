@@ -1,8 +1,16 @@
-use std::{collections::HashSet, hash::Hash, time::Duration};
+use std::{collections::HashSet, hash::Hash, num::NonZeroU32, time::Duration};
 
 use crate::{
     background::{QueuedRepoStatus, SyncConfig},
-    repo::{Backend, BranchFilterConfig, FileFilterConfig, RepoRef, Repository, SyncStatus},
+    remotes::{bitbucket, gitlab},
+    repo::{
+        api_surface::{ApiQuery, ApiSurface},
+        changes,
+        health::HealthReport,
+        Backend, BranchFilterConfig, FileFilterConfig, FilterUpdate, RepoRef, Repository,
+        SyncStatus,
+    },
+    semantic::coverage,
     state::RepositoryPool,
     Application,
 };
@@ -13,9 +21,18 @@ use axum::{
     Extension, Json,
 };
 use chrono::{DateTime, NaiveDateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 
-use super::{middleware::User, prelude::*};
+use super::{
+    limits::{self, Usage},
+    middleware::User,
+    prelude::*,
+    tenant::{self, Tenant},
+};
+
+mod channel;
+mod purge;
+pub(super) mod webhooks;
 
 #[derive(Serialize, Debug, PartialEq, Eq)]
 pub(crate) struct Branch {
@@ -37,6 +54,8 @@ pub(crate) struct Repo {
     pub(super) branch_filter: BranchFilterConfig,
     pub(super) file_filter: FileFilterConfig,
     pub(super) branches: Vec<Branch>,
+    pub(super) clone_depth: Option<NonZeroU32>,
+    pub(super) sparse_paths: Vec<String>,
 }
 
 impl From<(&RepoRef, &Repository)> for Repo {
@@ -149,6 +168,8 @@ impl From<(&RepoRef, &Repository)> for Repo {
             file_filter: repo.file_filter.clone(),
             branch_filter,
             branches,
+            clone_depth: repo.clone_depth,
+            sparse_paths: repo.sparse_paths.clone(),
         }
     }
 }
@@ -171,6 +192,56 @@ impl Repo {
             branch_filter: crate::repo::BranchFilterConfig::Select(vec![]),
             file_filter: Default::default(),
             branches: vec![],
+            clone_depth: None,
+            sparse_paths: vec![],
+        }
+    }
+}
+
+impl Repo {
+    pub(crate) fn from_gitlab(
+        local_duplicates: Vec<RepoRef>,
+        repo_ref: RepoRef,
+        origin: &gitlab::Project,
+    ) -> Self {
+        Repo {
+            provider: Backend::Gitlab,
+            name: repo_ref.display_name(),
+            repo_ref,
+            sync_status: SyncStatus::Uninitialized,
+            local_duplicates,
+            last_update: origin.last_activity_at,
+            last_index: None,
+            most_common_lang: None,
+            branch_filter: crate::repo::BranchFilterConfig::Select(vec![]),
+            file_filter: Default::default(),
+            branches: vec![],
+            clone_depth: None,
+            sparse_paths: vec![],
+        }
+    }
+}
+
+impl Repo {
+    pub(crate) fn from_bitbucket(
+        local_duplicates: Vec<RepoRef>,
+        repo_ref: RepoRef,
+        origin: &bitbucket::RemoteRepo,
+    ) -> Self {
+        Repo {
+            provider: Backend::Bitbucket,
+            name: repo_ref.display_name(),
+            repo_ref,
+            sync_status: SyncStatus::Uninitialized,
+            local_duplicates,
+            last_update: origin.updated_on.unwrap_or_default(),
+            last_index: None,
+            most_common_lang: None,
+            branch_filter: crate::repo::BranchFilterConfig::Select(vec![]),
+            file_filter: Default::default(),
+            branches: vec![],
+            clone_depth: None,
+            sparse_paths: vec![],
         }
     }
 }
@@ -197,9 +268,17 @@ pub(crate) enum ReposResponse {
     Item(Repo),
     SyncQueue(Vec<QueuedRepoStatus>),
     SyncQueued,
+    Changes(changes::Changes),
+    ApiSurface(ApiSurface),
+    Health(HealthRecord),
+    HealthHistory(Vec<HealthRecord>),
+    Embeddings(EmbeddingCoverage),
+    Deliveries(Vec<webhooks::DeliveryRecord>),
     #[cfg(feature = "ee-pro")]
     Unchanged,
     Deleted,
+    Purged(purge::Reclaimed),
+    PurgePreview(purge::Reclaimed),
 }
 
 impl super::ApiResponse for ReposResponse {}
@@ -220,18 +299,41 @@ pub(super) fn router() -> Router {
         .route("/queue", get(queue))
         .route("/status", get(index_status))
         .route("/indexed", indexed)
+        .route("/purge", delete(purge::purge))
         .route("/sync", get(sync).delete(delete_sync))
+        .route("/changes", get(search_changes))
+        .route("/api-surface", get(api_surface))
+        .route("/channel", get(channel::list).post(channel::ask))
+        .route(
+            "/symbols/:name/references",
+            get(super::intelligence::symbol_references),
+        )
+        .route("/health", get(health))
+        .route("/health/history", get(health_history))
+        .route("/embeddings", get(embeddings))
+        .route("/webhooks/:source/deliveries", get(webhooks::deliveries))
+        .route(
+            "/webhooks/:source/deliveries/:id/redeliver",
+            post(webhooks::redeliver),
+        )
 }
 
 /// Get a stream of status notifications about the indexing of each repository
 /// This endpoint opens an SSE stream
 //
-pub(super) async fn index_status(Extension(app): Extension<Application>) -> impl IntoResponse {
+pub(super) async fn index_status(
+    Extension(app): Extension<Application>,
+    tenant: Option<Extension<Tenant>>,
+) -> impl IntoResponse {
     let mut receiver = app.sync_queue.subscribe();
 
     Sse::new(async_stream::stream! {
         loop {
             if let Ok(event) = receiver.recv().await {
+                if !tenant::allows(&tenant, &event.reporef) {
+                    continue;
+                }
+
                 yield sse::Event::default().json_data(event).map_err(Box::new);
             }
         }
@@ -253,12 +355,40 @@ pub(crate) struct RepoParams {
     pub(crate) repo: RepoRef,
     #[serde(default)]
     pub(crate) shallow: bool,
+    /// Fetch only this many commits of history, and keep it that way on later syncs.
+    ///
+    /// `depth=null` fetches the full history again.
+    #[serde(default, deserialize_with = "deserialize_depth")]
+    pub(crate) depth: Option<Option<NonZeroU32>>,
+    /// Re-index every file, rather than only those that changed since the last index
+    #[serde(default)]
+    pub(crate) force: bool,
+}
+
+/// Read a missing `depth` as keeping the depth of the repository, and `null` as clearing it.
+fn deserialize_depth<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Option<NonZeroU32>>, D::Error> {
+    match Option::<String>::deserialize(deserializer)?.as_deref() {
+        None => Ok(None),
+        Some("null") => Ok(Some(None)),
+        Some(depth) => depth
+            .parse()
+            .map(|depth| Some(Some(depth)))
+            .map_err(|_| D::Error::custom("`depth` must be a positive number, or `null`")),
+    }
 }
 
 /// Live report of the state of the sync queue
 //
-pub(super) async fn queue(State(app): State<Application>) -> impl IntoResponse {
-    json(ReposResponse::SyncQueue(app.sync_queue.read_queue().await))
+pub(super) async fn queue(
+    State(app): State<Application>,
+    tenant: Option<Extension<Tenant>>,
+) -> impl IntoResponse {
+    let mut queue = app.sync_queue.read_queue().await;
+    queue.retain(|status| tenant::allows(&tenant, &status.reporef));
+
+    json(ReposResponse::SyncQueue(queue))
 }
 
 /// Retrieve all indexed repositories
@@ -266,12 +396,15 @@ pub(super) async fn queue(State(app): State<Application>) -> impl IntoResponse {
 pub(super) async fn indexed(
     Query(IndexedParams { repo }): Query<IndexedParams>,
     app: State<Application>,
+    tenant: Option<Extension<Tenant>>,
 ) -> Result<impl IntoResponse> {
     if let Some(repo) = repo {
         return get_by_id(
             Query(RepoParams {
                 repo,
                 shallow: false,
+                depth: None,
+                force: false,
             }),
             app,
         )
@@ -281,7 +414,11 @@ pub(super) async fn indexed(
     let mut repos = vec![];
     app.0
         .repo_pool
-        .scan_async(|k, v| repos.push(Repo::from((k, v))))
+        .scan_async(|k, v| {
+            if tenant::allows(&tenant, k) {
+                repos.push(Repo::from((k, v)))
+            }
+        })
         .await;
 
     Ok(json(ReposResponse::List(repos)))
@@ -302,6 +439,210 @@ pub(super) async fn get_by_id(
     }
 }
 
+#[derive(Deserialize)]
+pub(super) struct ChangesParams {
+    repo: RepoRef,
+    #[serde(default)]
+    q: String,
+    path: Option<String>,
+}
+
+/// Search the working tree of a local repository for changes that haven't been indexed yet
+//
+pub(super) async fn search_changes(
+    Query(ChangesParams { repo, q, path }): Query<ChangesParams>,
+    State(app): State<Application>,
+) -> Result<impl IntoResponse> {
+    if !repo.is_local() {
+        return Err(Error::user("only local repositories have a working tree"));
+    }
+
+    let (disk_path, indexed_commit, last_index_unix_secs) = app
+        .repo_pool
+        .read_async(&repo, |_, repo| {
+            (
+                repo.disk_path.clone(),
+                repo.indexed_commit.clone(),
+                repo.last_index_unix_secs,
+            )
+        })
+        .await
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "Can't find repository"))?;
+
+    let changes = tokio::task::spawn_blocking(move || {
+        changes::search(
+            &disk_path,
+            indexed_commit.as_deref(),
+            last_index_unix_secs,
+            &q,
+            path.as_deref(),
+        )
+    })
+    .await
+    .map_err(Error::internal)?
+    .map_err(Error::user)?;
+
+    Ok(json(ReposResponse::Changes(changes)))
+}
+
+#[derive(Deserialize)]
+pub(super) struct ApiSurfaceParams {
+    repo: RepoRef,
+    #[serde(default)]
+    q: String,
+    returns: Option<String>,
+}
+
+/// The services, endpoints and messages declared in the OpenAPI specs and protobuf files of an
+/// indexed repository
+//
+pub(super) async fn api_surface(
+    Query(ApiSurfaceParams { repo, q, returns }): Query<ApiSurfaceParams>,
+    State(app): State<Application>,
+) -> Result<impl IntoResponse> {
+    let commit = app
+        .repo_pool
+        .read_async(&repo, |_, r| r.indexed_commit.clone())
+        .await
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "Can't find repository"))?;
+
+    let surface = ApiSurface::for_repo(&app.indexes.file, &repo, commit.as_deref()).await;
+    let found = surface.search(&ApiQuery {
+        terms: &q,
+        returns: returns.as_deref(),
+    });
+
+    Ok(json(ReposResponse::ApiSurface(found)))
+}
+
+/// A health report, as written by the scheduled analysis.
+#[derive(Serialize, Debug)]
+pub(crate) struct HealthRecord {
+    /// The commit that was indexed when the report was written
+    indexed_commit: Option<String>,
+    created_at: NaiveDateTime,
+    report: HealthReport,
+    /// A summary of the report by the LLM, if one could be written
+    summary: Option<String>,
+}
+
+/// The latest health report of an indexed repository
+//
+pub(super) async fn health(
+    Query(RepoParams { repo, .. }): Query<RepoParams>,
+    State(app): State<Application>,
+) -> Result<impl IntoResponse> {
+    health_records(&app, &repo, 1)
+        .await?
+        .pop()
+        .map(|record| json(ReposResponse::Health(record)))
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "No health report for repository"))
+}
+
+/// Every health report kept for an indexed repository, latest first
+//
+pub(super) async fn health_history(
+    Query(RepoParams { repo, .. }): Query<RepoParams>,
+    State(app): State<Application>,
+) -> Result<impl IntoResponse> {
+    let records = health_records(&app, &repo, i64::MAX).await?;
+    Ok(json(ReposResponse::HealthHistory(records)))
+}
+
+async fn health_records(
+    app: &Application,
+    repo: &RepoRef,
+    limit: i64,
+) -> Result<Vec<HealthRecord>> {
+    if !app.repo_pool.contains_async(repo).await {
+        return Err(Error::new(ErrorKind::NotFound, "Can't find repository"));
+    }
+
+    let repo_str = repo.to_string();
+    sqlx::query!(
+        "SELECT indexed_commit, created_at, report, summary FROM repo_health_reports \
+         WHERE repo_ref = ? \
+         ORDER BY created_at DESC, id DESC \
+         LIMIT ?",
+        repo_str,
+        limit,
+    )
+    .fetch_all(&*app.sql)
+    .await?
+    .into_iter()
+    .map(|row| {
+        Ok(HealthRecord {
+            indexed_commit: row.indexed_commit,
+            created_at: row.created_at,
+            report: serde_json::from_str(&row.report).map_err(Error::internal)?,
+            summary: row.summary,
+        })
+    })
+    .collect()
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct EmbeddingCoverage {
+    /// Chunks currently cached as embedded, over every index of the repository
+    chunks: i64,
+    /// When the last index finished
+    finished_at: Option<i64>,
+    /// The last index that embedded at least one chunk
+    last_embedded_at: Option<i64>,
+    /// The last index that failed to embed or store at least one chunk
+    last_failed_at: Option<i64>,
+    /// What the last index embedded and skipped. Missing if the repository wasn't indexed since
+    /// coverage started being recorded.
+    last_run: Option<coverage::Run>,
+}
+
+/// How much of an indexed repository is embedded, and why chunks were skipped
+//
+pub(super) async fn embeddings(
+    Query(RepoParams { repo, .. }): Query<RepoParams>,
+    State(app): State<Application>,
+) -> Result<impl IntoResponse> {
+    if !app.repo_pool.contains_async(&repo).await {
+        return Err(Error::new(ErrorKind::NotFound, "Can't find repository"));
+    }
+
+    let repo_str = repo.to_string();
+    let chunks = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!: i64" FROM chunk_cache WHERE repo_ref = ?"#,
+        repo_str,
+    )
+    .fetch_one(&*app.sql)
+    .await?;
+
+    let row = sqlx::query!(
+        "SELECT last_run, finished_at, last_embedded_at, last_failed_at \
+         FROM embedding_coverage \
+         WHERE repo_ref = ?",
+        repo_str,
+    )
+    .fetch_optional(&*app.sql)
+    .await?;
+
+    let coverage = match row {
+        Some(row) => EmbeddingCoverage {
+            chunks,
+            finished_at: Some(row.finished_at),
+            last_embedded_at: row.last_embedded_at,
+            last_failed_at: row.last_failed_at,
+            last_run: Some(serde_json::from_str(&row.last_run).map_err(Error::internal)?),
+        },
+        None => EmbeddingCoverage {
+            chunks,
+            finished_at: None,
+            last_embedded_at: None,
+            last_failed_at: None,
+            last_run: None,
+        },
+    };
+
+    Ok(json(ReposResponse::Embeddings(coverage)))
+}
+
 /// Delete a repository from the disk and any indexes
 //
 pub(super) async fn delete_by_id(
@@ -328,15 +669,34 @@ pub(super) async fn delete_by_id(
 
 /// Synchronize a repo by its id
 pub(super) async fn sync(
-    Query(RepoParams { repo, shallow }): Query<RepoParams>,
+    Query(RepoParams {
+        repo,
+        shallow,
+        depth,
+        force,
+    }): Query<RepoParams>,
     State(app): State<Application>,
     Extension(user): Extension<User>,
 ) -> Result<impl IntoResponse> {
+    limits::record(&app, &user, Usage::IndexingJob).await?;
+
     // TODO: We can refactor `repo_pool` to also hold queued repos, instead of doing a calculation
     // like this which is prone to timing issues.
     let num_repos = app.repo_pool.len();
+    let sparse_paths = super::workspace::repo_paths::sparse_paths(&app, &repo).await?;
+    let filter_updates = sparse_paths.map(|sparse_paths| FilterUpdate {
+        sparse_paths: Some(sparse_paths),
+        ..Default::default()
+    });
+
     app.write_index()
-        .enqueue(SyncConfig::new(app.clone(), repo).shallow(shallow))
+        .enqueue(
+            SyncConfig::new(app.clone(), repo)
+                .shallow(shallow)
+                .clone_depth(depth)
+                .force(force)
+                .filter_updates(filter_updates),
+        )
         .await;
 
     app.with_analytics(|analytics| {
@@ -357,7 +717,10 @@ pub(super) async fn delete_sync(
 
 /// List all repositories that are either indexed, or available for indexing
 //
-pub(super) async fn available(State(app): State<Application>) -> impl IntoResponse {
+pub(super) async fn available(
+    State(app): State<Application>,
+    tenant: Option<Extension<Tenant>>,
+) -> impl IntoResponse {
     let unknown_github = app
         .credentials
         .github()
@@ -393,7 +756,68 @@ pub(super) async fn available(State(app): State<Application>) -> impl IntoRespon
         })
         .collect::<HashSet<_>>();
 
-    let repos = list_unique_repos(app.repo_pool.clone(), unknown_github).await;
+    let unknown_gitlab = app
+        .credentials
+        .gitlab()
+        .map(|gl| {
+            gl.projects
+                .iter()
+                .filter_map(|project| {
+                    let repo_ref = gl.repo_ref(&project.path_with_namespace).ok()?;
+                    let clone_urls = [&project.http_url_to_repo, &project.ssh_url_to_repo]
+                        .map(|url| url.to_lowercase());
+
+                    let mut local_duplicates = vec![];
+                    app.repo_pool.scan(|k, v| {
+                        if clone_urls.contains(&v.remote.to_string().to_lowercase()) {
+                            local_duplicates.push(k.clone())
+                        }
+                    });
+
+                    Some(Repo::from_gitlab(local_duplicates, repo_ref, project))
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let unknown_bitbucket = app
+        .credentials
+        .bitbucket()
+        .map(|bb| {
+            bb.repositories
+                .iter()
+                .filter_map(|repo| {
+                    let repo_ref = bb.repo_ref(&repo.path).ok()?;
+                    let clone_urls = repo
+                        .clone_urls
+                        .iter()
+                        .map(|url| url.to_lowercase())
+                        .collect::<Vec<_>>();
+
+                    let mut local_duplicates = vec![];
+                    app.repo_pool.scan(|k, v| {
+                        if clone_urls.contains(&v.remote.to_string().to_lowercase()) {
+                            local_duplicates.push(k.clone())
+                        }
+                    });
+
+                    Some(Repo::from_bitbucket(local_duplicates, repo_ref, repo))
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let mut repos = list_unique_repos(
+        app.repo_pool.clone(),
+        unknown_github
+            .into_iter()
+            .chain(unknown_gitlab)
+            .chain(unknown_bitbucket)
+            .collect(),
+    )
+    .await;
+    repos.retain(|repo| tenant::allows(&tenant, &repo.repo_ref));
+
     (StatusCode::OK, Json(ReposResponse::List(repos)))
 }
 
@@ -404,10 +828,13 @@ pub(super) struct SetIndexed {
 
 /// Update the list of repositories that are currently being indexed.
 /// This will automatically trigger a sync of currently un-indexed repositories.
+///
+/// With tenant isolation, only the repositories of the tenant are removed when left out.
 //
 pub(super) async fn set_indexed(
     State(app): State<Application>,
     Extension(user): Extension<User>,
+    tenant: Option<Extension<Tenant>>,
     Json(new_list): Json<SetIndexed>,
 ) -> impl IntoResponse {
     let mut repo_list = new_list.indexed.into_iter().collect::<HashSet<_>>();
@@ -418,7 +845,7 @@ pub(super) async fn set_indexed(
 
     app.repo_pool
         .for_each_async(|k, existing| {
-            if !repo_list.contains(k) {
+            if !repo_list.contains(k) && tenant::allows(&tenant, k) {
                 existing.mark_removed();
                 repo_list.insert(k.to_owned());
             }
@@ -481,11 +908,13 @@ async fn list_unique_repos(repo_pool: RepositoryPool, other: HashSet<Repo>) -> V
 
 #[cfg(test)]
 mod test {
-    use std::collections::HashSet;
+    use std::{collections::HashSet, num::NonZeroU32};
+
+    use axum::{extract::Query, http::Uri};
 
     use crate::repo::{GitProtocol, GitRemote, RepoRef, RepoRemote::Git, Repository, SyncStatus};
 
-    use super::{list_unique_repos, Repo, RepositoryPool};
+    use super::{list_unique_repos, Repo, RepoParams, RepositoryPool};
 
     #[tokio::test]
     async fn unique_repos_only() {
@@ -502,6 +931,7 @@ mod test {
                     }),
                     sync_status: SyncStatus::Done,
                     last_commit_unix_secs: 123456,
+                    indexed_commit: Default::default(),
                     last_index_unix_secs: 123456,
                     most_common_lang: Default::default(),
                     branch_filter: Default::default(),
@@ -509,6 +939,8 @@ mod test {
                     pub_sync_status: Default::default(),
                     locked: Default::default(),
                     shallow: Default::default(),
+                    clone_depth: Default::default(),
+                    sparse_paths: Default::default(),
                 },
             )
             .unwrap();
@@ -524,6 +956,7 @@ mod test {
                     }),
                     sync_status: SyncStatus::Done,
                     last_commit_unix_secs: 123456,
+                    indexed_commit: Default::default(),
                     last_index_unix_secs: 123456,
                     most_common_lang: Default::default(),
                     branch_filter: Default::default(),
@@ -531,6 +964,8 @@ mod test {
                     pub_sync_status: Default::default(),
                     locked: Default::default(),
                     shallow: Default::default(),
+                    clone_depth: Default::default(),
+                    sparse_paths: Default::default(),
                 },
             )
             .unwrap();
@@ -548,6 +983,7 @@ mod test {
                     }),
                     sync_status: SyncStatus::Uninitialized,
                     last_commit_unix_secs: 123456,
+                    indexed_commit: Default::default(),
                     last_index_unix_secs: 0,
                     most_common_lang: Default::default(),
                     branch_filter: Default::default(),
@@ -555,6 +991,8 @@ mod test {
                     pub_sync_status: Default::default(),
                     locked: Default::default(),
                     shallow: Default::default(),
+                    clone_depth: Default::default(),
+                    sparse_paths: Default::default(),
                 },
             )
                 .into(),
@@ -571,6 +1009,7 @@ mod test {
                 }),
                 sync_status: SyncStatus::Uninitialized,
                 last_commit_unix_secs: 123456,
+                indexed_commit: Default::default(),
                 last_index_unix_secs: 0,
                 most_common_lang: Default::default(),
                 branch_filter: Default::default(),
@@ -578,6 +1017,8 @@ mod test {
                 pub_sync_status: Default::default(),
                 locked: Default::default(),
                 shallow: Default::default(),
+                clone_depth: Default::default(),
+                sparse_paths: Default::default(),
             },
         )
             .into();
@@ -599,4 +1040,20 @@ mod test {
             unique
         );
     }
+
+    #[test]
+    fn parses_clone_depths() {
+        let depth = |query: &str| {
+            let uri = format!("/repos/sync?repo=github.com/org/repo{query}")
+                .parse::<Uri>()
+                .unwrap();
+            Query::<RepoParams>::try_from_uri(&uri).map(|params| params.0.depth)
+        };
+
+        assert_eq!(depth("").unwrap(), None);
+        assert_eq!(depth("&depth=50").unwrap(), Some(NonZeroU32::new(50)));
+        assert_eq!(depth("&depth=null").unwrap(), Some(None));
+        assert!(depth("&depth=0").is_err());
+        assert!(depth("&depth=full").is_err());
+    }
 }
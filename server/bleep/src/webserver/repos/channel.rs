@@ -0,0 +1,219 @@
+//! A channel per repository, where short questions get a single answer without starting a
+//! conversation.
+//!
+//! A question is answered from what a semantic search finds for it, with one model call and none
+//! of the tools of the agent. Everyone who can read the repository sees its channel, and messages
+//! are deleted after `channel_retention_days`, or once a repository has [`MAX_MESSAGES`].
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    agent::prompts,
+    llm_gateway,
+    query::parser::{Literal, SemanticQuery},
+    repo::RepoRef,
+    semantic::SemanticSearchParams,
+    webserver::{
+        self,
+        answer::default_answer_model,
+        limits::{self, Usage},
+        middleware::User,
+        tenant::{self, Tenant},
+        workspace, Error, ErrorKind,
+    },
+    Application,
+};
+
+/// The longest a question can be, in characters.
+const MAX_QUESTION_CHARS: usize = 500;
+
+/// The most messages kept per repository, older ones are deleted first.
+const MAX_MESSAGES: i64 = 1000;
+
+/// The number of code chunks a question is answered from.
+const CONTEXT_CHUNKS: u64 = 5;
+
+/// The number of messages returned at once.
+const PAGE_SIZE: i64 = 50;
+
+#[derive(Serialize)]
+pub(in crate::webserver) struct Message {
+    id: i64,
+    user_id: String,
+    question: String,
+    answer: String,
+    created_at: i64,
+}
+
+#[derive(Deserialize)]
+pub(in crate::webserver) struct ListParams {
+    repo: RepoRef,
+    /// Only messages older than the message with this ID, to page back
+    before: Option<i64>,
+}
+
+/// The messages of a repository's channel, newest first.
+pub(in crate::webserver) async fn list(
+    Query(params): Query<ListParams>,
+    State(app): State<Application>,
+    tenant: Option<Extension<Tenant>>,
+) -> webserver::Result<Json<Vec<Message>>> {
+    check_repo(&app, &tenant, &params.repo).await?;
+
+    let repo_ref = params.repo.to_string();
+    let messages = sqlx::query_as!(
+        Message,
+        r#"SELECT id AS "id!", user_id, question, answer, created_at
+        FROM repo_channel_messages
+        WHERE repo_ref = ?1 AND (?2 IS NULL OR id < ?2)
+        ORDER BY id DESC
+        LIMIT ?3"#,
+        repo_ref,
+        params.before,
+        PAGE_SIZE,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    Ok(Json(messages))
+}
+
+#[derive(Deserialize)]
+pub(in crate::webserver) struct Ask {
+    repo: RepoRef,
+    question: String,
+}
+
+/// Answer a question in a repository's channel.
+pub(in crate::webserver) async fn ask(
+    State(app): State<Application>,
+    Extension(user): Extension<User>,
+    tenant: Option<Extension<Tenant>>,
+    Json(params): Json<Ask>,
+) -> webserver::Result<Json<Message>> {
+    check_repo(&app, &tenant, &params.repo).await?;
+
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_owned();
+
+    let question = params.question.trim();
+    if question.is_empty() || question.chars().count() > MAX_QUESTION_CHARS {
+        return Err(Error::user(format!(
+            "questions must be 1 to {MAX_QUESTION_CHARS} characters long, ask longer ones in a \
+            conversation"
+        )));
+    }
+
+    limits::record(&app, &user, Usage::LlmCall).await?;
+
+    // Repositories in a workspace inherit its model policy and quotas
+    let mut model = default_answer_model();
+    if let Some(policy) = workspace::Policy::for_repo(&app.sql, &user, &params.repo).await? {
+        policy.record_answer(&app.sql, &user_id).await?;
+
+        if let Some(Ok(policy_model)) = policy.answer_model.as_deref().map(str::parse) {
+            model = policy_model;
+        }
+    }
+
+    let context = context(&app, &params.repo, question).await?;
+
+    let llm_gateway = user
+        .llm_gateway(&app)
+        .await
+        .map_err(|e| Error::user(e).with_status(StatusCode::UNAUTHORIZED))?
+        .model(model.model_name)
+        .temperature(0.0);
+
+    let messages = [
+        llm_gateway::api::Message::system(&prompts::channel_prompt(
+            &params.repo.display_name(),
+            &context,
+        )),
+        llm_gateway::api::Message::user(question),
+    ];
+    let answer = llm_gateway.chat(&messages, None).await?;
+    let answer = answer.trim();
+
+    let repo_ref = params.repo.to_string();
+    let message = sqlx::query_as!(
+        Message,
+        r#"INSERT INTO repo_channel_messages (repo_ref, user_id, question, answer)
+        VALUES (?, ?, ?, ?)
+        RETURNING id AS "id!", user_id, question, answer, created_at AS "created_at!""#,
+        repo_ref,
+        user_id,
+        question,
+        answer,
+    )
+    .fetch_one(&*app.sql)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM repo_channel_messages
+        WHERE repo_ref = ?1 AND id <= (
+            SELECT id FROM repo_channel_messages WHERE repo_ref = ?1
+            ORDER BY id DESC LIMIT 1 OFFSET ?2
+        )",
+        repo_ref,
+        MAX_MESSAGES,
+    )
+    .execute(&*app.sql)
+    .await?;
+
+    Ok(Json(message))
+}
+
+async fn check_repo(
+    app: &Application,
+    tenant: &Option<Extension<Tenant>>,
+    repo: &RepoRef,
+) -> webserver::Result<()> {
+    if !tenant::allows(tenant, repo) || !app.repo_pool.contains_async(repo).await {
+        return Err(Error::new(ErrorKind::NotFound, "Can't find repository"));
+    }
+
+    Ok(())
+}
+
+/// The code that a semantic search for the question finds, as the model reads it.
+async fn context(app: &Application, repo: &RepoRef, question: &str) -> webserver::Result<String> {
+    let query = SemanticQuery {
+        target: Some(Literal::Plain(question.into())),
+        repos: vec![Literal::Plain(repo.display_name().into())],
+        ..Default::default()
+    };
+
+    let chunks = app
+        .semantic
+        .search(
+            &query,
+            SemanticSearchParams {
+                limit: CONTEXT_CHUNKS,
+                offset: 0,
+                threshold: 0.3,
+                exact_match: false,
+            },
+        )
+        .await?;
+
+    Ok(chunks
+        .iter()
+        .map(|chunk| {
+            format!(
+                "{}:{}-{}\n{}\n\n",
+                chunk.relative_path,
+                chunk.start_line + 1,
+                chunk.end_line + 1,
+                chunk.text
+            )
+        })
+        .collect())
+}
@@ -0,0 +1,232 @@
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Extension,
+};
+use serde::Serialize;
+use tracing::info;
+
+use super::{RepoParams, ReposResponse};
+use crate::{
+    remotes,
+    repo::RepoRef,
+    storage::dir_size,
+    webserver::{answer::conversations, dry_run::DryRun, json, Error, ErrorKind, Result},
+    Application,
+};
+
+/// How long to wait for the sync queue to remove the repository.
+const REMOVAL_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Space reclaimed by removing a repository, per store.
+#[derive(Serialize, Default, Debug)]
+pub(crate) struct Reclaimed {
+    /// The git clone on disk. Local repositories are never deleted from disk.
+    ///
+    /// Clones in the shared object store also count the objects that only they used, which are
+    /// only known after the fact and left out of dry runs.
+    clone_bytes: u64,
+    /// Tantivy drops deleted documents from disk as their segments are merged, so some of the
+    /// space may only be reclaimed later. This is only known after the fact, and is 0 in dry runs.
+    tantivy_bytes: u64,
+    vector_points: u64,
+    /// The uncompressed size of the vectors of the removed points
+    vector_bytes: u64,
+    /// File and chunk caches, and metadata like retrieval settings and workspace attachments
+    sqlite_rows: u64,
+    sqlite_bytes: u64,
+}
+
+/// Remove a repository and everything stored about it, reporting the space reclaimed.
+///
+/// Conversations about the repository are kept, as they belong to their users, but their
+/// citations are flagged as unverifiable. The repository is detached from every workspace, as
+/// there is nothing left to search. A dry run reports what would be reclaimed, without removing
+/// anything.
+pub(in crate::webserver) async fn purge(
+    Query(RepoParams { repo, .. }): Query<RepoParams>,
+    Extension(dry_run): Extension<DryRun>,
+    State(app): State<Application>,
+) -> Result<impl IntoResponse> {
+    let disk_path = app
+        .repo_pool
+        .read_async(&repo, |_, r| r.disk_path.clone())
+        .await
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "Repo not found"))?;
+
+    let repo_str = repo.to_string();
+    let shared = !repo.is_local() && remotes::objects::is_shared(&app.config, &disk_path);
+    let clone_bytes = if repo.is_local() {
+        0
+    } else {
+        dir_size(disk_path).await
+    };
+
+    let store = remotes::objects::store_path(&app.config);
+    let store_before = if shared {
+        dir_size(store.clone()).await
+    } else {
+        0
+    };
+
+    let tantivy_before = tantivy_size(&app).await;
+    let sqlite_before = sqlite_usage(&app, &repo_str).await?;
+    let points_before = app
+        .semantic
+        .count_points_for_repo(&repo_str)
+        .await
+        .map_err(Error::internal)?;
+
+    if dry_run.is_set() {
+        return Ok(json(ReposResponse::PurgePreview(Reclaimed {
+            clone_bytes,
+            tantivy_bytes: 0,
+            vector_points: points_before,
+            vector_bytes: points_before * app.semantic.vector_bytes(),
+            sqlite_rows: sqlite_before.rows as u64,
+            sqlite_bytes: sqlite_before.bytes as u64,
+        })));
+    }
+
+    app.write_index().remove(repo.clone()).await;
+    wait_until_removed(&app, &repo).await?;
+    delete_metadata(&app, &repo_str).await?;
+
+    let unverified =
+        conversations::unverify_citations(&app.sql, &repo_str, "the repository was removed")
+            .await
+            .map_err(Error::internal)?;
+
+    let sqlite_after = sqlite_usage(&app, &repo_str).await?;
+    let points_after = app
+        .semantic
+        .count_points_for_repo(&repo_str)
+        .await
+        .map_err(Error::internal)?;
+
+    let pruned_bytes = if shared {
+        store_before.saturating_sub(dir_size(store).await)
+    } else {
+        0
+    };
+
+    let vector_points = points_before.saturating_sub(points_after);
+    let reclaimed = Reclaimed {
+        clone_bytes: clone_bytes + pruned_bytes,
+        tantivy_bytes: tantivy_before.saturating_sub(tantivy_size(&app).await),
+        vector_points,
+        vector_bytes: vector_points * app.semantic.vector_bytes(),
+        sqlite_rows: sqlite_before.rows.saturating_sub(sqlite_after.rows) as u64,
+        sqlite_bytes: sqlite_before.bytes.saturating_sub(sqlite_after.bytes) as u64,
+    };
+
+    info!(%repo, ?reclaimed, unverified, "purged repository");
+
+    Ok(json(ReposResponse::Purged(reclaimed)))
+}
+
+/// Wait for the sync queue to delete the repository from the pool and its indexes.
+async fn wait_until_removed(app: &Application, repo: &RepoRef) -> Result<()> {
+    let start = Instant::now();
+    while app.repo_pool.contains_async(repo).await || app.write_index().is_pending(repo).await {
+        if start.elapsed() > REMOVAL_TIMEOUT {
+            return Err(Error::internal(
+                "timed out waiting for the repository to be removed",
+            ));
+        }
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    Ok(())
+}
+
+/// Delete the rows that only make sense while the repository is indexed.
+///
+/// The file and chunk caches are cleared when the repository is removed from the indexes, but we
+/// clear them here too, in case an earlier removal was interrupted.
+async fn delete_metadata(app: &Application, repo_ref: &str) -> Result<()> {
+    let mut transaction = app.sql.begin().await?;
+
+    sqlx::query!("DELETE FROM file_cache WHERE repo_ref = ?", repo_ref)
+        .execute(&mut transaction)
+        .await?;
+    sqlx::query!("DELETE FROM chunk_cache WHERE repo_ref = ?", repo_ref)
+        .execute(&mut transaction)
+        .await?;
+    sqlx::query!(
+        "DELETE FROM tutorial_questions WHERE repo_ref = ?",
+        repo_ref
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM question_templates WHERE repo_ref = ?",
+        repo_ref
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM retrieval_settings WHERE repo_ref = ?",
+        repo_ref
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!("DELETE FROM repo_freshness WHERE repo_ref = ?", repo_ref)
+        .execute(&mut transaction)
+        .await?;
+    sqlx::query!("DELETE FROM workspace_repos WHERE repo_ref = ?", repo_ref)
+        .execute(&mut transaction)
+        .await?;
+    sqlx::query!("DELETE FROM notifications WHERE repo_ref = ?", repo_ref)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+struct SqliteUsage {
+    rows: i64,
+    bytes: i64,
+}
+
+/// The rows stored about a repository, and the approximate size of their contents.
+async fn sqlite_usage(app: &Application, repo_ref: &str) -> Result<SqliteUsage> {
+    let usage = sqlx::query_as!(
+        SqliteUsage,
+        r#"WITH sizes AS (
+            SELECT length(cache_hash) + length(repo_ref) AS bytes
+                FROM file_cache WHERE repo_ref = ?1
+            UNION ALL SELECT length(chunk_hash) + length(file_hash) + length(branches)
+                + length(repo_ref) FROM chunk_cache WHERE repo_ref = ?1
+            UNION ALL SELECT length(question) + length(tag) + length(repo_ref)
+                FROM tutorial_questions WHERE repo_ref = ?1
+            UNION ALL SELECT length(name) + length(template) + length(repo_ref)
+                FROM question_templates WHERE repo_ref = ?1
+            UNION ALL SELECT length(repo_ref) FROM retrieval_settings WHERE repo_ref = ?1
+            UNION ALL SELECT length(repo_ref) FROM repo_freshness WHERE repo_ref = ?1
+            UNION ALL SELECT length(repo_ref) FROM workspace_repos WHERE repo_ref = ?1
+            UNION ALL SELECT length(message) + length(repo_ref)
+                FROM notifications WHERE repo_ref = ?1
+        )
+        SELECT count(*) AS "rows!: i64", COALESCE(sum(bytes), 0) AS "bytes!: i64" FROM sizes"#,
+        repo_ref,
+    )
+    .fetch_one(&*app.sql)
+    .await?;
+
+    Ok(usage)
+}
+
+async fn tantivy_size(app: &Application) -> u64 {
+    let mut size = 0;
+    for name in ["repo", "content"] {
+        size += dir_size(app.config.index_path(name).as_ref().to_owned()).await;
+    }
+
+    size
+}
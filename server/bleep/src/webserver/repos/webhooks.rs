@@ -0,0 +1,577 @@
+//! Webhooks that resync repositories as soon as they are pushed to, rather than when they are
+//! next polled.
+//!
+//! Remotes can't sign in, so webhooks are served without authentication, and checked against the
+//! secret they are configured with instead.
+//!
+//! Every delivery is logged with its outcome, including those that fail to authenticate, so that
+//! a misconfigured webhook can be told apart from one that never fires. Anyone can send those, so
+//! fewer of them are kept, and they never push authenticated deliveries out of the log.
+//! Deliveries that named a repository can be redelivered by hand, which resyncs it as the
+//! original delivery would have.
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::NaiveDateTime;
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use tracing::{debug, warn};
+
+use super::ReposResponse;
+use crate::{
+    background::SyncConfig,
+    repo::{Backend, BranchFilter, RepoRef, Repository},
+    webserver::{
+        json,
+        limits::{self, Usage},
+        middleware::User,
+        tenant::{self, Tenant},
+        Error, ErrorKind, Response, Result,
+    },
+    Application,
+};
+
+/// The fields we use of GitLab push and tag push events.
+#[derive(Deserialize)]
+pub(in crate::webserver) struct GitlabEvent {
+    object_kind: String,
+    project: GitlabProject,
+}
+
+#[derive(Deserialize)]
+struct GitlabProject {
+    path_with_namespace: String,
+}
+
+/// How many deliveries are logged per webhook.
+const MAX_DELIVERIES: i64 = 500;
+
+/// How many deliveries that failed to authenticate are logged per webhook, on top of the others.
+const MAX_UNAUTHORIZED_DELIVERIES: i64 = 20;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(in crate::webserver) enum Source {
+    Github,
+    Gitlab,
+    SyncHook,
+}
+
+impl Source {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Github => "github",
+            Self::Gitlab => "gitlab",
+            Self::SyncHook => "sync_hook",
+        }
+    }
+}
+
+/// A delivery being handled, which is logged once it has been.
+struct Delivery {
+    source: Source,
+    start: Instant,
+    event: Option<String>,
+    repo: Option<RepoRef>,
+    branch: Option<String>,
+    is_head: Option<bool>,
+    outcome: &'static str,
+    redelivery_of: Option<i64>,
+}
+
+impl Delivery {
+    fn start(source: Source) -> Self {
+        Self {
+            source,
+            start: Instant::now(),
+            event: None,
+            repo: None,
+            branch: None,
+            is_head: None,
+            outcome: "unchanged",
+            redelivery_of: None,
+        }
+    }
+
+    /// Log the delivery. Failing to log it doesn't fail the delivery.
+    async fn finish<T>(self, app: &Application, result: &Result<T>) {
+        let (status, outcome) = match result {
+            Ok(_) => (StatusCode::OK, self.outcome.to_owned()),
+            Err(err) => (err.status, err.to_string()),
+        };
+
+        let source = self.source.as_str();
+        let status = i64::from(status.as_u16());
+        let latency_ms = self.start.elapsed().as_millis() as i64;
+        let repo_ref = self.repo.as_ref().map(RepoRef::to_string);
+
+        let logged = sqlx::query! {
+            "INSERT INTO webhook_deliveries \
+             (source, event, repo_ref, branch, is_head, status, outcome, latency_ms, redelivery_of) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            source,
+            self.event,
+            repo_ref,
+            self.branch,
+            self.is_head,
+            status,
+            outcome,
+            latency_ms,
+            self.redelivery_of,
+        }
+        .execute(&*app.sql)
+        .await;
+
+        // Unauthorized deliveries are pruned on their own, so that they can't flush the others.
+        let unauthorized = status == i64::from(StatusCode::UNAUTHORIZED.as_u16());
+        let limit = if unauthorized {
+            MAX_UNAUTHORIZED_DELIVERIES
+        } else {
+            MAX_DELIVERIES
+        };
+
+        let pruned = sqlx::query! {
+            "DELETE FROM webhook_deliveries \
+             WHERE source = ? AND (status = 401) = ? AND id NOT IN ( \
+                 SELECT id FROM webhook_deliveries \
+                 WHERE source = ? AND (status = 401) = ? \
+                 ORDER BY id DESC \
+                 LIMIT ? \
+             )",
+            source,
+            unauthorized,
+            source,
+            unauthorized,
+            limit,
+        }
+        .execute(&*app.sql)
+        .await;
+
+        if let Err(err) = logged.and(pruned) {
+            warn!(?err, source, "failed to log webhook delivery");
+        }
+    }
+}
+
+/// Resync a GitLab repository that was pushed to, if it is indexed
+pub(in crate::webserver) async fn gitlab(
+    State(app): State<Application>,
+    headers: HeaderMap,
+    Json(event): Json<GitlabEvent>,
+) -> Result<impl IntoResponse> {
+    let mut delivery = Delivery::start(Source::Gitlab);
+    delivery.event = Some(event.object_kind.clone());
+
+    let result = gitlab_event(&app, &headers, event, &mut delivery).await;
+    delivery.finish(&app, &result).await;
+    result
+}
+
+async fn gitlab_event(
+    app: &Application,
+    headers: &HeaderMap,
+    event: GitlabEvent,
+    delivery: &mut Delivery,
+) -> Result<Json<Response<'static>>> {
+    let secret = app
+        .config
+        .gitlab_webhook_secret
+        .as_ref()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "GitLab webhooks are not configured"))?;
+
+    let token = headers
+        .get("X-Gitlab-Token")
+        .map(|token| token.as_bytes())
+        .unwrap_or_default();
+
+    if ring::constant_time::verify_slices_are_equal(token, secret.expose_secret().as_bytes())
+        .is_err()
+    {
+        return Err(Error::user("invalid webhook token").with_status(StatusCode::UNAUTHORIZED));
+    }
+
+    if !matches!(event.object_kind.as_str(), "push" | "tag_push") {
+        return Ok(json(ReposResponse::Unchanged));
+    }
+
+    // Repositories are named after the instance they were added from
+    let Some(repo) = app
+        .credentials
+        .gitlab()
+        .and_then(|gl| gl.repo_ref(&event.project.path_with_namespace).ok())
+    else {
+        return Ok(json(ReposResponse::Unchanged));
+    };
+
+    resync(app, repo, None, delivery).await
+}
+
+/// The fields we use of GitHub push events.
+#[derive(Deserialize)]
+struct GithubPush {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    repository: GithubRepository,
+}
+
+#[derive(Deserialize)]
+struct GithubRepository {
+    full_name: String,
+    default_branch: String,
+}
+
+/// Resync a GitHub repository that was pushed to, if it indexes the branch that was pushed
+pub(in crate::webserver) async fn github(
+    State(app): State<Application>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse> {
+    let mut delivery = Delivery::start(Source::Github);
+    let result = github_event(&app, &headers, &body, &mut delivery).await;
+    delivery.finish(&app, &result).await;
+    result
+}
+
+async fn github_event(
+    app: &Application,
+    headers: &HeaderMap,
+    body: &[u8],
+    delivery: &mut Delivery,
+) -> Result<Json<Response<'static>>> {
+    let secret = app
+        .config
+        .github_webhook_secret
+        .as_ref()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "GitHub webhooks are not configured"))?;
+
+    verify_github_signature(secret.expose_secret(), headers, body)?;
+
+    // Other events, like the `ping` sent when a webhook is created, don't change the index
+    let event = headers
+        .get("X-GitHub-Event")
+        .and_then(|event| event.to_str().ok());
+    delivery.event = event.map(str::to_owned);
+    if event != Some("push") {
+        return Ok(json(ReposResponse::Unchanged));
+    }
+
+    let push: GithubPush = serde_json::from_slice(body).map_err(Error::user)?;
+
+    // Tags aren't indexed
+    let Some(branch) = push.git_ref.strip_prefix("refs/heads/") else {
+        return Ok(json(ReposResponse::Unchanged));
+    };
+
+    let Ok(repo) = RepoRef::new(Backend::Github, &push.repository.full_name) else {
+        return Ok(json(ReposResponse::Unchanged));
+    };
+
+    let is_head = branch == push.repository.default_branch;
+    resync(app, repo, Some((branch, is_head)), delivery).await
+}
+
+/// GitHub signs the body with the secret, rather than sending the secret itself
+fn verify_github_signature(secret: &str, headers: &HeaderMap, body: &[u8]) -> Result<()> {
+    let signature = headers
+        .get("X-Hub-Signature-256")
+        .and_then(|signature| signature.to_str().ok())
+        .and_then(|signature| signature.strip_prefix("sha256="))
+        .and_then(|signature| hex::decode(signature).ok())
+        .unwrap_or_default();
+
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes());
+    ring::hmac::verify(&key, body, &signature)
+        .map_err(|_| Error::user("invalid webhook signature").with_status(StatusCode::UNAUTHORIZED))
+}
+
+#[derive(Deserialize)]
+pub(in crate::webserver) struct SyncHookParams {
+    repo_ref: RepoRef,
+}
+
+#[derive(Deserialize, Default)]
+pub(in crate::webserver) struct SyncHook {
+    /// The branch that was pushed to. Without it, the repository is resynced regardless.
+    branch: Option<String>,
+    /// The default branch of the remote, to tell whether `branch` is `HEAD`
+    default_branch: Option<String>,
+}
+
+/// Resync a repository when CI or any other remote asks, checking the configured bearer token
+pub(in crate::webserver) async fn sync_hook(
+    State(app): State<Application>,
+    Query(params): Query<SyncHookParams>,
+    headers: HeaderMap,
+    hook: Option<Json<SyncHook>>,
+) -> Result<impl IntoResponse> {
+    let mut delivery = Delivery::start(Source::SyncHook);
+    let Json(hook) = hook.unwrap_or_default();
+
+    let result = sync_hook_event(&app, params.repo_ref, &headers, hook, &mut delivery).await;
+    delivery.finish(&app, &result).await;
+    result
+}
+
+async fn sync_hook_event(
+    app: &Application,
+    repo: RepoRef,
+    headers: &HeaderMap,
+    hook: SyncHook,
+    delivery: &mut Delivery,
+) -> Result<Json<Response<'static>>> {
+    let secret = app
+        .config
+        .sync_hook_secret
+        .as_ref()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "sync hooks are not configured"))?;
+
+    verify_sync_hook_token(secret.expose_secret(), headers)?;
+
+    let pushed = hook.branch.as_deref().map(|branch| {
+        // Without the default branch, the push may have been to `HEAD`
+        let is_head = hook
+            .default_branch
+            .as_deref()
+            .map_or(true, |default| default == branch);
+        (branch, is_head)
+    });
+
+    resync(app, repo, pushed, delivery).await
+}
+
+fn verify_sync_hook_token(secret: &str, headers: &HeaderMap) -> Result<()> {
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|auth| auth.to_str().ok())
+        .and_then(|auth| auth.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    ring::constant_time::verify_slices_are_equal(token.as_bytes(), secret.as_bytes())
+        .map_err(|_| Error::user("invalid sync hook token").with_status(StatusCode::UNAUTHORIZED))
+}
+
+#[derive(Deserialize)]
+pub(in crate::webserver) struct DeliveryParams {
+    /// The most deliveries to list, newest first
+    #[serde(default = "default_delivery_limit")]
+    limit: i64,
+}
+
+fn default_delivery_limit() -> i64 {
+    50
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct DeliveryRecord {
+    id: i64,
+    /// Like `push`, if the delivery was authenticated and named its event
+    event: Option<String>,
+    /// The repository the delivery was for, if it named one that could be resolved
+    repo_ref: Option<String>,
+    branch: Option<String>,
+    is_head: Option<bool>,
+    /// The HTTP status the delivery was answered with
+    status: i64,
+    /// `sync_queued`, `unchanged`, or why the delivery failed
+    outcome: String,
+    latency_ms: i64,
+    created_at: NaiveDateTime,
+    /// The delivery this one redelivered, if it was redelivered by hand
+    redelivery_of: Option<i64>,
+}
+
+/// The latest deliveries of a webhook, newest first
+///
+/// With tenants isolated, only the deliveries for repositories of the user's tenant are listed.
+pub(in crate::webserver) async fn deliveries(
+    State(app): State<Application>,
+    tenant: Option<Extension<Tenant>>,
+    Path(source): Path<Source>,
+    Query(params): Query<DeliveryParams>,
+) -> Result<impl IntoResponse> {
+    if !(1..=MAX_DELIVERIES).contains(&params.limit) {
+        return Err(Error::user(format!(
+            "`limit` must be between 1 and {MAX_DELIVERIES}"
+        )));
+    }
+
+    // Few enough deliveries are kept to filter them all by tenant.
+    let source = source.as_str();
+    let limit = if tenant.is_some() {
+        MAX_DELIVERIES + MAX_UNAUTHORIZED_DELIVERIES
+    } else {
+        params.limit
+    };
+    let deliveries = sqlx::query_as! {
+        DeliveryRecord,
+        "SELECT id, event, repo_ref, branch, is_head, status, outcome, latency_ms, created_at, \
+            redelivery_of \
+         FROM webhook_deliveries \
+         WHERE source = ? \
+         ORDER BY id DESC \
+         LIMIT ?",
+        source,
+        limit,
+    }
+    .fetch_all(&*app.sql)
+    .await?
+    .into_iter()
+    .filter(|delivery| match &tenant {
+        None => true,
+        Some(_) => delivery
+            .repo_ref
+            .as_deref()
+            .and_then(|repo| tenant::stored_repo(repo).ok().flatten())
+            .is_some(),
+    })
+    .take(params.limit as usize)
+    .collect::<Vec<_>>();
+
+    Ok(json(ReposResponse::Deliveries(deliveries)))
+}
+
+/// Handle a logged delivery again, resyncing the repository it was for
+///
+/// This is meant for deliveries that failed, or whose sync didn't finish. The redelivery is
+/// logged as a delivery of its own.
+pub(in crate::webserver) async fn redeliver(
+    State(app): State<Application>,
+    Extension(user): Extension<User>,
+    tenant: Option<Extension<Tenant>>,
+    Path((source, id)): Path<(Source, i64)>,
+) -> Result<impl IntoResponse> {
+    let source_str = source.as_str();
+    let row = sqlx::query! {
+        "SELECT event, repo_ref, branch, is_head FROM webhook_deliveries \
+         WHERE id = ? AND source = ?",
+        id,
+        source_str,
+    }
+    .fetch_optional(&*app.sql)
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "delivery was not found"))?;
+
+    let repo = row.repo_ref.ok_or_else(|| {
+        Error::user("the delivery didn't name a repository, so it can't be redelivered")
+    })?;
+
+    // Deliveries for the repositories of other tenants look like unknown ones.
+    let repo = tenant::parse_repo(&repo)
+        .ok()
+        .filter(|repo| tenant::allows(&tenant, repo))
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "delivery was not found"))?;
+
+    limits::record(&app, &user, Usage::IndexingJob).await?;
+
+    let mut delivery = Delivery::start(source);
+    delivery.event = row.event;
+    delivery.redelivery_of = Some(id);
+
+    let pushed = row
+        .branch
+        .as_deref()
+        .map(|branch| (branch, row.is_head.unwrap_or(true)));
+
+    let result = resync(&app, repo, pushed, &mut delivery).await;
+    delivery.finish(&app, &result).await;
+    result
+}
+
+/// Queue a sync of an indexed repository, unless the branch that was pushed to isn't indexed.
+///
+/// Syncs only reindex the files that changed since the last index, so small pushes are cheap.
+async fn resync(
+    app: &Application,
+    repo: RepoRef,
+    pushed: Option<(&str, bool)>,
+    delivery: &mut Delivery,
+) -> Result<Json<Response<'static>>> {
+    delivery.repo = Some(repo.clone());
+    delivery.branch = pushed.map(|(branch, _)| branch.to_owned());
+    delivery.is_head = pushed.map(|(_, is_head)| is_head);
+
+    let indexed = app
+        .repo_pool
+        .read_async(&repo, |_, r| match pushed {
+            Some((branch, is_head)) => indexes_branch(r, branch, is_head),
+            None => true,
+        })
+        .await
+        .unwrap_or_default();
+
+    if !indexed {
+        return Ok(json(ReposResponse::Unchanged));
+    }
+
+    debug!(%repo, ?pushed, "push triggered a resync");
+    app.write_index()
+        .enqueue(SyncConfig::new(app.clone(), repo))
+        .await;
+
+    delivery.outcome = "sync_queued";
+    Ok(json(ReposResponse::SyncQueued))
+}
+
+fn indexes_branch(repo: &Repository, branch: &str, is_head: bool) -> bool {
+    let filter = repo
+        .branch_filter
+        .as_ref()
+        .map(BranchFilter::from)
+        .unwrap_or_default();
+
+    // Remote branches are indexed under the name of the remote
+    filter.filter(is_head, &format!("origin/{branch}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "It's a Secret to Everybody";
+
+    fn headers(name: &'static str, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, value.parse().unwrap());
+        headers
+    }
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes());
+        format!("sha256={}", hex::encode(ring::hmac::sign(&key, body)))
+    }
+
+    #[test]
+    fn github_signatures() {
+        let body = br#"{"zen": "Keep it logically awesome."}"#;
+
+        let signed = headers("x-hub-signature-256", &sign(SECRET, body));
+        assert!(verify_github_signature(SECRET, &signed, body).is_ok());
+
+        // Signed with another secret, or for another body
+        let wrong = headers("x-hub-signature-256", &sign("another secret", body));
+        let err = verify_github_signature(SECRET, &wrong, body).unwrap_err();
+        assert_eq!(err.status, StatusCode::UNAUTHORIZED);
+        assert!(verify_github_signature(SECRET, &signed, b"{}").is_err());
+
+        let err = verify_github_signature(SECRET, &HeaderMap::new(), body).unwrap_err();
+        assert_eq!(err.status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn sync_hook_tokens() {
+        let bearer = |token: &str| headers("authorization", &format!("Bearer {token}"));
+
+        assert!(verify_sync_hook_token(SECRET, &bearer(SECRET)).is_ok());
+
+        let err = verify_sync_hook_token(SECRET, &bearer("guessed")).unwrap_err();
+        assert_eq!(err.status, StatusCode::UNAUTHORIZED);
+        assert!(verify_sync_hook_token(SECRET, &headers("authorization", SECRET)).is_err());
+        assert!(verify_sync_hook_token(SECRET, &HeaderMap::new()).is_err());
+    }
+}
@@ -1,24 +1,43 @@
 use super::prelude::*;
 use crate::{
+    background,
+    intelligence::structural,
     query::{
         execute::{
             ApiQuery, FileResultData, PagingMetadata, QueryResponse, QueryResult, ResultStats,
         },
         parser::{self},
     },
+    repo::{self, RepoRef},
     semantic::{self, Semantic},
+    Application,
 };
+use axum::extract::State;
 use tracing::error;
 
 pub(super) async fn semantic_code(
-    Query(args): Query<ApiQuery>,
+    Query(mut args): Query<ApiQuery>,
     Extension(semantic): Extension<Semantic>,
+    State(app): State<Application>,
 ) -> impl IntoResponse {
+    let _interactive = background::interactive();
+    args.branch =
+        repo::indexed_branch(&app.repo_pool, args.repo_ref.as_ref(), args.branch.take()).await;
     match parser::parse_nl(&args.q.clone()) {
-        Ok(q) => semantic::execute::execute(semantic, q, args)
-            .await
-            .map(json)
-            .map_err(super::Error::from),
+        Ok(mut q) => {
+            if q.branch.is_empty() {
+                q.branch.extend(
+                    args.branch
+                        .clone()
+                        .map(|b| parser::Literal::Plain(b.into())),
+                );
+            }
+
+            semantic::execute::execute(semantic, q, args)
+                .await
+                .map(json)
+                .map_err(super::Error::from)
+        }
         Err(err) => {
             error!(?err, "Couldn't parse query");
             Err(Error::new(ErrorKind::UpstreamService, "error"))
@@ -29,7 +48,9 @@ pub(super) async fn semantic_code(
 pub(super) async fn fuzzy_path(
     Query(args): Query<ApiQuery>,
     Extension(indexes): Extension<Arc<Indexes>>,
+    State(app): State<Application>,
 ) -> Result<impl IntoResponse> {
+    let _interactive = background::interactive();
     let q = parser::parse_nl(&args.q).map_err(|err| {
         error!(?err, "Couldn't parse query");
         Error::new(ErrorKind::UpstreamService, "parse error")
@@ -46,14 +67,14 @@ pub(super) async fn fuzzy_path(
         Error::new(ErrorKind::UpstreamService, "No repo_ref provided")
     })?;
 
+    let branch = match q.first_branch() {
+        Some(branch) => Some(branch.into_owned()),
+        None => repo::indexed_branch(&app.repo_pool, Some(repo_ref), args.branch.clone()).await,
+    };
+
     let data = indexes
         .file
-        .skim_fuzzy_path_match(
-            repo_ref,
-            target,
-            q.first_branch().as_deref(),
-            args.page_size,
-        )
+        .skim_fuzzy_path_match(repo_ref, target, branch.as_deref(), args.page_size)
         .await
         .map(|c: crate::indexes::reader::FileDocument| {
             QueryResult::FileResult(FileResultData::new(
@@ -73,5 +94,50 @@ pub(super) async fn fuzzy_path(
         data,
         metadata: PagingMetadata::new(args.page, args.page_size, None),
         stats: ResultStats::default(),
+        explain: None,
     }))
 }
+
+#[derive(Deserialize)]
+pub(super) struct StructuralParams {
+    repo_ref: RepoRef,
+    branch: Option<String>,
+    /// Only search files under this path
+    path: Option<String>,
+    #[serde(flatten)]
+    pattern: structural::Pattern,
+    #[serde(default = "default_structural_limit")]
+    limit: usize,
+}
+
+fn default_structural_limit() -> usize {
+    structural::MAX_MATCHES
+}
+
+/// Search the syntax trees of the files of a repository.
+pub(super) async fn structural(
+    Extension(indexes): Extension<Arc<Indexes>>,
+    axum::Json(params): axum::Json<StructuralParams>,
+) -> Result<axum::Json<structural::Results>> {
+    let _interactive = background::interactive();
+
+    if !(1..=structural::MAX_MATCHES).contains(&params.limit) {
+        return Err(Error::user(format!(
+            "`limit` must be between 1 and {}",
+            structural::MAX_MATCHES
+        )));
+    }
+
+    let matcher = params.pattern.compile().map_err(Error::user)?;
+    let results = matcher
+        .search(
+            &indexes.file,
+            &params.repo_ref,
+            params.branch.as_deref(),
+            params.path.as_deref(),
+            params.limit,
+        )
+        .await;
+
+    Ok(axum::Json(results))
+}
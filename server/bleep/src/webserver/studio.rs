@@ -21,9 +21,13 @@ use uuid::Uuid;
 
 use self::diff::{DiffChunk, DiffHunk};
 
-use super::{middleware::User, Error};
+use super::{
+    limits::{self, Usage},
+    middleware::User,
+    tenant, workspace, Error,
+};
 use crate::{
-    agent::{exchange::Exchange, prompts},
+    agent::{exchange::Exchange, policy::ToolAccess, prompts},
     analytics::StudioEvent,
     llm_gateway,
     repo::RepoRef,
@@ -692,6 +696,8 @@ pub async fn generate(
 
     let snapshot_id = latest_snapshot_id(studio_id, &*app.sql, &user_id).await?;
 
+    limits::record(&app, &user, Usage::LlmCall).await?;
+
     let llm_gateway = user
         .llm_gateway(&app)
         .await
@@ -935,6 +941,8 @@ pub async fn diff(
 
     let snapshot_id = latest_snapshot_id(studio_id, &*app.sql, &user_id).await?;
 
+    limits::record(&app, &user, Usage::LlmCall).await?;
+
     let llm_gateway = user
         .llm_gateway(&app)
         .await
@@ -957,6 +965,11 @@ pub async fn diff(
     let context =
         serde_json::from_str::<Vec<ContextFile>>(&context_json).map_err(Error::internal)?;
 
+    // Check the workspace tool policy before spending any tokens on a patch.
+    if let Ok((repo, _)) = context_repo_branch(&context) {
+        workspace::require_tool(&app.sql, &user_id, &repo, "diff", ToolAccess::Suggest).await?;
+    }
+
     let user_message = messages
         .iter()
         .rev()
@@ -1286,6 +1299,7 @@ pub async fn diff_apply(
     let diff_chunks = diff::relaxed_parse(&diff);
 
     let (repo, branch) = context_repo_branch(&context)?;
+    workspace::require_tool(&app.sql, &user_id, &repo, "diff_apply", ToolAccess::Write).await?;
 
     for (i, chunk) in diff_chunks.enumerate() {
         let mut file_content = if let Some(src) = &chunk.src {
@@ -1342,6 +1356,8 @@ pub async fn diff_apply(
         Query(webserver::repos::RepoParams {
             repo,
             shallow: false,
+            depth: None,
+            force: false,
         }),
         app,
         user,
@@ -1433,9 +1449,9 @@ pub async fn import(
     let thread_id = params.thread_id.to_string();
 
     let conversation = sqlx::query! {
-        "SELECT title, repo_ref, exchanges
+        "SELECT title, repo_ref, exchanges, exchanges_zstd
         FROM conversations
-        WHERE user_id = ? AND thread_id = ?",
+        WHERE user_id = ? AND thread_id = ? AND deleted_at IS NULL",
         user_id,
         thread_id,
     }
@@ -1443,9 +1459,13 @@ pub async fn import(
     .await?
     .ok_or_else(|| Error::not_found("conversation not found"))?;
 
-    let repo_ref = conversation.repo_ref;
-    let exchanges = serde_json::from_str::<Vec<Exchange>>(&conversation.exchanges)
-        .context("couldn't deserialize exchange list")?;
+    let repo_ref = tenant::stored_repo(&conversation.repo_ref)
+        .map_err(Error::internal)?
+        .ok_or_else(|| Error::not_found("conversation not found"))?;
+    let exchanges = super::answer::conversations::deserialize_exchanges(
+        conversation.exchanges,
+        conversation.exchanges_zstd.as_deref(),
+    )?;
 
     let snapshot_id = match params.studio_id {
         None => None,
@@ -1469,7 +1489,7 @@ pub async fn import(
         e.code_chunks.iter().map(|c| ContextFile {
             path: c.path.clone(),
             hidden: false,
-            repo: repo_ref.parse().unwrap(),
+            repo: repo_ref.clone(),
             branch: e.query.branch().next().map(Cow::into_owned),
             ranges: vec![c.start_line..c.end_line + 1],
         })
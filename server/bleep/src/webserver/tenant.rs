@@ -0,0 +1,670 @@
+//! Tenants, for serving several isolated customers from a single instance.
+//!
+//! With `--tenant-isolation`, every user has to belong to a tenant, and every repository belongs
+//! to at most one. Indexes and embeddings are kept per repository, so scoping requests to the
+//! repositories of a tenant scopes the indexes and vector collections they can read as well.
+//!
+//! Repositories are checked where requests name them: [`isolate`] runs the request with the
+//! tenant of the user, and every [`RepoRef`] deserialized while handling it, from the path, the
+//! query or the body, is checked against that tenant by [`visible`]. Repositories that handlers
+//! parse themselves are checked by [`parse_repo`], or by [`stored_repo`] when they were read back
+//! from storage. Searches that pick their repositories with `repo:` filters are checked by the
+//! middleware itself. Handlers that list repositories filter them with the [`Tenant`] it inserts.
+//!
+//! Requests made with a workspace key are scoped the same way, with or without isolation, to
+//! the repositories of the workspace.
+
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::Request,
+    middleware::{from_fn_with_state, Next},
+    response::Response,
+    Json,
+};
+use chrono::NaiveDateTime;
+
+use super::{middleware::User, prelude::*, tokens::WorkspaceKey};
+use crate::{db::SqlDb, query::parser, repo::RepoRef, Application};
+
+/// Endpoints that search every repository matching the `repo:` filters of `q`.
+const SEARCHES: &[&str] = &["/q", "/autocomplete", "/search/code"];
+
+/// Endpoints that answer from the repository they are given, and that search the ones matching
+/// the `repo:` filters of `q` as well.
+const ANSWERS: &[&str] = &["/answer", "/answer/ws"];
+
+tokio::task_local! {
+    /// The tenant of the request being handled.
+    static REQUEST: Scope;
+}
+
+struct Scope {
+    tenant: Tenant,
+    /// Whether the request named a repository of another tenant
+    denied: AtomicBool,
+}
+
+/// Whether the tenant of the request being handled can use a repository.
+///
+/// This is called as [`RepoRef`]s are deserialized, so that every repository a request names is
+/// checked however it is named. Outside of requests, and without isolation, every repository is
+/// visible.
+pub(crate) fn visible(repo: &RepoRef) -> bool {
+    REQUEST
+        .try_with(|scope| {
+            let allowed = scope.tenant.allows(repo);
+            if !allowed {
+                scope.denied.store(true, Ordering::Relaxed);
+            }
+            allowed
+        })
+        .unwrap_or(true)
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Tenant {
+    pub(crate) id: i64,
+    role: String,
+    repos: Arc<HashSet<String>>,
+}
+
+impl Tenant {
+    async fn load(db: &SqlDb, user_id: &str) -> Result<Option<Self>> {
+        let Some(member) = sqlx::query!(
+            "SELECT tenant_id, role FROM tenant_members WHERE user_id = ?",
+            user_id,
+        )
+        .fetch_optional(&**db)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        let repos = sqlx::query!(
+            "SELECT repo_ref FROM tenant_repos WHERE tenant_id = ?",
+            member.tenant_id,
+        )
+        .fetch_all(&**db)
+        .await?
+        .into_iter()
+        .map(|row| row.repo_ref)
+        .collect();
+
+        Ok(Some(Self {
+            id: member.tenant_id,
+            role: member.role,
+            repos: Arc::new(repos),
+        }))
+    }
+
+    /// The repositories of the workspace of a key, within the tenant of the member who created
+    /// it when tenants are isolated.
+    async fn for_key(app: &Application, key: &WorkspaceKey) -> Result<Self> {
+        let repos = sqlx::query!(
+            "SELECT repo_ref FROM workspace_repos WHERE workspace_id = ?",
+            key.workspace_id,
+        )
+        .fetch_all(&*app.sql)
+        .await?
+        .into_iter()
+        .map(|row| row.repo_ref);
+
+        if !app.config.tenant_isolation {
+            return Ok(Self {
+                id: 0,
+                role: "member".into(),
+                repos: Arc::new(repos.collect()),
+            });
+        }
+
+        let creator = Self::load(&app.sql, &key.created_by)
+            .await?
+            .ok_or_else(|| {
+                Error::user("the creator of this key is not a member of any tenant")
+                    .with_status(StatusCode::FORBIDDEN)
+            })?;
+
+        Ok(Self {
+            id: creator.id,
+            role: "member".into(),
+            repos: Arc::new(repos.filter(|repo| creator.repos.contains(repo)).collect()),
+        })
+    }
+
+    pub(crate) fn allows(&self, repo: &RepoRef) -> bool {
+        self.repos.contains(&repo.to_string())
+    }
+
+    fn require_admin(&self) -> Result<()> {
+        if self.role == "admin" {
+            Ok(())
+        } else {
+            Err(Error::user("only tenant admins can do this").with_status(StatusCode::FORBIDDEN))
+        }
+    }
+}
+
+/// Whether a repository is visible to the user, which is always the case without isolation.
+pub(crate) fn allows(tenant: &Option<Extension<Tenant>>, repo: &RepoRef) -> bool {
+    tenant.as_ref().map_or(true, |tenant| tenant.allows(repo))
+}
+
+/// Whether two users may share a workspace, which needs them to be in the same tenant.
+pub(crate) async fn same_tenant(app: &Application, a: &str, b: &str) -> Result<bool> {
+    if !app.config.tenant_isolation {
+        return Ok(true);
+    }
+
+    let shared = sqlx::query!(
+        "SELECT a.tenant_id FROM tenant_members a
+        INNER JOIN tenant_members b ON b.tenant_id = a.tenant_id
+        WHERE a.user_id = ? AND b.user_id = ?",
+        a,
+        b,
+    )
+    .fetch_optional(&*app.sql)
+    .await?;
+
+    Ok(shared.is_some())
+}
+
+pub(super) fn isolate(router: Router, app: Application) -> Router {
+    router.layer(from_fn_with_state(app, isolate_mw))
+}
+
+async fn isolate_mw(
+    State(app): State<Application>,
+    Extension(user): Extension<User>,
+    mut request: Request<Body>,
+    next: Next<Body>,
+) -> Result<Response> {
+    let key = request.extensions().get::<WorkspaceKey>().cloned();
+    if !app.config.tenant_isolation && key.is_none() {
+        return Ok(next.run(request).await);
+    }
+
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID").with_status(StatusCode::UNAUTHORIZED))?;
+
+    // Instance admins aren't scoped to a tenant, and are checked by `admin::guard`.
+    if request.uri().path().starts_with("/admin") {
+        return Ok(next.run(request).await);
+    }
+
+    let tenant = match key {
+        Some(key) => Tenant::for_key(&app, &key).await?,
+        None => Tenant::load(&app.sql, user_id).await?.ok_or_else(|| {
+            Error::user("you are not a member of any tenant").with_status(StatusCode::FORBIDDEN)
+        })?,
+    };
+
+    let path = request.uri().path().to_owned();
+    let searches = SEARCHES.contains(&path.as_str());
+    if searches || ANSWERS.contains(&path.as_str()) {
+        let q = request.uri().query().and_then(|query| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .find(|(key, _)| key == "q")
+                .map(|(_, q)| q.into_owned())
+        });
+
+        if let Some(q) = q {
+            let mut others = vec![];
+            app.repo_pool
+                .scan_async(|repo, _| {
+                    if !tenant.allows(repo) {
+                        others.push(repo.indexed_name().to_lowercase());
+                    }
+                })
+                .await;
+
+            check_search(&path, &q, searches, &others)?;
+        }
+    }
+
+    request.extensions_mut().insert(tenant.clone());
+
+    let scope = Scope {
+        tenant,
+        denied: AtomicBool::new(false),
+    };
+
+    REQUEST
+        .scope(scope, async move {
+            let response = next.run(request).await;
+
+            // Extractors reject repositories of other tenants with errors of their own, which
+            // would tell them apart from repositories that don't exist.
+            if REQUEST.with(|scope| scope.denied.load(Ordering::Relaxed)) {
+                return Err(not_found());
+            }
+
+            Ok(response)
+        })
+        .await
+}
+
+/// Parse a repository that a request names with a plain string, checking it like deserialized
+/// ones.
+pub(crate) fn parse_repo(repo: &str) -> Result<RepoRef> {
+    let repo = repo.parse::<RepoRef>().map_err(Error::user)?;
+    if visible(&repo) {
+        Ok(repo)
+    } else {
+        Err(not_found())
+    }
+}
+
+/// Parse a repository that was stored by the instance, such as in a row of the database or the
+/// payload of a chunk, rather than named by the request.
+///
+/// Repositories the tenant of the request can't use are `None`, without failing the request like
+/// [`parse_repo`] does, so that handlers can leave them out. Handlers don't parse repositories
+/// with [`str::parse`] or [`FromStr`](std::str::FromStr), which doesn't check them.
+pub(crate) fn stored_repo(repo: &str) -> anyhow::Result<Option<RepoRef>> {
+    let repo = repo.parse::<RepoRef>()?;
+    let allowed = REQUEST
+        .try_with(|scope| scope.tenant.allows(&repo))
+        .unwrap_or(true);
+
+    Ok(allowed.then_some(repo))
+}
+
+/// The same error as for a repository that doesn't exist, so as not to tell them apart.
+fn not_found() -> Error {
+    Error::new(ErrorKind::NotFound, "Can't find repository")
+}
+
+/// Searches match `repo:` filters by substring, so each filter has to be plain, and must not match
+/// a repository of another tenant.
+///
+/// Searches without a repository to default to have to name one.
+fn check_search(path: &str, q: &str, required: bool, others: &[String]) -> Result<()> {
+    // Unparseable queries are rejected by the handler, without searching anything
+    let filters: Vec<Option<Cow<str>>> = if path == "/search/code" || !required {
+        match parser::parse_nl(q) {
+            Ok(query) => query.repos.iter().map(|repo| repo.as_plain()).collect(),
+            Err(_) => return Ok(()),
+        }
+    } else {
+        match parser::parse(q) {
+            Ok(queries) => queries
+                .iter()
+                .map(|query| query.repo.as_ref().and_then(|repo| repo.as_plain()))
+                .collect(),
+            Err(_) => return Ok(()),
+        }
+    };
+
+    if filters.is_empty() && required {
+        return Err(Error::user(
+            "searches have to name one of your repositories with `repo:`",
+        ));
+    }
+
+    for filter in filters {
+        let filter = filter
+            .ok_or_else(|| {
+                Error::user("searches have to name one of your repositories with `repo:`")
+            })?
+            .to_lowercase();
+
+        if others.iter().any(|other| other.contains(&filter)) {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("`repo:{filter}` matches repositories outside of your tenant"),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub(super) struct ListItem {
+    id: i64,
+    name: String,
+    created_at: NaiveDateTime,
+    members: i64,
+    repos: i64,
+}
+
+/// List every tenant of the instance.
+pub(super) async fn list(State(app): State<Application>) -> Result<Json<Vec<ListItem>>> {
+    let tenants = sqlx::query_as!(
+        ListItem,
+        r#"SELECT t.id, t.name, t.created_at,
+            (SELECT COUNT(*) FROM tenant_members m WHERE m.tenant_id = t.id) AS "members!: i64",
+            (SELECT COUNT(*) FROM tenant_repos r WHERE r.tenant_id = t.id) AS "repos!: i64"
+        FROM tenants t
+        ORDER BY t.name"#,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    Ok(Json(tenants))
+}
+
+#[derive(Deserialize)]
+pub(super) struct Create {
+    name: String,
+}
+
+pub(super) async fn create(
+    State(app): State<Application>,
+    Json(params): Json<Create>,
+) -> Result<String> {
+    let name = params.name.trim();
+    if name.is_empty() {
+        return Err(Error::user("tenant names can't be empty"));
+    }
+
+    sqlx::query!(
+        r#"INSERT INTO tenants (name) VALUES (?)
+        ON CONFLICT (name) DO NOTHING
+        RETURNING id AS "id!""#,
+        name,
+    )
+    .fetch_optional(&*app.sql)
+    .await?
+    .ok_or_else(|| {
+        Error::user("a tenant with this name already exists").with_status(StatusCode::CONFLICT)
+    })
+    .map(|row| row.id.to_string())
+}
+
+/// Delete a tenant, with its memberships and claims on repositories.
+///
+/// The repositories stay indexed, and can only be used again once claimed by another tenant.
+pub(super) async fn delete(State(app): State<Application>, Path(id): Path<i64>) -> Result<()> {
+    sqlx::query!("DELETE FROM tenants WHERE id = ? RETURNING id", id)
+        .fetch_optional(&*app.sql)
+        .await?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown tenant"))
+        .map(|_| ())
+}
+
+#[derive(Deserialize)]
+pub(super) struct MemberParams {
+    role: String,
+}
+
+pub(super) async fn put_member(
+    State(app): State<Application>,
+    Path((id, member_id)): Path<(i64, String)>,
+    Json(params): Json<MemberParams>,
+) -> Result<()> {
+    upsert_member(&app.sql, id, &member_id, &params.role).await
+}
+
+pub(super) async fn delete_member(
+    State(app): State<Application>,
+    Path((id, member_id)): Path<(i64, String)>,
+) -> Result<()> {
+    remove_member(&app.sql, id, &member_id).await
+}
+
+#[derive(Deserialize)]
+pub(super) struct RepoParams {
+    repo_ref: RepoRef,
+}
+
+/// Give a repository to a tenant.
+///
+/// A repository of another tenant has to be removed from it first, so that it never changes hands
+/// by accident.
+pub(super) async fn add_repo(
+    State(app): State<Application>,
+    Path(id): Path<i64>,
+    Json(params): Json<RepoParams>,
+) -> Result<()> {
+    let repo_ref = params.repo_ref.to_string();
+    let owner = sqlx::query!(
+        r#"INSERT INTO tenant_repos (repo_ref, tenant_id) VALUES (?, ?)
+        ON CONFLICT (repo_ref) DO UPDATE SET tenant_id = tenant_id
+        RETURNING tenant_id AS "tenant_id!""#,
+        repo_ref,
+        id,
+    )
+    .fetch_one(&*app.sql)
+    .await?
+    .tenant_id;
+
+    if owner == id {
+        Ok(())
+    } else {
+        Err(Error::user("repository belongs to another tenant").with_status(StatusCode::CONFLICT))
+    }
+}
+
+pub(super) async fn remove_repo(
+    State(app): State<Application>,
+    Path(id): Path<i64>,
+    Query(params): Query<RepoParams>,
+) -> Result<()> {
+    let repo_ref = params.repo_ref.to_string();
+    sqlx::query!(
+        "DELETE FROM tenant_repos WHERE tenant_id = ? AND repo_ref = ? RETURNING repo_ref",
+        id,
+        repo_ref,
+    )
+    .fetch_optional(&*app.sql)
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "repository is not in this tenant"))
+    .map(|_| ())
+}
+
+#[derive(Serialize)]
+pub(super) struct Member {
+    user_id: String,
+    role: String,
+}
+
+#[derive(Serialize)]
+pub(super) struct Details {
+    id: i64,
+    name: String,
+    created_at: NaiveDateTime,
+    role: String,
+    members: Vec<Member>,
+    repos: Vec<String>,
+}
+
+/// The tenant of the current user.
+pub(super) async fn get_own(
+    State(app): State<Application>,
+    tenant: Option<Extension<Tenant>>,
+) -> Result<Json<Details>> {
+    let Extension(tenant) = tenant.ok_or_else(not_isolated)?;
+
+    let row = sqlx::query!(
+        "SELECT name, created_at FROM tenants WHERE id = ?",
+        tenant.id,
+    )
+    .fetch_one(&*app.sql)
+    .await?;
+
+    let members = sqlx::query_as!(
+        Member,
+        "SELECT user_id, role FROM tenant_members WHERE tenant_id = ? ORDER BY user_id",
+        tenant.id,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    let mut repos = tenant.repos.iter().cloned().collect::<Vec<_>>();
+    repos.sort();
+
+    Ok(Json(Details {
+        id: tenant.id,
+        name: row.name,
+        created_at: row.created_at,
+        role: tenant.role.clone(),
+        members,
+        repos,
+    }))
+}
+
+/// Add a user to the tenant of the current user, or change their role.
+pub(super) async fn put_own_member(
+    State(app): State<Application>,
+    Extension(user): Extension<User>,
+    tenant: Option<Extension<Tenant>>,
+    Path(member_id): Path<String>,
+    Json(params): Json<MemberParams>,
+) -> Result<()> {
+    let Extension(tenant) = tenant.ok_or_else(not_isolated)?;
+    tenant.require_admin()?;
+
+    if Some(member_id.as_str()) == user.username() {
+        return Err(Error::user("cannot change your own role"));
+    }
+
+    upsert_member(&app.sql, tenant.id, &member_id, &params.role).await
+}
+
+/// Remove a user from the tenant of the current user.
+pub(super) async fn delete_own_member(
+    State(app): State<Application>,
+    Extension(user): Extension<User>,
+    tenant: Option<Extension<Tenant>>,
+    Path(member_id): Path<String>,
+) -> Result<()> {
+    let Extension(tenant) = tenant.ok_or_else(not_isolated)?;
+    tenant.require_admin()?;
+
+    if Some(member_id.as_str()) == user.username() {
+        return Err(Error::user("admins cannot leave their tenant"));
+    }
+
+    remove_member(&app.sql, tenant.id, &member_id).await
+}
+
+/// A user can only be in one tenant, so adding a member of another tenant is refused rather than
+/// moving them.
+async fn upsert_member(db: &SqlDb, id: i64, member_id: &str, role: &str) -> Result<()> {
+    if !matches!(role, "admin" | "member") {
+        return Err(Error::user("role must be one of `admin` or `member`"));
+    }
+
+    let tenant_id = sqlx::query!(
+        r#"INSERT INTO tenant_members (user_id, tenant_id, role) VALUES (?, ?, ?)
+        ON CONFLICT (user_id) DO UPDATE SET
+            role = CASE WHEN tenant_id = excluded.tenant_id THEN excluded.role ELSE role END
+        RETURNING tenant_id AS "tenant_id!""#,
+        member_id,
+        id,
+        role,
+    )
+    .fetch_one(&**db)
+    .await?
+    .tenant_id;
+
+    if tenant_id == id {
+        Ok(())
+    } else {
+        Err(Error::user("user belongs to another tenant").with_status(StatusCode::CONFLICT))
+    }
+}
+
+async fn remove_member(db: &SqlDb, id: i64, member_id: &str) -> Result<()> {
+    sqlx::query!(
+        "DELETE FROM tenant_members WHERE tenant_id = ? AND user_id = ? RETURNING user_id",
+        id,
+        member_id,
+    )
+    .fetch_optional(&**db)
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown tenant member"))
+    .map(|_| ())
+}
+
+fn not_isolated() -> Error {
+    Error::new(ErrorKind::NotFound, "tenant isolation is not enabled")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn checks_repositories_as_they_are_deserialized() {
+        #[derive(Deserialize)]
+        struct Context {
+            #[allow(unused)]
+            repo: RepoRef,
+        }
+
+        let tenant = Tenant {
+            id: 1,
+            role: "member".into(),
+            repos: Arc::new(["github.com/a/b".to_owned()].into()),
+        };
+
+        let scope = Scope {
+            tenant,
+            denied: AtomicBool::new(false),
+        };
+
+        REQUEST
+            .scope(scope, async {
+                assert!(serde_json::from_str::<RepoRef>(r#""github.com/a/b""#).is_ok());
+                assert!(!REQUEST.with(|scope| scope.denied.load(Ordering::Relaxed)));
+
+                let nested = r#"[{"repo": "github.com/a/b"}, {"repo": "github.com/c/d"}]"#;
+                assert!(serde_json::from_str::<Vec<Context>>(nested).is_err());
+                assert!(REQUEST.with(|scope| scope.denied.load(Ordering::Relaxed)));
+            })
+            .await;
+
+        // Repositories are only checked while handling requests
+        assert!(serde_json::from_str::<RepoRef>(r#""github.com/c/d""#).is_ok());
+    }
+
+    #[tokio::test]
+    async fn leaves_out_stored_repositories_of_other_tenants() {
+        let scope = Scope {
+            tenant: Tenant {
+                id: 1,
+                role: "member".into(),
+                repos: Arc::new(["github.com/a/b".to_owned()].into()),
+            },
+            denied: AtomicBool::new(false),
+        };
+
+        REQUEST
+            .scope(scope, async {
+                assert!(stored_repo("github.com/a/b").unwrap().is_some());
+                assert!(stored_repo("github.com/c/d").unwrap().is_none());
+                assert!(stored_repo("nowhere/c/d").is_err());
+
+                // Other tenants' repositories are left out, rather than failing the request
+                assert!(!REQUEST.with(|scope| scope.denied.load(Ordering::Relaxed)));
+            })
+            .await;
+
+        assert!(stored_repo("github.com/c/d").unwrap().is_some());
+    }
+
+    #[test]
+    fn searches_stay_within_the_tenant() {
+        let others = vec!["github.com/acme/bloop-fork".to_owned()];
+
+        assert!(check_search("/q", "repo:bloopai/bloop foo", true, &others).is_ok());
+        assert!(check_search("/q", "repo:bloop foo", true, &others).is_err());
+        assert!(check_search("/q", "foo", true, &others).is_err());
+        assert!(check_search("/q", "repo:/bloop.*/ foo", true, &others).is_err());
+        assert!(check_search("/autocomplete", "repo:BLOOP-FORK", true, &others).is_err());
+
+        // Answers default to the repository they are asked about
+        assert!(check_search("/answer", "how does it work", false, &others).is_ok());
+        assert!(check_search("/answer", "how does repo:bloop work", false, &others).is_err());
+    }
+}
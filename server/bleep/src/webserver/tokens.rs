@@ -0,0 +1,758 @@
+//! Personal access tokens, for scripts and CI jobs that can't sign in.
+//!
+//! A token acts as the user who created it, but only on the routes its scopes allow, so that a
+//! token used to search in CI can't read conversations or ask questions. Requests made with a
+//! token carry it as `Authorization: Bearer bloop_pat_...`; other requests are authenticated as
+//! before. Tokens can't manage tokens, whatever their scopes.
+//!
+//! The token itself is only shown once, when it is created, and only a hash of it is stored. On
+//! cloud instances, requests made with a token reach upstream services through the latest session
+//! of the user it acts for, so tokens stop working once that session expires.
+//!
+//! Keys of [workspaces](super::workspace::keys) are accepted the same way, as
+//! `Bearer bloop_wk_...`. They act as a principal of their own rather than as a user, and only
+//! reach the repositories of their workspace.
+
+use std::time::Instant;
+
+use anyhow::Context;
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header::AUTHORIZATION, Method, Request},
+    middleware::{from_fn_with_state, Next},
+    response::Response,
+    Json,
+};
+use rand::Rng;
+use tower::ServiceExt;
+use tracing::warn;
+
+use super::{dry_run::route_matches, middleware::User, prelude::*};
+use crate::{env::Feature, Application};
+
+const TOKEN_PREFIX: &str = "bloop_pat_";
+
+pub(super) const KEY_PREFIX: &str = "bloop_wk_";
+
+/// The longest a token can be valid for, in days.
+pub(super) const MAX_EXPIRY_DAYS: i64 = 365;
+
+/// How many requests are logged per token.
+const MAX_REQUESTS: i64 = 200;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Scope {
+    /// Code and path search, and reading files
+    #[serde(rename = "read:search")]
+    ReadSearch,
+    /// Reading the conversations of the user
+    #[serde(rename = "read:conversations")]
+    ReadConversations,
+    /// Asking questions, which starts or continues conversations
+    #[serde(rename = "write:ask")]
+    WriteAsk,
+    /// Syncing repositories, and retrying and cancelling their jobs
+    #[serde(rename = "write:sync")]
+    SyncRepos,
+    /// Every route, except for the management of tokens
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+/// The scope needed by each route a token can call, where `:` segments match anything. Routes
+/// that aren't listed here need [`Scope::Admin`].
+const ROUTES: &[(Method, &str, Scope)] = &[
+    (Method::GET, "/q", Scope::ReadSearch),
+    (Method::GET, "/autocomplete", Scope::ReadSearch),
+    (Method::GET, "/search/code", Scope::ReadSearch),
+    (Method::GET, "/search/path", Scope::ReadSearch),
+    (Method::POST, "/search/structural", Scope::ReadSearch),
+    (Method::GET, "/file", Scope::ReadSearch),
+    (Method::GET, "/hoverable", Scope::ReadSearch),
+    (Method::GET, "/token-info", Scope::ReadSearch),
+    (Method::GET, "/token-value", Scope::ReadSearch),
+    (Method::GET, "/related-files", Scope::ReadSearch),
+    (Method::GET, "/related-files-with-ranges", Scope::ReadSearch),
+    (Method::GET, "/repos/indexed", Scope::ReadSearch),
+    (Method::GET, "/jobs", Scope::ReadSearch),
+    (Method::POST, "/jobs/:id/cancel", Scope::SyncRepos),
+    (Method::POST, "/jobs/:id/retry", Scope::SyncRepos),
+    (Method::GET, "/repos/sync", Scope::SyncRepos),
+    (Method::DELETE, "/repos/sync", Scope::SyncRepos),
+    (Method::GET, "/repos/channel", Scope::ReadSearch),
+    (Method::POST, "/repos/channel", Scope::WriteAsk),
+    (
+        Method::GET,
+        "/repos/symbols/:name/references",
+        Scope::ReadSearch,
+    ),
+    (
+        Method::GET,
+        "/answer/conversations",
+        Scope::ReadConversations,
+    ),
+    (
+        Method::GET,
+        "/answer/conversations/:thread_id",
+        Scope::ReadConversations,
+    ),
+    (
+        Method::GET,
+        "/answer/conversations/:thread_id/exchanges",
+        Scope::ReadConversations,
+    ),
+    (
+        Method::GET,
+        "/answer/conversations/:thread_id/exchanges/:exchange_id",
+        Scope::ReadConversations,
+    ),
+    (
+        Method::GET,
+        "/answer/conversations/:thread_id/export",
+        Scope::ReadConversations,
+    ),
+    (
+        Method::GET,
+        "/answer/conversations/:thread_id/usage",
+        Scope::ReadConversations,
+    ),
+    (Method::GET, "/answer", Scope::WriteAsk),
+    (Method::GET, "/answer/ws", Scope::WriteAsk),
+    (Method::GET, "/answer/explain", Scope::WriteAsk),
+    (Method::GET, "/answer/plan/execute", Scope::WriteAsk),
+    (
+        Method::POST,
+        "/answer/conversations/:thread_id/exchanges/:exchange_id/retry",
+        Scope::WriteAsk,
+    ),
+    (Method::POST, "/answer/vote", Scope::WriteAsk),
+];
+
+impl Scope {
+    /// The scope a token needs to call a route.
+    fn required(method: &Method, path: &str) -> Self {
+        ROUTES
+            .iter()
+            .find(|(m, pattern, _)| m == method && route_matches(pattern, path))
+            .map(|(.., scope)| *scope)
+            .unwrap_or(Self::Admin)
+    }
+
+    fn parse(scopes: &str) -> Vec<Self> {
+        parse_scopes(scopes)
+    }
+
+    fn join(scopes: &[Self]) -> String {
+        join_scopes(scopes)
+    }
+}
+
+/// What a workspace key can do. Keys can't have [`Scope::Admin`].
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum KeyScope {
+    /// Code and path search, and reading files
+    #[serde(rename = "search:read")]
+    SearchRead,
+    /// Asking questions, and reading the conversations of the key
+    #[serde(rename = "conversations:write")]
+    ConversationsWrite,
+    /// Syncing the repositories of the workspace
+    #[serde(rename = "repos:sync")]
+    ReposSync,
+}
+
+impl KeyScope {
+    /// The scopes of tokens that this grants.
+    fn grants(self) -> &'static [Scope] {
+        match self {
+            Self::SearchRead => &[Scope::ReadSearch],
+            Self::ConversationsWrite => &[Scope::WriteAsk, Scope::ReadConversations],
+            Self::ReposSync => &[Scope::SyncRepos],
+        }
+    }
+
+    pub(super) fn parse(scopes: &str) -> Vec<Self> {
+        parse_scopes(scopes)
+    }
+
+    pub(super) fn join(scopes: &[Self]) -> String {
+        join_scopes(scopes)
+    }
+}
+
+fn parse_scopes<T: serde::de::DeserializeOwned>(scopes: &str) -> Vec<T> {
+    scopes
+        .split_whitespace()
+        .filter_map(|scope| serde_json::from_value(scope.into()).ok())
+        .collect()
+}
+
+fn join_scopes<T: Serialize>(scopes: &[T]) -> String {
+    scopes
+        .iter()
+        .filter_map(|scope| serde_json::to_value(scope).ok())
+        .filter_map(|scope| scope.as_str().map(str::to_owned))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Whether a token with these scopes can call a route.
+fn allows(scopes: &[Scope], method: &Method, path: &str) -> bool {
+    if route_matches("/tokens", path) || path.starts_with("/tokens/") {
+        return false;
+    }
+
+    let required = Scope::required(method, path);
+    scopes
+        .iter()
+        .any(|scope| *scope == required || *scope == Scope::Admin)
+}
+
+/// Whether a workspace key with these scopes can call a route.
+fn key_allows(scopes: &[KeyScope], method: &Method, path: &str) -> bool {
+    let granted = scopes
+        .iter()
+        .flat_map(|scope| scope.grants())
+        .copied()
+        .collect::<Vec<_>>();
+
+    // None grant `Scope::Admin`, which the routes that aren't listed need, like managing keys
+    allows(&granted, method, path)
+}
+
+#[derive(Deserialize)]
+pub(super) struct Create {
+    name: String,
+    scopes: Vec<Scope>,
+    /// Stop accepting the token after this many days, instead of until revoked
+    expires_in_days: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub(super) struct Created {
+    #[serde(flatten)]
+    token: Token,
+    /// The token to authenticate with. It can't be shown again.
+    secret: String,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct Token {
+    id: i64,
+    name: String,
+    scopes: Vec<Scope>,
+    created_at: i64,
+    expires_at: Option<i64>,
+    last_used_at: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct TokenRequest {
+    method: String,
+    path: String,
+    status: i64,
+    latency_ms: i64,
+    created_at: i64,
+}
+
+/// Create a token for the user.
+pub(super) async fn create(
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+    Json(params): Json<Create>,
+) -> Result<Json<Created>> {
+    let user_id = user_id(&user)?;
+
+    let name = params.name.trim();
+    if name.is_empty() {
+        return Err(Error::user("tokens need a name"));
+    }
+
+    if params.scopes.is_empty() {
+        return Err(Error::user("tokens need at least one scope"));
+    }
+
+    if let Some(days) = params.expires_in_days {
+        if !(1..=MAX_EXPIRY_DAYS).contains(&days) {
+            return Err(Error::user(format!(
+                "tokens expire after 1 to {MAX_EXPIRY_DAYS} days"
+            )));
+        }
+    }
+
+    let secret = format!(
+        "{TOKEN_PREFIX}{}",
+        hex::encode(rand::thread_rng().gen::<[u8; 32]>())
+    );
+    let token_hash = hash(&secret);
+    let scopes = Scope::join(&params.scopes);
+    let expires_in = params.expires_in_days.map(|days| days * 24 * 3600);
+
+    let row = sqlx::query! {
+        "INSERT INTO personal_access_tokens (user_id, name, token_hash, scopes, expires_at) \
+         VALUES (?, ?, ?, ?, strftime('%s', 'now') + ?) \
+         RETURNING id AS \"id!\", created_at AS \"created_at!\", expires_at",
+        user_id,
+        name,
+        token_hash,
+        scopes,
+        expires_in,
+    }
+    .fetch_one(&*app.sql)
+    .await?;
+
+    Ok(Json(Created {
+        token: Token {
+            id: row.id,
+            name: name.to_owned(),
+            scopes: params.scopes,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            last_used_at: None,
+        },
+        secret,
+    }))
+}
+
+/// The tokens of the user that haven't been revoked or expired, newest first.
+pub(super) async fn list(
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+) -> Result<Json<Vec<Token>>> {
+    let user_id = user_id(&user)?;
+
+    let tokens = sqlx::query! {
+        "SELECT id, name, scopes, created_at, expires_at, last_used_at \
+         FROM personal_access_tokens \
+         WHERE user_id = ? AND revoked_at IS NULL \
+            AND (expires_at IS NULL OR expires_at > strftime('%s', 'now')) \
+         ORDER BY id DESC",
+        user_id,
+    }
+    .fetch_all(&*app.sql)
+    .await?
+    .into_iter()
+    .map(|row| Token {
+        id: row.id,
+        name: row.name,
+        scopes: Scope::parse(&row.scopes),
+        created_at: row.created_at,
+        expires_at: row.expires_at,
+        last_used_at: row.last_used_at,
+    })
+    .collect();
+
+    Ok(Json(tokens))
+}
+
+/// Revoke a token of the user, so that it stops being accepted.
+pub(super) async fn revoke(
+    Path(id): Path<i64>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+) -> Result<StatusCode> {
+    let user_id = user_id(&user)?;
+
+    let revoked = sqlx::query! {
+        "UPDATE personal_access_tokens SET revoked_at = strftime('%s', 'now') \
+         WHERE id = ? AND user_id = ? AND revoked_at IS NULL",
+        id,
+        user_id,
+    }
+    .execute(&*app.sql)
+    .await?
+    .rows_affected();
+
+    if revoked == 0 {
+        return Err(Error::new(ErrorKind::NotFound, "token was not found"));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The latest requests made with a token of the user, newest first, including those that were
+/// refused for lack of a scope.
+pub(super) async fn usage(
+    Path(id): Path<i64>,
+    Extension(user): Extension<User>,
+    State(app): State<Application>,
+) -> Result<Json<Vec<TokenRequest>>> {
+    let user_id = user_id(&user)?;
+
+    sqlx::query!(
+        "SELECT id FROM personal_access_tokens WHERE id = ? AND user_id = ?",
+        id,
+        user_id,
+    )
+    .fetch_optional(&*app.sql)
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "token was not found"))?;
+
+    let requests = sqlx::query_as! {
+        TokenRequest,
+        "SELECT method, path, status, latency_ms, created_at FROM personal_access_token_requests \
+         WHERE token_id = ? \
+         ORDER BY id DESC",
+        id,
+    }
+    .fetch_all(&*app.sql)
+    .await?;
+
+    Ok(Json(requests))
+}
+
+/// Authenticate requests that carry a token, and route them to `token_api` instead of `router`.
+///
+/// `token_api` should be the same routes as `router`, without the authentication of users that
+/// signed in.
+pub(super) fn authenticate(router: Router, token_api: Router, app: Application) -> Router {
+    let token_api = token_api
+        .layer(from_fn_with_state(app.clone(), token_user_mw))
+        .with_state(app.clone());
+
+    router.layer(from_fn_with_state((app, token_api), authenticate_mw))
+}
+
+/// The token a request was authenticated with.
+#[derive(Clone, Debug)]
+struct Authenticated {
+    user_id: String,
+    /// Whether this is a workspace key, which doesn't act as a user
+    key: bool,
+}
+
+/// The workspace key a request was authenticated with, which limits the repositories it reaches.
+#[derive(Clone, Debug)]
+pub(super) struct WorkspaceKey {
+    pub(super) workspace_id: i64,
+    /// The member who created the key, whose tenant the key belongs to
+    pub(super) created_by: String,
+}
+
+async fn authenticate_mw(
+    State((app, token_api)): State<(Application, axum::Router)>,
+    mut request: Request<Body>,
+    next: Next<Body>,
+) -> Result<Response> {
+    let Some(secret) = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .filter(|secret| secret.starts_with(TOKEN_PREFIX) || secret.starts_with(KEY_PREFIX))
+        .map(str::to_owned)
+    else {
+        return Ok(next.run(request).await);
+    };
+
+    if secret.starts_with(KEY_PREFIX) {
+        return authenticate_key(&app, token_api, &secret, request).await;
+    }
+
+    let token_hash = hash(&secret);
+    let token = sqlx::query! {
+        "SELECT id, user_id, scopes FROM personal_access_tokens \
+         WHERE token_hash = ? AND revoked_at IS NULL \
+            AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))",
+        token_hash,
+    }
+    .fetch_optional(&*app.sql)
+    .await?
+    .ok_or_else(|| Error::user("invalid access token").with_status(StatusCode::UNAUTHORIZED))?;
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    let start = Instant::now();
+
+    let response = if allows(&Scope::parse(&token.scopes), &method, &path) {
+        request.extensions_mut().insert(Authenticated {
+            user_id: token.user_id,
+            key: false,
+        });
+
+        match token_api.oneshot(request).await {
+            Ok(response) => response,
+            Err(infallible) => match infallible {},
+        }
+    } else {
+        Error::user(format!(
+            "this token needs the `{}` scope",
+            Scope::join(&[Scope::required(&method, &path)])
+        ))
+        .with_status(StatusCode::FORBIDDEN)
+        .into_response()
+    };
+
+    log_request(&app, token.id, &method, &path, response.status(), start).await;
+    Ok(response)
+}
+
+async fn authenticate_key(
+    app: &Application,
+    token_api: axum::Router,
+    secret: &str,
+    mut request: Request<Body>,
+) -> Result<Response> {
+    let key_hash = hash(secret);
+    let key = sqlx::query! {
+        "SELECT k.id, k.workspace_id, k.scopes, k.created_by FROM workspace_keys k \
+         INNER JOIN workspaces w ON w.id = k.workspace_id \
+         WHERE k.key_hash = ? AND k.revoked_at IS NULL AND w.deleted_at IS NULL \
+            AND (k.expires_at IS NULL OR k.expires_at > strftime('%s', 'now'))",
+        key_hash,
+    }
+    .fetch_optional(&*app.sql)
+    .await?
+    .ok_or_else(|| Error::user("invalid API key").with_status(StatusCode::UNAUTHORIZED))?;
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+
+    if !key_allows(&KeyScope::parse(&key.scopes), &method, &path) {
+        let needed = KeyScope::join(&key_scope_for(Scope::required(&method, &path)));
+        let message = if needed.is_empty() {
+            "API keys can't call this route".to_owned()
+        } else {
+            format!("this key needs the `{needed}` scope")
+        };
+
+        return Err(Error::user(message).with_status(StatusCode::FORBIDDEN));
+    }
+
+    if let Err(err) = sqlx::query! {
+        "UPDATE workspace_keys SET last_used_at = strftime('%s', 'now') WHERE id = ?",
+        key.id,
+    }
+    .execute(&*app.sql)
+    .await
+    {
+        warn!(
+            ?err,
+            key_id = key.id,
+            "failed to record use of workspace key"
+        );
+    }
+
+    request.extensions_mut().insert(Authenticated {
+        user_id: format!("key:{}", key.id),
+        key: true,
+    });
+    request.extensions_mut().insert(WorkspaceKey {
+        workspace_id: key.workspace_id,
+        created_by: key.created_by,
+    });
+
+    match token_api.oneshot(request).await {
+        Ok(response) => Ok(response),
+        Err(infallible) => match infallible {},
+    }
+}
+
+/// The key scopes that grant a token scope, if any do.
+fn key_scope_for(scope: Scope) -> Vec<KeyScope> {
+    [
+        KeyScope::SearchRead,
+        KeyScope::ConversationsWrite,
+        KeyScope::ReposSync,
+    ]
+    .into_iter()
+    .filter(|key_scope| key_scope.grants().contains(&scope))
+    .take(1)
+    .collect()
+}
+
+/// Act as the user who created the token, or as the principal of a workspace key.
+async fn token_user_mw(
+    State(app): State<Application>,
+    mut request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let Some(Authenticated { user_id, key }) = request.extensions().get().cloned() else {
+        return Error::user("missing access token")
+            .with_status(StatusCode::UNAUTHORIZED)
+            .into_response();
+    };
+
+    let workspace_key = request.extensions().get::<WorkspaceKey>().cloned();
+    let workspace_id = workspace_key.as_ref().map(|key| key.workspace_id);
+
+    let user = if app.env.allow(Feature::AuthorizationRequired) {
+        let Some(org_name) = app.credentials.github().and_then(|state| match state.auth {
+            crate::remotes::github::Auth::App { org, .. } => Some(org),
+            _ => None,
+        }) else {
+            return Error::new(ErrorKind::Configuration, "misconfigured instance").into_response();
+        };
+
+        // Keys call upstream services as the member who created them
+        let owner = match &workspace_key {
+            Some(key) => &key.created_by,
+            None => &user_id,
+        };
+
+        let Some(access_token) = app.sessions.access_token(owner) else {
+            return Error::user(format!(
+                "`{owner}` has to sign in to bloop again before this token can be used"
+            ))
+            .with_status(StatusCode::UNAUTHORIZED)
+            .into_response();
+        };
+
+        let crab_app = app.clone();
+        User::Cloud {
+            login: user_id,
+            org_name,
+            access_token,
+            workspace_id,
+            crab: Arc::new(move || {
+                let gh = crab_app.credentials.github().context("no github")?;
+                Ok(gh.client()?)
+            }),
+        }
+    } else {
+        // Instances that don't require authorization serve the user signed in to the app
+        match app.user().await {
+            // Keys keep conversations and quotas of their own
+            User::Desktop {
+                access_token, crab, ..
+            } if key => User::Desktop {
+                access_token,
+                login: user_id,
+                workspace_id,
+                crab,
+            },
+            user => user,
+        }
+    };
+
+    request.extensions_mut().insert(user);
+    next.run(request).await
+}
+
+async fn log_request(
+    app: &Application,
+    token_id: i64,
+    method: &Method,
+    path: &str,
+    status: StatusCode,
+    start: Instant,
+) {
+    let method = method.as_str();
+    let status = status.as_u16();
+    let latency_ms = start.elapsed().as_millis() as i64;
+
+    let logged = sqlx::query! {
+        "INSERT INTO personal_access_token_requests (token_id, method, path, status, latency_ms) \
+         VALUES (?, ?, ?, ?, ?)",
+        token_id,
+        method,
+        path,
+        status,
+        latency_ms,
+    }
+    .execute(&*app.sql)
+    .await;
+
+    let used = sqlx::query! {
+        "UPDATE personal_access_tokens SET last_used_at = strftime('%s', 'now') WHERE id = ?",
+        token_id,
+    }
+    .execute(&*app.sql)
+    .await;
+
+    let pruned = sqlx::query! {
+        "DELETE FROM personal_access_token_requests \
+         WHERE token_id = ? AND id NOT IN ( \
+             SELECT id FROM personal_access_token_requests \
+             WHERE token_id = ? \
+             ORDER BY id DESC \
+             LIMIT ? \
+         )",
+        token_id,
+        token_id,
+        MAX_REQUESTS,
+    }
+    .execute(&*app.sql)
+    .await;
+
+    if let Err(err) = logged.and(used).and(pruned) {
+        warn!(?err, token_id, "failed to log access token request");
+    }
+}
+
+pub(super) fn hash(secret: &str) -> String {
+    blake3::hash(secret.as_bytes()).to_string()
+}
+
+fn user_id(user: &User) -> Result<&str> {
+    user.username()
+        .ok_or_else(|| Error::user("didn't have user ID"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_routes() {
+        let search = [Scope::ReadSearch];
+        assert!(allows(&search, &Method::GET, "/q"));
+        assert!(allows(&search, &Method::GET, "/search/code"));
+        assert!(!allows(&search, &Method::GET, "/answer"));
+        assert!(!allows(&search, &Method::GET, "/answer/conversations"));
+
+        let conversations = [Scope::ReadConversations];
+        assert!(allows(
+            &conversations,
+            &Method::GET,
+            "/answer/conversations/4a3f/exchanges"
+        ));
+        assert!(!allows(
+            &conversations,
+            &Method::DELETE,
+            "/answer/conversations"
+        ));
+
+        let admin = [Scope::Admin];
+        assert!(allows(&admin, &Method::DELETE, "/repos/purge"));
+        assert!(!allows(&admin, &Method::POST, "/tokens"));
+        assert!(!allows(&admin, &Method::DELETE, "/tokens/3"));
+    }
+
+    #[test]
+    fn scopes_keys() {
+        let search = [KeyScope::SearchRead];
+        assert!(key_allows(&search, &Method::GET, "/q"));
+        assert!(!key_allows(&search, &Method::GET, "/repos/sync"));
+
+        let conversations = [KeyScope::ConversationsWrite];
+        assert!(key_allows(&conversations, &Method::GET, "/answer"));
+        assert!(key_allows(
+            &conversations,
+            &Method::GET,
+            "/answer/conversations/4a3f"
+        ));
+
+        let sync = [KeyScope::ReposSync];
+        assert!(key_allows(&sync, &Method::GET, "/repos/sync"));
+        assert!(key_allows(&sync, &Method::POST, "/jobs/12/retry"));
+
+        // Routes that need the admin scope, like managing keys
+        let all = [
+            KeyScope::SearchRead,
+            KeyScope::ConversationsWrite,
+            KeyScope::ReposSync,
+        ];
+        assert!(!key_allows(&all, &Method::POST, "/workspace/1/keys"));
+        assert!(!key_allows(&all, &Method::DELETE, "/repos/purge"));
+        assert!(!key_allows(&all, &Method::POST, "/tokens"));
+    }
+
+    #[test]
+    fn round_trips_scopes() {
+        let scopes = [Scope::ReadSearch, Scope::WriteAsk];
+        assert_eq!(Scope::join(&scopes), "read:search write:ask");
+        assert_eq!(Scope::parse("read:search write:ask unknown"), scopes);
+    }
+}
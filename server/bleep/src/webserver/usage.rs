@@ -0,0 +1,152 @@
+//! Tokens used and what they cost, per conversation and per user.
+//!
+//! Costs are summed over the calls to models with a known price. Calls to other models still
+//! count towards tokens.
+
+use axum::extract::{Path, Query, State};
+use axum::{Extension, Json};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use super::{middleware::User, Error, Result};
+use crate::Application;
+
+#[derive(Serialize, Debug, Default)]
+pub(super) struct Totals {
+    calls: i64,
+    prompt_tokens: i64,
+    completion_tokens: i64,
+    /// In USD
+    cost: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct ExchangeUsage {
+    exchange_id: String,
+    calls: i64,
+    prompt_tokens: i64,
+    completion_tokens: i64,
+    cost: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct ModelUsage {
+    model: String,
+    calls: i64,
+    prompt_tokens: i64,
+    completion_tokens: i64,
+    cost: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct ConversationUsage {
+    total: Totals,
+    /// In the order the exchanges were answered
+    exchanges: Vec<ExchangeUsage>,
+    models: Vec<ModelUsage>,
+}
+
+/// The usage of a conversation of the user, by exchange and by model.
+pub(super) async fn conversation(
+    State(app): State<Application>,
+    Extension(user): Extension<User>,
+    Path(thread_id): Path<uuid::Uuid>,
+) -> Result<Json<ConversationUsage>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+    let thread_id = thread_id.to_string();
+
+    let exchanges = sqlx::query_as!(
+        ExchangeUsage,
+        r#"SELECT exchange_id,
+            COUNT(*) AS "calls!: i64",
+            SUM(prompt_tokens) AS "prompt_tokens!: i64",
+            SUM(completion_tokens) AS "completion_tokens!: i64",
+            SUM(cost) AS "cost: f64"
+        FROM token_usage
+        WHERE user_id = ? AND thread_id = ?
+        GROUP BY exchange_id
+        ORDER BY MIN(id)"#,
+        user_id,
+        thread_id,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    let models = sqlx::query_as!(
+        ModelUsage,
+        r#"SELECT model,
+            COUNT(*) AS "calls!: i64",
+            SUM(prompt_tokens) AS "prompt_tokens!: i64",
+            SUM(completion_tokens) AS "completion_tokens!: i64",
+            SUM(cost) AS "cost: f64"
+        FROM token_usage
+        WHERE user_id = ? AND thread_id = ?
+        GROUP BY model
+        ORDER BY model"#,
+        user_id,
+        thread_id,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    let total = exchanges.iter().fold(Totals::default(), |mut total, e| {
+        total.calls += e.calls;
+        total.prompt_tokens += e.prompt_tokens;
+        total.completion_tokens += e.completion_tokens;
+        total.cost = match (total.cost, e.cost) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+        total
+    });
+
+    Ok(Json(ConversationUsage {
+        total,
+        exchanges,
+        models,
+    }))
+}
+
+#[derive(Deserialize)]
+pub(super) struct UsageParams {
+    /// Only count calls made since then
+    since: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct UserUsage {
+    user_id: String,
+    conversations: i64,
+    calls: i64,
+    prompt_tokens: i64,
+    completion_tokens: i64,
+    cost: Option<f64>,
+}
+
+/// The usage of every user, most expensive first, for attributing spend.
+pub(super) async fn by_user(
+    State(app): State<Application>,
+    Query(params): Query<UsageParams>,
+) -> Result<Json<Vec<UserUsage>>> {
+    let users = sqlx::query_as!(
+        UserUsage,
+        r#"SELECT user_id,
+            COUNT(DISTINCT thread_id) AS "conversations!: i64",
+            COUNT(*) AS "calls!: i64",
+            SUM(prompt_tokens) AS "prompt_tokens!: i64",
+            SUM(completion_tokens) AS "completion_tokens!: i64",
+            SUM(cost) AS "cost: f64"
+        FROM token_usage
+        WHERE ?1 IS NULL OR created_at >= ?1
+        GROUP BY user_id
+        ORDER BY SUM(cost) DESC, SUM(prompt_tokens) DESC"#,
+        params.since,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    Ok(Json(users))
+}
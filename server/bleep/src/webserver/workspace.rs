@@ -0,0 +1,1636 @@
+use super::{
+    answer::conversations,
+    dry_run::{Deletion, DryRun, Preview},
+    middleware::User,
+    tenant::{self, Tenant},
+    Error, ErrorKind,
+};
+use crate::{
+    agent::{
+        exchange::Exchange,
+        policy::{ToolAccess, ToolPolicy},
+    },
+    db::SqlDb,
+    repo::{Backend, RepoRef},
+    webserver, Application,
+};
+use axum::{
+    extract::{Extension, Json, Path, Query},
+    http::StatusCode,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::warn;
+
+pub mod annotations;
+pub mod estimate;
+pub mod integrations;
+pub mod keys;
+pub mod repo_filters;
+pub mod repo_paths;
+
+/// Repositories are stale after this many days behind upstream, unless a workspace sets otherwise.
+pub(crate) const DEFAULT_STALE_AFTER_DAYS: i64 = 7;
+
+/// Settings that are inherited by every repository in a workspace.
+///
+/// Unset values fall back to the application defaults.
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct Settings {
+    /// Model used for answers, overriding the model requested by the client
+    answer_model: Option<String>,
+    /// Model used for the agent, overriding the model requested by the client
+    agent_model: Option<String>,
+    /// Conversations older than this are deleted
+    retention_days: Option<i64>,
+    /// Maximum number of answers per user per day
+    daily_answer_quota: Option<i64>,
+    /// Tools that can be used on repositories, one of `read_only`, `read_suggest` or `read_write`
+    tool_policy: Option<String>,
+    /// Alert members once a repository's index is this many days behind upstream
+    stale_after_days: Option<i64>,
+}
+
+impl Settings {
+    fn validate(&self) -> webserver::Result<()> {
+        const MODELS: &[&str] = &["gpt-4", "gpt-4-turbo-24k", "gpt-3.5-turbo-finetuned"];
+
+        for model in [&self.answer_model, &self.agent_model]
+            .into_iter()
+            .flatten()
+        {
+            if !MODELS.contains(&model.as_str()) {
+                return Err(Error::user(format!("unknown model `{model}`")));
+            }
+        }
+
+        if matches!(self.retention_days, Some(days) if days < 1) {
+            return Err(Error::user("`retention_days` must be at least 1"));
+        }
+
+        if matches!(self.daily_answer_quota, Some(quota) if quota < 0) {
+            return Err(Error::user("`daily_answer_quota` cannot be negative"));
+        }
+
+        if let Some(policy) = &self.tool_policy {
+            policy.parse::<ToolPolicy>().map_err(Error::user)?;
+        }
+
+        if matches!(self.stale_after_days, Some(days) if days < 1) {
+            return Err(Error::user("`stale_after_days` must be at least 1"));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Create {
+    name: String,
+    #[serde(default)]
+    settings: Settings,
+}
+
+pub async fn create(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Json(params): Json<Create>,
+) -> webserver::Result<String> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    params.settings.validate()?;
+    let Settings {
+        answer_model,
+        agent_model,
+        retention_days,
+        daily_answer_quota,
+        tool_policy,
+        stale_after_days,
+    } = params.settings;
+
+    let mut transaction = app.sql.begin().await?;
+
+    let id = sqlx::query!(
+        "INSERT INTO workspaces (
+            name, answer_model, agent_model, retention_days, daily_answer_quota, tool_policy,
+            stale_after_days
+        )
+        VALUES (?, ?, ?, ?, ?, ?, ?)",
+        params.name,
+        answer_model,
+        agent_model,
+        retention_days,
+        daily_answer_quota,
+        tool_policy,
+        stale_after_days,
+    )
+    .execute(&mut transaction)
+    .await?
+    .last_insert_rowid();
+
+    sqlx::query!(
+        "INSERT INTO workspace_members (workspace_id, user_id, role) VALUES (?, ?, 'owner')",
+        id,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(id.to_string())
+}
+
+#[derive(Serialize)]
+pub struct ListItem {
+    id: i64,
+    name: String,
+    created_at: NaiveDateTime,
+    role: String,
+}
+
+pub async fn list(
+    app: Extension<Application>,
+    user: Extension<User>,
+) -> webserver::Result<Json<Vec<ListItem>>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    let workspaces = sqlx::query_as!(
+        ListItem,
+        "SELECT w.id, w.name, w.created_at, m.role
+        FROM workspaces w
+        INNER JOIN workspace_members m ON m.workspace_id = w.id
+        WHERE m.user_id = ? AND w.deleted_at IS NULL
+        ORDER BY w.created_at DESC",
+        user_id,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    Ok(Json(workspaces))
+}
+
+#[derive(Serialize)]
+pub struct Member {
+    user_id: String,
+    role: String,
+}
+
+#[derive(Serialize)]
+pub struct Workspace {
+    id: i64,
+    name: String,
+    created_at: NaiveDateTime,
+    role: String,
+    settings: Settings,
+    members: Vec<Member>,
+    repos: Vec<String>,
+    /// The directories this workspace needs of its sparsely attached repositories
+    repo_paths: BTreeMap<String, Vec<String>>,
+    freshness: Vec<RepoFreshness>,
+}
+
+/// How far the index of a repository is behind its upstream default branch.
+#[derive(Serialize)]
+pub struct RepoFreshness {
+    repo_ref: String,
+    behind_commits: i64,
+    /// Days since the oldest upstream commit that isn't indexed
+    behind_days: Option<i64>,
+    stale: bool,
+    checked_at: NaiveDateTime,
+}
+
+pub async fn get(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(id): Path<i64>,
+) -> webserver::Result<Json<Workspace>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    let role = member_role(&app.sql, id, &user_id).await?;
+
+    let row = sqlx::query!(
+        "SELECT name, created_at, answer_model, agent_model, retention_days, daily_answer_quota,
+            tool_policy, stale_after_days
+        FROM workspaces
+        WHERE id = ?",
+        id,
+    )
+    .fetch_one(&*app.sql)
+    .await?;
+
+    let members = sqlx::query_as!(
+        Member,
+        "SELECT user_id, role FROM workspace_members WHERE workspace_id = ?",
+        id,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    let attached = sqlx::query!(
+        "SELECT repo_ref, paths FROM workspace_repos WHERE workspace_id = ?",
+        id,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    let mut repos = vec![];
+    let mut repo_paths = BTreeMap::new();
+    for row in attached {
+        if let Some(paths) = row.paths {
+            let paths = serde_json::from_str(&paths).map_err(Error::internal)?;
+            repo_paths.insert(row.repo_ref.clone(), paths);
+        }
+
+        repos.push(row.repo_ref);
+    }
+
+    let stale_after_days = row.stale_after_days.unwrap_or(DEFAULT_STALE_AFTER_DAYS);
+    let freshness = sqlx::query!(
+        r#"SELECT f.repo_ref, f.behind_commits, f.checked_at,
+            CAST(julianday('now') - julianday(f.oldest_unindexed_at) AS INTEGER) AS "behind_days?: i64"
+        FROM repo_freshness f
+        INNER JOIN workspace_repos r ON r.repo_ref = f.repo_ref
+        WHERE r.workspace_id = ?
+        ORDER BY f.repo_ref"#,
+        id,
+    )
+    .fetch_all(&*app.sql)
+    .await?
+    .into_iter()
+    .map(|row| RepoFreshness {
+        stale: matches!(row.behind_days, Some(days) if days >= stale_after_days),
+        repo_ref: row.repo_ref,
+        behind_commits: row.behind_commits,
+        behind_days: row.behind_days,
+        checked_at: row.checked_at,
+    })
+    .collect();
+
+    Ok(Json(Workspace {
+        id,
+        name: row.name,
+        created_at: row.created_at,
+        role,
+        settings: Settings {
+            answer_model: row.answer_model,
+            agent_model: row.agent_model,
+            retention_days: row.retention_days,
+            daily_answer_quota: row.daily_answer_quota,
+            tool_policy: row.tool_policy,
+            stale_after_days: row.stale_after_days,
+        },
+        members,
+        repos,
+        repo_paths,
+        freshness,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct Patch {
+    name: Option<String>,
+    settings: Option<Settings>,
+}
+
+pub async fn patch(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(id): Path<i64>,
+    Json(patch): Json<Patch>,
+) -> webserver::Result<()> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    require_owner(&app.sql, id, &user_id).await?;
+
+    let mut transaction = app.sql.begin().await?;
+
+    if let Some(name) = patch.name {
+        sqlx::query!("UPDATE workspaces SET name = ? WHERE id = ?", name, id)
+            .execute(&mut transaction)
+            .await?;
+    }
+
+    if let Some(settings) = patch.settings {
+        settings.validate()?;
+        let Settings {
+            answer_model,
+            agent_model,
+            retention_days,
+            daily_answer_quota,
+            tool_policy,
+            stale_after_days,
+        } = settings;
+
+        sqlx::query!(
+            "UPDATE workspaces
+            SET answer_model = ?, agent_model = ?, retention_days = ?, daily_answer_quota = ?,
+                tool_policy = ?, stale_after_days = ?
+            WHERE id = ?",
+            answer_model,
+            agent_model,
+            retention_days,
+            daily_answer_quota,
+            tool_policy,
+            stale_after_days,
+            id,
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Move a workspace to the trash, where an admin can restore it until it is purged along with its
+/// members, repositories and settings.
+pub async fn delete(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Extension(dry_run): Extension<DryRun>,
+    Path(id): Path<i64>,
+) -> webserver::Result<Json<Preview>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    require_owner(&app.sql, id, &user_id).await?;
+
+    let mut deletion = Deletion::begin(&app.sql, dry_run).await?;
+
+    let members = sqlx::query_scalar!(
+        r#"SELECT count(*) AS "count!: i64" FROM workspace_members WHERE workspace_id = ?"#,
+        id
+    )
+    .fetch_one(&mut *deletion.transaction())
+    .await?;
+    let repos = sqlx::query_scalar!(
+        r#"SELECT count(*) AS "count!: i64" FROM workspace_repos WHERE workspace_id = ?"#,
+        id
+    )
+    .fetch_one(&mut *deletion.transaction())
+    .await?;
+
+    let workspaces = sqlx::query!(
+        "UPDATE workspaces SET deleted_at = datetime('now'), deleted_by = ?
+        WHERE id = ? AND deleted_at IS NULL",
+        user_id,
+        id,
+    )
+    .execute(&mut *deletion.transaction())
+    .await?
+    .rows_affected();
+
+    audit(
+        &mut *deletion.transaction(),
+        id,
+        Some(&user_id),
+        "workspace_deleted",
+        None,
+    )
+    .await?;
+
+    deletion.removed("workspaces", workspaces);
+    deletion.removed("workspace_members", members as u64);
+    deletion.removed("workspace_repos", repos as u64);
+    deletion.finish().await
+}
+
+#[derive(Deserialize)]
+pub struct PutMember {
+    role: String,
+}
+
+pub async fn put_member(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path((id, member_id)): Path<(i64, String)>,
+    Json(params): Json<PutMember>,
+) -> webserver::Result<()> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    require_owner(&app.sql, id, &user_id).await?;
+
+    if !matches!(params.role.as_str(), "owner" | "member") {
+        return Err(Error::user("role must be one of `owner` or `member`"));
+    }
+
+    if member_id == user_id {
+        return Err(Error::user("cannot change your own role"));
+    }
+
+    // Members of another tenant would see its repositories through the workspace
+    if !super::tenant::same_tenant(&app, &user_id, &member_id).await? {
+        return Err(Error::new(ErrorKind::NotFound, "unknown user"));
+    }
+
+    let mut transaction = app.sql.begin().await?;
+
+    let updated = sqlx::query!(
+        "UPDATE workspace_members SET role = ? WHERE workspace_id = ? AND user_id = ?",
+        params.role,
+        id,
+        member_id,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+
+    // Nobody joins a workspace without accepting, so new members are only invited.
+    if updated == 0 {
+        sqlx::query!(
+            "INSERT INTO workspace_invites (workspace_id, user_id, role, invited_by)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (workspace_id, user_id) DO UPDATE SET
+                role = excluded.role, invited_by = excluded.invited_by",
+            id,
+            member_id,
+            params.role,
+            user_id,
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        audit(
+            &mut transaction,
+            id,
+            Some(&user_id),
+            "member_invited",
+            Some(&format!("{member_id} ({})", params.role)),
+        )
+        .await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct Invite {
+    workspace_id: i64,
+    name: String,
+    role: String,
+    invited_by: String,
+    created_at: NaiveDateTime,
+}
+
+/// The pending invites of the user, newest first.
+pub async fn invites(
+    app: Extension<Application>,
+    user: Extension<User>,
+) -> webserver::Result<Json<Vec<Invite>>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    let invites = sqlx::query_as!(
+        Invite,
+        "SELECT i.workspace_id, w.name, i.role, i.invited_by, i.created_at
+        FROM workspace_invites i
+        INNER JOIN workspaces w ON w.id = i.workspace_id
+        WHERE i.user_id = ? AND w.deleted_at IS NULL
+        ORDER BY i.created_at DESC",
+        user_id,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    Ok(Json(invites))
+}
+
+/// Accept an invite, joining the workspace with the role it was given.
+pub async fn accept_invite(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(id): Path<i64>,
+) -> webserver::Result<()> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    let mut transaction = app.sql.begin().await?;
+
+    let role = sqlx::query_scalar!(
+        "DELETE FROM workspace_invites
+        WHERE workspace_id = ? AND user_id = ?
+            AND workspace_id IN (SELECT id FROM workspaces WHERE deleted_at IS NULL)
+        RETURNING role",
+        id,
+        user_id,
+    )
+    .fetch_optional(&mut transaction)
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "no invite to this workspace"))?;
+
+    sqlx::query!(
+        "INSERT INTO workspace_members (workspace_id, user_id, role) VALUES (?, ?, ?)
+        ON CONFLICT (workspace_id, user_id) DO NOTHING",
+        id,
+        user_id,
+        role,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    audit(
+        &mut transaction,
+        id,
+        Some(&user_id),
+        "invite_accepted",
+        None,
+    )
+    .await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Decline an invite. Owners withdraw invites the same way, through `delete_member`.
+pub async fn decline_invite(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(id): Path<i64>,
+) -> webserver::Result<()> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    sqlx::query!(
+        "DELETE FROM workspace_invites WHERE workspace_id = ? AND user_id = ? RETURNING user_id",
+        id,
+        user_id,
+    )
+    .fetch_optional(&*app.sql)
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "no invite to this workspace"))
+    .map(|_| ())
+}
+
+/// Remove a member from a workspace, or withdraw their invite.
+///
+/// Owners can remove other members, and members can remove themselves. The conversations that the
+/// member shared with the workspace stop being shared.
+pub async fn delete_member(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path((id, member_id)): Path<(i64, String)>,
+) -> webserver::Result<()> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    let role = member_role(&app.sql, id, &user_id).await?;
+
+    if member_id == user_id {
+        if role == "owner" {
+            return Err(Error::user("owners cannot leave their workspace"));
+        }
+    } else if role != "owner" {
+        return Err(forbidden());
+    }
+
+    let mut transaction = app.sql.begin().await?;
+
+    let members = sqlx::query!(
+        "DELETE FROM workspace_members WHERE workspace_id = ? AND user_id = ?",
+        id,
+        member_id,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+
+    let invites = sqlx::query!(
+        "DELETE FROM workspace_invites WHERE workspace_id = ? AND user_id = ?",
+        id,
+        member_id,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+
+    if members + invites == 0 {
+        return Err(Error::new(ErrorKind::NotFound, "unknown workspace member"));
+    }
+
+    sqlx::query!(
+        "DELETE FROM workspace_conversations WHERE workspace_id = ? AND user_id = ?",
+        id,
+        member_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct RepoParams {
+    repo_ref: RepoRef,
+}
+
+pub async fn add_repo(
+    app: Extension<Application>,
+    user: Extension<User>,
+    tenant: Option<Extension<Tenant>>,
+    Path(id): Path<i64>,
+    Json(params): Json<repo_paths::RepoPaths>,
+) -> webserver::Result<()> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    require_owner(&app.sql, id, &user_id).await?;
+
+    // Members could otherwise read repositories through the workspace that they can't see
+    if !tenant::allows(&tenant, &params.repo_ref)
+        || !app.repo_pool.contains_async(&params.repo_ref).await
+        || !can_read(&user, &params.repo_ref).await
+    {
+        return Err(Error::new(ErrorKind::NotFound, "Can't find repository"));
+    }
+
+    let paths = params.column()?;
+    let repo_ref = params.repo_ref.to_string();
+    // Attaching a repository by hand keeps it attached, even if a filter stops matching it.
+    sqlx::query!(
+        "INSERT INTO workspace_repos (workspace_id, repo_ref, paths) VALUES (?, ?, ?)
+        ON CONFLICT (workspace_id, repo_ref) DO UPDATE SET filter_id = NULL, paths = excluded.paths",
+        id,
+        repo_ref,
+        paths,
+    )
+    .execute(&*app.sql)
+    .await?;
+
+    repo_paths::apply(&app, &params.repo_ref).await
+}
+
+/// Whether the user can read a repository themselves, without going through a workspace.
+///
+/// GitHub repositories are checked with the user's own GitHub access. Other repositories are
+/// synced with the credentials of the instance, so every user of the tenant can already read them.
+async fn can_read(user: &User, repo_ref: &RepoRef) -> bool {
+    if repo_ref.backend() != Backend::Github {
+        return true;
+    }
+
+    let (Some(crab), Some((owner, name))) = (user.github_client(), repo_ref.name().split_once('/'))
+    else {
+        return false;
+    };
+
+    crab.repos(owner, name).get().await.is_ok()
+}
+
+pub async fn remove_repo(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Extension(dry_run): Extension<DryRun>,
+    Path(id): Path<i64>,
+    Query(params): Query<RepoParams>,
+) -> webserver::Result<Json<Preview>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    require_owner(&app.sql, id, &user_id).await?;
+
+    let mut deletion = Deletion::begin(&app.sql, dry_run).await?;
+
+    let repo_ref = params.repo_ref.to_string();
+    let removed = sqlx::query!(
+        "DELETE FROM workspace_repos WHERE workspace_id = ? AND repo_ref = ?",
+        id,
+        repo_ref,
+    )
+    .execute(&mut *deletion.transaction())
+    .await?
+    .rows_affected();
+
+    if removed == 0 {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            "repository is not in this workspace",
+        ));
+    }
+
+    deletion.removed("workspace_repos", removed);
+    let is_dry_run = deletion.is_dry_run();
+    let preview = deletion.finish().await?;
+
+    // The remaining workspaces may need less of the repository, or more.
+    if !is_dry_run {
+        repo_paths::apply(&app, &params.repo_ref).await?;
+    }
+
+    Ok(preview)
+}
+
+#[derive(Deserialize)]
+pub struct Search {
+    #[serde(default)]
+    q: String,
+}
+
+#[derive(Serialize)]
+pub struct ConversationItem {
+    thread_id: String,
+    user_id: String,
+    repo_ref: String,
+    created_at: i64,
+    title: String,
+    /// Exchanges added since the requesting user last read this conversation
+    unread: i64,
+}
+
+/// Share one of the user's conversations with the other members of a workspace.
+///
+/// Only conversations on repositories of the workspace can be shared.
+pub async fn share_conversation(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path((id, thread_id)): Path<(i64, uuid::Uuid)>,
+) -> webserver::Result<()> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    member_role(&app.sql, id, &user_id).await?;
+
+    let thread_id = thread_id.to_string();
+    let shared = sqlx::query!(
+        "INSERT INTO workspace_conversations (workspace_id, user_id, thread_id)
+        SELECT r.workspace_id, c.user_id, c.thread_id
+        FROM conversations c
+        INNER JOIN workspace_repos r ON r.repo_ref = c.repo_ref
+        WHERE r.workspace_id = ? AND c.user_id = ? AND c.thread_id = ? AND c.deleted_at IS NULL
+        ON CONFLICT (workspace_id, user_id, thread_id) DO NOTHING",
+        id,
+        user_id,
+        thread_id,
+    )
+    .execute(&*app.sql)
+    .await?
+    .rows_affected();
+
+    if shared == 0 && !is_shared(&app.sql, id, &user_id, &thread_id).await? {
+        return Err(Error::new(ErrorKind::NotFound, "thread was not found"));
+    }
+
+    Ok(())
+}
+
+/// Stop sharing one of the user's conversations with a workspace.
+pub async fn unshare_conversation(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path((id, thread_id)): Path<(i64, uuid::Uuid)>,
+) -> webserver::Result<()> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    let thread_id = thread_id.to_string();
+    sqlx::query!(
+        "DELETE FROM workspace_conversations
+        WHERE workspace_id = ? AND user_id = ? AND thread_id = ?
+        RETURNING thread_id",
+        id,
+        user_id,
+        thread_id,
+    )
+    .fetch_optional(&*app.sql)
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "thread is not shared"))
+    .map(|_| ())
+}
+
+async fn is_shared(db: &SqlDb, id: i64, user_id: &str, thread_id: &str) -> webserver::Result<bool> {
+    let shared = sqlx::query_scalar!(
+        r#"SELECT count(*) AS "count!: i64" FROM workspace_conversations
+        WHERE workspace_id = ? AND user_id = ? AND thread_id = ?"#,
+        id,
+        user_id,
+        thread_id,
+    )
+    .fetch_one(db.as_ref())
+    .await?;
+
+    Ok(shared > 0)
+}
+
+/// Search the conversations that members shared with this workspace.
+pub async fn conversations(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(id): Path<i64>,
+    Query(params): Query<Search>,
+) -> webserver::Result<Json<Vec<ConversationItem>>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    member_role(&app.sql, id, &user_id).await?;
+
+    let search = conversations::search_expr(&params.q);
+    let items = sqlx::query_as!(
+        ConversationItem,
+        r#"SELECT c.thread_id, c.user_id, c.repo_ref, c.created_at, c.title,
+            CASE WHEN c.user_id = ? THEN 0
+                ELSE max(c.exchange_count - COALESCE(cr.exchanges_read, 0), 0)
+            END AS "unread!: i64"
+        FROM conversations c
+        INNER JOIN workspace_repos r ON r.repo_ref = c.repo_ref
+        INNER JOIN workspace_members m ON m.workspace_id = r.workspace_id AND m.user_id = c.user_id
+        INNER JOIN workspace_conversations s ON s.workspace_id = r.workspace_id
+            AND s.user_id = c.user_id AND s.thread_id = c.thread_id
+        LEFT JOIN conversation_reads cr
+            ON cr.user_id = c.user_id AND cr.thread_id = c.thread_id AND cr.reader_id = ?
+        WHERE r.workspace_id = ? AND c.deleted_at IS NULL
+            AND (? IS NULL OR c.id IN
+                (SELECT rowid FROM conversations_fts WHERE conversations_fts MATCH ?))
+        ORDER BY c.created_at DESC"#,
+        user_id,
+        user_id,
+        id,
+        search,
+        search,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    Ok(Json(items))
+}
+
+/// Read a conversation that was shared with the workspace, marking it as read.
+///
+/// Views of other members' conversations are logged for admins, with `/admin/conversation-views`.
+pub async fn conversation(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path((id, thread_id)): Path<(i64, uuid::Uuid)>,
+) -> webserver::Result<Json<Vec<Exchange>>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    member_role(&app.sql, id, &user_id).await?;
+
+    let thread_id = thread_id.to_string();
+    let row = sqlx::query!(
+        "SELECT c.user_id, c.exchanges, c.exchanges_zstd
+        FROM conversations c
+        INNER JOIN workspace_repos r ON r.repo_ref = c.repo_ref
+        INNER JOIN workspace_members m ON m.workspace_id = r.workspace_id AND m.user_id = c.user_id
+        INNER JOIN workspace_conversations s ON s.workspace_id = r.workspace_id
+            AND s.user_id = c.user_id AND s.thread_id = c.thread_id
+        WHERE r.workspace_id = ? AND c.thread_id = ? AND c.deleted_at IS NULL",
+        id,
+        thread_id,
+    )
+    .fetch_optional(&*app.sql)
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "thread was not found"))?;
+
+    let exchanges =
+        conversations::deserialize_exchanges(row.exchanges, row.exchanges_zstd.as_deref())
+            .map_err(Error::internal)?
+            .into_iter()
+            .map(Exchange::compressed)
+            .collect::<Vec<_>>();
+
+    let exchanges_read = exchanges.len() as i64;
+    let mut transaction = app.sql.begin().await?;
+    sqlx::query!(
+        "INSERT INTO conversation_reads (user_id, thread_id, reader_id, exchanges_read)
+        VALUES (?, ?, ?, ?)
+        ON CONFLICT (user_id, thread_id, reader_id) DO UPDATE SET
+            exchanges_read = excluded.exchanges_read",
+        row.user_id,
+        thread_id,
+        user_id,
+        exchanges_read,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    if row.user_id != user_id {
+        sqlx::query!(
+            "INSERT INTO conversation_views
+                (workspace_id, user_id, thread_id, viewer_id, exchanges_seen)
+            VALUES (?, ?, ?, ?, ?)",
+            id,
+            row.user_id,
+            thread_id,
+            user_id,
+            exchanges_read,
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(Json(exchanges))
+}
+
+#[derive(Serialize)]
+pub struct Suggestion {
+    name: String,
+    repos: Vec<String>,
+    /// How active the user has recently been in these repositories
+    activity: i64,
+}
+
+/// Workspaces suggested to the user from their recent GitHub activity.
+///
+/// Repositories that are already in one of the user's workspaces are left out.
+pub async fn suggestions(
+    app: Extension<Application>,
+    user: Extension<User>,
+) -> webserver::Result<Json<Vec<Suggestion>>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    let rows = sqlx::query!(
+        "SELECT name, repos, activity
+        FROM workspace_suggestions
+        WHERE user_id = ?
+        ORDER BY activity DESC",
+        user_id,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    let attached = sqlx::query!(
+        "SELECT r.repo_ref
+        FROM workspace_repos r
+        INNER JOIN workspace_members m ON m.workspace_id = r.workspace_id
+        WHERE m.user_id = ?",
+        user_id,
+    )
+    .fetch_all(&*app.sql)
+    .await?
+    .into_iter()
+    .map(|row| row.repo_ref)
+    .collect::<std::collections::HashSet<_>>();
+
+    let mut suggestions = vec![];
+    for row in rows {
+        let repos = serde_json::from_str::<Vec<String>>(&row.repos)
+            .map_err(Error::internal)?
+            .into_iter()
+            .filter(|repo| !attached.contains(repo))
+            .collect::<Vec<_>>();
+
+        if !repos.is_empty() {
+            suggestions.push(Suggestion {
+                name: row.name,
+                repos,
+                activity: row.activity,
+            });
+        }
+    }
+
+    Ok(Json(suggestions))
+}
+
+#[derive(Deserialize)]
+pub struct StatsParams {
+    /// How many days back, including today, to compute statistics for.
+    #[serde(default = "default_stats_days")]
+    days: i64,
+}
+
+fn default_stats_days() -> i64 {
+    30
+}
+
+#[derive(Serialize)]
+pub struct ConversationStats {
+    conversations: i64,
+    by_day: Vec<DayCount>,
+    avg_exchanges: Option<f64>,
+    /// Median time between a query and its answer, in milliseconds
+    median_answer_latency_ms: Option<f64>,
+    top_askers: Vec<Asker>,
+}
+
+#[derive(Serialize)]
+pub struct DayCount {
+    day: String,
+    conversations: i64,
+}
+
+#[derive(Serialize)]
+pub struct Asker {
+    user_id: String,
+    conversations: i64,
+}
+
+/// Aggregate statistics over the conversations shared with a workspace, for dashboards.
+///
+/// Everything is computed in SQL, so that we never decompress the exchanges themselves.
+pub async fn conversation_stats(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(id): Path<i64>,
+    Query(params): Query<StatsParams>,
+) -> webserver::Result<Json<ConversationStats>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    if !(1..=366).contains(&params.days) {
+        return Err(Error::user("`days` must be between 1 and 366"));
+    }
+
+    member_role(&app.sql, id, &user_id).await?;
+
+    let since = format!("-{} days", params.days - 1);
+
+    let by_day = sqlx::query_as!(
+        DayCount,
+        r#"SELECT date(c.created_at, 'unixepoch') AS "day!: String",
+            count(*) AS "conversations!: i64"
+        FROM conversations c
+        INNER JOIN workspace_repos r ON r.repo_ref = c.repo_ref
+        INNER JOIN workspace_members m ON m.workspace_id = r.workspace_id AND m.user_id = c.user_id
+        INNER JOIN workspace_conversations s ON s.workspace_id = r.workspace_id
+            AND s.user_id = c.user_id AND s.thread_id = c.thread_id
+        WHERE r.workspace_id = ? AND c.deleted_at IS NULL
+            AND c.created_at >= strftime('%s', 'now', 'start of day', ?)
+        GROUP BY 1
+        ORDER BY 1"#,
+        id,
+        since,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    let totals = sqlx::query!(
+        r#"SELECT count(*) AS "conversations!: i64",
+            avg(c.exchange_count) AS "avg_exchanges?: f64"
+        FROM conversations c
+        INNER JOIN workspace_repos r ON r.repo_ref = c.repo_ref
+        INNER JOIN workspace_members m ON m.workspace_id = r.workspace_id AND m.user_id = c.user_id
+        INNER JOIN workspace_conversations s ON s.workspace_id = r.workspace_id
+            AND s.user_id = c.user_id AND s.thread_id = c.thread_id
+        WHERE r.workspace_id = ? AND c.deleted_at IS NULL
+            AND c.created_at >= strftime('%s', 'now', 'start of day', ?)"#,
+        id,
+        since,
+    )
+    .fetch_one(&*app.sql)
+    .await?;
+
+    // SQLite has no `median`, so we pick the middle one or two rows of the sorted latencies.
+    let median_answer_latency_ms = sqlx::query_scalar!(
+        r#"WITH latencies AS (
+            SELECT e.value AS ms
+            FROM conversations c
+            INNER JOIN workspace_repos r ON r.repo_ref = c.repo_ref
+            INNER JOIN workspace_members m
+                ON m.workspace_id = r.workspace_id AND m.user_id = c.user_id
+            INNER JOIN workspace_conversations s ON s.workspace_id = r.workspace_id
+                AND s.user_id = c.user_id AND s.thread_id = c.thread_id,
+                json_each(c.answer_latencies_ms) e
+            WHERE r.workspace_id = ? AND c.deleted_at IS NULL
+                AND c.created_at >= strftime('%s', 'now', 'start of day', ?)
+        ),
+        ranked AS (
+            SELECT ms, row_number() OVER (ORDER BY ms) AS n, count(*) OVER () AS total
+            FROM latencies
+            WHERE ms IS NOT NULL
+        )
+        SELECT avg(ms) AS "ms?: f64"
+        FROM ranked
+        WHERE n IN ((total + 1) / 2, (total + 2) / 2)"#,
+        id,
+        since,
+    )
+    .fetch_one(&*app.sql)
+    .await?;
+
+    let top_askers = sqlx::query_as!(
+        Asker,
+        r#"SELECT c.user_id, count(*) AS "conversations!: i64"
+        FROM conversations c
+        INNER JOIN workspace_repos r ON r.repo_ref = c.repo_ref
+        INNER JOIN workspace_members m ON m.workspace_id = r.workspace_id AND m.user_id = c.user_id
+        INNER JOIN workspace_conversations s ON s.workspace_id = r.workspace_id
+            AND s.user_id = c.user_id AND s.thread_id = c.thread_id
+        WHERE r.workspace_id = ? AND c.deleted_at IS NULL
+            AND c.created_at >= strftime('%s', 'now', 'start of day', ?)
+        GROUP BY c.user_id
+        ORDER BY 2 DESC, c.user_id
+        LIMIT 10"#,
+        id,
+        since,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    Ok(Json(ConversationStats {
+        conversations: totals.conversations,
+        by_day,
+        avg_exchanges: totals.avg_exchanges,
+        median_answer_latency_ms,
+        top_askers,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct ToolDenial {
+    user_id: String,
+    repo_ref: String,
+    tool: String,
+    access: String,
+    policy: String,
+    created_at: NaiveDateTime,
+}
+
+/// The audit log of tool uses that were denied by the workspace's tool policy, newest first.
+pub async fn tool_denials(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(id): Path<i64>,
+) -> webserver::Result<Json<Vec<ToolDenial>>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    require_owner(&app.sql, id, &user_id).await?;
+
+    let denials = sqlx::query_as!(
+        ToolDenial,
+        "SELECT user_id, repo_ref, tool, access, policy, created_at
+        FROM workspace_tool_denials
+        WHERE workspace_id = ?
+        ORDER BY id DESC
+        LIMIT 500",
+        id,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    Ok(Json(denials))
+}
+
+#[derive(Serialize)]
+pub struct Notification {
+    id: i64,
+    kind: String,
+    repo_ref: Option<String>,
+    message: String,
+    created_at: NaiveDateTime,
+    resolved_at: Option<NaiveDateTime>,
+}
+
+/// Alerts for the members of a workspace, such as stale repositories, newest first.
+pub async fn notifications(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(id): Path<i64>,
+) -> webserver::Result<Json<Vec<Notification>>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    member_role(&app.sql, id, &user_id).await?;
+
+    let notifications = sqlx::query_as!(
+        Notification,
+        "SELECT id, kind, repo_ref, message, created_at, resolved_at
+        FROM notifications
+        WHERE workspace_id = ?
+        ORDER BY id DESC
+        LIMIT 100",
+        id,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    Ok(Json(notifications))
+}
+
+/// The workspace settings that apply to a user asking questions about a repository.
+pub(crate) struct Policy {
+    pub(crate) workspace_id: i64,
+    pub(crate) answer_model: Option<String>,
+    pub(crate) agent_model: Option<String>,
+    daily_answer_quota: Option<i64>,
+}
+
+impl Policy {
+    /// Look up the policy of the workspace a repository belongs to.
+    ///
+    /// Workspace keys act in the workspace of the key. Otherwise, only workspaces the user is a
+    /// member of are considered. If there are several, the oldest workspace wins.
+    pub(crate) async fn for_repo(
+        db: &SqlDb,
+        user: &User,
+        repo_ref: &RepoRef,
+    ) -> webserver::Result<Option<Self>> {
+        if let Some(workspace_id) = user.workspace_id() {
+            let policy = sqlx::query_as!(
+                Policy,
+                "SELECT id as workspace_id, answer_model, agent_model, daily_answer_quota
+                FROM workspaces
+                WHERE id = ? AND deleted_at IS NULL",
+                workspace_id,
+            )
+            .fetch_optional(db.as_ref())
+            .await?;
+
+            return Ok(policy);
+        }
+
+        let Some(user_id) = user.username() else {
+            return Ok(None);
+        };
+
+        let repo_ref = repo_ref.to_string();
+
+        let policy = sqlx::query_as!(
+            Policy,
+            "SELECT w.id as workspace_id, w.answer_model, w.agent_model, w.daily_answer_quota
+            FROM workspaces w
+            INNER JOIN workspace_repos r ON r.workspace_id = w.id
+            INNER JOIN workspace_members m ON m.workspace_id = w.id
+            WHERE r.repo_ref = ? AND m.user_id = ? AND w.deleted_at IS NULL
+            ORDER BY w.id
+            LIMIT 1",
+            repo_ref,
+            user_id,
+        )
+        .fetch_optional(db.as_ref())
+        .await?;
+
+        Ok(policy)
+    }
+
+    /// The repositories of the workspace, which answers across the workspace search.
+    pub(crate) async fn repos(&self, db: &SqlDb) -> webserver::Result<Vec<RepoRef>> {
+        let repos = sqlx::query_scalar!(
+            "SELECT repo_ref FROM workspace_repos WHERE workspace_id = ? ORDER BY repo_ref",
+            self.workspace_id,
+        )
+        .fetch_all(db.as_ref())
+        .await?;
+
+        Ok(repos
+            .into_iter()
+            .filter_map(|r| tenant::stored_repo(&r).ok().flatten())
+            .collect())
+    }
+
+    /// Count an answer against the daily quota, failing if the quota has been used up.
+    pub(crate) async fn record_answer(&self, db: &SqlDb, user_id: &str) -> webserver::Result<()> {
+        let mut transaction = db.begin().await?;
+
+        let answers = sqlx::query!(
+            "SELECT answers FROM workspace_usage
+            WHERE workspace_id = ? AND user_id = ? AND day = date('now')",
+            self.workspace_id,
+            user_id,
+        )
+        .fetch_optional(&mut transaction)
+        .await?
+        .map_or(0, |row| row.answers);
+
+        if let Some(quota) = self.daily_answer_quota {
+            if answers >= quota {
+                return Err(Error::user("daily answer quota exceeded")
+                    .with_status(StatusCode::TOO_MANY_REQUESTS));
+            }
+        }
+
+        sqlx::query!(
+            "INSERT INTO workspace_usage (workspace_id, user_id, day, answers)
+            VALUES (?, ?, date('now'), 1)
+            ON CONFLICT (workspace_id, user_id, day) DO UPDATE SET answers = answers + 1",
+            self.workspace_id,
+            user_id,
+        )
+        .execute(&mut transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// The tools that can be used on a repository, by the strictest tool policy of the workspaces it
+/// belongs to.
+///
+/// Unlike [`Policy`], this applies to everyone who uses the repository and not only to members,
+/// as the policy protects the repository rather than the workspace.
+pub(crate) struct ToolRules {
+    /// The workspace the policy comes from, which denials are recorded for
+    workspace_id: i64,
+    policy: ToolPolicy,
+}
+
+impl ToolRules {
+    pub(crate) async fn for_repo(
+        db: &SqlDb,
+        repo_ref: &RepoRef,
+    ) -> webserver::Result<Option<Self>> {
+        let repo_ref = repo_ref.to_string();
+
+        let rules = sqlx::query!(
+            "SELECT w.id, w.tool_policy
+            FROM workspaces w
+            INNER JOIN workspace_repos r ON r.workspace_id = w.id
+            WHERE r.repo_ref = ? AND w.deleted_at IS NULL
+            ORDER BY w.id",
+            repo_ref,
+        )
+        .fetch_all(db.as_ref())
+        .await?
+        .into_iter()
+        .map(|row| Self {
+            workspace_id: row.id,
+            // Unknown values are treated as read-only, so that a bad setting never grants more
+            // access.
+            policy: match row.tool_policy.as_deref().map(str::parse) {
+                None => ToolPolicy::default(),
+                Some(Ok(policy)) => policy,
+                Some(Err(_)) => ToolPolicy::ReadOnly,
+            },
+        })
+        .min_by_key(|rules| rules.policy);
+
+        Ok(rules)
+    }
+
+    pub(crate) fn policy(&self) -> ToolPolicy {
+        self.policy
+    }
+
+    /// Check whether a tool can be used on a repository, recording a denial for the audit log if
+    /// not.
+    pub(crate) async fn authorize(
+        &self,
+        db: &SqlDb,
+        user_id: &str,
+        repo_ref: &RepoRef,
+        tool: &str,
+        access: ToolAccess,
+    ) -> anyhow::Result<bool> {
+        let policy = self.policy;
+        if policy.allows(access) {
+            return Ok(true);
+        }
+
+        warn!(
+            workspace_id = self.workspace_id,
+            user_id,
+            %repo_ref,
+            tool,
+            access = access.as_str(),
+            %policy,
+            "denied tool use by workspace policy"
+        );
+
+        let (repo_ref, access, policy) = (repo_ref.to_string(), access.as_str(), policy.as_str());
+        sqlx::query!(
+            "INSERT INTO workspace_tool_denials
+                (workspace_id, user_id, repo_ref, tool, access, policy)
+            VALUES (?, ?, ?, ?, ?, ?)",
+            self.workspace_id,
+            user_id,
+            repo_ref,
+            tool,
+            access,
+            policy,
+        )
+        .execute(db.as_ref())
+        .await?;
+
+        Ok(false)
+    }
+}
+
+/// Fail with `403 Forbidden` if the workspaces of a repository do not allow a tool.
+pub(crate) async fn require_tool(
+    db: &SqlDb,
+    user_id: &str,
+    repo_ref: &RepoRef,
+    tool: &str,
+    access: ToolAccess,
+) -> webserver::Result<()> {
+    let Some(rules) = ToolRules::for_repo(db, repo_ref).await? else {
+        return Ok(());
+    };
+
+    if rules.authorize(db, user_id, repo_ref, tool, access).await? {
+        Ok(())
+    } else {
+        Err(Error::user(format!(
+            "`{tool}` is not allowed by the workspace tool policy `{}`",
+            rules.policy
+        ))
+        .with_status(StatusCode::FORBIDDEN))
+    }
+}
+
+/// The other repositories of the workspaces that a repository belongs to.
+pub(crate) async fn sibling_repos(
+    db: &SqlDb,
+    repo_ref: &RepoRef,
+) -> Result<Vec<RepoRef>, sqlx::Error> {
+    let name = repo_ref.to_string();
+    let repos = sqlx::query_scalar!(
+        "SELECT DISTINCT b.repo_ref FROM workspace_repos a
+        INNER JOIN workspace_repos b ON b.workspace_id = a.workspace_id
+        INNER JOIN workspaces w ON w.id = a.workspace_id
+        WHERE a.repo_ref = ? AND b.repo_ref != a.repo_ref AND w.deleted_at IS NULL
+        ORDER BY b.repo_ref",
+        name,
+    )
+    .fetch_all(db.as_ref())
+    .await?;
+
+    Ok(repos
+        .into_iter()
+        .filter_map(|r| tenant::stored_repo(&r).ok().flatten())
+        .collect())
+}
+
+async fn member_role(db: &SqlDb, id: i64, user_id: &str) -> webserver::Result<String> {
+    sqlx::query!(
+        "SELECT m.role FROM workspace_members m
+        INNER JOIN workspaces w ON w.id = m.workspace_id
+        WHERE m.workspace_id = ? AND m.user_id = ? AND w.deleted_at IS NULL",
+        id,
+        user_id,
+    )
+    .fetch_optional(db.as_ref())
+    .await?
+    .map(|row| row.role)
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown workspace ID"))
+}
+
+async fn require_owner(db: &SqlDb, id: i64, user_id: &str) -> webserver::Result<()> {
+    if member_role(db, id, user_id).await? == "owner" {
+        Ok(())
+    } else {
+        Err(forbidden())
+    }
+}
+
+/// Record a change to a workspace in the audit log.
+pub(crate) async fn audit<'c>(
+    executor: impl sqlx::SqliteExecutor<'c>,
+    workspace_id: i64,
+    user_id: Option<&str>,
+    action: &str,
+    detail: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO audit_log (workspace_id, user_id, action, detail) VALUES (?, ?, ?, ?)",
+        workspace_id,
+        user_id,
+        action,
+        detail,
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+fn forbidden() -> Error {
+    Error::user("only workspace owners can do this").with_status(StatusCode::FORBIDDEN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn keys_act_in_their_workspace() {
+        let db: SqlDb = Arc::new(crate::db::test_pool().await);
+        let repo = "github.com/org/repo".parse::<RepoRef>().unwrap();
+
+        for query in [
+            "INSERT INTO workspaces (id, name, answer_model, daily_answer_quota)
+            VALUES (1, 'team', 'gpt-3.5-turbo-finetuned', 1)",
+            "INSERT INTO workspace_repos (workspace_id, repo_ref) VALUES (1, 'github.com/org/repo')",
+        ] {
+            sqlx::query(query).execute(db.as_ref()).await.unwrap();
+        }
+
+        let user = |workspace_id| User::Desktop {
+            access_token: String::new(),
+            login: "key:1".to_owned(),
+            workspace_id,
+            crab: Arc::new(|| anyhow::bail!("no github")),
+        };
+
+        // Keys aren't members of any workspace
+        assert!(Policy::for_repo(&db, &user(None), &repo)
+            .await
+            .unwrap()
+            .is_none());
+
+        let policy = Policy::for_repo(&db, &user(Some(1)), &repo)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(policy.workspace_id, 1);
+        assert_eq!(
+            policy.answer_model.as_deref(),
+            Some("gpt-3.5-turbo-finetuned")
+        );
+
+        policy.record_answer(&db, "key:1").await.unwrap();
+        policy.record_answer(&db, "key:1").await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn applies_the_strictest_tool_policy_to_everyone() {
+        let db: SqlDb = Arc::new(crate::db::test_pool().await);
+        let repo = "github.com/org/repo".parse::<RepoRef>().unwrap();
+
+        assert!(ToolRules::for_repo(&db, &repo).await.unwrap().is_none());
+
+        for query in [
+            "INSERT INTO workspaces (id, name, tool_policy) VALUES (1, 'open', 'read_write')",
+            "INSERT INTO workspaces (id, name, tool_policy) VALUES (2, 'review', 'read_suggest')",
+            "INSERT INTO workspaces (id, name, tool_policy, deleted_at)
+            VALUES (3, 'deleted', 'read_only', datetime('now'))",
+            "INSERT INTO workspace_members (workspace_id, user_id, role) VALUES (1, 'member', 'owner')",
+            "INSERT INTO workspace_repos (workspace_id, repo_ref)
+            VALUES (1, 'github.com/org/repo'), (2, 'github.com/org/repo'), (3, 'github.com/org/repo')",
+        ] {
+            sqlx::query(query).execute(db.as_ref()).await.unwrap();
+        }
+
+        let rules = ToolRules::for_repo(&db, &repo).await.unwrap().unwrap();
+        assert_eq!(rules.policy(), ToolPolicy::ReadSuggest);
+
+        for user_id in ["member", "outsider"] {
+            require_tool(&db, user_id, &repo, "diff", ToolAccess::Suggest)
+                .await
+                .unwrap();
+            require_tool(&db, user_id, &repo, "diff_apply", ToolAccess::Write)
+                .await
+                .unwrap_err();
+        }
+
+        let denials: Vec<(i64, String)> =
+            sqlx::query_as("SELECT workspace_id, user_id FROM workspace_tool_denials ORDER BY id")
+                .fetch_all(db.as_ref())
+                .await
+                .unwrap();
+        assert_eq!(
+            denials,
+            [(2, "member".to_owned()), (2, "outsider".to_owned())]
+        );
+    }
+}
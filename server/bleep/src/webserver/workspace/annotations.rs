@@ -0,0 +1,363 @@
+//! Notes that members of a workspace attach to parts of the answers in its conversations, to
+//! review an answer before it is shared more widely.
+//!
+//! An annotation covers a range of characters of an answer, and keeps the text it covered when it
+//! was made. Answers can be regenerated, so an annotation whose text no longer matches the answer
+//! is returned as outdated rather than dropped.
+
+use super::{member_role, require_owner};
+use crate::{
+    agent::exchange::Exchange,
+    db::SqlDb,
+    webserver::{self, answer::conversations, middleware::User, Error, ErrorKind},
+    Application,
+};
+use axum::{
+    extract::{Extension, Json, Path},
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+
+/// The longest an annotation can be, in characters.
+const MAX_BODY_CHARS: usize = 4000;
+
+#[derive(Serialize, Debug)]
+pub struct Annotation {
+    id: i64,
+    exchange_id: String,
+    /// The range of characters of the answer that is annotated, from `start` up to `end`
+    start: i64,
+    end: i64,
+    /// The annotated text, when the annotation was made
+    quote: String,
+    body: String,
+    author_id: String,
+    created_at: i64,
+    updated_at: Option<i64>,
+    resolved_at: Option<i64>,
+    resolved_by: Option<String>,
+    /// Whether the answer changed since, so that the range doesn't cover `quote` anymore
+    outdated: bool,
+}
+
+#[derive(Deserialize)]
+pub struct Create {
+    exchange_id: uuid::Uuid,
+    start: usize,
+    end: usize,
+    body: String,
+}
+
+#[derive(Deserialize)]
+pub struct Patch {
+    body: Option<String>,
+    resolved: Option<bool>,
+}
+
+/// The annotations of a conversation in the workspace, in the order of the answers they annotate.
+pub async fn list(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path((id, thread_id)): Path<(i64, uuid::Uuid)>,
+) -> webserver::Result<Json<Vec<Annotation>>> {
+    let user_id = user_id(&user)?;
+    member_role(&app.sql, id, &user_id).await?;
+
+    let (_, exchanges) = load(&app.sql, id, thread_id).await?;
+    let thread_id = thread_id.to_string();
+
+    let mut annotations = sqlx::query_as!(
+        Annotation,
+        r#"SELECT id, exchange_id, start_char AS start, end_char AS "end", quote, body, author_id,
+            created_at, updated_at, resolved_at, resolved_by, FALSE AS "outdated!: bool"
+        FROM conversation_annotations
+        WHERE workspace_id = ? AND thread_id = ?"#,
+        id,
+        thread_id,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    for annotation in &mut annotations {
+        let answer = exchanges
+            .iter()
+            .find(|e| e.id.to_string() == annotation.exchange_id)
+            .and_then(Exchange::answer);
+
+        annotation.outdated = answer
+            .and_then(|answer| quote(answer, annotation.start as usize, annotation.end as usize))
+            .map_or(true, |quote| quote != annotation.quote);
+    }
+
+    let position = |annotation: &Annotation| {
+        exchanges
+            .iter()
+            .position(|e| e.id.to_string() == annotation.exchange_id)
+            .unwrap_or(usize::MAX)
+    };
+    annotations.sort_by_key(|a| (position(a), a.start));
+
+    Ok(Json(annotations))
+}
+
+/// Annotate part of an answer in a conversation of the workspace.
+pub async fn create(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path((id, thread_id)): Path<(i64, uuid::Uuid)>,
+    Json(params): Json<Create>,
+) -> webserver::Result<Json<Annotation>> {
+    let user_id = user_id(&user)?;
+    member_role(&app.sql, id, &user_id).await?;
+
+    let body = validate_body(&params.body)?;
+
+    let (owner_id, exchanges) = load(&app.sql, id, thread_id).await?;
+    let answer = exchanges
+        .iter()
+        .find(|e| e.id == params.exchange_id)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "exchange was not found"))?
+        .answer()
+        .ok_or_else(|| Error::user("this exchange has no answer yet"))?;
+
+    let quote = quote(answer, params.start, params.end)
+        .filter(|quote| !quote.is_empty())
+        .ok_or_else(|| Error::user("the range must cover part of the answer"))?;
+
+    let thread_id = thread_id.to_string();
+    let exchange_id = params.exchange_id.to_string();
+    let (start, end) = (params.start as i64, params.end as i64);
+
+    let annotation = sqlx::query_as!(
+        Annotation,
+        r#"INSERT INTO conversation_annotations
+            (workspace_id, user_id, thread_id, exchange_id, start_char, end_char, quote, body,
+                author_id)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+        RETURNING id AS "id!", exchange_id, start_char AS start, end_char AS "end", quote, body,
+            author_id, created_at AS "created_at!", updated_at, resolved_at, resolved_by,
+            FALSE AS "outdated!: bool""#,
+        id,
+        owner_id,
+        thread_id,
+        exchange_id,
+        start,
+        end,
+        quote,
+        body,
+        user_id,
+    )
+    .fetch_one(&*app.sql)
+    .await?;
+
+    Ok(Json(annotation))
+}
+
+/// Edit an annotation, which only its author can do, or resolve it, which any member can do.
+pub async fn patch(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path((id, thread_id, annotation_id)): Path<(i64, uuid::Uuid, i64)>,
+    Json(params): Json<Patch>,
+) -> webserver::Result<StatusCode> {
+    let user_id = user_id(&user)?;
+    member_role(&app.sql, id, &user_id).await?;
+
+    let thread_id = thread_id.to_string();
+    let author_id = author(&app.sql, id, &thread_id, annotation_id).await?;
+
+    let mut transaction = app.sql.begin().await?;
+
+    if let Some(body) = &params.body {
+        if author_id != user_id {
+            return Err(Error::user("only the author of an annotation can edit it")
+                .with_status(StatusCode::FORBIDDEN));
+        }
+
+        let body = validate_body(body)?;
+        sqlx::query!(
+            "UPDATE conversation_annotations SET body = ?, updated_at = strftime('%s', 'now')
+            WHERE id = ?",
+            body,
+            annotation_id,
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+
+    match params.resolved {
+        Some(true) => {
+            sqlx::query!(
+                "UPDATE conversation_annotations
+                SET resolved_at = strftime('%s', 'now'), resolved_by = ?
+                WHERE id = ? AND resolved_at IS NULL",
+                user_id,
+                annotation_id,
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+        Some(false) => {
+            sqlx::query!(
+                "UPDATE conversation_annotations SET resolved_at = NULL, resolved_by = NULL
+                WHERE id = ?",
+                annotation_id,
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+        None => {}
+    }
+
+    transaction.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Delete an annotation, which its author and the owners of the workspace can do.
+pub async fn delete(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path((id, thread_id, annotation_id)): Path<(i64, uuid::Uuid, i64)>,
+) -> webserver::Result<StatusCode> {
+    let user_id = user_id(&user)?;
+    member_role(&app.sql, id, &user_id).await?;
+
+    let thread_id = thread_id.to_string();
+    if author(&app.sql, id, &thread_id, annotation_id).await? != user_id {
+        require_owner(&app.sql, id, &user_id).await?;
+    }
+
+    sqlx::query!(
+        "DELETE FROM conversation_annotations WHERE id = ?",
+        annotation_id
+    )
+    .execute(&*app.sql)
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The owner and exchanges of a conversation that was shared with the workspace.
+async fn load(
+    db: &SqlDb,
+    id: i64,
+    thread_id: uuid::Uuid,
+) -> webserver::Result<(String, Vec<Exchange>)> {
+    let thread_id = thread_id.to_string();
+    let row = sqlx::query!(
+        "SELECT c.user_id, c.exchanges, c.exchanges_zstd
+        FROM conversations c
+        INNER JOIN workspace_repos r ON r.repo_ref = c.repo_ref
+        INNER JOIN workspace_members m ON m.workspace_id = r.workspace_id AND m.user_id = c.user_id
+        INNER JOIN workspace_conversations s ON s.workspace_id = r.workspace_id
+            AND s.user_id = c.user_id AND s.thread_id = c.thread_id
+        WHERE r.workspace_id = ? AND c.thread_id = ? AND c.deleted_at IS NULL",
+        id,
+        thread_id,
+    )
+    .fetch_optional(db.as_ref())
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "thread was not found"))?;
+
+    let exchanges =
+        conversations::deserialize_exchanges(row.exchanges, row.exchanges_zstd.as_deref())
+            .map_err(Error::internal)?;
+
+    Ok((row.user_id, exchanges))
+}
+
+async fn author(
+    db: &SqlDb,
+    id: i64,
+    thread_id: &str,
+    annotation_id: i64,
+) -> webserver::Result<String> {
+    sqlx::query_scalar!(
+        "SELECT author_id FROM conversation_annotations
+        WHERE id = ? AND workspace_id = ? AND thread_id = ?",
+        annotation_id,
+        id,
+        thread_id,
+    )
+    .fetch_optional(db.as_ref())
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "annotation was not found"))
+}
+
+fn validate_body(body: &str) -> webserver::Result<&str> {
+    let body = body.trim();
+    if body.is_empty() || body.chars().count() > MAX_BODY_CHARS {
+        return Err(Error::user(format!(
+            "annotations must be 1 to {MAX_BODY_CHARS} characters long"
+        )));
+    }
+
+    Ok(body)
+}
+
+/// The characters of an answer from `start` up to `end`, if the answer is long enough.
+fn quote(answer: &str, start: usize, end: usize) -> Option<String> {
+    if start > end || end > answer.chars().count() {
+        return None;
+    }
+
+    Some(answer.chars().skip(start).take(end - start).collect())
+}
+
+fn user_id(user: &User) -> webserver::Result<String> {
+    user.username()
+        .ok_or_else(|| Error::user("didn't have user ID"))
+        .map(str::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_character_ranges() {
+        let answer = "Die Größe wird in `len` gespeichert";
+
+        assert_eq!(quote(answer, 4, 9).as_deref(), Some("Größe"));
+        assert_eq!(quote(answer, 0, 0).as_deref(), Some(""));
+        assert_eq!(quote(answer, 9, 4), None);
+        assert_eq!(quote(answer, 30, 36), None);
+    }
+
+    #[tokio::test]
+    async fn only_loads_shared_conversations() {
+        let db: SqlDb = std::sync::Arc::new(crate::db::test_pool().await);
+        let shared = uuid::Uuid::new_v4();
+        let private = uuid::Uuid::new_v4();
+
+        for query in [
+            "INSERT INTO workspaces (id, name) VALUES (1, 'team')".to_owned(),
+            "INSERT INTO workspace_members (workspace_id, user_id, role) VALUES (1, 'member', 'member')"
+                .to_owned(),
+            "INSERT INTO workspace_repos (workspace_id, repo_ref) VALUES (1, 'github.com/org/repo')"
+                .to_owned(),
+            format!(
+                "INSERT INTO conversations (created_at, user_id, thread_id, repo_ref, title, exchanges)
+                VALUES (0, 'member', '{shared}', 'github.com/org/repo', 'shared', '[]'),
+                    (0, 'member', '{private}', 'github.com/org/repo', 'private', '[]')"
+            ),
+            format!(
+                "INSERT INTO workspace_conversations (workspace_id, user_id, thread_id)
+                VALUES (1, 'member', '{shared}')"
+            ),
+        ] {
+            sqlx::query(&query).execute(db.as_ref()).await.unwrap();
+        }
+
+        assert_eq!(load(&db, 1, shared).await.unwrap().0, "member");
+        load(&db, 1, private).await.unwrap_err();
+
+        // Shares of former members are hidden
+        sqlx::query("DELETE FROM workspace_members")
+            .execute(db.as_ref())
+            .await
+            .unwrap();
+        load(&db, 1, shared).await.unwrap_err();
+    }
+}
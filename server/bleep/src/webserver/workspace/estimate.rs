@@ -0,0 +1,233 @@
+//! Estimates of what answering a question would cost, for budgeting batches of questions.
+//!
+//! Retrieval runs as it would for an answer, but no model is called. The prompt of the final answer
+//! is built from everything retrieval found and counted with the model's tokenizer, and the
+//! agent's search steps are assumed to read the same code.
+
+use super::member_role;
+use crate::{
+    agent::{self, model::LLMModel},
+    llm_gateway::api::Message,
+    query::parser::{self, Literal},
+    repo::RepoRef,
+    semantic::{Payload, SemanticSearchParams},
+    webserver::{self, answer, middleware::User, tenant, Error, ErrorKind},
+    Application,
+};
+use axum::extract::{Extension, Json, Path};
+use serde::{Deserialize, Serialize};
+
+/// The length of a short answer, for the low end of the cost range.
+const SHORT_ANSWER_TOKENS: usize = 100;
+
+/// Answers are rarely longer than this, unless the model leaves less room for them.
+const LONG_ANSWER_TOKENS: usize = 1024;
+
+#[derive(Deserialize)]
+pub struct Params {
+    q: String,
+    /// Only retrieve from this repository of the workspace, instead of all of them
+    repo_ref: Option<RepoRef>,
+    answer_model: Option<LLMModel>,
+    agent_model: Option<LLMModel>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Estimate {
+    answer_model: &'static str,
+    agent_model: &'static str,
+    /// Code chunks found by retrieval
+    chunks: usize,
+    /// Tokens of the final answer's prompt, with all of the code that retrieval found
+    prompt_tokens: usize,
+    /// The most tokens the answer model takes in, after leaving room for the answer
+    max_prompt_tokens: usize,
+    /// Whether everything that retrieval found fits the context of the answer model
+    fits_context: bool,
+    /// What the provider is expected to charge, in USD
+    cost: CostRange,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct CostRange {
+    /// One search step and a short answer
+    low: f64,
+    /// As many search steps as the agent takes at most, and a long answer
+    high: f64,
+}
+
+/// Estimate the prompt size and cost of answering a question over the repositories of a workspace.
+pub async fn estimate(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(id): Path<i64>,
+    Json(params): Json<Params>,
+) -> webserver::Result<Json<Estimate>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    member_role(&app.sql, id, &user_id).await?;
+
+    let settings = sqlx::query!(
+        "SELECT answer_model, agent_model FROM workspaces WHERE id = ?",
+        id,
+    )
+    .fetch_one(&*app.sql)
+    .await?;
+
+    // Workspace settings override what the client asks for, as they do for answers
+    let answer_model = settings
+        .answer_model
+        .as_deref()
+        .and_then(|m| m.parse().ok())
+        .or(params.answer_model)
+        .unwrap_or_else(answer::default_answer_model);
+    let agent_model = settings
+        .agent_model
+        .as_deref()
+        .and_then(|m| m.parse().ok())
+        .or(params.agent_model)
+        .unwrap_or_else(answer::default_agent_model);
+
+    let attached = sqlx::query_scalar!(
+        "SELECT repo_ref FROM workspace_repos WHERE workspace_id = ? ORDER BY repo_ref",
+        id,
+    )
+    .fetch_all(&*app.sql)
+    .await?
+    .into_iter()
+    .filter_map(|r| tenant::stored_repo(&r).ok().flatten())
+    .collect::<Vec<_>>();
+
+    let repos = match params.repo_ref {
+        Some(repo_ref) if attached.contains(&repo_ref) => vec![repo_ref],
+        Some(_) => {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                "the repository isn't attached to the workspace",
+            ))
+        }
+        None => attached,
+    };
+
+    let query = parser::parse_nl(&params.q).map_err(Error::user)?;
+    if query.target().is_none() {
+        return Err(Error::user("the question has nothing to search for"));
+    }
+
+    let mut chunks = vec![];
+    for repo_ref in &repos {
+        let retrieval = answer::settings::load(&app.sql, repo_ref).await?;
+        let query = parser::SemanticQuery {
+            repos: vec![Literal::Plain(repo_ref.display_name().into())],
+            ..query.clone()
+        };
+
+        let results = app
+            .semantic
+            .search(
+                &query,
+                SemanticSearchParams {
+                    limit: retrieval.semantic_k,
+                    offset: 0,
+                    threshold: retrieval.min_similarity,
+                    exact_match: false,
+                },
+            )
+            .await
+            .map_err(Error::internal)?;
+
+        chunks.extend(retrieval.limit_per_file(results, |chunk| chunk.relative_path.as_str()));
+    }
+
+    let context = context(&chunks);
+    let count = |model: &LLMModel, prompt: &str| {
+        let messages = [Message::system(prompt), Message::user(&params.q)];
+        tiktoken_rs::num_tokens_from_messages(
+            model.tokenizer,
+            &messages.iter().map(Into::into).collect::<Vec<_>>(),
+        )
+        .map_err(Error::internal)
+    };
+
+    let prompt_tokens = count(&answer_model, &(answer_model.system_prompt)(&context))?;
+    let step_tokens = count(&agent_model, &context)?;
+
+    let context_size = tiktoken_rs::model::get_context_size(answer_model.tokenizer);
+    let max_prompt_tokens = context_size.saturating_sub(answer_model.answer_headroom);
+
+    Ok(Json(Estimate {
+        answer_model: answer_model.model_name,
+        agent_model: agent_model.model_name,
+        chunks: chunks.len(),
+        prompt_tokens,
+        max_prompt_tokens,
+        fits_context: prompt_tokens <= max_prompt_tokens,
+        cost: cost_range(
+            &answer_model,
+            &agent_model,
+            prompt_tokens.min(max_prompt_tokens),
+            step_tokens,
+        ),
+    }))
+}
+
+/// The code chunks as the answer prompt lays them out, with numbered lines.
+fn context(chunks: &[Payload]) -> String {
+    if chunks.is_empty() {
+        return String::new();
+    }
+
+    let mut s = "##### CODE CHUNKS #####\n\n".to_owned();
+    for chunk in chunks {
+        let snippet = chunk
+            .text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| format!("{} {line}\n", i as u64 + chunk.start_line + 1))
+            .collect::<String>();
+
+        s += &format!("### {} ###\n{snippet}\n\n", chunk.relative_path);
+    }
+
+    s
+}
+
+fn cost_range(
+    answer_model: &LLMModel,
+    agent_model: &LLMModel,
+    prompt_tokens: usize,
+    step_tokens: usize,
+) -> CostRange {
+    let cost = |model: &LLMModel, prompt: usize, completion: usize| {
+        (prompt as f64 * model.prompt_price + completion as f64 * model.completion_price) / 1000.0
+    };
+
+    let long_answer = LONG_ANSWER_TOKENS.min(answer_model.answer_headroom);
+
+    CostRange {
+        low: cost(agent_model, step_tokens, 0)
+            + cost(answer_model, prompt_tokens, SHORT_ANSWER_TOKENS),
+        high: agent::MAX_STEPS as f64 * cost(agent_model, step_tokens, 0)
+            + cost(answer_model, prompt_tokens, long_answer),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::model::{GPT_3_5_TURBO_FINETUNED, GPT_4};
+
+    #[test]
+    fn prices_steps_and_answer() {
+        let range = cost_range(&GPT_3_5_TURBO_FINETUNED, &GPT_4, 2000, 1000);
+
+        // 1k step tokens on GPT-4, and 2k prompt tokens with 100 answer tokens on GPT-3.5
+        assert!((range.low - (0.03 + 0.006 + 0.0006)).abs() < 1e-9);
+
+        // Ten steps, and an answer as long as the 512 tokens of GPT-3.5's headroom
+        assert!((range.high - (0.3 + 0.006 + 512.0 * 0.006 / 1000.0)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,505 @@
+//! Issue trackers that members can file tickets in, pre-filled from an answer.
+
+use super::{member_role, require_owner};
+use crate::{
+    agent::exchange::Exchange,
+    fetch::Fetcher,
+    webserver::{
+        self,
+        answer::{
+            conversations::{self, ConversationId},
+            export,
+        },
+        middleware::User,
+        Error, ErrorKind,
+    },
+    Application,
+};
+use anyhow::{anyhow, bail, Context};
+use axum::extract::{Extension, Json, Path};
+use axum_extra::extract::cookie::Key;
+use chrono::NaiveDateTime;
+use rand::RngCore;
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    hkdf,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::warn;
+
+const LINEAR_API: &str = "https://api.linear.app/graphql";
+
+/// Jira rejects longer summaries.
+const MAX_TITLE_CHARS: usize = 250;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum Tracker {
+    Jira {
+        /// The site, like `https://example.atlassian.net` or `https://example.com/jira/`
+        url: url::Url,
+        /// The account the API token belongs to
+        email: String,
+        /// The key of the project tickets are created in
+        project: String,
+        #[serde(default = "default_issue_type")]
+        issue_type: String,
+    },
+    Linear {
+        team_id: String,
+    },
+}
+
+fn default_issue_type() -> String {
+    "Task".to_owned()
+}
+
+impl Tracker {
+    fn kind(&self) -> &'static str {
+        match self {
+            Tracker::Jira { .. } => "jira",
+            Tracker::Linear { .. } => "linear",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Put {
+    #[serde(flatten)]
+    tracker: Tracker,
+    /// A Jira API token, or a Linear API key. It is never returned.
+    token: String,
+}
+
+#[derive(Serialize)]
+pub struct Integration {
+    #[serde(flatten)]
+    tracker: Tracker,
+    created_at: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct CreateTicket {
+    thread_id: uuid::Uuid,
+    exchange_id: uuid::Uuid,
+    /// Where the conversation can be opened, to link back to it.
+    conversation_url: Option<url::Url>,
+}
+
+#[derive(Serialize)]
+pub struct Ticket {
+    key: String,
+    url: String,
+}
+
+pub async fn list(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(id): Path<i64>,
+) -> webserver::Result<Json<Vec<Integration>>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    member_role(&app.sql, id, &user_id).await?;
+
+    let integrations = sqlx::query!(
+        "SELECT settings, created_at FROM workspace_integrations
+        WHERE workspace_id = ?
+        ORDER BY kind",
+        id,
+    )
+    .fetch_all(&*app.sql)
+    .await?
+    .into_iter()
+    .map(|row| {
+        Ok(Integration {
+            tracker: serde_json::from_str(&row.settings).map_err(Error::internal)?,
+            created_at: row.created_at,
+        })
+    })
+    .collect::<webserver::Result<_>>()?;
+
+    Ok(Json(integrations))
+}
+
+/// Connect an issue tracker, replacing the credentials of the same kind.
+pub async fn put(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(id): Path<i64>,
+    Json(mut params): Json<Put>,
+) -> webserver::Result<()> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    require_owner(&app.sql, id, &user_id).await?;
+
+    if params.token.trim().is_empty() {
+        return Err(Error::user("`token` cannot be empty"));
+    }
+
+    if let Tracker::Jira { url, .. } = &mut params.tracker {
+        Fetcher::new(&app.config).check(url).map_err(Error::user)?;
+        *url = base_url(url);
+    }
+
+    let kind = params.tracker.kind();
+    let settings = serde_json::to_string(&params.tracker).map_err(Error::internal)?;
+    let sealed_token = seal(
+        &sealing_key(&app.cookie_key),
+        &token_aad(id, kind),
+        params.token.trim(),
+    );
+
+    sqlx::query!(
+        "INSERT INTO workspace_integrations (workspace_id, kind, settings, sealed_token)
+        VALUES (?, ?, ?, ?)
+        ON CONFLICT (workspace_id, kind) DO UPDATE SET
+            settings = excluded.settings,
+            sealed_token = excluded.sealed_token",
+        id,
+        kind,
+        settings,
+        sealed_token,
+    )
+    .execute(&*app.sql)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path((id, kind)): Path<(i64, String)>,
+) -> webserver::Result<()> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    require_owner(&app.sql, id, &user_id).await?;
+
+    sqlx::query!(
+        "DELETE FROM workspace_integrations WHERE workspace_id = ? AND kind = ? RETURNING kind",
+        id,
+        kind,
+    )
+    .fetch_optional(&*app.sql)
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown integration"))?;
+
+    Ok(())
+}
+
+/// File a ticket with the question of an exchange as its title, and the answer as its body.
+pub async fn create_ticket(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path((id, kind)): Path<(i64, String)>,
+    Json(params): Json<CreateTicket>,
+) -> webserver::Result<Json<Ticket>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    member_role(&app.sql, id, &user_id).await?;
+
+    let conversation_id = ConversationId {
+        thread_id: params.thread_id,
+        user_id,
+    };
+    let (repo_ref, exchanges) = conversations::load(&app.sql, &conversation_id)
+        .await?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "thread was not found"))?;
+
+    let repo_str = repo_ref.to_string();
+    let in_workspace = sqlx::query_scalar!(
+        "SELECT repo_ref FROM workspace_repos WHERE workspace_id = ? AND repo_ref = ?",
+        id,
+        repo_str,
+    )
+    .fetch_optional(&*app.sql)
+    .await?
+    .is_some();
+
+    if !in_workspace {
+        return Err(Error::user(
+            "the conversation is not about a repository of this workspace",
+        ));
+    }
+
+    let exchange = exchanges
+        .into_iter()
+        .find(|e| e.id == params.exchange_id)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "exchange was not found"))?;
+
+    if exchange.answer().is_none() {
+        return Err(Error::user("the exchange has not been answered"));
+    }
+
+    let row = sqlx::query!(
+        "SELECT settings, sealed_token FROM workspace_integrations
+        WHERE workspace_id = ? AND kind = ?",
+        id,
+        kind,
+    )
+    .fetch_optional(&*app.sql)
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown integration"))?;
+
+    let tracker: Tracker = serde_json::from_str(&row.settings).map_err(Error::internal)?;
+    let token = open(
+        &sealing_key(&app.cookie_key),
+        &token_aad(id, &kind),
+        &row.sealed_token,
+    )
+    .map_err(Error::internal)?;
+
+    let title = title(&exchange);
+    let body = export::render(
+        &exchange,
+        export::blob_url(&app, &repo_ref, &exchange)
+            .await
+            .as_deref(),
+        export::conversation_url(&app, params.thread_id, params.conversation_url).as_deref(),
+    );
+
+    // The error can come from any host the owner configured, so it is only logged.
+    let fetcher = Fetcher::new(&app.config);
+    file(&fetcher, &tracker, &token, &title, &body)
+        .await
+        .map(Json)
+        .map_err(|err| {
+            warn!(workspace_id = id, %kind, ?err, "failed to file ticket");
+            Error::new(
+                ErrorKind::UpstreamService,
+                "the issue tracker refused the ticket",
+            )
+        })
+}
+
+async fn file(
+    fetcher: &Fetcher,
+    tracker: &Tracker,
+    token: &str,
+    title: &str,
+    body: &str,
+) -> anyhow::Result<Ticket> {
+    match tracker {
+        Tracker::Jira {
+            url,
+            email,
+            project,
+            issue_type,
+        } => {
+            // Integrations saved before URLs were normalized may lack the trailing slash.
+            let url = base_url(url);
+            let fields = json!({
+                "fields": {
+                    "project": { "key": project },
+                    "summary": title,
+                    "description": body,
+                    "issuetype": { "name": issue_type },
+                }
+            });
+
+            let response = fetcher
+                .post(url.join("rest/api/2/issue")?, |request| {
+                    request.basic_auth(email, Some(token)).json(&fields)
+                })
+                .await?;
+
+            if !response.status.is_success() {
+                bail!("Jira responded with {}", response.status);
+            }
+
+            let response: serde_json::Value = serde_json::from_slice(&response.body)?;
+
+            let key = response["key"]
+                .as_str()
+                .context("Jira did not return an issue key")?
+                .to_owned();
+
+            Ok(Ticket {
+                url: url.join(&format!("browse/{key}"))?.to_string(),
+                key,
+            })
+        }
+        Tracker::Linear { team_id } => {
+            let response: serde_json::Value = reqwest::Client::new()
+                .post(LINEAR_API)
+                .header("Authorization", token)
+                .json(&json!({
+                    "query": "mutation ($input: IssueCreateInput!) {
+                        issueCreate(input: $input) { success issue { identifier url } }
+                    }",
+                    "variables": {
+                        "input": { "teamId": team_id, "title": title, "description": body }
+                    },
+                }))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            if let Some(error) = response["errors"][0]["message"].as_str() {
+                bail!("Linear refused the issue: {error}");
+            }
+
+            let issue = &response["data"]["issueCreate"]["issue"];
+            match (issue["identifier"].as_str(), issue["url"].as_str()) {
+                (Some(key), Some(url)) => Ok(Ticket {
+                    key: key.to_owned(),
+                    url: url.to_owned(),
+                }),
+                _ => bail!("Linear did not return the created issue"),
+            }
+        }
+    }
+}
+
+/// The URL of a Jira site, ending with a `/` so that API paths are joined below its context path,
+/// like `https://example.com/jira/`.
+fn base_url(url: &url::Url) -> url::Url {
+    let mut url = url.clone();
+    if !url.path().ends_with('/') {
+        url.set_path(&format!("{}/", url.path()));
+    }
+
+    url
+}
+
+/// The first line of the question, which has to fit in a ticket summary.
+fn title(exchange: &Exchange) -> String {
+    let query = exchange.query().unwrap_or_default();
+    let line = query.lines().next().unwrap_or_default().trim();
+
+    if line.chars().count() > MAX_TITLE_CHARS {
+        let truncated = line.chars().take(MAX_TITLE_CHARS - 1).collect::<String>();
+        format!("{}…", truncated.trim_end())
+    } else if line.is_empty() {
+        "Question about the codebase".to_owned()
+    } else {
+        line.to_owned()
+    }
+}
+
+/// The key tokens are sealed with, derived from the cookie key rather than reusing the key that
+/// cookies are encrypted with.
+fn sealing_key(cookie_key: &Key) -> [u8; 32] {
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, &[]).extract(cookie_key.master());
+    let mut key = [0; 32];
+    prk.expand(&[b"integrations".as_slice()], hkdf::HKDF_SHA256)
+        .and_then(|okm| okm.fill(&mut key))
+        .expect("bad key length");
+    key
+}
+
+/// Tokens are bound to their row, so that a token can't be moved to another workspace.
+fn token_aad(workspace_id: i64, kind: &str) -> String {
+    format!("{workspace_id}/{kind}")
+}
+
+/// Encrypt a token, prefixing it with the random nonce.
+fn seal(key: &[u8], aad: &str, token: &str) -> Vec<u8> {
+    let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key).expect("bad key"));
+
+    let mut nonce = [0; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let mut sealed = token.as_bytes().to_vec();
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::from(aad.as_bytes()),
+        &mut sealed,
+    )
+    .expect("encryption failed");
+
+    nonce.into_iter().chain(sealed).collect()
+}
+
+fn open(key: &[u8], aad: &str, sealed: &[u8]) -> anyhow::Result<String> {
+    if sealed.len() < NONCE_LEN {
+        bail!("sealed token is too short");
+    }
+
+    let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key).expect("bad key"));
+    let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| anyhow!("bad nonce"))?;
+
+    let mut buf = sealed.to_vec();
+    let token = key
+        .open_in_place(nonce, Aad::from(aad.as_bytes()), &mut buf)
+        .map_err(|_| anyhow!("failed to decrypt token"))?;
+
+    Ok(String::from_utf8(token.to_vec())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seals_tokens_to_their_row() {
+        let key = [7; 32];
+        let sealed = seal(&key, &token_aad(1, "jira"), "secret");
+
+        assert!(!sealed.windows(6).any(|w| w == b"secret"));
+        assert_eq!(
+            open(&key, &token_aad(1, "jira"), &sealed).unwrap(),
+            "secret"
+        );
+        assert!(open(&key, &token_aad(2, "jira"), &sealed).is_err());
+        assert!(open(&[8; 32], &token_aad(1, "jira"), &sealed).is_err());
+        assert!(open(&key, &token_aad(1, "jira"), &sealed[..4]).is_err());
+    }
+
+    #[test]
+    fn derives_its_own_key() {
+        let cookie_key = Key::generate();
+        let key = sealing_key(&cookie_key);
+
+        assert_eq!(key, sealing_key(&cookie_key));
+        assert_ne!(&key[..], cookie_key.encryption());
+        assert_ne!(key, sealing_key(&Key::generate()));
+    }
+
+    #[test]
+    fn keeps_the_context_path_of_jira() {
+        for site in ["https://example.com/jira", "https://example.com/jira/"] {
+            let url = base_url(&site.parse().unwrap());
+            assert_eq!(
+                url.join("rest/api/2/issue").unwrap().as_str(),
+                "https://example.com/jira/rest/api/2/issue"
+            );
+        }
+
+        let url = base_url(&"https://example.atlassian.net".parse().unwrap());
+        assert_eq!(url.as_str(), "https://example.atlassian.net/");
+    }
+
+    #[test]
+    fn titles_fit_in_a_summary() {
+        let exchange = |q: &str| {
+            let query = crate::query::parser::parse_nl(q).unwrap().into_owned();
+            Exchange::new(uuid::Uuid::nil(), query)
+        };
+
+        assert_eq!(
+            title(&exchange("how are users created")),
+            "how are users created"
+        );
+
+        let long = title(&exchange(&"word ".repeat(100)));
+        assert_eq!(long.chars().count(), MAX_TITLE_CHARS);
+        assert!(long.ends_with('…'));
+    }
+}
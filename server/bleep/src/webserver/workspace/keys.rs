@@ -0,0 +1,208 @@
+//! API keys of workspaces, for CI jobs that query the workspace without acting as a member.
+//!
+//! Keys are managed by owners, and authenticated by [`tokens`](crate::webserver::tokens) like
+//! personal access tokens. Their conversations and quotas are those of the key.
+
+use super::{audit, require_owner};
+use crate::{
+    webserver::{
+        self,
+        middleware::User,
+        tokens::{self, KeyScope},
+        Error, ErrorKind,
+    },
+    Application,
+};
+use axum::{
+    extract::{Extension, Json, Path},
+    http::StatusCode,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct Create {
+    name: String,
+    scopes: Vec<KeyScope>,
+    /// Stop accepting the key after this many days, instead of until revoked
+    expires_in_days: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct Created {
+    #[serde(flatten)]
+    key: Key,
+    /// The key to authenticate with. It can't be shown again.
+    secret: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Key {
+    id: i64,
+    name: String,
+    scopes: Vec<KeyScope>,
+    created_by: String,
+    created_at: i64,
+    expires_at: Option<i64>,
+    last_used_at: Option<i64>,
+}
+
+pub async fn create(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(id): Path<i64>,
+    Json(params): Json<Create>,
+) -> webserver::Result<Json<Created>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    require_owner(&app.sql, id, &user_id).await?;
+
+    let name = params.name.trim();
+    if name.is_empty() {
+        return Err(Error::user("keys need a name"));
+    }
+
+    if params.scopes.is_empty() {
+        return Err(Error::user("keys need at least one scope"));
+    }
+
+    if let Some(days) = params.expires_in_days {
+        if !(1..=tokens::MAX_EXPIRY_DAYS).contains(&days) {
+            return Err(Error::user(format!(
+                "keys expire after 1 to {} days",
+                tokens::MAX_EXPIRY_DAYS
+            )));
+        }
+    }
+
+    let secret = format!(
+        "{}{}",
+        tokens::KEY_PREFIX,
+        hex::encode(rand::thread_rng().gen::<[u8; 32]>())
+    );
+    let key_hash = tokens::hash(&secret);
+    let scopes = KeyScope::join(&params.scopes);
+    let expires_in = params.expires_in_days.map(|days| days * 24 * 3600);
+
+    let mut transaction = app.sql.begin().await?;
+
+    let row = sqlx::query!(
+        "INSERT INTO workspace_keys (workspace_id, name, key_hash, scopes, created_by, expires_at)
+        VALUES (?, ?, ?, ?, ?, strftime('%s', 'now') + ?)
+        RETURNING id AS \"id!\", created_at AS \"created_at!\", expires_at",
+        id,
+        name,
+        key_hash,
+        scopes,
+        user_id,
+        expires_in,
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
+    audit(
+        &mut transaction,
+        id,
+        Some(&user_id),
+        "key_created",
+        Some(&format!("{name} ({scopes})")),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(Created {
+        key: Key {
+            id: row.id,
+            name: name.to_owned(),
+            scopes: params.scopes,
+            created_by: user_id,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            last_used_at: None,
+        },
+        secret,
+    }))
+}
+
+/// The keys of a workspace that haven't been revoked or expired, newest first.
+pub async fn list(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(id): Path<i64>,
+) -> webserver::Result<Json<Vec<Key>>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    require_owner(&app.sql, id, &user_id).await?;
+
+    let keys = sqlx::query!(
+        "SELECT id, name, scopes, created_by, created_at, expires_at, last_used_at
+        FROM workspace_keys
+        WHERE workspace_id = ? AND revoked_at IS NULL
+            AND (expires_at IS NULL OR expires_at > strftime('%s', 'now'))
+        ORDER BY id DESC",
+        id,
+    )
+    .fetch_all(&*app.sql)
+    .await?
+    .into_iter()
+    .map(|row| Key {
+        id: row.id,
+        name: row.name,
+        scopes: KeyScope::parse(&row.scopes),
+        created_by: row.created_by,
+        created_at: row.created_at,
+        expires_at: row.expires_at,
+        last_used_at: row.last_used_at,
+    })
+    .collect();
+
+    Ok(Json(keys))
+}
+
+/// Revoke a key, so that it stops being accepted.
+pub async fn revoke(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path((id, key_id)): Path<(i64, i64)>,
+) -> webserver::Result<StatusCode> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    require_owner(&app.sql, id, &user_id).await?;
+
+    let mut transaction = app.sql.begin().await?;
+
+    let name = sqlx::query!(
+        "UPDATE workspace_keys SET revoked_at = strftime('%s', 'now')
+        WHERE id = ? AND workspace_id = ? AND revoked_at IS NULL
+        RETURNING name AS \"name!\"",
+        key_id,
+        id,
+    )
+    .fetch_optional(&mut transaction)
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "key was not found"))?
+    .name;
+
+    audit(
+        &mut transaction,
+        id,
+        Some(&user_id),
+        "key_revoked",
+        Some(&name),
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,418 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{member_role, repo_paths, require_owner};
+use crate::{
+    repo::{Backend, RepoRef},
+    webserver::{self, middleware::User, tenant, Error, ErrorKind},
+    Application,
+};
+use axum::extract::{Extension, Json, Path};
+use chrono::NaiveDateTime;
+use octocrab::models::Repository;
+use serde::{Deserialize, Serialize};
+use sqlx::Sqlite;
+
+/// Attach every GitHub repository of an org, or with a topic, to a workspace.
+#[derive(Serialize, Debug)]
+pub struct RepoFilter {
+    id: i64,
+    org: Option<String>,
+    topic: Option<String>,
+    created_at: NaiveDateTime,
+}
+
+impl RepoFilter {
+    fn matches(&self, owner: &str, topics: &[String]) -> bool {
+        let org = self
+            .org
+            .as_ref()
+            .map_or(true, |org| org.eq_ignore_ascii_case(owner));
+
+        let topic = self.topic.as_ref().map_or(true, |topic| {
+            topics.iter().any(|t| t.eq_ignore_ascii_case(topic))
+        });
+
+        org && topic
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Create {
+    org: Option<String>,
+    topic: Option<String>,
+}
+
+/// The result of re-evaluating the filters of a workspace.
+#[derive(Serialize, Default)]
+pub struct Evaluation {
+    added: Vec<String>,
+    removed: Vec<String>,
+}
+
+pub async fn list(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(id): Path<i64>,
+) -> webserver::Result<Json<Vec<RepoFilter>>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    member_role(&app.sql, id, &user_id).await?;
+
+    let filters = sqlx::query_as!(
+        RepoFilter,
+        "SELECT id, org, topic, created_at FROM workspace_repo_filters WHERE workspace_id = ?",
+        id,
+    )
+    .fetch_all(&*app.sql)
+    .await?;
+
+    Ok(Json(filters))
+}
+
+/// Add a filter, and attach the repositories it currently matches.
+pub async fn create(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(id): Path<i64>,
+    Json(params): Json<Create>,
+) -> webserver::Result<Json<Evaluation>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    require_owner(&app.sql, id, &user_id).await?;
+
+    let normalize = |value: Option<String>| {
+        value
+            .map(|v| v.trim().to_lowercase())
+            .filter(|v| !v.is_empty())
+    };
+
+    let (org, topic) = (normalize(params.org), normalize(params.topic));
+    if org.is_none() && topic.is_none() {
+        return Err(Error::user("a filter needs an `org`, a `topic`, or both"));
+    }
+
+    sqlx::query!(
+        "INSERT INTO workspace_repo_filters (workspace_id, org, topic) VALUES (?, ?, ?)",
+        id,
+        org,
+        topic,
+    )
+    .execute(&*app.sql)
+    .await?;
+
+    evaluate_filters(&app, id).await.map(Json)
+}
+
+/// Remove a filter, detaching the repositories it attached.
+///
+/// Repositories that the other filters of the workspace match too stay attached by those.
+pub async fn delete(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path((id, filter_id)): Path<(i64, i64)>,
+) -> webserver::Result<()> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    require_owner(&app.sql, id, &user_id).await?;
+
+    let mut transaction = app.sql.begin().await?;
+
+    // Without credentials, the other filters can't be matched, and are left for `evaluate`.
+    let removed = match app.credentials.github() {
+        Some(gh) => {
+            reconcile(&mut transaction, id, &gh.repositories, Some(filter_id))
+                .await?
+                .removed
+        }
+        None => vec![],
+    };
+
+    let detached = sqlx::query!(
+        "DELETE FROM workspace_repos WHERE workspace_id = ? AND filter_id = ?
+        RETURNING repo_ref AS \"repo_ref!\"",
+        id,
+        filter_id,
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM workspace_repo_filters WHERE workspace_id = ? AND id = ? RETURNING id",
+        id,
+        filter_id,
+    )
+    .fetch_optional(&mut transaction)
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown repository filter"))?;
+
+    transaction.commit().await?;
+
+    let detached = detached.into_iter().map(|row| row.repo_ref);
+    for repo_ref in detached.chain(removed) {
+        if let Some(repo_ref) = tenant::stored_repo(&repo_ref).map_err(Error::internal)? {
+            repo_paths::apply(&app, &repo_ref).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Re-evaluate the filters of a workspace, after repositories were created or retagged.
+pub async fn evaluate(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(id): Path<i64>,
+) -> webserver::Result<Json<Evaluation>> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    require_owner(&app.sql, id, &user_id).await?;
+
+    evaluate_filters(&app, id).await.map(Json)
+}
+
+/// Materialize the filters of a workspace into repository attachments.
+///
+/// Repositories that were attached by hand are never detached here.
+async fn evaluate_filters(app: &Application, id: i64) -> webserver::Result<Evaluation> {
+    let gh = app
+        .credentials
+        .github()
+        .ok_or_else(|| Error::user("no GitHub credentials. Please connect GitHub first"))?;
+
+    let mut transaction = app.sql.begin().await?;
+    let evaluation = reconcile(&mut transaction, id, &gh.repositories, None).await?;
+    transaction.commit().await?;
+
+    // Detached repositories may be needed whole by fewer workspaces now
+    for repo_ref in &evaluation.removed {
+        if let Some(repo_ref) = tenant::stored_repo(repo_ref).map_err(Error::internal)? {
+            repo_paths::apply(app, &repo_ref).await?;
+        }
+    }
+
+    Ok(evaluation)
+}
+
+/// Change the attachments of a workspace to match its filters against `repositories`.
+///
+/// The filter `without` is left out, so that the repositories it attached are handed over to the
+/// other filters that match them before it's removed.
+async fn reconcile(
+    transaction: &mut sqlx::Transaction<'_, Sqlite>,
+    id: i64,
+    repositories: &[Repository],
+    without: Option<i64>,
+) -> webserver::Result<Evaluation> {
+    let filters = sqlx::query_as!(
+        RepoFilter,
+        "SELECT id, org, topic, created_at FROM workspace_repo_filters
+        WHERE workspace_id = ? AND id IS NOT ?",
+        id,
+        without,
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    let matching = filters
+        .iter()
+        .map(|filter| {
+            let repos = repositories
+                .iter()
+                .filter(|repo| {
+                    let owner = repo.owner.as_ref().map(|o| o.login.as_str()).unwrap_or("");
+                    filter.matches(owner, repo.topics.as_deref().unwrap_or_default())
+                })
+                .filter_map(|repo| RepoRef::new(Backend::Github, repo.full_name.as_ref()?).ok())
+                .map(|repo_ref| repo_ref.to_string())
+                .collect::<HashSet<_>>();
+
+            (filter.id, repos)
+        })
+        .collect::<Vec<_>>();
+
+    let attached = sqlx::query!(
+        "SELECT repo_ref, filter_id FROM workspace_repos WHERE workspace_id = ?",
+        id,
+    )
+    .fetch_all(&mut *transaction)
+    .await?
+    .into_iter()
+    .map(|row| (row.repo_ref, row.filter_id))
+    .collect::<HashMap<_, _>>();
+
+    let changes = Changes::new(&matching, &attached);
+
+    for repo_ref in &changes.detach {
+        sqlx::query!(
+            "DELETE FROM workspace_repos WHERE workspace_id = ? AND repo_ref = ?",
+            id,
+            repo_ref,
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    for (repo_ref, filter_id) in &changes.reassign {
+        sqlx::query!(
+            "UPDATE workspace_repos SET filter_id = ? WHERE workspace_id = ? AND repo_ref = ?",
+            filter_id,
+            id,
+            repo_ref,
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    for (repo_ref, filter_id) in &changes.attach {
+        sqlx::query!(
+            "INSERT INTO workspace_repos (workspace_id, repo_ref, filter_id) VALUES (?, ?, ?)",
+            id,
+            repo_ref,
+            filter_id,
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    Ok(Evaluation {
+        added: changes
+            .attach
+            .into_iter()
+            .map(|(repo_ref, _)| repo_ref)
+            .collect(),
+        removed: changes.detach,
+    })
+}
+
+/// The changes that make the attachments of a workspace match its filters.
+#[derive(Default, Debug, PartialEq)]
+struct Changes {
+    /// Repositories that no filter attached, with the first filter that matches them
+    attach: Vec<(String, i64)>,
+    /// Repositories whose filter stopped matching them, with another filter that still does
+    reassign: Vec<(String, i64)>,
+    /// Repositories attached by filters, that no filter matches any more
+    detach: Vec<String>,
+}
+
+impl Changes {
+    /// Compare the repositories each filter matches, in order, with the filter that attached
+    /// each repository, or `None` for those attached by hand.
+    fn new(matching: &[(i64, HashSet<String>)], attached: &HashMap<String, Option<i64>>) -> Self {
+        let first_match = |repo_ref: &str| {
+            matching
+                .iter()
+                .find(|(_, repos)| repos.contains(repo_ref))
+                .map(|(filter_id, _)| *filter_id)
+        };
+
+        let mut changes = Self::default();
+        for (repo_ref, filter_id) in attached {
+            let Some(filter_id) = filter_id else {
+                continue;
+            };
+
+            let still_matches = matching
+                .iter()
+                .any(|(id, repos)| id == filter_id && repos.contains(repo_ref));
+
+            if still_matches {
+                continue;
+            }
+
+            match first_match(repo_ref) {
+                Some(other) => changes.reassign.push((repo_ref.clone(), other)),
+                None => changes.detach.push(repo_ref.clone()),
+            }
+        }
+
+        let unattached = matching
+            .iter()
+            .flat_map(|(_, repos)| repos)
+            .filter(|repo_ref| !attached.contains_key(*repo_ref))
+            .collect::<HashSet<_>>();
+
+        for repo_ref in unattached {
+            if let Some(filter_id) = first_match(repo_ref) {
+                changes.attach.push((repo_ref.clone(), filter_id));
+            }
+        }
+
+        changes.attach.sort();
+        changes.reassign.sort();
+        changes.detach.sort();
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(org: Option<&str>, topic: Option<&str>) -> RepoFilter {
+        RepoFilter {
+            id: 1,
+            org: org.map(str::to_owned),
+            topic: topic.map(str::to_owned),
+            created_at: NaiveDateTime::default(),
+        }
+    }
+
+    #[test]
+    fn matches_org_and_topic() {
+        let topics = ["payments".to_owned(), "rust".to_owned()];
+
+        assert!(!filter(Some("bloop"), None).matches("BloopAI", &[]));
+        assert!(filter(Some("bloopai"), None).matches("BloopAI", &[]));
+        assert!(filter(None, Some("payments")).matches("acme", &topics));
+        assert!(filter(Some("acme"), Some("payments")).matches("acme", &topics));
+        assert!(!filter(Some("acme"), Some("payments")).matches("other", &topics));
+        assert!(!filter(Some("acme"), Some("billing")).matches("acme", &topics));
+    }
+
+    #[test]
+    fn keeps_repositories_that_other_filters_match() {
+        let repos = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<HashSet<_>>();
+        let matching = [(1, repos(&["a", "b"])), (2, repos(&["b", "c"]))];
+
+        let attached = [
+            ("a".to_owned(), Some(1)),
+            ("c".to_owned(), Some(3)),
+            ("d".to_owned(), Some(1)),
+            ("e".to_owned(), None),
+        ]
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+        assert_eq!(
+            Changes::new(&matching, &attached),
+            Changes {
+                attach: vec![("b".to_owned(), 1)],
+                reassign: vec![("c".to_owned(), 2)],
+                detach: vec!["d".to_owned()],
+            }
+        );
+
+        // The filter that attached `b` is being removed, but the other one still matches it
+        let attached = [("b".to_owned(), Some(1))].into_iter().collect();
+        assert_eq!(
+            Changes::new(&matching[1..], &attached),
+            Changes {
+                reassign: vec![("b".to_owned(), 2)],
+                ..Changes::default()
+            }
+        );
+    }
+}
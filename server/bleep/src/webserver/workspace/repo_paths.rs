@@ -0,0 +1,158 @@
+//! Sparse indexing of repositories that a workspace only needs parts of, like a service in a
+//! monorepo.
+//!
+//! A repository attached to several workspaces indexes the union of their paths, or everything as
+//! soon as one of them needs the whole repository.
+
+use std::collections::BTreeSet;
+
+use super::require_owner;
+use crate::{
+    background::SyncConfig,
+    repo::{iterator::SparsePaths, FilterUpdate, RepoRef},
+    webserver::{self, middleware::User, Error, ErrorKind},
+    Application,
+};
+use axum::extract::{Extension, Json, Path};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct RepoPaths {
+    pub(super) repo_ref: RepoRef,
+    /// Directories to index, or `None` for the whole repository
+    #[serde(default)]
+    pub(super) paths: Option<Vec<String>>,
+}
+
+impl RepoPaths {
+    /// The normalized paths, as stored in `workspace_repos.paths`.
+    pub(super) fn column(&self) -> webserver::Result<Option<String>> {
+        let Some(ref paths) = self.paths else {
+            return Ok(None);
+        };
+
+        let sparse = SparsePaths::new(paths).map_err(Error::user)?;
+        if sparse.dirs().is_empty() {
+            return Ok(None);
+        }
+
+        serde_json::to_string(sparse.dirs())
+            .map(Some)
+            .map_err(Error::internal)
+    }
+}
+
+/// Change the paths of a repository that is already attached.
+pub async fn patch(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(id): Path<i64>,
+    Json(params): Json<RepoPaths>,
+) -> webserver::Result<()> {
+    let user_id = user
+        .username()
+        .ok_or_else(|| Error::user("didn't have user ID"))?
+        .to_string();
+
+    require_owner(&app.sql, id, &user_id).await?;
+
+    let paths = params.column()?;
+    let repo_ref = params.repo_ref.to_string();
+    sqlx::query!(
+        "UPDATE workspace_repos SET paths = ? WHERE workspace_id = ? AND repo_ref = ?
+        RETURNING repo_ref",
+        paths,
+        id,
+        repo_ref,
+    )
+    .fetch_optional(&*app.sql)
+    .await?
+    .ok_or_else(|| Error::new(ErrorKind::NotFound, "repository is not in this workspace"))?;
+
+    apply(&app, &params.repo_ref).await
+}
+
+/// The directories to index for a repository across all workspaces, or `None` if it isn't
+/// attached to any.
+pub(crate) async fn sparse_paths(
+    app: &Application,
+    repo_ref: &RepoRef,
+) -> webserver::Result<Option<Vec<String>>> {
+    let name = repo_ref.to_string();
+    let attachments = sqlx::query!("SELECT paths FROM workspace_repos WHERE repo_ref = ?", name)
+        .fetch_all(&*app.sql)
+        .await?
+        .into_iter()
+        .map(|row| {
+            row.paths
+                .map(|paths| serde_json::from_str::<Vec<String>>(&paths))
+                .transpose()
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Error::internal)?;
+
+    Ok(union(attachments))
+}
+
+/// Reindex a repository if the paths of its attachments changed.
+///
+/// Repositories that aren't attached to any workspace any more are indexed whole again, and those
+/// that aren't indexed yet pick their paths up when they are synced.
+pub(super) async fn apply(app: &Application, repo_ref: &RepoRef) -> webserver::Result<()> {
+    let paths = sparse_paths(app, repo_ref).await?.unwrap_or_default();
+
+    let changed = app
+        .repo_pool
+        .read_async(repo_ref, |_, repo| repo.sparse_paths != paths)
+        .await
+        .unwrap_or_default();
+
+    if changed {
+        let update = FilterUpdate {
+            sparse_paths: Some(paths),
+            ..Default::default()
+        };
+
+        app.write_index()
+            .enqueue(SyncConfig::new(app, repo_ref.clone()).filter_updates(Some(update)))
+            .await;
+    }
+
+    Ok(())
+}
+
+fn union(attachments: Vec<Option<Vec<String>>>) -> Option<Vec<String>> {
+    if attachments.is_empty() {
+        return None;
+    }
+
+    let mut dirs = BTreeSet::new();
+    for paths in attachments {
+        match paths {
+            Some(paths) => dirs.extend(paths),
+            None => return Some(vec![]),
+        }
+    }
+
+    Some(dirs.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whole_repository_wins() {
+        let paths = |p: &[&str]| Some(p.iter().map(|p| p.to_string()).collect::<Vec<_>>());
+
+        assert_eq!(union(vec![]), None);
+        assert_eq!(
+            union(vec![
+                paths(&["services/api"]),
+                paths(&["libs", "services/api"])
+            ]),
+            paths(&["libs", "services/api"])
+        );
+        assert_eq!(union(vec![paths(&["libs"]), None]), paths(&[]));
+    }
+}